@@ -0,0 +1,461 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Logical acquisitions: a set of paths and artifacts collected into a
+//! single zip or tar container with a manifest.
+//!
+//! ```no_run
+//! # async fn example(device: forensic_adb::Device) -> forensic_adb::Result<()> {
+//! use forensic_adb::acquisition::{Acquisition, AcquisitionSpec};
+//!
+//! let spec = AcquisitionSpec::new()
+//!     .path("/sdcard/DCIM")
+//!     .apk("org.example")?
+//!     .command("packages", "pm list packages -f");
+//! let manifest = Acquisition::new(&device)
+//!     .collect(spec)
+//!     .write_to("evidence.zip".as_ref())
+//!     .await?;
+//! println!("{} files", manifest.entries.len());
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::export::json_string;
+use crate::package::check_package_name;
+use crate::transfer::{HashAlgorithm, Hasher};
+use crate::{Device, DeviceError, Result, TransferOptions, UnixFileStatus, UnixPath, UnixPathBuf};
+
+/// Name of the manifest at the root of the container.
+pub const ACQUISITION_MANIFEST: &str = "manifest.json";
+
+/// Something to collect from the device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Artifact {
+    /// A file or directory, stored under `files/` with its device path.
+    Path(UnixPathBuf),
+    /// The base and split apks of a package, stored under `apks/<package>/`.
+    Apk(String),
+    /// The output of a shell command, stored as `commands/<name>.txt`.
+    Command { name: String, command: String },
+}
+
+/// The artifacts of an [`Acquisition`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AcquisitionSpec {
+    pub artifacts: Vec<Artifact>,
+}
+
+impl AcquisitionSpec {
+    pub fn new() -> AcquisitionSpec {
+        AcquisitionSpec::default()
+    }
+
+    pub fn path(mut self, path: &str) -> AcquisitionSpec {
+        self.artifacts.push(Artifact::Path(UnixPathBuf::from(path)));
+        self
+    }
+
+    /// Adds the apks of `package`, failing unless it is a valid package
+    /// name, which also keeps it from escaping `apks/` in the staging
+    /// directory.
+    pub fn apk(mut self, package: &str) -> Result<AcquisitionSpec> {
+        check_package_name(package)?;
+        self.artifacts.push(Artifact::Apk(package.to_owned()));
+        Ok(self)
+    }
+
+    pub fn command(mut self, name: &str, command: &str) -> AcquisitionSpec {
+        self.artifacts.push(Artifact::Command {
+            name: name.to_owned(),
+            command: command.to_owned(),
+        });
+        self
+    }
+}
+
+/// A file stored in the container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AcquiredFile {
+    /// Path inside the container.
+    pub path: String,
+    /// Device path or shell command the file came from.
+    pub source: String,
+    pub bytes: u64,
+    /// Hex SHA-256 digest of the contents.
+    pub sha256: String,
+    /// Modification time on the device, or when the output of a command
+    /// was captured.
+    pub modified: Option<SystemTime>,
+}
+
+/// Description of an acquisition, stored as [`ACQUISITION_MANIFEST`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AcquisitionManifest {
+    /// Name and version of this crate.
+    pub tool: String,
+    pub serial: String,
+    /// Output of `getprop`.
+    pub properties: BTreeMap<String, String>,
+    pub started: SystemTime,
+    /// When the last artifact was collected.
+    pub finished: SystemTime,
+    pub entries: Vec<AcquiredFile>,
+}
+
+impl AcquisitionManifest {
+    /// Returns the manifest as JSON, with times in seconds since the epoch.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{\n");
+        let _ = writeln!(json, "  \"tool\": {},", json_string(&self.tool));
+        let _ = writeln!(json, "  \"serial\": {},", json_string(&self.serial));
+        json.push_str("  \"properties\": {");
+        for (i, (key, value)) in self.properties.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            let _ = write!(
+                json,
+                "{separator}\n    {}: {}",
+                json_string(key),
+                json_string(value)
+            );
+        }
+        json.push_str("\n  },\n");
+        let _ = writeln!(json, "  \"started\": {},", unix_seconds(self.started));
+        let _ = writeln!(json, "  \"finished\": {},", unix_seconds(self.finished));
+        json.push_str("  \"entries\": [");
+        for (i, entry) in self.entries.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            let _ = write!(
+                json,
+                "{separator}\n    {{\"path\": {}, \"source\": {}, \"bytes\": {}, \"sha256\": {}, \"modified\": {}}}",
+                json_string(&entry.path),
+                json_string(&entry.source),
+                entry.bytes,
+                json_string(&entry.sha256),
+                entry
+                    .modified
+                    .map_or("null".to_owned(), |time| unix_seconds(time).to_string())
+            );
+        }
+        json.push_str("\n  ]\n}\n");
+        json
+    }
+}
+
+/// Collects artifacts from a device into an evidence container.
+#[derive(Debug, Clone)]
+pub struct Acquisition<'a> {
+    device: &'a Device,
+    spec: AcquisitionSpec,
+}
+
+impl<'a> Acquisition<'a> {
+    pub fn new(device: &'a Device) -> Acquisition<'a> {
+        Acquisition {
+            device,
+            spec: AcquisitionSpec::default(),
+        }
+    }
+
+    /// Adds the artifacts of `spec`.
+    pub fn collect(mut self, spec: AcquisitionSpec) -> Acquisition<'a> {
+        self.spec.artifacts.extend(spec.artifacts);
+        self
+    }
+
+    /// Collects the artifacts and writes the container to `dest`, a zip if
+    /// it ends in `.zip` and a tar if it ends in `.tar`.
+    ///
+    /// Artifacts are staged in a temporary directory first, then hashed
+    /// while they are copied into the container.  Any artifact that cannot
+    /// be collected fails the acquisition.
+    pub async fn write_to(&self, dest: &Path) -> Result<AcquisitionManifest> {
+        let format = match dest.extension().and_then(|ext| ext.to_str()) {
+            Some("zip") => ContainerFormat::Zip,
+            Some("tar") => ContainerFormat::Tar,
+            _ => {
+                return Err(DeviceError::Adb(format!(
+                    "unsupported container {}, expected .zip or .tar",
+                    dest.display()
+                )))
+            }
+        };
+
+        let device = self.device;
+        let started = SystemTime::now();
+        let properties = parse_getprop(&device.execute_host_shell_command("getprop").await?);
+
+        let staging = tempfile::tempdir()?;
+        let mut staged = Vec::new();
+        for artifact in &self.spec.artifacts {
+            self.stage(artifact, staging.path(), &mut staged).await?;
+        }
+
+        let mut manifest = AcquisitionManifest {
+            tool: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            serial: device.serial.clone(),
+            properties,
+            started,
+            finished: SystemTime::now(),
+            entries: Vec::new(),
+        };
+
+        let dest = dest.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            let _staging = staging;
+            let mut container = Container::create(format, &dest)?;
+            for (path, source, host_path) in staged {
+                let file = File::open(&host_path)?;
+                let metadata = file.metadata()?;
+                let modified = metadata.modified().ok();
+                let mut reader = HashingReader {
+                    inner: file,
+                    hasher: HashAlgorithm::Sha256.hasher(),
+                };
+                container.append(&path, &mut reader, metadata.len(), modified)?;
+                manifest.entries.push(AcquiredFile {
+                    path,
+                    source,
+                    bytes: metadata.len(),
+                    sha256: reader.hasher.finish(),
+                    modified,
+                });
+            }
+
+            let json = manifest.to_json();
+            container.append(
+                ACQUISITION_MANIFEST,
+                &mut json.as_bytes(),
+                json.len() as u64,
+                Some(manifest.finished),
+            )?;
+            container.finish()?;
+            Ok::<_, io::Error>(manifest)
+        })
+        .await
+        .map_err(io::Error::other)?
+        .map_err(DeviceError::from)
+    }
+
+    /// Pulls `artifact` below `staging`, recording each file as its
+    /// container path, source and host path.
+    async fn stage(
+        &self,
+        artifact: &Artifact,
+        staging: &Path,
+        staged: &mut Vec<(String, String, PathBuf)>,
+    ) -> Result<()> {
+        let device = self.device;
+        let options = TransferOptions::new().preserve_times(true);
+        match artifact {
+            Artifact::Path(path) => {
+                let dest = staging.join(container_path(path));
+                if let Some(parent) = dest.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                if device.stat(path).await?.file_mode == UnixFileStatus::Directory {
+                    let report = device
+                        .pull_dir_internal(path, &dest, None, &options)
+                        .await?;
+                    for file in report.files {
+                        if let Some(host_path) = file.host_path {
+                            staged.push((
+                                relative(staging, &host_path),
+                                file.device_path.display().to_string(),
+                                host_path,
+                            ));
+                        }
+                    }
+                } else {
+                    device.pull_to_file(path, &dest, &options).await?;
+                    staged.push((relative(staging, &dest), path.display().to_string(), dest));
+                }
+            }
+            Artifact::Apk(package) => {
+                let pulled = device
+                    .pull_apk(package, &staging.join("apks").join(package))
+                    .await?;
+                for apk in pulled.apks {
+                    if let Some(host_path) = apk.host_path {
+                        staged.push((
+                            relative(staging, &host_path),
+                            apk.device_path.display().to_string(),
+                            host_path,
+                        ));
+                    }
+                }
+            }
+            Artifact::Command { name, command } => {
+                if name.is_empty()
+                    || !name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+                {
+                    return Err(DeviceError::Adb(format!("invalid artifact name '{name}'")));
+                }
+                let output = device.execute_host_shell_command(command).await?;
+                let dest = staging.join("commands").join(format!("{name}.txt"));
+                tokio::fs::create_dir_all(staging.join("commands")).await?;
+                tokio::fs::write(&dest, output).await?;
+                staged.push((relative(staging, &dest), command.clone(), dest));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ContainerFormat {
+    Zip,
+    Tar,
+}
+
+enum Container {
+    Zip(zip::ZipWriter<File>),
+    Tar(tar::Builder<File>),
+}
+
+impl Container {
+    fn create(format: ContainerFormat, dest: &Path) -> io::Result<Container> {
+        let file = File::create(dest)?;
+        Ok(match format {
+            ContainerFormat::Zip => Container::Zip(zip::ZipWriter::new(file)),
+            ContainerFormat::Tar => Container::Tar(tar::Builder::new(file)),
+        })
+    }
+
+    fn append(
+        &mut self,
+        path: &str,
+        reader: &mut dyn Read,
+        size: u64,
+        modified: Option<SystemTime>,
+    ) -> io::Result<()> {
+        match self {
+            Container::Zip(zip) => {
+                let options = zip::write::SimpleFileOptions::default()
+                    .compression_method(zip::CompressionMethod::Stored)
+                    .large_file(size >= u32::MAX as u64);
+                zip.start_file(path, options).map_err(io::Error::other)?;
+                io::copy(reader, zip)?;
+            }
+            Container::Tar(tar) => {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(size);
+                header.set_mode(0o644);
+                header.set_mtime(modified.map_or(0, unix_seconds));
+                tar.append_data(&mut header, path, reader)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Container::Zip(zip) => {
+                zip.finish().map_err(io::Error::other)?;
+            }
+            Container::Tar(tar) => {
+                tar.into_inner()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Hashes what is read through it.
+struct HashingReader<R> {
+    inner: R,
+    hasher: Hasher,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Where a device path is stored, e.g. `files/sdcard/DCIM`.
+fn container_path(path: &UnixPath) -> PathBuf {
+    let mut dest = PathBuf::from("files");
+    for name in path.display().to_string().split('/') {
+        if !matches!(name, "" | "." | "..") {
+            dest.push(name);
+        }
+    }
+    dest
+}
+
+/// Returns `path` relative to `staging` with `/` separators.
+fn relative(staging: &Path, path: &Path) -> String {
+    let tail = path.strip_prefix(staging).unwrap_or(path);
+    let components: Vec<_> = tail
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect();
+    components.join("/")
+}
+
+/// Parses `[key]: [value]` lines.  Multi-line values are cut at the first
+/// line.
+fn parse_getprop(output: &str) -> BTreeMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.strip_prefix('[')?.split_once("]: [")?;
+            Some((
+                key.to_owned(),
+                value.strip_suffix(']').unwrap_or(value).to_owned(),
+            ))
+        })
+        .collect()
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apk_rejects_paths() {
+        for package in ["../../etc", "/etc", "org.example/..", ""] {
+            assert!(AcquisitionSpec::new().apk(package).is_err(), "{package}");
+        }
+        assert_eq!(
+            AcquisitionSpec::new().apk("org.example").unwrap().artifacts,
+            [Artifact::Apk("org.example".to_owned())]
+        );
+    }
+
+    #[test]
+    fn getprop_output() {
+        let properties = parse_getprop(
+            "[ro.product.model]: [Pixel 7]\n[ro.build.version.sdk]: [34]\n[persist.sys.multi]: [first\nsecond]\n",
+        );
+        assert_eq!(properties["ro.product.model"], "Pixel 7");
+        assert_eq!(properties["ro.build.version.sdk"], "34");
+        assert_eq!(properties["persist.sys.multi"], "first");
+    }
+
+    #[test]
+    fn device_paths_in_container() {
+        assert_eq!(
+            container_path(UnixPath::new("/sdcard/../DCIM/a.jpg")),
+            PathBuf::from("files/sdcard/DCIM/a.jpg")
+        );
+    }
+}
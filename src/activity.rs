@@ -0,0 +1,495 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Starting activities and opening URIs with `am start -W` and parsing what
+//! it reports, and the foreground activity and task stack from `dumpsys`.
+
+use std::fmt;
+use std::time::Duration as StdDuration;
+
+use crate::{shell, Device, DeviceError, Result, SYNC_REGEX};
+
+/// The `Status:` line of `am start -W`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LaunchStatus {
+    /// `ok`: the activity was drawn.
+    Ok,
+    /// `timeout`: the activity did not report being drawn in time.
+    Timeout,
+    /// Any other status, verbatim; empty if `am` printed none.
+    Other(String),
+}
+
+/// Result of [`Device::launch_activity`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LaunchResult {
+    pub status: LaunchStatus,
+    /// The activity that was started, e.g. `com.android.settings/.Settings`.
+    pub activity: Option<String>,
+    /// Time from the start request to the activity being drawn.
+    pub total_time: Option<StdDuration>,
+    /// Time `am` waited, including its own overhead.
+    pub wait_time: Option<StdDuration>,
+    /// `Warning:` lines, e.g. that the existing task was brought to the
+    /// front instead of starting the activity.
+    pub warnings: Vec<String>,
+}
+
+/// Why `am start` did not start an activity.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LaunchFailure {
+    /// `Error type 3`: the activity class does not exist.
+    ClassNotFound,
+    /// No activity matches the intent.
+    IntentNotResolved,
+    /// The activity is not exported or needs a permission the shell lacks.
+    PermissionDenied(String),
+    /// Output that is none of the above, verbatim.
+    Other(String),
+}
+
+impl fmt::Display for LaunchFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LaunchFailure::ClassNotFound => f.write_str("activity class does not exist"),
+            LaunchFailure::IntentNotResolved => f.write_str("unable to resolve intent"),
+            LaunchFailure::PermissionDenied(message) => {
+                write!(f, "permission denied: {message}")
+            }
+            LaunchFailure::Other(output) => f.write_str(output),
+        }
+    }
+}
+
+/// An activity as listed by `dumpsys`, see [`Device::current_activity`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunningActivity {
+    pub package: String,
+    /// The class as printed, relative to `package` if it starts with `.`.
+    pub activity: String,
+    /// Android user the activity runs as.
+    pub user: Option<u32>,
+    /// Task the activity belongs to; `dumpsys window` does not report it.
+    pub task_id: Option<u32>,
+}
+
+impl RunningActivity {
+    /// `package/activity`, as accepted by `am start -n`.
+    pub fn component(&self) -> String {
+        format!("{}/{}", self.package, self.activity)
+    }
+
+    /// The fully qualified class name.
+    pub fn class_name(&self) -> String {
+        match self.activity.starts_with('.') {
+            true => format!("{}{}", self.package, self.activity),
+            false => self.activity.clone(),
+        }
+    }
+
+    /// Parses the inside of `ActivityRecord{7ba3d2e u0 com.a/.Main t12}`
+    /// or `Window{5c4c0a3 u0 com.a/com.a.Main}`.
+    fn parse_record(record: &str) -> Option<RunningActivity> {
+        let record = record.split('}').next()?;
+        let mut user = None;
+        let mut task_id = None;
+        let mut component = None;
+        for token in record.split_whitespace().skip(1) {
+            if let Some(id) = token.strip_prefix('u').and_then(|id| id.parse().ok()) {
+                user = Some(id);
+            } else if let Some(id) = token.strip_prefix('t').and_then(|id| id.parse().ok()) {
+                task_id = Some(id);
+            } else if token.contains('/') {
+                component = Some(token);
+            }
+        }
+        let (package, activity) = component?.split_once('/')?;
+        Some(RunningActivity {
+            package: package.to_owned(),
+            activity: activity.to_owned(),
+            user,
+            task_id,
+        })
+    }
+}
+
+/// A task of [`Device::activity_stack`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActivityTask {
+    pub id: u32,
+    /// Activities of the task, topmost first.
+    pub activities: Vec<RunningActivity>,
+}
+
+/// Finds the first `marker` in `dumpsys` output and parses the record
+/// following it.
+fn find_record(output: &str, markers: &[&str]) -> Option<RunningActivity> {
+    output.lines().map(str::trim).find_map(|line| {
+        let rest = markers
+            .iter()
+            .find_map(|marker| line.strip_prefix(marker))?;
+        let start = rest.find('{')?;
+        RunningActivity::parse_record(&rest[start + 1..])
+    })
+}
+
+/// Groups the `Hist #n: ActivityRecord{...}` lines of `dumpsys activity
+/// activities` by task, in the order listed, i.e. topmost first.
+fn parse_activity_stack(output: &str) -> Vec<ActivityTask> {
+    let mut tasks: Vec<ActivityTask> = Vec::new();
+    for line in output.lines().map(str::trim) {
+        if !line.trim_start_matches("* ").starts_with("Hist") {
+            continue;
+        }
+        let Some(activity) = line
+            .split_once("ActivityRecord{")
+            .and_then(|(_, record)| RunningActivity::parse_record(record))
+        else {
+            continue;
+        };
+        let Some(id) = activity.task_id else {
+            continue;
+        };
+        match tasks.iter_mut().find(|task| task.id == id) {
+            Some(task) => task.activities.push(activity),
+            None => tasks.push(ActivityTask {
+                id,
+                activities: vec![activity],
+            }),
+        }
+    }
+    tasks
+}
+
+/// Activities the system shows to let the user pick among several apps.
+const CHOOSER_ACTIVITIES: [&str; 2] = ["ResolverActivity", "ChooserActivity"];
+
+impl LaunchResult {
+    /// Whether the system showed its app chooser instead of an app, as for
+    /// a URI that several apps handle without a default.
+    pub fn is_disambiguation(&self) -> bool {
+        self.activity.as_ref().is_some_and(|activity| {
+            CHOOSER_ACTIVITIES
+                .iter()
+                .any(|chooser| activity.ends_with(chooser))
+        })
+    }
+
+    /// Parses the output of `am start -W`.
+    pub fn parse(output: &str) -> std::result::Result<LaunchResult, LaunchFailure> {
+        let mut result = LaunchResult {
+            status: LaunchStatus::Other(String::new()),
+            activity: None,
+            total_time: None,
+            wait_time: None,
+            warnings: Vec::new(),
+        };
+        let mut status_seen = false;
+        let mut complete = false;
+        let millis = |value: &str| value.trim().parse().ok().map(StdDuration::from_millis);
+
+        for line in output.lines().map(str::trim) {
+            if let Some(status) = line.strip_prefix("Status:") {
+                status_seen = true;
+                result.status = match status.trim() {
+                    "ok" => LaunchStatus::Ok,
+                    "timeout" => LaunchStatus::Timeout,
+                    other => LaunchStatus::Other(other.to_owned()),
+                };
+            } else if let Some(activity) = line.strip_prefix("Activity:") {
+                result.activity = Some(activity.trim().to_owned());
+            } else if let Some(time) = line.strip_prefix("TotalTime:") {
+                result.total_time = millis(time);
+            } else if let Some(time) = line.strip_prefix("WaitTime:") {
+                result.wait_time = millis(time);
+            } else if let Some(warning) = line.strip_prefix("Warning:") {
+                result.warnings.push(warning.trim().to_owned());
+            } else if line == "Error type 3" || line.ends_with("does not exist.") {
+                return Err(LaunchFailure::ClassNotFound);
+            } else if line.contains("unable to resolve Intent") {
+                return Err(LaunchFailure::IntentNotResolved);
+            } else if line.contains("SecurityException")
+                || line.contains("Permission Denial")
+                || line.contains("do not have permission")
+            {
+                return Err(LaunchFailure::PermissionDenied(line.to_owned()));
+            } else if line.starts_with("Error") {
+                return Err(LaunchFailure::Other(line.to_owned()));
+            } else if line == "Complete" {
+                complete = true;
+            }
+        }
+
+        match status_seen || complete {
+            true => Ok(result),
+            false => Err(LaunchFailure::Other(output.trim().to_owned())),
+        }
+    }
+}
+
+impl Device {
+    /// Starts `package/activity` with `am start -W`, waiting until it is
+    /// drawn, and returns the status and timings `am` reports.
+    ///
+    /// An activity that could not be started fails with
+    /// [`DeviceError::LaunchFailed`].  An already running activity brought
+    /// to the front is not a failure; see [`LaunchResult::warnings`].
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self, am_start_args), fields(serial = %self.serial), err)
+    )]
+    pub async fn launch_activity<T: AsRef<str>>(
+        &self,
+        package: &str,
+        activity: &str,
+        am_start_args: &[T],
+    ) -> Result<LaunchResult> {
+        self.check_writable("launch activities")?;
+        let mut am_start = format!("am start{} -W -n {package}/{activity}", self.user_arg());
+
+        for arg in am_start_args {
+            am_start.push(' ');
+            if SYNC_REGEX.is_match(arg.as_ref()) {
+                am_start.push_str(&format!("\"{}\"", &shell::escape(arg.as_ref())));
+            } else {
+                am_start.push_str(&shell::escape(arg.as_ref()));
+            };
+        }
+
+        let output = self.execute_host_shell_command(&am_start).await?;
+        LaunchResult::parse(&output)
+            .map_err(|failure| DeviceError::LaunchFailed(format!("{package}/{activity}"), failure))
+    }
+}
+
+impl Device {
+    /// Opens `uri` like a tapped link, with `am start -W -a
+    /// android.intent.action.VIEW -d <uri>`, for testing app link
+    /// handling.
+    ///
+    /// `package_hint` restricts the intent to one app.  Without it, a URI
+    /// that several apps handle without a default shows the app chooser,
+    /// see [`LaunchResult::is_disambiguation`].  A URI no app handles fails
+    /// with [`LaunchFailure::IntentNotResolved`].
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self), fields(serial = %self.serial), err)
+    )]
+    pub async fn open_uri(&self, uri: &str, package_hint: Option<&str>) -> Result<LaunchResult> {
+        self.check_writable("open URIs")?;
+        let mut am_start = format!(
+            "am start{} -W -a android.intent.action.VIEW -d {}",
+            self.user_arg(),
+            shell::quote(uri)
+        );
+        if let Some(package) = package_hint {
+            // Package names never need quoting, so anything else is refused.
+            if package.is_empty()
+                || !package
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_'))
+            {
+                return Err(DeviceError::Adb(format!(
+                    "invalid package name '{package}'"
+                )));
+            }
+            am_start.push_str(&format!(" -p {package}"));
+        }
+
+        let output = self.execute_host_shell_command(&am_start).await?;
+        LaunchResult::parse(&output)
+            .map_err(|failure| DeviceError::LaunchFailed(uri.to_owned(), failure))
+    }
+
+    /// The activity in the foreground, `None` if there is none, e.g. while
+    /// the screen is locked on some releases.
+    ///
+    /// Reads the resumed activity from `dumpsys activity activities`,
+    /// falling back to the focused app of `dumpsys window`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn current_activity(&self) -> Result<Option<RunningActivity>> {
+        let output = self
+            .execute_host_shell_command("dumpsys activity activities")
+            .await?;
+        // `topResumedActivity=` from Android 10, `ResumedActivity:` from 11.
+        let resumed = find_record(
+            &output,
+            &[
+                "topResumedActivity=",
+                "ResumedActivity:",
+                "mResumedActivity:",
+            ],
+        );
+        if resumed.is_some() {
+            return Ok(resumed);
+        }
+
+        let output = self.execute_host_shell_command("dumpsys window").await?;
+        Ok(find_record(&output, &["mFocusedApp=", "mCurrentFocus="]))
+    }
+
+    /// The activity tasks from `dumpsys activity activities`, topmost
+    /// first.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn activity_stack(&self) -> Result<Vec<ActivityTask>> {
+        let output = self
+            .execute_host_shell_command("dumpsys activity activities")
+            .await?;
+        Ok(parse_activity_stack(&output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_launch_output() {
+        let result = LaunchResult::parse(
+            "Starting: Intent { cmp=com.android.settings/.Settings }\n\
+             Status: ok\n\
+             LaunchState: COLD\n\
+             Activity: com.android.settings/.Settings\n\
+             TotalTime: 512\n\
+             WaitTime: 530\n\
+             Complete\n",
+        )
+        .expect("launched");
+        assert_eq!(result.status, LaunchStatus::Ok);
+        assert_eq!(
+            result.activity.as_deref(),
+            Some("com.android.settings/.Settings")
+        );
+        assert_eq!(result.total_time, Some(StdDuration::from_millis(512)));
+        assert_eq!(result.wait_time, Some(StdDuration::from_millis(530)));
+        assert!(result.warnings.is_empty());
+
+        let result = LaunchResult::parse(
+            "Starting: Intent { cmp=com.android.settings/.Settings }\n\
+             Warning: Activity not started, its current task has been brought to the front\n\
+             Status: ok\n\
+             Activity: com.android.settings/.Settings\n\
+             WaitTime: 41\n\
+             Complete\n",
+        )
+        .expect("brought to front");
+        assert_eq!(result.total_time, None);
+        assert_eq!(
+            result.warnings,
+            ["Activity not started, its current task has been brought to the front"]
+        );
+    }
+
+    #[test]
+    fn parses_launch_failures() {
+        assert_eq!(
+            LaunchResult::parse(
+                "Starting: Intent { cmp=com.example/.Missing }\n\
+                 Error type 3\n\
+                 Error: Activity class {com.example/com.example.Missing} does not exist.\n"
+            ),
+            Err(LaunchFailure::ClassNotFound)
+        );
+        assert_eq!(
+            LaunchResult::parse(
+                "Starting: Intent { act=android.intent.action.VIEW }\n\
+                 Error: Activity not started, unable to resolve Intent { act=android.intent.action.VIEW }\n"
+            ),
+            Err(LaunchFailure::IntentNotResolved)
+        );
+        assert!(matches!(
+            LaunchResult::parse(
+                "Starting: Intent { cmp=com.example/.Private }\n\
+                 Exception occurred while executing 'start':\n\
+                 java.lang.SecurityException: Permission Denial: starting Intent not exported\n"
+            ),
+            Err(LaunchFailure::PermissionDenied(_))
+        ));
+        assert_eq!(
+            LaunchResult::parse("/system/bin/sh: am: not found\n"),
+            Err(LaunchFailure::Other(
+                "/system/bin/sh: am: not found".to_owned()
+            ))
+        );
+    }
+
+    const ACTIVITIES: &str = "ACTIVITY MANAGER ACTIVITIES (dumpsys activity activities)
+Display #0 (activities from top to bottom):
+  * Task{c5e2f7 #12 type=standard A=1000:com.android.settings U=0 visible=true sz=2}
+    topResumedActivity=ActivityRecord{9b0e8c2 u0 com.android.settings/.SubSettings t12}
+    * Hist  #1: ActivityRecord{9b0e8c2 u0 com.android.settings/.SubSettings t12}
+    * Hist  #0: ActivityRecord{1d2c3b4 u0 com.android.settings/.Settings t12}
+  * Task{a1b2c3 #8 type=home U=0 visible=false sz=1}
+    * Hist  #0: ActivityRecord{4bbd13d u0 com.android.launcher3/.uioverrides.QuickstepLauncher t8}
+  ResumedActivity: ActivityRecord{9b0e8c2 u0 com.android.settings/.SubSettings t12}
+";
+
+    #[test]
+    fn parses_current_activity() {
+        let activity =
+            find_record(ACTIVITIES, &["topResumedActivity=", "ResumedActivity:"]).expect("resumed");
+        assert_eq!(activity.component(), "com.android.settings/.SubSettings");
+        assert_eq!(activity.class_name(), "com.android.settings.SubSettings");
+        assert_eq!((activity.user, activity.task_id), (Some(0), Some(12)));
+
+        let window = find_record(
+            "  mCurrentFocus=Window{5c4c0a3 u0 com.android.chrome/org.chromium.chrome.browser.ChromeTabbedActivity}\n",
+            &["mCurrentFocus="],
+        )
+        .expect("focused window");
+        assert_eq!(window.package, "com.android.chrome");
+        assert_eq!(window.task_id, None);
+
+        assert_eq!(
+            find_record(
+                "  mCurrentFocus=Window{1f2e3d u0 NotificationShade}\n",
+                &["mCurrentFocus="]
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_activity_stack() {
+        let tasks = parse_activity_stack(ACTIVITIES);
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks[0].id, 12);
+        assert_eq!(
+            tasks[0]
+                .activities
+                .iter()
+                .map(|a| a.activity.as_str())
+                .collect::<Vec<_>>(),
+            [".SubSettings", ".Settings"]
+        );
+        assert_eq!(tasks[1].id, 8);
+        assert_eq!(tasks[1].activities[0].package, "com.android.launcher3");
+
+        let old = parse_activity_stack(
+            "    * TaskRecord{3f1d2 #5 A=com.android.settings U=0 StackId=1 sz=1}\n      \
+             Hist #0: ActivityRecord{2a3b u0 com.android.settings/.Settings t5}\n",
+        );
+        assert_eq!(old.len(), 1);
+        assert_eq!(old[0].activities[0].task_id, Some(5));
+    }
+
+    #[test]
+    fn detects_disambiguation() {
+        let result = LaunchResult::parse(
+            "Starting: Intent { act=android.intent.action.VIEW dat=https://example.com/... }\n\
+             Status: ok\n\
+             Activity: android/com.android.internal.app.ResolverActivity\n\
+             TotalTime: 210\n\
+             Complete\n",
+        )
+        .expect("chooser");
+        assert!(result.is_disambiguation());
+    }
+}
@@ -36,3 +36,42 @@ impl SyncCommand {
 }
 
 pub type DeviceSerial = String;
+
+/// Packet types of the shell v2 protocol (`shell,v2,raw:`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellPacket {
+    Stdin,
+    Stdout,
+    Stderr,
+    Exit,
+    CloseStdin,
+    WindowSizeChange,
+}
+
+impl ShellPacket {
+    // Returns the id byte that prefixes the packet on the wire.
+    pub fn id(&self) -> u8 {
+        use self::ShellPacket::*;
+        match *self {
+            Stdin => 0,
+            Stdout => 1,
+            Stderr => 2,
+            Exit => 3,
+            CloseStdin => 4,
+            WindowSizeChange => 5,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<ShellPacket> {
+        use self::ShellPacket::*;
+        match id {
+            0 => Some(Stdin),
+            1 => Some(Stdout),
+            2 => Some(Stderr),
+            3 => Some(Exit),
+            4 => Some(CloseStdin),
+            5 => Some(WindowSizeChange),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,151 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The [`AdbDevice`] trait, the common device operations as an object safe
+//! trait so that code using them can run against a
+//! [`FakeDevice`](crate::fake::FakeDevice) in unit tests.
+//!
+//! ```no_run
+//! use forensic_adb::{AdbDevice, Result, UnixPath};
+//!
+//! async fn model(device: &dyn AdbDevice) -> Result<String> {
+//!     let model = device
+//!         .execute_host_shell_command("getprop ro.product.model")
+//!         .await?;
+//!     Ok(model.trim().to_owned())
+//! }
+//! ```
+
+use futures_core::future::BoxFuture;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{Device, FileMetadata, Result, ShellOutput, UnixPath};
+
+/// Device operations implemented by [`Device`] and
+/// [`FakeDevice`](crate::fake::FakeDevice).
+///
+/// The methods behave like the [`Device`] methods of the same name.
+pub trait AdbDevice: Send + Sync {
+    fn serial(&self) -> &str;
+
+    fn execute_host_shell_command<'a>(
+        &'a self,
+        shell_command: &'a str,
+    ) -> BoxFuture<'a, Result<String>>;
+
+    fn run<'a>(&'a self, command: &'a str) -> BoxFuture<'a, Result<ShellOutput>>;
+
+    fn list_dir<'a>(&'a self, src: &'a UnixPath) -> BoxFuture<'a, Result<Vec<FileMetadata>>>;
+
+    fn stat<'a>(&'a self, path: &'a UnixPath) -> BoxFuture<'a, Result<FileMetadata>>;
+
+    fn path_exists<'a>(
+        &'a self,
+        path: &'a UnixPath,
+        enable_run_as: bool,
+    ) -> BoxFuture<'a, Result<bool>>;
+
+    fn pull<'a>(
+        &'a self,
+        src: &'a UnixPath,
+        buffer: &'a mut (dyn AsyncWrite + Unpin + Send),
+    ) -> BoxFuture<'a, Result<()>>;
+
+    fn push<'a>(
+        &'a self,
+        buffer: &'a mut (dyn AsyncRead + Unpin + Send),
+        dest: &'a UnixPath,
+        mode: u32,
+    ) -> BoxFuture<'a, Result<()>>;
+
+    fn remove<'a>(&'a self, path: &'a UnixPath) -> BoxFuture<'a, Result<()>>;
+
+    fn create_dir<'a>(&'a self, path: &'a UnixPath) -> BoxFuture<'a, Result<()>>;
+
+    fn chmod<'a>(
+        &'a self,
+        path: &'a UnixPath,
+        mask: &'a str,
+        recursive: bool,
+    ) -> BoxFuture<'a, Result<()>>;
+
+    fn list_packages(&self, third_party: bool) -> BoxFuture<'_, Result<Vec<String>>>;
+
+    fn is_app_installed<'a>(&'a self, package: &'a str) -> BoxFuture<'a, Result<bool>>;
+}
+
+impl AdbDevice for Device {
+    fn serial(&self) -> &str {
+        &self.serial
+    }
+
+    fn execute_host_shell_command<'a>(
+        &'a self,
+        shell_command: &'a str,
+    ) -> BoxFuture<'a, Result<String>> {
+        Box::pin(Device::execute_host_shell_command(self, shell_command))
+    }
+
+    fn run<'a>(&'a self, command: &'a str) -> BoxFuture<'a, Result<ShellOutput>> {
+        Box::pin(Device::run(self, command))
+    }
+
+    fn list_dir<'a>(&'a self, src: &'a UnixPath) -> BoxFuture<'a, Result<Vec<FileMetadata>>> {
+        Box::pin(Device::list_dir(self, src))
+    }
+
+    fn stat<'a>(&'a self, path: &'a UnixPath) -> BoxFuture<'a, Result<FileMetadata>> {
+        Box::pin(Device::stat(self, path))
+    }
+
+    fn path_exists<'a>(
+        &'a self,
+        path: &'a UnixPath,
+        enable_run_as: bool,
+    ) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(Device::path_exists(self, path, enable_run_as))
+    }
+
+    fn pull<'a>(
+        &'a self,
+        src: &'a UnixPath,
+        mut buffer: &'a mut (dyn AsyncWrite + Unpin + Send),
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move { Device::pull(self, src, &mut buffer).await })
+    }
+
+    fn push<'a>(
+        &'a self,
+        mut buffer: &'a mut (dyn AsyncRead + Unpin + Send),
+        dest: &'a UnixPath,
+        mode: u32,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move { Device::push(self, &mut buffer, dest, mode).await })
+    }
+
+    fn remove<'a>(&'a self, path: &'a UnixPath) -> BoxFuture<'a, Result<()>> {
+        Box::pin(Device::remove(self, path))
+    }
+
+    fn create_dir<'a>(&'a self, path: &'a UnixPath) -> BoxFuture<'a, Result<()>> {
+        Box::pin(Device::create_dir(self, path))
+    }
+
+    fn chmod<'a>(
+        &'a self,
+        path: &'a UnixPath,
+        mask: &'a str,
+        recursive: bool,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(Device::chmod(self, path, mask, recursive))
+    }
+
+    fn list_packages(&self, third_party: bool) -> BoxFuture<'_, Result<Vec<String>>> {
+        Box::pin(Device::list_packages(self, third_party))
+    }
+
+    fn is_app_installed<'a>(&'a self, package: &'a str) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(Device::is_app_installed(self, package))
+    }
+}
@@ -0,0 +1,347 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Extraction of installed apks, along with their signing certificates.
+
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::package::check_package_name;
+use crate::progress::DirectoryFileSink;
+use crate::transfer::{HashAlgorithm, SyncConnections};
+use crate::{
+    Device, DeviceError, DirectoryTransferProgress, FileTransferProgress, ProgressSink, Result,
+    TransferOptions, TransferredFile, UnixPathBuf,
+};
+
+/// Ids of the v3.1, v3 and v2 signature schemes in the APK Signing Block,
+/// in order of preference.
+const SIGNATURE_SCHEME_IDS: [u32; 3] = [0x1b93ad61, 0xf05368c0, 0x7109871a];
+
+const SIGNING_BLOCK_MAGIC: &[u8; 16] = b"APK Sig Block 42";
+
+const EOCD_SIGNATURE: [u8; 4] = [0x50, 0x4b, 0x05, 0x06];
+
+/// Size of the zip end of central directory record without its comment.
+const EOCD_SIZE: u64 = 22;
+
+/// The apks of a package pulled by [`Device::pull_apk`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PulledApk {
+    pub package: String,
+    /// The base apk first, followed by the splits.
+    pub apks: Vec<TransferredFile>,
+    /// Hex SHA-256 digests of the signing certificates of the base apk, as
+    /// shown by `apksigner verify --print-certs`.  Empty if the apk only has
+    /// a v1 (jar) signature.
+    pub signing_certificates: Vec<String>,
+}
+
+impl Device {
+    /// Pulls the base and split apks of the installed `package` into
+    /// `dest_dir`, keeping their file names.
+    pub async fn pull_apk(&self, package: &str, dest_dir: &Path) -> Result<PulledApk> {
+        self.pull_apk_internal(package, dest_dir, None).await
+    }
+
+    /// Like [`Device::pull_apk`], reporting the progress over all apks.
+    pub async fn pull_apk_with_progress(
+        &self,
+        package: &str,
+        dest_dir: &Path,
+        progress: impl ProgressSink<DirectoryTransferProgress>,
+    ) -> Result<PulledApk> {
+        self.pull_apk_internal(package, dest_dir, Some(&progress))
+            .await
+    }
+
+    async fn pull_apk_internal(
+        &self,
+        package: &str,
+        dest_dir: &Path,
+        progress: Option<&dyn ProgressSink<DirectoryTransferProgress>>,
+    ) -> Result<PulledApk> {
+        let mut sources = Vec::new();
+        for path in self.apk_paths(package).await? {
+            let size = self.stat(&path).await?.size as u64;
+            sources.push((path, size));
+        }
+
+        let total_files = sources.len();
+        let total_bytes = sources.iter().map(|(_, size)| size).sum();
+        let mut transferred_bytes = 0;
+        tokio::fs::create_dir_all(dest_dir).await?;
+
+        // The splits are pulled over one sync connection.
+        let options = TransferOptions::new();
+        let connections = Arc::new(SyncConnections::default());
+        let mut apks = Vec::with_capacity(total_files);
+        for (transferred_files, (src, size)) in sources.into_iter().enumerate() {
+            let name = src
+                .file_name()
+                .ok_or_else(|| DeviceError::Adb(format!("Invalid apk path {}", src.display())))?;
+            let dest = dest_dir.join(name);
+
+            let file_sink = progress.map(|sink| DirectoryFileSink {
+                sink,
+                base: DirectoryTransferProgress {
+                    directory_name: Some(package.to_owned()),
+                    total_files,
+                    transferred_files,
+                    total_bytes,
+                    transferred_bytes,
+                    current_file: Some(dest.display().to_string()),
+                    current_file_progress: FileTransferProgress {
+                        total_bytes: Some(size),
+                        transferred_bytes: 0,
+                        sparse_bytes: 0,
+                    },
+                },
+            });
+            let mut state = options.state();
+            state.connections = Some(connections.clone());
+            let pulled = self
+                .pull_file(
+                    &src,
+                    &dest,
+                    Some(size),
+                    file_sink
+                        .as_ref()
+                        .map(|sink| sink as &dyn ProgressSink<FileTransferProgress>),
+                    &options,
+                    state,
+                )
+                .await?;
+
+            let written = tokio::fs::metadata(&dest).await?.len();
+            if pulled.bytes != size || written != size {
+                return Err(DeviceError::Adb(format!(
+                    "{} is {} bytes, pulled {}",
+                    src.display(),
+                    size,
+                    written
+                )));
+            }
+            transferred_bytes += size;
+            apks.push(pulled);
+        }
+
+        let base = apks[0].host_path.clone().unwrap_or_default();
+        let signing_certificates = tokio::task::spawn_blocking(move || {
+            signing_certificate_digests(&mut std::fs::File::open(base)?)
+        })
+        .await
+        .map_err(io::Error::other)??;
+
+        Ok(PulledApk {
+            package: package.to_owned(),
+            apks,
+            signing_certificates,
+        })
+    }
+
+    /// Returns the paths reported by `pm path`, the base apk first.
+    async fn apk_paths(&self, package: &str) -> Result<Vec<UnixPathBuf>> {
+        check_package_name(package)?;
+        let pm = self.capabilities().await?.package_manager();
+        let output = self
+            .execute_host_shell_command(&format!("{pm} path{} {package}", self.user_arg()))
+            .await?;
+
+        let mut paths: Vec<_> = output
+            .lines()
+            .filter_map(|line| line.trim_end().strip_prefix("package:"))
+            .map(UnixPathBuf::from)
+            .collect();
+        if paths.is_empty() {
+            return Err(DeviceError::MissingPackage);
+        }
+        // `pm path` lists the base apk first; sort anyway to be safe.
+        paths.sort_by_key(|path| path.file_name() != Some("base.apk".as_ref()));
+        Ok(paths)
+    }
+}
+
+/// Returns the hex SHA-256 digests of the certificates of each signer in the
+/// APK Signing Block (v2 and later schemes) of `apk`.
+pub(crate) fn signing_certificate_digests<R: Read + Seek>(apk: &mut R) -> io::Result<Vec<String>> {
+    let Some(block) = read_signing_block(apk)? else {
+        return Ok(Vec::new());
+    };
+
+    let mut schemes = Vec::new();
+    let mut pairs = &block[8..block.len() - 24];
+    while !pairs.is_empty() {
+        let pair = take_u64_prefixed(&mut pairs)?;
+        if pair.len() < 4 {
+            return Err(malformed());
+        }
+        let id = u32::from_le_bytes(pair[..4].try_into().unwrap());
+        schemes.push((id, &pair[4..]));
+    }
+
+    let Some(signers) = SIGNATURE_SCHEME_IDS
+        .iter()
+        .find_map(|id| schemes.iter().find(|(scheme, _)| scheme == id))
+        .map(|(_, value)| *value)
+    else {
+        return Ok(Vec::new());
+    };
+
+    let mut digests = Vec::new();
+    let mut signers = take_u32_prefixed(&mut &signers[..])?;
+    while !signers.is_empty() {
+        let mut signer = take_u32_prefixed(&mut signers)?;
+        let mut signed_data = take_u32_prefixed(&mut signer)?;
+        let _digests = take_u32_prefixed(&mut signed_data)?;
+        let mut certificates = take_u32_prefixed(&mut signed_data)?;
+        // The first certificate is the signer's own, the rest its chain.
+        if !certificates.is_empty() {
+            let certificate = take_u32_prefixed(&mut certificates)?;
+            let mut hasher = HashAlgorithm::Sha256.hasher();
+            hasher.update(certificate);
+            digests.push(hasher.finish());
+        }
+    }
+    Ok(digests)
+}
+
+/// Reads the APK Signing Block, which sits right before the zip central
+/// directory.
+fn read_signing_block<R: Read + Seek>(apk: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let len = apk.seek(SeekFrom::End(0))?;
+    let tail_len = len.min(EOCD_SIZE + u16::MAX as u64);
+    apk.seek(SeekFrom::Start(len - tail_len))?;
+    let mut tail = vec![0; tail_len as usize];
+    apk.read_exact(&mut tail)?;
+
+    let eocd = (0..(tail.len() + 1).saturating_sub(EOCD_SIZE as usize))
+        .rev()
+        .find(|&i| tail[i..i + 4] == EOCD_SIGNATURE)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a zip file"))?;
+    let central_directory =
+        u32::from_le_bytes(tail[eocd + 16..eocd + 20].try_into().unwrap()) as u64;
+
+    if central_directory < 24 {
+        return Ok(None);
+    }
+    apk.seek(SeekFrom::Start(central_directory - 24))?;
+    let mut footer = [0; 24];
+    apk.read_exact(&mut footer)?;
+    if &footer[8..] != SIGNING_BLOCK_MAGIC {
+        return Ok(None);
+    }
+
+    let size = u64::from_le_bytes(footer[..8].try_into().unwrap());
+    let start = size
+        .checked_add(8)
+        .and_then(|n| central_directory.checked_sub(n))
+        .filter(|_| size >= 24)
+        .ok_or_else(malformed)?;
+    apk.seek(SeekFrom::Start(start))?;
+    let mut block = vec![0; (size + 8) as usize];
+    apk.read_exact(&mut block)?;
+    Ok(Some(block))
+}
+
+fn take_u32_prefixed<'a>(buf: &mut &'a [u8]) -> io::Result<&'a [u8]> {
+    if buf.len() < 4 {
+        return Err(malformed());
+    }
+    let len = u32::from_le_bytes(buf[..4].try_into().unwrap()) as usize;
+    take(buf, 4, len)
+}
+
+fn take_u64_prefixed<'a>(buf: &mut &'a [u8]) -> io::Result<&'a [u8]> {
+    if buf.len() < 8 {
+        return Err(malformed());
+    }
+    let len = u64::from_le_bytes(buf[..8].try_into().unwrap());
+    take(buf, 8, usize::try_from(len).map_err(|_| malformed())?)
+}
+
+fn take<'a>(buf: &mut &'a [u8], prefix: usize, len: usize) -> io::Result<&'a [u8]> {
+    let end = prefix.checked_add(len).ok_or_else(malformed)?;
+    if buf.len() < end {
+        return Err(malformed());
+    }
+    let value = &buf[prefix..end];
+    *buf = &buf[end..];
+    Ok(value)
+}
+
+fn malformed() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed APK Signing Block")
+}
+
+/// Builds an empty zip with a v2 signing block listing `certificates`, one
+/// signer each.
+#[cfg(test)]
+pub(crate) fn signed_apk(certificates: &[&[u8]]) -> Vec<u8> {
+    fn u32_prefixed(data: &[u8]) -> Vec<u8> {
+        let mut buf = (data.len() as u32).to_le_bytes().to_vec();
+        buf.extend_from_slice(data);
+        buf
+    }
+
+    let mut signers = Vec::new();
+    for certificate in certificates {
+        let mut signed_data = u32_prefixed(&[]);
+        signed_data.extend(u32_prefixed(&u32_prefixed(certificate)));
+        signers.extend(u32_prefixed(&u32_prefixed(&signed_data)));
+    }
+    let mut value = 0x7109871au32.to_le_bytes().to_vec();
+    value.extend(u32_prefixed(&signers));
+
+    let mut pairs = (value.len() as u64).to_le_bytes().to_vec();
+    pairs.extend(value);
+    let size = (pairs.len() + 24) as u64;
+
+    let mut apk = size.to_le_bytes().to_vec();
+    apk.extend(pairs);
+    apk.extend(size.to_le_bytes());
+    apk.extend_from_slice(SIGNING_BLOCK_MAGIC);
+
+    let central_directory = apk.len() as u32;
+    apk.extend(EOCD_SIGNATURE);
+    apk.extend([0; 12]);
+    apk.extend(central_directory.to_le_bytes());
+    apk.extend([0; 2]);
+    apk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn certificate_digests() {
+        let apk = signed_apk(&[b"first", b"second"]);
+        let digests = signing_certificate_digests(&mut Cursor::new(apk)).expect("digests");
+        let mut hasher = HashAlgorithm::Sha256.hasher();
+        hasher.update(b"first");
+        assert_eq!(digests.len(), 2);
+        assert_eq!(digests[0], hasher.finish());
+
+        // A zip without signing block, e.g. a v1-only apk.
+        let mut unsigned = EOCD_SIGNATURE.to_vec();
+        unsigned.extend([0; 18]);
+        assert_eq!(
+            signing_certificate_digests(&mut Cursor::new(unsigned)).expect("digests"),
+            Vec::<String>::new()
+        );
+        assert!(signing_certificate_digests(&mut Cursor::new(b"not a zip".to_vec())).is_err());
+    }
+
+    #[test]
+    fn corrupt_signing_block_size() {
+        let mut apk = signed_apk(&[b"certificate"]);
+        // The size field in front of the magic.
+        let footer = apk.len() - 22 - 24;
+        apk[footer..footer + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(signing_certificate_digests(&mut Cursor::new(apk)).is_err());
+    }
+}
@@ -0,0 +1,353 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! App ops of a package: what it may do and when it last used the camera,
+//! microphone, location and other guarded operations.
+
+use std::time::Duration;
+
+use crate::{Device, DeviceError, Result};
+
+/// Mode of an [`AppOp`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AppOpMode {
+    Allow,
+    /// Denied silently: the app gets empty data.
+    Ignore,
+    /// Denied with an error.
+    Deny,
+    /// Decided by the permission or the uid mode.
+    Default,
+    /// Allowed while the app is in the foreground.
+    Foreground,
+    Other(String),
+}
+
+impl AppOpMode {
+    fn parse(mode: &str) -> AppOpMode {
+        match mode {
+            "allow" => AppOpMode::Allow,
+            "ignore" => AppOpMode::Ignore,
+            "deny" | "errored" => AppOpMode::Deny,
+            "default" => AppOpMode::Default,
+            "foreground" => AppOpMode::Foreground,
+            other => AppOpMode::Other(other.to_owned()),
+        }
+    }
+}
+
+/// An operation of [`Device::app_ops`], e.g. `CAMERA` or `FINE_LOCATION`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppOp {
+    pub name: String,
+    /// Mode set for the package; [`AppOpMode::Default`] when only the uid
+    /// has one.
+    pub mode: AppOpMode,
+    /// Mode set for all packages of the uid, which takes precedence.
+    pub uid_mode: Option<AppOpMode>,
+    /// How long before the call the op was last allowed.
+    pub last_access: Option<Duration>,
+    /// How long before the call the op was last refused.
+    pub last_reject: Option<Duration>,
+    /// How long the last access lasted, for ops that run like `CAMERA`.
+    pub last_duration: Option<Duration>,
+    /// Device local time of the last access, `YYYY-MM-DD HH:MM:SS.mmm`.
+    pub last_access_time: Option<String>,
+    /// Device local time of the last refusal.
+    pub last_reject_time: Option<String>,
+}
+
+impl AppOp {
+    fn new(name: &str) -> AppOp {
+        AppOp {
+            name: name.to_owned(),
+            mode: AppOpMode::Default,
+            uid_mode: None,
+            last_access: None,
+            last_reject: None,
+            last_duration: None,
+            last_access_time: None,
+            last_reject_time: None,
+        }
+    }
+}
+
+impl Device {
+    /// Lists the app ops of `package` that have a mode or were used, with
+    /// when they were last allowed or refused.
+    ///
+    /// Runs `appops get` for [`Device::user`] and `dumpsys appops
+    /// --package` in one shell round trip; the latter adds the device time
+    /// of the last access where `appops get` reports only how long ago it
+    /// was.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self), fields(serial = %self.serial), err)
+    )]
+    pub async fn app_ops(&self, package: &str) -> Result<Vec<AppOp>> {
+        let outputs = self
+            .run_batch(&[
+                &format!("appops get{} {package}", self.user_arg()),
+                &format!("dumpsys appops --package {package}"),
+            ])
+            .await?;
+
+        let stdout = outputs[0].stdout_lossy();
+        if !outputs[0].success() || stdout.starts_with("Error") {
+            let mut message = outputs[0].stderr_lossy();
+            if message.is_empty() {
+                message = stdout;
+            }
+            return Err(DeviceError::Adb(message.trim().to_owned()));
+        }
+
+        let mut ops = Vec::new();
+        parse_app_ops(&stdout, &mut ops);
+        if outputs[1].success() {
+            let dumpsys = outputs[1].stdout_lossy();
+            parse_app_ops(&package_section(&dumpsys, package), &mut ops);
+        }
+        Ok(ops)
+    }
+}
+
+/// The `Package <package>:` section of `dumpsys appops`, which lists every
+/// package on releases that ignore `--package`.
+fn package_section(output: &str, package: &str) -> String {
+    let header = format!("Package {package}:");
+    let mut section = String::new();
+    let mut indent = None;
+    for line in output.lines() {
+        let depth = line.len() - line.trim_start().len();
+        match indent {
+            None if line.trim() == header => indent = Some(depth),
+            Some(indent) if depth <= indent && !line.trim().is_empty() => break,
+            Some(_) => {
+                section.push_str(line);
+                section.push('\n');
+            }
+            None => {}
+        }
+    }
+    section
+}
+
+/// Merges the ops of `appops get` or a `dumpsys appops` package section into
+/// `ops`.  Ops are listed as `CAMERA: allow; time=+5m10s ago; rejectTime=+1d
+/// ago; duration=+30s` (Android 11 and before), `CAMERA: allow` or `CAMERA
+/// (allow):` followed by `Access: [top-s] 2023-10-16 11:55:01.123 (-5m10s)
+/// duration=+30s` and `Reject:` lines, and `Uid mode: CAMERA: ignore`.
+fn parse_app_ops(output: &str, ops: &mut Vec<AppOp>) {
+    let mut current: Option<usize> = None;
+    for line in output.lines().map(str::trim) {
+        if let Some(rest) = line.strip_prefix("Uid mode: ") {
+            if let Some((name, mode)) = rest.split_once(": ") {
+                let index = op_index(ops, name);
+                ops[index].uid_mode = Some(AppOpMode::parse(mode.trim()));
+            }
+            current = None;
+        } else if let Some(access) = line.strip_prefix("Access: ") {
+            if let Some(index) = current {
+                let (time, ago, duration) = parse_access(access);
+                let op = &mut ops[index];
+                if ago.is_some_and(|ago| op.last_access.is_none_or(|last| ago <= last)) {
+                    op.last_access = ago;
+                    op.last_duration = duration.or(op.last_duration);
+                }
+                if time > op.last_access_time {
+                    op.last_access_time = time;
+                }
+            }
+        } else if let Some(reject) = line.strip_prefix("Reject: ") {
+            if let Some(index) = current {
+                let (time, ago, _) = parse_access(reject);
+                let op = &mut ops[index];
+                if ago.is_some_and(|ago| op.last_reject.is_none_or(|last| ago <= last)) {
+                    op.last_reject = ago;
+                }
+                if time > op.last_reject_time {
+                    op.last_reject_time = time;
+                }
+            }
+        } else if let Some((name, rest)) = op_header(line) {
+            let index = op_index(ops, name);
+            current = Some(index);
+            let mut fields = rest.split("; ");
+            if let Some(mode) = fields.next().filter(|mode| !mode.is_empty()) {
+                ops[index].mode = AppOpMode::parse(mode);
+            }
+            for field in fields {
+                let Some((key, value)) = field.split_once('=') else {
+                    continue;
+                };
+                let value = value.trim_end_matches(" ago");
+                match key {
+                    "time" => ops[index].last_access = parse_relative(value),
+                    "rejectTime" => ops[index].last_reject = parse_relative(value),
+                    "duration" => ops[index].last_duration = parse_relative(value),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// Name and the rest of `CAMERA: allow; ...` or `CAMERA (allow):`.
+fn op_header(line: &str) -> Option<(&str, &str)> {
+    let end = line.find([':', ' '])?;
+    let name = &line[..end];
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+    {
+        return None;
+    }
+    let rest = &line[end..];
+    if let Some(mode) = rest
+        .strip_prefix(" (")
+        .and_then(|rest| rest.strip_suffix("):"))
+    {
+        return Some((name, mode));
+    }
+    Some((name, rest.strip_prefix(':')?.trim()))
+}
+
+fn op_index(ops: &mut Vec<AppOp>, name: &str) -> usize {
+    match ops.iter().position(|op| op.name == name) {
+        Some(index) => index,
+        None => {
+            ops.push(AppOp::new(name));
+            ops.len() - 1
+        }
+    }
+}
+
+/// Parses `[top-s] 2023-10-16 11:55:01.123 (-5m10s123ms) duration=+30s`
+/// into the time, how long ago it was and the duration.
+fn parse_access(access: &str) -> (Option<String>, Option<Duration>, Option<Duration>) {
+    let access = match access.strip_prefix('[') {
+        Some(rest) => rest.split_once("] ").map_or(rest, |(_, rest)| rest),
+        None => access,
+    };
+    let (time, rest) = access.split_once(" (").unwrap_or((access, ""));
+    let ago = rest.split(')').next().and_then(parse_relative);
+    let duration = rest
+        .split_once("duration=")
+        .and_then(|(_, duration)| parse_relative(duration.split_whitespace().next()?));
+    let time = time.trim();
+    ((!time.is_empty()).then(|| time.to_owned()), ago, duration)
+}
+
+/// Parses Android's `TimeUtils` durations such as `+1d2h3m4s5ms` or
+/// `-5m10s123ms`.
+fn parse_relative(value: &str) -> Option<Duration> {
+    let value = value.trim().trim_start_matches(['+', '-']);
+    if value.is_empty() {
+        return None;
+    }
+    let mut total = Duration::ZERO;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let number: u64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+        let (unit, len) = if rest.starts_with("ms") {
+            (Duration::from_millis(1), 2)
+        } else {
+            match rest.chars().next()? {
+                'd' => (Duration::from_secs(86_400), 1),
+                'h' => (Duration::from_secs(3_600), 1),
+                'm' => (Duration::from_secs(60), 1),
+                's' => (Duration::from_secs(1), 1),
+                _ => return None,
+            }
+        };
+        total += unit * u32::try_from(number).ok()?;
+        rest = &rest[len..];
+    }
+    Some(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_relative() {
+        assert_eq!(
+            parse_relative("+1d2h3m4s5ms"),
+            Some(Duration::from_millis(93_784_005))
+        );
+        assert_eq!(
+            parse_relative("-5m10s123ms"),
+            Some(Duration::from_millis(310_123))
+        );
+        assert_eq!(parse_relative("+0ms"), Some(Duration::ZERO));
+        assert_eq!(parse_relative("never"), None);
+    }
+
+    #[test]
+    fn parses_legacy_appops_get() {
+        let mut ops = Vec::new();
+        parse_app_ops(
+            "Uid mode: COARSE_LOCATION: foreground\n\
+             COARSE_LOCATION: allow; time=+2h3m ago; rejectTime=+1d ago\n\
+             CAMERA: allow; time=+5m10s ago; duration=+30s\n\
+             RECORD_AUDIO: ignore; rejectTime=+3d ago\n",
+            &mut ops,
+        );
+        assert_eq!(ops.len(), 3);
+        assert_eq!(ops[0].uid_mode, Some(AppOpMode::Foreground));
+        assert_eq!(ops[0].last_access, Some(Duration::from_secs(7_380)));
+        assert_eq!(ops[0].last_reject, Some(Duration::from_secs(86_400)));
+        assert_eq!(ops[1].last_duration, Some(Duration::from_secs(30)));
+        assert_eq!(ops[2].mode, AppOpMode::Ignore);
+        assert_eq!(ops[2].last_access, None);
+    }
+
+    #[test]
+    fn parses_access_lines() {
+        let mut ops = Vec::new();
+        parse_app_ops(
+            "CAMERA: allow\n\
+             \x20   null=[\n\
+             \x20     Access: [top-s] 2023-10-16 11:55:01.123 (-5m10s123ms) duration=+30s\n\
+             \x20   ]\n\
+             \x20   scanner=[\n\
+             \x20     Access: [bg-s] 2023-10-15 09:00:00.000 (-1d2h55m1s) duration=+1s\n\
+             \x20     Reject: [bg-s] 2023-10-15 08:00:00.000 (-1d3h55m1s)\n\
+             \x20   ]\n",
+            &mut ops,
+        );
+        let dumpsys = package_section(
+            "  Uid 10123:\n\
+             \x20   Package com.example.other:\n\
+             \x20     CAMERA (ignore):\n\
+             \x20   Package com.example.app:\n\
+             \x20     FINE_LOCATION (allow):\n\
+             \x20         Access: [fg-s] 2023-10-16 10:00:00.000 (-2h)\n\
+             \x20 Uid 10124:\n",
+            "com.example.app",
+        );
+        parse_app_ops(&dumpsys, &mut ops);
+
+        assert_eq!(ops.len(), 2);
+        assert_eq!(
+            ops[0],
+            AppOp {
+                name: "CAMERA".to_owned(),
+                mode: AppOpMode::Allow,
+                uid_mode: None,
+                last_access: Some(Duration::from_millis(310_123)),
+                last_reject: Some(Duration::from_secs(100_501)),
+                last_duration: Some(Duration::from_secs(30)),
+                last_access_time: Some("2023-10-16 11:55:01.123".to_owned()),
+                last_reject_time: Some("2023-10-15 08:00:00.000".to_owned()),
+            }
+        );
+        assert_eq!(ops[1].name, "FINE_LOCATION");
+        assert_eq!(ops[1].last_access, Some(Duration::from_secs(7_200)));
+    }
+}
@@ -0,0 +1,294 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Directory pulls and app data backups through `tar` on the device.
+
+use bytes::{Bytes, BytesMut};
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::sync::mpsc;
+
+use crate::buffer_pool::PooledBuffer;
+use crate::transport::BoxedTransport;
+use crate::{
+    Device, DeviceError, DevicePath, FileTransferProgress, ProgressSink, Result, SuStrategy,
+    UnixPath, UnixPathBuf,
+};
+
+/// Blocking reader over chunks handed over from async code, so the
+/// synchronous `tar` crate can unpack while the archive is still arriving.
+struct ChannelReader {
+    receiver: mpsc::Receiver<Bytes>,
+    chunk: Bytes,
+}
+
+impl io::Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.receiver.blocking_recv() {
+                Some(chunk) => self.chunk = chunk,
+                None => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.chunk.len());
+        buf[..len].copy_from_slice(&self.chunk.split_to(len));
+        Ok(len)
+    }
+}
+
+/// Output of `tar` on the device, failing at the end if there was none.
+struct TarReader {
+    stream: BoxedTransport,
+    src: UnixPathBuf,
+    bytes: u64,
+}
+
+impl AsyncRead for TarReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        std::task::ready!(Pin::new(&mut self.stream).poll_read(cx, buf))?;
+        let n = buf.filled().len() - filled;
+        if n == 0 && self.bytes == 0 {
+            return Poll::Ready(Err(io::Error::other(empty_archive(&self.src))));
+        }
+        self.bytes += n as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Device {
+    /// Streams `src` as a tar archive into `writer` and returns the archive
+    /// size.
+    ///
+    /// Runs toybox `tar` over `exec:`, so a tree of many small files costs a
+    /// single round trip instead of one per file, and empty directories,
+    /// permissions and timestamps come along.  Entries are relative to `src`.
+    pub async fn pull_dir_tar<W: AsyncWrite + Unpin>(
+        &self,
+        src: &UnixPath,
+        writer: &mut W,
+    ) -> Result<u64> {
+        let mut stream = self.open_tar(src).await?;
+        let bytes = tokio::io::copy(&mut stream, writer).await?;
+        writer.flush().await?;
+
+        if bytes == 0 {
+            return Err(empty_archive(src));
+        }
+        Ok(bytes)
+    }
+
+    /// Like [`Device::pull_dir_tar`], unpacking into `dest_dir` while the
+    /// archive arrives.
+    pub async fn pull_dir_tar_unpack(&self, src: &UnixPath, dest_dir: &Path) -> Result<u64> {
+        let mut stream = self.open_tar(src).await?;
+
+        let (sender, receiver) = mpsc::channel(16);
+        let dest_dir = dest_dir.to_path_buf();
+        let unpack = tokio::task::spawn_blocking(move || {
+            tar::Archive::new(ChannelReader {
+                receiver,
+                chunk: Bytes::new(),
+            })
+            .unpack(dest_dir)
+        });
+
+        let mut bytes = 0u64;
+        let mut buf = BytesMut::with_capacity(64 * 1024);
+        loop {
+            buf.reserve(64 * 1024);
+            if stream.read_buf(&mut buf).await? == 0 {
+                break;
+            }
+            bytes += buf.len() as u64;
+            // A closed channel means unpacking failed; the error is below.
+            if sender.send(buf.split().freeze()).await.is_err() {
+                break;
+            }
+        }
+        drop(sender);
+
+        unpack.await.map_err(io::Error::other)??;
+        if bytes == 0 {
+            return Err(empty_archive(src));
+        }
+        Ok(bytes)
+    }
+
+    /// Returns the tar archive of `src` as a reader, to be piped into
+    /// compression, encryption or an upload without unpacking it.
+    ///
+    /// The archive is the one [`Device::pull_dir_tar`] writes.  When `tar`
+    /// produces no output, e.g. because `src` does not exist, reading fails
+    /// at the end instead of returning an empty archive.  Dropping the
+    /// reader closes the connection, which stops `tar` on the device.
+    pub async fn pull_dir_stream_tar(
+        &self,
+        src: &UnixPath,
+    ) -> Result<impl AsyncRead + Send + Unpin> {
+        Ok(TarReader {
+            stream: self.open_tar(src).await?,
+            src: src.to_path_buf(),
+            bytes: 0,
+        })
+    }
+
+    /// Streams the tar archive read from `reader` to the device and
+    /// extracts it into `dest_dir`, which is created if missing.  Returns
+    /// the number of bytes sent.
+    ///
+    /// One `exec:tar -xf -` connection replaces a sync round trip per file,
+    /// which matters when provisioning thousands of small files.  `reader`
+    /// must yield a complete archive: `tar` only exits once it reads the
+    /// end-of-archive blocks.  Anything `tar` prints, such as a corrupt
+    /// archive, fails with [`DeviceError::Adb`].
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial, dest_dir = %dest_dir.display()), err)
+    )]
+    pub async fn push_tar<R: AsyncRead + Unpin>(
+        &self,
+        reader: &mut R,
+        dest_dir: &UnixPath,
+    ) -> Result<u64> {
+        self.check_writable("push files")?;
+        self.create_dir(dest_dir).await?;
+
+        let command = format!("tar -xf - -C {}", DevicePath::new(dest_dir)?.quoted());
+        let command = if self.enable_run_as_for_path(dest_dir) {
+            let package = self
+                .run_as_package
+                .as_ref()
+                .ok_or(DeviceError::MissingPackage)?;
+            format!("run-as {package} {command}")
+        } else {
+            self.su.wrap(&command).unwrap_or(command)
+        };
+        let mut stream = self.open_service(&format!("exec:{command} 2>&1")).await?;
+        let bytes = tokio::io::copy(reader, &mut stream).await?;
+        stream.flush().await?;
+
+        // The connection closes once `tar` has extracted everything.
+        let mut output = String::new();
+        stream.read_to_string(&mut output).await?;
+        match output.trim() {
+            "" => Ok(bytes),
+            message => Err(DeviceError::Adb(format!(
+                "tar into {} failed: {message}",
+                dest_dir.display()
+            ))),
+        }
+    }
+
+    /// Starts `tar` over `src`, with entries relative to it.
+    async fn open_tar(&self, src: &UnixPath) -> Result<BoxedTransport> {
+        self.open_exec(
+            &format!("tar -cf - -C {} .", DevicePath::new(src)?.quoted()),
+            self.enable_run_as_for_path(src),
+        )
+        .await
+    }
+
+    /// Streams the private data of `package`, i.e. `shared_prefs`,
+    /// `databases`, `files` and the rest of its data directory, as a tar
+    /// archive into `writer` and returns the archive size.
+    ///
+    /// The sync protocol cannot read these directories.  With
+    /// [`Device::su`] set `tar` runs as root, otherwise through `run-as`,
+    /// which fails with [`DeviceError::NotDebuggable`] unless `package` is
+    /// debuggable.
+    pub async fn backup_app_data<W: AsyncWrite + Unpin>(
+        &self,
+        package: &str,
+        writer: &mut W,
+    ) -> Result<u64> {
+        self.backup_app_data_with_progress(package, writer, |_| {})
+            .await
+    }
+
+    /// Like [`Device::backup_app_data`], reporting the bytes received.  The
+    /// archive size is not known up front, so `total_bytes` is `None`.
+    pub async fn backup_app_data_with_progress<W: AsyncWrite + Unpin>(
+        &self,
+        package: &str,
+        writer: &mut W,
+        progress: impl ProgressSink<FileTransferProgress>,
+    ) -> Result<u64> {
+        // `package` goes unquoted into commands that may run as root.
+        if package.is_empty()
+            || !package
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_'))
+        {
+            return Err(DeviceError::Adb(format!(
+                "invalid package name '{package}'"
+            )));
+        }
+        let data_dir = format!("/data/user/{}/{package}", self.user.unwrap_or(0));
+        let mut stream = if self.su == SuStrategy::None {
+            let output = self.run(&format!("run-as {package} true")).await?;
+            if !output.success() {
+                let mut message = output.stderr_lossy();
+                if message.is_empty() {
+                    message = output.stdout_lossy();
+                }
+                return Err(DeviceError::NotDebuggable(
+                    package.to_owned(),
+                    message.trim().to_owned(),
+                ));
+            }
+            // `run-as` starts in the data directory.
+            self.open_service(&format!("exec:run-as {package} tar -cf - . 2>/dev/null"))
+                .await?
+        } else {
+            self.open_exec(&format!("tar -cf - -C {data_dir} ."), false)
+                .await?
+        };
+
+        let interval = self.progress_granularity.interval(None);
+        let mut bytes = 0u64;
+        let mut last_progress = 0;
+        let mut buf = PooledBuffer::filled(64 * 1024);
+        loop {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            writer.write_all(&buf[..n]).await?;
+            bytes += n as u64;
+            if bytes - last_progress >= interval {
+                progress.report(FileTransferProgress {
+                    total_bytes: None,
+                    transferred_bytes: bytes,
+                    sparse_bytes: 0,
+                });
+                last_progress = bytes;
+            }
+        }
+        writer.flush().await?;
+        progress.report(FileTransferProgress {
+            total_bytes: None,
+            transferred_bytes: bytes,
+            sparse_bytes: 0,
+        });
+
+        if bytes == 0 {
+            return Err(empty_archive(UnixPath::new(&data_dir)));
+        }
+        Ok(bytes)
+    }
+}
+
+/// `tar` writes nothing when it fails, e.g. because `src` does not exist.
+fn empty_archive(src: &UnixPath) -> DeviceError {
+    DeviceError::Adb(format!("tar of {} produced no output", src.display()))
+}
@@ -0,0 +1,309 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Chain-of-custody log of everything sent to a device.
+//!
+//! Each line of the log is a JSON record of one service request, e.g.
+//! `shell:id`, or one file transfer with its size and SHA-256 digest.
+//! Records are hash-chained: `chain` is the SHA-256 of the previous
+//! record's `chain` followed by the record itself, so removing or editing a
+//! line breaks every later one.  With a signing key each record also
+//! carries `signature`, an HMAC-SHA256 of its `chain`.
+//!
+//! ```no_run
+//! # async fn example() -> forensic_adb::Result<()> {
+//! use forensic_adb::{AuditLog, Device, Host};
+//! use std::sync::Arc;
+//!
+//! let log = AuditLog::open("case-42.jsonl".as_ref())?.signing_key(b"examiner key");
+//! let device = Device::builder(Host::default())
+//!     .audit_log(Arc::new(log))
+//!     .build()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::export::json_string;
+use crate::UnixPath;
+
+/// `chain` of the record before the first one.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Direction of an audited file transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferDirection {
+    Pull,
+    Push,
+}
+
+impl fmt::Display for TransferDirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TransferDirection::Pull => "pull",
+            TransferDirection::Push => "push",
+        })
+    }
+}
+
+/// Append-only JSONL audit log, attached with
+/// [`DeviceBuilder::audit_log`](crate::DeviceBuilder::audit_log).
+///
+/// Failing to write a record fails the operation being recorded.
+pub struct AuditLog {
+    key: Option<Vec<u8>>,
+    state: Mutex<AuditState>,
+}
+
+struct AuditState {
+    file: File,
+    sequence: u64,
+    chain: String,
+}
+
+impl fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuditLog")
+            .field("signed", &self.key.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl AuditLog {
+    /// Opens the log at `path`, continuing the chain of its existing
+    /// records.
+    pub fn open(path: &Path) -> io::Result<AuditLog> {
+        let mut sequence = 0;
+        let mut chain = GENESIS.to_owned();
+        if path.exists() {
+            for line in BufReader::new(File::open(path)?).lines() {
+                let line = line?;
+                if line.is_empty() {
+                    continue;
+                }
+                let (_, fields) = split_record(&line).ok_or_else(|| invalid(&line))?;
+                chain = fields.chain.to_owned();
+                sequence += 1;
+            }
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog {
+            key: None,
+            state: Mutex::new(AuditState {
+                file,
+                sequence,
+                chain,
+            }),
+        })
+    }
+
+    /// Signs records with an HMAC-SHA256 under `key`.
+    pub fn signing_key(mut self, key: &[u8]) -> AuditLog {
+        self.key = Some(key.to_vec());
+        self
+    }
+
+    /// Checks the chain, and with `key` the signatures, of the log at `path`
+    /// and returns the number of records.
+    pub fn verify(path: &Path, key: Option<&[u8]>) -> io::Result<u64> {
+        let mut records = 0;
+        let mut chain = GENESIS.to_owned();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let (body, fields) = split_record(&line).ok_or_else(|| invalid(&line))?;
+            chain = chain_hash(&chain, &body);
+            if fields.chain != chain {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("audit record {} does not match the chain", records + 1),
+                ));
+            }
+            if let Some(key) = key {
+                if fields.signature != Some(hmac_sha256(key, chain.as_bytes()).as_str()) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("audit record {} has a bad signature", records + 1),
+                    ));
+                }
+            }
+            records += 1;
+        }
+        Ok(records)
+    }
+
+    /// Records a request for `service` on the device `serial`.
+    pub(crate) fn command(&self, serial: &str, service: &str) -> io::Result<()> {
+        self.append(
+            serial,
+            &format!(
+                "\"event\": \"command\", \"service\": {}",
+                json_string(service)
+            ),
+        )
+    }
+
+    /// Records a completed transfer of `device_path`.
+    pub(crate) fn transfer(
+        &self,
+        serial: &str,
+        direction: TransferDirection,
+        device_path: &UnixPath,
+        bytes: u64,
+        sha256: &str,
+    ) -> io::Result<()> {
+        self.append(
+            serial,
+            &format!(
+                "\"event\": \"{direction}\", \"path\": {}, \"bytes\": {bytes}, \"sha256\": \"{sha256}\"",
+                json_string(&device_path.display().to_string())
+            ),
+        )
+    }
+
+    fn append(&self, serial: &str, fields: &str) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let body = format!(
+            "{{\"seq\": {}, \"time_ms\": {time}, \"serial\": {}, {fields}}}",
+            state.sequence + 1,
+            json_string(serial)
+        );
+        let chain = chain_hash(&state.chain, &body);
+
+        let mut line = body[..body.len() - 1].to_owned();
+        line.push_str(&format!(", \"chain\": \"{chain}\""));
+        if let Some(key) = &self.key {
+            line.push_str(&format!(
+                ", \"signature\": \"{}\"",
+                hmac_sha256(key, chain.as_bytes())
+            ));
+        }
+        line.push_str("}\n");
+
+        state.file.write_all(line.as_bytes())?;
+        state.file.flush()?;
+        state.sequence += 1;
+        state.chain = chain;
+        Ok(())
+    }
+}
+
+/// The `chain` and `signature` fields of a record.
+struct RecordFields<'a> {
+    chain: &'a str,
+    signature: Option<&'a str>,
+}
+
+/// Splits a line into the body the chain was computed over and the fields
+/// appended to it.  Strings in the body are escaped, so the field names
+/// cannot occur in it unescaped.
+fn split_record(line: &str) -> Option<(String, RecordFields<'_>)> {
+    let start = line.rfind(", \"chain\": \"")?;
+    let rest = line[start..].strip_prefix(", \"chain\": \"")?;
+    let (chain, rest) = rest.split_once('"')?;
+    let signature = match rest.strip_prefix(", \"signature\": \"") {
+        Some(rest) => Some(rest.split_once('"')?.0),
+        None => None,
+    };
+    Some((
+        format!("{}}}", &line[..start]),
+        RecordFields { chain, signature },
+    ))
+}
+
+fn chain_hash(previous: &str, body: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(previous.as_bytes());
+    hasher.update(body.as_bytes());
+    hex(&hasher.finalize())
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> String {
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(block.map(|b| b ^ 0x36));
+    inner.update(data);
+    let mut outer = Sha256::new();
+    outer.update(block.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+    hex(&outer.finalize())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn invalid(line: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("not an audit record: {line}"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hmac_test_vector() {
+        // RFC 4231, test case 2.
+        assert_eq!(
+            hmac_sha256(b"Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn chained_records() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("audit.jsonl");
+
+        let log = AuditLog::open(&path).expect("open").signing_key(b"key");
+        log.command("emulator-5554", "shell:echo \", \"chain\": \"")
+            .expect("record");
+        log.transfer(
+            "emulator-5554",
+            TransferDirection::Pull,
+            UnixPath::new("/sdcard/a.txt"),
+            3,
+            "abc",
+        )
+        .expect("record");
+        drop(log);
+
+        // Reopening continues the chain.
+        AuditLog::open(&path)
+            .expect("reopen")
+            .signing_key(b"key")
+            .command("emulator-5554", "shell:id")
+            .expect("record");
+        assert_eq!(AuditLog::verify(&path, Some(b"key")).expect("verify"), 3);
+        assert!(AuditLog::verify(&path, Some(b"other")).is_err());
+
+        let log = std::fs::read_to_string(&path).expect("read");
+        std::fs::write(&path, log.replacen("a.txt", "b.txt", 1)).expect("write");
+        assert!(AuditLog::verify(&path, None).is_err());
+    }
+}
@@ -0,0 +1,137 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Running many small commands in one shell session.
+//!
+//! Triage usually means dozens of short probes (`getprop`, `id`, `ls`, ...).
+//! Connecting to the server and switching the transport for each of them
+//! dominates the runtime, so [`Device::run_batch`] joins them into a single
+//! script and splits the output again at markers.
+
+use crate::{Device, DeviceError, Result, ShellOutput};
+
+/// Follows the output of each command in the batch, together with its index
+/// and, on stdout, its exit code.
+pub(crate) const BATCH_MARKER: &str = "\x1fforensic-adb-batch:";
+
+/// Joins `commands` into one script that prints a marker after each of them.
+///
+/// Every command runs in its own subshell on its own lines, so `exit`, `cd`
+/// or a trailing comment only affect that command.
+pub(crate) fn batch_script(commands: &[&str], separate_stderr: bool) -> String {
+    commands
+        .iter()
+        .enumerate()
+        .map(|(i, command)| {
+            let mut part = format!("(\n{command}\n); echo \"{BATCH_MARKER}{i}:$?\"");
+            if separate_stderr {
+                part.push_str(&format!("; echo \"{BATCH_MARKER}{i}\" >&2"));
+            }
+            part
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Cuts off everything up to and including the end of the current line.
+fn skip_line(output: &[u8]) -> (&[u8], &[u8]) {
+    match output.iter().position(|&b| b == b'\n') {
+        Some(end) => (&output[..end], &output[end + 1..]),
+        None => (output, &[]),
+    }
+}
+
+/// Splits the combined output of a script built by [`batch_script`].
+pub(crate) fn parse_batch_output(
+    count: usize,
+    stdout: &[u8],
+    stderr: &[u8],
+) -> Result<Vec<ShellOutput>> {
+    let marker = BATCH_MARKER.as_bytes();
+    let missing = |i: usize| DeviceError::Adb(format!("batch output is missing command {i}"));
+
+    let mut outputs = Vec::with_capacity(count);
+    let mut stdout = stdout;
+    for i in 0..count {
+        let position = find(stdout, marker).ok_or_else(|| missing(i))?;
+        let (status, rest) = skip_line(&stdout[position + marker.len()..]);
+        let (index, exit_code) = std::str::from_utf8(status)?
+            .trim()
+            .split_once(':')
+            .ok_or_else(|| missing(i))?;
+        if index.parse::<usize>()? != i {
+            return Err(missing(i));
+        }
+
+        outputs.push(ShellOutput {
+            stdout: stdout[..position].to_vec(),
+            stderr: Vec::new(),
+            exit_code: exit_code.parse()?,
+        });
+        stdout = rest;
+    }
+
+    let mut stderr = stderr;
+    for output in outputs.iter_mut() {
+        let Some(position) = find(stderr, marker) else {
+            break;
+        };
+        output.stderr = stderr[..position].to_vec();
+        stderr = skip_line(&stderr[position..]).1;
+    }
+
+    Ok(outputs)
+}
+
+impl Device {
+    /// Runs `commands` one after another in a single shell session and
+    /// returns the output and exit code of each, in order.
+    ///
+    /// Commands run in separate subshells, so a failing command does not
+    /// stop the rest of the batch.  Like [`Device::run`], stderr is only
+    /// available separately on devices with shell v2, and [`Device::su`] is
+    /// not applied.
+    pub async fn run_batch(&self, commands: &[&str]) -> Result<Vec<ShellOutput>> {
+        if commands.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let separate_stderr = self.supports_shell_v2().await?;
+        let output = self.run(&batch_script(commands, separate_stderr)).await?;
+
+        parse_batch_output(commands.len(), &output.stdout, &output.stderr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_output_per_command() {
+        let stdout = format!("a\n{BATCH_MARKER}0:0\nno newline{BATCH_MARKER}1:2\r\n");
+        let stderr = format!("{BATCH_MARKER}0\noops\n{BATCH_MARKER}1\n");
+        let outputs = parse_batch_output(2, stdout.as_bytes(), stderr.as_bytes()).expect("parsed");
+
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(outputs[0].stdout, b"a\n");
+        assert!(outputs[0].stderr.is_empty());
+        assert!(outputs[0].success());
+        assert_eq!(outputs[1].stdout, b"no newline");
+        assert_eq!(outputs[1].stderr, b"oops\n");
+        assert_eq!(outputs[1].exit_code, 2);
+    }
+
+    #[test]
+    fn missing_command_output() {
+        let stdout = format!("a\n{BATCH_MARKER}0:0\n");
+        assert!(parse_batch_output(2, stdout.as_bytes(), b"").is_err());
+    }
+}
@@ -0,0 +1,147 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Enrolled fingerprints and faces, which decide how a locked device can be
+//! unlocked and are documented before an examination.
+
+use crate::{Device, Result};
+
+/// Enrolled templates of one user, from [`Device::biometric_status`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BiometricEnrollment {
+    pub user_id: u32,
+    /// Enrolled fingers or faces.
+    pub templates: u32,
+    /// Successful authentications since boot.
+    pub accepted: u32,
+    /// Failed authentications since boot.
+    pub rejected: u32,
+}
+
+/// Result of [`Device::biometric_status`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BiometricStatus {
+    /// `None` when the device has no fingerprint service.
+    pub fingerprint: Option<Vec<BiometricEnrollment>>,
+    /// `None` when the device has no face service; vendor face unlock
+    /// outside it is not reported.
+    pub face: Option<Vec<BiometricEnrollment>>,
+}
+
+impl BiometricStatus {
+    pub fn fingerprint_enrolled(&self) -> bool {
+        enrolled(&self.fingerprint)
+    }
+
+    pub fn face_enrolled(&self) -> bool {
+        enrolled(&self.face)
+    }
+}
+
+fn enrolled(enrollments: &Option<Vec<BiometricEnrollment>>) -> bool {
+    enrollments
+        .iter()
+        .flatten()
+        .any(|enrollment| enrollment.templates > 0)
+}
+
+impl Device {
+    /// Reports, per user, how many fingerprints and faces are enrolled, from
+    /// `dumpsys fingerprint` and `dumpsys face` in one shell round trip.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn biometric_status(&self) -> Result<BiometricStatus> {
+        let outputs = self
+            .run_batch(&["dumpsys fingerprint", "dumpsys face"])
+            .await?;
+        let parse = |i: usize| {
+            let stdout = outputs[i].stdout_lossy();
+            let missing = |text: &str| text.contains("Can't find service");
+            match missing(&stdout) || missing(&outputs[i].stderr_lossy()) {
+                true => None,
+                false => Some(parse_enrollments(&stdout)),
+            }
+        };
+
+        Ok(BiometricStatus {
+            fingerprint: parse(0),
+            face: parse(1),
+        })
+    }
+}
+
+/// Parses the JSON summaries biometric services print, one per sensor:
+/// `{"service":"Fingerprint Manager","prints":[{"id":0,"count":2,
+/// "accept":15,"reject":3,...}]}`, where `id` is the user.  Sensors of the
+/// same user are added up.
+fn parse_enrollments(output: &str) -> Vec<BiometricEnrollment> {
+    let mut enrollments: Vec<BiometricEnrollment> = Vec::new();
+    for (start, _) in output.match_indices("\"prints\":[") {
+        let list = &output[start + "\"prints\":[".len()..];
+        let list = list.split(']').next().unwrap_or_default();
+        for object in list.split('}') {
+            let number = |key: &str| {
+                let start = object.find(&format!("\"{key}\":"))? + key.len() + 3;
+                object[start..]
+                    .split([',', '}'])
+                    .next()?
+                    .trim()
+                    .parse::<u32>()
+                    .ok()
+            };
+            let Some(user_id) = number("id") else {
+                continue;
+            };
+            let index = match enrollments.iter().position(|e| e.user_id == user_id) {
+                Some(index) => index,
+                None => {
+                    enrollments.push(BiometricEnrollment {
+                        user_id,
+                        ..BiometricEnrollment::default()
+                    });
+                    enrollments.len() - 1
+                }
+            };
+            let enrollment = &mut enrollments[index];
+            enrollment.templates += number("count").unwrap_or_default();
+            enrollment.accepted += number("accept").unwrap_or_default();
+            enrollment.rejected += number("reject").unwrap_or_default();
+        }
+    }
+    enrollments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_enrollments() {
+        let enrollments = parse_enrollments(
+            "Fingerprint Manager State:\n\
+             {\"service\":\"Fingerprint Manager\",\"prints\":[{\"id\":0,\"count\":2,\"accept\":15,\"reject\":3,\"acceptCrypto\":0,\"rejectCrypto\":0,\"permanentLockout\":0,\"temporaryLockout\":1},{\"id\":10,\"count\":0,\"accept\":0,\"reject\":0}]}\n\
+             {\"service\":\"Fingerprint Manager\",\"sensorId\":1,\"prints\":[{\"id\":0,\"count\":1,\"accept\":2,\"reject\":0}]}\n",
+        );
+        assert_eq!(
+            enrollments,
+            [
+                BiometricEnrollment {
+                    user_id: 0,
+                    templates: 3,
+                    accepted: 17,
+                    rejected: 3,
+                },
+                BiometricEnrollment {
+                    user_id: 10,
+                    templates: 0,
+                    accepted: 0,
+                    rejected: 0,
+                },
+            ]
+        );
+        assert!(parse_enrollments("{\"service\":\"Face Manager\",\"prints\":[]}").is_empty());
+    }
+}
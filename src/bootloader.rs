@@ -0,0 +1,78 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Bootloader lock and OEM unlock settings, which decide whether images can
+//! be flashed for an acquisition.
+
+use crate::locale::setting;
+use crate::verified_boot::bootloader_locked;
+use crate::{Device, Result};
+
+const BOOTLOADER_PROPERTIES: [&str; 7] = [
+    "getprop ro.boot.flash.locked",
+    "getprop ro.boot.vbmeta.device_state",
+    "getprop ro.oem_unlock_supported",
+    "getprop sys.oem_unlock_allowed",
+    "getprop ro.boot.warranty_bit",
+    "getprop ro.warranty_bit",
+    "getprop ro.bootloader",
+];
+
+/// Result of [`Device::bootloader_status`]; fields are `None` when the
+/// device does not expose them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BootloaderStatus {
+    pub locked: Option<bool>,
+    /// Whether the device can be OEM unlocked at all
+    /// (`ro.oem_unlock_supported`).
+    pub oem_unlock_supported: Option<bool>,
+    /// The "OEM unlocking" developer option (`sys.oem_unlock_allowed`),
+    /// which `fastboot flashing unlock` requires.
+    pub oem_unlock_allowed: Option<bool>,
+    /// Samsung's KNOX warranty bit, set for good once unsigned software
+    /// booted.
+    pub warranty_void: Option<bool>,
+    /// Bootloader version, e.g. `G991BXXU5CVF3`.
+    pub version: Option<String>,
+}
+
+impl BootloaderStatus {
+    /// Whether images can be flashed now.
+    pub fn is_flashable(&self) -> bool {
+        self.locked == Some(false)
+    }
+
+    /// Whether images can be flashed now or after `fastboot flashing
+    /// unlock`, which wipes user data.
+    pub fn can_unlock(&self) -> bool {
+        self.is_flashable()
+            || (self.oem_unlock_allowed == Some(true) && self.oem_unlock_supported != Some(false))
+    }
+}
+
+impl Device {
+    /// Reports the bootloader lock, the OEM unlock settings and the KNOX
+    /// warranty bit from `getprop`, in one shell round trip.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn bootloader_status(&self) -> Result<BootloaderStatus> {
+        let outputs = self.run_batch(&BOOTLOADER_PROPERTIES).await?;
+        let value = |i: usize| setting(&outputs[i]);
+        let flag = |i: usize| match value(i).as_deref() {
+            Some("1") => Some(true),
+            Some("0") => Some(false),
+            _ => None,
+        };
+
+        Ok(BootloaderStatus {
+            locked: bootloader_locked(value(0), value(1)),
+            oem_unlock_supported: flag(2),
+            oem_unlock_allowed: flag(3),
+            warranty_void: flag(4).or_else(|| flag(5)),
+            version: value(6).filter(|version| version != "unknown"),
+        })
+    }
+}
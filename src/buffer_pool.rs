@@ -0,0 +1,105 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Transfer buffers shared by all devices, so that many concurrent sync
+//! operations do not allocate and zero a fresh buffer each.
+
+use bytes::BytesMut;
+use once_cell::sync::Lazy;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+/// Buffers kept for reuse; more are freed when returned.
+const MAX_POOLED_BUFFERS: usize = 64;
+/// Larger buffers are not kept.
+const MAX_POOLED_CAPACITY: usize = 1024 * 1024;
+
+static POOL: Lazy<Mutex<Vec<BytesMut>>> = Lazy::new(Default::default);
+
+/// A buffer taken from the pool, returned to it when dropped.
+#[derive(Debug)]
+pub(crate) struct PooledBuffer(BytesMut);
+
+impl PooledBuffer {
+    /// An empty buffer with room for at least `capacity` bytes, for
+    /// `read_buf`.
+    pub(crate) fn empty(capacity: usize) -> PooledBuffer {
+        let mut buf = take(capacity);
+        buf.clear();
+        PooledBuffer(buf)
+    }
+
+    /// A buffer of `len` initialized bytes, for `read`.  The contents are
+    /// left over from earlier use; only bytes beyond the previous length of
+    /// a reused buffer are zeroed.
+    pub(crate) fn filled(len: usize) -> PooledBuffer {
+        let mut buf = take(len);
+        if buf.len() < len {
+            buf.resize(len, 0);
+        }
+        buf.truncate(len);
+        PooledBuffer(buf)
+    }
+}
+
+fn take(capacity: usize) -> BytesMut {
+    let mut pool = POOL.lock().unwrap_or_else(|e| e.into_inner());
+    match pool.iter().position(|buf| buf.capacity() >= capacity) {
+        Some(index) => pool.swap_remove(index),
+        None => BytesMut::with_capacity(capacity),
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = BytesMut;
+
+    fn deref(&self) -> &BytesMut {
+        &self.0
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut BytesMut {
+        &mut self.0
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let buf = std::mem::take(&mut self.0);
+        if buf.capacity() > MAX_POOLED_CAPACITY {
+            return;
+        }
+        let mut pool = POOL.lock().unwrap_or_else(|e| e.into_inner());
+        if pool.len() < MAX_POOLED_BUFFERS {
+            pool.push(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuses_buffers() {
+        let mut buf = PooledBuffer::filled(1000);
+        buf[0] = 7;
+        let ptr = buf.as_ptr();
+        drop(buf);
+
+        // Other tests share the pool, so the same buffer is not guaranteed;
+        // any buffer handed out must still be sized as requested.
+        let buf = PooledBuffer::filled(1000);
+        assert_eq!(buf.len(), 1000);
+        if buf.as_ptr() == ptr {
+            assert_eq!(buf[0], 7);
+        }
+        drop(buf);
+
+        let buf = PooledBuffer::empty(500);
+        assert!(buf.is_empty());
+        assert!(buf.capacity() >= 500);
+    }
+}
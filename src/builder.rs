@@ -0,0 +1,222 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::sync::Arc;
+
+use crate::adb::DeviceSerial;
+use crate::{
+    AndroidStorageInput, AuditLog, Device, DeviceError, Host, Result, SuStrategy, Timeouts,
+    UnixPath, UnixPathBuf,
+};
+
+/// Configures a [`Device`] before it is used.
+///
+/// ```no_run
+/// # async fn example() -> forensic_adb::Result<()> {
+/// use forensic_adb::{Device, Host, SuStrategy};
+///
+/// let device = Device::builder(Host::default())
+///     .serial("emulator-5554")
+///     .su(SuStrategy::Su0)
+///     .user(10)
+///     .build()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct DeviceBuilder {
+    host: Host,
+    serial: Option<DeviceSerial>,
+    run_as_package: Option<String>,
+    storage: Option<AndroidStorageInput>,
+    tempfile_dir: Option<UnixPathBuf>,
+    su: SuStrategy,
+    timeouts: Option<Timeouts>,
+    user: Option<u32>,
+    pull_concurrency: Option<usize>,
+    pull_to_vec_limit: Option<u64>,
+    audit: Option<Arc<AuditLog>>,
+    read_only: bool,
+    remove_roots: Vec<UnixPathBuf>,
+}
+
+impl DeviceBuilder {
+    pub fn new(host: Host) -> DeviceBuilder {
+        DeviceBuilder {
+            host,
+            serial: None,
+            run_as_package: None,
+            storage: None,
+            tempfile_dir: None,
+            su: SuStrategy::None,
+            timeouts: None,
+            user: None,
+            pull_concurrency: None,
+            pull_to_vec_limit: None,
+            audit: None,
+            read_only: false,
+            remove_roots: Vec::new(),
+        }
+    }
+
+    /// Selects the device by serial.  Without it the single online device,
+    /// or the one named by `ANDROID_SERIAL`, is used.
+    pub fn serial<T: Into<DeviceSerial>>(mut self, serial: T) -> DeviceBuilder {
+        self.serial = Some(serial.into());
+        self
+    }
+
+    /// Runs commands on the app's private data as `package`, see
+    /// [`Device::with_run_as`].
+    pub fn run_as_package<T: Into<String>>(mut self, package: T) -> DeviceBuilder {
+        self.run_as_package = Some(package.into());
+        self
+    }
+
+    /// Selects the storage once the device is found, see
+    /// [`Device::select_storage`].
+    pub fn storage(mut self, storage: AndroidStorageInput) -> DeviceBuilder {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// Directory for temporary files, see [`Device::tempfile_dir`].
+    pub fn tempfile_dir<T: AsRef<UnixPath>>(mut self, dir: T) -> DeviceBuilder {
+        self.tempfile_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn su(mut self, su: SuStrategy) -> DeviceBuilder {
+        self.su = su;
+        self
+    }
+
+    /// Overrides the timeouts inherited from the host.
+    pub fn timeouts(mut self, timeouts: Timeouts) -> DeviceBuilder {
+        self.timeouts = Some(timeouts);
+        self
+    }
+
+    /// Android user that `pm` and `am` commands operate on.
+    pub fn user(mut self, user: u32) -> DeviceBuilder {
+        self.user = Some(user);
+        self
+    }
+
+    /// Number of files pulled at the same time by [`Device::pull_dir`].
+    pub fn pull_concurrency(mut self, files: usize) -> DeviceBuilder {
+        self.pull_concurrency = Some(files);
+        self
+    }
+
+    /// Largest file [`Device::pull_to_vec`] reads, see
+    /// [`Device::pull_to_vec_limit`].
+    pub fn pull_to_vec_limit(mut self, bytes: u64) -> DeviceBuilder {
+        self.pull_to_vec_limit = Some(bytes);
+        self
+    }
+
+    /// Records the requests and transfers of the device in `log`, from the
+    /// checks of [`DeviceBuilder::run_as_package`] on.
+    pub fn audit_log(mut self, log: Arc<AuditLog>) -> DeviceBuilder {
+        self.audit = Some(log);
+        self
+    }
+
+    /// Blocks operations that modify the device, see [`Device::read_only`].
+    pub fn read_only(mut self, read_only: bool) -> DeviceBuilder {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Confines recursive removals to paths below `roots`, see
+    /// [`Device::remove_dir_all`].
+    pub fn remove_roots<I, P>(mut self, roots: I) -> DeviceBuilder
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<UnixPath>,
+    {
+        self.remove_roots = roots
+            .into_iter()
+            .map(|root| root.as_ref().to_path_buf())
+            .collect();
+        self
+    }
+
+    /// Looks up the device and applies the configuration.
+    pub async fn build(self) -> Result<Device> {
+        let mut device = self.host.device_or_default(self.serial.as_ref()).await?;
+
+        if let Some(timeouts) = self.timeouts {
+            device.timeouts = timeouts;
+        }
+        if let Some(dir) = self.tempfile_dir {
+            device.tempfile_dir = dir;
+        }
+        if let Some(files) = self.pull_concurrency {
+            device.pull_concurrency = files;
+        }
+        if let Some(bytes) = self.pull_to_vec_limit {
+            device.pull_to_vec_limit = bytes;
+        }
+        device.su = self.su;
+        device.user = self.user;
+        device.audit = self.audit;
+        device.read_only = self.read_only;
+        device.remove_roots = self.remove_roots;
+
+        if let Some(package) = self.run_as_package {
+            device = device.with_run_as(&package).await?;
+        }
+        if let Some(storage) = self.storage {
+            device.select_storage(storage).await?;
+        }
+
+        Ok(device)
+    }
+}
+
+impl Device {
+    /// Sets [`Device::run_as_package`] after checking that `run-as` works
+    /// for the package, i.e. that it is installed and debuggable.
+    pub async fn with_run_as(mut self, package: &str) -> Result<Device> {
+        // The package ends up unquoted in every `run-as` command line.
+        if package.is_empty()
+            || !package
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_'))
+        {
+            return Err(DeviceError::Adb(format!(
+                "invalid package name '{package}'"
+            )));
+        }
+        let output = self.run(&format!("run-as {package} true")).await?;
+        if !output.success() {
+            let mut message = output.stderr_lossy();
+            if message.is_empty() {
+                message = output.stdout_lossy();
+            }
+            return Err(DeviceError::NotDebuggable(
+                package.to_owned(),
+                message.trim().to_owned(),
+            ));
+        }
+
+        self.run_as_package = Some(package.to_owned());
+        Ok(self)
+    }
+
+    /// Makes operations that modify the device, such as pushes, removals,
+    /// `chmod`, installs and clearing app data, fail with
+    /// [`DeviceError::WriteBlocked`], so evidence is not changed by
+    /// accident.  Commands passed to the `execute_*` methods are not checked.
+    ///
+    /// To write anyway, override it on a copy:
+    /// `device.clone().read_only(false)`.
+    pub fn read_only(mut self, read_only: bool) -> Device {
+        self.read_only = read_only;
+        self
+    }
+}
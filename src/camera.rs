@@ -0,0 +1,375 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Cameras with their characteristics and the apps that recently opened
+//! them, from the camera service.
+
+use crate::{Device, Result};
+
+/// `HAL_PIXEL_FORMAT_BLOB`, the format of JPEG stream configurations.
+const FORMAT_JPEG: u32 = 33;
+
+/// Direction a camera faces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraFacing {
+    Back,
+    Front,
+    External,
+}
+
+/// An output size of a camera.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CameraResolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CameraResolution {
+    pub fn megapixels(&self) -> f64 {
+        f64::from(self.width) * f64::from(self.height) / 1_000_000.0
+    }
+}
+
+/// What a [`CameraUsageEvent`] records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraUsageAction {
+    /// An app opened the camera.
+    Connect,
+    /// An app closed the camera.
+    Disconnect,
+    /// An app lost the camera to one with higher priority.
+    Evict,
+    /// Opening the camera was refused, e.g. because it was disabled.
+    Reject,
+}
+
+/// An entry of the camera service's event log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CameraUsageEvent {
+    /// Device local time as logged, `MM-dd HH:mm:ss` without a year.
+    pub timestamp: String,
+    pub action: CameraUsageAction,
+    pub package: String,
+    pub pid: Option<u32>,
+}
+
+/// A camera of [`Device::cameras`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Camera {
+    /// Camera id as used by the camera2 API, e.g. `0`.
+    pub id: String,
+    pub facing: Option<CameraFacing>,
+    /// Clockwise rotation of the sensor in degrees.
+    pub orientation: Option<u32>,
+    pub has_flash: Option<bool>,
+    /// JPEG output sizes, largest first.
+    pub resolutions: Vec<CameraResolution>,
+    /// Package holding the camera open at the time of the call.
+    pub active_client: Option<String>,
+    /// Usage events, most recent first; the log keeps the last 100 events
+    /// of all cameras and is cleared by a reboot.
+    pub usage: Vec<CameraUsageEvent>,
+}
+
+impl Device {
+    /// Lists the cameras with their facing, orientation and resolutions,
+    /// and which apps recently opened or closed each one.
+    ///
+    /// Reads `dumpsys media.camera`, which needs the `shell` user or
+    /// root.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn cameras(&self) -> Result<Vec<Camera>> {
+        let output = self
+            .execute_host_shell_command("dumpsys media.camera")
+            .await?;
+        Ok(parse_cameras(&output))
+    }
+}
+
+enum Section {
+    Events,
+    Static(usize),
+    Other,
+}
+
+/// Parses the `== ... ==` sections of `dumpsys media.camera`: the static
+/// info of each camera (`== Camera HAL device device@3.5/legacy/0 (v3.5)
+/// static information: ==`, `== Camera device 0 static info: ==` before
+/// Android 10), the active clients and the event log.
+fn parse_cameras(output: &str) -> Vec<Camera> {
+    let mut cameras: Vec<Camera> = Vec::new();
+    let mut clients = Vec::new();
+    let mut events = Vec::new();
+    let mut section = Section::Other;
+    // Stream configurations span several lines after their key.
+    let mut configurations: Option<Vec<u32>> = None;
+
+    for line in output.lines().map(str::trim) {
+        if let Some(values) = configurations.as_mut() {
+            match line.strip_prefix('[') {
+                Some(rest) => {
+                    values.extend(rest.split_whitespace().filter_map(|token| match token {
+                        "OUTPUT" => Some(0),
+                        "INPUT" => Some(1),
+                        _ => token.trim_end_matches(']').parse().ok(),
+                    }));
+                    continue;
+                }
+                None => {
+                    if let (Section::Static(index), Some(values)) =
+                        (&section, configurations.take())
+                    {
+                        cameras[*index].resolutions = jpeg_sizes(&values);
+                    }
+                }
+            }
+        }
+
+        if let Some(title) = line.strip_prefix("== ") {
+            section = match static_camera_id(title) {
+                Some(id) => match cameras.iter().position(|camera| camera.id == id) {
+                    Some(index) => Section::Static(index),
+                    None => {
+                        cameras.push(Camera {
+                            id,
+                            facing: None,
+                            orientation: None,
+                            has_flash: None,
+                            resolutions: Vec::new(),
+                            active_client: None,
+                            usage: Vec::new(),
+                        });
+                        Section::Static(cameras.len() - 1)
+                    }
+                },
+                None if title.starts_with("Camera service events log") => Section::Events,
+                None => Section::Other,
+            };
+            continue;
+        }
+        if let Some(client) = line.strip_prefix("(Camera ID: ") {
+            clients.push(client.to_owned());
+            continue;
+        }
+
+        match section {
+            Section::Events => events.extend(parse_event(line)),
+            Section::Static(index) => {
+                let camera = &mut cameras[index];
+                if let Some(facing) = line.strip_prefix("Facing:") {
+                    camera.facing = camera.facing.or(parse_facing(facing));
+                } else if let Some(orientation) = line.strip_prefix("Orientation:") {
+                    camera.orientation = camera.orientation.or(orientation.trim().parse().ok());
+                } else if let Some(flash) = line.strip_prefix("Has a flash unit:") {
+                    camera.has_flash = Some(flash.trim() == "true");
+                } else if line.starts_with("android.scaler.availableStreamConfigurations ") {
+                    configurations = Some(Vec::new());
+                }
+            }
+            Section::Other => {}
+        }
+    }
+    if let (Section::Static(index), Some(values)) = (section, configurations) {
+        cameras[index].resolutions = jpeg_sizes(&values);
+    }
+
+    // `0, Cost: 100, PID: 12345, ..., Client Package Name: com.example, ...)`
+    for client in clients {
+        let Some((id, rest)) = client.split_once(',') else {
+            continue;
+        };
+        let package = rest
+            .split_once("Client Package Name: ")
+            .and_then(|(_, rest)| rest.split([',', ')']).next());
+        if let (Some(camera), Some(package)) =
+            (cameras.iter_mut().find(|camera| camera.id == id), package)
+        {
+            camera.active_client = Some(package.trim().to_owned());
+        }
+    }
+    for (id, event) in events {
+        if let Some(camera) = cameras.iter_mut().find(|camera| camera.id == id) {
+            camera.usage.push(event);
+        }
+    }
+    cameras
+}
+
+/// Camera id of a static info section title.
+fn static_camera_id(title: &str) -> Option<String> {
+    if let Some(rest) = title.strip_prefix("Camera HAL device ") {
+        if !rest.contains("static information") {
+            return None;
+        }
+        // `device@3.5/legacy/0 (v3.5) ...`; the id is the last component.
+        let name = rest.split_whitespace().next()?;
+        return name.rsplit('/').next().map(str::to_owned);
+    }
+    let rest = title.strip_prefix("Camera device ")?;
+    let (id, kind) = rest.split_once(' ')?;
+    kind.starts_with("static info").then(|| id.to_owned())
+}
+
+/// Parses `10-16 12:00:01 : CONNECT device 0 client for package
+/// com.example (PID 12345)`, returning the camera id with the event.
+fn parse_event(line: &str) -> Option<(String, CameraUsageEvent)> {
+    let (timestamp, message) = line.split_once(" : ")?;
+    let mut words = message.split_whitespace();
+    let action = match words.next()? {
+        "CONNECT" => CameraUsageAction::Connect,
+        "DISCONNECT" => CameraUsageAction::Disconnect,
+        "EVICT" => CameraUsageAction::Evict,
+        "REJECT" => CameraUsageAction::Reject,
+        _ => return None,
+    };
+    if words.next()? != "device" {
+        return None;
+    }
+    let id = words.next()?.to_owned();
+    let package = message
+        .split_once("package ")?
+        .1
+        .split_whitespace()
+        .next()?
+        .to_owned();
+    let pid = message
+        .split_once("(PID ")
+        .and_then(|(_, rest)| rest.split([')', ',']).next())
+        .and_then(|pid| pid.trim().parse().ok());
+    Some((
+        id,
+        CameraUsageEvent {
+            timestamp: timestamp.trim().to_owned(),
+            action,
+            package,
+            pid,
+        },
+    ))
+}
+
+fn parse_facing(facing: &str) -> Option<CameraFacing> {
+    match facing.trim().to_ascii_lowercase().as_str() {
+        "back" => Some(CameraFacing::Back),
+        "front" => Some(CameraFacing::Front),
+        "external" => Some(CameraFacing::External),
+        _ => None,
+    }
+}
+
+/// The JPEG output sizes of `format width height direction` stream
+/// configurations (`OUTPUT` read as 0), deduplicated and largest first.
+fn jpeg_sizes(values: &[u32]) -> Vec<CameraResolution> {
+    let mut sizes: Vec<_> = values
+        .chunks_exact(4)
+        .filter(|config| config[0] == FORMAT_JPEG && config[3] == 0)
+        .map(|config| CameraResolution {
+            width: config[1],
+            height: config[2],
+        })
+        .collect();
+    sizes.sort_by_key(|size| std::cmp::Reverse(u64::from(size.width) * u64::from(size.height)));
+    sizes.dedup();
+    sizes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cameras() {
+        let cameras = parse_cameras(
+            "== Service global info: ==\n\
+             \n\
+             Number of camera devices: 2\n\
+             Active Camera Clients:\n\
+             [\n\
+             (Camera ID: 1, Cost: 100, PID: 4321, Score: 0, State: 0User Id: 0, Client Package Name: com.example.chat, Conflicting Client Devices: {})\n\
+             ]\n\
+             \n\
+             == Camera service events log (most recent at top): ==\n\
+             \x20 10-16 12:00:01 : DISCONNECT device 0 client for package com.android.camera2 (PID 12345)\n\
+             \x20 10-16 11:59:50 : CONNECT device 0 client for package com.android.camera2 (PID 12345)\n\
+             \x20 10-16 11:58:00 : USER_SWITCH previous allowed user IDs: <None>, current allowed user IDs: 0\n\
+             \x20 10-16 11:57:00 : CONNECT device 1 client for package com.example.chat (PID 4321)\n\
+             \n\
+             == Camera HAL device device@3.5/legacy/0 (v3.5) static information: ==\n\
+             \x20 Resource cost: 100\n\
+             \x20 API1 info:\n\
+             \x20   Has a flash unit: true\n\
+             \x20   Facing: Back\n\
+             \x20   Orientation: 90\n\
+             \x20 Device static metadata:\n\
+             \x20     android.scaler.availableStreamConfigurations (d000a): int32[16]\n\
+             \x20       [34 1920 1080 OUTPUT 33 1920 1080 OUTPUT ]\n\
+             \x20       [33 4032 3024 OUTPUT 35 640 480 INPUT ]\n\
+             \x20     android.sensor.orientation (e000e): int32[1]\n\
+             \x20       [90 ]\n\
+             == Camera HAL device device@3.5/legacy/1 (v3.5) static information: ==\n\
+             \x20 API1 info:\n\
+             \x20   Has a flash unit: false\n\
+             \x20   Facing: Front\n\
+             \x20   Orientation: 270\n",
+        );
+        assert_eq!(cameras.len(), 2);
+        assert_eq!(
+            cameras[0],
+            Camera {
+                id: "0".to_owned(),
+                facing: Some(CameraFacing::Back),
+                orientation: Some(90),
+                has_flash: Some(true),
+                resolutions: vec![
+                    CameraResolution {
+                        width: 4032,
+                        height: 3024,
+                    },
+                    CameraResolution {
+                        width: 1920,
+                        height: 1080,
+                    },
+                ],
+                active_client: None,
+                usage: vec![
+                    CameraUsageEvent {
+                        timestamp: "10-16 12:00:01".to_owned(),
+                        action: CameraUsageAction::Disconnect,
+                        package: "com.android.camera2".to_owned(),
+                        pid: Some(12345),
+                    },
+                    CameraUsageEvent {
+                        timestamp: "10-16 11:59:50".to_owned(),
+                        action: CameraUsageAction::Connect,
+                        package: "com.android.camera2".to_owned(),
+                        pid: Some(12345),
+                    },
+                ],
+            }
+        );
+        assert_eq!(cameras[1].facing, Some(CameraFacing::Front));
+        assert_eq!(
+            cameras[1].active_client.as_deref(),
+            Some("com.example.chat")
+        );
+        assert_eq!(cameras[1].usage.len(), 1);
+    }
+
+    #[test]
+    fn parses_legacy_section_titles() {
+        assert_eq!(
+            static_camera_id("Camera device 1 static info: =="),
+            Some("1".to_owned())
+        );
+        assert_eq!(static_camera_id("Camera device 1 dynamic info: =="), None);
+        assert_eq!(
+            static_camera_id(
+                "Camera HAL device device@3.2/internal/2 (v3.4) static information: =="
+            ),
+            Some("2".to_owned())
+        );
+    }
+}
@@ -0,0 +1,133 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::{Device, Result};
+
+/// Command spellings and options that differ between Android releases,
+/// keyed on the API level (`ro.build.version.sdk`).
+///
+/// The typed wrappers on [`Device`] consult this so they work from API 21
+/// (Android 5.0) through current releases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    pub sdk: u32,
+}
+
+impl Capabilities {
+    pub fn new(sdk: u32) -> Capabilities {
+        Capabilities { sdk }
+    }
+
+    /// `cmd package` talks to the package service directly and avoids
+    /// starting a new VM per invocation; it exists since Android 7.0.
+    pub fn package_manager(&self) -> &'static str {
+        if self.sdk >= 24 {
+            "cmd package"
+        } else {
+            "pm"
+        }
+    }
+
+    /// Lists all processes.  Since Android 8.0 toybox `ps` only shows the
+    /// caller's processes unless `-A` is given.
+    pub fn ps_all(&self) -> &'static str {
+        if self.sdk >= 26 {
+            "ps -A"
+        } else {
+            "ps"
+        }
+    }
+
+    /// `touch -d` taking an ISO 8601 date, since toybox replaced toolbox in
+    /// Android 6.0.  Older releases only have `touch -t`.
+    pub fn supports_touch_date(&self) -> bool {
+        self.sdk >= 23
+    }
+
+    /// `pm install -g`, granting runtime permissions, since Android 6.0.
+    pub fn supports_install_grant(&self) -> bool {
+        self.sdk >= 23
+    }
+
+    /// Flag installing an instant app: `--ephemeral` on Android 7,
+    /// `--instant` since Android 8.0.
+    pub fn instant_install_flag(&self) -> Option<&'static str> {
+        match self.sdk {
+            26.. => Some("--instant"),
+            24..=25 => Some("--ephemeral"),
+            _ => None,
+        }
+    }
+
+    /// `pm install --dont-kill`, since Android 9.
+    pub fn supports_install_dont_kill(&self) -> bool {
+        self.sdk >= 28
+    }
+
+    /// `pm uninstall --versionCode`, since Android 8.0.
+    pub fn supports_uninstall_version_code(&self) -> bool {
+        self.sdk >= 26
+    }
+
+    /// `pm install --enable-rollback` and `pm rollback-app`, since Android 10.
+    pub fn supports_rollback(&self) -> bool {
+        self.sdk >= 29
+    }
+
+    /// `pm install --bypass-low-target-sdk-block`, since Android 14.
+    pub fn supports_bypass_low_target_sdk_block(&self) -> bool {
+        self.sdk >= 34
+    }
+}
+
+impl Device {
+    /// Returns the API level of the device.
+    ///
+    /// The value is queried once and shared by all clones of this device.
+    pub async fn sdk_level(&self) -> Result<u32> {
+        self.sdk
+            .get_or_try_init(|| async {
+                // Not subject to the su strategy: getprop works for any user.
+                let sdk = self
+                    .execute_host_command_to_string(
+                        "shell:getprop ro.build.version.sdk",
+                        true,
+                        false,
+                    )
+                    .await?;
+                Ok(sdk.trim().parse::<u32>()?)
+            })
+            .await
+            .copied()
+    }
+
+    pub async fn capabilities(&self) -> Result<Capabilities> {
+        Ok(Capabilities::new(self.sdk_level().await?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spellings_by_release() {
+        let lollipop = Capabilities::new(21);
+        assert_eq!(lollipop.package_manager(), "pm");
+        assert_eq!(lollipop.ps_all(), "ps");
+        assert!(!lollipop.supports_install_grant());
+        assert!(!lollipop.supports_touch_date());
+
+        let oreo = Capabilities::new(26);
+        assert_eq!(oreo.package_manager(), "cmd package");
+        assert_eq!(oreo.ps_all(), "ps -A");
+        assert!(oreo.supports_install_grant());
+        assert!(oreo.supports_touch_date());
+        assert!(!oreo.supports_bypass_low_target_sdk_block());
+        assert!(!oreo.supports_rollback());
+        assert!(Capabilities::new(29).supports_rollback());
+
+        assert!(Capabilities::new(34).supports_bypass_low_target_sdk_block());
+    }
+}
@@ -0,0 +1,286 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The cells the modems are registered with, as reported to apps through
+//! the telephony registry.
+
+use crate::{Device, Result};
+
+/// `Integer.MAX_VALUE`, which Android prints (also negated) for unavailable
+/// values.
+const UNAVAILABLE: i64 = i32::MAX as i64;
+const CELL_TYPES: [(&str, CellNetworkType); 6] = [
+    ("Gsm", CellNetworkType::Gsm),
+    ("Wcdma", CellNetworkType::Wcdma),
+    ("Tdscdma", CellNetworkType::Tdscdma),
+    ("Lte", CellNetworkType::Lte),
+    ("Nr", CellNetworkType::Nr),
+    ("Cdma", CellNetworkType::Cdma),
+];
+
+/// Radio access technology of a [`ServingCell`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellNetworkType {
+    Gsm,
+    Wcdma,
+    Tdscdma,
+    Lte,
+    /// 5G New Radio.
+    Nr,
+    Cdma,
+}
+
+/// A cell a modem is registered with, from [`Device::cell_info`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServingCell {
+    /// SIM slot, 0 on single-SIM devices.
+    pub phone_id: u32,
+    pub network_type: CellNetworkType,
+    /// Mobile country code, `None` for CDMA.
+    pub mcc: Option<String>,
+    /// Mobile network code; kept as text since `01` and `001` differ.
+    pub mnc: Option<String>,
+    /// Location area code (GSM, WCDMA, TD-SCDMA) or tracking area code
+    /// (LTE, NR); the network id for CDMA.
+    pub area_code: Option<u32>,
+    /// Cell id: CID, the 28-bit LTE CI or the 36-bit NR NCI; the base
+    /// station id for CDMA.
+    pub cell_id: Option<u64>,
+    /// Physical cell id (LTE, NR) or primary scrambling code (WCDMA).
+    pub pci: Option<u32>,
+    /// Channel number: ARFCN, UARFCN, EARFCN or NR-ARFCN.
+    pub channel: Option<u32>,
+    /// Operator name broadcast by the network.
+    pub operator: Option<String>,
+    /// Signal strength in dBm: RSRP for LTE and NR, RSCP for WCDMA and
+    /// TD-SCDMA, RSSI for GSM and CDMA.
+    pub signal_dbm: Option<i32>,
+    /// Signal bars from 0 to 4.
+    pub signal_level: Option<u32>,
+}
+
+impl Device {
+    /// Lists the serving cells of each SIM slot from `dumpsys
+    /// telephony.registry`, Android 7 and later.
+    ///
+    /// Identities are only reported while location is enabled, and
+    /// several cells can be serving one slot, e.g. LTE with an NR
+    /// secondary cell.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn cell_info(&self) -> Result<Vec<ServingCell>> {
+        let output = self
+            .execute_host_shell_command("dumpsys telephony.registry")
+            .await?;
+        Ok(parse_cell_info(&output))
+    }
+}
+
+/// Parses the `mCellInfo=[CellInfoLte:{mRegistered=YES ... CellIdentityLte:{
+/// mCi=... mPci=... mTac=... mMcc=310 mMnc=260 mAlphaLong=...}
+/// CellSignalStrengthLte: rssi=-61 rsrp=-92 ... level=3}, ...]` line of each
+/// `Phone Id=N` section, keeping the registered cells.
+fn parse_cell_info(output: &str) -> Vec<ServingCell> {
+    let mut cells = Vec::new();
+    let mut phone_id = 0;
+    for line in output.lines().map(str::trim) {
+        if let Some(id) = line.strip_prefix("Phone Id=") {
+            phone_id = id.trim().parse().unwrap_or(phone_id);
+        } else if let Some(list) = line.strip_prefix("mCellInfo=") {
+            cells.extend(
+                cell_chunks(list)
+                    .into_iter()
+                    .filter(|(_, chunk)| chunk.contains("mRegistered=YES"))
+                    .map(|(network_type, chunk)| parse_cell(phone_id, network_type, &chunk)),
+            );
+        }
+    }
+    cells
+}
+
+/// Splits a cell info list at each `CellInfo<Type>:{`, with `key = value`
+/// pairs (as NR signal strengths print them) joined to `key=value`.
+fn cell_chunks(list: &str) -> Vec<(CellNetworkType, String)> {
+    let starts: Vec<_> = list
+        .match_indices("CellInfo")
+        .filter_map(|(start, _)| {
+            let rest = &list[start + "CellInfo".len()..];
+            CELL_TYPES
+                .iter()
+                .find(|(name, _)| {
+                    rest.strip_prefix(name)
+                        .is_some_and(|rest| rest.starts_with(":{"))
+                })
+                .map(|(_, network_type)| (start, *network_type))
+        })
+        .collect();
+    starts
+        .iter()
+        .enumerate()
+        .map(|(i, (start, network_type))| {
+            let end = starts.get(i + 1).map_or(list.len(), |(end, _)| *end);
+            (*network_type, list[*start..end].replace(" = ", "="))
+        })
+        .collect()
+}
+
+fn parse_cell(phone_id: u32, network_type: CellNetworkType, chunk: &str) -> ServingCell {
+    let number = |key: &str| value(chunk, key).and_then(available);
+    let first = |keys: &[&str]| keys.iter().find_map(|key| number(key));
+
+    let (area_code, cell_id, pci, channel, signal_dbm) = match network_type {
+        CellNetworkType::Gsm => (
+            first(&["mLac"]),
+            first(&["mCid"]),
+            None,
+            first(&["mArfcn"]),
+            first(&["rssi"]),
+        ),
+        CellNetworkType::Wcdma => (
+            first(&["mLac"]),
+            first(&["mCid"]),
+            first(&["mPsc"]),
+            first(&["mUarfcn"]),
+            first(&["rscp", "ss"]),
+        ),
+        CellNetworkType::Tdscdma => (
+            first(&["mLac"]),
+            first(&["mCid"]),
+            None,
+            first(&["mUarfcn"]),
+            first(&["rscp"]),
+        ),
+        CellNetworkType::Lte => (
+            first(&["mTac"]),
+            first(&["mCi"]),
+            first(&["mPci"]),
+            first(&["mEarfcn"]),
+            first(&["rsrp"]),
+        ),
+        CellNetworkType::Nr => (
+            first(&["mTac"]),
+            first(&["mNci"]),
+            first(&["mPci"]),
+            first(&["mNrArfcn"]),
+            first(&["ssRsrp", "csiRsrp"]),
+        ),
+        CellNetworkType::Cdma => (
+            first(&["mNetworkId"]),
+            first(&["mBasestationId"]),
+            None,
+            None,
+            first(&["cdmaDbm", "evdoDbm"]),
+        ),
+    };
+    let code = |key: &str| {
+        value(chunk, key)
+            .filter(|code| !code.is_empty() && *code != "null" && available(code).is_some())
+            .map(str::to_owned)
+    };
+
+    ServingCell {
+        phone_id,
+        network_type,
+        mcc: code("mMcc"),
+        mnc: code("mMnc"),
+        area_code: area_code.and_then(|code| u32::try_from(code).ok()),
+        cell_id: cell_id.and_then(|id| u64::try_from(id).ok()),
+        pci: pci.and_then(|pci| u32::try_from(pci).ok()),
+        channel: channel.and_then(|channel| u32::try_from(channel).ok()),
+        operator: operator(chunk),
+        signal_dbm: signal_dbm.and_then(|dbm| i32::try_from(dbm).ok()),
+        signal_level: first(&["level", "mLevel"]).and_then(|level| u32::try_from(level).ok()),
+    }
+}
+
+/// Value of the first `key=value` token.
+fn value<'a>(chunk: &'a str, key: &str) -> Option<&'a str> {
+    chunk.split_whitespace().find_map(|token| {
+        token
+            .trim_start_matches('{')
+            .strip_prefix(key)?
+            .strip_prefix('=')
+            .map(|value| value.trim_end_matches(['}', ']', ',']))
+    })
+}
+
+/// Parses a number, `None` for `Integer.MAX_VALUE` and the `-1` older
+/// releases print for unknown identities.
+fn available(value: &str) -> Option<i64> {
+    value
+        .parse()
+        .ok()
+        .filter(|value: &i64| value.abs() != UNAVAILABLE && *value != -1)
+}
+
+/// `mAlphaLong`, which may contain spaces and runs up to `mAlphaShort=`.
+fn operator(chunk: &str) -> Option<String> {
+    let start = chunk.find("mAlphaLong=")? + "mAlphaLong=".len();
+    let rest = &chunk[start..];
+    let end = rest
+        .find(" mAlphaShort=")
+        .or_else(|| rest.find(['}', ' ']))
+        .unwrap_or(rest.len());
+    let name = rest[..end].trim();
+    (!name.is_empty() && name != "null").then(|| name.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_serving_cells() {
+        let cells = parse_cell_info(
+            "last known state:\n\
+             \x20 Phone Id=0\n\
+             \x20 mCallState=0\n\
+             \x20 mCellInfo=[CellInfoLte:{mRegistered=YES mTimeStamp=123ns mCellConnectionStatus=1 CellIdentityLte:{ mCi=12345678 mPci=123 mTac=4567 mEarfcn=1300 mBands=[3] mBandwidth=2147483647 mMcc=310 mMnc=260 mAlphaLong=T-Mobile US mAlphaShort=TMO mAdditionalPlmns={} mCsgInfo=null} CellSignalStrengthLte: rssi=-61 rsrp=-92 rsrq=-10 rssnr=2147483647 cqi=2147483647 ta=2147483647 level=3 parametersUseForLevel=0}, CellInfoLte:{mRegistered=NO mTimeStamp=123ns CellIdentityLte:{ mCi=2147483647 mPci=77 mTac=2147483647 mEarfcn=1300 mMcc=null mMnc=null mAlphaLong=null mAlphaShort=null} CellSignalStrengthLte: rssi=2147483647 rsrp=-110 level=1}, CellInfoNr:{mRegistered=YES CellIdentityNr:{ mPci=501 mTac=4567 mNrArfcn=632448 mBands=[78] mMcc=310 mMnc=260 mNci=68719476 mAlphaLong=T-Mobile US mAlphaShort=TMO mAdditionalPlmns={}} CellSignalStrengthNr:{ csiRsrp = -2147483647 csiRsrq = 2147483647 ssRsrp = -95 ssRsrq = -11 level = 2 }}]\n\
+             \x20 Phone Id=1\n\
+             \x20 mCellInfo=[CellInfoGsm:{mRegistered=YES mTimeStamp=123ns CellIdentityGsm:{ mLac=1234 mCid=5678 mArfcn=62 mBsic=0x12 mMcc=262 mMnc=01 mAlphaLong=Telekom.de mAlphaShort=Telekom} CellSignalStrengthGsm: rssi=-75 ber=2147483647 mTa=2147483647 mLevel=3}]\n\
+             local logs:\n",
+        );
+        assert_eq!(cells.len(), 3);
+        assert_eq!(
+            cells[0],
+            ServingCell {
+                phone_id: 0,
+                network_type: CellNetworkType::Lte,
+                mcc: Some("310".to_owned()),
+                mnc: Some("260".to_owned()),
+                area_code: Some(4567),
+                cell_id: Some(12345678),
+                pci: Some(123),
+                channel: Some(1300),
+                operator: Some("T-Mobile US".to_owned()),
+                signal_dbm: Some(-92),
+                signal_level: Some(3),
+            }
+        );
+        assert_eq!(cells[1].network_type, CellNetworkType::Nr);
+        assert_eq!(
+            (cells[1].cell_id, cells[1].pci, cells[1].signal_dbm),
+            (Some(68719476), Some(501), Some(-95))
+        );
+        assert_eq!(cells[1].signal_level, Some(2));
+        assert_eq!(
+            cells[2],
+            ServingCell {
+                phone_id: 1,
+                network_type: CellNetworkType::Gsm,
+                mcc: Some("262".to_owned()),
+                mnc: Some("01".to_owned()),
+                area_code: Some(1234),
+                cell_id: Some(5678),
+                pci: None,
+                channel: Some(62),
+                operator: Some("Telekom.de".to_owned()),
+                signal_dbm: Some(-75),
+                signal_level: Some(3),
+            }
+        );
+    }
+}
@@ -0,0 +1,161 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Offset of the device clock from the host clock, for normalizing device
+//! timestamps such as logcat lines or file mtimes to examiner time.
+
+use std::time::{Duration as StdDuration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::{Device, DeviceError, Result};
+
+/// Prints the device's wall clock with nanoseconds; toolbox `date` before
+/// Android 6.0 prints `%N` literally and only whole seconds are used.
+const DATE_COMMAND: &str = "exec:date +%s.%N";
+
+/// Result of [`Device::measure_clock_skew`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockSkew {
+    /// Device clock minus host clock in nanoseconds, positive when the
+    /// device is ahead.
+    pub offset_nanos: i64,
+    /// Half the shortest round trip, the most the offset can be off by
+    /// apart from the device clock's resolution.
+    pub uncertainty: StdDuration,
+    /// Standard deviation of the offsets of all samples.
+    pub jitter: StdDuration,
+    /// Number of samples taken.
+    pub samples: usize,
+}
+
+impl ClockSkew {
+    /// Converts a timestamp taken by the device clock to host time.
+    pub fn to_host_time(&self, device_time: SystemTime) -> SystemTime {
+        shift(device_time, -self.offset_nanos)
+    }
+
+    /// Converts a host timestamp to the device clock.
+    pub fn to_device_time(&self, host_time: SystemTime) -> SystemTime {
+        shift(host_time, self.offset_nanos)
+    }
+}
+
+fn shift(time: SystemTime, nanos: i64) -> SystemTime {
+    let delta = StdDuration::from_nanos(nanos.unsigned_abs());
+    match nanos < 0 {
+        true => time - delta,
+        false => time + delta,
+    }
+}
+
+impl Device {
+    /// Estimates the offset of the device clock from the host clock over
+    /// `samples` round trips.
+    ///
+    /// Each sample reads the device's wall clock with `date` and compares it
+    /// with the host time halfway through the round trip (Cristian's
+    /// algorithm).  The offset is taken from the sample with the shortest
+    /// round trip, as it is delayed the least; the spread of all samples is
+    /// reported as [`ClockSkew::jitter`].
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial, samples), err)
+    )]
+    pub async fn measure_clock_skew(&self, samples: usize) -> Result<ClockSkew> {
+        if samples == 0 {
+            return Err(DeviceError::Adb(
+                "clock skew needs at least one sample".to_owned(),
+            ));
+        }
+
+        let mut offsets = Vec::with_capacity(samples);
+        let mut best: Option<(StdDuration, i64)> = None;
+        for _ in 0..samples {
+            let sent = SystemTime::now();
+            let start = Instant::now();
+            // Not subject to the su strategy, which would only add latency.
+            let reply = self
+                .execute_host_command_to_string(DATE_COMMAND, true, false)
+                .await?;
+            let round_trip = start.elapsed();
+
+            let device_nanos = parse_date(reply.trim()).ok_or_else(|| {
+                DeviceError::Adb(format!("unexpected date output: {:?}", reply.trim()))
+            })?;
+            let host_nanos = unix_nanos(sent + round_trip / 2);
+            let offset = (device_nanos - host_nanos) as i64;
+            offsets.push(offset);
+            if best.is_none_or(|(shortest, _)| round_trip < shortest) {
+                best = Some((round_trip, offset));
+            }
+        }
+
+        let (round_trip, offset_nanos) = best.expect("at least one sample");
+        Ok(ClockSkew {
+            offset_nanos,
+            uncertainty: round_trip / 2,
+            jitter: StdDuration::from_nanos(std_dev(&offsets) as u64),
+            samples,
+        })
+    }
+}
+
+/// Parses `seconds.nanoseconds` since the epoch into nanoseconds.
+fn parse_date(output: &str) -> Option<i128> {
+    let (seconds, fraction) = output.split_once('.').unwrap_or((output, ""));
+    let seconds: i128 = seconds.parse().ok()?;
+    let nanos = match fraction.bytes().all(|b| b.is_ascii_digit()) {
+        // Pad or cut to nine digits.
+        true if !fraction.is_empty() => format!("{fraction:0<9}")[..9].parse().ok()?,
+        _ => 0,
+    };
+    Some(seconds * 1_000_000_000 + nanos)
+}
+
+fn unix_nanos(time: SystemTime) -> i128 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_nanos() as i128,
+        Err(e) => -(e.duration().as_nanos() as i128),
+    }
+}
+
+fn std_dev(values: &[i64]) -> f64 {
+    let mean = values.iter().map(|&v| v as f64).sum::<f64>() / values.len() as f64;
+    let variance = values
+        .iter()
+        .map(|&v| (v as f64 - mean).powi(2))
+        .sum::<f64>()
+        / values.len() as f64;
+    variance.sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_date_output() {
+        assert_eq!(
+            parse_date("1700000000.123456789"),
+            Some(1_700_000_000_123_456_789)
+        );
+        assert_eq!(parse_date("1700000000.5"), Some(1_700_000_000_500_000_000));
+        assert_eq!(parse_date("1700000000.%N"), Some(1_700_000_000_000_000_000));
+        assert_eq!(parse_date("1700000000"), Some(1_700_000_000_000_000_000));
+        assert_eq!(parse_date("date: bad format"), None);
+    }
+
+    #[test]
+    fn converts_between_clocks() {
+        let skew = ClockSkew {
+            offset_nanos: -2_500_000_000,
+            uncertainty: StdDuration::ZERO,
+            jitter: StdDuration::ZERO,
+            samples: 1,
+        };
+        let host = UNIX_EPOCH + StdDuration::from_secs(1_700_000_000);
+        let device = skew.to_device_time(host);
+        assert_eq!(device, host - StdDuration::from_millis(2500));
+        assert_eq!(skew.to_host_time(device), host);
+    }
+}
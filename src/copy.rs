@@ -0,0 +1,201 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Copying files on the device.
+
+use std::collections::BTreeMap;
+use std::io;
+
+#[cfg(not(feature = "tracing"))]
+use log::debug;
+#[cfg(feature = "tracing")]
+use tracing::debug;
+
+use crate::{Device, DeviceError, DevicePath, Result, UnixPath};
+
+impl Device {
+    /// Copies `src` to `dst` on the device, which must not exist yet.
+    ///
+    /// Directories need `recursive`.  With `preserve` the copy keeps modes,
+    /// ownership where permitted, and timestamps (`cp -a`).  Afterwards the
+    /// SHA-256 digests of all copied files are read back and compared with
+    /// the originals; a copy that does not match is removed again.
+    ///
+    /// The digests need `find` and `sha256sum`, which toolbox before Android
+    /// 6.0 lacks; there this fails with [`DeviceError::MissingFeature`]
+    /// before copying anything.  Other errors are typed as in
+    /// [`Device::rename`].
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial, src = %src.display(), dst = %dst.display()), err)
+    )]
+    pub async fn copy(
+        &self,
+        src: &UnixPath,
+        dst: &UnixPath,
+        recursive: bool,
+        preserve: bool,
+    ) -> Result<()> {
+        self.check_writable("copy files")?;
+        debug!("Copying {} to {}", src.display(), dst.display());
+
+        let enable_run_as = self.enable_run_as_for_path(src) || self.enable_run_as_for_path(dst);
+        let src_arg = DevicePath::new(src)?.quoted();
+        let dst_arg = DevicePath::new(dst)?.quoted();
+        self.check_absent(dst, enable_run_as).await?;
+        let original = self.tree_digests(src, enable_run_as).await?;
+
+        let flags = match (recursive, preserve) {
+            (true, true) => "-aR ",
+            (true, false) => "-r ",
+            (false, true) => "-p ",
+            (false, false) => "",
+        };
+        let output = self
+            .execute_host_shell_command_as(&format!("cp {flags}{src_arg} {dst_arg}"), enable_run_as)
+            .await?;
+        let copied = match output.trim() {
+            "" => self.verify_copy(&original, src, dst, enable_run_as).await,
+            message => Err(file_error(message)),
+        };
+        if let Err(err) = copied {
+            // `dst` did not exist before, so nothing of the caller's is lost.
+            self.execute_host_shell_command_as(&format!("rm -rf {dst_arg}"), enable_run_as)
+                .await?;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Fails with an [`io::ErrorKind::AlreadyExists`] error if `path`
+    /// exists.
+    pub(crate) async fn check_absent(&self, path: &UnixPath, enable_run_as: bool) -> Result<()> {
+        let quoted = DevicePath::new(path)?.quoted();
+        let listing = self
+            .execute_host_shell_command_as(&format!("ls -d {quoted}"), enable_run_as)
+            .await?;
+        let listing = listing.trim();
+        if listing.starts_with("ls:") {
+            if !listing.contains("No such file") {
+                return Err(file_error(listing));
+            }
+        } else if !listing.is_empty() {
+            return Err(DeviceError::Io(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", path.display()),
+            )));
+        }
+        Ok(())
+    }
+
+    /// Compares the digests of all files below `dst` with `original`, those
+    /// of `src`.
+    async fn verify_copy(
+        &self,
+        original: &BTreeMap<String, String>,
+        src: &UnixPath,
+        dst: &UnixPath,
+        enable_run_as: bool,
+    ) -> Result<()> {
+        if *original != self.tree_digests(dst, enable_run_as).await? {
+            return Err(DeviceError::Adb(format!(
+                "Copy of {} at {} does not match the original",
+                src.display(),
+                dst.display()
+            )));
+        }
+        Ok(())
+    }
+
+    /// SHA-256 digests of the regular files at or below `path`, keyed by
+    /// their path relative to it.
+    async fn tree_digests(
+        &self,
+        path: &UnixPath,
+        enable_run_as: bool,
+    ) -> Result<BTreeMap<String, String>> {
+        let quoted = DevicePath::new(path)?.quoted();
+        let output = self
+            .execute_host_shell_command_as(
+                &format!("find {quoted} -type f -exec sha256sum {{}} +"),
+                enable_run_as,
+            )
+            .await?;
+
+        let root = path.display().to_string();
+        output
+            .lines()
+            .map(|line| {
+                let Some((digest, file)) = line.split_once("  ") else {
+                    return Err(digest_error(line));
+                };
+                let relative = file.strip_prefix(&root).unwrap_or(file);
+                Ok((relative.to_owned(), digest.to_ascii_lowercase()))
+            })
+            .collect()
+    }
+}
+
+/// Turns a line of `find -exec sha256sum` that is not a digest into an
+/// error: [`DeviceError::MissingFeature`] for `sh: find: not found` or
+/// `find: sha256sum: inaccessible or not found`.
+fn digest_error(line: &str) -> DeviceError {
+    match line.trim_end().strip_suffix("not found") {
+        Some(message) => {
+            let message = message
+                .trim_end_matches("inaccessible or ")
+                .trim_end_matches(": ");
+            let tool = message.rsplit(": ").next().unwrap_or(message);
+            DeviceError::MissingFeature(tool.to_owned())
+        }
+        None => DeviceError::Adb(format!("sha256sum failed: {line}")),
+    }
+}
+
+/// Turns the message of a failed file command into an error, typed where
+/// the cause is recognizable.
+pub(crate) fn file_error(message: &str) -> DeviceError {
+    let lower = message.to_ascii_lowercase();
+    let kind = if lower.contains("permission denied")
+        || lower.contains("operation not permitted")
+        || lower.contains("read-only file system")
+    {
+        io::ErrorKind::PermissionDenied
+    } else if lower.contains("no such file") {
+        io::ErrorKind::NotFound
+    } else if lower.contains("directory not empty") {
+        io::ErrorKind::DirectoryNotEmpty
+    } else if lower.contains("is a directory") {
+        io::ErrorKind::IsADirectory
+    } else if lower.contains("not a directory") {
+        io::ErrorKind::NotADirectory
+    } else if lower.contains("file exists") {
+        io::ErrorKind::AlreadyExists
+    } else {
+        return DeviceError::Adb(message.to_owned());
+    };
+    DeviceError::Io(io::Error::new(kind, message.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_missing_digest_tools() {
+        for (line, tool) in [
+            ("/system/bin/sh: find: not found", "find"),
+            ("find: sha256sum: inaccessible or not found", "sha256sum"),
+        ] {
+            match digest_error(line) {
+                DeviceError::MissingFeature(missing) => assert_eq!(missing, tool),
+                other => panic!("Expected missing feature error, got {other:?}"),
+            }
+        }
+        assert!(matches!(
+            digest_error("find: /sdcard/x: Permission denied"),
+            DeviceError::Adb(_)
+        ));
+    }
+}
@@ -0,0 +1,139 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Deref;
+use unix_path::Component;
+
+use crate::{DeviceError, Result, UnixPath, UnixPathBuf};
+
+/// A device path that is safe to interpolate into shell commands.
+///
+/// Paths containing quotes, newlines, NUL bytes or `..` components are
+/// rejected, so a user-supplied file name cannot change the meaning of the
+/// commands built by e.g. [`Device::remove`](crate::Device::remove).
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DevicePath(UnixPathBuf);
+
+impl DevicePath {
+    pub fn new<P: AsRef<UnixPath>>(path: P) -> Result<DevicePath> {
+        let path = path.as_ref();
+        let display = path.display().to_string();
+        let invalid = |reason: String| DeviceError::InvalidPath(display.clone(), reason);
+
+        if display.is_empty() {
+            return Err(invalid("path is empty".to_owned()));
+        }
+        if let Some(c) = display
+            .chars()
+            .find(|c| matches!(c, '\'' | '"' | '\n' | '\r' | '\0'))
+        {
+            return Err(invalid(format!("contains {c:?}")));
+        }
+        if path.components().any(|c| c == Component::ParentDir) {
+            return Err(invalid("contains '..'".to_owned()));
+        }
+
+        Ok(DevicePath(path.to_path_buf()))
+    }
+
+    pub fn as_path(&self) -> &UnixPath {
+        &self.0
+    }
+
+    /// Returns the path in double quotes, with the characters that remain
+    /// special inside them escaped.
+    ///
+    /// Double quotes survive the re-quoting applied to `run-as` commands,
+    /// unlike single quotes.
+    pub fn quoted(&self) -> String {
+        let mut quoted = String::from('"');
+        for c in self.0.display().to_string().chars() {
+            if matches!(c, '\\' | '$' | '`') {
+                quoted.push('\\');
+            }
+            quoted.push(c);
+        }
+        quoted.push('"');
+        quoted
+    }
+}
+
+impl Deref for DevicePath {
+    type Target = UnixPath;
+
+    fn deref(&self) -> &UnixPath {
+        &self.0
+    }
+}
+
+impl AsRef<UnixPath> for DevicePath {
+    fn as_ref(&self) -> &UnixPath {
+        &self.0
+    }
+}
+
+impl fmt::Display for DevicePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.display())
+    }
+}
+
+impl TryFrom<&str> for DevicePath {
+    type Error = DeviceError;
+
+    fn try_from(path: &str) -> Result<DevicePath> {
+        DevicePath::new(path)
+    }
+}
+
+impl TryFrom<&UnixPath> for DevicePath {
+    type Error = DeviceError;
+
+    fn try_from(path: &UnixPath) -> Result<DevicePath> {
+        DevicePath::new(path)
+    }
+}
+
+impl From<DevicePath> for UnixPathBuf {
+    fn from(path: DevicePath) -> UnixPathBuf {
+        path.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_plain_paths() {
+        let path = DevicePath::new("/sdcard/My Photos/IMG 1.jpg").expect("valid");
+        assert_eq!(path.quoted(), "\"/sdcard/My Photos/IMG 1.jpg\"");
+        assert_eq!(path.to_string(), "/sdcard/My Photos/IMG 1.jpg");
+    }
+
+    #[test]
+    fn escapes_expansions() {
+        let path = DevicePath::new("/sdcard/$(reboot)`id`\\x").expect("valid");
+        assert_eq!(path.quoted(), "\"/sdcard/\\$(reboot)\\`id\\`\\\\x\"");
+    }
+
+    #[test]
+    fn rejects_unsafe_paths() {
+        for path in [
+            "",
+            "/sdcard/a'b",
+            "/sdcard/a\"; rm -rf /; \"",
+            "/sdcard/a\nreboot",
+            "/sdcard/../data",
+            "../data",
+        ] {
+            assert!(
+                matches!(DevicePath::new(path), Err(DeviceError::InvalidPath(..))),
+                "{path:?} should be rejected"
+            );
+        }
+    }
+}
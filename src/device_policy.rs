@@ -0,0 +1,310 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Device and profile owners, device admins and the restrictions they
+//! impose, which show whether and how a device is managed.
+
+use crate::{Device, Result};
+
+/// A device or profile owner of [`DevicePolicy`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PolicyOwner {
+    /// Admin receiver, e.g. `com.example.mdm/com.example.mdm.AdminReceiver`.
+    pub component: String,
+    pub package: String,
+    pub user_id: u32,
+    /// Whether the device is company owned, Android 11 and later.
+    pub organization_owned: Option<bool>,
+}
+
+/// An active device admin of [`DevicePolicy`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceAdmin {
+    /// Admin receiver as printed, e.g. `com.example.mdm/.AdminReceiver`.
+    pub component: String,
+    pub package: String,
+    pub user_id: u32,
+    pub uid: Option<u32>,
+    /// Policies the admin declared, e.g. `wipe-data` or `force-lock`.
+    pub policies: Vec<String>,
+    /// User restrictions the admin set, e.g. `no_install_unknown_sources`.
+    pub user_restrictions: Vec<String>,
+    /// Features the admin turned off, e.g. `disableCamera`.
+    pub disabled_features: Vec<String>,
+}
+
+/// Result of [`Device::device_policy`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DevicePolicy {
+    pub device_owner: Option<PolicyOwner>,
+    pub profile_owners: Vec<PolicyOwner>,
+    pub admins: Vec<DeviceAdmin>,
+}
+
+impl DevicePolicy {
+    /// Whether a device or profile owner manages the device.
+    pub fn is_managed(&self) -> bool {
+        self.device_owner.is_some() || !self.profile_owners.is_empty()
+    }
+}
+
+impl Device {
+    /// Reports the device owner, the profile owner of each user and the
+    /// active device admins with their policies and restrictions, from
+    /// `dumpsys device_policy`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn device_policy(&self) -> Result<DevicePolicy> {
+        let output = self
+            .execute_host_shell_command("dumpsys device_policy")
+            .await?;
+        Ok(parse_device_policy(&output))
+    }
+}
+
+enum Section {
+    DeviceOwner,
+    ProfileOwner,
+    Admins(u32),
+}
+
+enum AdminList {
+    Policies,
+    UserRestrictions,
+}
+
+/// Parses the `Device Owner:`, `Profile Owner (User 10):` and `Enabled
+/// Device Admins (User 0, provisioningState: 3):` sections of `dumpsys
+/// device_policy`.  Owners list `admin=ComponentInfo{...}`, `package=` and
+/// `User ID:`; admins start with a `package/.Receiver:` line followed by
+/// `uid=`, the `policies:` and `userRestrictions:` lists and `disable*=`
+/// flags.
+pub(crate) fn parse_device_policy(output: &str) -> DevicePolicy {
+    let mut policy = DevicePolicy::default();
+    let mut section: Option<(Section, usize)> = None;
+    let mut owner: Option<PolicyOwner> = None;
+    let mut admin_indent = None;
+    let mut list = None;
+
+    let finish_owner = |policy: &mut DevicePolicy, owner: Option<PolicyOwner>, device| {
+        if let Some(owner) = owner.filter(|owner| !owner.component.is_empty()) {
+            match device {
+                true => policy.device_owner = Some(owner),
+                false => policy.profile_owners.push(owner),
+            }
+        }
+    };
+
+    for line in output.lines() {
+        let text = line.trim();
+        if text.is_empty() {
+            continue;
+        }
+        let indent = line.len() - line.trim_start().len();
+
+        let header = section_header(text);
+        let ends_section = section
+            .as_ref()
+            .is_some_and(|(_, section_indent)| indent <= *section_indent);
+        if header.is_some() || ends_section {
+            if let Some((section, _)) = section.take() {
+                finish_owner(
+                    &mut policy,
+                    owner.take(),
+                    matches!(section, Section::DeviceOwner),
+                );
+            }
+            if let Some((kind, user_id)) = header {
+                if !matches!(kind, Section::Admins(_)) {
+                    owner = Some(PolicyOwner {
+                        user_id,
+                        ..PolicyOwner::default()
+                    });
+                }
+                section = Some((kind, indent));
+                admin_indent = None;
+                list = None;
+            }
+            continue;
+        }
+
+        match &section {
+            Some((Section::DeviceOwner | Section::ProfileOwner, _)) => {
+                let Some(owner) = owner.as_mut() else {
+                    continue;
+                };
+                if let Some(admin) = text.strip_prefix("admin=") {
+                    let component = admin
+                        .strip_prefix("ComponentInfo{")
+                        .map_or(admin, |admin| admin.trim_end_matches('}'));
+                    owner.component = component.to_owned();
+                    if owner.package.is_empty() {
+                        owner.package = package(component);
+                    }
+                } else if let Some(package) = text.strip_prefix("package=") {
+                    owner.package = package.to_owned();
+                } else if let Some(user_id) = text.strip_prefix("User ID:") {
+                    owner.user_id = user_id.trim().parse().unwrap_or(owner.user_id);
+                } else if let Some(owned) = text.strip_prefix("isOrganizationOwnedDevice=") {
+                    owner.organization_owned = Some(owned == "true");
+                }
+            }
+            Some((Section::Admins(user_id), _)) => {
+                let is_admin = text.ends_with(':')
+                    && text.contains('/')
+                    && admin_indent.is_none_or(|admin_indent| indent <= admin_indent);
+                if is_admin {
+                    admin_indent = Some(indent);
+                    list = None;
+                    let component = text.trim_end_matches(':');
+                    policy.admins.push(DeviceAdmin {
+                        component: component.to_owned(),
+                        package: package(component),
+                        user_id: *user_id,
+                        ..DeviceAdmin::default()
+                    });
+                    continue;
+                }
+                let Some(admin) = policy.admins.last_mut().filter(|_| admin_indent.is_some())
+                else {
+                    continue;
+                };
+                if text == "policies:" {
+                    list = Some(AdminList::Policies);
+                } else if text == "userRestrictions:" {
+                    list = Some(AdminList::UserRestrictions);
+                } else if let Some((key, value)) = text.split_once('=') {
+                    list = None;
+                    if key == "uid" {
+                        admin.uid = value.parse().ok();
+                    } else if key.starts_with("disable") && value == "true" {
+                        admin.disabled_features.push(key.to_owned());
+                    }
+                } else if text.ends_with(':') {
+                    list = None;
+                } else if text != "none" {
+                    match list {
+                        Some(AdminList::Policies) => admin.policies.push(text.to_owned()),
+                        Some(AdminList::UserRestrictions) => {
+                            admin.user_restrictions.push(text.to_owned())
+                        }
+                        None => {}
+                    }
+                }
+            }
+            None => {}
+        }
+    }
+    if let Some((section, _)) = section {
+        finish_owner(&mut policy, owner, matches!(section, Section::DeviceOwner));
+    }
+    policy
+}
+
+/// The section a header line starts, with the user of a profile owner.
+fn section_header(text: &str) -> Option<(Section, u32)> {
+    if text.starts_with("Device Owner:") {
+        return Some((Section::DeviceOwner, 0));
+    }
+    if let Some(rest) = text.strip_prefix("Profile Owner (User ") {
+        return Some((Section::ProfileOwner, user_id(rest)));
+    }
+    let rest = text.strip_prefix("Enabled Device Admins (User ")?;
+    Some((Section::Admins(user_id(rest)), 0))
+}
+
+/// User id at the start of `10):` or `0, provisioningState: 3):`.
+fn user_id(rest: &str) -> u32 {
+    rest.split([')', ','])
+        .next()
+        .and_then(|id| id.trim().parse().ok())
+        .unwrap_or_default()
+}
+
+fn package(component: &str) -> String {
+    component
+        .split_once('/')
+        .map_or(component, |(package, _)| package)
+        .to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_device_policy() {
+        let policy = parse_device_policy(
+            "Current Device Policy Manager state:\n\
+             \x20 Immutable state:\n\
+             \x20   mHasFeature=true\n\
+             \x20 Device Owner: \n\
+             \x20   admin=ComponentInfo{com.example.mdm/com.example.mdm.AdminReceiver}\n\
+             \x20   name=\n\
+             \x20   package=com.example.mdm\n\
+             \x20   isOrganizationOwnedDevice=true\n\
+             \x20   User ID: 0\n\
+             \n\
+             \x20 Profile Owner (User 10): \n\
+             \x20   admin=ComponentInfo{com.example.work/.Receiver}\n\
+             \x20   name=Work\n\
+             \x20   package=com.example.work\n\
+             \x20 Enabled Device Admins (User 0, provisioningState: 3):\n\
+             \x20   com.example.mdm/.AdminReceiver:\n\
+             \x20     uid=10123\n\
+             \x20     testOnlyAdmin=false\n\
+             \x20     policies:\n\
+             \x20       wipe-data\n\
+             \x20       force-lock\n\
+             \x20     passwordQuality=0x0\n\
+             \x20     disableCamera=true\n\
+             \x20     disableScreenCapture=false\n\
+             \x20     userRestrictions:\n\
+             \x20       no_install_unknown_sources\n\
+             \x20       no_usb_file_transfer\n\
+             \x20     parentAdmin:\n\
+             \x20       uid=10123\n\
+             \x20   com.google.android.gms/com.google.android.gms.mdm.receivers.MdmDeviceAdminReceiver:\n\
+             \x20     uid=10090\n\
+             \x20     policies:\n\
+             \x20       force-lock\n\
+             \x20 Encryption Status: active\n",
+        );
+        assert!(policy.is_managed());
+        assert_eq!(
+            policy.device_owner,
+            Some(PolicyOwner {
+                component: "com.example.mdm/com.example.mdm.AdminReceiver".to_owned(),
+                package: "com.example.mdm".to_owned(),
+                user_id: 0,
+                organization_owned: Some(true),
+            })
+        );
+        assert_eq!(policy.profile_owners.len(), 1);
+        assert_eq!(policy.profile_owners[0].user_id, 10);
+        assert_eq!(policy.profile_owners[0].package, "com.example.work");
+        assert_eq!(policy.admins.len(), 2);
+        assert_eq!(
+            policy.admins[0],
+            DeviceAdmin {
+                component: "com.example.mdm/.AdminReceiver".to_owned(),
+                package: "com.example.mdm".to_owned(),
+                user_id: 0,
+                uid: Some(10123),
+                policies: vec!["wipe-data".to_owned(), "force-lock".to_owned()],
+                user_restrictions: vec![
+                    "no_install_unknown_sources".to_owned(),
+                    "no_usb_file_transfer".to_owned()
+                ],
+                disabled_features: vec!["disableCamera".to_owned()],
+            }
+        );
+        assert_eq!(policy.admins[1].package, "com.google.android.gms");
+        assert_eq!(policy.admins[1].policies, ["force-lock"]);
+
+        assert!(!parse_device_policy("  Device Owner: null\n").is_managed());
+    }
+}
@@ -0,0 +1,673 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Talking to `adbd` directly, without an adb server.
+//!
+//! [`DeviceDirect`] speaks the adb message protocol (`CNXN`, `AUTH`,
+//! `OPEN`/`WRTE`/`OKAY`/`CLSE`) over one connection, e.g. tcp/5555, and
+//! multiplexes the services opened on it.  Its [`Host`] answers the few
+//! server requests a [`Device`] makes itself and forwards everything else
+//! to `adbd`, so shell, sync and package operations work unchanged.
+//!
+//! ```no_run
+//! # async fn example() -> forensic_adb::Result<()> {
+//! use forensic_adb::{AdbKeySet, DeviceDirect};
+//!
+//! let keys = AdbKeySet::load_default()?;
+//! let direct = DeviceDirect::connect("192.168.1.20:5555", &keys).await?;
+//! let device = direct.device().await?;
+//! println!("{}", device.execute_host_shell_command("id").await?);
+//! # Ok(())
+//! # }
+//! ```
+
+use futures_core::future::BoxFuture;
+use futures_util::future::join;
+#[cfg(not(feature = "tracing"))]
+use log::debug;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::io::{
+    AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf,
+};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+#[cfg(feature = "tracing")]
+use tracing::debug;
+
+use crate::adb::SyncCommand;
+use crate::transport::{BoxedTransport, Connector, TcpConnector};
+use crate::{AdbKeySet, Device, DeviceError, DeviceState, Host, Result, Timeouts};
+
+pub(crate) const A_CNXN: u32 = 0x4e58_4e43;
+pub(crate) const A_AUTH: u32 = 0x4854_5541;
+pub(crate) const A_OPEN: u32 = 0x4e45_504f;
+pub(crate) const A_OKAY: u32 = 0x5941_4b4f;
+pub(crate) const A_CLSE: u32 = 0x4553_4c43;
+pub(crate) const A_WRTE: u32 = 0x4554_5257;
+pub(crate) const A_STLS: u32 = 0x534c_5453;
+
+pub(crate) const AUTH_TOKEN: u32 = 1;
+pub(crate) const AUTH_SIGNATURE: u32 = 2;
+pub(crate) const AUTH_RSAPUBLICKEY: u32 = 3;
+
+/// Protocol version without mandatory checksums.
+pub(crate) const A_VERSION: u32 = 0x0100_0001;
+
+/// Largest payload accepted in either direction.
+pub(crate) const MAX_PAYLOAD: u32 = 1024 * 1024;
+
+/// Port `adbd` listens on for `adb tcpip`.
+pub const DEFAULT_ADBD_PORT: u16 = 5555;
+
+/// Features announced to `adbd`, those this crate handles.
+const HOST_FEATURES: &[&str] = &[
+    "shell_v2",
+    "cmd",
+    "stat_v2",
+    "ls_v2",
+    "fixed_push_mkdir",
+    "apex",
+    "abb",
+    "abb_exec",
+    "fixed_push_symlink_timestamp",
+    "remount_shell",
+    "sendrecv_v2",
+];
+
+/// One adb protocol message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Message {
+    pub(crate) command: u32,
+    pub(crate) arg0: u32,
+    pub(crate) arg1: u32,
+    pub(crate) data: Vec<u8>,
+}
+
+impl Message {
+    pub(crate) fn new(command: u32, arg0: u32, arg1: u32, data: &[u8]) -> Message {
+        Message {
+            command,
+            arg0,
+            arg1,
+            data: data.to_vec(),
+        }
+    }
+}
+
+pub(crate) async fn read_message<R: AsyncRead + Unpin + ?Sized>(
+    reader: &mut R,
+) -> io::Result<Message> {
+    let mut header = [0u8; 24];
+    reader.read_exact(&mut header).await?;
+    let word =
+        |i: usize| u32::from_le_bytes([header[i], header[i + 1], header[i + 2], header[i + 3]]);
+    let (command, arg0, arg1, length, magic) = (word(0), word(4), word(8), word(12), word(20));
+    if magic != !command || length > MAX_PAYLOAD {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid adb message header {header:02x?}"),
+        ));
+    }
+
+    let mut data = vec![0; length as usize];
+    reader.read_exact(&mut data).await?;
+    Ok(Message {
+        command,
+        arg0,
+        arg1,
+        data,
+    })
+}
+
+pub(crate) async fn write_message<W: AsyncWrite + Unpin + ?Sized>(
+    writer: &mut W,
+    message: &Message,
+) -> io::Result<()> {
+    // Devices before `A_VERSION` still check the checksum.
+    let checksum = message
+        .data
+        .iter()
+        .fold(0u32, |sum, b| sum.wrapping_add(u32::from(*b)));
+    let mut buf = Vec::with_capacity(24 + message.data.len());
+    for word in [
+        message.command,
+        message.arg0,
+        message.arg1,
+        message.data.len() as u32,
+        checksum,
+        !message.command,
+    ] {
+        buf.extend(word.to_le_bytes());
+    }
+    buf.extend(&message.data);
+    writer.write_all(&buf).await?;
+    writer.flush().await
+}
+
+/// Receiving ends of one stream.
+pub(crate) struct StreamReceivers {
+    /// `OKAY` arguments: the remote id, for the open and each write.
+    acks: mpsc::UnboundedReceiver<u32>,
+    /// `WRTE` payloads.
+    data: mpsc::UnboundedReceiver<Vec<u8>>,
+}
+
+struct StreamSenders {
+    acks: mpsc::UnboundedSender<u32>,
+    data: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+/// The streams open on one adb connection, used by both ends of it.
+pub(crate) struct Multiplexer {
+    writer: tokio::sync::Mutex<WriteHalf<BoxedTransport>>,
+    streams: Mutex<HashMap<u32, StreamSenders>>,
+    next_id: AtomicU32,
+    max_payload: usize,
+}
+
+impl Multiplexer {
+    pub(crate) fn new(writer: WriteHalf<BoxedTransport>, max_payload: u32) -> Multiplexer {
+        Multiplexer {
+            writer: tokio::sync::Mutex::new(writer),
+            streams: Mutex::default(),
+            next_id: AtomicU32::new(1),
+            max_payload: max_payload.clamp(1, MAX_PAYLOAD) as usize,
+        }
+    }
+
+    fn streams(&self) -> MutexGuard<'_, HashMap<u32, StreamSenders>> {
+        self.streams.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) async fn send(
+        &self,
+        command: u32,
+        arg0: u32,
+        arg1: u32,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let message = Message::new(command, arg0, arg1, data);
+        write_message(&mut *self.writer.lock().await, &message).await
+    }
+
+    /// Allocates a local id for a new stream.
+    pub(crate) fn register(&self) -> (u32, StreamReceivers) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (acks_tx, acks) = mpsc::unbounded_channel();
+        let (data_tx, data) = mpsc::unbounded_channel();
+        self.streams().insert(
+            id,
+            StreamSenders {
+                acks: acks_tx,
+                data: data_tx,
+            },
+        );
+        (id, StreamReceivers { acks, data })
+    }
+
+    /// Opens `service` on the other end and returns the local and remote
+    /// ids, or `None` if it was refused.
+    pub(crate) async fn open(
+        &self,
+        service: &str,
+    ) -> io::Result<Option<(u32, u32, StreamReceivers)>> {
+        let (local, mut receivers) = self.register();
+        let mut request = service.as_bytes().to_vec();
+        request.push(0);
+        self.send(A_OPEN, local, 0, &request).await?;
+
+        match receivers.acks.recv().await {
+            Some(remote) => Ok(Some((local, remote, receivers))),
+            None => Ok(None),
+        }
+    }
+
+    /// Routes `OKAY`, `WRTE` and `CLSE` to their stream and returns any
+    /// other message.
+    pub(crate) fn dispatch(&self, message: Message) -> Option<Message> {
+        let mut streams = self.streams();
+        match message.command {
+            A_OKAY => {
+                if let Some(stream) = streams.get(&message.arg1) {
+                    let _ = stream.acks.send(message.arg0);
+                }
+            }
+            A_WRTE => {
+                if let Some(stream) = streams.get(&message.arg1) {
+                    let _ = stream.data.send(message.data);
+                }
+            }
+            A_CLSE => {
+                streams.remove(&message.arg1);
+            }
+            _ => return Some(message),
+        }
+        None
+    }
+
+    /// Ends all streams after the connection is lost.
+    pub(crate) fn close_all(&self) {
+        self.streams().clear();
+    }
+
+    /// Copies between `io` and the stream until either side closes it.
+    ///
+    /// Each write waits for the other end's `OKAY` before the next one is
+    /// sent, and incoming data is acknowledged once `io` took it, so neither
+    /// side buffers more than a message.  As with the adb server, `io`
+    /// reaching EOF closes the whole stream.
+    pub(crate) async fn pump<T: AsyncRead + AsyncWrite>(
+        &self,
+        io: T,
+        local: u32,
+        remote: u32,
+        receivers: StreamReceivers,
+    ) {
+        let StreamReceivers { mut acks, mut data } = receivers;
+        let (mut io_read, mut io_write) = tokio::io::split(io);
+
+        let upstream = async {
+            let mut buf = vec![0; self.max_payload];
+            loop {
+                let n = match io_read.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => n,
+                };
+                if self.send(A_WRTE, local, remote, &buf[..n]).await.is_err()
+                    || acks.recv().await.is_none()
+                {
+                    return;
+                }
+            }
+            let open = self.streams().remove(&local).is_some();
+            if open {
+                let _ = self.send(A_CLSE, local, remote, &[]).await;
+            }
+        };
+        let downstream = async {
+            while let Some(chunk) = data.recv().await {
+                if io_write.write_all(&chunk).await.is_err()
+                    || self.send(A_OKAY, local, remote, &[]).await.is_err()
+                {
+                    break;
+                }
+            }
+            let _ = io_write.shutdown().await;
+        };
+        join(upstream, downstream).await;
+    }
+}
+
+/// The identity `adbd` sent in its `CNXN` banner, e.g.
+/// `device::ro.product.name=x;ro.product.model=y;features=shell_v2,cmd`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceBanner {
+    /// `device`, `recovery`, `bootloader`, `sideload` or `rescue`.
+    pub state: String,
+    /// The `ro.product.*` properties, keyed by their full name.
+    pub properties: BTreeMap<String, String>,
+    /// Features supported by both `adbd` and this crate.
+    pub features: BTreeSet<String>,
+}
+
+impl DeviceBanner {
+    pub(crate) fn parse(banner: &[u8]) -> DeviceBanner {
+        let banner = String::from_utf8_lossy(banner);
+        let banner = banner.trim_end_matches('\0');
+        let mut parts = banner.splitn(3, ':');
+        let state = parts.next().unwrap_or_default().to_owned();
+        let _serial = parts.next();
+
+        let mut parsed = DeviceBanner {
+            state,
+            ..Default::default()
+        };
+        for pair in parts.next().unwrap_or_default().split(';') {
+            let Some((key, value)) = pair.split_once('=') else {
+                continue;
+            };
+            if key == "features" {
+                parsed.features = value
+                    .split(',')
+                    .filter(|feature| HOST_FEATURES.contains(feature))
+                    .map(str::to_owned)
+                    .collect();
+            } else {
+                parsed.properties.insert(key.to_owned(), value.to_owned());
+            }
+        }
+        parsed
+    }
+
+    /// The connection state as reported by `host:devices`.
+    pub fn device_state(&self) -> DeviceState {
+        DeviceState::from(self.state.as_str())
+    }
+
+    /// `product`, `model` and `device`, as in `adb devices -l`.
+    fn info(&self) -> BTreeMap<String, String> {
+        ["product", "model", "device"]
+            .into_iter()
+            .filter_map(|key| {
+                let value = if key == "product" {
+                    self.properties.get("ro.product.name")
+                } else {
+                    self.properties.get(&format!("ro.product.{key}"))
+                }?;
+                Some((key.to_owned(), value.clone()))
+            })
+            .collect()
+    }
+}
+
+/// An authenticated connection to `adbd`.
+#[derive(Debug, Clone)]
+pub struct DeviceDirect {
+    session: Arc<Session>,
+}
+
+struct Session {
+    serial: String,
+    banner: DeviceBanner,
+    mux: Arc<Multiplexer>,
+    reader: JoinHandle<()>,
+}
+
+impl std::fmt::Debug for Session {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Session")
+            .field("serial", &self.serial)
+            .field("banner", &self.banner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+impl DeviceDirect {
+    /// Connects to `adbd` at `addr`, `host` or `host:port`, and
+    /// authenticates with `keys`.
+    ///
+    /// If the device knows none of the keys, the first one is offered for
+    /// the user to accept, and this waits until they do.
+    pub async fn connect(addr: &str, keys: &AdbKeySet) -> Result<DeviceDirect> {
+        let addr = if addr.contains(':') {
+            addr.to_owned()
+        } else {
+            format!("{addr}:{DEFAULT_ADBD_PORT}")
+        };
+        let transport = TcpConnector::new(addr.clone(), Timeouts::default().connect)
+            .connect()
+            .await?;
+        DeviceDirect::over(transport, &addr, keys).await
+    }
+
+    /// Runs the handshake over an already open `transport` and names the
+    /// device `serial`.
+    pub async fn over(
+        mut transport: BoxedTransport,
+        serial: &str,
+        keys: &AdbKeySet,
+    ) -> Result<DeviceDirect> {
+        let mut banner = format!("host::features={}", HOST_FEATURES.join(",")).into_bytes();
+        banner.push(0);
+        write_message(
+            &mut transport,
+            &Message::new(A_CNXN, A_VERSION, MAX_PAYLOAD, &banner),
+        )
+        .await?;
+
+        let mut signing_keys = keys.keys().iter();
+        let mut offered_public_key = false;
+        let connected = loop {
+            let message = read_message(&mut transport).await?;
+            match message.command {
+                A_CNXN => break message,
+                A_AUTH if message.arg0 == AUTH_TOKEN => {
+                    if let Some(key) = signing_keys.next() {
+                        let signature = key.sign(&message.data)?;
+                        write_message(
+                            &mut transport,
+                            &Message::new(A_AUTH, AUTH_SIGNATURE, 0, &signature),
+                        )
+                        .await?;
+                    } else if let (false, Some(key)) = (offered_public_key, keys.keys().first()) {
+                        debug!("No adb key accepted by {}, offering a new one", serial);
+                        let mut public_key = key.public_key().into_bytes();
+                        public_key.push(0);
+                        write_message(
+                            &mut transport,
+                            &Message::new(A_AUTH, AUTH_RSAPUBLICKEY, 0, &public_key),
+                        )
+                        .await?;
+                        offered_public_key = true;
+                    } else {
+                        return Err(DeviceError::Adb(format!(
+                            "adbd at {serial} accepted none of the adb keys"
+                        )));
+                    }
+                }
+                A_STLS => {
+                    return Err(DeviceError::Adb(format!(
+                        "adbd at {serial} requires TLS, which is not supported"
+                    )))
+                }
+                command => {
+                    return Err(DeviceError::Adb(format!(
+                        "unexpected adb message {command:#010x} during handshake"
+                    )))
+                }
+            }
+        };
+
+        let banner = DeviceBanner::parse(&connected.data);
+        debug!("Connected to adbd at {}: {:?}", serial, banner);
+        let (reader, writer) = tokio::io::split(transport);
+        let mux = Arc::new(Multiplexer::new(writer, connected.arg1));
+        let reader = tokio::spawn(read_loop(mux.clone(), reader));
+
+        Ok(DeviceDirect {
+            session: Arc::new(Session {
+                serial: serial.to_owned(),
+                banner,
+                mux,
+                reader,
+            }),
+        })
+    }
+
+    /// Name of the device, the address it was connected at.
+    pub fn serial(&self) -> &str {
+        &self.session.serial
+    }
+
+    pub fn banner(&self) -> &DeviceBanner {
+        &self.session.banner
+    }
+
+    /// A [`Host`] whose only device is this one.
+    ///
+    /// Port forwards and other requests handled by the adb server itself
+    /// fail; device services are passed through.
+    pub fn host(&self) -> Host {
+        Host {
+            connector: Some(Arc::new(DirectConnector {
+                session: self.session.clone(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the [`Device`] on this connection.
+    pub async fn device(&self) -> Result<Device> {
+        Device::new(
+            self.host(),
+            self.session.serial.clone(),
+            self.session.banner.info(),
+        )
+        .await
+    }
+}
+
+async fn read_loop(mux: Arc<Multiplexer>, mut reader: ReadHalf<BoxedTransport>) {
+    loop {
+        match read_message(&mut reader).await {
+            Ok(message) => {
+                if let Some(message) = mux.dispatch(message) {
+                    debug!("Ignoring adb message {:#010x}", message.command);
+                }
+            }
+            Err(e) => {
+                debug!("adbd connection lost: {}", e);
+                break;
+            }
+        }
+    }
+    mux.close_all();
+}
+
+/// Serves the adb server protocol for a [`DeviceDirect`].
+#[derive(Debug)]
+struct DirectConnector {
+    session: Arc<Session>,
+}
+
+impl Connector for DirectConnector {
+    fn connect(&self) -> BoxFuture<'_, Result<BoxedTransport>> {
+        Box::pin(async move {
+            let (client, server) = tokio::io::duplex(2 * MAX_PAYLOAD as usize);
+            let session = self.session.clone();
+            tokio::spawn(async move {
+                let _ = serve(session, server).await;
+            });
+            Ok(Box::new(client) as BoxedTransport)
+        })
+    }
+}
+
+async fn serve(session: Arc<Session>, mut stream: DuplexStream) -> io::Result<()> {
+    loop {
+        let mut length = [0u8; 4];
+        match stream.read_exact(&mut length).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let length = std::str::from_utf8(&length)
+            .ok()
+            .and_then(|s| usize::from_str_radix(s, 16).ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid length"))?;
+        let mut request = vec![0; length];
+        stream.read_exact(&mut request).await?;
+        let request = String::from_utf8_lossy(&request).into_owned();
+
+        let serial = session.serial.as_str();
+        if let Some(target) = request.strip_prefix("host:transport:") {
+            if target != serial {
+                return write_fail(&mut stream, &format!("device '{target}' not found")).await;
+            }
+            stream.write_all(SyncCommand::Okay.code()).await?;
+            continue;
+        }
+        if request == "host:transport-any" {
+            stream.write_all(SyncCommand::Okay.code()).await?;
+            continue;
+        }
+
+        let host_service = match request.strip_prefix("host-serial:") {
+            Some(rest) => match rest.strip_prefix(serial).and_then(|s| s.strip_prefix(':')) {
+                Some(service) => Some(service),
+                None => return write_fail(&mut stream, "device not found").await,
+            },
+            None => request.strip_prefix("host:"),
+        };
+        if let Some(service) = host_service {
+            let banner = &session.banner;
+            let payload = match service {
+                "version" => "0029".to_owned(),
+                "features" => banner
+                    .features
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(","),
+                "get-state" => banner.state.clone(),
+                "get-serialno" => serial.to_owned(),
+                "devices" => format!("{serial}\t{}\n", banner.state),
+                "devices-l" => {
+                    let mut line = format!("{serial}\t{}", banner.state);
+                    for (key, value) in banner.info() {
+                        line.push_str(&format!(" {key}:{value}"));
+                    }
+                    line + "\n"
+                }
+                _ => {
+                    return write_fail(
+                        &mut stream,
+                        &format!("'{service}' is not available over a direct adbd connection"),
+                    )
+                    .await
+                }
+            };
+            stream.write_all(SyncCommand::Okay.code()).await?;
+            stream
+                .write_all(format!("{:04x}", payload.len()).as_bytes())
+                .await?;
+            return stream.write_all(payload.as_bytes()).await;
+        }
+
+        let Some((local, remote, receivers)) = session.mux.open(&request).await? else {
+            return write_fail(&mut stream, &format!("closed: {request}")).await;
+        };
+        stream.write_all(SyncCommand::Okay.code()).await?;
+        session.mux.pump(stream, local, remote, receivers).await;
+        return Ok(());
+    }
+}
+
+async fn write_fail(stream: &mut DuplexStream, message: &str) -> io::Result<()> {
+    stream.write_all(SyncCommand::Fail.code()).await?;
+    stream
+        .write_all(format!("{:04x}", message.len()).as_bytes())
+        .await?;
+    stream.write_all(message.as_bytes()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn message_round_trip() {
+        let message = Message::new(A_WRTE, 3, 7, b"hello");
+        let mut buf = Vec::new();
+        write_message(&mut buf, &message).await.expect("write");
+        assert_eq!(&buf[..4], b"WRTE");
+        assert_eq!(&buf[16..20], &532u32.to_le_bytes());
+        assert_eq!(read_message(&mut &buf[..]).await.expect("read"), message);
+
+        buf[20] ^= 1;
+        assert!(read_message(&mut &buf[..]).await.is_err());
+    }
+
+    #[test]
+    fn banner() {
+        let banner = DeviceBanner::parse(
+            b"device::ro.product.name=sargo;ro.product.model=Pixel 3a;ro.product.device=sargo;features=shell_v2,cmd,sendrecv_v2_brotli\0",
+        );
+        assert_eq!(banner.device_state(), DeviceState::Device);
+        assert_eq!(banner.info()["model"], "Pixel 3a");
+        assert_eq!(
+            banner.features.into_iter().collect::<Vec<_>>(),
+            ["cmd", "shell_v2"]
+        );
+    }
+}
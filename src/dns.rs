@@ -0,0 +1,185 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! DNS servers per network and the Private DNS (DNS over TLS) setting.
+
+use std::net::IpAddr;
+
+use crate::locale::setting;
+use crate::{Device, Result};
+
+/// The Private DNS setting of Android 9 and later.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrivateDnsMode {
+    Off,
+    /// Encrypts DNS when the network's server supports it ("Automatic").
+    Opportunistic,
+    /// Always uses the given DNS over TLS provider.
+    Strict(String),
+}
+
+/// DNS configuration of one network, from its link properties.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NetworkDns {
+    /// Network id, the `netId` used in routing tables and logs.
+    pub network_id: Option<u32>,
+    pub interface: Option<String>,
+    /// Transports as printed by `dumpsys`, e.g. `WIFI` or `CELLULAR|VPN`.
+    pub transports: Option<String>,
+    pub servers: Vec<IpAddr>,
+    pub search_domains: Vec<String>,
+    /// Provider the network uses for DNS over TLS in strict mode.
+    pub private_dns_server: Option<String>,
+}
+
+/// Result of [`Device::dns_config`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DnsConfig {
+    /// `None` when never changed, which means opportunistic on Android 9
+    /// and later and no Private DNS before.
+    pub private_dns_mode: Option<PrivateDnsMode>,
+    /// Connected networks.
+    pub networks: Vec<NetworkDns>,
+}
+
+impl Device {
+    /// Reports the DNS servers and search domains of each connected network
+    /// and the Private DNS setting, from `dumpsys connectivity` and the
+    /// global settings in one shell round trip.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn dns_config(&self) -> Result<DnsConfig> {
+        let outputs = self
+            .run_batch(&[
+                "settings get global private_dns_mode",
+                "settings get global private_dns_specifier",
+                "dumpsys connectivity",
+            ])
+            .await?;
+
+        let private_dns_mode = match setting(&outputs[0]).as_deref() {
+            Some("off") => Some(PrivateDnsMode::Off),
+            Some("opportunistic") => Some(PrivateDnsMode::Opportunistic),
+            Some("hostname") => Some(PrivateDnsMode::Strict(
+                setting(&outputs[1]).unwrap_or_default(),
+            )),
+            _ => None,
+        };
+
+        Ok(DnsConfig {
+            private_dns_mode,
+            networks: parse_networks(&outputs[2].stdout_lossy()),
+        })
+    }
+}
+
+/// Text after `key` up to the next space, e.g. `wlan0` for `InterfaceName: `,
+/// without the braces closing the enclosing object.
+pub(crate) fn field<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let start = line.find(key)? + key.len();
+    let value = line[start..].split_whitespace().next()?;
+    Some(value.trim_end_matches(['}', ']']))
+}
+
+/// Items of the comma-separated list following `key`, e.g. `DnsAddresses:
+/// [ /192.168.1.1,/fe80::1 ]`.
+pub(crate) fn list<'a>(line: &'a str, key: &str) -> Vec<&'a str> {
+    let Some(start) = line.find(key) else {
+        return Vec::new();
+    };
+    let rest = line[start + key.len()..].trim_start();
+    let Some(items) = rest
+        .strip_prefix('[')
+        .and_then(|rest| rest.split(']').next())
+    else {
+        return Vec::new();
+    };
+    items
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// The `NetworkAgentInfo` lines of `dumpsys connectivity` with their network
+/// id, one per network: the same network can be listed in several sections.
+pub(crate) fn network_agents(output: &str) -> Vec<(Option<u32>, &str)> {
+    let mut agents: Vec<(Option<u32>, &str)> = Vec::new();
+    for line in output.lines() {
+        if !line.contains("NetworkAgentInfo") || !line.contains("lp{") {
+            continue;
+        }
+        let network_id = line
+            .find("network{")
+            .and_then(|start| line[start + "network{".len()..].split('}').next())
+            .and_then(|id| id.parse().ok());
+        if network_id.is_none() || agents.iter().all(|(id, _)| *id != network_id) {
+            agents.push((network_id, line));
+        }
+    }
+    agents
+}
+
+/// Parses the link properties embedded in each network agent line as
+/// `lp{{InterfaceName: wlan0 ... DnsAddresses: [ /192.168.1.1,/fe80::1 ]
+/// Domains: home.lan ... PrivateDnsServerName: dns.google ...}}`.
+fn parse_networks(output: &str) -> Vec<NetworkDns> {
+    network_agents(output)
+        .into_iter()
+        .map(|(network_id, line)| NetworkDns {
+            network_id,
+            interface: field(line, "InterfaceName: ").map(str::to_owned),
+            transports: field(line, "Transports: ").map(str::to_owned),
+            servers: list(line, "DnsAddresses:")
+                .into_iter()
+                .filter_map(|address| address.trim_start_matches('/').parse().ok())
+                .collect(),
+            search_domains: field(line, "Domains: ")
+                .filter(|domains| *domains != "null")
+                .map(|domains| {
+                    domains
+                        .split(',')
+                        .filter(|domain| !domain.is_empty())
+                        .map(str::to_owned)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            private_dns_server: field(line, "PrivateDnsServerName: ")
+                .filter(|name| *name != "null")
+                .map(str::to_owned),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_networks() {
+        let output = "Current Networks:\n\
+                      \x20 NetworkAgentInfo{network{100}  handle{432902426637}  ni{WIFI CONNECTED extra: }  Score{76}  lp{{InterfaceName: wlan0 LinkAddresses: [ 192.168.1.23/24 ] DnsAddresses: [ /192.168.1.1,/fe80::1 ] Domains: home.lan MTU: 0 TcpBufferSizes: 524288 UsePrivateDns: true PrivateDnsServerName: dns.example.com Routes: [ 0.0.0.0/0 -> 192.168.1.1 wlan0 ]}}  nc{[ Transports: WIFI Capabilities: INTERNET&NOT_RESTRICTED ]}}\n\
+                      \x20 NetworkAgentInfo{network{101}  ni{MOBILE[LTE] CONNECTED}  lp{{InterfaceName: rmnet_data0 LinkAddresses: [ 10.12.0.5/30 ] DnsAddresses: [ /10.0.0.53 ] Domains: null PrivateDnsServerName: null}}  nc{[ Transports: CELLULAR Capabilities: INTERNET ]}}\n\
+                      Network Requests:\n\
+                      \x20 NetworkAgentInfo{network{100}  lp{{InterfaceName: wlan0}}}\n";
+        let networks = parse_networks(output);
+        assert_eq!(networks.len(), 2);
+        assert_eq!(
+            networks[0],
+            NetworkDns {
+                network_id: Some(100),
+                interface: Some("wlan0".to_owned()),
+                transports: Some("WIFI".to_owned()),
+                servers: vec!["192.168.1.1".parse().unwrap(), "fe80::1".parse().unwrap()],
+                search_domains: vec!["home.lan".to_owned()],
+                private_dns_server: Some("dns.example.com".to_owned()),
+            }
+        );
+        assert_eq!(networks[1].transports.as_deref(), Some("CELLULAR"));
+        assert!(networks[1].search_domains.is_empty());
+        assert_eq!(networks[1].private_dns_server, None);
+    }
+}
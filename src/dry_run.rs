@@ -0,0 +1,104 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Reports of what destructive operations touch, so they can be previewed.
+//!
+//! Transfers are previewed with [`TransferOptions::dry_run`](crate::TransferOptions::dry_run).
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    staging_apk_path, Device, DeviceError, InstallOptions, Result, UnixFileStatus, UnixPath,
+    UnixPathBuf,
+};
+
+/// What [`Device::remove_with_report`] deleted, or would delete.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RemovalReport {
+    /// Files, symbolic links and special files.
+    pub files: Vec<UnixPathBuf>,
+    /// Directories, the removed path itself first if it is one.
+    pub directories: Vec<UnixPathBuf>,
+    /// Total size of the regular files.
+    pub bytes: u64,
+}
+
+/// What [`Device::install_package_dry_run`] found `install_package` would do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstallPlan {
+    pub apk_path: PathBuf,
+    /// Bytes that would be pushed.
+    pub bytes: u64,
+    /// Where the apk would be staged on the device.
+    pub staging_path: UnixPathBuf,
+    /// The package manager command that would be run.
+    pub command: String,
+}
+
+impl Device {
+    /// Like [`Device::remove`], reporting the removed entries.  With
+    /// `dry_run` nothing is deleted.
+    ///
+    /// Entries are enumerated over the sync protocol, so a path that is only
+    /// reachable through run-as is reported as empty.
+    pub async fn remove_with_report(
+        &self,
+        path: &UnixPath,
+        dry_run: bool,
+    ) -> Result<RemovalReport> {
+        let mut report = RemovalReport::default();
+
+        match self.stat(path).await {
+            Ok(metadata) if metadata.file_mode == UnixFileStatus::Directory => {
+                report.directories.push(path.to_path_buf());
+                for entry in self.list_dir(path).await? {
+                    let entry_path = path.join(&entry.path);
+                    match entry.file_mode {
+                        UnixFileStatus::Directory => report.directories.push(entry_path),
+                        UnixFileStatus::RegularFile => {
+                            report.bytes += entry.size as u64;
+                            report.files.push(entry_path);
+                        }
+                        _ => report.files.push(entry_path),
+                    }
+                }
+            }
+            Ok(metadata) => {
+                if metadata.file_mode == UnixFileStatus::RegularFile {
+                    report.bytes = metadata.size as u64;
+                }
+                report.files.push(path.to_path_buf());
+            }
+            // `rm -rf` accepts missing paths, so there is nothing to report.
+            Err(DeviceError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        if !dry_run {
+            self.remove(path).await?;
+        }
+
+        Ok(report)
+    }
+
+    /// Reports what [`Device::install_package`] would push and run, without
+    /// touching the device.
+    pub async fn install_package_dry_run(
+        &self,
+        apk_path: &Path,
+        options: &InstallOptions,
+    ) -> Result<InstallPlan> {
+        let staging_path = staging_apk_path(apk_path)?;
+        let bytes = std::fs::metadata(apk_path)?.len();
+        let command = self.install_command(&staging_path, options).await?;
+
+        Ok(InstallPlan {
+            apk_path: apk_path.to_path_buf(),
+            bytes,
+            staging_path,
+            command,
+        })
+    }
+}
@@ -0,0 +1,171 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Storage encryption and whether each user's credential encrypted storage
+//! is unlocked, which decides what data can be reached.
+
+use crate::locale::setting;
+use crate::profile::parse_user_records;
+use crate::{Device, Result};
+
+/// How user data is encrypted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncryptionType {
+    /// File-based encryption, mandatory since Android 10: each user has
+    /// device encrypted (DE) storage and credential encrypted (CE) storage
+    /// that unlocks with their lock screen credential.
+    FileBased,
+    /// Full-disk encryption of the whole `userdata` partition, Android 5 to
+    /// 9.
+    FullDisk,
+}
+
+/// Run state of a user, from `dumpsys user`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UserRunState {
+    Booting,
+    /// Running with CE storage still locked, before the first unlock.
+    RunningLocked,
+    RunningUnlocking,
+    RunningUnlocked,
+    Stopping,
+    Shutdown,
+    /// A state this crate does not know.
+    Other(String),
+}
+
+impl UserRunState {
+    pub(crate) fn parse(state: &str) -> UserRunState {
+        match state {
+            "BOOTING" => UserRunState::Booting,
+            "RUNNING_LOCKED" => UserRunState::RunningLocked,
+            "RUNNING_UNLOCKING" => UserRunState::RunningUnlocking,
+            "RUNNING_UNLOCKED" => UserRunState::RunningUnlocked,
+            "STOPPING" => UserRunState::Stopping,
+            "SHUTDOWN" => UserRunState::Shutdown,
+            other => UserRunState::Other(other.to_owned()),
+        }
+    }
+}
+
+/// Storage state of one user of [`EncryptionState`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserStorageState {
+    pub user_id: u32,
+    pub name: Option<String>,
+    /// `None` for users that are not running.
+    pub state: Option<UserRunState>,
+}
+
+impl UserStorageState {
+    /// Whether the user's CE storage is unlocked, so that app data other
+    /// than the direct boot aware parts can be read.
+    pub fn ce_unlocked(&self) -> bool {
+        matches!(
+            self.state,
+            Some(UserRunState::RunningUnlocking | UserRunState::RunningUnlocked)
+        )
+    }
+}
+
+/// Result of [`Device::encryption_state`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EncryptionState {
+    /// `ro.crypto.state`: `encrypted`, `unencrypted` or `unsupported`.
+    pub crypto_state: Option<String>,
+    /// `None` when the data is not encrypted.
+    pub encryption_type: Option<EncryptionType>,
+    /// Metadata encryption of the file system, `ro.crypto.metadata.enabled`.
+    pub metadata_encryption: bool,
+    pub users: Vec<UserStorageState>,
+}
+
+impl Device {
+    /// Reports how storage is encrypted and whether each user's credential
+    /// encrypted storage is unlocked, from the `ro.crypto.*` properties and
+    /// `dumpsys user` in one shell round trip.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn encryption_state(&self) -> Result<EncryptionState> {
+        let outputs = self
+            .run_batch(&[
+                "getprop ro.crypto.state",
+                "getprop ro.crypto.type",
+                "getprop ro.crypto.metadata.enabled",
+                "dumpsys user",
+            ])
+            .await?;
+        let crypto_state = setting(&outputs[0]);
+
+        // `ro.crypto.type` exists since Android 7; before, only FDE existed.
+        let encryption_type = match (crypto_state.as_deref(), setting(&outputs[1]).as_deref()) {
+            (Some("encrypted"), Some("file")) => Some(EncryptionType::FileBased),
+            (Some("encrypted"), Some("block") | None) => Some(EncryptionType::FullDisk),
+            _ => None,
+        };
+
+        Ok(EncryptionState {
+            crypto_state,
+            encryption_type,
+            metadata_encryption: setting(&outputs[2]).as_deref() == Some("true"),
+            users: parse_users(&outputs[3].stdout_lossy()),
+        })
+    }
+}
+
+/// The users of `dumpsys user` with their run state.
+fn parse_users(output: &str) -> Vec<UserStorageState> {
+    parse_user_records(output)
+        .into_iter()
+        .map(|user| UserStorageState {
+            user_id: user.user_id,
+            name: user.name,
+            state: user.state,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_users() {
+        let users = parse_users(
+            "Current user: 0\n\
+             Users:\n\
+             \x20 UserInfo{0:Owner:c13} serialNo=0 isPrimary=true\n\
+             \x20   Type: android.os.usertype.full.SYSTEM\n\
+             \x20   State: RUNNING_UNLOCKED\n\
+             \x20 UserInfo{10:Work profile:1030} serialNo=10 isPrimary=false\n\
+             \x20   State: RUNNING_LOCKED\n\
+             \x20 UserInfo{11:Guest:814}\n\
+             \x20   State: -1\n",
+        );
+        assert_eq!(
+            users,
+            [
+                UserStorageState {
+                    user_id: 0,
+                    name: Some("Owner".to_owned()),
+                    state: Some(UserRunState::RunningUnlocked),
+                },
+                UserStorageState {
+                    user_id: 10,
+                    name: Some("Work profile".to_owned()),
+                    state: Some(UserRunState::RunningLocked),
+                },
+                UserStorageState {
+                    user_id: 11,
+                    name: Some("Guest".to_owned()),
+                    state: None,
+                },
+            ]
+        );
+        assert!(users[0].ce_unlocked());
+        assert!(!users[1].ce_unlocked() && !users[2].ce_unlocked());
+    }
+}
@@ -0,0 +1,201 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Export of an installed app with its splits and OBB files, to reinstall it
+//! on another device.
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use crate::package::check_package_name;
+use crate::{Device, DeviceError, Result, TransferOptions, UnixFileStatus, UnixPathBuf};
+
+/// Name of the manifest written next to the apks.
+pub const EXPORT_MANIFEST: &str = "manifest.json";
+
+/// What [`Device::export_app`] exported, also written as
+/// [`EXPORT_MANIFEST`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AppExport {
+    pub package: String,
+    pub version_code: Option<u64>,
+    pub version_name: Option<String>,
+    /// File names of the base apk and the splits, base first.
+    pub apks: Vec<String>,
+    /// Paths of the OBB files relative to the export, e.g.
+    /// `Android/obb/org.example/main.1.org.example.obb`.
+    pub obbs: Vec<String>,
+    /// Hex SHA-256 digests of the signing certificates.
+    pub signing_certificates: Vec<String>,
+    /// Total size of the apks and OBB files.
+    pub bytes: u64,
+}
+
+impl AppExport {
+    /// Returns the manifest as JSON.
+    pub fn to_json(&self) -> String {
+        fn list(values: &[String]) -> String {
+            let values: Vec<_> = values.iter().map(|value| json_string(value)).collect();
+            format!("[{}]", values.join(", "))
+        }
+
+        let mut json = String::from("{\n");
+        let _ = writeln!(json, "  \"package\": {},", json_string(&self.package));
+        let _ = writeln!(
+            json,
+            "  \"version_code\": {},",
+            self.version_code
+                .map_or("null".to_owned(), |code| code.to_string())
+        );
+        let _ = writeln!(
+            json,
+            "  \"version_name\": {},",
+            self.version_name
+                .as_deref()
+                .map_or("null".to_owned(), json_string)
+        );
+        let _ = writeln!(json, "  \"apks\": {},", list(&self.apks));
+        let _ = writeln!(json, "  \"obbs\": {},", list(&self.obbs));
+        let _ = writeln!(
+            json,
+            "  \"signing_certificates\": {},",
+            list(&self.signing_certificates)
+        );
+        let _ = writeln!(json, "  \"bytes\": {}", self.bytes);
+        json.push_str("}\n");
+        json
+    }
+}
+
+impl Device {
+    /// Exports the installed `package` with its split apks and the OBB files
+    /// under `Android/obb/<package>` to `dest`.
+    ///
+    /// If `dest` ends in `.apks` a zip archive is written, as read by split
+    /// apk installers, otherwise `dest` is a directory.  Either holds the
+    /// apks at the top, the OBB files under `Android/obb/<package>/` and an
+    /// [`EXPORT_MANIFEST`] describing them.
+    pub async fn export_app(&self, package: &str, dest: &Path) -> Result<AppExport> {
+        // `package` names a shell argument and the OBB directory.
+        check_package_name(package)?;
+        let archive = dest.extension().is_some_and(|ext| ext == "apks");
+        let staging = if archive {
+            Some(tempfile::tempdir()?)
+        } else {
+            None
+        };
+        let dir = staging.as_ref().map_or(dest, |staging| staging.path());
+
+        let pulled = self.pull_apk(package, dir).await?;
+        let mut export = AppExport {
+            package: package.to_owned(),
+            signing_certificates: pulled.signing_certificates,
+            ..Default::default()
+        };
+        for apk in pulled.apks {
+            export.bytes += apk.bytes;
+            if let Some(name) = apk.device_path.file_name() {
+                export.apks.push(name.to_string_lossy().into_owned());
+            }
+        }
+
+        let obb_dir = format!("Android/obb/{package}");
+        let device_obb_dir = UnixPathBuf::from("/sdcard").join(&obb_dir);
+        let has_obbs = match self.stat(&device_obb_dir).await {
+            Ok(metadata) => metadata.file_mode == UnixFileStatus::Directory,
+            Err(DeviceError::Io(e)) if e.kind() == io::ErrorKind::NotFound => false,
+            Err(e) => return Err(e),
+        };
+        if has_obbs {
+            let report = self
+                .pull_dir_internal(
+                    &device_obb_dir,
+                    &dir.join(&obb_dir),
+                    None,
+                    &TransferOptions::default(),
+                )
+                .await?;
+            for file in report.files {
+                export.bytes += file.bytes;
+                if let Ok(tail) = file.device_path.strip_prefix("/sdcard") {
+                    export.obbs.push(tail.display().to_string());
+                }
+            }
+            export.obbs.sort();
+        }
+
+        (export.version_code, export.version_name) = self.package_version(package).await?;
+        std::fs::write(dir.join(EXPORT_MANIFEST), export.to_json())?;
+
+        if archive {
+            let mut entries = export.apks.clone();
+            entries.extend(export.obbs.iter().cloned());
+            entries.push(EXPORT_MANIFEST.to_owned());
+            let dir = dir.to_path_buf();
+            let dest = dest.to_path_buf();
+            tokio::task::spawn_blocking(move || write_archive(&dir, &entries, &dest))
+                .await
+                .map_err(io::Error::other)??;
+        }
+
+        Ok(export)
+    }
+
+    /// Returns the version code and name from `dumpsys package`.
+    async fn package_version(&self, package: &str) -> Result<(Option<u64>, Option<String>)> {
+        let output = self
+            .execute_host_shell_command(&format!("dumpsys package {package}"))
+            .await?;
+
+        let mut version_code = None;
+        let mut version_name = None;
+        for line in output.lines().map(str::trim) {
+            // The name takes the whole line and may contain spaces.
+            if let Some(name) = line.strip_prefix("versionName=") {
+                version_name = version_name.or(Some(name.to_owned()));
+                continue;
+            }
+            for field in line.split_whitespace() {
+                if let Some(code) = field.strip_prefix("versionCode=") {
+                    version_code = version_code.or(code.parse().ok());
+                }
+            }
+        }
+        Ok((version_code, version_name))
+    }
+}
+
+/// Stores `entries` of `dir` uncompressed in a zip at `dest`; apks and OBB
+/// files are compressed already.
+fn write_archive(dir: &Path, entries: &[String], dest: &Path) -> io::Result<()> {
+    let mut zip = zip::ZipWriter::new(File::create(dest)?);
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored)
+        .large_file(true);
+    for entry in entries {
+        zip.start_file(entry.as_str(), options)
+            .map_err(io::Error::other)?;
+        io::copy(&mut File::open(dir.join(entry))?, &mut zip)?;
+    }
+    zip.finish().map_err(io::Error::other)?;
+    Ok(())
+}
+
+pub(crate) fn json_string(value: &str) -> String {
+    let mut json = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
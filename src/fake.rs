@@ -0,0 +1,368 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! In-memory [`AdbDevice`] for unit tests of code built on this crate.
+//!
+//! Unlike [`MockServer`](crate::testing::MockServer), which fakes the adb
+//! protocol underneath a real [`Device`](crate::Device), a [`FakeDevice`]
+//! fakes the API itself: files live in a map, shell commands return canned
+//! output, and nothing is parsed or sent anywhere.
+//!
+//! ```
+//! # async fn example() -> forensic_adb::Result<()> {
+//! use forensic_adb::fake::FakeDevice;
+//! use forensic_adb::{AdbDevice, UnixPath};
+//!
+//! let device = FakeDevice::new("emulator-5554");
+//! device.on_shell("getprop ro.product.model", "Pixel\n");
+//! device.add_file("/sdcard/notes.txt", "hello");
+//!
+//! let model = device
+//!     .execute_host_shell_command("getprop ro.product.model")
+//!     .await?;
+//! assert_eq!(model, "Pixel\n");
+//! assert_eq!(device.commands(), ["getprop ro.product.model"]);
+//!
+//! let mut notes = Vec::new();
+//! device.pull(UnixPath::new("/sdcard/notes.txt"), &mut notes).await?;
+//! assert_eq!(notes, b"hello");
+//! # Ok(())
+//! # }
+//! ```
+
+use futures_core::future::BoxFuture;
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::testing::{normalize, now, parent_of, MockEntry};
+use crate::{AdbDevice, DeviceError, FileMetadata, Result, ShellOutput, UnixFileStatus, UnixPath};
+
+/// A scriptable device with an in-memory filesystem.
+///
+/// Clones share their state, so a test can keep a handle to inspect what
+/// the code under test did.
+#[derive(Debug, Clone, Default)]
+pub struct FakeDevice {
+    serial: String,
+    state: Arc<Mutex<FakeState>>,
+}
+
+#[derive(Debug, Default)]
+struct FakeState {
+    files: BTreeMap<String, MockEntry>,
+    shell: BTreeMap<String, ShellOutput>,
+    /// Installed packages and whether they are third-party.
+    packages: BTreeMap<String, bool>,
+    commands: Vec<String>,
+}
+
+impl FakeDevice {
+    pub fn new(serial: &str) -> FakeDevice {
+        FakeDevice {
+            serial: serial.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, FakeState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Sets the output of a shell command.
+    ///
+    /// Commands without a canned response produce empty output.
+    pub fn on_shell<T: AsRef<[u8]>>(&self, command: &str, output: T) {
+        self.on_shell_result(command, output, "", 0);
+    }
+
+    /// Sets stdout, stderr and exit code of a shell command.
+    pub fn on_shell_result<T: AsRef<[u8]>, E: AsRef<[u8]>>(
+        &self,
+        command: &str,
+        stdout: T,
+        stderr: E,
+        exit_code: i32,
+    ) {
+        self.state().shell.insert(
+            command.to_owned(),
+            ShellOutput {
+                stdout: stdout.as_ref().to_vec(),
+                stderr: stderr.as_ref().to_vec(),
+                exit_code,
+            },
+        );
+    }
+
+    /// Returns the shell commands run so far, in order.
+    pub fn commands(&self) -> Vec<String> {
+        self.state().commands.clone()
+    }
+
+    /// Adds a regular file with mode 0644, creating its parent directories.
+    pub fn add_file<T: AsRef<[u8]>>(&self, path: &str, data: T) {
+        self.insert(
+            path,
+            MockEntry::File {
+                data: data.as_ref().to_vec(),
+                mode: 0o644,
+                mtime: now(),
+            },
+        );
+    }
+
+    /// Adds a directory, creating its parents.
+    pub fn add_dir(&self, path: &str) {
+        self.insert(
+            path,
+            MockEntry::Directory {
+                mode: 0o755,
+                mtime: now(),
+            },
+        );
+    }
+
+    /// Adds an arbitrary entry, creating its parent directories.
+    pub fn insert(&self, path: &str, entry: MockEntry) {
+        let path = normalize(path);
+        let mut state = self.state();
+        let mut parent = parent_of(&path);
+        while let Some(dir) = parent {
+            state
+                .files
+                .entry(dir.clone())
+                .or_insert(MockEntry::Directory {
+                    mode: 0o755,
+                    mtime: now(),
+                });
+            parent = parent_of(&dir);
+        }
+        state.files.insert(path, entry);
+    }
+
+    /// Returns the entry at `path`.
+    pub fn entry(&self, path: &str) -> Option<MockEntry> {
+        self.state().files.get(&normalize(path)).cloned()
+    }
+
+    /// Returns the contents of the regular file at `path`.
+    pub fn file(&self, path: &str) -> Option<Vec<u8>> {
+        match self.entry(path) {
+            Some(MockEntry::File { data, .. }) => Some(data),
+            _ => None,
+        }
+    }
+
+    /// Marks `package` as installed.
+    pub fn add_package(&self, package: &str, third_party: bool) {
+        self.state()
+            .packages
+            .insert(package.to_owned(), third_party);
+    }
+
+    fn shell(&self, command: &str) -> ShellOutput {
+        let mut state = self.state();
+        state.commands.push(command.to_owned());
+        state.shell.get(command).cloned().unwrap_or_default()
+    }
+
+    fn metadata(&self, path: &str, name: String, depth: Option<usize>) -> Option<FileMetadata> {
+        let (mode, size, mtime) = self.state().files.get(path)?.stat();
+        let file_mode = match mode & 0xf000 {
+            0x4000 => UnixFileStatus::Directory,
+            0xa000 => UnixFileStatus::SymbolicLink,
+            _ => UnixFileStatus::RegularFile,
+        };
+        Some(FileMetadata {
+            path: name,
+            file_mode,
+            size,
+            modified_time: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(mtime.into())),
+            depth,
+        })
+    }
+
+    /// Paths of the entries below `dir`, excluding `dir` itself.
+    fn descendants(&self, dir: &str) -> Vec<String> {
+        let prefix = if dir == "/" {
+            "/".to_owned()
+        } else {
+            format!("{dir}/")
+        };
+        self.state()
+            .files
+            .keys()
+            .filter(|path| path.starts_with(&prefix))
+            .cloned()
+            .collect()
+    }
+}
+
+fn not_found(path: &UnixPath) -> DeviceError {
+    DeviceError::Io(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("remote path not found: {}", path.display()),
+    ))
+}
+
+impl AdbDevice for FakeDevice {
+    fn serial(&self) -> &str {
+        &self.serial
+    }
+
+    fn execute_host_shell_command<'a>(
+        &'a self,
+        shell_command: &'a str,
+    ) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            let output = self.shell(shell_command);
+            let mut merged = output.stdout;
+            merged.extend(output.stderr);
+            Ok(String::from_utf8(merged).map_err(|e| e.utf8_error())?)
+        })
+    }
+
+    fn run<'a>(&'a self, command: &'a str) -> BoxFuture<'a, Result<ShellOutput>> {
+        Box::pin(async move { Ok(self.shell(command)) })
+    }
+
+    fn list_dir<'a>(&'a self, src: &'a UnixPath) -> BoxFuture<'a, Result<Vec<FileMetadata>>> {
+        Box::pin(async move {
+            let dir = normalize(&src.display().to_string());
+            let prefix_len = if dir == "/" { 1 } else { dir.len() + 1 };
+            Ok(self
+                .descendants(&dir)
+                .into_iter()
+                .filter_map(|path| {
+                    let name = path[prefix_len..].to_owned();
+                    let depth = name.matches('/').count();
+                    self.metadata(&path, name, Some(depth))
+                })
+                .collect())
+        })
+    }
+
+    fn stat<'a>(&'a self, path: &'a UnixPath) -> BoxFuture<'a, Result<FileMetadata>> {
+        Box::pin(async move {
+            let name = path.display().to_string();
+            self.metadata(&normalize(&name), name, None)
+                .ok_or_else(|| not_found(path))
+        })
+    }
+
+    fn path_exists<'a>(
+        &'a self,
+        path: &'a UnixPath,
+        _enable_run_as: bool,
+    ) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move { Ok(self.entry(&path.display().to_string()).is_some()) })
+    }
+
+    fn pull<'a>(
+        &'a self,
+        src: &'a UnixPath,
+        buffer: &'a mut (dyn AsyncWrite + Unpin + Send),
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let data = self
+                .file(&src.display().to_string())
+                .ok_or_else(|| not_found(src))?;
+            buffer.write_all(&data).await?;
+            buffer.flush().await?;
+            Ok(())
+        })
+    }
+
+    fn push<'a>(
+        &'a self,
+        buffer: &'a mut (dyn AsyncRead + Unpin + Send),
+        dest: &'a UnixPath,
+        mode: u32,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut data = Vec::new();
+            buffer.read_to_end(&mut data).await?;
+            self.insert(
+                &dest.display().to_string(),
+                MockEntry::File {
+                    data,
+                    mode: mode & 0o7777,
+                    mtime: now(),
+                },
+            );
+            Ok(())
+        })
+    }
+
+    fn remove<'a>(&'a self, path: &'a UnixPath) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let path = normalize(&path.display().to_string());
+            let below = self.descendants(&path);
+            let mut state = self.state();
+            state.files.remove(&path);
+            for path in below {
+                state.files.remove(&path);
+            }
+            Ok(())
+        })
+    }
+
+    fn create_dir<'a>(&'a self, path: &'a UnixPath) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.add_dir(&path.display().to_string());
+            Ok(())
+        })
+    }
+
+    fn chmod<'a>(
+        &'a self,
+        path: &'a UnixPath,
+        mask: &'a str,
+        recursive: bool,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let new_mode = u32::from_str_radix(mask, 8).map_err(|_| {
+                DeviceError::Adb(format!(
+                    "FakeDevice only supports octal modes, not '{mask}'"
+                ))
+            })?;
+            let path = normalize(&path.display().to_string());
+            let mut paths = vec![path.clone()];
+            if recursive {
+                paths.extend(self.descendants(&path));
+            }
+
+            let mut state = self.state();
+            if !state.files.contains_key(&path) {
+                return Err(not_found(UnixPath::new(&path)));
+            }
+            for path in paths {
+                if let Some(MockEntry::File { mode, .. } | MockEntry::Directory { mode, .. }) =
+                    state.files.get_mut(&path)
+                {
+                    *mode = new_mode;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn list_packages(&self, third_party: bool) -> BoxFuture<'_, Result<Vec<String>>> {
+        Box::pin(async move {
+            Ok(self
+                .state()
+                .packages
+                .iter()
+                .filter(|(_, is_third_party)| !third_party || **is_third_party)
+                .map(|(package, _)| package.clone())
+                .collect())
+        })
+    }
+
+    fn is_app_installed<'a>(&'a self, package: &'a str) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move { Ok(self.state().packages.contains_key(package)) })
+    }
+}
@@ -0,0 +1,61 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::collections::BTreeSet;
+
+use crate::{Device, Result};
+
+impl Device {
+    /// Returns the features supported by both the adb server and the device,
+    /// e.g. `shell_v2` or `sendrecv_v2`.
+    ///
+    /// The list is queried once and shared by all clones of this device.
+    pub async fn features(&self) -> Result<&BTreeSet<String>> {
+        self.features
+            .get_or_try_init(|| async {
+                let features = self
+                    .host
+                    .execute_command(&format!("host-serial:{}:features", self.serial), true, true)
+                    .await?;
+
+                Ok(features
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|feature| !feature.is_empty())
+                    .map(str::to_owned)
+                    .collect())
+            })
+            .await
+    }
+
+    /// Whether `feature` is supported, see [`Device::features`].
+    pub async fn supports(&self, feature: &str) -> Result<bool> {
+        Ok(self.features().await?.contains(feature))
+    }
+
+    /// Whether shell commands can report stderr and exit codes separately.
+    pub async fn supports_shell_v2(&self) -> Result<bool> {
+        self.supports("shell_v2").await
+    }
+
+    /// Whether the sync protocol supports the v2 `SND2`/`RCV2` requests.
+    pub async fn supports_sendrecv_v2(&self) -> Result<bool> {
+        self.supports("sendrecv_v2").await
+    }
+
+    /// Whether the sync protocol supports the v2 `STA2`/`LST2` requests.
+    pub async fn supports_stat_v2(&self) -> Result<bool> {
+        self.supports("stat_v2").await
+    }
+
+    /// Whether the Android binder bridge (`abb:`) service is available.
+    pub async fn supports_abb(&self) -> Result<bool> {
+        self.supports("abb").await
+    }
+
+    /// Whether the raw binder bridge (`abb_exec:`) service is available.
+    pub async fn supports_abb_exec(&self) -> Result<bool> {
+        self.supports("abb_exec").await
+    }
+}
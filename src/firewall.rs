@@ -0,0 +1,316 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Firewall rules from `iptables`, or the network policy restrictions they
+//! are generated from when the rules cannot be read.
+
+use crate::{Device, Result};
+
+const IPTABLES_TABLES: [&str; 4] = ["filter", "nat", "mangle", "raw"];
+
+/// Address family of a [`FirewallChain`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+/// A rule of a [`FirewallChain`], a `-A` line of `iptables -S`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FirewallRule {
+    /// Match and target as printed after the chain name, e.g. `-m owner
+    /// --uid-owner 10123 -j DROP`.
+    pub spec: String,
+    /// Target of `-j` or `-g`: a verdict such as `DROP` or another chain.
+    pub target: Option<String>,
+}
+
+/// A chain of an `iptables` table with its rules in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FirewallChain {
+    pub family: IpFamily,
+    /// `filter`, `nat`, `mangle` or `raw`.
+    pub table: String,
+    pub name: String,
+    /// Default verdict of built-in chains, `None` for user-defined chains.
+    pub policy: Option<String>,
+    pub rules: Vec<FirewallRule>,
+}
+
+/// A per-app policy of [`NetPolicyRestrictions`], e.g.
+/// `REJECT_METERED_BACKGROUND`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UidPolicy {
+    pub uid: u32,
+    pub policy: String,
+}
+
+/// A per-app rule of one of the firewall chains netd maintains for
+/// [`NetPolicyRestrictions`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UidFirewallRule {
+    /// Chain as named by `dumpsys`, e.g. `standby`, `dozable` or
+    /// `powersave`.
+    pub chain: String,
+    pub uid: u32,
+    /// 1 allows, 2 denies.
+    pub rule: u32,
+}
+
+/// Restrictions `dumpsys netpolicy` reports, which the firewall rules are
+/// generated from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NetPolicyRestrictions {
+    /// Data saver.
+    pub restrict_background: Option<bool>,
+    /// Battery saver.
+    pub restrict_power: Option<bool>,
+    /// Doze.
+    pub device_idle: Option<bool>,
+    pub uid_policies: Vec<UidPolicy>,
+    pub uid_firewall_rules: Vec<UidFirewallRule>,
+}
+
+/// Result of [`Device::firewall_rules`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FirewallRules {
+    /// The chains of all readable `iptables` and `ip6tables` tables.
+    Iptables(Vec<FirewallChain>),
+    /// Without root, the restrictions from `dumpsys netpolicy`.
+    NetPolicy(NetPolicyRestrictions),
+}
+
+impl Device {
+    /// Collects the firewall rules, to document which network access apps
+    /// had.
+    ///
+    /// Reading `iptables` needs root, `adbd` running as root or a
+    /// [`SuStrategy`](crate::SuStrategy).  Otherwise the per-app restrictions
+    /// and power saving modes of `dumpsys netpolicy` are returned.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn firewall_rules(&self) -> Result<FirewallRules> {
+        let tables: Vec<_> = [(IpFamily::V4, "iptables"), (IpFamily::V6, "ip6tables")]
+            .into_iter()
+            .flat_map(|(family, binary)| {
+                IPTABLES_TABLES
+                    .into_iter()
+                    .map(move |table| (family, table, format!("{binary} -t {table} -S")))
+            })
+            .collect();
+        let commands: Vec<_> = tables
+            .iter()
+            .map(|(_, _, command)| self.su.wrap(command).unwrap_or_else(|| command.clone()))
+            .collect();
+        let outputs = self
+            .run_batch(&commands.iter().map(String::as_str).collect::<Vec<_>>())
+            .await?;
+
+        // Tables the kernel lacks fail on their own, e.g. `nat` for IPv6 on
+        // old kernels; only when all fail are the rules unreadable.
+        if outputs.iter().any(|output| output.success()) {
+            let chains = tables
+                .iter()
+                .zip(&outputs)
+                .filter(|(_, output)| output.success())
+                .flat_map(|((family, table, _), output)| {
+                    parse_iptables(*family, table, &output.stdout_lossy())
+                })
+                .collect();
+            return Ok(FirewallRules::Iptables(chains));
+        }
+
+        let output = self.execute_host_shell_command("dumpsys netpolicy").await?;
+        Ok(FirewallRules::NetPolicy(parse_netpolicy(&output)))
+    }
+}
+
+/// Parses `iptables -S`: `-P CHAIN POLICY` and `-N CHAIN` declare chains,
+/// `-A CHAIN SPEC` appends rules.
+fn parse_iptables(family: IpFamily, table: &str, output: &str) -> Vec<FirewallChain> {
+    let mut chains: Vec<FirewallChain> = Vec::new();
+    for line in output.lines() {
+        let mut parts = line.trim().splitn(3, ' ');
+        let (Some(command), Some(name)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        let rest = parts.next().unwrap_or_default().trim();
+
+        let index = match chains.iter().position(|chain| chain.name == name) {
+            Some(index) => index,
+            None if matches!(command, "-P" | "-N" | "-A") => {
+                chains.push(FirewallChain {
+                    family,
+                    table: table.to_owned(),
+                    name: name.to_owned(),
+                    policy: None,
+                    rules: Vec::new(),
+                });
+                chains.len() - 1
+            }
+            None => continue,
+        };
+        match command {
+            "-P" => chains[index].policy = Some(rest.to_owned()),
+            "-A" => {
+                let tokens: Vec<_> = rest.split_whitespace().collect();
+                let target = tokens
+                    .iter()
+                    .position(|token| *token == "-j" || *token == "-g")
+                    .and_then(|i| tokens.get(i + 1))
+                    .map(|target| target.to_string());
+                chains[index].rules.push(FirewallRule {
+                    spec: rest.to_owned(),
+                    target,
+                });
+            }
+            _ => {}
+        }
+    }
+    chains
+}
+
+/// Parses the parts of `dumpsys netpolicy` that restrict network access:
+/// the `Restrict background:` style switches, `UID=10123 policy=4
+/// (REJECT_METERED_BACKGROUND)` lines and the `UID firewall standby rules:`
+/// style sections listing `UID=10123: 2`.
+fn parse_netpolicy(output: &str) -> NetPolicyRestrictions {
+    let mut restrictions = NetPolicyRestrictions::default();
+    let mut chain = None;
+    for line in output.lines().map(str::trim) {
+        let switch = |key: &str| line.strip_prefix(key).map(|value| value.trim() == "true");
+        if let Some(value) = switch("Restrict background:") {
+            restrictions.restrict_background = Some(value);
+        } else if let Some(value) = switch("Restrict power:") {
+            restrictions.restrict_power = Some(value);
+        } else if let Some(value) = switch("Device idle:") {
+            restrictions.device_idle = Some(value);
+        } else if let Some(name) = line
+            .strip_prefix("UID firewall ")
+            .and_then(|rest| rest.strip_suffix(" rules:"))
+        {
+            chain = Some(name.to_owned());
+        } else if let Some(rest) = line.strip_prefix("UID=") {
+            if let Some((uid, policy)) = rest.split_once(" policy=") {
+                let Ok(uid) = uid.parse() else {
+                    continue;
+                };
+                // `4 (REJECT_METERED_BACKGROUND)`; keep the name if given.
+                let policy = match policy.split_once('(') {
+                    Some((_, name)) => name.trim_end_matches(')'),
+                    None => policy,
+                };
+                restrictions.uid_policies.push(UidPolicy {
+                    uid,
+                    policy: policy.trim().to_owned(),
+                });
+            } else if let (Some(chain), Some((uid, rule))) = (&chain, rest.split_once(':')) {
+                let (Ok(uid), Ok(rule)) = (uid.parse(), rule.trim().parse()) else {
+                    continue;
+                };
+                restrictions.uid_firewall_rules.push(UidFirewallRule {
+                    chain: chain.clone(),
+                    uid,
+                    rule,
+                });
+            }
+        } else if line.ends_with(':') {
+            chain = None;
+        }
+    }
+    restrictions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_iptables() {
+        let chains = parse_iptables(
+            IpFamily::V4,
+            "filter",
+            "-P INPUT ACCEPT\n\
+             -P OUTPUT ACCEPT\n\
+             -N fw_standby\n\
+             -A OUTPUT -j fw_OUTPUT\n\
+             -A fw_standby -m owner --uid-owner 10123 -j DROP\n\
+             -A fw_standby -m comment --comment \"app standby\" -j RETURN\n",
+        );
+        assert_eq!(chains.len(), 3);
+        assert_eq!(chains[0].policy.as_deref(), Some("ACCEPT"));
+        assert_eq!(chains[1].rules[0].target.as_deref(), Some("fw_OUTPUT"));
+        assert_eq!(
+            chains[2],
+            FirewallChain {
+                family: IpFamily::V4,
+                table: "filter".to_owned(),
+                name: "fw_standby".to_owned(),
+                policy: None,
+                rules: vec![
+                    FirewallRule {
+                        spec: "-m owner --uid-owner 10123 -j DROP".to_owned(),
+                        target: Some("DROP".to_owned()),
+                    },
+                    FirewallRule {
+                        spec: "-m comment --comment \"app standby\" -j RETURN".to_owned(),
+                        target: Some("RETURN".to_owned()),
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn parses_netpolicy() {
+        let restrictions = parse_netpolicy(
+            "System ready: true\n\
+             Restrict background: true\n\
+             Restrict power: false\n\
+             Device idle: false\n\
+             Policy for UIDs:\n\
+             \x20 UID=10123 policy=4 (REJECT_METERED_BACKGROUND)\n\
+             \x20 UID=10124 policy=1\n\
+             UID firewall standby rules:\n\
+             \x20 UID=10089: 2\n\
+             UID firewall dozable rules:\n\
+             \x20 UID=1000: 1\n\
+             Status for all known UIDs:\n\
+             \x20 UID=10005 state=2 (fg svc)\n",
+        );
+        assert_eq!(restrictions.restrict_background, Some(true));
+        assert_eq!(restrictions.restrict_power, Some(false));
+        assert_eq!(restrictions.device_idle, Some(false));
+        assert_eq!(
+            restrictions.uid_policies,
+            [
+                UidPolicy {
+                    uid: 10123,
+                    policy: "REJECT_METERED_BACKGROUND".to_owned(),
+                },
+                UidPolicy {
+                    uid: 10124,
+                    policy: "1".to_owned(),
+                },
+            ]
+        );
+        assert_eq!(
+            restrictions.uid_firewall_rules,
+            [
+                UidFirewallRule {
+                    chain: "standby".to_owned(),
+                    uid: 10089,
+                    rule: 2,
+                },
+                UidFirewallRule {
+                    chain: "dozable".to_owned(),
+                    uid: 1000,
+                    rule: 1,
+                },
+            ]
+        );
+    }
+}
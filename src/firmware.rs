@@ -0,0 +1,93 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Build, patch level and firmware versions, which date the software a
+//! device runs and the vulnerabilities it may have.
+
+use crate::locale::setting;
+use crate::{Device, Result};
+
+const FIRMWARE_COMMANDS: [&str; 10] = [
+    "getprop ro.build.fingerprint",
+    "getprop ro.build.id",
+    "getprop ro.build.version.incremental",
+    "getprop ro.build.version.release",
+    "getprop ro.build.date.utc",
+    "getprop ro.build.version.security_patch",
+    "getprop ro.vendor.build.security_patch",
+    "getprop gsm.version.baseband",
+    "getprop ro.bootloader",
+    "cat /proc/version",
+];
+
+/// Result of [`Device::firmware_info`]; fields are `None` when the build
+/// does not set them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FirmwareInfo {
+    pub fingerprint: Option<String>,
+    /// Build id, e.g. `TQ3A.230805.001`.
+    pub build_id: Option<String>,
+    pub incremental: Option<String>,
+    /// Android version, e.g. `13`.
+    pub android_version: Option<String>,
+    /// Build time in seconds since the Unix epoch.
+    pub build_time: Option<u64>,
+    /// Android security patch level, `YYYY-MM-DD`.
+    pub security_patch: Option<String>,
+    /// Security patch level of the vendor image, Android 8 and later.
+    pub vendor_security_patch: Option<String>,
+    /// Modem firmware versions, one per modem.
+    pub baseband: Vec<String>,
+    pub bootloader: Option<String>,
+    /// Kernel release, e.g. `5.10.157-android13-4-00001-g5c7ff5dc7aac`.
+    pub kernel_release: Option<String>,
+    /// All of `/proc/version`, with the compiler and build date.
+    pub kernel_version: Option<String>,
+}
+
+impl Device {
+    /// Collects the build, security patch levels and baseband, bootloader
+    /// and kernel versions from `getprop` and `/proc/version`, in one shell
+    /// round trip.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn firmware_info(&self) -> Result<FirmwareInfo> {
+        let outputs = self.run_batch(&FIRMWARE_COMMANDS).await?;
+        let value = |i: usize| setting(&outputs[i]);
+
+        let mut baseband: Vec<String> = Vec::new();
+        for version in value(7).iter().flat_map(|versions| versions.split(',')) {
+            let version = version.trim();
+            if !version.is_empty() && !baseband.iter().any(|known| known == version) {
+                baseband.push(version.to_owned());
+            }
+        }
+
+        // `Linux version 5.10.157-android13-4 (builder@host) (clang ...) #1 ...`
+        let kernel_version = value(9);
+        let kernel_release = kernel_version.as_deref().and_then(|version| {
+            version
+                .strip_prefix("Linux version ")?
+                .split_whitespace()
+                .next()
+                .map(str::to_owned)
+        });
+
+        Ok(FirmwareInfo {
+            fingerprint: value(0),
+            build_id: value(1),
+            incremental: value(2),
+            android_version: value(3),
+            build_time: value(4).and_then(|time| time.parse().ok()),
+            security_patch: value(5),
+            vendor_security_patch: value(6),
+            baseband,
+            bootloader: value(8).filter(|version| version != "unknown"),
+            kernel_release,
+            kernel_version,
+        })
+    }
+}
@@ -0,0 +1,62 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::time::Instant;
+use tokio::time::{timeout, Duration};
+
+use crate::{Device, DeviceError, DeviceInfo, DeviceState, Result};
+
+/// Deadline used by [`Device::is_online`].
+pub const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+const PING_REPLY: &str = "forensic-adb-ping";
+
+impl Device {
+    /// Checks that the device is listed in state `device` and answers a
+    /// trivial shell command, all within `deadline`.
+    ///
+    /// Returns the latency of the shell round trip.  A device that is listed
+    /// in another state yields [`DeviceError::NotOnline`], one that is not
+    /// listed at all [`DeviceError::UnknownDevice`].
+    pub async fn ping(&self, deadline: Duration) -> Result<Duration> {
+        timeout(deadline, self.ping_inner())
+            .await
+            .map_err(|_| DeviceError::CommandTimeout)?
+    }
+
+    async fn ping_inner(&self) -> Result<Duration> {
+        let info = self
+            .host
+            .devices::<Vec<DeviceInfo>>()
+            .await?
+            .into_iter()
+            .find(|info| info.serial == self.serial)
+            .ok_or_else(|| DeviceError::UnknownDevice(self.serial.clone()))?;
+        if info.state != DeviceState::Device {
+            return Err(DeviceError::NotOnline(self.serial.clone(), info.state));
+        }
+
+        let start = Instant::now();
+        // Not subject to the su strategy, so a hanging su prompt does not
+        // make a healthy device look stuck.
+        let reply = self
+            .execute_host_command_to_string(&format!("shell:echo {PING_REPLY}"), true, false)
+            .await?;
+        let latency = start.elapsed();
+
+        if reply.trim() != PING_REPLY {
+            return Err(DeviceError::Adb(format!(
+                "unexpected ping reply: {:?}",
+                reply.trim()
+            )));
+        }
+
+        Ok(latency)
+    }
+
+    /// Whether [`Device::ping`] succeeds within [`HEALTH_CHECK_TIMEOUT`].
+    pub async fn is_online(&self) -> bool {
+        self.ping(HEALTH_CHECK_TIMEOUT).await.is_ok()
+    }
+}
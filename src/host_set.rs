@@ -0,0 +1,131 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Several adb servers used together, e.g. the local one and those of lab
+//! machines reached like `adb -H`.
+//!
+//! ```no_run
+//! # async fn example() -> forensic_adb::Result<()> {
+//! use forensic_adb::{Host, HostSet};
+//!
+//! let hosts = HostSet::new().add("local", Host::default()).add(
+//!     "lab-1",
+//!     Host {
+//!         host: Some("lab-1.example".to_owned()),
+//!         ..Host::default()
+//!     },
+//! );
+//! for device in hosts.devices().await {
+//!     println!("{} on {}", device.info.serial, device.host);
+//! }
+//! let device = hosts.device("R58M123ABC").await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use futures_util::future::join_all;
+#[cfg(not(feature = "tracing"))]
+use log::warn;
+#[cfg(feature = "tracing")]
+use tracing::warn;
+
+use crate::{Device, DeviceError, DeviceInfo, DeviceState, Host, Result};
+
+/// A device listed by [`HostSet::devices`], tagged with its server.
+#[derive(Debug, Clone)]
+pub struct HostedDevice {
+    /// Name the server was added with.
+    pub host: String,
+    pub info: DeviceInfo,
+}
+
+/// Named adb servers, queried together.
+#[derive(Debug, Clone, Default)]
+pub struct HostSet {
+    hosts: Vec<(String, Host)>,
+}
+
+impl HostSet {
+    pub fn new() -> HostSet {
+        HostSet::default()
+    }
+
+    /// Adds `host` as `name`, replacing a host added with the same name.
+    pub fn add(mut self, name: &str, host: Host) -> HostSet {
+        self.hosts.retain(|(existing, _)| existing != name);
+        self.hosts.push((name.to_owned(), host));
+        self
+    }
+
+    /// Returns the host added as `name`.
+    pub fn host(&self, name: &str) -> Option<&Host> {
+        self.hosts
+            .iter()
+            .find(|(existing, _)| existing == name)
+            .map(|(_, host)| host)
+    }
+
+    /// Returns the names of the hosts in the order they were added.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.hosts.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Lists the devices of all hosts, queried concurrently.
+    ///
+    /// Hosts that cannot be reached are logged and skipped, so one lab
+    /// machine being down does not hide the devices of the others.
+    pub async fn devices(&self) -> Vec<HostedDevice> {
+        let listings = join_all(
+            self.hosts
+                .iter()
+                .map(|(_, host)| host.devices::<Vec<DeviceInfo>>()),
+        )
+        .await;
+
+        let mut devices = Vec::new();
+        for ((name, _), listing) in self.hosts.iter().zip(listings) {
+            match listing {
+                Ok(infos) => devices.extend(infos.into_iter().map(|info| HostedDevice {
+                    host: name.clone(),
+                    info,
+                })),
+                Err(err) => warn!("Skipping adb server {}: {}", name, err),
+            }
+        }
+        devices
+    }
+
+    /// Returns the online device with `serial` from whichever host has it.
+    ///
+    /// Fails with [`DeviceError::MultipleDevices`] if several hosts list the
+    /// serial, e.g. an emulator port used on two machines; pick the host
+    /// with [`HostSet::device_on`] then.
+    pub async fn device(&self, serial: &str) -> Result<Device> {
+        let mut matches = self.devices().await.into_iter().filter(|device| {
+            device.info.serial == serial && device.info.state == DeviceState::Device
+        });
+
+        let Some(found) = matches.next() else {
+            return Err(DeviceError::UnknownDevice(serial.to_owned()));
+        };
+        if matches.next().is_some() {
+            return Err(DeviceError::MultipleDevices);
+        }
+
+        let host = self
+            .host(&found.host)
+            .cloned()
+            .ok_or_else(|| DeviceError::UnknownDevice(serial.to_owned()))?;
+        Device::new(host, found.info.serial, found.info.info).await
+    }
+
+    /// Returns the device with `serial` on the host added as `name`.
+    pub async fn device_on(&self, name: &str, serial: &str) -> Result<Device> {
+        let host = self
+            .host(name)
+            .cloned()
+            .ok_or_else(|| DeviceError::Adb(format!("Unknown adb server '{name}'")))?;
+        host.device_or_default(Some(&serial)).await
+    }
+}
@@ -0,0 +1,198 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Package installer sessions, for split apks and installs streamed
+//! straight into the package manager.
+
+#[cfg(not(feature = "tracing"))]
+use log::debug;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+#[cfg(feature = "tracing")]
+use tracing::debug;
+
+use crate::buffer_pool::PooledBuffer;
+use crate::{Device, DeviceError, FileTransferProgress, InstallOptions, ProgressSink, Result};
+
+/// Entry point for install sessions, see [`Device::install_session`].
+#[derive(Debug, Clone, Copy)]
+pub struct PackageInstaller<'a> {
+    device: &'a Device,
+}
+
+/// An open install session.
+///
+/// Nothing is installed until [`InstallSession::commit`].  A session that is
+/// neither committed nor abandoned stays on the device until it expires, so
+/// call [`InstallSession::abandon`] when giving up.
+#[derive(Debug)]
+pub struct InstallSession<'a> {
+    device: &'a Device,
+    id: u32,
+}
+
+impl Device {
+    /// Returns the installer for `pm install-create` sessions.
+    ///
+    /// Unlike [`Device::install_package`], apks are streamed into the
+    /// session without staging them on the device, and an app can be
+    /// installed from several split apks at once.
+    pub fn install_session(&self) -> PackageInstaller<'_> {
+        PackageInstaller { device: self }
+    }
+}
+
+impl<'a> PackageInstaller<'a> {
+    /// Creates a session installing with `options`.
+    pub async fn create(&self, options: &InstallOptions) -> Result<InstallSession<'a>> {
+        self.device.check_writable("install packages")?;
+        let capabilities = self.device.capabilities().await?;
+        let command = format!(
+            "{} install-create{}",
+            capabilities.package_manager(),
+            options.args(&capabilities, self.device.user)?
+        );
+        let output = self.device.execute_host_shell_command(&command).await?;
+        let id = parse_session_id(&output)
+            .ok_or_else(|| DeviceError::PackageManagerError(output.clone()))?;
+        debug!("Created install session {}", id);
+
+        Ok(InstallSession {
+            device: self.device,
+            id,
+        })
+    }
+}
+
+impl<'a> InstallSession<'a> {
+    /// Returns the session id assigned by the package manager.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Streams `size` bytes of `reader` into the session as the apk `name`,
+    /// e.g. `base.apk` or `split_config.arm64_v8a.apk`.
+    pub async fn write<R: AsyncRead + Unpin>(
+        &self,
+        name: &str,
+        reader: &mut R,
+        size: u64,
+    ) -> Result<()> {
+        self.write_with_progress(name, reader, size, |_| {}).await
+    }
+
+    /// Like [`InstallSession::write`], reporting the progress of this apk.
+    pub async fn write_with_progress<R: AsyncRead + Unpin>(
+        &self,
+        name: &str,
+        reader: &mut R,
+        size: u64,
+        progress: impl ProgressSink<FileTransferProgress>,
+    ) -> Result<()> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+        {
+            return Err(DeviceError::Adb(format!("invalid split name '{name}'")));
+        }
+
+        let capabilities = self.device.capabilities().await?;
+        let command = format!(
+            "{} install-write -S {size} {} {name} -",
+            capabilities.package_manager(),
+            self.id
+        );
+        let command = self.device.su.wrap(&command).unwrap_or(command);
+        let mut stream = self
+            .device
+            .open_service(&format!("exec:{command} 2>&1"))
+            .await?;
+
+        let interval = self.device.progress_granularity.interval(Some(size));
+        let mut reader = reader.take(size);
+        let mut buf = PooledBuffer::filled(64 * 1024);
+        let mut transferred = 0;
+        let mut last_progress = 0;
+        progress.report(FileTransferProgress {
+            total_bytes: Some(size),
+            transferred_bytes: 0,
+            sparse_bytes: 0,
+        });
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            stream.write_all(&buf[..n]).await?;
+            transferred += n as u64;
+            if transferred - last_progress >= interval || transferred == size {
+                progress.report(FileTransferProgress {
+                    total_bytes: Some(size),
+                    transferred_bytes: transferred,
+                    sparse_bytes: 0,
+                });
+                last_progress = transferred;
+            }
+        }
+        stream.flush().await?;
+        if transferred < size {
+            return Err(DeviceError::Adb(format!(
+                "{name} ended after {transferred} of {size} bytes"
+            )));
+        }
+
+        // `pm` stops reading after `size` bytes and reports the result.
+        let mut output = String::new();
+        stream.read_to_string(&mut output).await?;
+        if !output.starts_with("Success") {
+            return Err(DeviceError::PackageManagerError(output));
+        }
+
+        Ok(())
+    }
+
+    /// Installs the apks written to the session.
+    pub async fn commit(self) -> Result<()> {
+        self.finish("install-commit").await
+    }
+
+    /// Discards the session and the apks written to it.
+    pub async fn abandon(self) -> Result<()> {
+        self.finish("install-abandon").await
+    }
+
+    async fn finish(self, subcommand: &str) -> Result<()> {
+        let pm = self.device.capabilities().await?.package_manager();
+        let output = self
+            .device
+            .execute_host_shell_command(&format!("{pm} {subcommand} {}", self.id))
+            .await?;
+        if !output.starts_with("Success") {
+            return Err(DeviceError::PackageManagerError(output));
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses `Success: created install session [1234]`.
+fn parse_session_id(output: &str) -> Option<u32> {
+    let (_, rest) = output.split_once('[')?;
+    let (id, _) = rest.split_once(']')?;
+    id.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_id() {
+        assert_eq!(
+            parse_session_id("Success: created install session [1054223127]\n"),
+            Some(1054223127)
+        );
+        assert_eq!(parse_session_id("Error: failed to create session"), None);
+    }
+}
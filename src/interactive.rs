@@ -0,0 +1,232 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Interactive shell sessions over the shell v2 protocol.
+//!
+//! ```no_run
+//! # async fn example(device: forensic_adb::Device) -> forensic_adb::Result<()> {
+//! use forensic_adb::interactive::ShellEvent;
+//! use futures::StreamExt;
+//! use tokio::io::AsyncWriteExt;
+//!
+//! let mut shell = device.interactive_shell().await?;
+//! shell.stdin.resize(24, 80).await?;
+//! shell.stdin.write_all(b"getprop ro.product.model\n").await?;
+//! shell.stdin.write_all(b"exit\n").await?;
+//!
+//! while let Some(event) = shell.events.next().await {
+//!     match event? {
+//!         ShellEvent::Stdout(data) => print!("{}", String::from_utf8_lossy(&data)),
+//!         ShellEvent::Stderr(data) => eprint!("{}", String::from_utf8_lossy(&data)),
+//!         ShellEvent::Exit(code) => println!("exited with {code}"),
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use bytes::Bytes;
+use futures_core::stream::Stream;
+#[cfg(not(feature = "tracing"))]
+use log::trace;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+#[cfg(feature = "tracing")]
+use tracing::trace;
+
+use crate::adb::ShellPacket;
+use crate::transport::BoxedTransport;
+use crate::{command_timeout, encode_message, read_response, Device, DeviceError, Result};
+
+/// Largest stdin payload sent in a single packet.
+const MAX_STDIN_PACKET: usize = 16 * 1024;
+
+/// Terminal type announced to the device when none is given.
+const DEFAULT_TERM: &str = "xterm-256color";
+
+/// Output of an interactive shell.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShellEvent {
+    Stdout(Bytes),
+    /// Rarely seen, as the PTY merges stderr into stdout.
+    Stderr(Bytes),
+    /// The shell exited; this is the last event.
+    Exit(i32),
+}
+
+/// A running interactive shell, see [`Device::interactive_shell`].
+///
+/// Both halves can be moved to separate tasks.
+pub struct InteractiveShell {
+    pub stdin: ShellStdin,
+    pub events: ShellEvents,
+}
+
+/// Stream of [`ShellEvent`]s produced by an interactive shell.
+pub type ShellEvents = Pin<Box<dyn Stream<Item = Result<ShellEvent>> + Send>>;
+
+/// Stdin of an interactive shell.
+///
+/// Bytes written through [`AsyncWrite`] are forwarded to the shell as typed
+/// on a terminal.  Call [`flush`](AsyncWriteExt::flush) to make sure they
+/// have been sent.
+pub struct ShellStdin {
+    inner: WriteHalf<BoxedTransport>,
+    /// Framed packet not yet fully written to `inner`.
+    pending: Vec<u8>,
+    written: usize,
+}
+
+impl ShellStdin {
+    fn new(inner: WriteHalf<BoxedTransport>) -> ShellStdin {
+        ShellStdin {
+            inner,
+            pending: Vec::new(),
+            written: 0,
+        }
+    }
+
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.pending.len() {
+            let n =
+                ready!(Pin::new(&mut self.inner).poll_write(cx, &self.pending[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.pending.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    async fn send_packet(&mut self, packet: ShellPacket, payload: &[u8]) -> Result<()> {
+        self.flush().await?;
+        self.inner
+            .write_all(&encode_packet(packet, payload))
+            .await?;
+        self.inner.flush().await?;
+        Ok(())
+    }
+
+    /// Tells the shell that the terminal has `rows` lines of `cols`
+    /// characters.
+    pub async fn resize(&mut self, rows: u16, cols: u16) -> Result<()> {
+        let size = format!("{rows}x{cols},0x0\0");
+        self.send_packet(ShellPacket::WindowSizeChange, size.as_bytes())
+            .await
+    }
+
+    /// Sends Ctrl-C, which interrupts the foreground process of the shell.
+    pub async fn interrupt(&mut self) -> Result<()> {
+        self.send_packet(ShellPacket::Stdin, b"\x03").await
+    }
+
+    /// Signals end of input, like Ctrl-D on an empty line.
+    pub async fn close(&mut self) -> Result<()> {
+        self.send_packet(ShellPacket::CloseStdin, &[]).await
+    }
+}
+
+impl AsyncWrite for ShellStdin {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+
+        let len = buf.len().min(MAX_STDIN_PACKET);
+        this.pending = encode_packet(ShellPacket::Stdin, &buf[..len]);
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+fn encode_packet(packet: ShellPacket, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(5 + payload.len());
+    bytes.push(packet.id());
+    bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+fn read_events(mut stream: ReadHalf<BoxedTransport>) -> ShellEvents {
+    Box::pin(async_stream::try_stream! {
+        loop {
+            let mut header = [0u8; 5];
+            stream.read_exact(&mut header).await?;
+            let length = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+            let mut payload = vec![0u8; length];
+            stream.read_exact(&mut payload).await?;
+
+            match ShellPacket::from_id(header[0]) {
+                Some(ShellPacket::Stdout) => yield ShellEvent::Stdout(payload.into()),
+                Some(ShellPacket::Stderr) => yield ShellEvent::Stderr(payload.into()),
+                Some(ShellPacket::Exit) => {
+                    yield ShellEvent::Exit(payload.first().copied().unwrap_or_default().into());
+                    break;
+                }
+                _ => trace!("ignoring shell v2 packet {}", header[0]),
+            }
+        }
+    })
+}
+
+impl Device {
+    /// Starts an interactive login shell in a PTY.
+    pub async fn interactive_shell(&self) -> Result<InteractiveShell> {
+        self.interactive_shell_with("", None).await
+    }
+
+    /// Starts `command` in a PTY, announcing `term` (default
+    /// `xterm-256color`) as the terminal type.  An empty command starts a
+    /// login shell.
+    pub async fn interactive_shell_with(
+        &self,
+        command: &str,
+        term: Option<&str>,
+    ) -> Result<InteractiveShell> {
+        if !self.supports_shell_v2().await? {
+            return Err(DeviceError::MissingFeature("shell_v2".to_owned()));
+        }
+
+        let mut stream = self.connect_transport().await?;
+
+        let request = format!(
+            "shell,v2,TERM={},pty:{command}",
+            term.unwrap_or(DEFAULT_TERM)
+        );
+        trace!("interactive_shell: >> {:?}", &request);
+        self.audit_command(&request)?;
+        stream
+            .write_all(encode_message(&request)?.as_bytes())
+            .await?;
+        command_timeout(
+            self.timeouts.command,
+            read_response(&mut stream, false, false),
+        )
+        .await?;
+
+        let (reader, writer) = tokio::io::split(stream);
+        Ok(InteractiveShell {
+            stdin: ShellStdin::new(writer),
+            events: read_events(reader),
+        })
+    }
+}
@@ -0,0 +1,186 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Lock screen state, and waking and dismissing an insecure lock screen.
+
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::{Device, Result};
+
+/// How long [`Device::wake_and_unlock_if_insecure`] waits for the lock
+/// screen to go away.
+const UNLOCK_TIMEOUT: Duration = Duration::from_secs(2);
+const UNLOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What the lock screen asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyguardSecurity {
+    /// The lock screen is disabled.
+    None,
+    /// Swipe to unlock.
+    Swipe,
+    /// PIN, pattern or password.
+    Secure,
+}
+
+/// Result of [`Device::keyguard_state`]; fields are `None` when the
+/// release does not report them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyguardState {
+    /// The lock screen is showing.
+    pub locked: Option<bool>,
+    pub security: Option<KeyguardSecurity>,
+    pub screen_on: Option<bool>,
+    /// Smart Lock (a trust agent) keeps the device unlocked.
+    pub trusted: Option<bool>,
+}
+
+impl Device {
+    /// Reports whether the lock screen is showing, what unlocking it takes
+    /// and whether the screen is on, from `dumpsys window policy` and
+    /// `dumpsys deviceidle` in one shell round trip.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn keyguard_state(&self) -> Result<KeyguardState> {
+        let outputs = self
+            .run_batch(&["dumpsys window policy", "dumpsys deviceidle"])
+            .await?;
+        Ok(parse_keyguard_state(
+            &outputs[0].stdout_lossy(),
+            &outputs[1].stdout_lossy(),
+        ))
+    }
+
+    /// Wakes the screen and dismisses the lock screen with key events when
+    /// unlocking needs no credential, returning whether the device ended up
+    /// unlocked.
+    ///
+    /// Secure lock screens are left alone, so that no failed attempt counts
+    /// towards a lockout or wipe.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn wake_and_unlock_if_insecure(&self) -> Result<bool> {
+        let state = self.keyguard_state().await?;
+        if state.locked == Some(false) && state.screen_on != Some(false) {
+            return Ok(true);
+        }
+        if state.security != Some(KeyguardSecurity::Swipe) && state.locked != Some(false) {
+            return Ok(false);
+        }
+        self.check_writable("send key events")?;
+
+        if state.screen_on != Some(true) {
+            self.execute_host_shell_command("input keyevent KEYCODE_WAKEUP")
+                .await?;
+        }
+        if state.locked != Some(false) {
+            self.execute_host_shell_command("input keyevent KEYCODE_MENU")
+                .await?;
+        }
+
+        let deadline = Instant::now() + UNLOCK_TIMEOUT;
+        loop {
+            let state = self.keyguard_state().await?;
+            if state.locked == Some(false) {
+                return Ok(true);
+            }
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+            sleep(UNLOCK_POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// Parses the `key=value` tokens of the keyguard delegate in `dumpsys window
+/// policy` (`showing=true`, `secure=true`, `deviceHasKeyguard=true`,
+/// `screenState=SCREEN_STATE_ON`, `mTrusted=false`; `mShowingLockscreen=`
+/// and `mScreenOnFully=` before Android 9) and `mScreenOn=`/`mScreenLocked=`
+/// of `dumpsys deviceidle`.
+fn parse_keyguard_state(policy: &str, deviceidle: &str) -> KeyguardState {
+    let value = |output: &str, key: &str| {
+        output.split_whitespace().find_map(|token| {
+            token
+                .strip_prefix(key)
+                .and_then(|rest| rest.strip_prefix('='))
+                .map(str::to_owned)
+        })
+    };
+    let flag = |output: &str, keys: &[&str]| {
+        keys.iter()
+            .find_map(|key| value(output, key))
+            .and_then(|value| match value.as_str() {
+                "true" => Some(true),
+                "false" => Some(false),
+                _ => None,
+            })
+    };
+
+    let locked = flag(policy, &["showing", "mIsShowing", "mShowingLockscreen"])
+        .or_else(|| flag(deviceidle, &["mScreenLocked"]));
+    let security = match (
+        flag(policy, &["deviceHasKeyguard"]),
+        flag(policy, &["enabled"]),
+        flag(policy, &["secure", "mIsSecure"]),
+    ) {
+        (Some(false), _, _) | (_, Some(false), Some(false)) => Some(KeyguardSecurity::None),
+        (_, _, Some(true)) => Some(KeyguardSecurity::Secure),
+        (_, _, Some(false)) => Some(KeyguardSecurity::Swipe),
+        _ => None,
+    };
+    let screen_on = flag(deviceidle, &["mScreenOn"])
+        .or_else(|| value(policy, "screenState").map(|state| state == "SCREEN_STATE_ON"))
+        .or_else(|| flag(policy, &["mScreenOnFully"]));
+
+    KeyguardState {
+        locked,
+        security,
+        screen_on,
+        trusted: flag(policy, &["mTrusted"]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_state() {
+        let policy = "WINDOW MANAGER POLICY STATE (dumpsys window policy)\n\
+                      \x20   KeyguardServiceDelegate\n\
+                      \x20     showing=true\n\
+                      \x20     occluded=false\n\
+                      \x20     secure=true\n\
+                      \x20     deviceHasKeyguard=true\n\
+                      \x20     enabled=true\n\
+                      \x20     screenState=SCREEN_STATE_OFF\n\
+                      \x20     KeyguardStateMonitor\n\
+                      \x20       mIsShowing=true\n\
+                      \x20       mTrusted=false\n";
+        assert_eq!(
+            parse_keyguard_state(policy, "  mScreenOn=false\n  mScreenLocked=true\n"),
+            KeyguardState {
+                locked: Some(true),
+                security: Some(KeyguardSecurity::Secure),
+                screen_on: Some(false),
+                trusted: Some(false),
+            }
+        );
+        // Without `deviceidle`, the screen state of the delegate.
+        assert_eq!(parse_keyguard_state(policy, "").screen_on, Some(false));
+
+        let legacy = "    mShowingLockscreen=false mShowingDream=false\n\
+                      \x20   mScreenOnEarly=true mScreenOnFully=true\n\
+                      \x20   KeyguardServiceDelegate\n\
+                      \x20     deviceHasKeyguard=false\n";
+        let state = parse_keyguard_state(legacy, "");
+        assert_eq!(state.locked, Some(false));
+        assert_eq!(state.security, Some(KeyguardSecurity::None));
+        assert_eq!(state.screen_on, Some(true));
+    }
+}
@@ -2,38 +2,214 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+/// Records a value on a field of the current `tracing` span. Expands to
+/// nothing unless the `tracing` feature is enabled.
+macro_rules! record_span {
+    ($field:literal, $value:expr) => {
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record($field, $value);
+    };
+}
+
+pub mod acquisition;
+pub mod activity;
 pub mod adb;
+pub mod adb_device;
+pub mod apk;
+pub mod appops;
+pub mod archive;
+pub mod audit;
+pub mod batch;
+pub mod biometric;
+pub mod bootloader;
+mod buffer_pool;
+pub mod builder;
+pub mod camera;
+pub mod capabilities;
+pub mod cellular;
+pub mod clock;
+pub mod copy;
+pub mod device_path;
+pub mod device_policy;
+pub mod direct;
+pub mod dns;
+pub mod dry_run;
+pub mod encryption;
+pub mod export;
+pub mod features;
+pub mod firewall;
+pub mod firmware;
+pub mod health;
+pub mod host_set;
+pub mod install_session;
+pub mod interactive;
+pub mod keyguard;
+pub mod keys;
+pub mod locale;
+pub mod metadata;
+pub mod network;
+pub mod open_files;
+pub mod package;
+pub mod permissions;
+pub mod power_whitelist;
+pub mod process;
+pub mod profile;
+pub mod progress;
+pub mod record;
+pub mod remove;
+pub mod rename;
+pub mod resilient;
+pub mod resume;
+pub mod retry;
+pub mod screencap;
 pub mod shell;
+pub mod shell_v2;
+mod sparse;
+pub mod special_access;
+pub mod storage;
+pub mod tcpdump;
+pub mod temp;
+pub mod transfer;
+pub mod transport;
+pub mod ui;
+pub mod usb;
+pub mod verified_boot;
+pub mod volumes;
+pub mod vpn;
+pub mod wifi;
+pub mod workspace;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod fake;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
 #[cfg(test)]
 pub mod test;
 
+use bytes::{Buf, Bytes, BytesMut};
 use futures_core::stream::Stream;
+use futures_util::{StreamExt, TryStreamExt};
+#[cfg(not(feature = "tracing"))]
 use log::{debug, trace, warn};
 use once_cell::sync::Lazy;
 use regex::Regex;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::convert::TryFrom;
+use std::future::Future;
 use std::io;
 use std::iter::FromIterator;
 use std::num::{ParseIntError, TryFromIntError};
 use std::path::{Component, Path};
 use std::str::Utf8Error;
-use std::time::{Duration as StdDuration, SystemTime};
+use std::sync::Arc;
+use std::time::{Duration as StdDuration, Instant, SystemTime};
 use thiserror::Error;
 use tokio::fs::File;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
-use tokio::net::TcpStream;
 use tokio::process::Command;
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::OnceCell;
 use tokio::time::{timeout, Duration};
+#[cfg(feature = "tracing")]
+use tracing::{debug, trace, warn};
 pub use unix_path::{Path as UnixPath, PathBuf as UnixPathBuf};
-use uuid::Uuid;
 use walkdir::WalkDir;
 
+pub use crate::activity::{
+    ActivityTask, LaunchFailure, LaunchResult, LaunchStatus, RunningActivity,
+};
 use crate::adb::{DeviceSerial, SyncCommand};
+pub use crate::adb_device::AdbDevice;
+pub use crate::apk::PulledApk;
+pub use crate::appops::{AppOp, AppOpMode};
+pub use crate::audit::AuditLog;
+use crate::audit::TransferDirection;
+pub use crate::biometric::{BiometricEnrollment, BiometricStatus};
+pub use crate::bootloader::BootloaderStatus;
+use crate::buffer_pool::PooledBuffer;
+pub use crate::builder::DeviceBuilder;
+pub use crate::camera::{
+    Camera, CameraFacing, CameraResolution, CameraUsageAction, CameraUsageEvent,
+};
+pub use crate::capabilities::Capabilities;
+pub use crate::cellular::{CellNetworkType, ServingCell};
+pub use crate::clock::ClockSkew;
+use crate::copy::file_error;
+pub use crate::device_path::DevicePath;
+pub use crate::device_policy::{DeviceAdmin, DevicePolicy, PolicyOwner};
+pub use crate::direct::{DeviceBanner, DeviceDirect};
+pub use crate::dns::{DnsConfig, NetworkDns, PrivateDnsMode};
+pub use crate::dry_run::{InstallPlan, RemovalReport};
+pub use crate::encryption::{EncryptionState, EncryptionType, UserRunState, UserStorageState};
+pub use crate::export::AppExport;
+pub use crate::firewall::{
+    FirewallChain, FirewallRule, FirewallRules, IpFamily, NetPolicyRestrictions, UidFirewallRule,
+    UidPolicy,
+};
+pub use crate::firmware::FirmwareInfo;
+pub use crate::host_set::{HostSet, HostedDevice};
+pub use crate::install_session::{InstallSession, PackageInstaller};
+pub use crate::keyguard::{KeyguardSecurity, KeyguardState};
+pub use crate::keys::{AdbKey, AdbKeySet};
+pub use crate::locale::{ClockFormat, LocaleInfo};
+pub use crate::metadata::ExtendedMetadata;
+pub use crate::network::{
+    InterfaceAddress, InterfaceState, Neighbor, NeighborState, NetworkInterface, Route,
+};
+pub use crate::open_files::{OpenFd, OpenFile, OpenFileFilter, OpenFileType};
+pub use crate::package::{
+    InstallLocation, InstallOptions, PackageListEntry, PackageListOptions, UninstallFailure,
+    UninstallOptions,
+};
+pub use crate::permissions::PermissionGrant;
+pub use crate::power_whitelist::{PowerWhitelist, PowerWhitelistEntry};
+pub use crate::process::{
+    FdTarget, LruPosition, MemoryMapping, OomInfo, OomPriority, ProcessFd, Signal,
+};
+pub use crate::profile::ManagedProfile;
+use crate::progress::{ConcurrentDirectoryProgress, DirectoryFileSink};
+pub use crate::progress::{ProgressGranularity, ProgressSink};
+pub use crate::resilient::{ConnectionEvent, ResilientDevice};
+use crate::retry::with_retry;
+pub use crate::retry::RetryPolicy;
+pub use crate::screencap::{Frame, ScreenQuality};
+#[cfg(feature = "image")]
+pub use crate::screencap::{Screenshot, ScreenshotFormat};
+pub use crate::shell_v2::ShellOutput;
+pub use crate::special_access::{SpecialAccessComponent, SpecialAccessServices};
+pub use crate::storage::{AndroidStorage, AndroidStorageInput};
+pub use crate::tcpdump::{CaptureStats, TcpdumpOptions};
+pub use crate::temp::DeviceTempPath;
+use crate::transfer::{
+    create_host_symlink, set_directory_mtime, Hasher, SyncConnections, TransferState,
+};
+pub use crate::transfer::{
+    HashAlgorithm, PulledSymlink, SymlinkMode, TransferOptions, TransferReport, TransferredFile,
+};
+use crate::transport::IdleTimeout;
+#[cfg(unix)]
+pub use crate::transport::UnixConnector;
+pub use crate::transport::{BoxedTransport, Connector, ServerAddress, TcpConnector, Transport};
+pub use crate::ui::{UiBounds, UiHierarchy, UiNode, UiSelector};
+pub use crate::usb::UsbAdbInterface;
+pub use crate::verified_boot::{VerifiedBoot, VerifiedBootState, VerityMode};
+pub use crate::volumes::{StorageVolume, VolumeState, VolumeType};
+pub use crate::vpn::{ActiveVpn, HttpProxy, VpnStatus};
+pub use crate::wifi::{WifiNetwork, WifiStatus};
+pub use crate::workspace::Workspace;
 
 const ADB_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const ADB_SYNC_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Read buffer of sync pulls, adb's maxdata, so that one read takes in
+/// several 64K DATA chunks.
+const SYNC_PULL_BUFFER_SIZE: usize = 256 * 1024;
+/// Mode of the directories `push` creates for its destination on Android 9
+/// and earlier.
+const PUSH_DIR_MODE: u32 = 0o755;
+/// Default for [`Device::pull_concurrency`].
+pub const DEFAULT_PULL_CONCURRENCY: usize = 4;
+/// Default for [`Device::pull_to_vec_limit`], 16 MiB.
+pub const DEFAULT_PULL_TO_VEC_LIMIT: u64 = 16 * 1024 * 1024;
 
 pub type Result<T> = std::result::Result<T, DeviceError>;
 
@@ -84,6 +260,32 @@ pub enum DeviceError {
     PackageManagerError(String),
     #[error("Timed out while opening ADB connection")]
     ConnectTimeout,
+    #[error("Timed out waiting for ADB response")]
+    CommandTimeout,
+    #[error("Package '{0}' is not debuggable: {1}")]
+    NotDebuggable(String, String),
+    #[error("Invalid adb server address '{0}'")]
+    InvalidServerAddress(String),
+    #[error("Device does not support the '{0}' feature")]
+    MissingFeature(String),
+    #[error("Invalid device path '{0}': {1}")]
+    InvalidPath(String, String),
+    #[error("Android device '{0}' is not online: {1:?}")]
+    NotOnline(String, DeviceState),
+    #[error("Checksum mismatch for '{0}': host {1}, device {2}")]
+    ChecksumMismatch(String, String, String),
+    #[error("Invalid glob pattern '{0}': {1}")]
+    InvalidPattern(String, String),
+    #[error("Uninstalling '{0}' failed: {1}")]
+    UninstallFailed(String, UninstallFailure),
+    #[error("Launching '{0}' failed: {1}")]
+    LaunchFailed(String, LaunchFailure),
+    #[error("Refusing to {0} on a read-only device")]
+    WriteBlocked(String),
+    #[error("Invalid adb key '{0}': {1}")]
+    InvalidKey(String, String),
+    #[error("Refusing to remove '{0}': {1}")]
+    RemoveBlocked(String, String),
 }
 
 fn encode_message(payload: &str) -> Result<String> {
@@ -135,7 +337,7 @@ fn parse_device_brief(line: &str) -> Option<DeviceBrief> {
 }
 
 /// Reads the payload length of a host message from the stream.
-async fn read_length<R: AsyncRead + Unpin>(stream: &mut R) -> Result<usize> {
+async fn read_length<R: AsyncRead + Unpin + ?Sized>(stream: &mut R) -> Result<usize> {
     let mut bytes = [0; 4];
     stream.read_exact(&mut bytes).await?;
 
@@ -145,7 +347,7 @@ async fn read_length<R: AsyncRead + Unpin>(stream: &mut R) -> Result<usize> {
 }
 
 /// Reads the payload length of a device message from the stream.
-async fn read_length_little_endian<R: AsyncRead + Unpin>(reader: &mut R) -> Result<usize> {
+async fn read_length_little_endian<R: AsyncRead + Unpin + ?Sized>(reader: &mut R) -> Result<usize> {
     let mut bytes = [0; 4];
     reader.read_exact(&mut bytes).await?;
 
@@ -158,7 +360,7 @@ async fn read_length_little_endian<R: AsyncRead + Unpin>(reader: &mut R) -> Resu
 }
 
 /// Writes the payload length of a device message to the stream.
-async fn write_length_little_endian<W: AsyncWrite + Unpin>(
+async fn write_length_little_endian<W: AsyncWrite + Unpin + ?Sized>(
     writer: &mut W,
     n: usize,
 ) -> Result<usize> {
@@ -171,8 +373,219 @@ async fn write_length_little_endian<W: AsyncWrite + Unpin>(
     writer.write(&bytes[..]).await.map_err(DeviceError::Io)
 }
 
+/// Awaits `future`, failing with [`DeviceError::CommandTimeout`] if it does not
+/// complete within `limit`.
+async fn command_timeout<T>(
+    limit: Option<Duration>,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match limit {
+        Some(limit) => timeout(limit, future)
+            .await
+            .map_err(|_| DeviceError::CommandTimeout)?,
+        None => future.await,
+    }
+}
+
+/// `touch` prints nothing on success.
+fn check_touch_output(path: &UnixPath, output: &str) -> Result<()> {
+    match output.trim() {
+        "" => Ok(()),
+        message => Err(DeviceError::Adb(format!(
+            "touch {}: {}",
+            path.display(),
+            message
+        ))),
+    }
+}
+
+/// Splits seconds since the epoch into a UTC date and time of day.
+fn utc_from_unix(secs: u64) -> ((u64, u64, u64), (u64, u64, u64)) {
+    let (days, rem) = (secs / 86400, secs % 86400);
+    // Civil-from-days, counting from 0000-03-01 so leap days fall last.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    ((year, month, day), (rem / 3600, rem % 3600 / 60, rem % 60))
+}
+
+/// Sends a sync `STAT` request for `path` over `stream` and reads the
+/// reply.  The outer error leaves the connection unusable; the inner one is
+/// about `path`.
+async fn stat_over(stream: &mut BoxedTransport, path: &UnixPath) -> Result<Result<FileMetadata>> {
+    // Send "STAT" command with path
+    stream.write_all(SyncCommand::Stat.code()).await?;
+    let args = format!("{}", path.display()).into_bytes();
+    write_length_little_endian(stream, args.len()).await?;
+    stream.write_all(&args).await?;
+
+    // Read response
+    let mut response_code = [0u8; 4];
+    stream.read_exact(&mut response_code).await?;
+
+    if &response_code != SyncCommand::Stat.code() {
+        return Err(DeviceError::Adb(format!(
+            "Invalid response code: {:?}",
+            std::str::from_utf8(&response_code)
+        )));
+    }
+
+    // Read the 12 bytes containing mode (4), size (4), and time (4)
+    let mut stat_data = [0u8; 12];
+    stream.read_exact(&mut stat_data).await?;
+
+    // Parse the data
+    let mode = u32::from_le_bytes(stat_data[0..4].try_into().unwrap());
+    let size = u32::from_le_bytes(stat_data[4..8].try_into().unwrap());
+    let time = u32::from_le_bytes(stat_data[8..12].try_into().unwrap());
+
+    // Mode 0 indicates the remote path does not exist.
+    if mode == 0 {
+        return Ok(Err(DeviceError::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("remote path not found: {}", path.display()),
+        ))));
+    }
+
+    // Convert mode to UnixFileStatus
+    let file_mode = match mode & 0xF000 {
+        0x4000 => UnixFileStatus::Directory,
+        0x2000 => UnixFileStatus::CharacterDevice,
+        0x6000 => UnixFileStatus::BlockDevice,
+        0x8000 => UnixFileStatus::RegularFile,
+        0xA000 => UnixFileStatus::SymbolicLink,
+        0xC000 => UnixFileStatus::Socket,
+        _ => {
+            return Ok(Err(DeviceError::Adb(format!(
+                "Unknown file mode: {mode:#x}"
+            ))))
+        }
+    };
+
+    Ok(Ok(FileMetadata {
+        path: path.display().to_string(),
+        file_mode,
+        size,
+        modified_time: if time == 0 {
+            None
+        } else {
+            Some(SystemTime::UNIX_EPOCH + StdDuration::from_secs(time as u64))
+        },
+        depth: None,
+    }))
+}
+
+/// Reads from `stream` until `buf` holds at least `n` bytes, reusing the
+/// space of consumed bytes rather than growing `buf`.
+async fn fill_buffer<R: AsyncRead + Unpin + ?Sized>(
+    stream: &mut R,
+    buf: &mut BytesMut,
+    n: usize,
+) -> Result<()> {
+    while buf.len() < n {
+        let spare = buf.capacity() - buf.len();
+        if spare < n - buf.len() || spare < SYNC_PULL_BUFFER_SIZE / 4 {
+            buf.reserve(SYNC_PULL_BUFFER_SIZE.max(n) - buf.len());
+        }
+        if stream.read_buf(buf).await? == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+    }
+    Ok(())
+}
+
+/// Reads the next `DENT` of a `LIST` response, skipping `.` and `..`.
+/// Returns `None` once the listing is `DONE`.
+async fn read_dent(
+    stream: &mut dyn Transport,
+    buf: &mut [u8],
+    depth: usize,
+    prefix: &str,
+) -> Result<Option<FileMetadata>> {
+    loop {
+        stream.read_exact(&mut buf[0..4]).await?;
+
+        if &buf[0..4] == SyncCommand::Dent.code() {
+            // From https://github.com/cstyan/adbDocumentation/blob/6d025b3e4af41be6f93d37f516a8ac7913688623/README.md:
+            //
+            // A four-byte integer representing file mode - first 9 bits of this mode represent
+            // the file permissions, as with chmod mode. Bits 14 to 16 seem to represent the
+            // file type, one of 0b100 (file), 0b010 (directory), 0b101 (symlink)
+            // A four-byte integer representing file size.
+            // A four-byte integer representing last modified time in seconds since Unix Epoch.
+            // A four-byte integer representing file name length.
+            // A utf-8 string representing the file name.
+            let mode = read_length_little_endian(stream).await?;
+            let size = read_length_little_endian(stream).await?;
+            let time = read_length_little_endian(stream).await?;
+            let mod_time = SystemTime::UNIX_EPOCH + StdDuration::from_secs(time as u64);
+            let name_length = read_length_little_endian(stream).await?;
+            stream.read_exact(&mut buf[0..name_length]).await?;
+
+            let mut name = std::str::from_utf8(&buf[0..name_length])?.to_owned();
+
+            if name == "." || name == ".." {
+                continue;
+            }
+
+            if !prefix.is_empty() {
+                name = format!("{}/{}", prefix, &name);
+            }
+
+            let file_type = (mode >> 13) & 0b111;
+            let metadata = match file_type {
+                0b010 => FileMetadata {
+                    path: name,
+                    file_mode: UnixFileStatus::Directory,
+                    size: 0,
+                    modified_time: Some(mod_time),
+                    depth: Some(depth),
+                },
+                0b100 => FileMetadata {
+                    path: name,
+                    file_mode: UnixFileStatus::RegularFile,
+                    size: size as u32,
+                    modified_time: Some(mod_time),
+                    depth: Some(depth),
+                },
+                0b101 => FileMetadata {
+                    path: name,
+                    file_mode: UnixFileStatus::SymbolicLink,
+                    size: 0,
+                    modified_time: Some(mod_time),
+                    depth: Some(depth),
+                },
+                _ => return Err(DeviceError::Adb(format!("Invalid file mode {file_type}"))),
+            };
+
+            return Ok(Some(metadata));
+        } else if &buf[0..4] == SyncCommand::Done.code() {
+            // "DONE" command indicates end of file transfer
+            return Ok(None);
+        } else if &buf[0..4] == SyncCommand::Fail.code() {
+            let n = buf.len().min(read_length_little_endian(stream).await?);
+
+            stream.read_exact(&mut buf[0..n]).await?;
+
+            let message = std::str::from_utf8(&buf[0..n])
+                .map(|s| format!("adb error: {s}"))
+                .unwrap_or_else(|_| "adb error was not utf-8".into());
+
+            return Err(DeviceError::Adb(message));
+        } else {
+            return Err(DeviceError::Adb("FAIL (unknown)".to_owned()));
+        }
+    }
+}
+
 async fn read_response(
-    stream: &mut TcpStream,
+    stream: &mut dyn Transport,
     has_output: bool,
     has_length: bool,
 ) -> Result<Vec<u8>> {
@@ -294,25 +707,167 @@ impl From<DeviceInfo> for DeviceBrief {
     }
 }
 
+/// Timeouts applied to connections with the adb server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Time allowed to establish a connection.  Defaults to 5 seconds.
+    pub connect: Duration,
+    /// Time allowed for the complete response of a command, including shell
+    /// command output.  Defaults to no limit, as shell commands may
+    /// legitimately run for a long time.
+    pub command: Option<Duration>,
+    /// Time a sync transfer (push, pull, list, stat) may go without any
+    /// progress before it is aborted.  Defaults to 60 seconds.
+    pub sync_idle: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Timeouts {
+        Timeouts {
+            connect: ADB_CONNECT_TIMEOUT,
+            command: None,
+            sync_idle: Some(ADB_SYNC_IDLE_TIMEOUT),
+        }
+    }
+}
+
+/// How shell commands are elevated to root on rooted devices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SuStrategy {
+    /// Commands run as the shell user.
+    #[default]
+    None,
+    /// `su 0 sh -c '<command>'`, as understood by the AOSP `su` binary.
+    Su0,
+    /// `su -c '<command>'`, as understood by Magisk and SuperSU.
+    SuC,
+}
+
+impl SuStrategy {
+    /// Returns `command` wrapped in the `su` invocation, if any.
+    pub fn wrap(&self, command: &str) -> Option<String> {
+        let quoted = format!("'{}'", command.replace('\'', "'\"'\"'"));
+        match self {
+            SuStrategy::None => None,
+            SuStrategy::Su0 => Some(format!("su 0 sh -c {quoted}")),
+            SuStrategy::SuC => Some(format!("su -c {quoted}")),
+        }
+    }
+}
+
+/// Mode and owner for [`Device::create_dir_with`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CreateDirOptions {
+    /// `mkdir -m`, e.g. `0o755`.  Without it the shell's umask applies.
+    pub mode: Option<u32>,
+    /// `user[:group]` passed to `chown`, which needs root: `adbd` running
+    /// as root or a [`SuStrategy`].  Otherwise `chown` fails with a
+    /// [`io::ErrorKind::PermissionDenied`] error.
+    pub owner: Option<String>,
+}
+
+impl CreateDirOptions {
+    pub fn new() -> CreateDirOptions {
+        CreateDirOptions::default()
+    }
+
+    pub fn mode(mut self, mode: u32) -> CreateDirOptions {
+        self.mode = Some(mode);
+        self
+    }
+
+    pub fn owner(mut self, owner: &str) -> CreateDirOptions {
+        self.owner = Some(owner.to_owned());
+        self
+    }
+}
+
 /// Represents a connection to an ADB host, which multiplexes the connections to
 /// individual devices.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct Host {
     /// The TCP host to connect to.  Defaults to `"localhost"`.
     pub host: Option<String>,
     /// The TCP port to connect to.  Defaults to `5037`.
     pub port: Option<u16>,
+    /// Server address used instead of `host:port`, e.g. a unix socket.
+    /// While it and both `host` and `port` are `None`, `ADB_SERVER_SOCKET`
+    /// is used if set.
+    pub address: Option<ServerAddress>,
+    /// Custom transport used instead of a TCP connection to `host:port`.
+    pub connector: Option<Arc<dyn Connector>>,
+    /// Timeouts for connections made by this host.  Devices created from
+    /// this host inherit them.
+    pub timeouts: Timeouts,
+    /// Retry policy for transient failures.  Disabled by default; devices
+    /// created from this host inherit it.
+    pub retry: Option<RetryPolicy>,
 }
 
+/// Honors `ADB_SERVER_SOCKET` and `ANDROID_ADB_SERVER_PORT` like the adb
+/// client does.  With `ADB_SERVER_SOCKET` set `host` and `port` are left
+/// `None`, so that setting either connects to `host:port` instead.
 impl Default for Host {
     fn default() -> Host {
+        if env_server_socket().is_some() {
+            return Host {
+                host: None,
+                port: None,
+                address: None,
+                connector: None,
+                timeouts: Timeouts::default(),
+                retry: None,
+            };
+        }
+        let port = std::env::var("ANDROID_ADB_SERVER_PORT")
+            .ok()
+            .and_then(|port| match port.parse::<u16>() {
+                Ok(port) => Some(port),
+                Err(err) => {
+                    warn!("Ignoring ANDROID_ADB_SERVER_PORT: {}", err);
+                    None
+                }
+            });
+
         Host {
             host: Some("localhost".to_string()),
-            port: Some(5037),
+            port: Some(port.unwrap_or(5037)),
+            address: None,
+            connector: None,
+            timeouts: Timeouts::default(),
+            retry: None,
+        }
+    }
+}
+
+/// The address in `ADB_SERVER_SOCKET`, if it is set and valid.
+fn env_server_socket() -> Option<ServerAddress> {
+    let socket = std::env::var("ADB_SERVER_SOCKET").ok()?;
+    match socket.parse::<ServerAddress>() {
+        Ok(address) => Some(address),
+        Err(err) => {
+            warn!("Ignoring ADB_SERVER_SOCKET: {}", err);
+            None
         }
     }
 }
 
+impl PartialEq for Host {
+    fn eq(&self, other: &Host) -> bool {
+        let same_connector = match (&self.connector, &other.connector) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        self.host == other.host
+            && self.port == other.port
+            && self.address == other.address
+            && self.timeouts == other.timeouts
+            && self.retry == other.retry
+            && same_connector
+    }
+}
+
 impl Host {
     /// Searches for available devices, and selects the one as specified by `device_serial`.
     ///
@@ -360,10 +915,7 @@ impl Host {
     pub async fn start_server(&self, adb_path: Option<&str>) -> Result<()> {
         let adb_path = adb_path.unwrap_or("adb");
         let mut command = Command::new(adb_path);
-        command
-            .arg("-H")
-            .arg(self.host.clone().unwrap_or("localhost".to_owned()));
-        command.arg("-P").arg(self.port.unwrap_or(5037).to_string());
+        self.server_args(&mut command);
         command.arg("start-server");
         #[cfg(target_os = "windows")]
         command.creation_flags(0x08000000); // CREATE_NO_WINDOW
@@ -381,10 +933,7 @@ impl Host {
     pub async fn kill_server(&self, adb_path: Option<&str>) -> Result<()> {
         let adb_path = adb_path.unwrap_or("adb");
         let mut command = Command::new(adb_path);
-        command
-            .arg("-H")
-            .arg(self.host.clone().unwrap_or("localhost".to_owned()));
-        command.arg("-P").arg(self.port.unwrap_or(5037).to_string());
+        self.server_args(&mut command);
         command.arg("kill-server");
         #[cfg(target_os = "windows")]
         command.creation_flags(0x08000000); // CREATE_NO_WINDOW
@@ -404,22 +953,68 @@ impl Host {
         }
     }
 
-    pub async fn connect(&self) -> Result<TcpStream> {
-        let addr = format!(
-            "{}:{}",
-            self.host.clone().unwrap_or_else(|| "localhost".to_owned()),
-            self.port.unwrap_or(5037)
-        );
+    /// The server [`Host::connect`] connects to unless a custom
+    /// [`Connector`] is configured: [`Host::address`], else `host:port`
+    /// when either is set, else `ADB_SERVER_SOCKET`, else
+    /// `localhost:5037`.
+    pub fn server_address(&self) -> ServerAddress {
+        self.resolve_server_address(env_server_socket)
+    }
 
-        let stream = timeout(ADB_CONNECT_TIMEOUT, TcpStream::connect(&addr))
-            .await
-            .map_err(|_| DeviceError::ConnectTimeout)??;
+    fn resolve_server_address(
+        &self,
+        env_socket: impl FnOnce() -> Option<ServerAddress>,
+    ) -> ServerAddress {
+        if let Some(address) = &self.address {
+            return address.clone();
+        }
+        if self.host.is_none() && self.port.is_none() {
+            if let Some(address) = env_socket() {
+                return address;
+            }
+        }
+        ServerAddress::Tcp {
+            host: self.host.clone().unwrap_or_else(|| "localhost".to_owned()),
+            port: self.port.unwrap_or(5037),
+        }
+    }
+
+    /// Points the `adb` client at [`Host::server_address`].
+    fn server_args(&self, command: &mut Command) {
+        match self.server_address() {
+            ServerAddress::Tcp { host, port } => {
+                command.arg("-H").arg(host);
+                command.arg("-P").arg(port.to_string());
+            }
+            address => {
+                command.arg("-L").arg(address.to_string());
+            }
+        }
+    }
 
-        stream.set_nodelay(true)?;
+    /// Opens a new connection to the adb server, using the custom
+    /// [`Connector`] if one is configured.
+    pub async fn connect(&self) -> Result<BoxedTransport> {
+        with_retry(self.retry.as_ref(), || {
+            self.connect_within(self.timeouts.connect)
+        })
+        .await
+    }
 
-        Ok(stream)
+    async fn connect_within(&self, connect_timeout: Duration) -> Result<BoxedTransport> {
+        if let Some(connector) = &self.connector {
+            return timeout(connect_timeout, connector.connect())
+                .await
+                .map_err(|_| DeviceError::ConnectTimeout)?;
+        }
+
+        self.server_address().connect(connect_timeout).await
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), err)
+    )]
     pub async fn execute_command(
         &self,
         command: &str,
@@ -431,7 +1026,11 @@ impl Host {
         stream
             .write_all(encode_message(command)?.as_bytes())
             .await?;
-        let bytes = read_response(&mut stream, has_output, has_length).await?;
+        let bytes = command_timeout(
+            self.timeouts.command,
+            read_response(&mut stream, has_output, has_length),
+        )
+        .await?;
         // TODO: should we assert no bytes were read?
 
         let response = std::str::from_utf8(&bytes)?;
@@ -450,7 +1049,10 @@ impl Host {
     }
 
     pub async fn get_host_version(&self) -> Result<u64> {
-        let response = self.execute_host_command("version", true, true).await?;
+        let response = with_retry(self.retry.as_ref(), || {
+            self.execute_host_command("version", true, true)
+        })
+        .await?;
         if let Ok(version) = u64::from_str_radix(&response, 16) {
             Ok(version)
         } else {
@@ -468,12 +1070,22 @@ impl Host {
     }
 
     pub async fn features<B: FromIterator<String>>(&self) -> Result<B> {
-        let features = self.execute_host_command("features", true, true).await?;
+        let features = with_retry(self.retry.as_ref(), || {
+            self.execute_host_command("features", true, true)
+        })
+        .await?;
         Ok(features.split(',').map(|x| x.to_owned()).collect())
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), err)
+    )]
     pub async fn devices<B: FromIterator<DeviceInfo>>(&self) -> Result<B> {
-        let response = self.execute_host_command("devices-l", true, true).await?;
+        let response = with_retry(self.retry.as_ref(), || {
+            self.execute_host_command("devices-l", true, true)
+        })
+        .await?;
 
         let infos: B = response.lines().filter_map(parse_device_info).collect();
 
@@ -559,8 +1171,58 @@ pub struct Device {
 
     pub run_as_package: Option<String>,
 
-    /// Cache intermediate tempfile name used in pushing via run_as.
-    pub tempfile: UnixPathBuf,
+    /// Directory for temporary files, such as the intermediate file of
+    /// `run-as` pushes.  Defaults to `/data/local/tmp`.
+    pub tempfile_dir: UnixPathBuf,
+
+    /// Timeouts for connections to this device.  Initialized from the host.
+    pub timeouts: Timeouts,
+
+    /// Retry policy for transient failures.  Initialized from the host.
+    pub retry: Option<RetryPolicy>,
+
+    /// How often byte progress is reported by the `*_with_progress` transfers.
+    pub progress_granularity: ProgressGranularity,
+
+    /// Number of files [`Device::pull_dir`] transfers at the same time.
+    pub pull_concurrency: usize,
+
+    /// Largest file in bytes [`Device::pull_to_vec`] and
+    /// [`Device::pull_to_string`] read into memory.
+    pub pull_to_vec_limit: u64,
+
+    /// How shell commands not run via `run-as` are elevated to root by
+    /// [`Device::execute_host_shell_command`], the shell streams and `exec:`
+    /// services.  [`Device::run`] and [`Device::run_batch`] run commands as
+    /// given; wrap them with [`SuStrategy::wrap`] where root is needed.
+    pub su: SuStrategy,
+
+    /// Android user whose packages `pm` and `am` commands operate on.
+    /// Defaults to the tools' own default, usually the current user.
+    pub user: Option<u32>,
+
+    /// Log recording every request and transfer, see [`AuditLog`].
+    pub audit: Option<Arc<AuditLog>>,
+
+    /// Whether operations modifying the device fail with
+    /// [`DeviceError::WriteBlocked`], see [`Device::read_only`].
+    pub read_only: bool,
+
+    /// Directories below which recursive removals may delete.  Empty to
+    /// allow any path the guard of [`Device::remove_dir_all`] accepts.
+    pub remove_roots: Vec<UnixPathBuf>,
+
+    /// Storage chosen by [`Device::select_storage`], if any.
+    pub storage: Option<AndroidStorage>,
+
+    /// Base directory of the selected storage.
+    pub storage_root: Option<UnixPathBuf>,
+
+    /// Cached result of [`Device::features`].
+    features: Arc<OnceCell<BTreeSet<String>>>,
+
+    /// Cached result of [`Device::sdk_level`].
+    sdk: Arc<OnceCell<u32>>,
 }
 
 impl Device {
@@ -569,32 +1231,129 @@ impl Device {
         serial: DeviceSerial,
         info: BTreeMap<String, String>,
     ) -> Result<Device> {
-        let mut device = Device {
+        let timeouts = host.timeouts;
+        let retry = host.retry;
+        Ok(Device {
             host,
             serial,
             info,
             run_as_package: None,
-            tempfile: UnixPathBuf::from("/data/local/tmp"),
-        };
-        device
-            .tempfile
-            .push(Uuid::new_v4().as_hyphenated().to_string());
+            tempfile_dir: UnixPathBuf::from("/data/local/tmp"),
+            timeouts,
+            retry,
+            progress_granularity: ProgressGranularity::default(),
+            pull_concurrency: DEFAULT_PULL_CONCURRENCY,
+            pull_to_vec_limit: DEFAULT_PULL_TO_VEC_LIMIT,
+            su: SuStrategy::None,
+            user: None,
+            audit: None,
+            read_only: false,
+            remove_roots: Vec::new(),
+            storage: None,
+            storage_root: None,
+            features: Arc::default(),
+            sdk: Arc::default(),
+        })
+    }
 
-        Ok(device)
+    /// Returns a builder for a device connected through `host`.
+    pub fn builder(host: Host) -> DeviceBuilder {
+        DeviceBuilder::new(host)
+    }
+
+    /// Returns the `--user` argument for `pm` and `am`, if a user is set.
+    fn user_arg(&self) -> String {
+        match self.user {
+            Some(user) => format!(" --user {user}"),
+            None => String::new(),
+        }
     }
 
     pub async fn clear_app_data(&self, package: &str) -> Result<bool> {
-        self.execute_host_shell_command(&format!("pm clear {package}"))
+        self.check_writable("clear app data")?;
+        let pm = self.capabilities().await?.package_manager();
+        self.execute_host_shell_command(&format!("{pm} clear{} {package}", self.user_arg()))
             .await
             .map(|v| v.contains("Success"))
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial, path = %path.display()), err)
+    )]
     pub async fn create_dir(&self, path: &UnixPath) -> Result<()> {
+        self.check_writable("create directories")?;
         debug!("Creating {}", path.display());
 
         let enable_run_as = self.enable_run_as_for_path(path);
-        self.execute_host_shell_command_as(&format!("mkdir -p {}", path.display()), enable_run_as)
+        let path = DevicePath::new(path)?;
+        self.execute_host_shell_command_as(&format!("mkdir -p {}", path.quoted()), enable_run_as)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Creates `path` and its missing ancestors with the mode and owner of
+    /// `options`, which are applied to every directory created here when
+    /// it is created; directories that already exist are left unchanged.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial, path = %path.display()), err)
+    )]
+    pub async fn create_dir_with(&self, path: &UnixPath, options: &CreateDirOptions) -> Result<()> {
+        if options.mode.is_none() && options.owner.is_none() {
+            return self.create_dir(path).await;
+        }
+        self.check_writable("create directories")?;
+        debug!("Creating {} with {:?}", path.display(), options);
+
+        if let Some(owner) = &options.owner {
+            if owner.is_empty()
+                || !owner
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-' | ':'))
+            {
+                return Err(DeviceError::Adb(format!("Invalid owner '{owner}'")));
+            }
+        }
+
+        // `mkdir -p -m` only applies the mode to the last component, so all
+        // missing directories are named, outermost first.
+        let enable_run_as = self.enable_run_as_for_path(path);
+        let mut missing = Vec::new();
+        let mut current = Some(path);
+        while let Some(dir) = current {
+            if self.path_exists(dir, enable_run_as).await? {
+                break;
+            }
+            missing.push(DevicePath::new(dir)?.quoted());
+            current = dir.parent();
+        }
+        if missing.is_empty() {
+            return Ok(());
+        }
+        missing.reverse();
+        let dirs = missing.join(" ");
+
+        let mode = options
+            .mode
+            .map(|mode| format!(" -m {mode:o}"))
+            .unwrap_or_default();
+        let output = self
+            .execute_host_shell_command_as(&format!("mkdir -p{mode} {dirs}"), enable_run_as)
             .await?;
+        if !output.trim().is_empty() {
+            return Err(file_error(output.trim()));
+        }
+
+        if let Some(owner) = &options.owner {
+            let output = self
+                .execute_host_shell_command_as(&format!("chown {owner} {dirs}"), enable_run_as)
+                .await?;
+            if !output.trim().is_empty() {
+                return Err(file_error(output.trim()));
+            }
+        }
 
         Ok(())
     }
@@ -610,7 +1369,9 @@ impl Device {
     }
 
     pub async fn chmod(&self, path: &UnixPath, mask: &str, recursive: bool) -> Result<()> {
+        self.check_writable("chmod")?;
         let enable_run_as = self.enable_run_as_for_path(path);
+        let path = DevicePath::new(path)?;
 
         let recursive = match recursive {
             true => " -R",
@@ -618,7 +1379,7 @@ impl Device {
         };
 
         self.execute_host_shell_command_as(
-            &format!("chmod {} {} {}", recursive, mask, path.display()),
+            &format!("chmod {} {} {}", recursive, mask, path.quoted()),
             enable_run_as,
         )
         .await?;
@@ -626,29 +1387,184 @@ impl Device {
         Ok(())
     }
 
-    pub async fn execute_host_command(
-        &self,
-        command: &str,
-        has_output: bool,
-        has_length: bool,
-    ) -> Result<Vec<u8>> {
-        let mut stream = self.host.connect().await?;
+    /// Sets the modification time of `path`, e.g. to restore the original
+    /// timestamp after a push.  The file is not created if it is missing.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial, path = %path.display()), err)
+    )]
+    pub async fn set_mtime(&self, path: &UnixPath, time: SystemTime) -> Result<()> {
+        self.check_writable("set file times")?;
+        let enable_run_as = self.enable_run_as_for_path(path);
+        let quoted = DevicePath::new(path)?.quoted();
+
+        let secs = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_err(|_| DeviceError::Adb(format!("Time before 1970 for {}", path.display())))?
+            .as_secs();
+        let ((year, month, day), (hour, minute, second)) = utc_from_unix(secs);
+        let output = if self.capabilities().await?.supports_touch_date() {
+            let command = format!(
+                "touch -c -m -d {year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}Z {quoted}"
+            );
+            self.execute_host_shell_command_as(&command, enable_run_as)
+                .await?
+        } else {
+            // toolbox reads `-t` in local time.  It has no `env`, and
+            // `run-as` execs its arguments without a shell, so `TZ` is set
+            // in front of `run-as`, which passes the environment on.
+            let command = format!(
+                "touch -m -t {year:04}{month:02}{day:02}.{hour:02}{minute:02}{second:02} {quoted}"
+            );
+            match &self.run_as_package {
+                Some(package) if enable_run_as => {
+                    self.execute_host_command_to_string(
+                        &format!("shell:TZ=UTC run-as {package} {command}"),
+                        true,
+                        false,
+                    )
+                    .await?
+                }
+                _ => {
+                    self.execute_host_shell_command(&format!("TZ=UTC {command}"))
+                        .await?
+                }
+            }
+        };
+
+        check_touch_output(path, &output)
+    }
+
+    /// Creates `path` as an empty file, or sets its access and modification
+    /// times to now if it exists.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial, path = %path.display()), err)
+    )]
+    pub async fn touch(&self, path: &UnixPath) -> Result<()> {
+        self.check_writable("create files")?;
+        let enable_run_as = self.enable_run_as_for_path(path);
+        let path_arg = DevicePath::new(path)?;
+
+        let output = self
+            .execute_host_shell_command_as(&format!("touch {}", path_arg.quoted()), enable_run_as)
+            .await?;
+        check_touch_output(path, &output)
+    }
+
+    /// Opens a connection to the adb server and switches it to this device.
+    ///
+    /// Nothing has been sent to the device yet, so transient failures are
+    /// retried according to the device's [`RetryPolicy`].
+    async fn connect_transport(&self) -> Result<BoxedTransport> {
+        with_retry(self.retry.as_ref(), || self.connect_transport_once()).await
+    }
+
+    async fn connect_transport_once(&self) -> Result<BoxedTransport> {
+        let mut stream = self.host.connect_within(self.timeouts.connect).await?;
 
         let switch_command = format!("host:transport:{}", self.serial);
-        trace!("execute_host_command: >> {:?}", &switch_command);
+        trace!("connect_transport: >> {:?}", &switch_command);
         stream
             .write_all(encode_message(&switch_command)?.as_bytes())
             .await?;
-        let _bytes = read_response(&mut stream, false, false).await?;
-        trace!("execute_host_command: << {:?}", _bytes);
+        let _bytes = command_timeout(
+            self.timeouts.command,
+            read_response(&mut stream, false, false),
+        )
+        .await?;
+        trace!("connect_transport: << {:?}", _bytes);
         // TODO: should we assert no bytes were read?
 
+        Ok(stream)
+    }
+
+    /// Opens a connection to this device and initializes the sync protocol
+    /// used for file transfers.
+    ///
+    /// The returned stream fails with [`io::ErrorKind::TimedOut`] when the
+    /// transfer stalls for longer than [`Timeouts::sync_idle`].
+    async fn connect_sync(&self) -> Result<BoxedTransport> {
+        let stream = self.connect_transport().await?;
+        self.start_sync(stream).await
+    }
+
+    /// [`Device::connect_sync`] without retrying the connection, for
+    /// operations that are retried as a whole.
+    async fn connect_sync_once(&self) -> Result<BoxedTransport> {
+        let stream = self.connect_transport_once().await?;
+        self.start_sync(stream).await
+    }
+
+    async fn start_sync(&self, mut stream: BoxedTransport) -> Result<BoxedTransport> {
+        let message = encode_message("sync:")?;
+        stream.write_all(message.as_bytes()).await?;
+        let _bytes = command_timeout(
+            self.timeouts.command,
+            read_response(&mut stream, false, true),
+        )
+        .await?;
+
+        match self.timeouts.sync_idle {
+            Some(idle) => Ok(Box::new(IdleTimeout::new(stream, idle))),
+            None => Ok(stream),
+        }
+    }
+
+    /// Takes an idle sync connection from `state`, or opens a new one.
+    async fn take_sync(&self, state: &TransferState) -> Result<BoxedTransport> {
+        match state.connections.as_ref().and_then(|c| c.take()) {
+            Some(stream) => Ok(stream),
+            None => self.connect_sync().await,
+        }
+    }
+
+    /// Fails with [`DeviceError::WriteBlocked`] for `operation` on a
+    /// read-only device.
+    pub(crate) fn check_writable(&self, operation: &str) -> Result<()> {
+        if self.read_only {
+            return Err(DeviceError::WriteBlocked(operation.to_owned()));
+        }
+        Ok(())
+    }
+
+    /// Records a request for `service` in the [`Device::audit`] log.
+    pub(crate) fn audit_command(&self, service: &str) -> Result<()> {
+        if let Some(audit) = &self.audit {
+            audit.command(&self.serial, service)?;
+        }
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip(self),
+            fields(serial = %self.serial, bytes = tracing::field::Empty),
+            err
+        )
+    )]
+    pub async fn execute_host_command(
+        &self,
+        command: &str,
+        has_output: bool,
+        has_length: bool,
+    ) -> Result<Vec<u8>> {
+        let mut stream = self.connect_transport().await?;
+
         trace!("execute_host_command: >> {:?}", &command);
+        self.audit_command(command)?;
         stream
             .write_all(encode_message(command)?.as_bytes())
             .await?;
-        let bytes = read_response(&mut stream, has_output, has_length).await?;
+        let bytes = command_timeout(
+            self.timeouts.command,
+            read_response(&mut stream, has_output, has_length),
+        )
+        .await?;
         trace!("execute_host_command: << {:?}", bstr::BStr::new(&bytes));
+        record_span!("bytes", bytes.len());
 
         Ok(bytes)
     }
@@ -685,6 +1601,84 @@ impl Device {
             .await
     }
 
+    /// Runs `shell_command` and yields its output as it arrives, for
+    /// long-running commands such as `logcat` or `top`.
+    ///
+    /// The stream ends when the command exits.  Dropping it closes the
+    /// connection, which terminates the command on the device.
+    pub fn execute_host_shell_stream<'a>(
+        &'a self,
+        shell_command: &'a str,
+    ) -> impl Stream<Item = Result<Bytes>> + 'a {
+        async_stream::try_stream! {
+            let command = match self.su.wrap(shell_command) {
+                Some(elevated) => format!("shell:{elevated}"),
+                None => format!("shell:{shell_command}"),
+            };
+
+            let mut stream = self.connect_transport().await?;
+            trace!("execute_host_shell_stream: >> {:?}", &command);
+            self.audit_command(&command)?;
+            stream
+                .write_all(encode_message(&command)?.as_bytes())
+                .await?;
+            command_timeout(
+                self.timeouts.command,
+                read_response(&mut stream, false, false),
+            )
+            .await?;
+
+            let mut buf = BytesMut::with_capacity(64 * 1024);
+            loop {
+                buf.reserve(64 * 1024);
+                if stream.read_buf(&mut buf).await? == 0 {
+                    break;
+                }
+                yield buf.split().freeze();
+            }
+        }
+    }
+
+    /// Starts `command` over `exec:` and returns the stream carrying its
+    /// output, for binary data such as archives.  Stderr is discarded so
+    /// that it cannot corrupt the output.
+    pub(crate) async fn open_exec(
+        &self,
+        command: &str,
+        enable_run_as: bool,
+    ) -> Result<BoxedTransport> {
+        let command = if enable_run_as {
+            let package = self
+                .run_as_package
+                .as_ref()
+                .ok_or(DeviceError::MissingPackage)?;
+            format!("exec:run-as {package} {command} 2>/dev/null")
+        } else {
+            let command = self.su.wrap(command).unwrap_or_else(|| command.to_owned());
+            format!("exec:{command} 2>/dev/null")
+        };
+
+        self.open_service(&command).await
+    }
+
+    /// Opens `service`, e.g. `exec:cat`, and returns the stream for
+    /// talking to it once the device accepted.
+    pub(crate) async fn open_service(&self, service: &str) -> Result<BoxedTransport> {
+        let mut stream = self.connect_transport().await?;
+        trace!("open_service: >> {:?}", service);
+        self.audit_command(service)?;
+        stream
+            .write_all(encode_message(service)?.as_bytes())
+            .await?;
+        command_timeout(
+            self.timeouts.command,
+            read_response(&mut stream, false, false),
+        )
+        .await?;
+
+        Ok(stream)
+    }
+
     pub async fn execute_host_exec_out_command(&self, shell_command: &str) -> Result<Vec<u8>> {
         self.execute_host_command(&format!("exec:{shell_command}"), true, false)
             .await
@@ -742,41 +1736,42 @@ impl Device {
                 .await;
         }
 
+        if let Some(elevated) = self.su.wrap(shell_command) {
+            return self
+                .execute_host_command_to_string(&format!("shell:{elevated}"), true, false)
+                .await;
+        }
+
         self.execute_host_command_to_string(&format!("shell:{shell_command}"), true, false)
             .await
     }
 
     pub async fn is_app_installed(&self, package: &str) -> Result<bool> {
-        self.execute_host_shell_command(&format!("pm path {package}"))
+        let pm = self.capabilities().await?.package_manager();
+        self.execute_host_shell_command(&format!("{pm} path{} {package}", self.user_arg()))
             .await
             .map(|v| v.contains("package:"))
     }
 
+    /// Like [`Device::launch_activity`], returning whether the activity
+    /// was started instead of the details.
     pub async fn launch<T: AsRef<str>>(
         &self,
         package: &str,
         activity: &str,
         am_start_args: &[T],
     ) -> Result<bool> {
-        let mut am_start = format!("am start -W -n {package}/{activity}");
-
-        for arg in am_start_args {
-            am_start.push(' ');
-            if SYNC_REGEX.is_match(arg.as_ref()) {
-                am_start.push_str(&format!("\"{}\"", &shell::escape(arg.as_ref())));
-            } else {
-                am_start.push_str(&shell::escape(arg.as_ref()));
-            };
+        match self.launch_activity(package, activity, am_start_args).await {
+            Ok(_) => Ok(true),
+            Err(DeviceError::LaunchFailed(..)) => Ok(false),
+            Err(e) => Err(e),
         }
-
-        self.execute_host_shell_command(&am_start)
-            .await
-            .map(|v| v.contains("Complete"))
     }
 
     pub async fn force_stop(&self, package: &str) -> Result<()> {
+        self.check_writable("force-stop apps")?;
         debug!("Force stopping Android package: {}", package);
-        self.execute_host_shell_command(&format!("am force-stop {package}"))
+        self.execute_host_shell_command(&format!("am force-stop{} {package}", self.user_arg()))
             .await
             .and(Ok(()))
     }
@@ -810,6 +1805,7 @@ impl Device {
     }
 
     pub async fn reverse_port(&self, remote: u16, local: u16) -> Result<u16> {
+        self.check_writable("reverse ports")?;
         let command = format!("reverse:forward:tcp:{remote};tcp:{local}");
         let response = self
             .execute_host_command_to_string(&command, true, false)
@@ -836,6 +1832,14 @@ impl Device {
             .and(Ok(()))
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(serial = %self.serial, src = %src.display(), entries = tracing::field::Empty),
+            err
+        )
+    )]
     pub async fn list_dir(&self, src: &UnixPath) -> Result<Vec<FileMetadata>> {
         let src = src.to_path_buf();
         let mut queue = vec![(src.clone(), 0, "".to_string())];
@@ -843,7 +1847,11 @@ impl Device {
         let mut listings = Vec::new();
 
         while let Some((next, depth, prefix)) = queue.pop() {
-            for listing in self.list_dir_flat(&next, depth, prefix).await? {
+            let flat = with_retry(self.retry.as_ref(), || {
+                self.list_dir_flat(&next, depth, prefix.clone())
+            })
+            .await?;
+            for listing in flat {
                 if listing.file_mode == UnixFileStatus::Directory {
                     let mut child = src.clone();
                     child.push(listing.path.clone());
@@ -854,6 +1862,7 @@ impl Device {
             }
         }
 
+        record_span!("entries", listings.len());
         Ok(listings)
     }
 
@@ -863,158 +1872,124 @@ impl Device {
         depth: usize,
         prefix: String,
     ) -> Result<Vec<FileMetadata>> {
-        // Implement the ADB protocol to list a directory from the device.
-        let mut stream = self.host.connect().await?;
-
-        // Send "host:transport" command with device serial
-        let message = encode_message(&format!("host:transport:{}", self.serial))?;
-        stream.write_all(message.as_bytes()).await?;
-        let _bytes = read_response(&mut stream, false, true).await?;
-
-        // Send "sync:" command to initialize file transfer
-        let message = encode_message("sync:")?;
-        stream.write_all(message.as_bytes()).await?;
-        let _bytes = read_response(&mut stream, false, true).await?;
-
-        // Send "LIST" command with name of the directory
-        stream.write_all(SyncCommand::List.code()).await?;
-        let args_ = format!("{}", src.display());
-        let args = args_.as_bytes();
-        write_length_little_endian(&mut stream, args.len()).await?;
-        stream.write_all(args).await?;
+        let mut stream = self
+            .start_list(self.connect_sync_once().await?, src)
+            .await?;
 
         // Use the maximum 64K buffer to transfer the file contents.
-        let mut buf = vec![0; 64 * 1024];
+        let mut buf = PooledBuffer::filled(64 * 1024);
 
         let mut listings = Vec::new();
+        while let Some(metadata) = read_dent(&mut stream, &mut buf, depth, &prefix).await? {
+            listings.push(metadata);
+        }
 
-        // Read "DENT" command one or more times for the directory entries
-        loop {
-            stream.read_exact(&mut buf[0..4]).await?;
-
-            if &buf[0..4] == SyncCommand::Dent.code() {
-                // From https://github.com/cstyan/adbDocumentation/blob/6d025b3e4af41be6f93d37f516a8ac7913688623/README.md:
-                //
-                // A four-byte integer representing file mode - first 9 bits of this mode represent
-                // the file permissions, as with chmod mode. Bits 14 to 16 seem to represent the
-                // file type, one of 0b100 (file), 0b010 (directory), 0b101 (symlink)
-                // A four-byte integer representing file size.
-                // A four-byte integer representing last modified time in seconds since Unix Epoch.
-                // A four-byte integer representing file name length.
-                // A utf-8 string representing the file name.
-                let mode = read_length_little_endian(&mut stream).await?;
-                let size = read_length_little_endian(&mut stream).await?;
-                let time = read_length_little_endian(&mut stream).await?;
-                let mod_time = SystemTime::UNIX_EPOCH + StdDuration::from_secs(time as u64);
-                let name_length = read_length_little_endian(&mut stream).await?;
-                stream.read_exact(&mut buf[0..name_length]).await?;
-
-                let mut name = std::str::from_utf8(&buf[0..name_length])?.to_owned();
-
-                if name == "." || name == ".." {
-                    continue;
-                }
+        Ok(listings)
+    }
 
-                if !prefix.is_empty() {
-                    name = format!("{}/{}", prefix, &name);
+    /// Lists `src` recursively like [`Device::list_dir`], but yields entries
+    /// as they arrive instead of collecting them.
+    ///
+    /// Subdirectories are only listed once the stream reaches them, so
+    /// memory use stays flat even for trees with hundreds of thousands of
+    /// files.
+    pub fn list_dir_stream<'a>(
+        &'a self,
+        src: &'a UnixPath,
+    ) -> impl Stream<Item = Result<FileMetadata>> + 'a {
+        async_stream::try_stream! {
+            let mut queue = vec![(src.to_path_buf(), 0, String::new())];
+            let mut buf = PooledBuffer::filled(64 * 1024);
+
+            while let Some((next, depth, prefix)) = queue.pop() {
+                let mut stream = self.start_list(self.connect_sync().await?, &next).await?;
+                while let Some(metadata) = read_dent(&mut stream, &mut buf, depth, &prefix).await? {
+                    if metadata.file_mode == UnixFileStatus::Directory {
+                        queue.push((src.join(&metadata.path), depth + 1, metadata.path.clone()));
+                    }
+                    yield metadata;
                 }
-
-                let file_type = (mode >> 13) & 0b111;
-                let metadata = match file_type {
-                    0b010 => FileMetadata {
-                        path: name,
-                        file_mode: UnixFileStatus::Directory,
-                        size: 0,
-                        modified_time: Some(mod_time),
-                        depth: Some(depth),
-                    },
-                    0b100 => FileMetadata {
-                        path: name,
-                        file_mode: UnixFileStatus::RegularFile,
-                        size: size as u32,
-                        modified_time: Some(mod_time),
-                        depth: Some(depth),
-                    },
-                    0b101 => FileMetadata {
-                        path: name,
-                        file_mode: UnixFileStatus::SymbolicLink,
-                        size: 0,
-                        modified_time: Some(mod_time),
-                        depth: Some(depth),
-                    },
-                    _ => return Err(DeviceError::Adb(format!("Invalid file mode {file_type}"))),
-                };
-
-                listings.push(metadata);
-            } else if &buf[0..4] == SyncCommand::Done.code() {
-                // "DONE" command indicates end of file transfer
-                break;
-            } else if &buf[0..4] == SyncCommand::Fail.code() {
-                let n = buf.len().min(read_length_little_endian(&mut stream).await?);
-
-                stream.read_exact(&mut buf[0..n]).await?;
-
-                let message = std::str::from_utf8(&buf[0..n])
-                    .map(|s| format!("adb error: {s}"))
-                    .unwrap_or_else(|_| "adb error was not utf-8".into());
-
-                return Err(DeviceError::Adb(message));
-            } else {
-                return Err(DeviceError::Adb("FAIL (unknown)".to_owned()));
             }
         }
+    }
 
-        Ok(listings)
+    /// Sends the `LIST` request for `src` over the sync connection `stream`.
+    async fn start_list(
+        &self,
+        mut stream: BoxedTransport,
+        src: &UnixPath,
+    ) -> Result<BoxedTransport> {
+        // Implement the ADB protocol to list a directory from the device.
+
+        // Send "LIST" command with name of the directory
+        stream.write_all(SyncCommand::List.code()).await?;
+        let args_ = format!("{}", src.display());
+        let args = args_.as_bytes();
+        write_length_little_endian(&mut stream, args.len()).await?;
+        stream.write_all(args).await?;
+
+        Ok(stream)
     }
 
     pub async fn path_exists(&self, path: &UnixPath, enable_run_as: bool) -> Result<bool> {
-        self.execute_host_shell_command_as(format!("ls {}", path.display()).as_str(), enable_run_as)
+        let path = DevicePath::new(path)?;
+        self.execute_host_shell_command_as(format!("ls {}", path.quoted()).as_str(), enable_run_as)
             .await
             .map(|path| !path.contains("No such file or directory"))
     }
 
     pub async fn pull<W: AsyncWrite + Unpin>(&self, src: &UnixPath, buffer: &mut W) -> Result<()> {
-        self.pull_internal(src, buffer, None, None).await
+        self.pull_internal(src, buffer, None, None, &mut TransferState::default())
+            .await
+            .and(Ok(()))
     }
 
     pub async fn pull_with_progress<W: AsyncWrite + Unpin>(
         &self,
         src: &UnixPath,
         buffer: &mut W,
-        progress_sender: UnboundedSender<FileTransferProgress>,
+        progress: impl ProgressSink<FileTransferProgress>,
     ) -> Result<()> {
         let metadata = self.stat(src).await?;
         let total_bytes = metadata.size as u64;
 
-        self.pull_internal(src, buffer, Some(total_bytes), Some(progress_sender))
-            .await
+        self.pull_internal(
+            src,
+            buffer,
+            Some(total_bytes),
+            Some(&progress),
+            &mut TransferState::default(),
+        )
+        .await
+        .and(Ok(()))
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(serial = %self.serial, src = %src.display(), bytes = tracing::field::Empty),
+            err
+        )
+    )]
     async fn pull_internal<W: AsyncWrite + Unpin>(
         &self,
         src: &UnixPath,
         buffer: &mut W,
         total_bytes: Option<u64>,
-        progress_sender: Option<UnboundedSender<FileTransferProgress>>,
-    ) -> Result<()> {
-        if let (Some(total), Some(sender)) = (total_bytes, &progress_sender) {
-            let _ = sender.send(FileTransferProgress {
-                total_bytes: total,
+        progress: Option<&dyn ProgressSink<FileTransferProgress>>,
+        state: &mut TransferState,
+    ) -> Result<u64> {
+        if let (Some(total), Some(progress)) = (total_bytes, progress) {
+            progress.report(FileTransferProgress {
+                total_bytes: Some(total),
                 transferred_bytes: 0,
+                sparse_bytes: 0,
             });
         }
 
-        let mut stream = self.host.connect().await?;
-
-        // Send "host:transport" command with device serial
-        let message = encode_message(&format!("host:transport:{}", self.serial))?;
-        stream.write_all(message.as_bytes()).await?;
-        let _bytes = read_response(&mut stream, false, true).await?;
-
-        // Send "sync:" command to initialize file transfer
-        let message = encode_message("sync:")?;
-        stream.write_all(message.as_bytes()).await?;
-        let _bytes = read_response(&mut stream, false, true).await?;
+        let started = Instant::now();
+        let mut stream = self.take_sync(state).await?;
 
         // Send "RECV" command with name of the file
         stream.write_all(SyncCommand::Recv.code()).await?;
@@ -1023,58 +1998,71 @@ impl Device {
         write_length_little_endian(&mut stream, args.len()).await?;
         stream.write_all(args).await?;
 
-        // Use the maximum 64K buffer to transfer the file contents.
-        let mut buf = vec![0; 64 * 1024];
+        // File contents are handed to the caller straight from the read
+        // buffer, which is taken from the pool and holds several DATA chunks.
+        let mut buf = PooledBuffer::empty(SYNC_PULL_BUFFER_SIZE);
+        let mut audit_hasher = self.audit.as_ref().map(|_| HashAlgorithm::Sha256.hasher());
         let mut transferred = 0u64;
         let mut last_progress = 0u64;
-        // Progress interval: ~100 steps, clamped [256KiB, 4MiB]
-        let interval: u64 = if let Some(total) = total_bytes {
-            let base = (total / 100).max(256 * 1024);
-            base.min(4 * 1024 * 1024)
-        } else {
-            1024 * 1024
-        };
+        let interval = self.progress_granularity.interval(total_bytes);
 
         // Read "DATA" command one or more times for the file content
         loop {
-            stream.read_exact(&mut buf[0..4]).await?;
+            fill_buffer(&mut stream, &mut buf, 4).await?;
+            let command = buf.split_to(4);
 
-            if &buf[0..4] == SyncCommand::Data.code() {
-                let mut len = read_length_little_endian(&mut stream).await?;
-                // Read exactly `len` bytes, chunked if larger than our buffer
+            if &command[..] == SyncCommand::Data.code() {
+                fill_buffer(&mut stream, &mut buf, 4).await?;
+                let mut len = buf.get_u32_le() as usize;
+                // Pass on exactly `len` bytes, as far as they are buffered
                 while len > 0 {
+                    fill_buffer(&mut stream, &mut buf, 1).await?;
                     let take = len.min(buf.len());
-                    stream.read_exact(&mut buf[0..take]).await?;
-                    buffer.write_all(&buf[0..take]).await?;
+                    let chunk = &buf[..take];
+                    buffer.write_all(chunk).await?;
+                    if let Some(hasher) = &mut state.hasher {
+                        hasher.update(chunk);
+                    }
+                    if let Some(hasher) = &mut audit_hasher {
+                        hasher.update(chunk);
+                    }
+                    if state.sparse {
+                        state.sparse_bytes += sparse::sparse_bytes(chunk, transferred);
+                    }
+                    buf.advance(take);
                     transferred += take as u64;
                     len -= take;
 
                     // Throttled progress updates
-                    if let Some(sender) = &progress_sender {
+                    if let Some(progress) = progress {
                         if transferred - last_progress >= interval {
-                            let _ = sender.send(FileTransferProgress {
-                                total_bytes: total_bytes.unwrap_or(0),
+                            progress.report(FileTransferProgress {
+                                total_bytes,
                                 transferred_bytes: transferred,
+                                sparse_bytes: state.sparse_bytes,
                             });
                             last_progress = transferred;
                         }
                     }
                 }
-            } else if &buf[0..4] == SyncCommand::Done.code() {
-                // "DONE" command indicates end of file transfer
-                if let Some(sender) = &progress_sender {
-                    let _ = sender.send(FileTransferProgress {
-                        total_bytes: total_bytes.unwrap_or(0),
+            } else if &command[..] == SyncCommand::Done.code() {
+                // "DONE" command indicates end of file transfer; its length
+                // is unused but must be read before the next request.
+                fill_buffer(&mut stream, &mut buf, 4).await?;
+                if let Some(progress) = progress {
+                    progress.report(FileTransferProgress {
+                        total_bytes,
                         transferred_bytes: transferred,
+                        sparse_bytes: state.sparse_bytes,
                     });
                 }
                 break;
-            } else if &buf[0..4] == SyncCommand::Fail.code() {
-                let n = buf.len().min(read_length_little_endian(&mut stream).await?);
-
-                stream.read_exact(&mut buf[0..n]).await?;
+            } else if &command[..] == SyncCommand::Fail.code() {
+                fill_buffer(&mut stream, &mut buf, 4).await?;
+                let n = (buf.get_u32_le() as usize).min(64 * 1024);
+                fill_buffer(&mut stream, &mut buf, n).await?;
 
-                let message = std::str::from_utf8(&buf[0..n])
+                let message = std::str::from_utf8(&buf[..n])
                     .map(|s| format!("adb error: {s}"))
                     .unwrap_or_else(|_| "adb error was not utf-8".into());
 
@@ -1084,24 +2072,71 @@ impl Device {
             }
         }
 
+        state.elapsed = started.elapsed();
+        if let Some(connections) = &state.connections {
+            connections.put(stream);
+        }
+        self.audit_transfer(TransferDirection::Pull, src, transferred, audit_hasher)?;
+        Ok(transferred)
+    }
+
+    /// Records a completed transfer in the [`Device::audit`] log.
+    fn audit_transfer(
+        &self,
+        direction: TransferDirection,
+        path: &UnixPath,
+        bytes: u64,
+        hasher: Option<Hasher>,
+    ) -> Result<()> {
+        if let (Some(audit), Some(hasher)) = (&self.audit, hasher) {
+            audit.transfer(&self.serial, direction, path, bytes, &hasher.finish())?;
+        }
         Ok(())
     }
 
+    /// Pulls the directory `src` into `dest_dir`, transferring up to
+    /// [`Device::pull_concurrency`] files at once.
     pub async fn pull_dir(&self, src: &UnixPath, dest_dir: &Path) -> Result<()> {
-        self.pull_dir_internal(src, dest_dir, None).await
+        self.pull_dir_internal(src, dest_dir, None, &TransferOptions::default())
+            .await
+            .and(Ok(()))
     }
 
-    async fn pull_dir_internal(
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(serial = %self.serial, src = %src.display(), files = tracing::field::Empty, bytes = tracing::field::Empty),
+            err
+        )
+    )]
+    pub(crate) async fn pull_dir_internal(
         &self,
         src: &UnixPath,
         dest_dir: &Path,
-        progress_sender: Option<UnboundedSender<DirectoryTransferProgress>>,
-    ) -> Result<()> {
-        let src = src.to_path_buf();
-        let dest_dir = dest_dir.to_path_buf();
+        progress: Option<&dyn ProgressSink<DirectoryTransferProgress>>,
+        options: &TransferOptions,
+    ) -> Result<TransferReport> {
+        if options.skip_totals {
+            let entries = self.list_dir_stream(src);
+            return self
+                .pull_entry_stream(src, entries, None, dest_dir, progress, options)
+                .await;
+        }
+        let entries = self.list_dir(src).await?;
+        self.pull_entries(src, entries, dest_dir, progress, options)
+            .await
+    }
 
-        // Gather entries once
-        let entries = self.list_dir(&src).await?;
+    /// Pulls the given entries of a listing of `src` into `dest_dir`.
+    pub(crate) async fn pull_entries(
+        &self,
+        src: &UnixPath,
+        entries: Vec<FileMetadata>,
+        dest_dir: &Path,
+        progress: Option<&dyn ProgressSink<DirectoryTransferProgress>>,
+        options: &TransferOptions,
+    ) -> Result<TransferReport> {
         // Compute totals
         let mut total_files = 0usize;
         let mut total_bytes = 0u64;
@@ -1112,9 +2147,38 @@ impl Device {
             }
         }
 
+        let entries = futures_util::stream::iter(entries.into_iter().map(Ok));
+        self.pull_entry_stream(
+            src,
+            entries,
+            Some((total_files, total_bytes)),
+            dest_dir,
+            progress,
+            options,
+        )
+        .await
+    }
+
+    /// Pulls the entries of a listing of `src` into `dest_dir` as they
+    /// arrive.  Progress reports zero totals unless `totals` gives the
+    /// number of files and bytes.
+    async fn pull_entry_stream<S: Stream<Item = Result<FileMetadata>>>(
+        &self,
+        src: &UnixPath,
+        entries: S,
+        totals: Option<(usize, u64)>,
+        dest_dir: &Path,
+        progress: Option<&dyn ProgressSink<DirectoryTransferProgress>>,
+        options: &TransferOptions,
+    ) -> Result<TransferReport> {
+        let src = src.to_path_buf();
+        let dest_dir = dest_dir.to_path_buf();
+        let started = Instant::now();
+        let (total_files, total_bytes) = totals.unwrap_or_default();
+
         // Send initial progress if progress reporting is enabled
-        if let Some(sender) = &progress_sender {
-            let _ = sender.send(DirectoryTransferProgress {
+        if let Some(progress) = progress {
+            progress.report(DirectoryTransferProgress {
                 directory_name: Some(src.display().to_string()),
                 total_files,
                 transferred_files: 0,
@@ -1122,113 +2186,142 @@ impl Device {
                 transferred_bytes: 0,
                 current_file: None,
                 current_file_progress: FileTransferProgress {
-                    total_bytes: 0,
+                    total_bytes: Some(0),
                     transferred_bytes: 0,
+                    sparse_bytes: 0,
                 },
             });
         }
 
-        let mut transferred_files = 0usize;
-        let mut transferred_bytes = 0u64;
-
-        for entry in entries {
-            match entry.file_mode {
-                UnixFileStatus::SymbolicLink => {} // Ignored
-                UnixFileStatus::Directory => {
-                    let mut d = dest_dir.clone();
-                    d.push(&entry.path);
-                    std::fs::create_dir_all(&d)?;
+        // Create directories as they are listed and pull files concurrently.
+        let mut dirs = Vec::new();
+        let mut links = Vec::new();
+        let files = entries.try_filter_map(|entry| {
+            let mut d = dest_dir.clone();
+            d.push(&entry.path);
+            let file = match entry.file_mode {
+                UnixFileStatus::SymbolicLink if options.symlinks == SymlinkMode::Recreate => {
+                    links.push((src.join(&entry.path), d));
+                    None
                 }
-                UnixFileStatus::RegularFile => {
-                    let mut s = src.clone();
-                    s.push(&entry.path);
-                    let mut d = dest_dir.clone();
-                    d.push(&entry.path);
-
-                    let file_size = entry.size as u64;
-
-                    // Create a channel for file progress if directory progress is enabled
-                    let (file_sender, mut file_receiver): (
-                        Option<UnboundedSender<FileTransferProgress>>,
-                        Option<UnboundedReceiver<FileTransferProgress>>,
-                    ) = progress_sender
-                        .as_ref()
-                        .map(|_| tokio::sync::mpsc::unbounded_channel())
-                        .map(|(s, r)| (Some(s), Some(r)))
-                        .unwrap_or((None, None));
-
-                    // Send directory progress with current file
-                    if let Some(sender) = &progress_sender {
-                        let current_file_path = d.display().to_string();
-                        let _ = sender.send(DirectoryTransferProgress {
-                            directory_name: None,
-                            total_files,
-                            transferred_files,
-                            total_bytes,
-                            transferred_bytes,
-                            current_file: Some(current_file_path.clone()),
-                            current_file_progress: FileTransferProgress {
-                                total_bytes: file_size,
-                                transferred_bytes: 0,
-                            },
-                        });
-
-                        // Spawn a task to handle file progress updates if progress reporting is enabled
-                        if let Some(mut receiver) = file_receiver.take() {
-                            let sender = sender.clone();
-                            let current_file_path_clone = current_file_path.clone();
-                            tokio::spawn(async move {
-                                while let Some(file_progress) = receiver.recv().await {
-                                    let _ = sender.send(DirectoryTransferProgress {
-                                        directory_name: None,
-                                        total_files,
-                                        transferred_files,
-                                        total_bytes,
-                                        transferred_bytes: transferred_bytes
-                                            + file_progress.transferred_bytes,
-                                        current_file: Some(current_file_path_clone.clone()),
-                                        current_file_progress: file_progress,
-                                    });
-                                }
-                            });
+                UnixFileStatus::SymbolicLink => None, // Skipped
+                UnixFileStatus::Directory => {
+                    if !options.dry_run {
+                        if let Err(e) = std::fs::create_dir_all(&d) {
+                            return futures_util::future::ready(Err(e.into()));
                         }
                     }
+                    dirs.push((d, entry.modified_time));
+                    None
+                }
+                UnixFileStatus::RegularFile => Some((
+                    src.join(&entry.path),
+                    d,
+                    entry.size as u64,
+                    entry.modified_time,
+                )),
+                _ => None,
+            };
+            futures_util::future::ready(Ok(file))
+        });
 
-                    // Pull file with progress if enabled
-                    self.pull_internal(
-                        &s,
-                        &mut File::create(&d).await?,
-                        Some(file_size),
-                        file_sender,
-                    )
-                    .await?;
+        let aggregate = ConcurrentDirectoryProgress::new(progress, total_files, total_bytes);
+        let aggregate = &aggregate;
+        // Each of the concurrent pulls reuses the sync connection of an
+        // earlier one, if any is idle.
+        let connections = &Arc::new(SyncConnections::default());
+        let mut pulled: Vec<(usize, TransferredFile)> = if options.dry_run {
+            files
+                .map_ok(|(s, d, file_size, _)| TransferredFile {
+                    device_path: s,
+                    host_path: Some(d),
+                    bytes: file_size,
+                    sparse_bytes: 0,
+                    digest: None,
+                    elapsed: StdDuration::ZERO,
+                })
+                .enumerate()
+                .map(|(index, file)| file.map(|file| (index, file)))
+                .try_collect()
+                .await?
+        } else {
+            files
+                .enumerate()
+                .map(|(index, file)| async move {
+                    let (s, d, file_size, mtime) = file?;
+                    let file_sink = aggregate.file(index, d.display().to_string());
+
+                    // Entries may come without their parent directories.
+                    if let Some(parent) = d.parent() {
+                        tokio::fs::create_dir_all(parent).await?;
+                    }
+                    let mut state = options.state();
+                    state.mtime = mtime;
+                    state.connections = Some(connections.clone());
+                    let pulled = self
+                        .pull_file(
+                            &s,
+                            &d,
+                            Some(file_size),
+                            aggregate
+                                .enabled()
+                                .then_some(&file_sink as &dyn ProgressSink<FileTransferProgress>),
+                            options,
+                            state,
+                        )
+                        .await?;
+
+                    aggregate.finish(index, file_size, pulled.sparse_bytes);
+                    Ok::<_, DeviceError>((index, pulled))
+                })
+                .buffer_unordered(self.pull_concurrency.max(1))
+                .try_collect()
+                .await?
+        };
+        pulled.sort_by_key(|(index, _)| *index);
 
-                    transferred_files += 1;
-                    transferred_bytes += file_size;
-
-                    // Emit post-file progress update
-                    if let Some(sender) = &progress_sender {
-                        let _ = sender.send(DirectoryTransferProgress {
-                            directory_name: None,
-                            total_files,
-                            transferred_files,
-                            total_bytes,
-                            transferred_bytes,
-                            current_file: None,
-                            current_file_progress: FileTransferProgress {
-                                total_bytes: file_size,
-                                transferred_bytes: file_size,
-                            },
-                        });
+        let mut symlinks = Vec::with_capacity(links.len());
+        for (s, d) in links {
+            let target = self.read_link(&s).await?;
+            let created = if options.dry_run {
+                false
+            } else {
+                match create_host_symlink(&target, &d) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        warn!("Failed to create symlink {}: {}", d.display(), e);
+                        false
+                    }
+                }
+            };
+            symlinks.push(PulledSymlink {
+                device_path: s,
+                host_path: d,
+                target,
+                created,
+            });
+        }
+
+        // Writing the files touched their directories, so restore those last,
+        // children before parents.
+        if options.preserve_times && !options.dry_run {
+            for (d, mtime) in dirs.iter().rev() {
+                if let Some(mtime) = mtime {
+                    if let Err(e) = set_directory_mtime(d, *mtime) {
+                        warn!("Failed to set the time of {}: {}", d.display(), e);
                     }
                 }
-                _ => {}
             }
         }
 
+        let (transferred_files, transferred_bytes) = aggregate.transferred();
+
+        record_span!("files", transferred_files);
+        record_span!("bytes", transferred_bytes);
+
         // Final summary
-        if let Some(sender) = &progress_sender {
-            let _ = sender.send(DirectoryTransferProgress {
+        if let Some(progress) = progress {
+            progress.report(DirectoryTransferProgress {
                 directory_name: None,
                 total_files,
                 transferred_files,
@@ -1236,13 +2329,21 @@ impl Device {
                 transferred_bytes,
                 current_file: None,
                 current_file_progress: FileTransferProgress {
-                    total_bytes: 0,
+                    total_bytes: Some(0),
                     transferred_bytes: 0,
+                    sparse_bytes: 0,
                 },
             });
         }
 
-        Ok(())
+        Ok(TransferReport {
+            files: pulled.into_iter().map(|(_, file)| file).collect(),
+            symlinks,
+            elapsed: match options.dry_run {
+                true => StdDuration::ZERO,
+                false => started.elapsed(),
+            },
+        })
     }
 
     pub async fn push<R: AsyncRead + Unpin>(
@@ -1251,29 +2352,60 @@ impl Device {
         dest: &UnixPath,
         mode: u32,
     ) -> Result<()> {
-        self.push_internal(buffer, dest, mode, None, None).await
+        self.push_internal(
+            buffer,
+            dest,
+            mode,
+            None,
+            None,
+            &mut TransferState::default(),
+        )
+        .await
+        .and(Ok(()))
     }
 
+    /// Pushes like [`Device::push`] and reports the progress to `progress`.
+    ///
+    /// `total_bytes` may be `None` for readers of unknown length, such as a
+    /// tar stream generated on the fly; the reports then only carry the
+    /// bytes transferred so far.
     pub async fn push_with_progress<R: AsyncRead + Unpin>(
         &self,
         buffer: &mut R,
         dest: &UnixPath,
         mode: u32,
-        total_bytes: u64,
-        progress_sender: UnboundedSender<FileTransferProgress>,
+        total_bytes: impl Into<Option<u64>>,
+        progress: impl ProgressSink<FileTransferProgress>,
     ) -> Result<()> {
-        self.push_internal(buffer, dest, mode, Some(total_bytes), Some(progress_sender))
-            .await
+        self.push_internal(
+            buffer,
+            dest,
+            mode,
+            total_bytes.into(),
+            Some(&progress),
+            &mut TransferState::default(),
+        )
+        .await
+        .and(Ok(()))
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(serial = %self.serial, dest = %dest.display(), bytes = tracing::field::Empty),
+            err
+        )
+    )]
     async fn push_internal<R: AsyncRead + Unpin>(
         &self,
         buffer: &mut R,
         dest: &UnixPath,
         mode: u32,
         total_bytes: Option<u64>,
-        progress_sender: Option<UnboundedSender<FileTransferProgress>>,
-    ) -> Result<()> {
+        progress: Option<&dyn ProgressSink<FileTransferProgress>>,
+        state: &mut TransferState,
+    ) -> Result<u64> {
         // Implement the ADB protocol to send a file to the device.
         // The protocol consists of the following steps:
         // * Send "host:transport" command with device serial
@@ -1281,68 +2413,72 @@ impl Device {
         // * Send "SEND" command with name and mode of the file
         // * Send "DATA" command one or more times for the file content
         // * Send "DONE" command to indicate end of file transfer
-        if let (Some(total), Some(sender)) = (total_bytes, &progress_sender) {
-            let _ = sender.send(FileTransferProgress {
-                total_bytes: total,
+        self.check_writable("push files")?;
+        if let Some(progress) = progress {
+            progress.report(FileTransferProgress {
+                total_bytes,
                 transferred_bytes: 0,
+                sparse_bytes: 0,
             });
         }
-
-        let enable_run_as = self.enable_run_as_for_path(&dest.to_path_buf());
-        let dest1 = match enable_run_as {
-            true => self.tempfile.as_path(),
-            false => UnixPath::new(dest),
+        let started = Instant::now();
+
+        let enable_run_as = self.enable_run_as_for_path(dest);
+        // A file of its own per push, so concurrent pushes do not collide.
+        let temp = match enable_run_as {
+            true => Some(DeviceTempPath::new(
+                self.clone(),
+                self.temp_path("push.")?,
+                false,
+            )),
+            false => None,
+        };
+        let dest1 = temp.as_ref().map_or(dest, |temp| temp.path());
+        // Use cp -a to preserve the permissions set by push.
+        let copy_command = match enable_run_as {
+            true => Some(format!(
+                "cp -aR {} {}",
+                DevicePath::new(dest1)?.quoted(),
+                DevicePath::new(dest)?.quoted()
+            )),
+            false => None,
         };
 
         // If the destination directory does not exist, adb will
-        // create it and any necessary ancestors however it will not
-        // set the directory permissions to 0o777.  In addition,
-        // Android 9 (P) has a bug in its push implementation which
-        // will cause a push which creates directories to fail with
-        // the error `secure_mkdirs failed: Operation not
-        // permitted`. We can work around this by creating the
-        // destination directories prior to the push.  Collect the
-        // ancestors of the destination directory which do not yet
-        // exist so we can create them and adjust their permissions
-        // prior to performing the push.
-        let mut current = dest.parent();
-        let mut leaf: Option<&UnixPath> = None;
-        let mut root: Option<&UnixPath> = None;
-
-        while let Some(path) = current {
-            if self.path_exists(path, enable_run_as).await? {
-                break;
-            }
-            if leaf.is_none() {
-                leaf = Some(path);
-            }
-            root = Some(path);
-            current = path.parent();
-        }
-
-        if let Some(path) = leaf {
-            self.create_dir(path).await?;
-        }
-
-        if let Some(path) = root {
-            // On Android 9 (P) and earlier we adjust permissions to avoid
-            // secure_mkdirs issues. On Android 10+ chmod -R may not be permitted
-            // on some storage backends (e.g. sdcard), leading to push failures.
-            let android_version: u32 = (self.get_android_version().await).unwrap_or_default();
-            if android_version < 10 {
-                self.chmod(path, "777", true).await?;
+        // create it and any necessary ancestors, but Android 9 (P) has
+        // a bug in its push implementation which will cause a push
+        // which creates directories to fail with the error
+        // `secure_mkdirs failed: Operation not permitted`.  We can work
+        // around this by creating the destination directories prior to
+        // the push.  Up to Android 9 they are created with an explicit
+        // mode rather than the shell's umask; on Android 10+ setting the
+        // mode may not be permitted on some storage backends (e.g.
+        // sdcard), leading to push failures.
+        if let Some(parent) = dest.parent() {
+            if !self.path_exists(parent, enable_run_as).await? {
+                let android_version: u32 = (self.get_android_version().await).unwrap_or_default();
+                let options = match android_version < 10 {
+                    true => CreateDirOptions::new().mode(PUSH_DIR_MODE),
+                    false => CreateDirOptions::new(),
+                };
+                // The sync push below runs as the shell user, which cannot
+                // write to directories `su` would create as root.
+                let shell = match self.su {
+                    SuStrategy::None => None,
+                    _ => Some(Device {
+                        su: SuStrategy::None,
+                        ..self.clone()
+                    }),
+                };
+                shell
+                    .as_ref()
+                    .unwrap_or(self)
+                    .create_dir_with(parent, &options)
+                    .await?;
             }
         }
 
-        let mut stream = self.host.connect().await?;
-
-        let message = encode_message(&format!("host:transport:{}", self.serial))?;
-        stream.write_all(message.as_bytes()).await?;
-        let _bytes = read_response(&mut stream, false, true).await?;
-
-        let message = encode_message("sync:")?;
-        stream.write_all(message.as_bytes()).await?;
-        let _bytes = read_response(&mut stream, false, true).await?;
+        let mut stream = self.take_sync(state).await?;
 
         stream.write_all(SyncCommand::Send.code()).await?;
         let args_ = format!("{},{}", dest1.display(), mode);
@@ -1350,85 +2486,103 @@ impl Device {
         write_length_little_endian(&mut stream, args.len()).await?;
         stream.write_all(args).await?;
 
-        // Use a 32K buffer to transfer the file contents
-        // TODO: Maybe adjust to maxdata (256K)
-        let mut buf = vec![0; 32 * 1024];
+        // DATA chunks are limited to 64K.  The next chunk is read from the
+        // source while the current one is written, so that neither side
+        // waits for the other.
+        let mut buf = PooledBuffer::filled(64 * 1024);
+        let mut next = PooledBuffer::filled(64 * 1024);
+        let mut audit_hasher = self.audit.as_ref().map(|_| HashAlgorithm::Sha256.hasher());
         let mut transferred = 0u64;
         let mut last_progress = 0u64;
-        // Progress interval: ~100 steps, clamped [256KiB, 4MiB]
-        let interval: u64 = if let Some(total) = total_bytes {
-            let base = (total / 100).max(256 * 1024);
-            base.min(4 * 1024 * 1024)
-        } else {
-            1024 * 1024
-        };
+        let interval = self.progress_granularity.interval(total_bytes);
 
-        loop {
-            let len = buffer.read(&mut buf).await?;
-            if len == 0 {
-                // We're done, send the final progress update
-                if let Some(sender) = &progress_sender {
-                    let _ = sender.send(FileTransferProgress {
-                        total_bytes: total_bytes.unwrap_or(0),
-                        transferred_bytes: transferred,
-                    });
-                }
-                break;
+        let mut len = buffer.read(&mut buf).await?;
+        while len > 0 {
+            let chunk = &buf[0..len];
+            if let Some(hasher) = &mut state.hasher {
+                hasher.update(chunk);
+            }
+            if let Some(hasher) = &mut audit_hasher {
+                hasher.update(chunk);
             }
 
-            stream.write_all(SyncCommand::Data.code()).await?;
-            write_length_little_endian(&mut stream, len).await?;
-            stream.write_all(&buf[0..len]).await?;
+            let write = async {
+                let mut header = [0; 8];
+                header[..4].copy_from_slice(SyncCommand::Data.code());
+                header[4..].copy_from_slice(&(len as u32).to_le_bytes());
+                stream.write_all(&header).await?;
+                stream.write_all(chunk).await
+            };
+            let (_, next_len) =
+                futures_util::future::try_join(write, buffer.read(&mut next)).await?;
 
             transferred += len as u64;
+            std::mem::swap(&mut buf, &mut next);
+            len = next_len;
 
             // Throttled progress updates
-            if let Some(sender) = &progress_sender {
+            if let Some(progress) = progress {
                 if transferred - last_progress >= interval {
-                    let _ = sender.send(FileTransferProgress {
-                        total_bytes: total_bytes.unwrap_or(0),
+                    progress.report(FileTransferProgress {
+                        total_bytes,
                         transferred_bytes: transferred,
+                        sparse_bytes: 0,
                     });
                     last_progress = transferred;
                 }
             }
         }
 
+        // We're done, send the final progress update
+        if let Some(progress) = progress {
+            progress.report(FileTransferProgress {
+                total_bytes,
+                transferred_bytes: transferred,
+                sparse_bytes: 0,
+            });
+        }
+
         // https://android.googlesource.com/platform/system/core/+/master/adb/SYNC.TXT#66
         //
         // When the file is transferred a sync request "DONE" is sent, where length is set
         // to the last modified time for the file. The server responds to this last
         // request (but not to chunk requests) with an "OKAY" sync response (length can
         // be ignored).
-        let time: u32 = ((SystemTime::now().duration_since(SystemTime::UNIX_EPOCH))
-            .unwrap()
-            .as_secs()
+        let time: u32 = ((state
+            .mtime
+            .unwrap_or_else(SystemTime::now)
+            .duration_since(SystemTime::UNIX_EPOCH))
+        .unwrap_or_default()
+        .as_secs()
             & 0xFFFF_FFFF) as u32;
 
         stream.write_all(SyncCommand::Done.code()).await?;
         write_length_little_endian(&mut stream, time as usize).await?;
+        record_span!("bytes", transferred);
 
         // Status.
         stream.read_exact(&mut buf[0..4]).await?;
 
         if buf.starts_with(SyncCommand::Okay.code()) {
-            if enable_run_as {
-                // Use cp -a to preserve the permissions set by push.
+            state.elapsed = started.elapsed();
+            if let Some(connections) = &state.connections {
+                read_length_little_endian(&mut stream).await?;
+                connections.put(stream);
+            }
+            if let Some(copy_command) = copy_command {
                 let result = self
-                    .execute_host_shell_command_as(
-                        format!("cp -aR {} {}", dest1.display(), dest.display()).as_str(),
-                        enable_run_as,
-                    )
+                    .execute_host_shell_command_as(&copy_command, enable_run_as)
                     .await;
-                if self.remove(dest1).await.is_err() {
-                    warn!("Failed to remove {}", dest1.display());
+                if let Some(temp) = temp {
+                    temp.discard().await;
                 }
                 result?;
             }
-            Ok(())
+            self.audit_transfer(TransferDirection::Push, dest, transferred, audit_hasher)?;
+            Ok(transferred)
         } else if buf.starts_with(SyncCommand::Fail.code()) {
-            if enable_run_as && self.remove(dest1).await.is_err() {
-                warn!("Failed to remove {}", dest1.display());
+            if let Some(temp) = temp {
+                temp.discard().await;
             }
             let n = buf.len().min(read_length_little_endian(&mut stream).await?);
 
@@ -1440,41 +2594,89 @@ impl Device {
 
             Err(DeviceError::Adb(message))
         } else {
-            if self.remove(dest1).await.is_err() {
-                warn!("Failed to remove {}", dest1.display());
+            match temp {
+                Some(temp) => temp.discard().await,
+                None => {
+                    if self.remove_file(dest).await.is_err() {
+                        warn!("Failed to remove {}", dest.display());
+                    }
+                }
             }
             Err(DeviceError::Adb("FAIL (unknown)".to_owned()))
         }
     }
 
     pub async fn push_dir(&self, source: &Path, dest_dir: &UnixPath, mode: u32) -> Result<()> {
-        self.push_dir_internal(source, dest_dir, mode, None).await
+        self.push_dir_internal(source, dest_dir, mode, None, &TransferOptions::default())
+            .await
+            .and(Ok(()))
     }
 
-    async fn push_dir_internal(
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(serial = %self.serial, dest = %dest_dir.display(), files = tracing::field::Empty, bytes = tracing::field::Empty),
+            err
+        )
+    )]
+    pub(crate) async fn push_dir_internal(
         &self,
         source: &Path,
         dest_dir: &UnixPath,
         mode: u32,
-        progress_sender: Option<UnboundedSender<DirectoryTransferProgress>>,
-    ) -> Result<()> {
+        progress: Option<&dyn ProgressSink<DirectoryTransferProgress>>,
+        options: &TransferOptions,
+    ) -> Result<TransferReport> {
         debug!("Pushing {} to {}", source.display(), dest_dir.display());
+        let started = Instant::now();
 
         // Collect file entries once
-        let mut files: Vec<(std::path::PathBuf, u64)> = Vec::new();
+        let mut files: Vec<(std::path::PathBuf, u64, Option<SystemTime>)> = Vec::new();
         for entry in WalkDir::new(source).follow_links(false) {
             let entry = entry?;
             let metadata = entry.metadata()?;
             if metadata.is_file() {
-                files.push((entry.path().to_path_buf(), metadata.len()));
+                let mtime = match options.preserve_times {
+                    true => Some(metadata.modified()?),
+                    false => None,
+                };
+                files.push((entry.path().to_path_buf(), metadata.len(), mtime));
             }
         }
         let total_files = files.len();
-        let total_bytes: u64 = files.iter().map(|(_, sz)| *sz).sum();
+        let total_bytes: u64 = files.iter().map(|(_, sz, _)| *sz).sum();
+
+        if options.dry_run {
+            let files = files
+                .into_iter()
+                .map(|(path, file_size, _)| {
+                    let tail = path
+                        .strip_prefix(source)
+                        .map_err(|e| io::Error::other(e.to_string()))?;
+                    Ok(TransferredFile {
+                        device_path: append_components(dest_dir, tail)?,
+                        host_path: Some(path),
+                        bytes: file_size,
+                        sparse_bytes: 0,
+                        digest: None,
+                        elapsed: StdDuration::ZERO,
+                    })
+                })
+                .collect::<Result<_>>()?;
+            return Ok(TransferReport {
+                files,
+                symlinks: Vec::new(),
+                elapsed: StdDuration::ZERO,
+            });
+        }
+        self.check_writable("push files")?;
+        // The files are pushed one after another over one sync connection.
+        let connections = Arc::new(SyncConnections::default());
 
         // Send initial progress if progress reporting is enabled
-        if let Some(sender) = &progress_sender {
-            let _ = sender.send(DirectoryTransferProgress {
+        if let Some(progress) = progress {
+            progress.report(DirectoryTransferProgress {
                 directory_name: Some(dest_dir.display().to_string()),
                 total_files,
                 transferred_files: 0,
@@ -1482,16 +2684,18 @@ impl Device {
                 transferred_bytes: 0,
                 current_file: None,
                 current_file_progress: FileTransferProgress {
-                    total_bytes: 0,
+                    total_bytes: Some(0),
                     transferred_bytes: 0,
+                    sparse_bytes: 0,
                 },
             });
         }
 
         let mut transferred_files = 0usize;
         let mut transferred_bytes = 0u64;
+        let mut pushed = Vec::with_capacity(total_files);
 
-        for (path, file_size) in files {
+        for (path, file_size, mtime) in files {
             let mut file = BufReader::new(File::open(&path).await?);
 
             let tail = path
@@ -1500,63 +2704,58 @@ impl Device {
 
             let dest = append_components(dest_dir, tail)?;
 
-            // Create a channel for file progress if directory progress is enabled
-            let (file_sender, mut file_receiver): (
-                Option<UnboundedSender<FileTransferProgress>>,
-                Option<UnboundedReceiver<FileTransferProgress>>,
-            ) = progress_sender
-                .as_ref()
-                .map(|_| tokio::sync::mpsc::unbounded_channel())
-                .map(|(s, r)| (Some(s), Some(r)))
-                .unwrap_or((None, None));
-
-            // Send directory progress with current file
-            if let Some(sender) = &progress_sender {
-                let current_file_path = dest.display().to_string();
-                let _ = sender.send(DirectoryTransferProgress {
+            // Forward file progress as directory progress if enabled
+            let file_sink = progress.map(|sink| DirectoryFileSink {
+                sink,
+                base: DirectoryTransferProgress {
                     directory_name: None,
                     total_files,
                     transferred_files,
                     total_bytes,
                     transferred_bytes,
-                    current_file: Some(current_file_path.clone()),
+                    current_file: Some(dest.display().to_string()),
                     current_file_progress: FileTransferProgress {
-                        total_bytes: file_size,
+                        total_bytes: Some(file_size),
                         transferred_bytes: 0,
+                        sparse_bytes: 0,
                     },
-                });
-
-                // Spawn a task to handle file progress updates if progress reporting is enabled
-                if let Some(mut receiver) = file_receiver.take() {
-                    let sender = sender.clone();
-                    let current_file_path_clone = current_file_path.clone();
-                    tokio::spawn(async move {
-                        while let Some(file_progress) = receiver.recv().await {
-                            let _ = sender.send(DirectoryTransferProgress {
-                                directory_name: None,
-                                total_files,
-                                transferred_files,
-                                total_bytes,
-                                transferred_bytes: transferred_bytes
-                                    + file_progress.transferred_bytes,
-                                current_file: Some(current_file_path_clone.clone()),
-                                current_file_progress: file_progress,
-                            });
-                        }
-                    });
-                }
-            }
+                },
+            });
 
             // Push file with progress if enabled
-            self.push_internal(&mut file, &dest, mode, Some(file_size), file_sender)
+            let mut state = options.state();
+            state.mtime = mtime;
+            state.connections = Some(connections.clone());
+            let bytes = self
+                .push_internal(
+                    &mut file,
+                    &dest,
+                    mode,
+                    Some(file_size),
+                    file_sink
+                        .as_ref()
+                        .map(|sink| sink as &dyn ProgressSink<FileTransferProgress>),
+                    &mut state,
+                )
                 .await?;
 
+            let elapsed = state.elapsed;
+            let digest = self.verify_digest(&dest, options, state).await?;
+            pushed.push(TransferredFile {
+                device_path: dest,
+                host_path: Some(path),
+                bytes,
+                sparse_bytes: 0,
+                digest,
+                elapsed,
+            });
+
             transferred_files += 1;
             transferred_bytes += file_size;
 
             // Emit post-file progress update
-            if let Some(sender) = &progress_sender {
-                let _ = sender.send(DirectoryTransferProgress {
+            if let Some(progress) = progress {
+                progress.report(DirectoryTransferProgress {
                     directory_name: None,
                     total_files,
                     transferred_files,
@@ -1564,16 +2763,20 @@ impl Device {
                     transferred_bytes,
                     current_file: None,
                     current_file_progress: FileTransferProgress {
-                        total_bytes: file_size,
+                        total_bytes: Some(file_size),
                         transferred_bytes: file_size,
+                        sparse_bytes: 0,
                     },
                 });
             }
         }
 
+        record_span!("files", transferred_files);
+        record_span!("bytes", transferred_bytes);
+
         // Final summary
-        if let Some(sender) = &progress_sender {
-            let _ = sender.send(DirectoryTransferProgress {
+        if let Some(progress) = progress {
+            progress.report(DirectoryTransferProgress {
                 directory_name: None,
                 total_files,
                 transferred_files,
@@ -1581,13 +2784,18 @@ impl Device {
                 transferred_bytes,
                 current_file: None,
                 current_file_progress: FileTransferProgress {
-                    total_bytes: 0,
+                    total_bytes: Some(0),
                     transferred_bytes: 0,
+                    sparse_bytes: 0,
                 },
             });
         }
 
-        Ok(())
+        Ok(TransferReport {
+            files: pushed,
+            symlinks: Vec::new(),
+            elapsed: started.elapsed(),
+        })
     }
 
     pub async fn push_dir_with_progress(
@@ -1595,27 +2803,44 @@ impl Device {
         source: &Path,
         dest_dir: &UnixPath,
         mode: u32,
-        progress_sender: UnboundedSender<DirectoryTransferProgress>,
+        progress: impl ProgressSink<DirectoryTransferProgress>,
     ) -> Result<()> {
-        self.push_dir_internal(source, dest_dir, mode, Some(progress_sender))
-            .await
+        self.push_dir_internal(
+            source,
+            dest_dir,
+            mode,
+            Some(&progress),
+            &TransferOptions::default(),
+        )
+        .await
+        .and(Ok(()))
     }
 
     pub async fn pull_dir_with_progress(
         &self,
         src: &UnixPath,
         dest_dir: &Path,
-        progress_sender: UnboundedSender<DirectoryTransferProgress>,
+        progress: impl ProgressSink<DirectoryTransferProgress>,
     ) -> Result<()> {
-        self.pull_dir_internal(src, dest_dir, Some(progress_sender))
+        self.pull_dir_internal(src, dest_dir, Some(&progress), &TransferOptions::default())
             .await
+            .and(Ok(()))
     }
 
+    /// Removes `path` recursively with `rm -rf`, after checking it against
+    /// the guard of [`Device::remove_dir_all`].  Unlike that, failures
+    /// printed by `rm` are not reported.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial, path = %path.display()), err)
+    )]
     pub async fn remove(&self, path: &UnixPath) -> Result<()> {
+        self.check_writable("remove files")?;
+        self.check_removable(path)?;
         debug!("Deleting {}", path.display());
 
         self.execute_host_shell_command_as(
-            &format!("rm -rf {}", path.display()),
+            &format!("rm -rf {}", DevicePath::new(path)?.quoted()),
             self.enable_run_as_for_path(path),
         )
         .await?;
@@ -1639,108 +2864,51 @@ impl Device {
         Ok(())
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial, path = %path.display()), err)
+    )]
     pub async fn stat(&self, path: &UnixPath) -> Result<FileMetadata> {
-        // Implement the ADB protocol to get file statistics from the device
-        let mut stream = self.host.connect().await?;
-
-        // Send "host:transport" command with device serial
-        let message = encode_message(&format!("host:transport:{}", self.serial))?;
-        stream.write_all(message.as_bytes()).await?;
-        let _bytes = read_response(&mut stream, false, true).await?;
-
-        // Send "sync:" command to initialize file transfer
-        let message = encode_message("sync:")?;
-        stream.write_all(message.as_bytes()).await?;
-        let _bytes = read_response(&mut stream, false, true).await?;
-
-        // Send "STAT" command with path
-        stream.write_all(SyncCommand::Stat.code()).await?;
-        let args = format!("{}", path.display()).into_bytes();
-        write_length_little_endian(&mut stream, args.len()).await?;
-        stream.write_all(&args).await?;
-
-        // Read response
-        let mut response_code = [0u8; 4];
-        stream.read_exact(&mut response_code).await?;
-
-        if &response_code != SyncCommand::Stat.code() {
-            return Err(DeviceError::Adb(format!(
-                "Invalid response code: {:?}",
-                std::str::from_utf8(&response_code)
-            )));
-        }
-
-        // Read the 12 bytes containing mode (4), size (4), and time (4)
-        let mut stat_data = [0u8; 12];
-        stream.read_exact(&mut stat_data).await?;
-
-        // Parse the data
-        let mode = u32::from_le_bytes(stat_data[0..4].try_into().unwrap());
-        let size = u32::from_le_bytes(stat_data[4..8].try_into().unwrap());
-        let time = u32::from_le_bytes(stat_data[8..12].try_into().unwrap());
-
-        // Mode 0 indicates the remote path does not exist.
-        if mode == 0 {
-            return Err(DeviceError::Io(io::Error::new(
-                io::ErrorKind::NotFound,
-                format!("remote path not found: {}", path.display()),
-            )));
-        }
-
-        // Convert mode to UnixFileStatus
-        let file_mode = match mode & 0xF000 {
-            0x4000 => UnixFileStatus::Directory,
-            0x2000 => UnixFileStatus::CharacterDevice,
-            0x6000 => UnixFileStatus::BlockDevice,
-            0x8000 => UnixFileStatus::RegularFile,
-            0xA000 => UnixFileStatus::SymbolicLink,
-            0xC000 => UnixFileStatus::Socket,
-            _ => return Err(DeviceError::Adb(format!("Unknown file mode: {mode:#x}"))),
-        };
+        with_retry(self.retry.as_ref(), || self.stat_once(path)).await
+    }
 
-        Ok(FileMetadata {
-            path: path.display().to_string(),
-            file_mode,
-            size,
-            modified_time: if time == 0 {
-                None
-            } else {
-                Some(SystemTime::UNIX_EPOCH + StdDuration::from_secs(time as u64))
-            },
-            depth: None,
-        })
+    async fn stat_once(&self, path: &UnixPath) -> Result<FileMetadata> {
+        let mut stream = self.connect_sync_once().await?;
+        stat_over(&mut stream, path).await?
     }
 
-    pub async fn install_package(
+    /// Stats each of `paths` like [`Device::stat`], but over one sync
+    /// connection.  The outer error is a failure of the connection; missing
+    /// paths fail individually.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial, paths = paths.len()), err)
+    )]
+    pub async fn stat_many<P: AsRef<UnixPath>>(
         &self,
-        apk_path: &Path,
-        reinstall: bool,
-        grant_runtime_permissions: bool,
-        bypass_low_target_sdk_block: bool,
-    ) -> Result<()> {
-        let apk_path = apk_path.to_path_buf();
+        paths: &[P],
+    ) -> Result<Vec<Result<FileMetadata>>> {
+        let mut stream = self.connect_sync().await?;
+        let mut stats = Vec::with_capacity(paths.len());
+        for path in paths {
+            stats.push(stat_over(&mut stream, path.as_ref()).await?);
+        }
+        Ok(stats)
+    }
 
-        let base_name = apk_path
-            .file_name()
-            .ok_or(DeviceError::Adb("Invalid apk path".to_owned()))?
-            .to_str()
-            .ok_or(DeviceError::Adb("Invalid apk path".to_owned()))?;
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial, apk = %apk_path.display()), err)
+    )]
+    pub async fn install_package(&self, apk_path: &Path, options: &InstallOptions) -> Result<()> {
+        self.check_writable("install packages")?;
+        let apk_path = apk_path.to_path_buf();
+        let tmp_apk_path = staging_apk_path(&apk_path)?;
 
-        let tmp_apk_path = UnixPathBuf::from("/data/local/tmp").join(base_name);
         let mut file = BufReader::new(File::open(apk_path).await?);
         self.push(&mut file, &tmp_apk_path, 0o644).await?;
 
-        let mut command = "pm install".to_owned();
-        if reinstall {
-            command.push_str(" -r");
-        }
-        if grant_runtime_permissions {
-            command.push_str(" -g");
-        }
-        if bypass_low_target_sdk_block && self.get_android_version().await? >= 14 {
-            command.push_str(" --bypass-low-target-sdk-block");
-        }
-        command.push_str(&format!(" \"{}\"", tmp_apk_path.display()));
+        let command = self.install_command(&tmp_apk_path, options).await?;
         let output = self.execute_host_shell_command(&command).await?;
 
         self.execute_host_shell_command(format!("rm \"{}\"", tmp_apk_path.display()).as_str())
@@ -1753,66 +2921,38 @@ impl Device {
         Ok(())
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial, apk = %apk_path.display()), err)
+    )]
     pub async fn install_package_with_progress(
         &self,
         apk_path: &Path,
-        reinstall: bool,
-        grant_runtime_permissions: bool,
-        bypass_low_target_sdk_block: bool,
-        progress_sender: UnboundedSender<f32>,
+        options: &InstallOptions,
+        progress: impl ProgressSink<f32>,
     ) -> Result<()> {
+        self.check_writable("install packages")?;
         let apk_path = apk_path.to_path_buf();
-
-        let base_name = apk_path
-            .file_name()
-            .ok_or(DeviceError::Adb("Invalid apk path".to_owned()))?
-            .to_str()
-            .ok_or(DeviceError::Adb("Invalid apk path".to_owned()))?;
+        let tmp_apk_path = staging_apk_path(&apk_path)?;
 
         let file_metadata = std::fs::metadata(&apk_path)?;
         let file_size = file_metadata.len();
 
-        let (push_sender, mut push_receiver) =
-            tokio::sync::mpsc::unbounded_channel::<FileTransferProgress>();
-
-        tokio::spawn({
-            let progress_sender = progress_sender.clone();
-            async move {
-                while let Some(push_progress) = push_receiver.recv().await {
-                    // Map push progress to install progress (up to 90%)
-                    if file_size == 0 {
-                        let _ = progress_sender.send(0.9);
-                    } else {
-                        let frac = push_progress.transferred_bytes as f32 / file_size as f32;
-                        let prog = (frac * 0.9).clamp(0.0, 0.9);
-                        let _ = progress_sender.send(prog);
-                    }
-                }
+        // Map push progress to install progress (up to 90%)
+        let push_progress = |push_progress: FileTransferProgress| {
+            if file_size == 0 {
+                progress.report(0.9);
+            } else {
+                let frac = push_progress.transferred_bytes as f32 / file_size as f32;
+                progress.report((frac * 0.9).clamp(0.0, 0.9));
             }
-        });
+        };
 
-        let tmp_apk_path = UnixPathBuf::from("/data/local/tmp").join(base_name);
         let mut file = BufReader::new(File::open(&apk_path).await?);
-        self.push_with_progress(&mut file, &tmp_apk_path, 0o644, file_size, push_sender)
+        self.push_with_progress(&mut file, &tmp_apk_path, 0o644, file_size, push_progress)
             .await?;
 
-        // If the file was empty, ensure we nudged to 0.9; otherwise the spawned task
-        // will already have emitted final ~0.9 on completion.
-        if file_size == 0 {
-            let _ = progress_sender.send(0.9);
-        }
-
-        let mut command = "pm install".to_owned();
-        if reinstall {
-            command.push_str(" -r");
-        }
-        if grant_runtime_permissions {
-            command.push_str(" -g");
-        }
-        if bypass_low_target_sdk_block && self.get_android_version().await? >= 14 {
-            command.push_str(" --bypass-low-target-sdk-block");
-        }
-        command.push_str(&format!(" \"{}\"", tmp_apk_path.display()));
+        let command = self.install_command(&tmp_apk_path, options).await?;
         let output = self.execute_host_shell_command(&command).await?;
 
         self.execute_host_shell_command(format!("rm \"{}\"", tmp_apk_path.display()).as_str())
@@ -1822,46 +2962,56 @@ impl Device {
             return Err(DeviceError::PackageManagerError(output));
         }
 
-        let _ = progress_sender.send(1.0);
+        progress.report(1.0);
 
         Ok(())
     }
 
-    pub async fn uninstall_package(&self, package: &str) -> Result<()> {
-        let command = format!("pm uninstall {package}");
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self), fields(serial = %self.serial), err)
+    )]
+    pub async fn uninstall_package(&self, package: &str, options: &UninstallOptions) -> Result<()> {
+        self.check_writable("uninstall packages")?;
+        let capabilities = self.capabilities().await?;
+        let command = format!(
+            "{} uninstall{} {package}",
+            capabilities.package_manager(),
+            options.args(&capabilities, self.user)?
+        );
         let output = self.execute_host_shell_command(&command).await?;
         if !output.starts_with("Success") {
-            return Err(DeviceError::PackageManagerError(output));
+            return Err(DeviceError::UninstallFailed(
+                package.to_owned(),
+                UninstallFailure::parse(&output),
+            ));
         }
 
         Ok(())
     }
 
     pub async fn list_packages(&self, third_party: bool) -> Result<Vec<String>> {
-        let command = if third_party {
-            "pm list packages -3"
-        } else {
-            "pm list packages"
-        };
-        let output = self.execute_host_shell_command(command).await?;
-        let mut packages = output
-            .lines()
-            .filter(|line| line.starts_with("package:"))
-            .map(|line| {
-                Ok(line
-                    .split_once(':')
-                    .ok_or(DeviceError::Adb(
-                        "Failed to parse package list line".to_owned(),
-                    ))?
-                    .1
-                    .to_owned())
-            })
-            .collect::<Result<Vec<_>>>()?;
-        packages.sort();
-        Ok(packages)
+        let options = PackageListOptions::new().third_party(third_party);
+        Ok(self
+            .list_packages_with(&options)
+            .await?
+            .into_iter()
+            .map(|package| package.name)
+            .collect())
     }
 }
 
+/// Where `install_package` stages `apk_path` on the device.
+fn staging_apk_path(apk_path: &Path) -> Result<UnixPathBuf> {
+    let base_name = apk_path
+        .file_name()
+        .ok_or(DeviceError::Adb("Invalid apk path".to_owned()))?
+        .to_str()
+        .ok_or(DeviceError::Adb("Invalid apk path".to_owned()))?;
+
+    Ok(UnixPathBuf::from("/data/local/tmp").join(base_name))
+}
+
 pub(crate) fn append_components(
     base: &UnixPath,
     tail: &Path,
@@ -1884,8 +3034,12 @@ pub(crate) fn append_components(
 
 #[derive(Debug, Clone)]
 pub struct FileTransferProgress {
-    pub total_bytes: u64,
+    /// Size of the file; `None` for pushes from a reader of unknown length.
+    pub total_bytes: Option<u64>,
     pub transferred_bytes: u64,
+    /// Part of `transferred_bytes` that a sparse pull (see
+    /// [`TransferOptions::sparse`]) left as holes instead of writing it.
+    pub sparse_bytes: u64,
 }
 
 #[derive(Debug, Clone)]
@@ -0,0 +1,128 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Locale, input method and clock format settings, as recorded in device
+//! examination reports.
+
+use crate::{Device, Result, ShellOutput};
+
+/// Whether the device shows times in 12 or 24 hour format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockFormat {
+    Hour12,
+    Hour24,
+}
+
+/// Result of [`Device::locale_info`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LocaleInfo {
+    /// Locales in order of preference as language tags, e.g. `en-US`.
+    pub locales: Vec<String>,
+    /// Locale the build starts with, `ro.product.locale`.
+    pub default_locale: Option<String>,
+    /// Installed input methods by component, e.g.
+    /// `com.android.inputmethod.latin/.LatinIME`.
+    pub input_methods: Vec<String>,
+    /// Input methods the user enabled.
+    pub enabled_input_methods: Vec<String>,
+    /// Input method in use.
+    pub default_input_method: Option<String>,
+    /// `None` when the format follows the locale.
+    pub clock_format: Option<ClockFormat>,
+}
+
+const LOCALE_COMMANDS: [&str; 9] = [
+    "settings get system system_locales",
+    "getprop persist.sys.locale",
+    "getprop persist.sys.language",
+    "getprop persist.sys.country",
+    "getprop ro.product.locale",
+    "ime list -a -s",
+    "settings get secure enabled_input_methods",
+    "settings get secure default_input_method",
+    "settings get system time_12_24",
+];
+
+impl Device {
+    /// Collects the system locales, input methods and the 12/24 hour clock
+    /// setting in one shell round trip.
+    ///
+    /// The locale list comes from `system_locales` (Android 7.0+), falling
+    /// back to `persist.sys.locale` and then `persist.sys.language` and
+    /// `persist.sys.country` of older releases.  Settings a release does not
+    /// have are left empty rather than failing.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn locale_info(&self) -> Result<LocaleInfo> {
+        let outputs = self.run_batch(&LOCALE_COMMANDS).await?;
+        let value = |i: usize| setting(&outputs[i]);
+
+        let mut locales = value(0)
+            .map(|list| {
+                list.split(',')
+                    .map(|tag| tag.trim().to_owned())
+                    .filter(|tag| !tag.is_empty())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        if locales.is_empty() {
+            if let Some(locale) = value(1) {
+                locales.push(locale);
+            } else if let Some(language) = value(2) {
+                locales.push(match value(3) {
+                    Some(country) => format!("{language}-{country}"),
+                    None => language,
+                });
+            }
+        }
+
+        let input_methods = match outputs[5].success() {
+            true => outputs[5]
+                .stdout_lossy()
+                .lines()
+                .map(str::trim)
+                .filter(|line| line.contains('/'))
+                .map(str::to_owned)
+                .collect(),
+            false => Vec::new(),
+        };
+        // `id;subtype;subtype:id`, subtypes being numeric hashes.
+        let enabled_input_methods = value(6)
+            .map(|list| {
+                list.split(':')
+                    .filter_map(|entry| entry.split(';').next())
+                    .filter(|id| !id.is_empty())
+                    .map(str::to_owned)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(LocaleInfo {
+            locales,
+            default_locale: value(4),
+            input_methods,
+            enabled_input_methods,
+            default_input_method: value(7),
+            clock_format: match value(8).as_deref() {
+                Some("12") => Some(ClockFormat::Hour12),
+                Some("24") => Some(ClockFormat::Hour24),
+                _ => None,
+            },
+        })
+    }
+}
+
+/// Value printed by `getprop` or `settings get`, which print nothing and
+/// `null` for unset keys.
+pub(crate) fn setting(output: &ShellOutput) -> Option<String> {
+    if !output.success() {
+        return None;
+    }
+    match output.stdout_lossy().trim() {
+        "" | "null" => None,
+        value => Some(value.to_owned()),
+    }
+}
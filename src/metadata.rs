@@ -0,0 +1,232 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! File metadata beyond what the sync `STAT` request reports, parsed from
+//! `ls -ladZ`.
+
+use crate::copy::file_error;
+use crate::{Device, DeviceError, DevicePath, Result, UnixFileStatus, UnixPath, UnixPathBuf};
+
+/// Metadata of a device file as listed by `ls -ladZ`, see
+/// [`Device::stat_extended`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedMetadata {
+    pub path: String,
+    /// `None` for types [`UnixFileStatus`] has no variant for, e.g. FIFOs.
+    pub file_mode: Option<UnixFileStatus>,
+    /// The mode as `ls` prints it, e.g. `drwxrwx--x`.
+    pub permissions: String,
+    /// Hard link count; toolbox before Android 6.0 does not print it.
+    pub links: Option<u64>,
+    pub owner: String,
+    pub group: String,
+    /// SELinux context, e.g. `u:object_r:media_rw_data_file:s0`.
+    pub selinux_context: Option<String>,
+    /// Size in bytes; `None` for device nodes and where `ls -Z` leaves the
+    /// size out.
+    pub size: Option<u64>,
+    /// Target of a symbolic link.
+    pub link_target: Option<UnixPathBuf>,
+}
+
+impl Device {
+    /// Returns the permissions, owner and group names, SELinux context and
+    /// link target of `path`, which the sync protocol does not report even
+    /// with `stat_v2`.
+    ///
+    /// The output of `ls` is parsed, so this works on devices and adb
+    /// servers without `stat_v2` too.  Symbolic links are not followed.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial, path = %path.display()), err)
+    )]
+    pub async fn stat_extended(&self, path: &UnixPath) -> Result<ExtendedMetadata> {
+        let quoted = DevicePath::new(path)?.quoted();
+        let output = self
+            .execute_host_shell_command_as(
+                &format!("ls -ladZ {quoted}"),
+                self.enable_run_as_for_path(path),
+            )
+            .await?;
+
+        let line = output.lines().find(|line| !line.trim().is_empty());
+        match line {
+            Some(line) if line.starts_with("ls:") => Err(file_error(line)),
+            Some(line) => parse_ls_line(line, &path.display().to_string())
+                .ok_or_else(|| DeviceError::Adb(format!("Unexpected ls output: {}", line.trim()))),
+            None => Err(DeviceError::Adb(format!(
+                "ls printed nothing for {}",
+                path.display()
+            ))),
+        }
+    }
+}
+
+/// Splits off the next whitespace separated field of `rest`.
+fn next_field<'a>(rest: &mut &'a str) -> Option<&'a str> {
+    let trimmed = rest.trim_start();
+    if trimmed.is_empty() {
+        return None;
+    }
+    let end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+    let (field, remainder) = trimmed.split_at(end);
+    *rest = remainder;
+    Some(field)
+}
+
+fn is_number(field: &str) -> bool {
+    !field.is_empty() && field.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Parses one `ls -ladZ` line of `path`.
+///
+/// toybox prints `mode links owner group context size date time name`,
+/// toolbox before Android 6.0 only `mode owner group context name`.  Device
+/// nodes have `major, minor` in place of the size.
+fn parse_ls_line(line: &str, path: &str) -> Option<ExtendedMetadata> {
+    let mut rest = line.trim_end();
+
+    let permissions = next_field(&mut rest)?;
+    let file_mode = match permissions.chars().next()? {
+        '-' => Some(UnixFileStatus::RegularFile),
+        'd' => Some(UnixFileStatus::Directory),
+        'l' => Some(UnixFileStatus::SymbolicLink),
+        'c' => Some(UnixFileStatus::CharacterDevice),
+        'b' => Some(UnixFileStatus::BlockDevice),
+        's' => Some(UnixFileStatus::Socket),
+        'p' => None,
+        _ => return None,
+    };
+    if permissions.len() < 10 {
+        return None;
+    }
+
+    let mut field = next_field(&mut rest)?;
+    let links = match is_number(field) {
+        true => {
+            let links = field.parse().ok();
+            field = next_field(&mut rest)?;
+            links
+        }
+        false => None,
+    };
+    let owner = field;
+    let group = next_field(&mut rest)?;
+
+    // Contexts have four parts, `user:role:type:level`; `?` means none.
+    let mut selinux_context = None;
+    let before_context = rest;
+    match next_field(&mut rest) {
+        Some("?") => {}
+        Some(field) if field.matches(':').count() >= 3 => selinux_context = Some(field.to_owned()),
+        _ => rest = before_context,
+    }
+
+    // Size (or device numbers), date and time, unless the name follows.
+    let name = rest.trim_start();
+    let size = if name.starts_with(path) {
+        None
+    } else {
+        let field = next_field(&mut rest)?;
+        let size = match field.strip_suffix(',') {
+            Some(_) => {
+                next_field(&mut rest)?;
+                None
+            }
+            None => Some(field.parse().ok()?),
+        };
+        next_field(&mut rest)?;
+        next_field(&mut rest)?;
+        size
+    };
+
+    let name = rest.trim_start();
+    let link_target = name
+        .strip_prefix(path)
+        .and_then(|tail| tail.strip_prefix(" -> "))
+        .map(UnixPathBuf::from);
+
+    Some(ExtendedMetadata {
+        path: path.to_owned(),
+        file_mode,
+        permissions: permissions.to_owned(),
+        links,
+        owner: owner.to_owned(),
+        group: group.to_owned(),
+        selinux_context,
+        size,
+        link_target,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_toybox_lines() {
+        let file = parse_ls_line(
+            "-rw-rw---- 1 u0_a123 media_rw u:object_r:media_rw_data_file:s0 1234 2023-11-14 22:13 /sdcard/My File.txt",
+            "/sdcard/My File.txt",
+        )
+        .expect("file");
+        assert_eq!(file.file_mode, Some(UnixFileStatus::RegularFile));
+        assert_eq!(file.permissions, "-rw-rw----");
+        assert_eq!(file.links, Some(1));
+        assert_eq!(
+            (file.owner.as_str(), file.group.as_str()),
+            ("u0_a123", "media_rw")
+        );
+        assert_eq!(
+            file.selinux_context.as_deref(),
+            Some("u:object_r:media_rw_data_file:s0")
+        );
+        assert_eq!(file.size, Some(1234));
+        assert_eq!(file.link_target, None);
+
+        let link = parse_ls_line(
+            "lrw-r--r-- 1 root root u:object_r:rootfs:s0 21 2009-01-01 08:00 /sdcard -> /storage/self/primary",
+            "/sdcard",
+        )
+        .expect("link");
+        assert_eq!(link.file_mode, Some(UnixFileStatus::SymbolicLink));
+        assert_eq!(
+            link.link_target,
+            Some(UnixPathBuf::from("/storage/self/primary"))
+        );
+
+        let node = parse_ls_line(
+            "crw-rw-rw- 1 root root u:object_r:null_device:s0 1,   3 2023-11-14 08:00 /dev/null",
+            "/dev/null",
+        )
+        .expect("device node");
+        assert_eq!(node.file_mode, Some(UnixFileStatus::CharacterDevice));
+        assert_eq!(node.size, None);
+
+        let unlabeled = parse_ls_line(
+            "drwxr-xr-x 2 root root ? 4096 2023-11-14 08:00 /mnt/x",
+            "/mnt/x",
+        )
+        .expect("no context");
+        assert_eq!(unlabeled.selinux_context, None);
+    }
+
+    #[test]
+    fn parses_toolbox_lines() {
+        let file = parse_ls_line(
+            "-rw-rw---- root     sdcard_r u:object_r:fuse:s0 a.txt",
+            "a.txt",
+        )
+        .expect("file");
+        assert_eq!(file.links, None);
+        assert_eq!(
+            (file.owner.as_str(), file.group.as_str()),
+            ("root", "sdcard_r")
+        );
+        assert_eq!(file.selinux_context.as_deref(), Some("u:object_r:fuse:s0"));
+        assert_eq!(file.size, None);
+
+        assert_eq!(parse_ls_line("total 0", "a.txt"), None);
+    }
+}
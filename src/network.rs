@@ -0,0 +1,539 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Network interface configuration, routes and neighbors from `ip`, for
+//! device examination reports.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::{Device, DeviceError, Result, ShellOutput};
+
+/// Operational state of a [`NetworkInterface`], RFC 2863 as reported by
+/// the kernel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InterfaceState {
+    Up,
+    Down,
+    /// Up but waiting for an external event, e.g. Wi-Fi authentication.
+    Dormant,
+    LowerLayerDown,
+    NotPresent,
+    Testing,
+    /// The driver does not report a state, as for loopback and many
+    /// cellular interfaces.
+    Unknown,
+}
+
+impl InterfaceState {
+    fn parse(state: &str) -> InterfaceState {
+        match state {
+            "UP" => InterfaceState::Up,
+            "DOWN" => InterfaceState::Down,
+            "DORMANT" => InterfaceState::Dormant,
+            "LOWERLAYERDOWN" => InterfaceState::LowerLayerDown,
+            "NOTPRESENT" => InterfaceState::NotPresent,
+            "TESTING" => InterfaceState::Testing,
+            _ => InterfaceState::Unknown,
+        }
+    }
+}
+
+/// An address assigned to a [`NetworkInterface`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceAddress {
+    pub address: IpAddr,
+    pub prefix_len: u8,
+    /// `global`, `link` or `host`.
+    pub scope: Option<String>,
+}
+
+/// A network interface of [`Device::network_interfaces`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkInterface {
+    pub index: u32,
+    /// Name without the parent of stacked interfaces, e.g. `rmnet_data0`
+    /// for `rmnet_data0@rmnet_ipa0`.
+    pub name: String,
+    /// Link layer, e.g. `ether`, `loopback` or `none`.
+    pub link_type: Option<String>,
+    /// Hardware address, for Ethernet-like links.
+    pub mac: Option<String>,
+    pub mtu: Option<u32>,
+    pub state: InterfaceState,
+    /// Interface flags such as `UP`, `BROADCAST` or `LOWER_UP`.
+    pub flags: Vec<String>,
+    pub addresses: Vec<InterfaceAddress>,
+}
+
+impl NetworkInterface {
+    /// Whether the interface was brought up, whatever the link state.
+    pub fn is_up(&self) -> bool {
+        self.flags.iter().any(|flag| flag == "UP")
+    }
+
+    pub fn ipv4(&self) -> impl Iterator<Item = &InterfaceAddress> {
+        self.addresses.iter().filter(|a| a.address.is_ipv4())
+    }
+
+    pub fn ipv6(&self) -> impl Iterator<Item = &InterfaceAddress> {
+        self.addresses.iter().filter(|a| a.address.is_ipv6())
+    }
+}
+
+/// A route of [`Device::routes`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    /// `unicast`, `local`, `broadcast`, `unreachable`, `prohibit`, ...
+    pub route_type: String,
+    /// Destination network, `0.0.0.0/0` or `::/0` for `default`.
+    pub destination: IpAddr,
+    pub prefix_len: u8,
+    pub gateway: Option<IpAddr>,
+    pub interface: Option<String>,
+    /// Android keeps a table per network, named after its interface or
+    /// numbered, e.g. for VPNs; `main` for routes listed without one.
+    pub table: String,
+    /// `global` when not listed, `link` or `host`.
+    pub scope: String,
+    /// Who added the route, e.g. `kernel` or `static`.
+    pub protocol: Option<String>,
+    /// Preferred source address.
+    pub source: Option<IpAddr>,
+    pub metric: Option<u32>,
+}
+
+impl Route {
+    pub fn is_default(&self) -> bool {
+        self.prefix_len == 0
+    }
+}
+
+/// Reachability of a [`Neighbor`], as tracked by the kernel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NeighborState {
+    Reachable,
+    /// Not confirmed recently; still used, but probed on the next send.
+    Stale,
+    Delay,
+    Probe,
+    /// Resolution failed; the host did not answer.
+    Failed,
+    /// Resolution is in progress.
+    Incomplete,
+    /// Configured rather than learned.
+    Permanent,
+    Noarp,
+    Other(String),
+}
+
+impl NeighborState {
+    fn parse(state: &str) -> NeighborState {
+        match state {
+            "REACHABLE" => NeighborState::Reachable,
+            "STALE" => NeighborState::Stale,
+            "DELAY" => NeighborState::Delay,
+            "PROBE" => NeighborState::Probe,
+            "FAILED" => NeighborState::Failed,
+            "INCOMPLETE" => NeighborState::Incomplete,
+            "PERMANENT" => NeighborState::Permanent,
+            "NOARP" => NeighborState::Noarp,
+            other => NeighborState::Other(other.to_owned()),
+        }
+    }
+}
+
+/// An ARP (IPv4) or NDP (IPv6) entry of [`Device::neighbors`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Neighbor {
+    pub ip: IpAddr,
+    /// `None` while unresolved, e.g. [`NeighborState::Failed`].
+    pub mac: Option<String>,
+    pub interface: Option<String>,
+    pub state: NeighborState,
+    /// Whether the neighbor announced itself as an IPv6 router.
+    pub router: bool,
+}
+
+impl Device {
+    /// Lists the network interfaces with their hardware and IP addresses,
+    /// from `ip -o link` and `ip -o addr` in one shell round trip.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn network_interfaces(&self) -> Result<Vec<NetworkInterface>> {
+        let outputs = self.run_batch(&["ip -o link", "ip -o addr"]).await?;
+        check_outputs(&outputs)?;
+
+        let mut interfaces = parse_links(&outputs[0].stdout_lossy());
+        parse_addresses(&outputs[1].stdout_lossy(), &mut interfaces);
+        Ok(interfaces)
+    }
+
+    /// Lists the IPv4 and IPv6 routes of all routing tables, which shows
+    /// the networks in use, e.g. a VPN or tethering, at capture time.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn routes(&self) -> Result<Vec<Route>> {
+        let outputs = self
+            .run_batch(&["ip route show table all", "ip -6 route show table all"])
+            .await?;
+        check_outputs(&outputs)?;
+
+        let mut routes = parse_routes(&outputs[0].stdout_lossy(), false);
+        routes.extend(parse_routes(&outputs[1].stdout_lossy(), true));
+        Ok(routes)
+    }
+
+    /// Lists the ARP and IPv6 neighbor cache, the hosts on the local
+    /// networks the device talked to recently.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn neighbors(&self) -> Result<Vec<Neighbor>> {
+        let output = self.run("ip neigh").await?;
+        check_outputs(std::slice::from_ref(&output))?;
+        Ok(parse_neighbors(&output.stdout_lossy()))
+    }
+}
+
+fn check_outputs(outputs: &[ShellOutput]) -> Result<()> {
+    for output in outputs {
+        if !output.success() {
+            let mut message = output.stderr_lossy();
+            if message.is_empty() {
+                message = output.stdout_lossy();
+            }
+            return Err(DeviceError::Adb(message.trim().to_owned()));
+        }
+    }
+    Ok(())
+}
+
+/// `ip -o` puts each record on one line, replacing the line breaks within
+/// it by `\`.
+fn tokens(line: &str) -> Vec<&str> {
+    line.split(|c: char| c == '\\' || c.is_whitespace())
+        .filter(|token| !token.is_empty())
+        .collect()
+}
+
+/// Parses `ip -o link` lines like `3: wlan0: <BROADCAST,UP> mtu 1500 ...
+/// state UP ...\    link/ether 02:00:00:44:55:66 brd ff:ff:ff:ff:ff:ff`.
+fn parse_links(output: &str) -> Vec<NetworkInterface> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let tokens = tokens(line);
+            let index = tokens.first()?.strip_suffix(':')?.parse().ok()?;
+            let name = tokens.get(1)?.strip_suffix(':')?;
+            let name = name.split('@').next().unwrap_or(name);
+            let flags = tokens
+                .get(2)
+                .and_then(|flags| flags.strip_prefix('<')?.strip_suffix('>'))
+                .map(|flags| {
+                    flags
+                        .split(',')
+                        .filter(|flag| !flag.is_empty())
+                        .map(str::to_owned)
+                        .collect()
+                })
+                .unwrap_or_default();
+            let value = |key: &str| {
+                tokens
+                    .iter()
+                    .position(|token| *token == key)
+                    .and_then(|i| tokens.get(i + 1))
+                    .copied()
+            };
+            let link = tokens.iter().position(|token| token.starts_with("link/"));
+            let mac = link
+                .and_then(|i| tokens.get(i + 1))
+                .filter(|address| address.len() == 17 && address.contains(':'))
+                .map(|address| address.to_string());
+
+            Some(NetworkInterface {
+                index,
+                name: name.to_owned(),
+                link_type: link.map(|i| tokens[i]["link/".len()..].to_owned()),
+                mac,
+                mtu: value("mtu").and_then(|mtu| mtu.parse().ok()),
+                state: value("state").map_or(InterfaceState::Unknown, InterfaceState::parse),
+                flags,
+                addresses: Vec::new(),
+            })
+        })
+        .collect()
+}
+
+/// Adds the addresses of `ip -o addr` lines like `3: wlan0    inet
+/// 192.168.1.23/24 brd 192.168.1.255 scope global wlan0\ ...` to the
+/// interfaces with the same index.
+fn parse_addresses(output: &str, interfaces: &mut [NetworkInterface]) {
+    for line in output.lines() {
+        let tokens = tokens(line);
+        let Some(index) = tokens
+            .first()
+            .and_then(|index| index.strip_suffix(':')?.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        let Some(interface) = interfaces.iter_mut().find(|i| i.index == index) else {
+            continue;
+        };
+        let Some(family) = tokens.iter().position(|t| *t == "inet" || *t == "inet6") else {
+            continue;
+        };
+        // Point-to-point links show `inet LOCAL peer REMOTE/PREFIX`.
+        let Some(address) = tokens.get(family + 1) else {
+            continue;
+        };
+        let (address, prefix_len) = match address.split_once('/') {
+            Some((address, prefix_len)) => (address, prefix_len.parse().ok()),
+            None => (*address, None),
+        };
+        let Ok(address) = address.parse::<IpAddr>() else {
+            continue;
+        };
+        let scope = tokens
+            .iter()
+            .position(|token| *token == "scope")
+            .and_then(|i| tokens.get(i + 1))
+            .map(|scope| scope.to_string());
+
+        interface.addresses.push(InterfaceAddress {
+            address,
+            prefix_len: prefix_len.unwrap_or(if address.is_ipv4() { 32 } else { 128 }),
+            scope,
+        });
+    }
+}
+
+/// Parses `ip route` lines like `[TYPE] PREFIX [via GATEWAY] [dev IFACE]
+/// [table TABLE] [proto PROTO] [scope SCOPE] [src ADDRESS] [metric N]`.
+/// Indented `nexthop` lines of multipath routes are skipped.
+fn parse_routes(output: &str, ipv6: bool) -> Vec<Route> {
+    const TYPES: [&str; 10] = [
+        "unicast",
+        "local",
+        "broadcast",
+        "multicast",
+        "unreachable",
+        "prohibit",
+        "blackhole",
+        "throw",
+        "nat",
+        "anycast",
+    ];
+
+    output
+        .lines()
+        .filter(|line| !line.starts_with(char::is_whitespace))
+        .filter_map(|line| {
+            let mut tokens: &[&str] = &line.split_whitespace().collect::<Vec<_>>();
+            let route_type = match tokens.first() {
+                Some(first) if TYPES.contains(first) => {
+                    tokens = &tokens[1..];
+                    first
+                }
+                _ => "unicast",
+            };
+
+            let (destination, prefix_len) = match *tokens.first()? {
+                "default" if ipv6 => (IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+                "default" => (IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+                prefix => {
+                    let (address, prefix_len) = match prefix.split_once('/') {
+                        Some((address, prefix_len)) => (address, prefix_len.parse().ok()?),
+                        None => (prefix, if ipv6 { 128 } else { 32 }),
+                    };
+                    (address.parse().ok()?, prefix_len)
+                }
+            };
+            let value = |key: &str| {
+                tokens
+                    .iter()
+                    .position(|token| *token == key)
+                    .and_then(|i| tokens.get(i + 1))
+                    .copied()
+            };
+
+            Some(Route {
+                route_type: route_type.to_owned(),
+                destination,
+                prefix_len,
+                gateway: value("via").and_then(|gateway| gateway.parse().ok()),
+                interface: value("dev").map(str::to_owned),
+                table: value("table").unwrap_or("main").to_owned(),
+                scope: value("scope").unwrap_or("global").to_owned(),
+                protocol: value("proto").map(str::to_owned),
+                source: value("src").and_then(|source| source.parse().ok()),
+                metric: value("metric").and_then(|metric| metric.parse().ok()),
+            })
+        })
+        .collect()
+}
+
+/// Parses `ip neigh` lines like `192.168.1.1 dev wlan0 lladdr
+/// aa:bb:cc:dd:ee:ff router REACHABLE`, the state being last.
+fn parse_neighbors(output: &str) -> Vec<Neighbor> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let tokens: Vec<_> = line.split_whitespace().collect();
+            let value = |key: &str| {
+                tokens
+                    .iter()
+                    .position(|token| *token == key)
+                    .and_then(|i| tokens.get(i + 1))
+                    .map(|value| value.to_string())
+            };
+
+            Some(Neighbor {
+                ip: tokens.first()?.parse().ok()?,
+                mac: value("lladdr"),
+                interface: value("dev"),
+                state: NeighborState::parse(tokens.last()?),
+                router: tokens.contains(&"router"),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINKS: &str = "1: lo: <LOOPBACK,UP,LOWER_UP> mtu 65536 qdisc noqueue state UNKNOWN mode DEFAULT group default qlen 1000\\    link/loopback 00:00:00:00:00:00 brd 00:00:00:00:00:00\n\
+                         3: wlan0: <BROADCAST,MULTICAST,UP,LOWER_UP> mtu 1500 qdisc mq state UP mode DORMANT group default qlen 3000\\    link/ether 02:00:00:44:55:66 brd ff:ff:ff:ff:ff:ff\n\
+                         12: rmnet_data0@rmnet_ipa0: <UP,LOWER_UP> mtu 1410 qdisc htb state UNKNOWN mode DEFAULT group default qlen 1000\\    link/[530] \n\
+                         14: dummy0: <BROADCAST,NOARP> mtu 1500 qdisc noop state DOWN mode DEFAULT group default qlen 1000\\    link/ether 6a:4d:c9:0e:1f:2a brd ff:ff:ff:ff:ff:ff\n";
+
+    const ADDRESSES: &str = "1: lo    inet 127.0.0.1/8 scope host lo\\       valid_lft forever preferred_lft forever\n\
+                             1: lo    inet6 ::1/128 scope host \\       valid_lft forever preferred_lft forever\n\
+                             3: wlan0    inet 192.168.1.23/24 brd 192.168.1.255 scope global wlan0\\       valid_lft forever preferred_lft forever\n\
+                             3: wlan0    inet6 fe80::ff:fe44:5566/64 scope link \\       valid_lft forever preferred_lft forever\n\
+                             12: rmnet_data0    inet 10.12.0.5 peer 10.12.0.6/32 scope global rmnet_data0\\       valid_lft forever preferred_lft forever\n";
+
+    #[test]
+    fn parses_interfaces() {
+        let mut interfaces = parse_links(LINKS);
+        parse_addresses(ADDRESSES, &mut interfaces);
+        assert_eq!(interfaces.len(), 4);
+
+        let wlan = &interfaces[1];
+        assert_eq!((wlan.index, wlan.name.as_str()), (3, "wlan0"));
+        assert_eq!(wlan.link_type.as_deref(), Some("ether"));
+        assert_eq!(wlan.mac.as_deref(), Some("02:00:00:44:55:66"));
+        assert_eq!((wlan.mtu, &wlan.state), (Some(1500), &InterfaceState::Up));
+        assert!(wlan.is_up());
+        assert_eq!(
+            wlan.ipv4().collect::<Vec<_>>(),
+            [&InterfaceAddress {
+                address: "192.168.1.23".parse().unwrap(),
+                prefix_len: 24,
+                scope: Some("global".to_owned()),
+            }]
+        );
+        assert_eq!(
+            wlan.ipv6().map(|a| a.scope.as_deref()).collect::<Vec<_>>(),
+            [Some("link")]
+        );
+
+        let rmnet = &interfaces[2];
+        assert_eq!(rmnet.name, "rmnet_data0");
+        assert_eq!(rmnet.mac, None);
+        assert_eq!(rmnet.state, InterfaceState::Unknown);
+        assert_eq!(
+            rmnet.addresses[0].address,
+            "10.12.0.5".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(rmnet.addresses[0].prefix_len, 32);
+
+        assert!(!interfaces[3].is_up());
+        assert_eq!(interfaces[3].state, InterfaceState::Down);
+        assert!(interfaces[3].addresses.is_empty());
+    }
+
+    #[test]
+    fn parses_routes() {
+        let routes = parse_routes(
+            "default via 192.168.1.1 dev wlan0 table wlan0 proto static \n\
+             192.168.1.0/24 dev wlan0 table wlan0 proto kernel scope link src 192.168.1.23 \n\
+             0.0.0.0/1 dev tun0 table 1021 proto static scope link \n\
+             broadcast 127.255.255.255 dev lo table local proto kernel scope link src 127.0.0.1 \n\
+             unreachable default dev lo table 97 proto static metric 4294967295 \n",
+            false,
+        );
+        assert_eq!(routes.len(), 5);
+        assert_eq!(
+            routes[0],
+            Route {
+                route_type: "unicast".to_owned(),
+                destination: "0.0.0.0".parse().unwrap(),
+                prefix_len: 0,
+                gateway: Some("192.168.1.1".parse().unwrap()),
+                interface: Some("wlan0".to_owned()),
+                table: "wlan0".to_owned(),
+                scope: "global".to_owned(),
+                protocol: Some("static".to_owned()),
+                source: None,
+                metric: None,
+            }
+        );
+        assert!(routes[0].is_default());
+        assert_eq!(routes[1].scope, "link");
+        assert_eq!(routes[1].source, Some("192.168.1.23".parse().unwrap()));
+        assert_eq!(
+            (routes[2].interface.as_deref(), routes[2].table.as_str()),
+            (Some("tun0"), "1021")
+        );
+        assert_eq!(
+            (routes[3].route_type.as_str(), routes[3].prefix_len),
+            ("broadcast", 32)
+        );
+        assert_eq!(routes[4].route_type, "unreachable");
+        assert_eq!(routes[4].metric, Some(4294967295));
+
+        let routes = parse_routes(
+            "fe80::/64 dev wlan0 table wlan0 proto kernel metric 256 pref medium\n\
+             default proto static metric 1024 pref medium\n\
+             \tnexthop via fe80::1 dev wlan0 weight 1\n",
+            true,
+        );
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].prefix_len, 64);
+        assert_eq!(routes[0].table, "wlan0");
+        assert_eq!(routes[1].destination, "::".parse::<IpAddr>().unwrap());
+        assert_eq!(routes[1].table, "main");
+    }
+
+    #[test]
+    fn parses_neighbors() {
+        let neighbors = parse_neighbors(
+            "192.168.1.1 dev wlan0 lladdr aa:bb:cc:dd:ee:ff REACHABLE\n\
+             192.168.1.7 dev wlan0  FAILED\n\
+             fe80::1 dev wlan0 lladdr aa:bb:cc:dd:ee:ff router STALE\n",
+        );
+        assert_eq!(
+            neighbors[0],
+            Neighbor {
+                ip: "192.168.1.1".parse().unwrap(),
+                mac: Some("aa:bb:cc:dd:ee:ff".to_owned()),
+                interface: Some("wlan0".to_owned()),
+                state: NeighborState::Reachable,
+                router: false,
+            }
+        );
+        assert_eq!(
+            (&neighbors[1].mac, &neighbors[1].state),
+            (&None, &NeighborState::Failed)
+        );
+        assert!(neighbors[2].ip.is_ipv6() && neighbors[2].router);
+        assert_eq!(neighbors[2].state, NeighborState::Stale);
+    }
+}
@@ -0,0 +1,293 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! System-wide listing of open files, to see which processes hold evidence
+//! files open before pulling them.
+
+use crate::process::{parse_fd_line, FdTarget};
+use crate::{Device, Result};
+
+/// Lists the open file descriptors of every process readable to the shell
+/// user, as `/proc/PID` followed by `ls -l` of its `fd` directory.
+const PROC_FD_SCRIPT: &str = "for p in /proc/[0-9]*; do echo \"$p $(cat $p/comm 2>/dev/null)\"; ls -l $p/fd 2>/dev/null; done";
+
+/// Which descriptor of a process an [`OpenFile`] is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpenFd {
+    /// A numbered file descriptor.
+    Number(u32),
+    /// The working directory, `cwd`.
+    Cwd,
+    /// The root directory, `rtd`.
+    Root,
+    /// The executable, `txt`.
+    Program,
+    /// A memory-mapped file, `mem`.
+    Mapped,
+    Other(String),
+}
+
+impl OpenFd {
+    /// Parses an `lsof` FD column such as `cwd` or `12u`, whose suffix is
+    /// the access mode.
+    fn parse(fd: &str) -> OpenFd {
+        match fd {
+            "cwd" => OpenFd::Cwd,
+            "rtd" => OpenFd::Root,
+            "txt" => OpenFd::Program,
+            "mem" => OpenFd::Mapped,
+            _ => fd
+                .trim_end_matches(['r', 'w', 'u'])
+                .parse()
+                .map(OpenFd::Number)
+                .unwrap_or_else(|_| OpenFd::Other(fd.to_owned())),
+        }
+    }
+}
+
+/// What an [`OpenFile`] is, from the `lsof` TYPE column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpenFileType {
+    Regular,
+    Directory,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    /// Unix, IP and netlink sockets.
+    Socket,
+    /// Files listed by the `/proc` fallback, which does not stat them.
+    Unknown,
+    Other(String),
+}
+
+impl OpenFileType {
+    fn parse(file_type: &str) -> OpenFileType {
+        match file_type {
+            "REG" => OpenFileType::Regular,
+            "DIR" => OpenFileType::Directory,
+            "CHR" => OpenFileType::CharDevice,
+            "BLK" => OpenFileType::BlockDevice,
+            "FIFO" => OpenFileType::Fifo,
+            "unix" | "IPv4" | "IPv6" | "sock" | "netlink" => OpenFileType::Socket,
+            other => OpenFileType::Other(other.to_owned()),
+        }
+    }
+}
+
+/// A file held open by a process, a row of [`Device::open_files`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenFile {
+    pub pid: u32,
+    /// Process name, as in `/proc/PID/comm`.
+    pub process: String,
+    pub fd: OpenFd,
+    pub file_type: OpenFileType,
+    /// File path, or a pseudo name such as `socket:[123456]`.  Files
+    /// deleted while open end in ` (deleted)`.
+    pub path: String,
+}
+
+/// Which rows [`Device::open_files`] returns; by default all of them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OpenFileFilter {
+    pub path_prefix: Option<String>,
+    pub pid: Option<u32>,
+    pub process: Option<String>,
+}
+
+impl OpenFileFilter {
+    pub fn new() -> OpenFileFilter {
+        OpenFileFilter::default()
+    }
+
+    /// Only files under `prefix`, e.g. `/data/data/com.example.app/`.
+    pub fn path_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.path_prefix = Some(prefix.into());
+        self
+    }
+
+    pub fn pid(mut self, pid: u32) -> Self {
+        self.pid = Some(pid);
+        self
+    }
+
+    /// Only processes named exactly `process`.
+    pub fn process(mut self, process: impl Into<String>) -> Self {
+        self.process = Some(process.into());
+        self
+    }
+
+    fn matches(&self, file: &OpenFile) -> bool {
+        self.path_prefix
+            .as_ref()
+            .is_none_or(|prefix| file.path.starts_with(prefix.as_str()))
+            && self.pid.is_none_or(|pid| file.pid == pid)
+            && self
+                .process
+                .as_ref()
+                .is_none_or(|process| &file.process == process)
+    }
+}
+
+impl Device {
+    /// Lists the files processes hold open, e.g. to find out which app
+    /// still writes to a database before pulling it.
+    ///
+    /// Uses toybox `lsof` (Android 7.0+) and falls back to walking
+    /// `/proc/PID/fd`, which lacks the `cwd`, `txt` and `mem` rows and the
+    /// file types.  Without root only the shell user's own processes are
+    /// listed.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self), fields(serial = %self.serial), err)
+    )]
+    pub async fn open_files(&self, filter: &OpenFileFilter) -> Result<Vec<OpenFile>> {
+        let output = self.execute_host_shell_command("lsof").await?;
+        let files = match output.starts_with("COMMAND") {
+            true => parse_lsof(&output),
+            false => parse_proc_fds(&self.execute_host_shell_command(PROC_FD_SCRIPT).await?),
+        };
+        Ok(files
+            .into_iter()
+            .filter(|file| filter.matches(file))
+            .collect())
+    }
+}
+
+/// Parses `lsof` output.  Columns before NAME may be empty, so NAME is cut
+/// at the position of its header, which both toybox and classic `lsof`
+/// align with.
+fn parse_lsof(output: &str) -> Vec<OpenFile> {
+    let mut lines = output.lines();
+    let Some(name_column) = lines.next().and_then(|header| header.find("NAME")) else {
+        return Vec::new();
+    };
+
+    lines
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            let process = columns.next()?.to_owned();
+            let pid = columns.next()?.parse().ok()?;
+            let _user = columns.next()?;
+            let fd = OpenFd::parse(columns.next()?);
+            let file_type = OpenFileType::parse(columns.next()?);
+            let path = line.get(name_column..)?.trim();
+            // Descriptors of processes the user may not inspect.
+            if path.is_empty() || path.ends_with("Permission denied)") {
+                return None;
+            }
+
+            Some(OpenFile {
+                pid,
+                process,
+                fd,
+                file_type,
+                path: path.to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// Parses the output of [`PROC_FD_SCRIPT`].
+fn parse_proc_fds(output: &str) -> Vec<OpenFile> {
+    let mut files = Vec::new();
+    let mut process = None;
+    for line in output.lines() {
+        if let Some(rest) = line.strip_prefix("/proc/") {
+            let (pid, name) = rest.split_once(' ').unwrap_or((rest, ""));
+            process = pid.parse::<u32>().ok().map(|pid| (pid, name.trim()));
+            continue;
+        }
+        let (Some((pid, name)), Some(entry)) = (process, parse_fd_line(line)) else {
+            continue;
+        };
+
+        let (file_type, path) = match entry.target {
+            FdTarget::File(path) => (OpenFileType::Unknown, path),
+            FdTarget::Deleted(path) => (OpenFileType::Unknown, format!("{path} (deleted)")),
+            FdTarget::Socket(inode) => (OpenFileType::Socket, format!("socket:[{inode}]")),
+            FdTarget::Pipe(inode) => (OpenFileType::Fifo, format!("pipe:[{inode}]")),
+            FdTarget::AnonInode(name) => (
+                OpenFileType::Other("a_inode".to_owned()),
+                format!("anon_inode:[{name}]"),
+            ),
+            FdTarget::Other(target) => (OpenFileType::Unknown, target),
+        };
+        files.push(OpenFile {
+            pid,
+            process: name.to_owned(),
+            fd: OpenFd::Number(entry.fd),
+            file_type,
+            path,
+        });
+    }
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_lsof() {
+        let output = "COMMAND     PID       USER   FD      TYPE             DEVICE  SIZE/OFF       NODE NAME\n\
+                      init          1       root  cwd   unknown                                         /proc/1/cwd (readlink: Permission denied)\n\
+                      app.example 4242     u0_a99  cwd       DIR              253,5      4096          2 /\n\
+                      app.example 4242     u0_a99   41u      REG              253,5     20480      81921 /data/data/com.example.app/databases/app db.sqlite\n\
+                      app.example 4242     u0_a99   43u     unix                          0t0     123456 socket\n";
+        let files = parse_lsof(output);
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[0].fd, OpenFd::Cwd);
+        assert_eq!(files[0].file_type, OpenFileType::Directory);
+        assert_eq!(
+            files[1],
+            OpenFile {
+                pid: 4242,
+                process: "app.example".to_owned(),
+                fd: OpenFd::Number(41),
+                file_type: OpenFileType::Regular,
+                path: "/data/data/com.example.app/databases/app db.sqlite".to_owned(),
+            }
+        );
+        assert_eq!(files[2].file_type, OpenFileType::Socket);
+    }
+
+    #[test]
+    fn parses_proc_fds() {
+        let output = "/proc/1 init\n\
+                      /proc/4242 app.example\n\
+                      total 0\n\
+                      lrwx------ 1 u0_a99 u0_a99 64 2024-05-01 10:00 41 -> /data/data/com.example.app/databases/app.db\n\
+                      lr-x------ 1 u0_a99 u0_a99 64 2024-05-01 10:00 44 -> pipe:[7890]\n";
+        let files = parse_proc_fds(output);
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            (files[0].pid, files[0].process.as_str()),
+            (4242, "app.example")
+        );
+        assert_eq!(files[0].fd, OpenFd::Number(41));
+        assert_eq!(files[1].path, "pipe:[7890]");
+        assert_eq!(files[1].file_type, OpenFileType::Fifo);
+    }
+
+    #[test]
+    fn filters_rows() {
+        let file = OpenFile {
+            pid: 4242,
+            process: "app.example".to_owned(),
+            fd: OpenFd::Number(41),
+            file_type: OpenFileType::Regular,
+            path: "/data/data/com.example.app/databases/app.db".to_owned(),
+        };
+        assert!(OpenFileFilter::new().matches(&file));
+        assert!(OpenFileFilter::new()
+            .path_prefix("/data/data/com.example.app/")
+            .pid(4242)
+            .matches(&file));
+        assert!(!OpenFileFilter::new().path_prefix("/sdcard/").matches(&file));
+        assert!(!OpenFileFilter::new()
+            .process("system_server")
+            .matches(&file));
+    }
+}
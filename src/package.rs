@@ -0,0 +1,671 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Options for the package manager commands.
+
+use std::fmt;
+
+use crate::{Capabilities, Device, DeviceError, Result, UnixPath, UnixPathBuf};
+
+/// Value of `pm install --install-location`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstallLocation {
+    /// Let the system decide.
+    Auto,
+    InternalOnly,
+    PreferExternal,
+}
+
+impl InstallLocation {
+    fn code(&self) -> u8 {
+        match self {
+            InstallLocation::Auto => 0,
+            InstallLocation::InternalOnly => 1,
+            InstallLocation::PreferExternal => 2,
+        }
+    }
+}
+
+/// Flags for [`Device::install_package`].
+///
+/// Flags the device does not know are left out, like `-g` before Android
+/// 6.0, except for [`InstallOptions::instant`], which fails the install
+/// rather than silently installing a full app.
+///
+/// ```no_run
+/// # async fn example(device: forensic_adb::Device) -> forensic_adb::Result<()> {
+/// use forensic_adb::InstallOptions;
+///
+/// let options = InstallOptions::new().reinstall(true).grant_runtime_permissions(true);
+/// device.install_package("app.apk".as_ref(), &options).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InstallOptions {
+    /// `-r`: replace an installed app, keeping its data.
+    pub reinstall: bool,
+    /// `-g`: grant all runtime permissions.
+    pub grant_runtime_permissions: bool,
+    /// `-d`: allow a lower version code.  Together with `reinstall` this
+    /// moves to an older version without wiping the app data; release
+    /// builds only allow it for debuggable apps.
+    pub allow_downgrade: bool,
+    /// `-t`: allow apks with `android:testOnly`.
+    pub allow_test: bool,
+    /// `--user`, overriding [`Device::user`].
+    pub user: Option<u32>,
+    /// `--instant` (`--ephemeral` on Android 7).
+    pub instant: bool,
+    /// `--abi`: install for this ABI, e.g. `armeabi-v7a`.
+    pub abi: Option<String>,
+    pub install_location: Option<InstallLocation>,
+    /// `-i`: package name recorded as the installer.
+    pub installer: Option<String>,
+    /// `--dont-kill`: keep the app running when adding a split.
+    pub dont_kill: bool,
+    /// `--bypass-low-target-sdk-block`, needed for old apps since Android 14.
+    pub bypass_low_target_sdk_block: bool,
+    /// `--enable-rollback`: keep the replaced version so that
+    /// [`Device::rollback_package`] can restore it.  Since Android 10.
+    pub enable_rollback: bool,
+}
+
+impl InstallOptions {
+    pub fn new() -> InstallOptions {
+        InstallOptions::default()
+    }
+
+    pub fn reinstall(mut self, reinstall: bool) -> InstallOptions {
+        self.reinstall = reinstall;
+        self
+    }
+
+    pub fn grant_runtime_permissions(mut self, grant: bool) -> InstallOptions {
+        self.grant_runtime_permissions = grant;
+        self
+    }
+
+    pub fn allow_downgrade(mut self, allow: bool) -> InstallOptions {
+        self.allow_downgrade = allow;
+        self
+    }
+
+    pub fn allow_test(mut self, allow: bool) -> InstallOptions {
+        self.allow_test = allow;
+        self
+    }
+
+    pub fn user(mut self, user: u32) -> InstallOptions {
+        self.user = Some(user);
+        self
+    }
+
+    pub fn instant(mut self, instant: bool) -> InstallOptions {
+        self.instant = instant;
+        self
+    }
+
+    pub fn abi(mut self, abi: &str) -> InstallOptions {
+        self.abi = Some(abi.to_owned());
+        self
+    }
+
+    pub fn install_location(mut self, location: InstallLocation) -> InstallOptions {
+        self.install_location = Some(location);
+        self
+    }
+
+    pub fn installer(mut self, package: &str) -> InstallOptions {
+        self.installer = Some(package.to_owned());
+        self
+    }
+
+    pub fn dont_kill(mut self, dont_kill: bool) -> InstallOptions {
+        self.dont_kill = dont_kill;
+        self
+    }
+
+    pub fn bypass_low_target_sdk_block(mut self, bypass: bool) -> InstallOptions {
+        self.bypass_low_target_sdk_block = bypass;
+        self
+    }
+
+    pub fn enable_rollback(mut self, enable: bool) -> InstallOptions {
+        self.enable_rollback = enable;
+        self
+    }
+
+    /// Returns the flags as understood by a device with `capabilities`,
+    /// each preceded by a space.
+    pub fn args(&self, capabilities: &Capabilities, user: Option<u32>) -> Result<String> {
+        let mut args = String::new();
+        if let Some(user) = self.user.or(user) {
+            args.push_str(&format!(" --user {user}"));
+        }
+        if self.reinstall {
+            args.push_str(" -r");
+        }
+        if self.grant_runtime_permissions && capabilities.supports_install_grant() {
+            args.push_str(" -g");
+        }
+        if self.allow_downgrade {
+            args.push_str(" -d");
+        }
+        if self.allow_test {
+            args.push_str(" -t");
+        }
+        if self.instant {
+            let flag = capabilities
+                .instant_install_flag()
+                .ok_or_else(|| DeviceError::MissingFeature("instant apps".to_owned()))?;
+            args.push(' ');
+            args.push_str(flag);
+        }
+        if let Some(abi) = &self.abi {
+            // ABIs like `arm64-v8a` never need quoting, so anything else is
+            // refused.
+            if abi.is_empty()
+                || !abi
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'))
+            {
+                return Err(DeviceError::Adb(format!("invalid abi '{abi}'")));
+            }
+            args.push_str(&format!(" --abi {abi}"));
+        }
+        if let Some(location) = self.install_location {
+            args.push_str(&format!(" --install-location {}", location.code()));
+        }
+        if let Some(installer) = &self.installer {
+            check_package_name(installer)?;
+            args.push_str(&format!(" -i {installer}"));
+        }
+        if self.dont_kill && capabilities.supports_install_dont_kill() {
+            args.push_str(" --dont-kill");
+        }
+        if self.bypass_low_target_sdk_block && capabilities.supports_bypass_low_target_sdk_block() {
+            args.push_str(" --bypass-low-target-sdk-block");
+        }
+        if self.enable_rollback && capabilities.supports_rollback() {
+            args.push_str(" --enable-rollback");
+        }
+        Ok(args)
+    }
+}
+
+/// Flags for [`Device::uninstall_package`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UninstallOptions {
+    /// `-k`: keep the data and cache directories.
+    pub keep_data: bool,
+    /// `--user`, overriding [`Device::user`].
+    pub user: Option<u32>,
+    /// `--versionCode`: only uninstall this version, e.g. to roll back an
+    /// update of a system app.  Fails before Android 8.0 instead of
+    /// removing every version.
+    pub version_code: Option<u64>,
+}
+
+impl UninstallOptions {
+    pub fn new() -> UninstallOptions {
+        UninstallOptions::default()
+    }
+
+    pub fn keep_data(mut self, keep: bool) -> UninstallOptions {
+        self.keep_data = keep;
+        self
+    }
+
+    pub fn user(mut self, user: u32) -> UninstallOptions {
+        self.user = Some(user);
+        self
+    }
+
+    pub fn version_code(mut self, version_code: u64) -> UninstallOptions {
+        self.version_code = Some(version_code);
+        self
+    }
+
+    /// Returns the flags as understood by a device with `capabilities`,
+    /// each preceded by a space.
+    pub fn args(&self, capabilities: &Capabilities, user: Option<u32>) -> Result<String> {
+        let mut args = String::new();
+        if self.keep_data {
+            args.push_str(" -k");
+        }
+        if let Some(user) = self.user.or(user) {
+            args.push_str(&format!(" --user {user}"));
+        }
+        if let Some(version_code) = self.version_code {
+            if !capabilities.supports_uninstall_version_code() {
+                return Err(DeviceError::MissingFeature("--versionCode".to_owned()));
+            }
+            args.push_str(&format!(" --versionCode {version_code}"));
+        }
+        Ok(args)
+    }
+}
+
+/// Why `pm uninstall` failed, parsed from e.g.
+/// `Failure [DELETE_FAILED_DEVICE_POLICY_MANAGER]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UninstallFailure {
+    /// `DELETE_FAILED_INTERNAL_ERROR`, which older releases also report for
+    /// unknown packages.
+    InternalError,
+    /// `DELETE_FAILED_DEVICE_POLICY_MANAGER`: the app is an active device
+    /// administrator.
+    DevicePolicyManager,
+    /// `DELETE_FAILED_USER_RESTRICTED`
+    UserRestricted,
+    /// `DELETE_FAILED_OWNER_BLOCKED`
+    OwnerBlocked,
+    /// `DELETE_FAILED_ABORTED`
+    Aborted,
+    /// `DELETE_FAILED_USED_SHARED_LIBRARY`
+    UsedSharedLibrary,
+    /// `DELETE_FAILED_APP_PINNED`
+    AppPinned,
+    /// The package is not installed for the user, e.g. `not installed for 0`.
+    NotInstalled,
+    /// Output that is none of the above, verbatim.
+    Other(String),
+}
+
+impl UninstallFailure {
+    pub fn parse(output: &str) -> UninstallFailure {
+        let reason = output
+            .split_once("Failure [")
+            .and_then(|(_, rest)| rest.split_once(']'))
+            .map(|(reason, _)| reason);
+
+        match reason {
+            Some("DELETE_FAILED_INTERNAL_ERROR") => UninstallFailure::InternalError,
+            Some("DELETE_FAILED_DEVICE_POLICY_MANAGER") => UninstallFailure::DevicePolicyManager,
+            Some("DELETE_FAILED_USER_RESTRICTED") => UninstallFailure::UserRestricted,
+            Some("DELETE_FAILED_OWNER_BLOCKED") => UninstallFailure::OwnerBlocked,
+            Some("DELETE_FAILED_ABORTED") => UninstallFailure::Aborted,
+            Some("DELETE_FAILED_USED_SHARED_LIBRARY") => UninstallFailure::UsedSharedLibrary,
+            Some("DELETE_FAILED_APP_PINNED") => UninstallFailure::AppPinned,
+            Some(reason) if reason.starts_with("not installed") => UninstallFailure::NotInstalled,
+            _ => UninstallFailure::Other(output.trim().to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for UninstallFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            UninstallFailure::InternalError => "DELETE_FAILED_INTERNAL_ERROR",
+            UninstallFailure::DevicePolicyManager => "DELETE_FAILED_DEVICE_POLICY_MANAGER",
+            UninstallFailure::UserRestricted => "DELETE_FAILED_USER_RESTRICTED",
+            UninstallFailure::OwnerBlocked => "DELETE_FAILED_OWNER_BLOCKED",
+            UninstallFailure::Aborted => "DELETE_FAILED_ABORTED",
+            UninstallFailure::UsedSharedLibrary => "DELETE_FAILED_USED_SHARED_LIBRARY",
+            UninstallFailure::AppPinned => "DELETE_FAILED_APP_PINNED",
+            UninstallFailure::NotInstalled => "not installed",
+            UninstallFailure::Other(output) => output,
+        })
+    }
+}
+
+/// Flags for [`Device::list_packages_with`].
+///
+/// The state filters combine, e.g. `system` with `disabled` lists disabled
+/// system apps.
+///
+/// ```no_run
+/// # async fn example(device: forensic_adb::Device) -> forensic_adb::Result<()> {
+/// use forensic_adb::PackageListOptions;
+///
+/// let options = PackageListOptions::new().third_party(true).apk_paths(true);
+/// for package in device.list_packages_with(&options).await? {
+///     println!("{} {:?}", package.name, package.apk_path);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PackageListOptions {
+    /// `-f`: report the path of the base apk.
+    pub apk_paths: bool,
+    /// `-d`: only disabled packages.
+    pub disabled: bool,
+    /// `-e`: only enabled packages.
+    pub enabled: bool,
+    /// `-s`: only system packages.
+    pub system: bool,
+    /// `-3`: only third-party packages.
+    pub third_party: bool,
+    /// `-u`: include packages uninstalled with `-k`, whose data is kept.
+    pub uninstalled: bool,
+    /// `-U`: report the uid of each package.
+    pub show_uid: bool,
+    /// `--uid`: only packages running as this uid.
+    pub uid: Option<u32>,
+    /// Only packages whose name contains this text.
+    pub filter: Option<String>,
+}
+
+impl PackageListOptions {
+    pub fn new() -> PackageListOptions {
+        PackageListOptions::default()
+    }
+
+    pub fn apk_paths(mut self, apk_paths: bool) -> PackageListOptions {
+        self.apk_paths = apk_paths;
+        self
+    }
+
+    pub fn disabled(mut self, disabled: bool) -> PackageListOptions {
+        self.disabled = disabled;
+        self
+    }
+
+    pub fn enabled(mut self, enabled: bool) -> PackageListOptions {
+        self.enabled = enabled;
+        self
+    }
+
+    pub fn system(mut self, system: bool) -> PackageListOptions {
+        self.system = system;
+        self
+    }
+
+    pub fn third_party(mut self, third_party: bool) -> PackageListOptions {
+        self.third_party = third_party;
+        self
+    }
+
+    pub fn uninstalled(mut self, uninstalled: bool) -> PackageListOptions {
+        self.uninstalled = uninstalled;
+        self
+    }
+
+    pub fn show_uid(mut self, show_uid: bool) -> PackageListOptions {
+        self.show_uid = show_uid;
+        self
+    }
+
+    pub fn uid(mut self, uid: u32) -> PackageListOptions {
+        self.uid = Some(uid);
+        self
+    }
+
+    pub fn filter(mut self, filter: &str) -> PackageListOptions {
+        self.filter = Some(filter.to_owned());
+        self
+    }
+
+    /// Returns the flags, each preceded by a space.
+    pub fn args(&self) -> Result<String> {
+        let mut args = String::new();
+        for (set, flag) in [
+            (self.apk_paths, " -f"),
+            (self.disabled, " -d"),
+            (self.enabled, " -e"),
+            (self.system, " -s"),
+            (self.third_party, " -3"),
+            (self.uninstalled, " -u"),
+            (self.show_uid, " -U"),
+        ] {
+            if set {
+                args.push_str(flag);
+            }
+        }
+        if let Some(uid) = self.uid {
+            args.push_str(&format!(" --uid {uid}"));
+        }
+        if let Some(filter) = &self.filter {
+            // Package names never need quoting, so anything else is refused.
+            if !filter
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_'))
+            {
+                return Err(DeviceError::Adb(format!(
+                    "invalid package name filter '{filter}'"
+                )));
+            }
+            args.push(' ');
+            args.push_str(filter);
+        }
+        Ok(args)
+    }
+}
+
+/// A package listed by [`Device::list_packages_with`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageListEntry {
+    pub name: String,
+    /// Path of the base apk, with [`PackageListOptions::apk_paths`].
+    pub apk_path: Option<UnixPathBuf>,
+    /// With [`PackageListOptions::show_uid`].
+    pub uid: Option<u32>,
+}
+
+impl PackageListEntry {
+    /// Parses a line such as `package:/data/app/~~a==/org.example-b==/base.apk=org.example uid:10123`.
+    fn parse(line: &str, apk_paths: bool) -> Option<PackageListEntry> {
+        let entry = line.strip_prefix("package:")?.trim_end();
+        let (entry, uid) = match entry.rsplit_once(" uid:") {
+            Some((entry, uid)) => (entry, Some(uid.parse().ok()?)),
+            None => (entry, None),
+        };
+        // Apk paths may contain `=` themselves, package names cannot.
+        let (apk_path, name) = match entry.rsplit_once('=') {
+            Some((path, name)) if apk_paths => (Some(UnixPathBuf::from(path)), name),
+            _ => (None, entry),
+        };
+
+        Some(PackageListEntry {
+            name: name.to_owned(),
+            apk_path,
+            uid,
+        })
+    }
+}
+
+impl Device {
+    /// Builds the install command for an apk staged at `tmp_apk_path`.
+    pub(crate) async fn install_command(
+        &self,
+        tmp_apk_path: &UnixPath,
+        options: &InstallOptions,
+    ) -> Result<String> {
+        let capabilities = self.capabilities().await?;
+        Ok(format!(
+            "{} install{} \"{}\"",
+            capabilities.package_manager(),
+            options.args(&capabilities, self.user)?,
+            tmp_apk_path.display()
+        ))
+    }
+
+    /// Lists packages with `pm list packages`, sorted by name.
+    pub async fn list_packages_with(
+        &self,
+        options: &PackageListOptions,
+    ) -> Result<Vec<PackageListEntry>> {
+        let pm = self.capabilities().await?.package_manager();
+        let command = format!("{pm} list packages{}{}", self.user_arg(), options.args()?);
+        let output = self.execute_host_shell_command(&command).await?;
+        let mut packages = output
+            .lines()
+            .filter(|line| line.starts_with("package:"))
+            .map(|line| {
+                PackageListEntry::parse(line, options.apk_paths).ok_or_else(|| {
+                    DeviceError::Adb(format!("Failed to parse package list line: {line}"))
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(packages)
+    }
+
+    /// Rolls `package` back to the version it replaced, keeping its data.
+    ///
+    /// Only possible for updates installed with
+    /// [`InstallOptions::enable_rollback`], on Android 10 and later.
+    pub async fn rollback_package(&self, package: &str) -> Result<()> {
+        self.check_writable("roll back packages")?;
+        let capabilities = self.capabilities().await?;
+        if !capabilities.supports_rollback() {
+            return Err(DeviceError::MissingFeature("rollback".to_owned()));
+        }
+
+        let command = format!("{} rollback-app {package}", capabilities.package_manager());
+        let output = self.execute_host_shell_command(&command).await?;
+        if !output.starts_with("Success") {
+            return Err(DeviceError::PackageManagerError(output));
+        }
+
+        Ok(())
+    }
+}
+
+/// Fails unless `package` looks like a package name, which never needs
+/// quoting in a shell command or escapes the directory it is joined to.
+pub(crate) fn check_package_name(package: &str) -> Result<()> {
+    if package.is_empty()
+        || !package
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_'))
+        || package.split('.').any(str::is_empty)
+    {
+        return Err(DeviceError::Adb(format!(
+            "invalid package name '{package}'"
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn install_args_by_release() {
+        let options = InstallOptions::new()
+            .reinstall(true)
+            .grant_runtime_permissions(true)
+            .allow_downgrade(true)
+            .install_location(InstallLocation::InternalOnly)
+            .installer("com.android.vending")
+            .dont_kill(true);
+        assert_eq!(
+            options.args(&Capabilities::new(21), None).expect("args"),
+            " -r -d --install-location 1 -i com.android.vending"
+        );
+        assert_eq!(
+            options
+                .args(&Capabilities::new(30), Some(10))
+                .expect("args"),
+            " --user 10 -r -g -d --install-location 1 -i com.android.vending --dont-kill"
+        );
+
+        let instant = InstallOptions::new().instant(true).user(0);
+        assert_eq!(
+            instant
+                .args(&Capabilities::new(25), Some(10))
+                .expect("args"),
+            " --user 0 --ephemeral"
+        );
+        assert_eq!(
+            instant.args(&Capabilities::new(26), None).expect("args"),
+            " --user 0 --instant"
+        );
+        assert!(matches!(
+            instant.args(&Capabilities::new(23), None),
+            Err(DeviceError::MissingFeature(_))
+        ));
+    }
+
+    #[test]
+    fn install_args_reject_unsafe_values() {
+        let capabilities = Capabilities::new(30);
+        assert_eq!(
+            InstallOptions::new()
+                .abi("arm64-v8a")
+                .args(&capabilities, None)
+                .expect("args"),
+            " --abi arm64-v8a"
+        );
+        for options in [
+            InstallOptions::new().installer("x; reboot"),
+            InstallOptions::new().abi("arm64 v8a"),
+            InstallOptions::new().abi(""),
+        ] {
+            assert!(matches!(
+                options.args(&capabilities, None),
+                Err(DeviceError::Adb(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn package_list_args_and_entries() {
+        let options = PackageListOptions::new()
+            .apk_paths(true)
+            .system(true)
+            .disabled(true)
+            .uid(1000)
+            .filter("com.android");
+        assert_eq!(
+            options.args().expect("args"),
+            " -f -d -s --uid 1000 com.android"
+        );
+        assert!(PackageListOptions::new()
+            .filter("a; reboot")
+            .args()
+            .is_err());
+
+        assert_eq!(
+            PackageListEntry::parse(
+                "package:/data/app/~~Xy==/org.example-Ab==/base.apk=org.example uid:10123",
+                true
+            ),
+            Some(PackageListEntry {
+                name: "org.example".to_owned(),
+                apk_path: Some(UnixPathBuf::from(
+                    "/data/app/~~Xy==/org.example-Ab==/base.apk"
+                )),
+                uid: Some(10123),
+            })
+        );
+        assert_eq!(
+            PackageListEntry::parse("package:android", false),
+            Some(PackageListEntry {
+                name: "android".to_owned(),
+                apk_path: None,
+                uid: None,
+            })
+        );
+    }
+
+    #[test]
+    fn uninstall_args_and_failures() {
+        let options = UninstallOptions::new().keep_data(true).version_code(42);
+        assert_eq!(
+            options
+                .args(&Capabilities::new(34), Some(10))
+                .expect("args"),
+            " -k --user 10 --versionCode 42"
+        );
+        assert!(options.args(&Capabilities::new(25), None).is_err());
+
+        assert_eq!(
+            UninstallFailure::parse("Failure [DELETE_FAILED_DEVICE_POLICY_MANAGER]\n"),
+            UninstallFailure::DevicePolicyManager
+        );
+        assert_eq!(
+            UninstallFailure::parse("Failure [not installed for 0]\n"),
+            UninstallFailure::NotInstalled
+        );
+        assert_eq!(
+            UninstallFailure::parse("Error: java.lang.SecurityException\n"),
+            UninstallFailure::Other("Error: java.lang.SecurityException".to_owned())
+        );
+    }
+}
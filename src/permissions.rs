@@ -0,0 +1,178 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Runtime permission report: which apps may use the camera, microphone,
+//! location, contacts and other dangerous permissions, and who decided so.
+
+use crate::{Device, Result};
+
+/// A runtime permission of a package for one user, a row of
+/// [`Device::permission_report`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PermissionGrant {
+    pub package: String,
+    pub uid: Option<u32>,
+    pub user_id: u32,
+    /// E.g. `android.permission.CAMERA`.
+    pub permission: String,
+    pub granted: bool,
+    /// Grant flags, e.g. `USER_SET`, `USER_FIXED`, `POLICY_FIXED` or
+    /// `GRANTED_BY_DEFAULT`.
+    pub flags: Vec<String>,
+}
+
+impl PermissionGrant {
+    /// Whether `flag`, e.g. `USER_SET`, is among the grant flags.
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|known| known == flag)
+    }
+}
+
+impl Device {
+    /// Lists the runtime permissions of every package with their granted
+    /// state and grant flags, from `dumpsys package packages`.
+    ///
+    /// Covers all users, or only [`Device::user`] when it is set.  Devices
+    /// before Android 6.0 have no runtime permissions and report none.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn permission_report(&self) -> Result<Vec<PermissionGrant>> {
+        let output = self
+            .execute_host_shell_command("dumpsys package packages")
+            .await?;
+        let mut grants = parse_runtime_permissions(&output);
+        if let Some(user) = self.user {
+            grants.retain(|grant| grant.user_id == user);
+        }
+        Ok(grants)
+    }
+}
+
+/// Parses the `Package [com.example] (1a2b3c):` sections of `dumpsys
+/// package`: `userId=`, then per `User 0:` a `runtime permissions:` list of
+/// `android.permission.CAMERA: granted=true, flags=[ USER_SET|... ]`.
+fn parse_runtime_permissions(output: &str) -> Vec<PermissionGrant> {
+    let mut grants = Vec::new();
+    let mut package: Option<(String, Option<u32>)> = None;
+    let mut user_id = None;
+    let mut runtime_indent = None;
+
+    for line in output.lines() {
+        let text = line.trim();
+        if text.is_empty() {
+            continue;
+        }
+        let indent = line.len() - line.trim_start().len();
+        if indent == 0 {
+            // `Packages:` or the next section, e.g. `Shared users:`.
+            package = None;
+            continue;
+        }
+        if runtime_indent.is_some_and(|runtime_indent| indent <= runtime_indent) {
+            runtime_indent = None;
+        }
+
+        if let Some(name) = text.strip_prefix("Package [") {
+            package = name
+                .split_once(']')
+                .map(|(name, _)| (name.to_owned(), None));
+            user_id = None;
+            runtime_indent = None;
+            continue;
+        }
+        let Some((name, uid)) = package.as_mut() else {
+            continue;
+        };
+
+        if runtime_indent.is_some() {
+            let Some((permission, rest)) = text.split_once(": granted=") else {
+                continue;
+            };
+            let flags = rest
+                .split_once("flags=[")
+                .and_then(|(_, flags)| flags.split_once(']'))
+                .map(|(flags, _)| {
+                    flags
+                        .split('|')
+                        .map(str::trim)
+                        .filter(|flag| !flag.is_empty())
+                        .map(str::to_owned)
+                        .collect()
+                })
+                .unwrap_or_default();
+            grants.push(PermissionGrant {
+                package: name.clone(),
+                uid: *uid,
+                user_id: user_id.unwrap_or_default(),
+                permission: permission.to_owned(),
+                granted: rest.starts_with("true"),
+                flags,
+            });
+        } else if let Some(id) = text.strip_prefix("userId=") {
+            *uid = id.split_whitespace().next().and_then(|id| id.parse().ok());
+        } else if let Some(user) = text.strip_prefix("User ") {
+            user_id = user.split(':').next().and_then(|id| id.parse().ok());
+        } else if text == "runtime permissions:" && user_id.is_some() {
+            runtime_indent = Some(indent);
+        }
+    }
+    grants
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_runtime_permissions() {
+        let grants = parse_runtime_permissions(
+            "Packages:\n\
+             \x20 Package [com.example.app] (1a2b3c):\n\
+             \x20   userId=10123\n\
+             \x20   requested permissions:\n\
+             \x20     android.permission.CAMERA\n\
+             \x20   install permissions:\n\
+             \x20     android.permission.INTERNET: granted=true\n\
+             \x20   User 0: ceDataInode=1234 installed=true hidden=false\n\
+             \x20     gids=[3003]\n\
+             \x20     runtime permissions:\n\
+             \x20       android.permission.CAMERA: granted=true, flags=[ USER_SET|USER_SENSITIVE_WHEN_GRANTED ]\n\
+             \x20       android.permission.ACCESS_FINE_LOCATION: granted=false, flags=[ USER_FIXED ]\n\
+             \x20     enabledComponents:\n\
+             \x20       com.example.app.Receiver\n\
+             \x20   User 10: ceDataInode=0 installed=true hidden=false\n\
+             \x20     runtime permissions:\n\
+             \x20       android.permission.CAMERA: granted=false\n\
+             \x20 Package [com.example.other] (4d5e6f):\n\
+             \x20   userId=10124\n\
+             \n\
+             Shared users:\n\
+             \x20 SharedUser [android.uid.system] (7a8b9c):\n\
+             \x20   User 0:\n\
+             \x20     runtime permissions:\n\
+             \x20       android.permission.CAMERA: granted=true\n",
+        );
+        assert_eq!(grants.len(), 3);
+        assert_eq!(
+            grants[0],
+            PermissionGrant {
+                package: "com.example.app".to_owned(),
+                uid: Some(10123),
+                user_id: 0,
+                permission: "android.permission.CAMERA".to_owned(),
+                granted: true,
+                flags: vec![
+                    "USER_SET".to_owned(),
+                    "USER_SENSITIVE_WHEN_GRANTED".to_owned()
+                ],
+            }
+        );
+        assert!(!grants[1].granted);
+        assert!(grants[1].has_flag("USER_FIXED"));
+        assert_eq!(grants[2].user_id, 10);
+        assert!(grants[2].flags.is_empty());
+    }
+}
@@ -0,0 +1,111 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Doze whitelist: apps exempt from battery optimization, which keeps them
+//! running in the background.
+
+use crate::{Device, DeviceError, Result};
+
+/// An app of [`PowerWhitelist`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PowerWhitelistEntry {
+    pub package: String,
+    pub uid: Option<u32>,
+    /// Exempt from app standby and background restrictions but still
+    /// subject to Doze (`system-excidle`).
+    pub except_idle: bool,
+}
+
+/// Result of [`Device::power_whitelist`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PowerWhitelist {
+    /// Exemptions the system image grants.
+    pub system: Vec<PowerWhitelistEntry>,
+    /// Exemptions granted by the user or with `dumpsys deviceidle
+    /// whitelist +<package>`.
+    pub user: Vec<PowerWhitelistEntry>,
+}
+
+impl PowerWhitelist {
+    /// Whether `package` is exempt, by the system or the user.
+    pub fn is_exempt(&self, package: &str) -> bool {
+        self.system
+            .iter()
+            .chain(&self.user)
+            .any(|entry| entry.package == package)
+    }
+}
+
+impl Device {
+    /// Lists the apps exempt from battery optimization, from `dumpsys
+    /// deviceidle whitelist`.
+    ///
+    /// Fails with [`DeviceError::MissingFeature`] before Android 6.0, which
+    /// has no Doze.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn power_whitelist(&self) -> Result<PowerWhitelist> {
+        let output = self
+            .execute_host_shell_command("dumpsys deviceidle whitelist")
+            .await?;
+        if output.contains("Can't find service") {
+            return Err(DeviceError::MissingFeature("deviceidle".to_owned()));
+        }
+        Ok(parse_power_whitelist(&output))
+    }
+}
+
+/// Parses `system-excidle,com.android.providers.downloads,10012`,
+/// `system,com.google.android.gms,10089` and `user,com.example,10123`
+/// lines.
+fn parse_power_whitelist(output: &str) -> PowerWhitelist {
+    let mut whitelist = PowerWhitelist::default();
+    for line in output.lines() {
+        let mut fields = line.trim().split(',');
+        let (Some(kind), Some(package)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        let (list, except_idle) = match kind {
+            "system" => (&mut whitelist.system, false),
+            "system-excidle" => (&mut whitelist.system, true),
+            "user" => (&mut whitelist.user, false),
+            _ => continue,
+        };
+        list.push(PowerWhitelistEntry {
+            package: package.to_owned(),
+            uid: fields.next().and_then(|uid| uid.trim().parse().ok()),
+            except_idle,
+        });
+    }
+    whitelist
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_power_whitelist() {
+        let whitelist = parse_power_whitelist(
+            "system-excidle,com.android.providers.downloads,10012\n\
+             system,com.google.android.gms,10089\n\
+             user,com.example.app,10123\n\
+             Unknown option: bogus\n",
+        );
+        assert_eq!(whitelist.system.len(), 2);
+        assert!(whitelist.system[0].except_idle);
+        assert_eq!(
+            whitelist.user,
+            [PowerWhitelistEntry {
+                package: "com.example.app".to_owned(),
+                uid: Some(10123),
+                except_idle: false,
+            }]
+        );
+        assert!(whitelist.is_exempt("com.google.android.gms"));
+        assert!(!whitelist.is_exempt("com.example.other"));
+    }
+}
@@ -0,0 +1,662 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Device processes: signalling and killing them, falling back to the
+//! activity manager for app processes the shell user may not signal, and
+//! their standing with the low memory killer.
+
+#[cfg(not(feature = "tracing"))]
+use log::debug;
+use tokio::time::{sleep, Duration, Instant};
+#[cfg(feature = "tracing")]
+use tracing::debug;
+
+use crate::{Device, DeviceError, Result, ShellOutput, SuStrategy};
+
+/// How long a terminating signal or `am force-stop` may take to end the
+/// process.
+const PROCESS_EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long `am kill` is given before `am force-stop`; it only ends
+/// processes in the background, so it either works quickly or not at all.
+const AM_KILL_TIMEOUT: Duration = Duration::from_secs(1);
+const PROCESS_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A signal for [`Device::kill_process`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    Hup,
+    Int,
+    Quit,
+    Kill,
+    Usr1,
+    Usr2,
+    Term,
+    Cont,
+    Stop,
+}
+
+impl Signal {
+    /// The signal number on Linux.
+    pub fn number(&self) -> u32 {
+        match self {
+            Signal::Hup => 1,
+            Signal::Int => 2,
+            Signal::Quit => 3,
+            Signal::Kill => 9,
+            Signal::Usr1 => 10,
+            Signal::Usr2 => 12,
+            Signal::Term => 15,
+            Signal::Cont => 18,
+            Signal::Stop => 19,
+        }
+    }
+
+    /// Whether the signal ends a process that does not handle it, so that
+    /// [`Device::kill_process`] waits for the process to exit.
+    pub fn terminates(&self) -> bool {
+        matches!(
+            self,
+            Signal::Hup | Signal::Int | Signal::Quit | Signal::Kill | Signal::Term
+        )
+    }
+}
+
+/// Importance the activity manager assigned a process, from the
+/// `oom_score_adj` bands of `ProcessList`; later variants are killed first
+/// under memory pressure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OomPriority {
+    /// Native daemons, never killed.
+    Native,
+    /// `system_server`.
+    System,
+    /// Persistent apps and their services, e.g. the phone app.
+    Persistent,
+    /// The app the user is interacting with.
+    Foreground,
+    /// Apps with visible activities.
+    Visible,
+    /// Apps the user would notice being killed, e.g. playing music.
+    Perceptible,
+    /// Apps running a backup.
+    Backup,
+    /// Heavy-weight apps that cannot save their state.
+    HeavyWeight,
+    /// Apps running services.
+    Service,
+    /// The launcher.
+    Home,
+    /// The app the user was in before.
+    Previous,
+    /// Services that have been running for a long time.
+    ServiceB,
+    /// Cached apps, the first to go.
+    Cached,
+}
+
+impl OomPriority {
+    /// Classifies an `oom_score_adj` value.
+    pub fn from_adj(adj: i32) -> OomPriority {
+        match adj {
+            i32::MIN..=-1000 => OomPriority::Native,
+            -999..=-801 => OomPriority::System,
+            -800..=-1 => OomPriority::Persistent,
+            0..=99 => OomPriority::Foreground,
+            100..=199 => OomPriority::Visible,
+            200..=299 => OomPriority::Perceptible,
+            300..=399 => OomPriority::Backup,
+            400..=499 => OomPriority::HeavyWeight,
+            500..=599 => OomPriority::Service,
+            600..=699 => OomPriority::Home,
+            700..=799 => OomPriority::Previous,
+            800..=899 => OomPriority::ServiceB,
+            900..=i32::MAX => OomPriority::Cached,
+        }
+    }
+}
+
+/// Where a process is in the activity manager's least recently used list,
+/// from `dumpsys activity lru`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LruPosition {
+    /// Index in the list; 0 is the least recently used process.
+    pub position: u32,
+    /// Number of processes in the list.
+    pub total: u32,
+    /// Adjustment as abbreviated by `dumpsys`, e.g. `fg` or `cch+75`.
+    pub adj_label: String,
+    /// Process state as abbreviated by `dumpsys`, e.g. `TOP` or `CEM`.
+    pub proc_state: String,
+}
+
+/// Result of [`Device::process_oom_info`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OomInfo {
+    pub pid: u32,
+    /// Badness the kernel ranks processes by when out of memory, 0 to 1000.
+    pub oom_score: u32,
+    /// Adjustment the activity manager set, -1000 to 1000.
+    pub oom_score_adj: i32,
+    /// `None` for processes the activity manager does not track, like
+    /// native daemons.
+    pub lru: Option<LruPosition>,
+}
+
+impl OomInfo {
+    pub fn priority(&self) -> OomPriority {
+        OomPriority::from_adj(self.oom_score_adj)
+    }
+}
+
+/// What a file descriptor of [`Device::process_fds`] refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FdTarget {
+    /// A file or device node, e.g. an open database.
+    File(String),
+    /// A file that was deleted while open, without the ` (deleted)` suffix.
+    Deleted(String),
+    /// A socket by inode, as listed in `/proc/net/{tcp,udp,unix}`.
+    Socket(u64),
+    /// A pipe by inode.
+    Pipe(u64),
+    /// An anonymous inode, e.g. `eventfd`, `sync_file` or `dmabuf`.
+    AnonInode(String),
+    /// Any other link target.
+    Other(String),
+}
+
+impl FdTarget {
+    fn parse(target: &str) -> FdTarget {
+        let inode = |prefix: &str| {
+            target
+                .strip_prefix(prefix)
+                .and_then(|rest| rest.strip_prefix('['))
+                .and_then(|rest| rest.strip_suffix(']'))
+        };
+        if let Some(path) = target.strip_suffix(" (deleted)") {
+            FdTarget::Deleted(path.to_owned())
+        } else if target.starts_with('/') {
+            FdTarget::File(target.to_owned())
+        } else if let Some(inode) = inode("socket:").and_then(|i| i.parse().ok()) {
+            FdTarget::Socket(inode)
+        } else if let Some(inode) = inode("pipe:").and_then(|i| i.parse().ok()) {
+            FdTarget::Pipe(inode)
+        } else if let Some(name) = target.strip_prefix("anon_inode:") {
+            let name = name.trim_start_matches('[').trim_end_matches(']');
+            FdTarget::AnonInode(name.to_owned())
+        } else {
+            FdTarget::Other(target.to_owned())
+        }
+    }
+}
+
+/// An open file descriptor of a process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessFd {
+    pub fd: u32,
+    pub target: FdTarget,
+}
+
+/// A mapped memory region of a process, a line of `/proc/PID/maps`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryMapping {
+    pub start: u64,
+    /// First address after the region.
+    pub end: u64,
+    pub readable: bool,
+    pub writable: bool,
+    pub executable: bool,
+    /// Shared with other processes rather than copy-on-write.
+    pub shared: bool,
+    /// Offset into the mapped file.
+    pub offset: u64,
+    /// Device of the mapped file as `major:minor` in hex.
+    pub device: String,
+    /// Inode of the mapped file, 0 for anonymous memory.
+    pub inode: u64,
+    /// Mapped file or pseudo path such as `[stack]` or `[anon:libc_malloc]`;
+    /// `None` for unnamed anonymous memory.
+    pub path: Option<String>,
+}
+
+impl MemoryMapping {
+    pub fn size(&self) -> u64 {
+        self.end.saturating_sub(self.start)
+    }
+
+    fn parse(line: &str) -> Option<MemoryMapping> {
+        let mut fields = line.splitn(6, ' ');
+        let (start, end) = fields.next()?.split_once('-')?;
+        let start = u64::from_str_radix(start, 16).ok()?;
+        let end = u64::from_str_radix(end, 16).ok()?;
+        if end < start {
+            return None;
+        }
+        let perms = fields.next()?.as_bytes();
+        if perms.len() != 4 {
+            return None;
+        }
+        let offset = fields.next()?;
+        let device = fields.next()?;
+        let inode = fields.next()?;
+        let path = fields.next().map(str::trim).filter(|path| !path.is_empty());
+
+        Some(MemoryMapping {
+            start,
+            end,
+            readable: perms[0] == b'r',
+            writable: perms[1] == b'w',
+            executable: perms[2] == b'x',
+            shared: perms[3] == b's',
+            offset: u64::from_str_radix(offset, 16).ok()?,
+            device: device.to_owned(),
+            inode: inode.parse().ok()?,
+            path: path.map(str::to_owned),
+        })
+    }
+}
+
+impl Device {
+    /// Sends `signal` to process `pid` and returns whether it existed.
+    ///
+    /// For signals that [terminate](Signal::terminates) a process, waits
+    /// until it is gone, failing if it is still running after 5 seconds.
+    /// Without root the shell user may not signal app processes; those are
+    /// ended with `am kill` and, if it is still running, `am force-stop` of
+    /// the app, whatever `signal` is.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self), fields(serial = %self.serial), err)
+    )]
+    pub async fn kill_process(&self, pid: u32, signal: Signal) -> Result<bool> {
+        self.check_writable("kill processes")?;
+        if !self.process_exists(pid).await? {
+            return Ok(false);
+        }
+
+        let output = self
+            .execute_host_shell_command(&format!("kill -{} {pid}", signal.number()))
+            .await?;
+        let message = output.trim();
+        if message.contains("No such process") {
+            return Ok(false);
+        }
+        if message.contains("not permitted") {
+            self.kill_app_process(pid, message).await?;
+            return Ok(true);
+        }
+        if !message.is_empty() {
+            return Err(DeviceError::Adb(message.to_owned()));
+        }
+
+        if signal.terminates() && !self.wait_for_exit(pid, PROCESS_EXIT_TIMEOUT).await? {
+            return Err(DeviceError::Adb(format!(
+                "process {pid} is still running after signal {}",
+                signal.number()
+            )));
+        }
+        Ok(true)
+    }
+
+    /// Kills the processes named `name`, e.g. `com.android.chrome` or
+    /// `logd`, with [`Signal::Term`] like [`Device::kill_process`].  Returns
+    /// whether there were any.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self), fields(serial = %self.serial), err)
+    )]
+    pub async fn kill_process_by_name(&self, name: &str) -> Result<bool> {
+        self.check_writable("kill processes")?;
+        let ps = self.capabilities().await?.ps_all();
+        let output = self.execute_host_shell_command(ps).await?;
+
+        let mut existed = false;
+        for pid in parse_ps_pids(&output, name) {
+            existed |= self.kill_process(pid, Signal::Term).await?;
+        }
+        Ok(existed)
+    }
+
+    /// Reads how likely process `pid` is to be killed for memory, `None`
+    /// if it is not running.
+    ///
+    /// Combines the kernel's `/proc/PID/oom_score` and `oom_score_adj` with
+    /// the position in `dumpsys activity lru`, in one shell round trip.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self), fields(serial = %self.serial), err)
+    )]
+    pub async fn process_oom_info(&self, pid: u32) -> Result<Option<OomInfo>> {
+        let scores = format!("cat /proc/{pid}/oom_score /proc/{pid}/oom_score_adj");
+        let outputs = self.run_batch(&[&scores, "dumpsys activity lru"]).await?;
+        parse_oom_info(pid, &outputs[0], &outputs[1].stdout_lossy())
+    }
+
+    /// Lists the open file descriptors of process `pid` from
+    /// `/proc/PID/fd`, e.g. to see which databases and sockets a running
+    /// app holds.
+    ///
+    /// Other users' processes need root, or `run-as` for processes of the
+    /// [`run_as_package`](Device::run_as_package).
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self), fields(serial = %self.serial), err)
+    )]
+    pub async fn process_fds(&self, pid: u32) -> Result<Vec<ProcessFd>> {
+        let output = self
+            .process_command(pid, &format!("ls -l /proc/{pid}/fd"))
+            .await?;
+        parse_fds(&output)
+    }
+
+    /// Reads the memory mappings of process `pid` from `/proc/PID/maps`,
+    /// e.g. to see which libraries a running app loaded.  Permissions as
+    /// for [`Device::process_fds`].
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self), fields(serial = %self.serial), err)
+    )]
+    pub async fn process_maps(&self, pid: u32) -> Result<Vec<MemoryMapping>> {
+        let output = self
+            .process_command(pid, &format!("cat /proc/{pid}/maps"))
+            .await?;
+        output
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                MemoryMapping::parse(line).ok_or_else(|| DeviceError::Adb(line.trim().to_owned()))
+            })
+            .collect()
+    }
+
+    /// Runs `command` inspecting process `pid`, via `run-as` if needed.
+    async fn process_command(&self, pid: u32, command: &str) -> Result<String> {
+        let enable_run_as = self.run_as_for_process(pid).await?;
+        self.execute_host_shell_command_as(command, enable_run_as)
+            .await
+    }
+
+    async fn process_exists(&self, pid: u32) -> Result<bool> {
+        let output = self
+            .execute_host_shell_command(&format!("[ -d /proc/{pid} ] && echo alive"))
+            .await?;
+        Ok(output.trim() == "alive")
+    }
+
+    /// Polls until `pid` is gone; `false` if it outlived `timeout`.
+    async fn wait_for_exit(&self, pid: u32, timeout: Duration) -> Result<bool> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if !self.process_exists(pid).await? {
+                return Ok(true);
+            }
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+            sleep(PROCESS_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Ends the app owning `pid` through the activity manager, after `kill`
+    /// failed with `denied`.
+    async fn kill_app_process(&self, pid: u32, denied: &str) -> Result<()> {
+        let Some(package) = self.process_package(pid).await? else {
+            return Err(DeviceError::Adb(denied.to_owned()));
+        };
+        let package = package.as_str();
+
+        debug!("Killing {} ({}) through the activity manager", package, pid);
+        self.execute_host_shell_command(&format!("am kill{} {package}", self.user_arg()))
+            .await?;
+        if self.wait_for_exit(pid, AM_KILL_TIMEOUT).await? {
+            return Ok(());
+        }
+        self.force_stop(package).await?;
+        if self.wait_for_exit(pid, PROCESS_EXIT_TIMEOUT).await? {
+            return Ok(());
+        }
+        Err(DeviceError::Adb(format!(
+            "process {pid} of {package} is still running after am force-stop"
+        )))
+    }
+
+    /// The package an app process belongs to, `None` for native processes.
+    async fn process_package(&self, pid: u32) -> Result<Option<String>> {
+        let cmdline = self
+            .execute_host_shell_command(&format!("cat /proc/{pid}/cmdline"))
+            .await?;
+        // App processes are named after the package, `com.app:service` for
+        // secondary processes.
+        let name = cmdline.split('\0').next().unwrap_or_default();
+        let package = name.split(':').next().unwrap_or_default();
+        let valid = package.contains('.')
+            && package
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_'));
+        Ok(valid.then(|| package.to_owned()))
+    }
+
+    /// Whether commands about `pid` go through `run-as`: without a
+    /// [`SuStrategy`], processes of the
+    /// [`run_as_package`](Device::run_as_package) can only be inspected as
+    /// their own user.
+    async fn run_as_for_process(&self, pid: u32) -> Result<bool> {
+        let Some(run_as_package) = &self.run_as_package else {
+            return Ok(false);
+        };
+        if self.su != SuStrategy::None {
+            return Ok(false);
+        }
+        Ok(self.process_package(pid).await?.as_ref() == Some(run_as_package))
+    }
+}
+
+fn parse_oom_info(pid: u32, scores: &ShellOutput, lru: &str) -> Result<Option<OomInfo>> {
+    if !scores.success() {
+        return Ok(None);
+    }
+    let scores = scores.stdout_lossy();
+    let mut lines = scores.lines().map(str::trim);
+    let (Some(oom_score), Some(oom_score_adj)) = (lines.next(), lines.next()) else {
+        return Err(DeviceError::Adb(format!(
+            "unexpected oom scores of process {pid}: {}",
+            scores.trim()
+        )));
+    };
+    Ok(Some(OomInfo {
+        pid,
+        oom_score: oom_score.parse()?,
+        oom_score_adj: oom_score_adj.parse()?,
+        lru: parse_lru_position(lru, pid),
+    }))
+}
+
+/// Finds `pid` in `dumpsys activity lru` output, whose entries look like
+/// `#45: fg     TOP  LCMN 12345:com.android.chrome/u0a123 act:activities`
+/// (fewer columns on older releases).
+fn parse_lru_position(output: &str, pid: u32) -> Option<LruPosition> {
+    let mut total = 0;
+    let mut found = None;
+    for line in output.lines() {
+        let Some((position, rest)) = line
+            .trim()
+            .strip_prefix('#')
+            .and_then(|l| l.split_once(':'))
+        else {
+            continue;
+        };
+        let Ok(position) = position.parse::<u32>() else {
+            continue;
+        };
+        total += 1;
+
+        let columns: Vec<_> = rest.split_whitespace().collect();
+        let process = columns.iter().position(|column| {
+            column
+                .split_once(':')
+                .is_some_and(|(p, name)| p.parse() == Ok(pid) && name.contains('/'))
+        });
+        if matches!(process, Some(2..)) {
+            found = Some((position, columns[0].to_owned(), columns[1].to_owned()));
+        }
+    }
+    found.map(|(position, adj_label, proc_state)| LruPosition {
+        position,
+        total,
+        adj_label,
+        proc_state,
+    })
+}
+
+/// Parses `ls -l` of `/proc/PID/fd`, whose lines end in `FD -> TARGET`;
+/// any other line is an error message.
+fn parse_fds(output: &str) -> Result<Vec<ProcessFd>> {
+    output
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with("total "))
+        .map(|line| parse_fd_line(line).ok_or_else(|| DeviceError::Adb(line.trim().to_owned())))
+        .collect()
+}
+
+pub(crate) fn parse_fd_line(line: &str) -> Option<ProcessFd> {
+    let (attributes, target) = line.split_once(" -> ")?;
+    Some(ProcessFd {
+        fd: attributes.split_whitespace().last()?.parse().ok()?,
+        target: FdTarget::parse(target),
+    })
+}
+
+/// The pids of the processes named `name` in `ps` output, whose second
+/// column is the pid and last column the name, with toybox and toolbox.
+fn parse_ps_pids(output: &str, name: &str) -> Vec<u32> {
+    output
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            let pid = columns.nth(1)?.parse().ok()?;
+            (columns.last()? == name).then_some(pid)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_oom_adj() {
+        assert_eq!(OomPriority::from_adj(-1000), OomPriority::Native);
+        assert_eq!(OomPriority::from_adj(-900), OomPriority::System);
+        assert_eq!(OomPriority::from_adj(0), OomPriority::Foreground);
+        assert_eq!(OomPriority::from_adj(250), OomPriority::Perceptible);
+        assert_eq!(OomPriority::from_adj(975), OomPriority::Cached);
+        assert!(OomPriority::Cached > OomPriority::Visible);
+    }
+
+    #[test]
+    fn finds_lru_position() {
+        let output = "ACTIVITY MANAGER LRU PROCESSES (dumpsys activity lru)\n\
+                      Activities:\n\
+                      #2: fg     TOP  LCMN 12345:com.android.chrome/u0a123 act:activities|recents\n\
+                      Other:\n\
+                      #1: pers   PER  LCMN 1234:com.android.systemui/u0a50\n\
+                      #0: cch+75 CEM  ---- 5678:com.example.app/u0a99\n";
+        assert_eq!(
+            parse_lru_position(output, 5678),
+            Some(LruPosition {
+                position: 0,
+                total: 3,
+                adj_label: "cch+75".to_owned(),
+                proc_state: "CEM".to_owned(),
+            })
+        );
+        assert_eq!(
+            parse_lru_position(output, 12345).map(|lru| lru.position),
+            Some(2)
+        );
+        assert_eq!(parse_lru_position(output, 123), None);
+
+        let old = "  #12: fore   T    4321:com.android.launcher/u0a20 act:activities\n";
+        assert_eq!(
+            parse_lru_position(old, 4321).map(|lru| lru.proc_state),
+            Some("T".to_owned())
+        );
+    }
+
+    #[test]
+    fn parses_fds() {
+        let output = "total 0\n\
+                      lrwx------ 1 u0_a99 u0_a99 64 2024-05-01 10:00 0 -> /dev/null\n\
+                      lrwx------ 1 u0_a99 u0_a99 64 2024-05-01 10:00 41 -> /data/data/com.example.app/databases/app.db\n\
+                      lrwx------ 1 u0_a99 u0_a99 64 2024-05-01 10:00 42 -> /data/data/com.example.app/cache/tmp (deleted)\n\
+                      lrwx------ 1 u0_a99 u0_a99 64 2024-05-01 10:00 43 -> socket:[123456]\n\
+                      lr-x------ 1 u0_a99 u0_a99 64 2024-05-01 10:00 44 -> pipe:[7890]\n\
+                      lrwx------ 1 u0_a99 u0_a99 64 2024-05-01 10:00 45 -> anon_inode:[eventfd]\n\
+                      lrwx------ 1 u0_a99 u0_a99 64 2024-05-01 10:00 46 -> anon_inode:sync_file\n";
+        let targets: Vec<_> = parse_fds(output)
+            .expect("fds")
+            .into_iter()
+            .map(|fd| (fd.fd, fd.target))
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                (0, FdTarget::File("/dev/null".to_owned())),
+                (
+                    41,
+                    FdTarget::File("/data/data/com.example.app/databases/app.db".to_owned())
+                ),
+                (
+                    42,
+                    FdTarget::Deleted("/data/data/com.example.app/cache/tmp".to_owned())
+                ),
+                (43, FdTarget::Socket(123456)),
+                (44, FdTarget::Pipe(7890)),
+                (45, FdTarget::AnonInode("eventfd".to_owned())),
+                (46, FdTarget::AnonInode("sync_file".to_owned())),
+            ]
+        );
+
+        assert!(parse_fds("ls: /proc/1/fd: Permission denied\n").is_err());
+    }
+
+    #[test]
+    fn parses_maps() {
+        let lib = MemoryMapping::parse(
+            "7b2c4e1000-7b2c4e5000 r-xp 00042000 fd:05 1234                       /system/lib64/libc.so",
+        )
+        .expect("lib");
+        assert_eq!(
+            (lib.start, lib.size(), lib.offset),
+            (0x7b2c4e1000, 0x4000, 0x42000)
+        );
+        assert!(lib.readable && !lib.writable && lib.executable && !lib.shared);
+        assert_eq!((lib.device.as_str(), lib.inode), ("fd:05", 1234));
+        assert_eq!(lib.path.as_deref(), Some("/system/lib64/libc.so"));
+
+        let anonymous = MemoryMapping::parse("7b2c500000-7b2c600000 rw-s 00000000 00:00 0 ")
+            .expect("anonymous");
+        assert!(anonymous.shared);
+        assert_eq!(anonymous.path, None);
+
+        assert!(MemoryMapping::parse("cat: /proc/1/maps: Permission denied").is_none());
+        assert!(MemoryMapping::parse("7b2c600000-7b2c500000 r--p 00000000 00:00 0").is_none());
+    }
+
+    #[test]
+    fn finds_pids_by_name() {
+        let toybox = "USER           PID  PPID     VSZ    RSS WCHAN            ADDR S NAME\n\
+                      root             1     0 10943108 11560 0                 0 S init\n\
+                      u0_a123      12345   789 15264072 201980 0                0 S com.android.chrome\n\
+                      u0_a123      12399   789 14988332 120440 0                0 S com.android.chrome:sandboxed_process0\n";
+        assert_eq!(parse_ps_pids(toybox, "com.android.chrome"), [12345]);
+        assert_eq!(parse_ps_pids(toybox, "init"), [1]);
+
+        let toolbox = "USER     PID   PPID  VSIZE  RSS     WCHAN    PC         NAME\n\
+                       system    612   198   1567152 55236 ffffffff 00000000 S com.android.settings\n";
+        assert_eq!(parse_ps_pids(toolbox, "com.android.settings"), [612]);
+        assert!(parse_ps_pids(toolbox, "USER").is_empty());
+    }
+}
@@ -0,0 +1,160 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Work profiles and where their data is stored.
+//!
+//! A work profile is a separate Android user; to run the `pm` and `am`
+//! helpers against it, set [`Device::user`] to its
+//! [`user_id`](ManagedProfile::user_id).
+
+use crate::encryption::UserRunState;
+use crate::{Device, Result, UnixPathBuf};
+
+/// `UserInfo.FLAG_MANAGED_PROFILE`.
+const FLAG_MANAGED_PROFILE: u32 = 0x20;
+
+/// A work profile of [`Device::managed_profiles`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManagedProfile {
+    pub user_id: u32,
+    pub name: Option<String>,
+    /// User the profile belongs to, Android 11 and later.
+    pub parent_user_id: Option<u32>,
+    /// `None` when the profile is not running, e.g. while work apps are
+    /// paused.
+    pub state: Option<UserRunState>,
+}
+
+impl ManagedProfile {
+    /// Root of the apps' credential encrypted data, `/data/user/<id>`.
+    pub fn ce_data_root(&self) -> UnixPathBuf {
+        UnixPathBuf::from(format!("/data/user/{}", self.user_id))
+    }
+
+    /// Root of the apps' device encrypted data, `/data/user_de/<id>`.
+    pub fn de_data_root(&self) -> UnixPathBuf {
+        UnixPathBuf::from(format!("/data/user_de/{}", self.user_id))
+    }
+
+    /// Shared storage of the profile, `/data/media/<id>`, which only the
+    /// profile itself sees as `/storage/emulated/<id>`.
+    pub fn media_root(&self) -> UnixPathBuf {
+        UnixPathBuf::from(format!("/data/media/{}", self.user_id))
+    }
+}
+
+/// A user as `dumpsys user` lists it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct UserRecord {
+    pub(crate) user_id: u32,
+    pub(crate) name: Option<String>,
+    pub(crate) flags: Option<u32>,
+    /// E.g. `android.os.usertype.profile.MANAGED`, Android 11 and later.
+    pub(crate) user_type: Option<String>,
+    pub(crate) parent_user_id: Option<u32>,
+    pub(crate) state: Option<UserRunState>,
+}
+
+impl Device {
+    /// Lists the work profiles, from `dumpsys user`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn managed_profiles(&self) -> Result<Vec<ManagedProfile>> {
+        let output = self.execute_host_shell_command("dumpsys user").await?;
+        Ok(parse_user_records(&output)
+            .into_iter()
+            .filter(|user| {
+                user.flags
+                    .is_some_and(|flags| flags & FLAG_MANAGED_PROFILE != 0)
+                    || user.user_type.as_deref() == Some("android.os.usertype.profile.MANAGED")
+            })
+            .map(|user| ManagedProfile {
+                user_id: user.user_id,
+                name: user.name,
+                parent_user_id: user.parent_user_id,
+                state: user.state,
+            })
+            .collect())
+    }
+}
+
+/// Parses the `UserInfo{10:Work profile:1030} serialNo=10 isPrimary=false
+/// parentId=0` lines of `dumpsys user`, with hexadecimal flags, and the
+/// `Type:` and `State: RUNNING_UNLOCKED` lines following each.
+pub(crate) fn parse_user_records(output: &str) -> Vec<UserRecord> {
+    let mut users: Vec<UserRecord> = Vec::new();
+    for line in output.lines().map(str::trim) {
+        if let Some(info) = line.strip_prefix("UserInfo{") {
+            let Some((info, rest)) = info.split_once('}') else {
+                continue;
+            };
+            let mut parts = info.splitn(3, ':');
+            let Some(Ok(user_id)) = parts.next().map(str::parse) else {
+                continue;
+            };
+            if users.iter().any(|user| user.user_id == user_id) {
+                continue;
+            }
+            let name = parts
+                .next()
+                .filter(|name| !name.is_empty() && *name != "null")
+                .map(str::to_owned);
+            users.push(UserRecord {
+                user_id,
+                name,
+                flags: parts
+                    .next()
+                    .and_then(|flags| u32::from_str_radix(flags, 16).ok()),
+                user_type: None,
+                parent_user_id: rest
+                    .split_whitespace()
+                    .find_map(|token| token.strip_prefix("parentId="))
+                    .and_then(|id| id.parse().ok()),
+                state: None,
+            });
+        } else if let Some(user) = users.last_mut() {
+            if let Some(user_type) = line.strip_prefix("Type: ") {
+                user.user_type = Some(user_type.trim().to_owned());
+            } else if let Some(state) = line.strip_prefix("State: ") {
+                if user.state.is_none() && state.starts_with(|c: char| c.is_ascii_uppercase()) {
+                    user.state = Some(UserRunState::parse(state.trim()));
+                }
+            }
+        }
+    }
+    users
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_user_records() {
+        let users = parse_user_records(
+            "Users:\n\
+             \x20 UserInfo{0:Owner:c13} serialNo=0 isPrimary=true parentId=-1\n\
+             \x20   Type: android.os.usertype.full.SYSTEM\n\
+             \x20   State: RUNNING_UNLOCKED\n\
+             \x20 UserInfo{10:Work profile:1030} serialNo=10 isPrimary=false parentId=0\n\
+             \x20   Type: android.os.usertype.profile.MANAGED\n\
+             \x20   State: RUNNING_LOCKED\n",
+        );
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[0].parent_user_id, None);
+        assert_eq!(
+            users[1],
+            UserRecord {
+                user_id: 10,
+                name: Some("Work profile".to_owned()),
+                flags: Some(0x1030),
+                user_type: Some("android.os.usertype.profile.MANAGED".to_owned()),
+                parent_user_id: Some(0),
+                state: Some(UserRunState::RunningLocked),
+            }
+        );
+    }
+}
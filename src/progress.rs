@@ -0,0 +1,236 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use tokio::sync::mpsc::{Sender, UnboundedSender};
+
+use crate::{DirectoryTransferProgress, FileTransferProgress};
+
+/// Receives progress updates of long running operations.
+///
+/// Implemented for tokio channels, for closures taking the progress value and,
+/// with the `indicatif` feature, for `indicatif::ProgressBar`.
+pub trait ProgressSink<P>: Send + Sync {
+    fn report(&self, progress: P);
+}
+
+impl<P: Send> ProgressSink<P> for UnboundedSender<P> {
+    fn report(&self, progress: P) {
+        let _ = self.send(progress);
+    }
+}
+
+/// Updates are dropped rather than awaited when the channel is full.
+impl<P: Send> ProgressSink<P> for Sender<P> {
+    fn report(&self, progress: P) {
+        let _ = self.try_send(progress);
+    }
+}
+
+impl<P, F: Fn(P) + Send + Sync> ProgressSink<P> for F {
+    fn report(&self, progress: P) {
+        self(progress)
+    }
+}
+
+/// Controls how often byte progress is reported during a transfer.
+///
+/// Updates are sent every `total / steps` bytes, clamped to
+/// `[min_interval, max_interval]`.  When the total size is unknown an update
+/// is sent every `max_interval` bytes.  The first and last update of a
+/// transfer are always reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgressGranularity {
+    pub steps: u64,
+    pub min_interval: u64,
+    pub max_interval: u64,
+}
+
+impl Default for ProgressGranularity {
+    fn default() -> ProgressGranularity {
+        ProgressGranularity {
+            steps: 100,
+            min_interval: 256 * 1024,
+            max_interval: 4 * 1024 * 1024,
+        }
+    }
+}
+
+impl ProgressGranularity {
+    /// Reports after every chunk written to or read from the device.
+    pub fn every_chunk() -> ProgressGranularity {
+        ProgressGranularity {
+            steps: u64::MAX,
+            min_interval: 0,
+            max_interval: 0,
+        }
+    }
+
+    /// Returns the number of bytes between two updates.
+    pub fn interval(&self, total_bytes: Option<u64>) -> u64 {
+        match total_bytes {
+            Some(total) => (total / self.steps.max(1))
+                .max(self.min_interval)
+                .min(self.max_interval),
+            None => self.max_interval,
+        }
+    }
+}
+
+#[cfg(feature = "indicatif")]
+impl ProgressSink<FileTransferProgress> for indicatif::ProgressBar {
+    fn report(&self, progress: FileTransferProgress) {
+        if let Some(total) = progress.total_bytes {
+            self.set_length(total);
+        }
+        self.set_position(progress.transferred_bytes);
+    }
+}
+
+#[cfg(feature = "indicatif")]
+impl ProgressSink<DirectoryTransferProgress> for indicatif::ProgressBar {
+    fn report(&self, progress: DirectoryTransferProgress) {
+        self.set_length(progress.total_bytes);
+        self.set_position(progress.transferred_bytes);
+        if let Some(file) = progress.current_file {
+            self.set_message(file);
+        }
+    }
+}
+
+/// Install progress is a fraction between 0 and 1, shown as a percentage.
+#[cfg(feature = "indicatif")]
+impl ProgressSink<f32> for indicatif::ProgressBar {
+    fn report(&self, progress: f32) {
+        self.set_length(100);
+        self.set_position((progress * 100.0) as u64);
+    }
+}
+
+/// Forwards per-file progress as directory progress.
+pub(crate) struct DirectoryFileSink<'a> {
+    pub(crate) sink: &'a dyn ProgressSink<DirectoryTransferProgress>,
+    pub(crate) base: DirectoryTransferProgress,
+}
+
+impl ProgressSink<FileTransferProgress> for DirectoryFileSink<'_> {
+    fn report(&self, progress: FileTransferProgress) {
+        let mut update = self.base.clone();
+        update.transferred_bytes += progress.transferred_bytes;
+        update.current_file_progress = progress;
+        self.sink.report(update);
+    }
+}
+
+/// Aggregates the progress of files transferred concurrently, so the
+/// reported byte count covers finished files plus every file in flight.
+pub(crate) struct ConcurrentDirectoryProgress<'a> {
+    sink: Option<&'a dyn ProgressSink<DirectoryTransferProgress>>,
+    state: Mutex<ConcurrentState>,
+}
+
+struct ConcurrentState {
+    /// Counts only finished files.
+    done: DirectoryTransferProgress,
+    /// Bytes transferred so far of each file in flight, by index.
+    in_flight: BTreeMap<usize, u64>,
+}
+
+impl<'a> ConcurrentDirectoryProgress<'a> {
+    pub(crate) fn new(
+        sink: Option<&'a dyn ProgressSink<DirectoryTransferProgress>>,
+        total_files: usize,
+        total_bytes: u64,
+    ) -> ConcurrentDirectoryProgress<'a> {
+        ConcurrentDirectoryProgress {
+            sink,
+            state: Mutex::new(ConcurrentState {
+                done: DirectoryTransferProgress {
+                    directory_name: None,
+                    total_files,
+                    transferred_files: 0,
+                    total_bytes,
+                    transferred_bytes: 0,
+                    current_file: None,
+                    current_file_progress: FileTransferProgress {
+                        total_bytes: Some(0),
+                        transferred_bytes: 0,
+                        sparse_bytes: 0,
+                    },
+                },
+                in_flight: BTreeMap::new(),
+            }),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, ConcurrentState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether per-file progress is wanted at all.
+    pub(crate) fn enabled(&self) -> bool {
+        self.sink.is_some()
+    }
+
+    /// Returns a sink for the file with the given index.
+    pub(crate) fn file(&self, index: usize, name: String) -> ConcurrentFileSink<'_, 'a> {
+        ConcurrentFileSink {
+            parent: self,
+            index,
+            name,
+        }
+    }
+
+    /// Records a finished file and reports the new totals.
+    pub(crate) fn finish(&self, index: usize, size: u64, sparse_bytes: u64) {
+        let mut state = self.state();
+        state.in_flight.remove(&index);
+        state.done.transferred_files += 1;
+        state.done.transferred_bytes += size;
+
+        if let Some(sink) = self.sink {
+            let mut update = state.done.clone();
+            update.transferred_bytes += state.in_flight.values().sum::<u64>();
+            update.current_file_progress = FileTransferProgress {
+                total_bytes: Some(size),
+                transferred_bytes: size,
+                sparse_bytes,
+            };
+            sink.report(update);
+        }
+    }
+
+    /// Returns the number of finished files and their bytes.
+    pub(crate) fn transferred(&self) -> (usize, u64) {
+        let state = self.state();
+        (state.done.transferred_files, state.done.transferred_bytes)
+    }
+}
+
+/// Per-file view of a [`ConcurrentDirectoryProgress`].
+pub(crate) struct ConcurrentFileSink<'p, 'a> {
+    parent: &'p ConcurrentDirectoryProgress<'a>,
+    index: usize,
+    name: String,
+}
+
+impl ProgressSink<FileTransferProgress> for ConcurrentFileSink<'_, '_> {
+    fn report(&self, progress: FileTransferProgress) {
+        let Some(sink) = self.parent.sink else {
+            return;
+        };
+
+        let mut state = self.parent.state();
+        state
+            .in_flight
+            .insert(self.index, progress.transferred_bytes);
+
+        let mut update = state.done.clone();
+        update.transferred_bytes += state.in_flight.values().sum::<u64>();
+        update.current_file = Some(self.name.clone());
+        update.current_file_progress = progress;
+        sink.report(update);
+    }
+}
@@ -0,0 +1,549 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Recording of adb server sessions and their replay, to turn interactions
+//! with a real device into deterministic regression tests.
+//!
+//! [`RecordingConnector`] passes connections through and appends every
+//! request and response to a file.  [`ReplayConnector`] serves that file:
+//! each connection is matched to a recorded one by the requests written to
+//! it, so connections may be opened in any order.  Past the `sync:`
+//! service, each request is matched on its own against all recorded sync
+//! requests, because the files concurrent pulls of [`Device::pull_dir`]
+//! take turns on a sync connection with depend on scheduling.  Requests
+//! must be byte-identical to the recorded ones; anything that changes
+//! between runs, such as pushed timestamps, has to be fixed by the test.
+//!
+//! ```no_run
+//! # async fn example() -> forensic_adb::Result<()> {
+//! use forensic_adb::record::{RecordingConnector, ReplayConnector};
+//! use forensic_adb::{Host, UnixPath};
+//! use std::path::Path;
+//! use std::sync::Arc;
+//!
+//! let recording = Path::new("tests/pull_dir.rec");
+//! let host = Host {
+//!     connector: Some(Arc::new(RecordingConnector::new(Host::default(), recording)?)),
+//!     ..Host::default()
+//! };
+//! let device = host.device_or_default::<String>(None).await?;
+//! device.pull_dir(UnixPath::new("/sdcard/DCIM"), Path::new("dcim")).await?;
+//!
+//! // Later, without a device:
+//! let host = Host {
+//!     connector: Some(Arc::new(ReplayConnector::open(recording)?)),
+//!     ..Host::default()
+//! };
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Device::pull_dir`]: crate::Device::pull_dir
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures_core::future::BoxFuture;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::transport::{BoxedTransport, Connector};
+use crate::{Host, Result};
+
+const HEADER: &str = "# forensic-adb recording v1";
+/// The `sync:` service request, after which a connection carries sync
+/// requests.
+const SYNC_SERVICE: &[u8] = b"0005sync:";
+
+/// Records the connections of a [`Host`] to a file.
+///
+/// Each line holds the connection number, `>` for bytes sent to the server
+/// or `<` for bytes received, and the bytes in base64.
+#[derive(Debug)]
+pub struct RecordingConnector {
+    inner: Host,
+    file: Arc<Mutex<File>>,
+    connections: AtomicU32,
+}
+
+impl RecordingConnector {
+    /// Records the connections made to `inner` to `path`, replacing any
+    /// previous recording.
+    pub fn new(mut inner: Host, path: &Path) -> io::Result<RecordingConnector> {
+        // The outer host retries; a retried connection is recorded anew.
+        inner.retry = None;
+        let mut file = File::create(path)?;
+        writeln!(file, "{HEADER}")?;
+        Ok(RecordingConnector {
+            inner,
+            file: Arc::new(Mutex::new(file)),
+            connections: AtomicU32::new(0),
+        })
+    }
+}
+
+impl Connector for RecordingConnector {
+    fn connect(&self) -> BoxFuture<'_, Result<BoxedTransport>> {
+        Box::pin(async move {
+            let inner = self.inner.connect().await?;
+            Ok(Box::new(RecordingTransport {
+                inner,
+                connection: self.connections.fetch_add(1, Ordering::Relaxed),
+                file: self.file.clone(),
+            }) as BoxedTransport)
+        })
+    }
+}
+
+struct RecordingTransport {
+    inner: BoxedTransport,
+    connection: u32,
+    file: Arc<Mutex<File>>,
+}
+
+impl RecordingTransport {
+    fn record(&self, direction: char, bytes: &[u8]) -> io::Result<()> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        writeln!(
+            file,
+            "{} {direction} {}",
+            self.connection,
+            STANDARD.encode(bytes)
+        )
+    }
+}
+
+impl AsyncRead for RecordingTransport {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            self.record('<', &buf.filled()[filled..])?;
+        }
+        poll
+    }
+}
+
+impl AsyncWrite for RecordingTransport {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = poll {
+            self.record('>', &buf[..n])?;
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// One recorded connection.
+#[derive(Debug, Default)]
+struct Recorded {
+    requests: Vec<u8>,
+    responses: Vec<u8>,
+    /// For each received chunk, the bytes sent before it and the end of
+    /// the chunk in `responses`.
+    ready: Vec<(usize, usize)>,
+    /// For sync connections, the length of the requests and responses up
+    /// to and including the `sync:` service.
+    sync_prefix: Option<(usize, usize)>,
+    /// The requests and responses of a sync connection after the prefix.
+    exchanges: Vec<Exchange>,
+}
+
+/// A request on a sync connection and the response to it.
+#[derive(Debug, Default)]
+struct Exchange {
+    request: Vec<u8>,
+    response: Vec<u8>,
+    claimed: bool,
+}
+
+impl Recorded {
+    /// Response bytes that may be served once `written` request bytes were
+    /// received.
+    fn available(&self, written: usize) -> usize {
+        self.ready
+            .iter()
+            .take_while(|(before, _)| *before <= written)
+            .last()
+            .map_or(0, |(_, end)| *end)
+    }
+
+    /// Splits off the requests and responses following the `sync:` service
+    /// of a sync connection.  The client writes a request only once it has
+    /// read the response to the previous one, so each exchange starts with
+    /// a write following a read.
+    fn split_sync(&mut self, mut exchanges: Vec<Exchange>) {
+        let Some(end) = exchanges
+            .iter()
+            .position(|exchange| exchange.request.ends_with(SYNC_SERVICE))
+        else {
+            return;
+        };
+        let prefix: Vec<Exchange> = exchanges.drain(..=end).collect();
+        self.sync_prefix = Some((
+            prefix.iter().map(|exchange| exchange.request.len()).sum(),
+            prefix.iter().map(|exchange| exchange.response.len()).sum(),
+        ));
+        self.exchanges = exchanges;
+    }
+
+    /// Whether this is a sync connection opened with `prefix`.
+    fn has_sync_prefix(&self, prefix: &[u8]) -> bool {
+        self.sync_prefix
+            .is_some_and(|(len, _)| self.requests[..len] == *prefix)
+    }
+}
+
+#[derive(Debug)]
+struct ReplayState {
+    recorded: Vec<Recorded>,
+    claimed: Vec<bool>,
+}
+
+/// Serves a recording made with [`RecordingConnector`].
+#[derive(Debug, Clone)]
+pub struct ReplayConnector {
+    state: Arc<Mutex<ReplayState>>,
+}
+
+impl ReplayConnector {
+    pub fn open(path: &Path) -> io::Result<ReplayConnector> {
+        let mut recorded: Vec<Recorded> = Vec::new();
+        let mut exchanges: Vec<Vec<Exchange>> = Vec::new();
+        for (number, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid recording line {}", number + 1),
+                )
+            };
+
+            let mut fields = line.splitn(3, ' ');
+            let connection: usize = fields
+                .next()
+                .and_then(|c| c.parse().ok())
+                .ok_or_else(invalid)?;
+            let direction = fields.next().ok_or_else(invalid)?;
+            let bytes = fields
+                .next()
+                .and_then(|b| STANDARD.decode(b).ok())
+                .ok_or_else(invalid)?;
+
+            if recorded.len() <= connection {
+                recorded.resize_with(connection + 1, Recorded::default);
+                exchanges.resize_with(connection + 1, Vec::new);
+            }
+            let recorded = &mut recorded[connection];
+            let exchanges = &mut exchanges[connection];
+            match direction {
+                ">" => {
+                    if exchanges
+                        .last()
+                        .is_none_or(|last| !last.response.is_empty())
+                    {
+                        exchanges.push(Exchange::default());
+                    }
+                    if let Some(last) = exchanges.last_mut() {
+                        last.request.extend(&bytes);
+                    }
+                    recorded.requests.extend(bytes);
+                }
+                "<" => {
+                    if exchanges.is_empty() {
+                        exchanges.push(Exchange::default());
+                    }
+                    if let Some(last) = exchanges.last_mut() {
+                        last.response.extend(&bytes);
+                    }
+                    recorded.responses.extend(bytes);
+                    recorded
+                        .ready
+                        .push((recorded.requests.len(), recorded.responses.len()));
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        for (recorded, exchanges) in recorded.iter_mut().zip(exchanges) {
+            recorded.split_sync(exchanges);
+        }
+        let claimed = vec![false; recorded.len()];
+        Ok(ReplayConnector {
+            state: Arc::new(Mutex::new(ReplayState { recorded, claimed })),
+        })
+    }
+
+    fn state(&self) -> MutexGuard<'_, ReplayState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Number of recorded connections not replayed yet; a sync connection
+    /// counts until all of its requests were replayed.
+    pub fn remaining(&self) -> usize {
+        let state = self.state();
+        state
+            .recorded
+            .iter()
+            .zip(&state.claimed)
+            .filter(|(recorded, claimed)| match recorded.sync_prefix {
+                Some(_) => recorded.exchanges.iter().any(|exchange| !exchange.claimed),
+                None => !**claimed,
+            })
+            .count()
+    }
+}
+
+impl Connector for ReplayConnector {
+    fn connect(&self) -> BoxFuture<'_, Result<BoxedTransport>> {
+        Box::pin(async move {
+            let candidates = (0..self.state().recorded.len()).collect();
+            Ok(Box::new(ReplayTransport {
+                replay: self.clone(),
+                candidates,
+                claimed: None,
+                written: Vec::new(),
+                served: 0,
+                sync: None,
+                waker: None,
+            }) as BoxedTransport)
+        })
+    }
+}
+
+/// A replayed connection.  Until the requests single out one recorded
+/// connection, every unclaimed recording they match stays a candidate.
+/// Sync connections are never claimed; past the `sync:` service they are
+/// served from the exchanges of all recorded sync connections.
+struct ReplayTransport {
+    replay: ReplayConnector,
+    candidates: Vec<usize>,
+    claimed: Option<usize>,
+    written: Vec<u8>,
+    served: usize,
+    sync: Option<SyncReplay>,
+    waker: Option<Waker>,
+}
+
+/// A replayed connection past the `sync:` service.
+struct SyncReplay {
+    /// Requests up to and including the `sync:` service.
+    prefix: Vec<u8>,
+    /// Written bytes of a request not matched yet.
+    pending: Vec<u8>,
+    /// Responses not read yet.
+    queued: VecDeque<u8>,
+}
+
+impl SyncReplay {
+    /// Queues the response of an unclaimed recorded exchange whose request
+    /// is `pending`, if it is complete.
+    fn answer(&mut self, state: &mut ReplayState) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let mut partial = false;
+        for recorded in state
+            .recorded
+            .iter_mut()
+            .filter(|recorded| recorded.has_sync_prefix(&self.prefix))
+        {
+            for exchange in recorded.exchanges.iter_mut().filter(|e| !e.claimed) {
+                if exchange.request == self.pending {
+                    exchange.claimed = true;
+                    self.queued.extend(&exchange.response);
+                    self.pending.clear();
+                    return Ok(());
+                }
+                partial |= exchange.request.starts_with(&self.pending);
+            }
+        }
+        match partial {
+            true => Ok(()),
+            false => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "no recorded sync request matches {:?}",
+                    String::from_utf8_lossy(&self.pending)
+                ),
+            )),
+        }
+    }
+}
+
+impl ReplayTransport {
+    /// Drops candidates claimed by other connections, and claims the last
+    /// one left.
+    fn narrow(&mut self, state: &mut ReplayState) -> io::Result<()> {
+        if self.claimed.is_none() {
+            self.candidates.retain(|&index| !state.claimed[index]);
+            if let [index] = self.candidates[..] {
+                if state.recorded[index].sync_prefix.is_some() {
+                    return Ok(());
+                }
+                state.claimed[index] = true;
+                self.claimed = Some(index);
+            }
+        }
+        if self.candidates.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "no recorded connection matches the requests {:?}",
+                    String::from_utf8_lossy(&self.written)
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl AsyncRead for ReplayTransport {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        let replay = this.replay.clone();
+        let mut state = replay.state();
+        if let Some(sync) = &mut this.sync {
+            if sync.queued.is_empty() {
+                this.waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+            let n = buf.remaining().min(sync.queued.len());
+            let chunk: Vec<u8> = sync.queued.drain(..n).collect();
+            buf.put_slice(&chunk);
+            return Poll::Ready(Ok(()));
+        }
+        this.narrow(&mut state)?;
+
+        let first = &state.recorded[this.candidates[0]];
+        let available = first.available(this.written.len());
+        if this.served < available {
+            let n = buf.remaining().min(available - this.served);
+            let chunk = &first.responses[this.served..this.served + n];
+            buf.put_slice(chunk);
+            // Recordings answering differently are not this connection.
+            let chunk = chunk.to_vec();
+            let (served, written) = (this.served, this.written.len());
+            this.candidates.retain(|&index| {
+                let recorded = &state.recorded[index];
+                recorded.available(written) >= served + n
+                    && recorded.responses[served..served + n] == chunk[..]
+            });
+            this.served += n;
+            this.narrow(&mut state)?;
+            return Poll::Ready(Ok(()));
+        }
+
+        if this.written.len() >= first.requests.len() && this.served >= first.responses.len() {
+            // The recorded connection was closed here.
+            return Poll::Ready(Ok(()));
+        }
+        this.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl AsyncWrite for ReplayTransport {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let replay = this.replay.clone();
+        let mut state = replay.state();
+        if let Some(sync) = &mut this.sync {
+            sync.pending.extend_from_slice(buf);
+            sync.answer(&mut state)?;
+        } else {
+            this.written.extend_from_slice(buf);
+            let written = &this.written;
+            // Past the `sync:` service, requests are matched one at a time.
+            let sync_start = this.candidates.iter().find_map(|&index| {
+                let recorded = &state.recorded[index];
+                let (requests, responses) = recorded.sync_prefix?;
+                written
+                    .starts_with(&recorded.requests[..requests])
+                    .then_some((index, requests, responses))
+            });
+            if let Some((index, requests, responses)) = sync_start {
+                let recorded = &state.recorded[index];
+                let mut sync = SyncReplay {
+                    prefix: written[..requests].to_vec(),
+                    pending: written[requests..].to_vec(),
+                    queued: recorded.responses[this.served.min(responses)..responses]
+                        .iter()
+                        .copied()
+                        .collect(),
+                };
+                sync.answer(&mut state)?;
+                this.candidates.clear();
+                this.sync = Some(sync);
+            } else {
+                this.candidates
+                    .retain(|&index| state.recorded[index].requests.starts_with(written));
+                this.narrow(&mut state)?;
+            }
+        }
+        if let Some(waker) = this.waker.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for ReplayTransport {
+    fn drop(&mut self) {
+        // Several identical recordings may still be candidates; use up one.
+        if self.claimed.is_none() && self.sync.is_none() {
+            let mut state = self.replay.state();
+            let unclaimed = self.candidates.iter().find(|&&index| {
+                !state.claimed[index] && state.recorded[index].sync_prefix.is_none()
+            });
+            if let Some(&index) = unclaimed {
+                state.claimed[index] = true;
+            }
+        }
+    }
+}
@@ -0,0 +1,106 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Removal of single files, empty directories and whole trees.
+//!
+//! Recursive removals ([`Device::remove_dir_all`] and [`Device::remove`])
+//! are guarded: they refuse `/` and its direct children, relative paths,
+//! and, once [`Device::remove_roots`] is set, anything not strictly below
+//! one of those roots.
+
+use unix_path::Component;
+
+#[cfg(not(feature = "tracing"))]
+use log::debug;
+#[cfg(feature = "tracing")]
+use tracing::debug;
+
+use crate::copy::file_error;
+use crate::{Device, DeviceError, DevicePath, Result, UnixPath};
+
+impl Device {
+    /// Removes the file or symbolic link at `path`; a missing file is not an
+    /// error.  Directories fail with an
+    /// [`std::io::ErrorKind::IsADirectory`] error.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial, path = %path.display()), err)
+    )]
+    pub async fn remove_file(&self, path: &UnixPath) -> Result<()> {
+        self.check_writable("remove files")?;
+        debug!("Deleting file {}", path.display());
+        self.remove_with(path, "rm -f").await
+    }
+
+    /// Removes the empty directory at `path`.  A directory with entries
+    /// fails with an [`std::io::ErrorKind::DirectoryNotEmpty`] error.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial, path = %path.display()), err)
+    )]
+    pub async fn remove_dir(&self, path: &UnixPath) -> Result<()> {
+        self.check_writable("remove files")?;
+        debug!("Deleting directory {}", path.display());
+        self.remove_with(path, "rmdir").await
+    }
+
+    /// Removes `path` and everything below it, after checking it against
+    /// the removal guard; a missing path is not an error.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial, path = %path.display()), err)
+    )]
+    pub async fn remove_dir_all(&self, path: &UnixPath) -> Result<()> {
+        self.check_writable("remove files")?;
+        self.check_removable(path)?;
+        debug!("Deleting tree {}", path.display());
+        self.remove_with(path, "rm -rf").await
+    }
+
+    async fn remove_with(&self, path: &UnixPath, command: &str) -> Result<()> {
+        let quoted = DevicePath::new(path)?.quoted();
+        let output = self
+            .execute_host_shell_command_as(
+                &format!("{command} {quoted}"),
+                self.enable_run_as_for_path(path),
+            )
+            .await?;
+        match output.trim() {
+            "" => Ok(()),
+            message => Err(file_error(message)),
+        }
+    }
+
+    /// Fails with [`DeviceError::RemoveBlocked`] unless `path` may be
+    /// removed recursively.
+    pub(crate) fn check_removable(&self, path: &UnixPath) -> Result<()> {
+        DevicePath::new(path)?;
+        let blocked = |reason: &str| {
+            Err(DeviceError::RemoveBlocked(
+                path.display().to_string(),
+                reason.to_owned(),
+            ))
+        };
+
+        if !path.is_absolute() {
+            return blocked("not an absolute path");
+        }
+        let depth = path
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .count();
+        if depth < 2 {
+            return blocked("too close to the root");
+        }
+        if !self.remove_roots.is_empty()
+            && !self
+                .remove_roots
+                .iter()
+                .any(|root| path.starts_with(root) && path != root.as_path())
+        {
+            return blocked("not below an allowed root");
+        }
+        Ok(())
+    }
+}
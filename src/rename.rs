@@ -0,0 +1,72 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Renaming and moving device files.
+
+#[cfg(not(feature = "tracing"))]
+use log::debug;
+#[cfg(feature = "tracing")]
+use tracing::debug;
+
+use crate::copy::file_error;
+use crate::{Device, DevicePath, Result, UnixPath};
+
+impl Device {
+    /// Moves `src` to `dst`, which must not exist yet.
+    ///
+    /// Rather than replacing `dst`, or moving `src` into it when it is a
+    /// directory, this fails with an [`std::io::ErrorKind::AlreadyExists`]
+    /// error.  Missing paths and denied access are reported as
+    /// [`std::io::ErrorKind::NotFound`] and
+    /// [`std::io::ErrorKind::PermissionDenied`].
+    ///
+    /// Where `mv` cannot move across filesystems (toolbox before Android
+    /// 6.0), `src` is copied with [`Device::copy`], which verifies the copy,
+    /// and only then removed.  That fallback needs toybox's `cp -a`, `find`
+    /// and `sha256sum`; without them it fails with
+    /// [`DeviceError::MissingFeature`](crate::DeviceError::MissingFeature)
+    /// and leaves `src` in place.  Sources [`Device::remove`] would refuse
+    /// fail with [`DeviceError::RemoveBlocked`](crate::DeviceError::RemoveBlocked)
+    /// before anything is copied.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial, src = %src.display(), dst = %dst.display()), err)
+    )]
+    pub async fn rename(&self, src: &UnixPath, dst: &UnixPath) -> Result<()> {
+        self.check_writable("rename files")?;
+        debug!("Renaming {} to {}", src.display(), dst.display());
+
+        let enable_run_as = self.enable_run_as_for_path(src) || self.enable_run_as_for_path(dst);
+        let src_arg = DevicePath::new(src)?.quoted();
+        let dst_arg = DevicePath::new(dst)?.quoted();
+        self.check_absent(dst, enable_run_as).await?;
+
+        let output = self
+            .execute_host_shell_command_as(&format!("mv {src_arg} {dst_arg}"), enable_run_as)
+            .await?;
+        let message = output.trim();
+        if message.is_empty() {
+            return Ok(());
+        }
+        if !message.contains("Cross-device link") {
+            return Err(file_error(message));
+        }
+
+        // The source is deleted recursively afterwards, so it has to pass
+        // the same guard as `remove`.
+        self.check_removable(src)?;
+        debug!(
+            "Moving {} across filesystems, copying instead",
+            src.display()
+        );
+        self.copy(src, dst, true, true).await?;
+        let output = self
+            .execute_host_shell_command_as(&format!("rm -rf {src_arg}"), enable_run_as)
+            .await?;
+        match output.trim() {
+            "" => Ok(()),
+            message => Err(file_error(message)),
+        }
+    }
+}
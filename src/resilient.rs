@@ -0,0 +1,197 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A device handle that survives the device dropping off the bus.
+
+use futures_core::stream::Stream;
+#[cfg(not(feature = "tracing"))]
+use log::{debug, warn};
+use std::future::poll_fn;
+use std::pin::pin;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout, Duration};
+#[cfg(feature = "tracing")]
+use tracing::{debug, warn};
+
+use crate::{Device, DeviceError, DeviceState, Result};
+
+/// Delay before reconnecting to the adb server after `track-devices` failed.
+const TRACK_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Connection changes reported by a [`ResilientDevice`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// The device left the `device` state.  Carries the new state, or `None`
+    /// if it is no longer listed at all.
+    Disconnected(Option<DeviceState>),
+    /// The device is online again and its port forwards were restored.
+    Reconnected,
+    /// Restoring state after a reconnect failed.
+    RestoreFailed(String),
+}
+
+#[derive(Debug, Default)]
+struct Restorable {
+    /// `(local, remote)` pairs set up through [`ResilientDevice::forward_port`].
+    forwards: Vec<(u16, u16)>,
+    /// `(remote, local)` pairs set up through [`ResilientDevice::reverse_port`].
+    reverses: Vec<(u16, u16)>,
+}
+
+/// Wraps a [`Device`] and watches `host:track-devices` for it.
+///
+/// When the device disappears and comes back, e.g. because of a flaky USB
+/// cable during a long capture, port forwards made through this wrapper are
+/// re-established.  The run-as package and other configuration live on the
+/// [`Device`] itself and therefore carry over unchanged.
+///
+/// The watcher task stops when the `ResilientDevice` is dropped.
+#[derive(Debug)]
+pub struct ResilientDevice {
+    device: Device,
+    restorable: Arc<Mutex<Restorable>>,
+    online: watch::Receiver<bool>,
+    events: broadcast::Sender<ConnectionEvent>,
+    watcher: JoinHandle<()>,
+}
+
+impl ResilientDevice {
+    /// Starts watching the device.  Must be called within a tokio runtime.
+    pub fn new(device: Device) -> ResilientDevice {
+        let restorable = Arc::new(Mutex::new(Restorable::default()));
+        let (online_sender, online) = watch::channel(true);
+        let (events, _) = broadcast::channel(16);
+
+        let watcher = tokio::spawn(watch_device(
+            device.clone(),
+            restorable.clone(),
+            online_sender,
+            events.clone(),
+        ));
+
+        ResilientDevice {
+            device,
+            restorable,
+            online,
+            events,
+            watcher,
+        }
+    }
+
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// Whether the device was online at the last `track-devices` update.
+    pub fn is_connected(&self) -> bool {
+        *self.online.borrow()
+    }
+
+    /// Returns a receiver for connection changes from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.events.subscribe()
+    }
+
+    /// Waits until the device is online, for at most `limit`.
+    pub async fn wait_online(&self, limit: Duration) -> Result<()> {
+        let mut online = self.online.clone();
+        timeout(limit, online.wait_for(|online| *online))
+            .await
+            .map_err(|_| DeviceError::CommandTimeout)?
+            .map_err(|_| DeviceError::Adb("device watcher stopped".to_owned()))?;
+        Ok(())
+    }
+
+    /// Like [`Device::forward_port`], and restored after reconnects.
+    pub async fn forward_port(&self, local: u16, remote: u16) -> Result<u16> {
+        let local = self.device.forward_port(local, remote).await?;
+        lock(&self.restorable).forwards.push((local, remote));
+        Ok(local)
+    }
+
+    /// Like [`Device::reverse_port`], and restored after reconnects.
+    pub async fn reverse_port(&self, remote: u16, local: u16) -> Result<u16> {
+        let remote = self.device.reverse_port(remote, local).await?;
+        lock(&self.restorable).reverses.push((remote, local));
+        Ok(remote)
+    }
+}
+
+impl Drop for ResilientDevice {
+    fn drop(&mut self) {
+        self.watcher.abort();
+    }
+}
+
+fn lock(restorable: &Mutex<Restorable>) -> std::sync::MutexGuard<'_, Restorable> {
+    restorable.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+async fn restore(device: &Device, restorable: &Mutex<Restorable>) -> Result<()> {
+    let (forwards, reverses) = {
+        let restorable = lock(restorable);
+        (restorable.forwards.clone(), restorable.reverses.clone())
+    };
+
+    for (local, remote) in forwards {
+        device.forward_port(local, remote).await?;
+    }
+    for (remote, local) in reverses {
+        device.reverse_port(remote, local).await?;
+    }
+
+    Ok(())
+}
+
+async fn watch_device(
+    device: Device,
+    restorable: Arc<Mutex<Restorable>>,
+    online: watch::Sender<bool>,
+    events: broadcast::Sender<ConnectionEvent>,
+) {
+    let host = device.host.clone();
+
+    loop {
+        let mut updates = pin!(host.track_devices());
+
+        while let Some(update) = poll_fn(|cx| updates.as_mut().poll_next(cx)).await {
+            let devices = match update {
+                Ok(devices) => devices,
+                Err(err) => {
+                    warn!("Tracking devices failed: {}", err);
+                    break;
+                }
+            };
+
+            let state = devices
+                .into_iter()
+                .find(|brief| brief.serial == device.serial)
+                .map(|brief| brief.state);
+            let is_online = state == Some(DeviceState::Device);
+            let was_online = *online.borrow();
+
+            if was_online && !is_online {
+                debug!("{} disconnected ({:?})", device.serial, state);
+                online.send_replace(false);
+                let _ = events.send(ConnectionEvent::Disconnected(state));
+            } else if !was_online && is_online {
+                debug!("{} reconnected", device.serial);
+                match restore(&device, &restorable).await {
+                    Ok(()) => {
+                        let _ = events.send(ConnectionEvent::Reconnected);
+                    }
+                    Err(err) => {
+                        warn!("Failed to restore state of {}: {}", device.serial, err);
+                        let _ = events.send(ConnectionEvent::RestoreFailed(err.to_string()));
+                    }
+                }
+                online.send_replace(true);
+            }
+        }
+
+        sleep(TRACK_RETRY_DELAY).await;
+    }
+}
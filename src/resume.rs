@@ -0,0 +1,308 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Transfers of large files with `dd` on the device: resuming interrupted
+//! transfers and pulling ranges of one file in parallel.
+
+#[cfg(not(feature = "tracing"))]
+use log::debug;
+use std::io::SeekFrom;
+use std::path::Path;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::{sleep, Duration, Instant};
+#[cfg(feature = "tracing")]
+use tracing::debug;
+
+use crate::audit::TransferDirection;
+use crate::buffer_pool::PooledBuffer;
+use crate::transfer::HashAlgorithm;
+use crate::{Device, DeviceError, DevicePath, Result, TransferredFile, UnixPath};
+
+/// How much of the already transferred data is compared before resuming.
+pub const RESUME_OVERLAP: u64 = 1024 * 1024;
+
+/// Ranges of [`Device::pull_parallel`] start at multiples of this, the
+/// `dd` block size.
+const PARALLEL_ALIGNMENT: u64 = 64 * 1024;
+
+/// How long [`Device::push_resume`] waits for `dd` to write out the data
+/// after the connection closed.
+const APPEND_SETTLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl Device {
+    /// Continues an interrupted pull of `src` into `writer`, which holds the
+    /// first `offset` bytes, and returns the new length.
+    ///
+    /// The last [`RESUME_OVERLAP`] bytes before `offset` are compared with
+    /// the device first, failing with [`DeviceError::ChecksumMismatch`] if
+    /// the file changed.  The rest is streamed with `dd` over `exec:`, which
+    /// unlike sync `RECV` can start in the middle of a file.  If the
+    /// connection drops again, resume from the returned length.
+    pub async fn pull_resume<W: AsyncRead + AsyncWrite + AsyncSeek + Unpin>(
+        &self,
+        src: &UnixPath,
+        writer: &mut W,
+        offset: u64,
+    ) -> Result<u64> {
+        let quoted = DevicePath::new(src)?.quoted();
+        let enable_run_as = self.enable_run_as_for_path(src);
+
+        let overlap = offset.min(RESUME_OVERLAP);
+        if overlap > 0 {
+            let start = offset - overlap;
+            writer.seek(SeekFrom::Start(start)).await?;
+            let mut data = vec![0; overlap as usize];
+            writer.read_exact(&mut data).await?;
+            let mut hasher = HashAlgorithm::Sha256.hasher();
+            hasher.update(&data);
+            let host = hasher.finish();
+
+            // Piped outside of run-as, which only needs to cover `dd`.
+            let mut output = String::new();
+            self.open_exec(
+                &format!(
+                    "dd if={quoted} iflag=skip_bytes,count_bytes skip={start} count={overlap} bs=65536 2>/dev/null | sha256sum"
+                ),
+                enable_run_as,
+            )
+            .await?
+            .read_to_string(&mut output)
+            .await?;
+            let device = output.split_whitespace().next().unwrap_or_default();
+            if host != device {
+                return Err(DeviceError::ChecksumMismatch(
+                    src.display().to_string(),
+                    host,
+                    device.to_owned(),
+                ));
+            }
+        }
+
+        debug!("Resuming pull of {} at {}", src.display(), offset);
+        writer.seek(SeekFrom::Start(offset)).await?;
+        let mut stream = self
+            .open_exec(
+                &format!("dd if={quoted} iflag=skip_bytes skip={offset} bs=65536"),
+                enable_run_as,
+            )
+            .await?;
+        let bytes = tokio::io::copy(&mut stream, writer).await?;
+        writer.flush().await?;
+
+        Ok(offset + bytes)
+    }
+
+    /// Pushes `reader` to `dest`, continuing a previous, interrupted push
+    /// if `dest` already holds a prefix of it.  Returns the number of bytes
+    /// sent now.
+    ///
+    /// The partial remote file is verified by comparing its SHA-256 digest
+    /// with the same prefix of `reader`, failing with
+    /// [`DeviceError::ChecksumMismatch`] if they differ.  The remainder is
+    /// appended with `dd` over `exec:`, as the sync protocol can only write
+    /// whole files.  `mode` is applied once the file is complete.
+    pub async fn push_resume<R: AsyncRead + AsyncSeek + Unpin>(
+        &self,
+        reader: &mut R,
+        dest: &UnixPath,
+        mode: u32,
+    ) -> Result<u64> {
+        self.check_writable("push files")?;
+        let quoted = DevicePath::new(dest)?.quoted();
+        let enable_run_as = self.enable_run_as_for_path(dest);
+
+        let total = reader.seek(SeekFrom::End(0)).await?;
+        let offset = self.remote_size(dest).await?.unwrap_or(0);
+        if offset > total {
+            return Err(DeviceError::Adb(format!(
+                "{} is larger than the pushed data",
+                dest.display()
+            )));
+        }
+
+        if offset > 0 {
+            reader.seek(SeekFrom::Start(0)).await?;
+            let mut hasher = HashAlgorithm::Sha256.hasher();
+            let mut prefix = (&mut *reader).take(offset);
+            let mut buf = PooledBuffer::filled(64 * 1024);
+            loop {
+                let n = prefix.read(&mut buf).await?;
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&buf[..n]);
+            }
+            let host = hasher.finish();
+            let device = self.checksum(dest, HashAlgorithm::Sha256).await?;
+            if host != device {
+                return Err(DeviceError::ChecksumMismatch(
+                    dest.display().to_string(),
+                    host,
+                    device,
+                ));
+            }
+        }
+
+        let mut sent = 0;
+        if offset < total {
+            debug!("Resuming push to {} at {}", dest.display(), offset);
+            reader.seek(SeekFrom::Start(offset)).await?;
+            let mut stream = self
+                .open_exec(
+                    &format!("dd of={quoted} oflag=append conv=notrunc bs=65536"),
+                    enable_run_as,
+                )
+                .await?;
+            sent = tokio::io::copy(reader, &mut stream).await?;
+            // Closing the connection ends the input of `dd`.
+            stream.shutdown().await?;
+            drop(stream);
+
+            // `dd` may still be writing when the connection is gone.
+            let deadline = Instant::now() + APPEND_SETTLE_TIMEOUT;
+            loop {
+                let size = self.remote_size(dest).await?.unwrap_or(0);
+                if size == total {
+                    break;
+                }
+                if Instant::now() >= deadline {
+                    return Err(DeviceError::Adb(format!(
+                        "push to {} incomplete: {} of {} bytes",
+                        dest.display(),
+                        size,
+                        total
+                    )));
+                }
+                sleep(Duration::from_millis(100)).await;
+            }
+        }
+
+        self.execute_host_shell_command_as(&format!("chmod {mode:o} {quoted}"), enable_run_as)
+            .await?;
+
+        Ok(sent)
+    }
+
+    /// Pulls `src` into a new host file at `dest` over `streams` concurrent
+    /// connections, each streaming one range of the file with `dd`.
+    ///
+    /// `dest` is preallocated and each range written in place, so for large
+    /// files, such as partition images, the transfer is no longer limited by
+    /// the throughput of a single connection.  The SHA-256 digest of the
+    /// whole file is compared with the device afterwards, failing with
+    /// [`DeviceError::ChecksumMismatch`] if the file changed meanwhile.
+    pub async fn pull_parallel(
+        &self,
+        src: &UnixPath,
+        dest: &Path,
+        streams: usize,
+    ) -> Result<TransferredFile> {
+        let started = Instant::now();
+        let quoted = DevicePath::new(src)?.quoted();
+        let enable_run_as = self.enable_run_as_for_path(src);
+        let size = self
+            .remote_size(src)
+            .await?
+            .ok_or_else(|| DeviceError::Adb(format!("{} does not exist", src.display())))?;
+
+        let file = tokio::fs::File::create(dest).await?;
+        file.set_len(size).await?;
+        drop(file);
+
+        let streams = streams.max(1) as u64;
+        let range = size.div_ceil(streams).div_ceil(PARALLEL_ALIGNMENT) * PARALLEL_ALIGNMENT;
+        let ranges = (0..size)
+            .step_by(range.max(1) as usize)
+            .map(|start| (start, range.min(size - start)));
+        debug!(
+            "Pulling {} ({} bytes) in ranges of {} bytes",
+            src.display(),
+            size,
+            range
+        );
+
+        futures_util::future::try_join_all(ranges.map(|(start, count)| {
+            let quoted = &quoted;
+            async move {
+                let mut file = tokio::fs::OpenOptions::new().write(true).open(dest).await?;
+                file.seek(SeekFrom::Start(start)).await?;
+                let mut stream = self
+                    .open_exec(
+                        &format!(
+                            "dd if={quoted} iflag=skip_bytes,count_bytes skip={start} count={count} bs=65536"
+                        ),
+                        enable_run_as,
+                    )
+                    .await?;
+                let copied = tokio::io::copy(&mut stream, &mut file).await?;
+                file.flush().await?;
+                if copied != count {
+                    return Err(DeviceError::Adb(format!(
+                        "pull of {} at {}: {} of {} bytes",
+                        src.display(),
+                        start,
+                        copied,
+                        count
+                    )));
+                }
+                Ok(())
+            }
+        }))
+        .await?;
+        let elapsed = started.elapsed();
+
+        let mut hasher = HashAlgorithm::Sha256.hasher();
+        let mut file = tokio::fs::File::open(dest).await?;
+        let mut buf = PooledBuffer::filled(1024 * 1024);
+        loop {
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+        let host = hasher.finish();
+        let device = self.checksum(src, HashAlgorithm::Sha256).await?;
+        if host != device {
+            return Err(DeviceError::ChecksumMismatch(
+                src.display().to_string(),
+                host,
+                device,
+            ));
+        }
+        if let Some(audit) = &self.audit {
+            audit.transfer(&self.serial, TransferDirection::Pull, src, size, &host)?;
+        }
+
+        Ok(TransferredFile {
+            device_path: src.to_path_buf(),
+            host_path: Some(dest.to_path_buf()),
+            bytes: size,
+            sparse_bytes: 0,
+            digest: Some(host),
+            elapsed,
+        })
+    }
+
+    /// Returns the size of `path`, or `None` if it does not exist.  Unlike
+    /// sync `STAT` this is not limited to 4 GiB.
+    async fn remote_size(&self, path: &UnixPath) -> Result<Option<u64>> {
+        let output = self
+            .execute_host_shell_command_as(
+                &format!("stat -c %s {}", DevicePath::new(path)?.quoted()),
+                self.enable_run_as_for_path(path),
+            )
+            .await?;
+
+        match output.trim().parse() {
+            Ok(size) => Ok(Some(size)),
+            Err(_) if output.contains("No such file") => Ok(None),
+            Err(_) => Err(DeviceError::Adb(format!(
+                "stat {} failed: {}",
+                path.display(),
+                output.trim()
+            ))),
+        }
+    }
+}
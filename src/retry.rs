@@ -0,0 +1,198 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+#[cfg(not(feature = "tracing"))]
+use log::warn;
+use std::future::Future;
+use std::io;
+use tokio::time::{sleep, Duration};
+#[cfg(feature = "tracing")]
+use tracing::warn;
+
+use crate::{DeviceError, Result};
+
+/// Opt-in policy for retrying operations that failed due to transient
+/// conditions, such as a device briefly dropping off the bus.
+///
+/// When set on [`Host::retry`](crate::Host::retry) it applies to connections
+/// to the adb server, switching connections to a device, and idempotent
+/// queries (`devices`, `features`, `stat`, `list_dir`).  Transfers and shell
+/// commands are not replayed once they have started.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry.
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between attempts.
+    pub max_backoff: Duration,
+    /// Factor the delay grows by after every failed attempt.
+    pub multiplier: u32,
+    /// Decides whether an error is worth retrying.
+    pub retryable: fn(&DeviceError) -> bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2,
+            retryable: is_transient,
+        }
+    }
+}
+
+impl PartialEq for RetryPolicy {
+    fn eq(&self, other: &RetryPolicy) -> bool {
+        self.max_attempts == other.max_attempts
+            && self.initial_backoff == other.initial_backoff
+            && self.max_backoff == other.max_backoff
+            && self.multiplier == other.multiplier
+            && self.retryable as usize == other.retryable as usize
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the delay to wait before the given retry (starting at 1).
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = self
+            .multiplier
+            .max(1)
+            .saturating_pow(retry.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+
+    /// Runs `operation` until it succeeds, fails with an error that is not
+    /// retryable, or `max_attempts` is exhausted.  The last error is returned.
+    pub async fn run<T, F, Fut>(&self, mut operation: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match operation().await {
+                Err(err) if attempt < self.max_attempts && (self.retryable)(&err) => {
+                    let delay = self.backoff(attempt);
+                    warn!(
+                        "Transient adb failure (attempt {}/{}): {}, retrying in {:?}",
+                        attempt, self.max_attempts, err, delay
+                    );
+                    sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Runs `operation` under `policy`, or exactly once if there is none.
+pub(crate) async fn with_retry<T, F, Fut>(
+    policy: Option<&RetryPolicy>,
+    mut operation: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    match policy {
+        Some(policy) => policy.run(operation).await,
+        None => operation().await,
+    }
+}
+
+/// Default classifier: connection failures, stalled connections and the
+/// adb server reporting a device as offline or missing.
+pub fn is_transient(err: &DeviceError) -> bool {
+    match err {
+        DeviceError::ConnectTimeout => true,
+        DeviceError::Io(err) => matches!(
+            err.kind(),
+            io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::UnexpectedEof
+                | io::ErrorKind::TimedOut
+        ),
+        DeviceError::Adb(message) => {
+            message.contains("device offline")
+                || message.contains("not found")
+                    && (message.contains("device '") || message.contains("no devices"))
+                || message.contains("device still authorizing")
+                || message.contains("device still connecting")
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_and_is_capped() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+            ..Default::default()
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(400));
+        assert_eq!(policy.backoff(4), Duration::from_millis(500));
+        assert_eq!(policy.backoff(40), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn classifies_transient_errors() {
+        assert!(is_transient(&DeviceError::ConnectTimeout));
+        assert!(is_transient(&DeviceError::Adb(
+            "adb error: device offline".to_owned()
+        )));
+        assert!(is_transient(&DeviceError::Adb(
+            "adb error: device 'abc' not found".to_owned()
+        )));
+        assert!(is_transient(&DeviceError::Io(io::Error::from(
+            io::ErrorKind::ConnectionReset
+        ))));
+        assert!(!is_transient(&DeviceError::Adb(
+            "adb error: remote object does not exist".to_owned()
+        )));
+        assert!(!is_transient(&DeviceError::MissingPackage));
+    }
+
+    #[tokio::test]
+    async fn run_stops_after_max_attempts() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let mut calls = 0;
+        let result: Result<()> = policy
+            .run(|| {
+                calls += 1;
+                async { Err(DeviceError::ConnectTimeout) }
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls, 3);
+
+        let mut calls = 0;
+        let result: Result<()> = policy
+            .run(|| {
+                calls += 1;
+                async { Err(DeviceError::MissingPackage) }
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+}
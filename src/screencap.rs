@@ -0,0 +1,320 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Screenshots with `screencap`, single or as a stream of frames, decoded
+//! to RGBA pixels with the `image` feature.
+
+use futures_core::Stream;
+use std::time::SystemTime;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+
+use crate::{Device, DeviceError, Result};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+impl Device {
+    /// Takes a screenshot of the default display as PNG.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn screencap(&self) -> Result<Vec<u8>> {
+        let png = self.execute_host_exec_out_command("screencap -p").await?;
+        if !png.starts_with(PNG_SIGNATURE) {
+            return Err(DeviceError::Adb(format!(
+                "screencap did not print a PNG: {}",
+                String::from_utf8_lossy(&png[..png.len().min(200)]).trim()
+            )));
+        }
+        Ok(png)
+    }
+
+    /// Captures the screen `fps` times per second, for live previews.
+    ///
+    /// Every frame is one `screencap` round trip, so slow devices deliver
+    /// fewer frames: ticks missed while a capture is running are skipped
+    /// rather than bunched up.  The stream runs until it is dropped or a
+    /// capture fails; `fps` of 0 fails right away.
+    pub fn screen_stream(
+        &self,
+        fps: u32,
+        quality: ScreenQuality,
+    ) -> impl Stream<Item = Result<Frame>> + '_ {
+        async_stream::try_stream! {
+            if fps == 0 {
+                Err(DeviceError::Adb("screen stream needs at least 1 fps".to_owned()))?;
+            }
+            let mut ticks = interval(Duration::from_secs(1) / fps);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+            for index in 0.. {
+                ticks.tick().await;
+                let captured_at = SystemTime::now();
+                let data = match quality {
+                    ScreenQuality::Png => self.screencap().await?,
+                    ScreenQuality::Raw => self.execute_host_exec_out_command("screencap").await?,
+                };
+                yield Frame {
+                    index,
+                    captured_at,
+                    data,
+                };
+            }
+        }
+    }
+
+    /// Takes a screenshot of the default display and decodes it.
+    ///
+    /// The raw framebuffer is transferred rather than a PNG, which saves
+    /// compressing it on the device.
+    #[cfg(feature = "image")]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn screencap_decoded(&self) -> Result<Screenshot> {
+        let raw = self.execute_host_exec_out_command("screencap").await?;
+        Screenshot::decode(&raw)
+    }
+}
+
+/// How [`Device::screen_stream`] transfers frames.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScreenQuality {
+    /// PNG, compressed on the device: less data, fewer frames per second.
+    #[default]
+    Png,
+    /// The uncompressed framebuffer: little device CPU, several MB per
+    /// frame, for USB connections.
+    Raw,
+}
+
+/// A frame of [`Device::screen_stream`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// Position in the stream, from 0.
+    pub index: u64,
+    /// Host time the capture was requested.
+    pub captured_at: SystemTime,
+    /// PNG or raw `screencap` output as chosen by [`ScreenQuality`];
+    /// `Screenshot::decode` (feature `image`) handles both.
+    pub data: Vec<u8>,
+}
+
+/// Pixel format a [`Screenshot`] was decoded from.
+#[cfg(feature = "image")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScreenshotFormat {
+    Png,
+    Rgba8888,
+    Rgbx8888,
+    Rgb888,
+    Rgb565,
+}
+
+#[cfg(feature = "image")]
+impl ScreenshotFormat {
+    /// Maps an `android.graphics.PixelFormat` constant.
+    fn from_android(format: u32) -> Option<ScreenshotFormat> {
+        match format {
+            1 => Some(ScreenshotFormat::Rgba8888),
+            2 => Some(ScreenshotFormat::Rgbx8888),
+            3 => Some(ScreenshotFormat::Rgb888),
+            4 => Some(ScreenshotFormat::Rgb565),
+            _ => None,
+        }
+    }
+
+    fn bytes_per_pixel(self) -> usize {
+        match self {
+            ScreenshotFormat::Png => 0,
+            ScreenshotFormat::Rgba8888 | ScreenshotFormat::Rgbx8888 => 4,
+            ScreenshotFormat::Rgb888 => 3,
+            ScreenshotFormat::Rgb565 => 2,
+        }
+    }
+}
+
+/// A decoded screenshot, see [`Device::screencap_decoded`].
+#[cfg(feature = "image")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screenshot {
+    pub width: u32,
+    pub height: u32,
+    /// Format the pixels were converted from.
+    pub format: ScreenshotFormat,
+    /// Rows of RGBA pixels, four bytes each, top to bottom.
+    pub pixels: Vec<u8>,
+}
+
+#[cfg(feature = "image")]
+impl Screenshot {
+    /// Decodes the output of `screencap -p` (PNG) or plain `screencap`
+    /// (the raw framebuffer).
+    ///
+    /// Raw output starts with width, height and pixel format as
+    /// little-endian `u32`s, followed by the color space on Android 9 and
+    /// later.
+    pub fn decode(data: &[u8]) -> Result<Screenshot> {
+        if data.starts_with(PNG_SIGNATURE) {
+            return decode_png(data);
+        }
+
+        let field = |i: usize| {
+            data.get(i * 4..i * 4 + 4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        };
+        let (Some(width), Some(height), Some(format)) = (field(0), field(1), field(2)) else {
+            return Err(invalid("raw screenshot", "header is truncated"));
+        };
+        let format = ScreenshotFormat::from_android(format)
+            .ok_or_else(|| invalid("raw screenshot", &format!("pixel format {format}")))?;
+
+        let size = width as usize * height as usize * format.bytes_per_pixel();
+        let header = match data.len().checked_sub(size) {
+            Some(header @ (12 | 16)) => header,
+            _ => {
+                return Err(invalid(
+                    "raw screenshot",
+                    &format!(
+                        "{} bytes for {width}x{height} {format:?} pixels",
+                        data.len()
+                    ),
+                ))
+            }
+        };
+
+        let source = &data[header..];
+        let pixels = match format {
+            ScreenshotFormat::Rgba8888 => source.to_vec(),
+            ScreenshotFormat::Rgbx8888 => source
+                .chunks_exact(4)
+                .flat_map(|p| [p[0], p[1], p[2], 0xff])
+                .collect(),
+            ScreenshotFormat::Rgb888 => source
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 0xff])
+                .collect(),
+            ScreenshotFormat::Rgb565 => source
+                .chunks_exact(2)
+                .flat_map(|p| {
+                    let v = u16::from_le_bytes([p[0], p[1]]);
+                    let (r, g, b) = ((v >> 11) & 0x1f, (v >> 5) & 0x3f, v & 0x1f);
+                    // Scale to 8 bits so that the maximum maps to 0xff.
+                    [
+                        (r * 255 / 31) as u8,
+                        (g * 255 / 63) as u8,
+                        (b * 255 / 31) as u8,
+                        0xff,
+                    ]
+                })
+                .collect(),
+            ScreenshotFormat::Png => unreachable!("not a raw format"),
+        };
+
+        Ok(Screenshot {
+            width,
+            height,
+            format,
+            pixels,
+        })
+    }
+}
+
+#[cfg(feature = "image")]
+fn decode_png(data: &[u8]) -> Result<Screenshot> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder
+        .read_info()
+        .map_err(|e| invalid("PNG screenshot", &e.to_string()))?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut buf)
+        .map_err(|e| invalid("PNG screenshot", &e.to_string()))?;
+    buf.truncate(info.buffer_size());
+
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => buf,
+        png::ColorType::Rgb => buf
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 0xff])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buf
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        png::ColorType::Grayscale => buf.iter().flat_map(|&v| [v, v, v, 0xff]).collect(),
+        png::ColorType::Indexed => {
+            return Err(invalid("PNG screenshot", "palette was not expanded"))
+        }
+    };
+
+    Ok(Screenshot {
+        width: info.width,
+        height: info.height,
+        format: ScreenshotFormat::Png,
+        pixels,
+    })
+}
+
+#[cfg(feature = "image")]
+fn invalid(what: &str, reason: &str) -> DeviceError {
+    DeviceError::Adb(format!("Invalid {what}: {reason}"))
+}
+
+#[cfg(all(test, feature = "image"))]
+mod tests {
+    use super::*;
+
+    fn raw(width: u32, height: u32, format: u32, dataspace: bool, pixels: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&width.to_le_bytes());
+        data.extend_from_slice(&height.to_le_bytes());
+        data.extend_from_slice(&format.to_le_bytes());
+        if dataspace {
+            data.extend_from_slice(&0u32.to_le_bytes());
+        }
+        data.extend_from_slice(pixels);
+        data
+    }
+
+    #[test]
+    fn decodes_raw_framebuffers() {
+        let pixels = [1, 2, 3, 4, 5, 6, 7, 8];
+        let shot = Screenshot::decode(&raw(2, 1, 1, true, &pixels)).expect("rgba");
+        assert_eq!((shot.width, shot.height), (2, 1));
+        assert_eq!(shot.format, ScreenshotFormat::Rgba8888);
+        assert_eq!(shot.pixels, pixels);
+
+        let shot = Screenshot::decode(&raw(2, 1, 2, false, &pixels)).expect("rgbx");
+        assert_eq!(shot.pixels, [1, 2, 3, 0xff, 5, 6, 7, 0xff]);
+
+        let shot =
+            Screenshot::decode(&raw(1, 1, 4, false, &0xf800u16.to_le_bytes())).expect("rgb565");
+        assert_eq!(shot.pixels, [0xff, 0, 0, 0xff]);
+
+        assert!(Screenshot::decode(&raw(2, 2, 1, true, &pixels)).is_err());
+        assert!(Screenshot::decode(&raw(1, 1, 42, true, &pixels[..4])).is_err());
+        assert!(Screenshot::decode(b"/system/bin/sh: screencap: not found\n").is_err());
+    }
+
+    #[test]
+    fn decodes_png() {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, 2, 1);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().expect("header");
+        writer
+            .write_image_data(&[10, 20, 30, 40, 50, 60])
+            .expect("data");
+        writer.finish().expect("finish");
+
+        let shot = Screenshot::decode(&png).expect("png");
+        assert_eq!((shot.width, shot.height), (2, 1));
+        assert_eq!(shot.format, ScreenshotFormat::Png);
+        assert_eq!(shot.pixels, [10, 20, 30, 0xff, 40, 50, 60, 0xff]);
+    }
+}
@@ -40,9 +40,15 @@ pub fn escape(input: &str) -> String {
     output.replace("'\n'", r"\n")
 }
 
+/// Quotes a string in single quotes, so that the shell passes it on
+/// verbatim, `&`, `?`, `#` and the like included.
+pub fn quote(input: &str) -> String {
+    format!("'{}'", input.replace('\'', r"'\''"))
+}
+
 #[cfg(test)]
 mod tests {
-    use super::escape;
+    use super::{escape, quote};
 
     #[test]
     fn empty_escape() {
@@ -59,6 +65,13 @@ mod tests {
         assert_eq!(escape("あい"), "\\あ\\い");
     }
 
+    #[test]
+    fn quote_special_characters() {
+        assert_eq!(quote("a?b=1&c=$HOME#x"), "'a?b=1&c=$HOME#x'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+        assert_eq!(quote(""), "''");
+    }
+
     #[test]
     fn escape_newline() {
         assert_eq!(escape(r"'\n'"), "\\\'\\\\n\\\'");
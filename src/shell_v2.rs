@@ -0,0 +1,195 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Structured shell execution.
+//!
+//! [`Device::run`] uses the shell v2 protocol, which keeps stdout and stderr
+//! apart and reports the exit code of the command.  Devices without the
+//! `shell_v2` feature fall back to the legacy `shell:` service with the exit
+//! code appended to the output behind a marker.
+
+#[cfg(not(feature = "tracing"))]
+use log::trace;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+#[cfg(feature = "tracing")]
+use tracing::trace;
+
+use crate::adb::ShellPacket;
+use crate::transport::Transport;
+use crate::{command_timeout, encode_message, read_response, Device, DeviceError, Result};
+
+/// Separates the output of a legacy shell command from its exit code.
+pub(crate) const EXIT_MARKER: &str = "\x1fforensic-adb-exit:";
+
+/// Output and exit status of a shell command.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShellOutput {
+    pub stdout: Vec<u8>,
+    /// Always empty on devices without shell v2, where stderr is merged into
+    /// stdout.
+    pub stderr: Vec<u8>,
+    pub exit_code: i32,
+}
+
+impl ShellOutput {
+    /// Whether the command exited with status 0.
+    pub fn success(&self) -> bool {
+        self.exit_code == 0
+    }
+
+    /// Stdout decoded as UTF-8, with invalid sequences replaced.
+    pub fn stdout_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.stdout).replace("\r\n", "\n")
+    }
+
+    /// Stderr decoded as UTF-8, with invalid sequences replaced.
+    pub fn stderr_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.stderr).replace("\r\n", "\n")
+    }
+}
+
+/// Wraps `command` so the legacy shell prints its exit code after the output.
+///
+/// Like in [`batch_script`](crate::batch::batch_script) the command gets
+/// lines of its own, so a trailing comment cannot swallow the `echo`.
+pub(crate) fn wrap_legacy_command(command: &str) -> String {
+    format!("(\n{command}\n); echo \"{EXIT_MARKER}$?\"")
+}
+
+/// Splits the output of a command wrapped by [`wrap_legacy_command`].
+pub(crate) fn parse_legacy_output(mut output: Vec<u8>) -> Result<ShellOutput> {
+    let marker = EXIT_MARKER.as_bytes();
+    let position = output
+        .windows(marker.len())
+        .rposition(|window| window == marker)
+        .ok_or_else(|| DeviceError::Adb("shell output is missing the exit code".to_owned()))?;
+
+    let exit_code = std::str::from_utf8(&output[position + marker.len()..])?
+        .trim()
+        .parse::<i32>()?;
+    output.truncate(position);
+
+    Ok(ShellOutput {
+        stdout: output,
+        stderr: Vec::new(),
+        exit_code,
+    })
+}
+
+/// Reads shell v2 packets until the exit packet arrives.
+async fn read_shell_v2(stream: &mut dyn Transport) -> Result<ShellOutput> {
+    let mut output = ShellOutput::default();
+
+    loop {
+        let mut header = [0u8; 5];
+        stream.read_exact(&mut header).await?;
+        let length = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+        let mut payload = vec![0u8; length];
+        stream.read_exact(&mut payload).await?;
+
+        match ShellPacket::from_id(header[0]) {
+            Some(ShellPacket::Stdout) => output.stdout.extend_from_slice(&payload),
+            Some(ShellPacket::Stderr) => output.stderr.extend_from_slice(&payload),
+            Some(ShellPacket::Exit) => {
+                output.exit_code = payload.first().copied().unwrap_or_default().into();
+                return Ok(output);
+            }
+            _ => trace!("ignoring shell v2 packet {}", header[0]),
+        }
+    }
+}
+
+impl Device {
+    /// Runs `command` and returns its output and exit code.
+    ///
+    /// Unlike [`Device::execute_host_shell_command`] this does not require
+    /// inspecting the output to find out whether the command succeeded.
+    /// `command` runs as the shell user: neither [`Device::su`] nor
+    /// [`Device::run_as_package`] is applied.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self), fields(serial = %self.serial, exit_code = tracing::field::Empty), err)
+    )]
+    pub async fn run(&self, command: &str) -> Result<ShellOutput> {
+        let output = if self.supports_shell_v2().await? {
+            self.run_shell_v2(command).await?
+        } else {
+            let output = self
+                .execute_host_command(
+                    &format!("shell:{}", wrap_legacy_command(command)),
+                    true,
+                    false,
+                )
+                .await?;
+            parse_legacy_output(output)?
+        };
+        record_span!("exit_code", output.exit_code);
+
+        Ok(output)
+    }
+
+    async fn run_shell_v2(&self, command: &str) -> Result<ShellOutput> {
+        let mut stream = self.connect_transport().await?;
+
+        let request = format!("shell,v2,raw:{command}");
+        trace!("run_shell_v2: >> {:?}", &request);
+        self.audit_command(&request)?;
+        stream
+            .write_all(encode_message(&request)?.as_bytes())
+            .await?;
+
+        command_timeout(self.timeouts.command, async {
+            read_response(&mut stream, false, false).await?;
+
+            // Nothing is written to stdin, so let the command see EOF.  The
+            // command may already have exited and closed the connection.
+            let _ = stream
+                .write_all(&[ShellPacket::CloseStdin.id(), 0, 0, 0, 0])
+                .await;
+
+            read_shell_v2(&mut stream).await
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_output_with_exit_code() {
+        let output = parse_legacy_output(format!("foo\nbar\n{EXIT_MARKER}3\n").into_bytes())
+            .expect("parsed");
+        assert_eq!(output.stdout, b"foo\nbar\n");
+        assert_eq!(output.exit_code, 3);
+        assert!(!output.success());
+    }
+
+    #[test]
+    fn legacy_output_without_trailing_newline() {
+        let output =
+            parse_legacy_output(format!("foo{EXIT_MARKER}0\r\n").into_bytes()).expect("parsed");
+        assert_eq!(output.stdout, b"foo");
+        assert!(output.success());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn legacy_command_with_trailing_comment() {
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(wrap_legacy_command("echo foo; exit 3 # comment"))
+            .output()
+            .expect("sh");
+        let output = parse_legacy_output(output.stdout).expect("parsed");
+        assert_eq!(output.stdout, b"foo\n");
+        assert_eq!(output.exit_code, 3);
+    }
+
+    #[test]
+    fn legacy_output_missing_marker() {
+        assert!(parse_legacy_output(b"foo\n".to_vec()).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Hole-preserving writes for pulls of sparse files such as emulator images
+//! and partition dumps.
+//!
+//! The sync protocol always carries every byte, so zeros still travel over
+//! the wire; what is saved is the host disk space.  Pushes cannot create
+//! holes on the device for the same reason.
+
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::fs::File;
+use tokio::io::{AsyncSeek, AsyncWrite, AsyncWriteExt};
+
+/// Size of the blocks checked for zeros, the usual file system block size.
+const SPARSE_BLOCK: u64 = 4096;
+
+/// Bytes from `pos` up to the next block boundary.
+fn to_boundary(pos: u64) -> usize {
+    (SPARSE_BLOCK - pos % SPARSE_BLOCK) as usize
+}
+
+/// Length of the zero blocks at the start of `buf`, which is found at offset
+/// `pos` of the file.  Blocks cut off by the end of `buf` do not count.
+fn zero_blocks(buf: &[u8], pos: u64) -> usize {
+    let mut len = 0;
+    loop {
+        let end = len + to_boundary(pos + len as u64);
+        if end > buf.len() || buf[len..end].iter().any(|b| *b != 0) {
+            return len;
+        }
+        len = end;
+    }
+}
+
+/// Length of the data at the start of `buf` before the next zero block.
+fn data_blocks(buf: &[u8], pos: u64) -> usize {
+    let mut len = 0;
+    while len < buf.len() && zero_blocks(&buf[len..], pos + len as u64) == 0 {
+        len = buf.len().min(len + to_boundary(pos + len as u64));
+    }
+    len
+}
+
+/// Number of bytes of `buf` that a [`SparseFile`] leaves as holes.
+pub(crate) fn sparse_bytes(buf: &[u8], pos: u64) -> u64 {
+    let mut sparse = 0;
+    let mut len = 0;
+    while len < buf.len() {
+        let zeros = zero_blocks(&buf[len..], pos + len as u64);
+        sparse += zeros as u64;
+        len += zeros + data_blocks(&buf[len + zeros..], pos + (len + zeros) as u64);
+    }
+    sparse
+}
+
+/// Writer that seeks over blocks of zeros instead of writing them.
+///
+/// [`SparseFile::finish`] must be called at the end so that a trailing hole
+/// still counts towards the file length.
+pub(crate) struct SparseFile {
+    file: File,
+    /// Logical offset of the next byte.
+    pos: u64,
+    /// Zero blocks were skipped since the last write.
+    hole: bool,
+    seeking: bool,
+}
+
+impl SparseFile {
+    pub(crate) fn new(file: File) -> SparseFile {
+        SparseFile {
+            file,
+            pos: 0,
+            hole: false,
+            seeking: false,
+        }
+    }
+
+    /// Flushes the file and extends it over a trailing hole.
+    pub(crate) async fn finish(mut self) -> io::Result<File> {
+        self.file.flush().await?;
+        if self.hole {
+            self.file.set_len(self.pos).await?;
+        }
+        Ok(self.file)
+    }
+
+    /// Moves the file cursor past a pending hole.
+    fn poll_skip_hole(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if !self.hole {
+            return Poll::Ready(Ok(()));
+        }
+        if !self.seeking {
+            // Finish pending writes before seeking.
+            ready!(Pin::new(&mut self.file).poll_complete(cx))?;
+            Pin::new(&mut self.file).start_seek(SeekFrom::Start(self.pos))?;
+            self.seeking = true;
+        }
+        ready!(Pin::new(&mut self.file).poll_complete(cx))?;
+        self.seeking = false;
+        self.hole = false;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for SparseFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        let zeros = zero_blocks(buf, this.pos);
+        if zeros > 0 {
+            this.pos += zeros as u64;
+            this.hole = true;
+            return Poll::Ready(Ok(zeros));
+        }
+
+        ready!(this.poll_skip_hole(cx))?;
+        let len = data_blocks(buf, this.pos);
+        let written = ready!(Pin::new(&mut this.file).poll_write(cx, &buf[..len]))?;
+        this.pos += written as u64;
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().file).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().file).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_aligned_zero_runs() {
+        let mut buf = vec![0u8; 3 * 4096];
+        buf[4096] = 1;
+        assert_eq!(zero_blocks(&buf, 0), 4096);
+        assert_eq!(data_blocks(&buf[4096..], 4096), 4096);
+        assert_eq!(sparse_bytes(&buf, 0), 2 * 4096);
+
+        // Blocks cut off by the end of the buffer are written.
+        assert_eq!(sparse_bytes(&buf[..4000], 0), 0);
+        assert_eq!(sparse_bytes(&buf[..4096 + 100], 4096 - 100), 100);
+        assert_eq!(sparse_bytes(b"data", 0), 0);
+    }
+}
@@ -0,0 +1,127 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Apps holding special access: accessibility services, notification
+//! listeners, device admins and usage access, the capabilities stalkerware
+//! relies on to read the screen, messages and app usage.
+
+use crate::device_policy::parse_device_policy;
+use crate::locale::setting;
+use crate::{Device, Result};
+
+const SPECIAL_ACCESS_COMMANDS: [&str; 4] = [
+    "settings get secure enabled_accessibility_services",
+    "settings get secure enabled_notification_listeners",
+    "dumpsys device_policy",
+    "appops query-op GET_USAGE_STATS allow",
+];
+
+/// A service or receiver of [`SpecialAccessServices`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpecialAccessComponent {
+    /// Component as stored, e.g. `com.example/.SpyService`.
+    pub component: String,
+    pub package: String,
+}
+
+impl SpecialAccessComponent {
+    fn new(component: &str) -> SpecialAccessComponent {
+        SpecialAccessComponent {
+            component: component.to_owned(),
+            package: component
+                .split_once('/')
+                .map_or(component, |(package, _)| package)
+                .to_owned(),
+        }
+    }
+}
+
+/// Result of [`Device::special_access_services`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpecialAccessServices {
+    /// Enabled accessibility services, which see and can act on the screen.
+    pub accessibility_services: Vec<SpecialAccessComponent>,
+    /// Enabled notification listeners, which read every notification.
+    pub notification_listeners: Vec<SpecialAccessComponent>,
+    /// Active device admin receivers of all users.
+    pub device_admins: Vec<SpecialAccessComponent>,
+    /// Packages allowed to read usage stats (`GET_USAGE_STATS`).
+    pub usage_access: Vec<String>,
+}
+
+impl SpecialAccessServices {
+    /// The packages holding any of the accesses, sorted and deduplicated.
+    pub fn packages(&self) -> Vec<&str> {
+        let mut packages: Vec<&str> = self
+            .accessibility_services
+            .iter()
+            .chain(&self.notification_listeners)
+            .chain(&self.device_admins)
+            .map(|component| component.package.as_str())
+            .chain(self.usage_access.iter().map(String::as_str))
+            .collect();
+        packages.sort_unstable();
+        packages.dedup();
+        packages
+    }
+}
+
+impl Device {
+    /// Reports the enabled accessibility services and notification
+    /// listeners from the secure settings, the device admins from `dumpsys
+    /// device_policy` and the usage access grantees from `appops`, in one
+    /// shell round trip.
+    ///
+    /// Settings and app ops are those of the current user.  Usage access is
+    /// empty on releases whose `appops` lacks `query-op`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn special_access_services(&self) -> Result<SpecialAccessServices> {
+        let outputs = self.run_batch(&SPECIAL_ACCESS_COMMANDS).await?;
+        let components = |i: usize| {
+            setting(&outputs[i])
+                .map(|value| {
+                    value
+                        .split(':')
+                        .map(str::trim)
+                        .filter(|component| !component.is_empty())
+                        .map(SpecialAccessComponent::new)
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        let device_admins = if outputs[2].success() {
+            parse_device_policy(&outputs[2].stdout_lossy())
+                .admins
+                .iter()
+                .map(|admin| SpecialAccessComponent::new(&admin.component))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        // One package per line, or `No operations.`.
+        let usage_access = if outputs[3].success() {
+            outputs[3]
+                .stdout_lossy()
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.contains(' '))
+                .map(str::to_owned)
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        Ok(SpecialAccessServices {
+            accessibility_services: components(0),
+            notification_listeners: components(1),
+            device_admins,
+            usage_access,
+        })
+    }
+}
@@ -0,0 +1,115 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+#[cfg(not(feature = "tracing"))]
+use log::debug;
+#[cfg(feature = "tracing")]
+use tracing::debug;
+
+use crate::{Device, DeviceError, DevicePath, Result, UnixPathBuf};
+
+/// API level from which files written by the shell user to shared storage
+/// are no longer readable by apps (scoped storage, Android 11).
+const SCOPED_STORAGE_SDK: u32 = 30;
+
+/// Where on the device files are placed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AndroidStorage {
+    /// The private data directory of the `run-as` package.
+    App,
+    /// `/data/local/tmp`, writable by the shell user.
+    Internal,
+    /// Shared storage, `$EXTERNAL_STORAGE`.
+    Sdcard,
+}
+
+/// Requested storage, see [`Device::select_storage`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AndroidStorageInput {
+    /// Probe the device for the best option.
+    #[default]
+    Auto,
+    App,
+    Internal,
+    Sdcard,
+}
+
+impl Device {
+    /// Decides where files are placed and records the decision in
+    /// [`Device::storage`] and [`Device::storage_root`].
+    ///
+    /// `Auto` picks `App` when `run-as` works for [`Device::run_as_package`],
+    /// `Sdcard` when `$EXTERNAL_STORAGE` is writable and the device predates
+    /// scoped storage, and `Internal` otherwise.  The probes do not modify
+    /// the device, so `Auto` also works on a [`read_only`](Device::read_only)
+    /// device.
+    pub async fn select_storage(&mut self, input: AndroidStorageInput) -> Result<AndroidStorage> {
+        let storage = match input {
+            AndroidStorageInput::Auto => self.probe_storage().await?,
+            AndroidStorageInput::App => {
+                if self.run_as_package.is_none() {
+                    return Err(DeviceError::MissingPackage);
+                }
+                AndroidStorage::App
+            }
+            AndroidStorageInput::Internal => AndroidStorage::Internal,
+            AndroidStorageInput::Sdcard => AndroidStorage::Sdcard,
+        };
+
+        let root = match storage {
+            AndroidStorage::App => {
+                let package = self
+                    .run_as_package
+                    .as_ref()
+                    .ok_or(DeviceError::MissingPackage)?;
+                UnixPathBuf::from("/data/data").join(package)
+            }
+            AndroidStorage::Internal => UnixPathBuf::from("/data/local/tmp"),
+            AndroidStorage::Sdcard => self.external_storage().await?,
+        };
+
+        debug!("Selected {:?} storage at {}", storage, root.display());
+        self.storage = Some(storage);
+        self.storage_root = Some(root);
+
+        Ok(storage)
+    }
+
+    async fn probe_storage(&self) -> Result<AndroidStorage> {
+        if let Some(package) = &self.run_as_package {
+            if self.run(&format!("run-as {package} true")).await?.success() {
+                return Ok(AndroidStorage::App);
+            }
+            debug!("run-as is not available for {}", package);
+        }
+
+        if self.sdk_level().await? < SCOPED_STORAGE_SDK {
+            // `test -w` leaves the evidence untouched, unlike creating a file.
+            let root = self.external_storage().await?;
+            let writable = self
+                .run(&format!("test -w {}", DevicePath::new(&root)?.quoted()))
+                .await?
+                .success();
+            if writable {
+                return Ok(AndroidStorage::Sdcard);
+            }
+            debug!("{} is not writable", root.display());
+        }
+
+        Ok(AndroidStorage::Internal)
+    }
+
+    /// Returns `$EXTERNAL_STORAGE`, or `/sdcard` if it is not set.
+    async fn external_storage(&self) -> Result<UnixPathBuf> {
+        let path = self
+            .execute_host_shell_command("echo $EXTERNAL_STORAGE")
+            .await?;
+        let path = path.trim();
+
+        Ok(match path.is_empty() {
+            true => UnixPathBuf::from("/sdcard"),
+            false => UnixPathBuf::from(path),
+        })
+    }
+}
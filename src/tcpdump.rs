@@ -0,0 +1,259 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Packet capture with `tcpdump` on the device, streamed to a pcap file on
+//! the host.
+
+use std::path::PathBuf;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::{timeout_at, Duration, Instant};
+
+use crate::progress::ProgressSink;
+use crate::shell::quote;
+use crate::{Device, DeviceError, Result, UnixPath};
+
+/// Where [`TcpdumpOptions::push_binary`] puts `tcpdump`.
+const TCPDUMP_DEVICE_PATH: &str = "/data/local/tmp/tcpdump";
+const PCAP_HEADER_LEN: usize = 24;
+const PCAP_RECORD_HEADER_LEN: usize = 16;
+/// Largest snapshot length tcpdump accepts; longer records mean the stream
+/// is not pcap.
+const PCAP_MAX_RECORD_LEN: usize = 262_144;
+
+/// Options for [`Device::tcpdump`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TcpdumpOptions {
+    /// `-i`; all interfaces when unset.
+    pub interface: Option<String>,
+    /// Capture filter in pcap-filter syntax, e.g. `port 53`.
+    pub filter: Option<String>,
+    /// `-s`: bytes kept per packet.
+    pub snaplen: Option<u32>,
+    /// `-c`: stop after this many packets.
+    pub packet_count: Option<u64>,
+    /// Stop after this long.
+    pub duration: Option<Duration>,
+    /// Static `tcpdump` build on the host to push first, for devices that
+    /// lack one.
+    pub push_binary: Option<PathBuf>,
+}
+
+impl TcpdumpOptions {
+    pub fn new() -> TcpdumpOptions {
+        TcpdumpOptions::default()
+    }
+
+    pub fn interface(mut self, interface: &str) -> TcpdumpOptions {
+        self.interface = Some(interface.to_owned());
+        self
+    }
+
+    pub fn filter(mut self, filter: &str) -> TcpdumpOptions {
+        self.filter = Some(filter.to_owned());
+        self
+    }
+
+    pub fn snaplen(mut self, snaplen: u32) -> TcpdumpOptions {
+        self.snaplen = Some(snaplen);
+        self
+    }
+
+    pub fn packet_count(mut self, packet_count: u64) -> TcpdumpOptions {
+        self.packet_count = Some(packet_count);
+        self
+    }
+
+    pub fn duration(mut self, duration: Duration) -> TcpdumpOptions {
+        self.duration = Some(duration);
+        self
+    }
+
+    pub fn push_binary(mut self, path: impl Into<PathBuf>) -> TcpdumpOptions {
+        self.push_binary = Some(path.into());
+        self
+    }
+
+    /// The `tcpdump` command line writing packets unbuffered to stdout.
+    fn command(&self, binary: &str) -> String {
+        let mut command = format!("{binary} -U -w -");
+        command.push_str(&format!(
+            " -i {}",
+            quote(self.interface.as_deref().unwrap_or("any"))
+        ));
+        if let Some(snaplen) = self.snaplen {
+            command.push_str(&format!(" -s {snaplen}"));
+        }
+        if let Some(count) = self.packet_count {
+            command.push_str(&format!(" -c {count}"));
+        }
+        if let Some(filter) = &self.filter {
+            command.push_str(&format!(" {}", quote(filter)));
+        }
+        command
+    }
+}
+
+/// Counters of a capture, reported while it runs and returned at its end.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CaptureStats {
+    pub packets: u64,
+    /// Bytes written to the pcap file, headers included.
+    pub bytes: u64,
+}
+
+impl Device {
+    /// Captures packets with `tcpdump` and writes them to `writer` as a
+    /// pcap file, returning the counters once the capture ends.
+    ///
+    /// See [`Device::tcpdump_with_progress`].
+    pub async fn tcpdump<W: AsyncWrite + Unpin>(
+        &self,
+        options: &TcpdumpOptions,
+        writer: &mut W,
+    ) -> Result<CaptureStats> {
+        self.tcpdump_with_progress(options, writer, |_| {}).await
+    }
+
+    /// Captures packets with `tcpdump` and writes them to `writer` as a
+    /// pcap file, reporting the counters as packets arrive.
+    ///
+    /// The capture ends after [`TcpdumpOptions::packet_count`] packets or
+    /// [`TcpdumpOptions::duration`], and otherwise runs until the future
+    /// is dropped.  Only whole packets are written, so `writer` holds a
+    /// valid pcap file however the capture ended; closing the connection
+    /// stops `tcpdump` on the device.  Capturing needs root, `adbd` running
+    /// as root or a [`SuStrategy`](crate::SuStrategy).
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn tcpdump_with_progress<W: AsyncWrite + Unpin>(
+        &self,
+        options: &TcpdumpOptions,
+        writer: &mut W,
+        progress: impl ProgressSink<CaptureStats>,
+    ) -> Result<CaptureStats> {
+        let binary = match &options.push_binary {
+            Some(path) => {
+                let mut file = tokio::fs::File::open(path).await?;
+                self.push(&mut file, UnixPath::new(TCPDUMP_DEVICE_PATH), 0o755)
+                    .await?;
+                TCPDUMP_DEVICE_PATH
+            }
+            None => "tcpdump",
+        };
+        let deadline = options.duration.map(|duration| Instant::now() + duration);
+        let mut stream = self.open_exec(&options.command(binary), false).await?;
+
+        let mut stats = CaptureStats::default();
+        let mut buf = Vec::with_capacity(64 * 1024);
+        let mut big_endian = None;
+        loop {
+            let read = stream.read_buf(&mut buf);
+            let n = match deadline {
+                Some(deadline) => match timeout_at(deadline, read).await {
+                    Ok(n) => n?,
+                    Err(_) => break,
+                },
+                None => read.await?,
+            };
+
+            // Write what is complete: the file header, then whole records.
+            let mut consumed = 0;
+            let endianness = match big_endian {
+                Some(big_endian) => big_endian,
+                None if buf.len() >= PCAP_HEADER_LEN => {
+                    let is_big_endian = pcap_endianness(&buf[..4])?;
+                    big_endian = Some(is_big_endian);
+                    consumed = PCAP_HEADER_LEN;
+                    is_big_endian
+                }
+                None if n == 0 => break,
+                None => continue,
+            };
+            let packets = stats.packets;
+            while let Some(header) = buf.get(consumed..consumed + PCAP_RECORD_HEADER_LEN) {
+                let length = [header[8], header[9], header[10], header[11]];
+                let length = match endianness {
+                    true => u32::from_be_bytes(length),
+                    false => u32::from_le_bytes(length),
+                } as usize;
+                if length > PCAP_MAX_RECORD_LEN {
+                    return Err(DeviceError::Adb(format!(
+                        "tcpdump output is corrupt: {length} byte packet"
+                    )));
+                }
+                let end = consumed + PCAP_RECORD_HEADER_LEN + length;
+                if buf.len() < end {
+                    break;
+                }
+                consumed = end;
+                stats.packets += 1;
+            }
+
+            if consumed > 0 {
+                writer.write_all(&buf[..consumed]).await?;
+                buf.drain(..consumed);
+                stats.bytes += consumed as u64;
+                if stats.packets > packets {
+                    progress.report(stats);
+                }
+            }
+            if n == 0 {
+                break;
+            }
+        }
+        writer.flush().await?;
+
+        if big_endian.is_none() {
+            return Err(DeviceError::Adb(
+                "tcpdump did not start a capture; it needs root and may have to be pushed"
+                    .to_owned(),
+            ));
+        }
+        Ok(stats)
+    }
+}
+
+/// Whether a pcap file with this magic stores numbers big-endian, for
+/// micro- and nanosecond timestamps.
+fn pcap_endianness(magic: &[u8]) -> Result<bool> {
+    match magic {
+        [0xa1, 0xb2, 0xc3, 0xd4] | [0xa1, 0xb2, 0x3c, 0x4d] => Ok(true),
+        [0xd4, 0xc3, 0xb2, 0xa1] | [0x4d, 0x3c, 0xb2, 0xa1] => Ok(false),
+        _ => Err(DeviceError::Adb(format!(
+            "tcpdump did not write pcap: {}",
+            String::from_utf8_lossy(magic)
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_command() {
+        assert_eq!(
+            TcpdumpOptions::new().command("tcpdump"),
+            "tcpdump -U -w - -i 'any'"
+        );
+        assert_eq!(
+            TcpdumpOptions::new()
+                .interface("wlan0")
+                .snaplen(128)
+                .packet_count(10)
+                .filter("host 10.0.0.1 and port 443")
+                .command(TCPDUMP_DEVICE_PATH),
+            "/data/local/tmp/tcpdump -U -w - -i 'wlan0' -s 128 -c 10 'host 10.0.0.1 and port 443'"
+        );
+    }
+
+    #[test]
+    fn detects_endianness() {
+        assert!(!pcap_endianness(&[0xd4, 0xc3, 0xb2, 0xa1]).unwrap());
+        assert!(pcap_endianness(&[0xa1, 0xb2, 0x3c, 0x4d]).unwrap());
+        assert!(pcap_endianness(b"tcpd").is_err());
+    }
+}
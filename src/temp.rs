@@ -0,0 +1,155 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Temporary files and directories on the device, removed again when their
+//! guard is dropped.
+
+#[cfg(not(feature = "tracing"))]
+use log::warn;
+#[cfg(feature = "tracing")]
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::copy::file_error;
+use crate::{Device, DeviceError, DevicePath, Result, UnixPath, UnixPathBuf};
+
+/// A temporary file or directory on the device, see
+/// [`Device::create_temp_file`] and [`Device::create_temp_dir`].
+///
+/// Dropping the guard removes the path from a background task, on a
+/// best-effort basis.  [`DeviceTempPath::close`] removes it and reports
+/// failures, [`DeviceTempPath::keep`] leaves it on the device.
+#[derive(Debug)]
+pub struct DeviceTempPath {
+    device: Device,
+    path: UnixPathBuf,
+    dir: bool,
+    armed: bool,
+}
+
+impl DeviceTempPath {
+    pub(crate) fn new(device: Device, path: UnixPathBuf, dir: bool) -> DeviceTempPath {
+        DeviceTempPath {
+            device,
+            path,
+            dir,
+            armed: true,
+        }
+    }
+
+    pub fn path(&self) -> &UnixPath {
+        &self.path
+    }
+
+    /// Disarms the guard and returns the path, which stays on the device.
+    pub fn keep(mut self) -> UnixPathBuf {
+        self.armed = false;
+        std::mem::take(&mut self.path)
+    }
+
+    /// Removes the path now.
+    pub async fn close(mut self) -> Result<()> {
+        self.armed = false;
+        remove_temp(&self.device, &self.path, self.dir).await
+    }
+
+    /// Removes the path now, logging a failure instead of returning it.
+    pub(crate) async fn discard(self) {
+        let path = self.path.clone();
+        if let Err(e) = self.close().await {
+            warn!("Failed to remove {}: {}", path.display(), e);
+        }
+    }
+}
+
+impl Drop for DeviceTempPath {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        let (device, path, dir) = (self.device.clone(), self.path.clone(), self.dir);
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move {
+                    if let Err(e) = remove_temp(&device, &path, dir).await {
+                        warn!("Failed to remove {}: {}", path.display(), e);
+                    }
+                });
+            }
+            Err(_) => warn!(
+                "Leaving {} on the device, no runtime to remove it",
+                path.display()
+            ),
+        }
+    }
+}
+
+async fn remove_temp(device: &Device, path: &UnixPath, dir: bool) -> Result<()> {
+    let command = match dir {
+        true => "rm -rf",
+        false => "rm -f",
+    };
+    let quoted = DevicePath::new(path)?.quoted();
+    let output = device
+        .execute_host_shell_command_as(
+            &format!("{command} {quoted}"),
+            device.enable_run_as_for_path(path),
+        )
+        .await?;
+    match output.trim() {
+        "" => Ok(()),
+        message => Err(file_error(message)),
+    }
+}
+
+impl Device {
+    /// A new, unused name in [`Device::tempfile_dir`] starting with `prefix`.
+    pub(crate) fn temp_path(&self, prefix: &str) -> Result<UnixPathBuf> {
+        if prefix.contains('/') {
+            return Err(DeviceError::InvalidPath(
+                prefix.to_owned(),
+                "prefix contains '/'".to_owned(),
+            ));
+        }
+        let path = self
+            .tempfile_dir
+            .join(format!("{prefix}{}", Uuid::new_v4().as_hyphenated()));
+        DevicePath::new(&path)?;
+        Ok(path)
+    }
+
+    /// Creates an empty file in [`Device::tempfile_dir`] whose name starts
+    /// with `prefix`.
+    pub async fn create_temp_file(&self, prefix: &str) -> Result<DeviceTempPath> {
+        self.check_writable("create temporary files")?;
+        let temp = DeviceTempPath::new(self.clone(), self.temp_path(prefix)?, false);
+        self.touch(temp.path()).await?;
+        Ok(temp)
+    }
+
+    /// Creates a directory in [`Device::tempfile_dir`] that only its owner
+    /// can access.  Dropping the guard removes it with its contents.
+    pub async fn create_temp_dir(&self) -> Result<DeviceTempPath> {
+        self.create_temp_dir_with_prefix("tmp.").await
+    }
+
+    pub(crate) async fn create_temp_dir_with_prefix(&self, prefix: &str) -> Result<DeviceTempPath> {
+        self.check_writable("create temporary files")?;
+        let temp = DeviceTempPath::new(self.clone(), self.temp_path(prefix)?, true);
+        let output = self
+            .execute_host_shell_command_as(
+                &format!("mkdir -m 700 {}", DevicePath::new(temp.path())?.quoted()),
+                self.enable_run_as_for_path(temp.path()),
+            )
+            .await?;
+        match output.trim() {
+            "" => Ok(temp),
+            message => {
+                // Nothing was created, so there is nothing to remove.
+                temp.keep();
+                Err(file_error(message))
+            }
+        }
+    }
+}
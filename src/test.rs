@@ -77,6 +77,176 @@ fn encode_message_with_invalid_string() {
     encode_message(&"a".repeat(65536)).expect_err("string lengths exceeds 4 bytes");
 }
 
+#[derive(Debug)]
+struct ScriptedConnector {
+    response: &'static [u8],
+    /// Keep the connection open after the response instead of closing it.
+    hold: bool,
+}
+
+impl Connector for ScriptedConnector {
+    fn connect(&self) -> futures_core::future::BoxFuture<'_, Result<BoxedTransport>> {
+        Box::pin(async move {
+            let (client, mut server) = tokio::io::duplex(1024);
+            let response = self.response;
+            let hold = self.hold;
+            tokio::spawn(async move {
+                let mut request = [0u8; 16];
+                let _ = server.read(&mut request).await;
+                let _ = server.write_all(response).await;
+                if hold {
+                    std::future::pending::<()>().await;
+                }
+            });
+            Ok(Box::new(client) as BoxedTransport)
+        })
+    }
+}
+
+#[derive(Debug)]
+struct UnreachableConnector;
+
+impl Connector for UnreachableConnector {
+    fn connect(&self) -> futures_core::future::BoxFuture<'_, Result<BoxedTransport>> {
+        Box::pin(std::future::pending())
+    }
+}
+
+#[tokio::test]
+async fn host_custom_connector() {
+    let host = Host {
+        connector: Some(Arc::new(ScriptedConnector {
+            response: b"OKAY00040029",
+            hold: false,
+        })),
+        ..Default::default()
+    };
+
+    let version = host.get_host_version().await.expect("to get host version");
+    assert_eq!(version, 0x29);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn host_unix_socket() {
+    let dir = tempdir().expect("tempdir");
+    let path = dir.path().join("adb.sock");
+    let listener = tokio::net::UnixListener::bind(&path).expect("bind socket");
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.expect("accept");
+        let mut request = [0u8; 16];
+        stream.read_exact(&mut request).await.expect("request");
+        assert_eq!(&request, b"000Chost:version");
+        stream.write_all(b"OKAY00040029").await.expect("response");
+    });
+
+    let host = Host {
+        address: Some(
+            format!("localfilesystem:{}", path.display())
+                .parse()
+                .expect("address"),
+        ),
+        ..Default::default()
+    };
+
+    let version = host.get_host_version().await.expect("to get host version");
+    assert_eq!(version, 0x29);
+}
+
+#[test]
+fn host_explicit_address_wins_over_env_socket() {
+    let env_socket = || Some(ServerAddress::Abstract("adb".to_owned()));
+    let host = Host {
+        host: Some("lab-1.example".to_owned()),
+        port: None,
+        ..Host::default()
+    };
+    assert_eq!(
+        host.resolve_server_address(env_socket),
+        ServerAddress::Tcp {
+            host: "lab-1.example".to_owned(),
+            port: 5037
+        }
+    );
+
+    let host = Host {
+        host: None,
+        port: None,
+        ..Host::default()
+    };
+    assert_eq!(
+        host.resolve_server_address(env_socket),
+        ServerAddress::Abstract("adb".to_owned())
+    );
+
+    let unix = ServerAddress::Unix(PathBuf::from("/tmp/adb.sock"));
+    let host = Host {
+        address: Some(unix.clone()),
+        ..host
+    };
+    assert_eq!(host.resolve_server_address(env_socket), unix);
+}
+
+#[tokio::test]
+async fn host_connect_timeout() {
+    let host = Host {
+        connector: Some(Arc::new(UnreachableConnector)),
+        timeouts: Timeouts {
+            connect: Duration::from_millis(50),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    match host.get_host_version().await {
+        Err(DeviceError::ConnectTimeout) => {}
+        other => panic!("Expected connect timeout, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn host_command_timeout() {
+    let host = Host {
+        connector: Some(Arc::new(ScriptedConnector {
+            response: b"OKAY",
+            hold: true,
+        })),
+        timeouts: Timeouts {
+            command: Some(Duration::from_millis(50)),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    match host.get_host_version().await {
+        Err(DeviceError::CommandTimeout) => {}
+        other => panic!("Expected command timeout, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn device_sync_idle_timeout() {
+    let host = Host {
+        connector: Some(Arc::new(ScriptedConnector {
+            response: b"OKAYOKAY",
+            hold: true,
+        })),
+        timeouts: Timeouts {
+            sync_idle: Some(Duration::from_millis(50)),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let device = Device::new(host, "stalled".to_owned(), BTreeMap::new())
+        .await
+        .expect("device");
+
+    match device.stat(UnixPath::new("/sdcard")).await {
+        Err(DeviceError::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::TimedOut),
+        other => panic!("Expected sync idle timeout, got {other:?}"),
+    }
+}
+
 async fn run_device_test<F>(test: F)
 where
     F: for<'a> FnOnce(&'a Device, &'a TempDir, &'a UnixPath) -> BoxFuture<'a, ()>
@@ -198,7 +368,7 @@ async fn host_device_or_default() {
         .expect("connected device with serial");
     assert_eq!(device.run_as_package, None);
     assert_eq!(device.serial, expected_device.serial);
-    assert!(device.tempfile.starts_with("/data/local/tmp"));
+    assert_eq!(device.tempfile_dir, UnixPathBuf::from("/data/local/tmp"));
 }
 
 #[tokio::test]
@@ -1078,3 +1248,4447 @@ fn format_own_device_error_types() {
 //     })
 //     .await;
 // }
+
+#[tokio::test]
+async fn mock_host_devices() {
+    let server = testing::MockServer::with_device("mock-1");
+    server.add_device("mock-2", "unauthorized");
+
+    let devices: Vec<DeviceInfo> = server.host().devices().await.expect("to query devices");
+    assert_eq!(devices.len(), 2);
+    assert_eq!(devices[0].serial, "mock-1");
+    assert_eq!(devices[0].state, DeviceState::Device);
+    assert_eq!(devices[1].state, DeviceState::Unauthorized);
+
+    let device = server
+        .host()
+        .device_or_default::<String>(None)
+        .await
+        .expect("only one device is online");
+    assert_eq!(device.serial, "mock-1");
+}
+
+#[tokio::test]
+async fn mock_device_shell_command() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("uname", "Linux\r\n");
+
+    let device = server.device("mock").await.expect("device");
+    assert_eq!(
+        device
+            .execute_host_shell_command("uname")
+            .await
+            .expect("to have shell output"),
+        "Linux\n"
+    );
+    assert!(server
+        .requests()
+        .contains(&"host:transport:mock".to_owned()));
+}
+
+#[tokio::test]
+async fn mock_device_unknown_serial() {
+    let server = testing::MockServer::with_device("mock");
+    server
+        .host()
+        .device_or_default::<String>(Some(&"other".to_owned()))
+        .await
+        .expect_err("invalid serial");
+}
+
+#[tokio::test]
+async fn mock_device_push_pull_stat() {
+    let server = testing::MockServer::with_device("mock");
+    let device = server.device("mock").await.expect("device");
+    let remote_path = UnixPath::new("/sdcard/test/foo.binary");
+
+    let content: Vec<u8> = (0..100000u32).map(|i| b'0' + (i % 10) as u8).collect();
+    device
+        .push(
+            &mut std::io::Cursor::new(content.clone()),
+            remote_path,
+            0o644,
+        )
+        .await
+        .expect("file has been pushed");
+    assert_eq!(
+        server.file("/sdcard/test/foo.binary"),
+        Some(content.clone())
+    );
+
+    let mut buffer = Vec::new();
+    device
+        .pull(remote_path, &mut buffer)
+        .await
+        .expect("file has been pulled");
+    assert_eq!(buffer, content);
+
+    let stats = device.stat(remote_path).await.expect("to get file stats");
+    assert_eq!(stats.file_mode, UnixFileStatus::RegularFile);
+    assert_eq!(stats.size, content.len() as u32);
+
+    match device.stat(UnixPath::new("/sdcard/missing")).await {
+        Err(DeviceError::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::NotFound),
+        other => panic!("Expected not found error, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn mock_device_list_and_pull_dir() {
+    let server = testing::MockServer::with_device("mock");
+    server.add_file("/sdcard/root/foo1.bar", "foo1.bar");
+    server.add_file("/sdcard/root/bar/foo3.bar", "bar/foo3.bar");
+    server.add_file("/sdcard/root/bar/more/foo3.bar", "bar/more/foo3.bar");
+    server.add_symlink("/sdcard/root/link", "/sdcard/root/foo1.bar");
+    let device = server.device("mock").await.expect("device");
+
+    let mut listings = device
+        .list_dir(UnixPath::new("/sdcard/root"))
+        .await
+        .expect("to list_dir");
+    listings.sort_by_key(|f| f.path.clone());
+    let paths: Vec<_> = listings.iter().map(|f| f.path.as_str()).collect();
+    assert_eq!(
+        paths,
+        [
+            "bar",
+            "bar/foo3.bar",
+            "bar/more",
+            "bar/more/foo3.bar",
+            "foo1.bar",
+            "link"
+        ]
+    );
+    assert_eq!(listings[1].depth, Some(1));
+    assert_eq!(listings[5].file_mode, UnixFileStatus::SymbolicLink);
+
+    let tmp_dir = tempdir().expect("create temp dir");
+    device
+        .pull_dir(UnixPath::new("/sdcard/root"), tmp_dir.path())
+        .await
+        .expect("to pull_dir");
+    for file in ["foo1.bar", "bar/foo3.bar", "bar/more/foo3.bar"] {
+        let content = std::fs::read_to_string(tmp_dir.path().join(file)).expect("pulled file");
+        assert_eq!(content, file);
+    }
+}
+
+#[tokio::test]
+async fn mock_device_list_dir_stream() {
+    use futures::StreamExt;
+
+    let server = testing::MockServer::with_device("mock");
+    for i in 0..50 {
+        server.add_file(&format!("/sdcard/DCIM/{i}.jpg"), "jpg");
+    }
+    server.add_file("/sdcard/DCIM/nested/deeper/x.jpg", "jpg");
+    let device = server.device("mock").await.expect("device");
+
+    let stream = device.list_dir_stream(UnixPath::new("/sdcard/DCIM"));
+    futures::pin_mut!(stream);
+    let first = stream.next().await.expect("entry").expect("metadata");
+    assert_eq!(first.depth, Some(0));
+    // Only the top directory has been listed so far.
+    let lists = |server: &testing::MockServer| {
+        server
+            .requests()
+            .iter()
+            .filter(|r| r.starts_with("sync:LIST"))
+            .count()
+    };
+    assert_eq!(lists(&server), 1);
+
+    let mut streamed: Vec<_> = vec![first];
+    while let Some(entry) = stream.next().await {
+        streamed.push(entry.expect("metadata"));
+    }
+    streamed.sort_by_key(|f| f.path.clone());
+
+    let mut listed = device
+        .list_dir(UnixPath::new("/sdcard/DCIM"))
+        .await
+        .expect("list_dir");
+    listed.sort_by_key(|f| f.path.clone());
+    assert_eq!(streamed, listed);
+    assert!(streamed
+        .iter()
+        .any(|f| f.path == "nested/deeper/x.jpg" && f.depth == Some(2)));
+}
+
+#[tokio::test]
+async fn mock_device_parallel_pull_dir() {
+    let server = testing::MockServer::with_device("mock");
+    let mut expected = Vec::new();
+    for i in 0..20 {
+        let path = format!("dir{}/file{i}.bin", i % 3);
+        let content = vec![b'a' + i as u8; 40 * 1024 + i];
+        server.add_file(&format!("/sdcard/many/{path}"), &content);
+        expected.push((path, content));
+    }
+    let total_bytes: u64 = expected.iter().map(|(_, c)| c.len() as u64).sum();
+
+    let mut device = server.device("mock").await.expect("device");
+    device.pull_concurrency = 4;
+    device.progress_granularity = ProgressGranularity::every_chunk();
+
+    let updates = std::sync::Mutex::new(Vec::new());
+    let tmp_dir = tempdir().expect("create temp dir");
+    device
+        .pull_dir_with_progress(
+            UnixPath::new("/sdcard/many"),
+            tmp_dir.path(),
+            |progress: DirectoryTransferProgress| updates.lock().unwrap().push(progress),
+        )
+        .await
+        .expect("directory has been pulled");
+
+    for (path, content) in &expected {
+        assert_eq!(
+            &std::fs::read(tmp_dir.path().join(path)).expect("pulled file"),
+            content
+        );
+    }
+
+    let updates = updates.into_inner().unwrap();
+    assert!(updates
+        .windows(2)
+        .all(|w| w[0].transferred_bytes <= w[1].transferred_bytes));
+    assert!(updates.iter().all(|p| p.transferred_bytes <= total_bytes));
+    let last = updates.last().expect("progress updates");
+    assert_eq!(last.transferred_files, 20);
+    assert_eq!(last.transferred_bytes, total_bytes);
+}
+
+#[tokio::test]
+async fn mock_device_verified_transfers() {
+    let server = testing::MockServer::with_device("mock");
+    server.add_file("/sdcard/evidence/a.txt", "alpha");
+    server.add_file("/sdcard/evidence/sub/b.txt", "beta");
+    let device = server.device("mock").await.expect("device");
+    let options = TransferOptions::new().verify(HashAlgorithm::Sha256);
+
+    let tmp_dir = tempdir().expect("create temp dir");
+    let report = device
+        .pull_dir_with_options(UnixPath::new("/sdcard/evidence"), tmp_dir.path(), &options)
+        .await
+        .expect("verified pull");
+    assert_eq!(report.files.len(), 2);
+    assert_eq!(report.total_bytes(), 9);
+    let a = report
+        .files
+        .iter()
+        .find(|f| f.device_path == UnixPath::new("/sdcard/evidence/a.txt"))
+        .expect("a.txt");
+    assert_eq!(
+        a.digest.as_deref(),
+        Some("8ed3f6ad685b959ead7022518e1af76cd816f8e8ec7ccdda1ed4018e8f2223f8")
+    );
+    assert_eq!(
+        a.host_path.as_deref(),
+        Some(tmp_dir.path().join("a.txt").as_path())
+    );
+
+    let pushed = device
+        .push_with_options(
+            &mut "gamma".as_bytes(),
+            UnixPath::new("/sdcard/c.txt"),
+            0o644,
+            &TransferOptions::new().verify(HashAlgorithm::Md5),
+        )
+        .await
+        .expect("verified push");
+    assert_eq!(pushed.bytes, 5);
+    assert!(pushed.digest.is_some());
+
+    // A device whose copy differs from what was transferred.
+    server.on_shell(
+        "sha256sum \"/sdcard/evidence/a.txt\"",
+        "0000000000000000000000000000000000000000000000000000000000000000  /sdcard/evidence/a.txt\n",
+    );
+    let mut buffer = Vec::new();
+    match device
+        .pull_with_options(
+            UnixPath::new("/sdcard/evidence/a.txt"),
+            &mut buffer,
+            &options,
+        )
+        .await
+    {
+        Err(DeviceError::ChecksumMismatch(path, host, device)) => {
+            assert_eq!(path, "/sdcard/evidence/a.txt");
+            assert_ne!(host, device);
+        }
+        other => panic!("Expected checksum mismatch, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn mock_device_preserve_times() {
+    use crate::testing::MockEntry;
+
+    let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    let server = testing::MockServer::with_device("mock");
+    server.insert(
+        "/sdcard/old/sub/photo.jpg",
+        MockEntry::File {
+            data: b"jpg".to_vec(),
+            mode: 0o644,
+            mtime: 1_000_000_000,
+        },
+    );
+    server.insert(
+        "/sdcard/old/sub",
+        MockEntry::Directory {
+            mode: 0o755,
+            mtime: 1_000_000_000,
+        },
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let tmp_dir = tempdir().expect("create temp dir");
+    device
+        .pull_dir_with_options(
+            UnixPath::new("/sdcard/old"),
+            tmp_dir.path(),
+            &TransferOptions::new().preserve_times(true),
+        )
+        .await
+        .expect("pull");
+    let modified = |path: &str| {
+        std::fs::metadata(tmp_dir.path().join(path))
+            .expect("metadata")
+            .modified()
+            .expect("mtime")
+    };
+    assert_eq!(modified("sub/photo.jpg"), old);
+    assert_eq!(modified("sub"), old);
+
+    device
+        .push_with_options(
+            &mut "new".as_bytes(),
+            UnixPath::new("/sdcard/pushed.txt"),
+            0o644,
+            &TransferOptions::new().mtime(old),
+        )
+        .await
+        .expect("push");
+    match server.entry("/sdcard/pushed.txt") {
+        Some(MockEntry::File { mtime, .. }) => assert_eq!(mtime, 1_000_000_000),
+        other => panic!("Expected pushed file, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn mock_device_pull_dir_symlinks() {
+    let server = testing::MockServer::with_device("mock");
+    server.add_file("/sdcard/links/real.txt", "real");
+    server.add_symlink("/sdcard/links/relative", "real.txt");
+    server.add_symlink("/sdcard/links/absolute", "/sdcard/links/real.txt");
+    let device = server.device("mock").await.expect("device");
+
+    let tmp_dir = tempdir().expect("create temp dir");
+    let report = device
+        .pull_dir_with_options(
+            UnixPath::new("/sdcard/links"),
+            tmp_dir.path(),
+            &TransferOptions::new(),
+        )
+        .await
+        .expect("pull");
+    assert!(report.symlinks.is_empty());
+    assert!(!tmp_dir.path().join("relative").exists());
+
+    let tmp_dir = tempdir().expect("create temp dir");
+    let mut report = device
+        .pull_dir_with_options(
+            UnixPath::new("/sdcard/links"),
+            tmp_dir.path(),
+            &TransferOptions::new().symlinks(SymlinkMode::Recreate),
+        )
+        .await
+        .expect("pull");
+    report.symlinks.sort_by_key(|link| link.device_path.clone());
+    let targets: Vec<_> = report
+        .symlinks
+        .iter()
+        .map(|link| link.target.display().to_string())
+        .collect();
+    assert_eq!(targets, ["/sdcard/links/real.txt", "real.txt"]);
+
+    #[cfg(unix)]
+    {
+        assert!(report.symlinks.iter().all(|link| link.created));
+        let relative = tmp_dir.path().join("relative");
+        assert_eq!(
+            std::fs::read_link(&relative).expect("link"),
+            PathBuf::from("real.txt")
+        );
+        assert_eq!(std::fs::read_to_string(relative).expect("target"), "real");
+    }
+}
+
+#[tokio::test]
+async fn mock_device_pull_matching() {
+    let server = testing::MockServer::with_device("mock");
+    server.add_file("/sdcard/DCIM/top.jpg", "top");
+    server.add_file("/sdcard/DCIM/Camera/a.jpg", "aa");
+    server.add_file("/sdcard/DCIM/Camera/2024/b.jpg", "bbb");
+    server.add_file("/sdcard/DCIM/Camera/c.png", "cccc");
+    let device = server.device("mock").await.expect("device");
+
+    let updates = std::sync::Mutex::new(Vec::new());
+    let tmp_dir = tempdir().expect("create temp dir");
+    let report = device
+        .pull_matching_with_progress(
+            UnixPath::new("/sdcard/DCIM"),
+            "/sdcard/DCIM/Camera/**/*.jpg",
+            tmp_dir.path(),
+            &TransferOptions::new(),
+            |progress: DirectoryTransferProgress| updates.lock().unwrap().push(progress),
+        )
+        .await
+        .expect("pull");
+
+    assert_eq!(report.files.len(), 2);
+    assert_eq!(report.total_bytes(), 5);
+    assert_eq!(
+        std::fs::read_to_string(tmp_dir.path().join("Camera/2024/b.jpg")).expect("b"),
+        "bbb"
+    );
+    assert!(tmp_dir.path().join("Camera/a.jpg").exists());
+    assert!(!tmp_dir.path().join("top.jpg").exists());
+    assert!(!tmp_dir.path().join("Camera/c.png").exists());
+
+    let updates = updates.into_inner().unwrap();
+    let last = updates.last().expect("progress updates");
+    assert_eq!((last.total_files, last.total_bytes), (2, 5));
+    assert_eq!(last.transferred_files, 2);
+
+    let tmp_dir = tempdir().expect("create temp dir");
+    let report = device
+        .pull_matching(
+            UnixPath::new("/sdcard/DCIM"),
+            "*.jpg",
+            tmp_dir.path(),
+            &TransferOptions::new(),
+        )
+        .await
+        .expect("pull");
+    assert_eq!(report.files.len(), 1);
+    assert!(tmp_dir.path().join("top.jpg").exists());
+
+    assert!(matches!(
+        device
+            .pull_matching(
+                UnixPath::new("/sdcard/DCIM"),
+                "[",
+                tmp_dir.path(),
+                &TransferOptions::new(),
+            )
+            .await,
+        Err(DeviceError::InvalidPattern(..))
+    ));
+}
+
+#[tokio::test]
+async fn mock_device_dry_run() {
+    let server = testing::MockServer::with_device("mock");
+    server.add_file("/sdcard/case/a.txt", "aaa");
+    server.add_file("/sdcard/case/sub/b.txt", "bb");
+    let device = server.device("mock").await.expect("device");
+    let dry_run = TransferOptions::new().dry_run(true);
+
+    let tmp_dir = tempdir().expect("create temp dir");
+    let dest = tmp_dir.path().join("out");
+    let report = device
+        .pull_dir_with_options(UnixPath::new("/sdcard/case"), &dest, &dry_run)
+        .await
+        .expect("dry pull");
+    assert_eq!(report.files.len(), 2);
+    assert_eq!(report.total_bytes(), 5);
+    assert!(!dest.exists());
+
+    let mut buffer = Vec::new();
+    let pulled = device
+        .pull_with_options(UnixPath::new("/sdcard/case/a.txt"), &mut buffer, &dry_run)
+        .await
+        .expect("dry pull");
+    assert_eq!(pulled.bytes, 3);
+    assert!(buffer.is_empty());
+
+    std::fs::create_dir_all(tmp_dir.path().join("up")).expect("dir");
+    std::fs::write(tmp_dir.path().join("up/c.txt"), "cccc").expect("file");
+    let report = device
+        .push_dir_with_options(
+            &tmp_dir.path().join("up"),
+            UnixPath::new("/sdcard/up"),
+            0o644,
+            &dry_run,
+        )
+        .await
+        .expect("dry push");
+    assert_eq!(
+        report.files[0].device_path,
+        UnixPathBuf::from("/sdcard/up/c.txt")
+    );
+    assert_eq!(report.total_bytes(), 4);
+    assert!(server.file("/sdcard/up/c.txt").is_none());
+
+    let removal = device
+        .remove_with_report(UnixPath::new("/sdcard/case"), true)
+        .await
+        .expect("dry remove");
+    assert_eq!(removal.files.len(), 2);
+    assert_eq!(removal.directories.len(), 2);
+    assert_eq!(removal.bytes, 5);
+    assert!(!server.requests().iter().any(|r| r.contains("rm -rf")));
+
+    let apk = tmp_dir.path().join("app.apk");
+    std::fs::write(&apk, "apk").expect("apk");
+    let plan = device
+        .install_package_dry_run(
+            &apk,
+            &InstallOptions::new()
+                .reinstall(true)
+                .grant_runtime_permissions(true),
+        )
+        .await
+        .expect("dry install");
+    assert_eq!(plan.bytes, 3);
+    assert_eq!(
+        plan.command,
+        "cmd package install -r -g \"/data/local/tmp/app.apk\""
+    );
+    assert!(!server
+        .requests()
+        .iter()
+        .any(|r| r.contains("pm install") || r.contains("app.apk")));
+}
+
+#[tokio::test]
+async fn mock_device_pull_dir_tar() {
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Directory);
+    header.set_mode(0o755);
+    header.set_size(0);
+    builder
+        .append_data(&mut header, "./empty/", std::io::empty())
+        .expect("dir");
+    let mut header = tar::Header::new_gnu();
+    header.set_mode(0o644);
+    header.set_size(5);
+    header.set_mtime(1_600_000_000);
+    builder
+        .append_data(&mut header, "./sub/a.txt", &b"hello"[..])
+        .expect("file");
+    let archive = builder.into_inner().expect("archive");
+
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("tar -cf - -C \"/sdcard/case\" . 2>/dev/null", &archive);
+    let device = server.device("mock").await.expect("device");
+
+    let mut buffer = Vec::new();
+    let bytes = device
+        .pull_dir_tar(UnixPath::new("/sdcard/case"), &mut buffer)
+        .await
+        .expect("tar");
+    assert_eq!(bytes, archive.len() as u64);
+    assert_eq!(buffer, archive);
+
+    let tmp_dir = tempdir().expect("create temp dir");
+    device
+        .pull_dir_tar_unpack(UnixPath::new("/sdcard/case"), tmp_dir.path())
+        .await
+        .expect("unpack");
+    let file = tmp_dir.path().join("sub/a.txt");
+    assert_eq!(std::fs::read(&file).expect("file"), b"hello");
+    assert_eq!(
+        std::fs::metadata(&file)
+            .expect("metadata")
+            .modified()
+            .expect("mtime"),
+        SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000)
+    );
+    assert!(tmp_dir.path().join("empty").is_dir());
+
+    assert!(device
+        .pull_dir_tar(UnixPath::new("/sdcard/missing"), &mut Vec::new())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn mock_device_pull_dir_stream_tar() {
+    use tokio::io::AsyncReadExt;
+
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_mode(0o644);
+    header.set_size(5);
+    builder
+        .append_data(&mut header, "./a.txt", &b"hello"[..])
+        .expect("file");
+    let archive = builder.into_inner().expect("archive");
+
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("tar -cf - -C \"/sdcard/case\" . 2>/dev/null", &archive);
+    let device = server.device("mock").await.expect("device");
+
+    let mut reader = device
+        .pull_dir_stream_tar(UnixPath::new("/sdcard/case"))
+        .await
+        .expect("stream");
+    let mut buffer = Vec::new();
+    reader.read_to_end(&mut buffer).await.expect("read");
+    assert_eq!(buffer, archive);
+
+    let mut reader = device
+        .pull_dir_stream_tar(UnixPath::new("/sdcard/missing"))
+        .await
+        .expect("stream");
+    assert!(reader.read_to_end(&mut Vec::new()).await.is_err());
+}
+
+#[tokio::test]
+async fn mock_device_push_tar() {
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Directory);
+    header.set_mode(0o755);
+    header.set_size(0);
+    builder
+        .append_data(&mut header, "./fixtures/", std::io::empty())
+        .expect("dir");
+    for i in 0..3 {
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(1);
+        builder
+            .append_data(&mut header, format!("./fixtures/{i}.txt"), &b"x"[..])
+            .expect("file");
+    }
+    let archive = builder.into_inner().expect("archive");
+
+    let server = testing::MockServer::with_device("mock");
+    let device = server.device("mock").await.expect("device");
+
+    let bytes = device
+        .push_tar(&mut &archive[..], UnixPath::new("/data/local/tmp/case"))
+        .await
+        .expect("push_tar");
+    assert_eq!(bytes, archive.len() as u64);
+    assert!(server
+        .requests()
+        .contains(&"shell:mkdir -p \"/data/local/tmp/case\"".to_owned()));
+    for i in 0..3 {
+        assert_eq!(
+            server.file(&format!("/data/local/tmp/case/fixtures/{i}.txt")),
+            Some(b"x".to_vec())
+        );
+    }
+
+    let mut garbage = vec![0xaa; 512];
+    garbage.extend_from_slice(&[0; 1024]);
+    match device
+        .push_tar(&mut &garbage[..], UnixPath::new("/data/local/tmp/case"))
+        .await
+    {
+        Err(DeviceError::Adb(message)) => assert!(message.contains("Not tar"), "{message}"),
+        other => panic!("Expected tar error, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn mock_device_sparse_pull() {
+    let mut image = vec![0u8; 64 * 1024];
+    image.extend_from_slice(b"data");
+    image.resize(128 * 1024 + 4, 0);
+    let server = testing::MockServer::with_device("mock");
+    server.add_file("/sdcard/disk.img", &image);
+    server.add_file("/sdcard/dump/tail.img", vec![0u8; 8192]);
+    let device = server.device("mock").await.expect("device");
+
+    let tmp_dir = tempdir().expect("create temp dir");
+    let dest = tmp_dir.path().join("disk.img");
+    let pulled = device
+        .pull_to_file(
+            UnixPath::new("/sdcard/disk.img"),
+            &dest,
+            &TransferOptions::new().sparse(true),
+        )
+        .await
+        .expect("sparse pull");
+    assert_eq!(std::fs::read(&dest).expect("image"), image);
+    assert_eq!(pulled.bytes, image.len() as u64);
+    assert_eq!(pulled.sparse_bytes, 16 * 4096 + 15 * 4096);
+
+    // A trailing hole still counts towards the length.
+    let report = device
+        .pull_dir_with_options(
+            UnixPath::new("/sdcard/dump"),
+            tmp_dir.path(),
+            &TransferOptions::new().sparse(true),
+        )
+        .await
+        .expect("sparse pull");
+    assert_eq!(report.files[0].sparse_bytes, 8192);
+    assert_eq!(
+        std::fs::read(tmp_dir.path().join("tail.img")).expect("tail"),
+        vec![0u8; 8192]
+    );
+
+    let dest = tmp_dir.path().join("dense.img");
+    let pulled = device
+        .pull_to_file(
+            UnixPath::new("/sdcard/disk.img"),
+            &dest,
+            &TransferOptions::new(),
+        )
+        .await
+        .expect("pull");
+    assert_eq!(pulled.sparse_bytes, 0);
+    assert_eq!(std::fs::read(&dest).expect("image"), image);
+}
+
+#[tokio::test]
+async fn mock_device_pull_resume() {
+    let image: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let (start, offset) = (1024 * 1024, 2 * 1024 * 1024);
+    let mut hasher = HashAlgorithm::Sha256.hasher();
+    hasher.update(&image[start..offset]);
+
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        &format!("dd if=\"/sdcard/big.img\" iflag=skip_bytes,count_bytes skip={start} count={start} bs=65536 2>/dev/null | sha256sum 2>/dev/null"),
+        format!("{}  -\n", hasher.finish()),
+    );
+    server.on_shell(
+        &format!("dd if=\"/sdcard/big.img\" iflag=skip_bytes skip={offset} bs=65536 2>/dev/null"),
+        &image[offset..],
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let tmp_dir = tempdir().expect("create temp dir");
+    let path = tmp_dir.path().join("big.img");
+    std::fs::write(&path, &image[..offset]).expect("partial");
+    let mut file = tokio::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .await
+        .expect("open");
+    let length = device
+        .pull_resume(UnixPath::new("/sdcard/big.img"), &mut file, offset as u64)
+        .await
+        .expect("resume");
+    assert_eq!(length, image.len() as u64);
+    assert_eq!(std::fs::read(&path).expect("image"), image);
+
+    // The partial file no longer matches the device.
+    let mut corrupted = image[..offset].to_vec();
+    corrupted[offset - 1] ^= 0xff;
+    let mut cursor = std::io::Cursor::new(corrupted);
+    assert!(matches!(
+        device
+            .pull_resume(UnixPath::new("/sdcard/big.img"), &mut cursor, offset as u64)
+            .await,
+        Err(DeviceError::ChecksumMismatch(..))
+    ));
+}
+
+#[tokio::test]
+async fn mock_device_push_resume() {
+    let data: Vec<u8> = (0..200 * 1024).map(|i| (i % 253) as u8).collect();
+    let server = testing::MockServer::with_device("mock");
+    server.add_file("/sdcard/upload.obb", &data[..70 * 1024]);
+    let device = server.device("mock").await.expect("device");
+
+    let sent = device
+        .push_resume(
+            &mut std::io::Cursor::new(data.clone()),
+            UnixPath::new("/sdcard/upload.obb"),
+            0o640,
+        )
+        .await
+        .expect("resume");
+    assert_eq!(sent, 130 * 1024);
+    assert_eq!(server.file("/sdcard/upload.obb").expect("pushed"), data);
+    assert!(server
+        .requests()
+        .contains(&"shell:chmod 640 \"/sdcard/upload.obb\"".to_owned()));
+
+    // Nothing is left to send, and a fresh push starts from zero.
+    let sent = device
+        .push_resume(
+            &mut std::io::Cursor::new(data.clone()),
+            UnixPath::new("/sdcard/upload.obb"),
+            0o640,
+        )
+        .await
+        .expect("complete");
+    assert_eq!(sent, 0);
+    let sent = device
+        .push_resume(
+            &mut std::io::Cursor::new(data.clone()),
+            UnixPath::new("/sdcard/fresh.obb"),
+            0o640,
+        )
+        .await
+        .expect("fresh");
+    assert_eq!(sent, data.len() as u64);
+
+    server.add_file("/sdcard/other.obb", b"different");
+    assert!(matches!(
+        device
+            .push_resume(
+                &mut std::io::Cursor::new(data),
+                UnixPath::new("/sdcard/other.obb"),
+                0o640,
+            )
+            .await,
+        Err(DeviceError::ChecksumMismatch(..))
+    ));
+}
+
+#[tokio::test]
+async fn mock_device_install_options() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "cmd package install --user 10 -r -d --abi arm64-v8a \"/data/local/tmp/app.apk\"",
+        "Success\n",
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let tmp_dir = tempdir().expect("create temp dir");
+    let apk = tmp_dir.path().join("app.apk");
+    std::fs::write(&apk, "apk").expect("apk");
+    let options = InstallOptions::new()
+        .user(10)
+        .reinstall(true)
+        .allow_downgrade(true)
+        .abi("arm64-v8a");
+    device
+        .install_package(&apk, &options)
+        .await
+        .expect("install");
+
+    assert!(matches!(
+        device.install_package(&apk, &InstallOptions::new()).await,
+        Err(DeviceError::PackageManagerError(_))
+    ));
+}
+
+#[tokio::test]
+async fn mock_device_uninstall_options() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("cmd package uninstall -k org.example.app", "Success\n");
+    server.on_shell(
+        "cmd package uninstall org.example.admin",
+        "Failure [DELETE_FAILED_DEVICE_POLICY_MANAGER]\n",
+    );
+    let device = server.device("mock").await.expect("device");
+
+    device
+        .uninstall_package("org.example.app", &UninstallOptions::new().keep_data(true))
+        .await
+        .expect("uninstall");
+
+    match device
+        .uninstall_package("org.example.admin", &UninstallOptions::new())
+        .await
+    {
+        Err(DeviceError::UninstallFailed(package, reason)) => {
+            assert_eq!(package, "org.example.admin");
+            assert_eq!(reason, UninstallFailure::DevicePolicyManager);
+        }
+        other => panic!("unexpected result: {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn mock_device_rollback_package() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("cmd package rollback-app org.example.app", "Success\n");
+    server.on_shell(
+        "cmd package rollback-app org.example.other",
+        "No available rollbacks for: org.example.other\n",
+    );
+    let device = server.device("mock").await.expect("device");
+
+    device
+        .rollback_package("org.example.app")
+        .await
+        .expect("rollback");
+    assert!(matches!(
+        device.rollback_package("org.example.other").await,
+        Err(DeviceError::PackageManagerError(_))
+    ));
+
+    let server = testing::MockServer::with_device("old");
+    server.on_shell("getprop ro.build.version.sdk", "28\n");
+    let device = server.device("old").await.expect("device");
+    assert!(matches!(
+        device.rollback_package("org.example.app").await,
+        Err(DeviceError::MissingFeature(_))
+    ));
+}
+
+#[tokio::test]
+async fn mock_device_install_session() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "cmd package install-create -r",
+        "Success: created install session [42]\n",
+    );
+    server.on_shell("cmd package install-commit 42", "Success\n");
+    server.on_shell("cmd package install-abandon 42", "Success\n");
+    let device = server.device("mock").await.expect("device");
+
+    let session = device
+        .install_session()
+        .create(&InstallOptions::new().reinstall(true))
+        .await
+        .expect("create");
+    assert_eq!(session.id(), 42);
+
+    let base = vec![b'b'; 100 * 1024];
+    session
+        .write("base.apk", &mut &base[..], base.len() as u64)
+        .await
+        .expect("write base");
+    let updates = std::sync::Mutex::new(Vec::new());
+    session
+        .write_with_progress(
+            "split_config.en.apk",
+            &mut &b"split"[..],
+            5,
+            |p: FileTransferProgress| updates.lock().unwrap().push(p.transferred_bytes),
+        )
+        .await
+        .expect("write split");
+    assert_eq!(updates.into_inner().unwrap().last(), Some(&5));
+    assert_eq!(server.file("/data/app/vmdl42.tmp/base.apk"), Some(base));
+    assert_eq!(
+        server.file("/data/app/vmdl42.tmp/split_config.en.apk"),
+        Some(b"split".to_vec())
+    );
+
+    assert!(session
+        .write("../base.apk", &mut &b""[..], 0)
+        .await
+        .is_err());
+    assert!(session
+        .write("short.apk", &mut &b"abc"[..], 10)
+        .await
+        .is_err());
+    session.commit().await.expect("commit");
+
+    let session = device
+        .install_session()
+        .create(&InstallOptions::new().reinstall(true))
+        .await
+        .expect("create");
+    session.abandon().await.expect("abandon");
+
+    server.on_shell(
+        "cmd package install-create -d",
+        "Error: failed to create session\n",
+    );
+    assert!(matches!(
+        device
+            .install_session()
+            .create(&InstallOptions::new().allow_downgrade(true))
+            .await,
+        Err(DeviceError::PackageManagerError(_))
+    ));
+}
+
+#[tokio::test]
+async fn mock_device_list_packages_with() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "cmd package list packages -f -3 -U",
+        "package:/data/app/~~Q==/org.example.b-R==/base.apk=org.example.b uid:10124\n\
+         package:/data/app/~~S==/org.example.a-T==/base.apk=org.example.a uid:10123\n",
+    );
+    server.on_shell("cmd package list packages -d -s", "package:com.android.a\n");
+    let device = server.device("mock").await.expect("device");
+
+    let packages = device
+        .list_packages_with(
+            &PackageListOptions::new()
+                .apk_paths(true)
+                .third_party(true)
+                .show_uid(true),
+        )
+        .await
+        .expect("packages");
+    assert_eq!(
+        packages,
+        vec![
+            PackageListEntry {
+                name: "org.example.a".to_owned(),
+                apk_path: Some(UnixPathBuf::from(
+                    "/data/app/~~S==/org.example.a-T==/base.apk"
+                )),
+                uid: Some(10123),
+            },
+            PackageListEntry {
+                name: "org.example.b".to_owned(),
+                apk_path: Some(UnixPathBuf::from(
+                    "/data/app/~~Q==/org.example.b-R==/base.apk"
+                )),
+                uid: Some(10124),
+            },
+        ]
+    );
+
+    let disabled = device
+        .list_packages_with(&PackageListOptions::new().system(true).disabled(true))
+        .await
+        .expect("packages");
+    assert_eq!(disabled.len(), 1);
+    assert_eq!(disabled[0].name, "com.android.a");
+    assert_eq!(disabled[0].apk_path, None);
+}
+
+#[tokio::test]
+async fn mock_device_pull_apk() {
+    let server = testing::MockServer::with_device("mock");
+    let base = apk::signed_apk(&[b"certificate"]);
+    let split = vec![b's'; 1000];
+    server.add_file("/data/app/org.example-1/base.apk", &base);
+    server.add_file("/data/app/org.example-1/split_config.en.apk", &split);
+    server.on_shell(
+        "cmd package path org.example",
+        "package:/data/app/org.example-1/split_config.en.apk\n\
+         package:/data/app/org.example-1/base.apk\n",
+    );
+    server.on_shell("cmd package path org.missing", "");
+    let device = server.device("mock").await.expect("device");
+    let tmp_dir = tempdir().expect("tempdir");
+
+    let updates = std::sync::Mutex::new(Vec::new());
+    let pulled = device
+        .pull_apk_with_progress(
+            "org.example",
+            tmp_dir.path(),
+            |p: DirectoryTransferProgress| updates.lock().unwrap().push(p.transferred_bytes),
+        )
+        .await
+        .expect("pull apk");
+
+    assert_eq!(pulled.package, "org.example");
+    assert_eq!(pulled.apks.len(), 2);
+    assert_eq!(
+        pulled.apks[0].host_path.as_deref(),
+        Some(tmp_dir.path().join("base.apk").as_path())
+    );
+    assert_eq!(
+        std::fs::read(tmp_dir.path().join("split_config.en.apk")).expect("split"),
+        split
+    );
+    let mut hasher = transfer::HashAlgorithm::Sha256.hasher();
+    hasher.update(b"certificate");
+    assert_eq!(pulled.signing_certificates, vec![hasher.finish()]);
+    assert_eq!(
+        updates.into_inner().unwrap().last(),
+        Some(&(base.len() as u64 + 1000))
+    );
+
+    assert!(matches!(
+        device.pull_apk("org.missing", tmp_dir.path()).await,
+        Err(DeviceError::MissingPackage)
+    ));
+
+    let requests = server.requests().len();
+    match device.pull_apk("x; rm -rf /data", tmp_dir.path()).await {
+        Err(DeviceError::Adb(message)) => assert!(message.contains("invalid package name")),
+        other => panic!("Expected invalid package error, got {other:?}"),
+    }
+    assert_eq!(server.requests().len(), requests);
+}
+
+#[tokio::test]
+async fn mock_device_export_app() {
+    let server = testing::MockServer::with_device("mock");
+    server.add_file(
+        "/data/app/org.example-1/base.apk",
+        apk::signed_apk(&[b"certificate"]),
+    );
+    server.add_file("/data/app/org.example-1/split_config.en.apk", b"split");
+    server.add_file(
+        "/sdcard/Android/obb/org.example/main.7.org.example.obb",
+        b"obb",
+    );
+    server.on_shell(
+        "cmd package path org.example",
+        "package:/data/app/org.example-1/base.apk\n\
+         package:/data/app/org.example-1/split_config.en.apk\n",
+    );
+    server.on_shell(
+        "dumpsys package org.example",
+        "Packages:\n    versionCode=7 minSdk=24 targetSdk=34\n    versionName=1.0 \"beta\"\n",
+    );
+    let device = server.device("mock").await.expect("device");
+    let tmp_dir = tempdir().expect("tempdir");
+
+    let dir = tmp_dir.path().join("export");
+    let export = device
+        .export_app("org.example", &dir)
+        .await
+        .expect("export");
+    assert_eq!(export.apks, vec!["base.apk", "split_config.en.apk"]);
+    assert_eq!(
+        export.obbs,
+        vec!["Android/obb/org.example/main.7.org.example.obb"]
+    );
+    assert_eq!(export.version_code, Some(7));
+    assert_eq!(export.version_name.as_deref(), Some("1.0 \"beta\""));
+    assert_eq!(export.signing_certificates.len(), 1);
+    assert_eq!(
+        std::fs::read(dir.join("Android/obb/org.example/main.7.org.example.obb")).expect("obb"),
+        b"obb"
+    );
+    let manifest = std::fs::read_to_string(dir.join(export::EXPORT_MANIFEST)).expect("manifest");
+    assert_eq!(manifest, export.to_json());
+    assert!(manifest.contains("\"apks\": [\"base.apk\", \"split_config.en.apk\"],"));
+
+    let archive = tmp_dir.path().join("org.example.apks");
+    device
+        .export_app("org.example", &archive)
+        .await
+        .expect("export archive");
+    let mut zip =
+        zip::ZipArchive::new(std::fs::File::open(&archive).expect("archive")).expect("zip");
+    let mut names: Vec<_> = zip.file_names().map(str::to_owned).collect();
+    names.sort();
+    assert_eq!(
+        names,
+        vec![
+            "Android/obb/org.example/main.7.org.example.obb",
+            "base.apk",
+            "manifest.json",
+            "split_config.en.apk",
+        ]
+    );
+    let mut split = String::new();
+    std::io::Read::read_to_string(
+        &mut zip.by_name("split_config.en.apk").expect("split"),
+        &mut split,
+    )
+    .expect("read");
+    assert_eq!(split, "split");
+
+    let requests = server.requests().len();
+    for package in ["../../DCIM", "x; reboot"] {
+        match device.export_app(package, tmp_dir.path()).await {
+            Err(DeviceError::Adb(message)) => assert!(message.contains("invalid package name")),
+            other => panic!("Expected invalid package error, got {other:?}"),
+        }
+    }
+    assert_eq!(server.requests().len(), requests);
+}
+
+#[tokio::test]
+async fn mock_device_backup_app_data() {
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_mode(0o660);
+    header.set_size(7);
+    builder
+        .append_data(&mut header, "./shared_prefs/prefs.xml", &b"<map />"[..])
+        .expect("file");
+    let archive = builder.into_inner().expect("archive");
+
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell_result("run-as org.example true", "", "", 0);
+    server.on_shell("run-as org.example tar -cf - . 2>/dev/null", &archive);
+    server.on_shell_result(
+        "run-as org.release true",
+        "",
+        "run-as: package not debuggable: org.release\n",
+        1,
+    );
+    server.on_shell(
+        "su -c 'tar -cf - -C /data/user/0/org.release .' 2>/dev/null",
+        &archive,
+    );
+    let mut device = server.device("mock").await.expect("device");
+
+    let updates = std::sync::Mutex::new(Vec::new());
+    let mut buffer = Vec::new();
+    let bytes = device
+        .backup_app_data_with_progress("org.example", &mut buffer, |p: FileTransferProgress| {
+            updates.lock().unwrap().push(p.transferred_bytes)
+        })
+        .await
+        .expect("backup");
+    assert_eq!(bytes, archive.len() as u64);
+    assert_eq!(buffer, archive);
+    assert_eq!(updates.into_inner().unwrap().last(), Some(&bytes));
+
+    match device.backup_app_data("org.release", &mut Vec::new()).await {
+        Err(DeviceError::NotDebuggable(package, message)) => {
+            assert_eq!(package, "org.release");
+            assert_eq!(message, "run-as: package not debuggable: org.release");
+        }
+        other => panic!("Expected not debuggable error, got {other:?}"),
+    }
+
+    device.su = SuStrategy::SuC;
+    let mut buffer = Vec::new();
+    device
+        .backup_app_data("org.release", &mut buffer)
+        .await
+        .expect("backup as root");
+    assert_eq!(buffer, archive);
+
+    let requests = server.requests().len();
+    match device
+        .backup_app_data("org.release/../../x; reboot", &mut Vec::new())
+        .await
+    {
+        Err(DeviceError::Adb(message)) => assert!(message.contains("invalid package name")),
+        other => panic!("Expected invalid package error, got {other:?}"),
+    }
+    assert_eq!(server.requests().len(), requests);
+}
+
+#[tokio::test]
+async fn mock_device_acquisition() {
+    let server = testing::MockServer::with_device("mock");
+    server.add_file("/sdcard/DCIM/a.jpg", b"jpeg");
+    server.add_file("/sdcard/DCIM/sub/b.jpg", b"more jpeg");
+    server.add_file("/sdcard/notes.txt", b"notes");
+    server.add_file(
+        "/data/app/org.example-1/base.apk",
+        apk::signed_apk(&[b"certificate"]),
+    );
+    server.on_shell(
+        "cmd package path org.example",
+        "package:/data/app/org.example-1/base.apk\n",
+    );
+    server.on_shell(
+        "getprop",
+        "[ro.product.model]: [Pixel]\n[ro.serialno]: [mock]\n",
+    );
+    server.on_shell("id", "uid=2000(shell)\n");
+    let device = server.device("mock").await.expect("device");
+    let tmp_dir = tempdir().expect("tempdir");
+
+    let spec = acquisition::AcquisitionSpec::new()
+        .path("/sdcard/DCIM")
+        .path("/sdcard/notes.txt")
+        .apk("org.example")
+        .expect("apk")
+        .command("id", "id");
+    let acquisition = acquisition::Acquisition::new(&device).collect(spec);
+
+    let dest = tmp_dir.path().join("evidence.zip");
+    let manifest = acquisition.write_to(&dest).await.expect("zip");
+    assert_eq!(manifest.serial, "mock");
+    assert_eq!(manifest.properties["ro.product.model"], "Pixel");
+    let mut paths: Vec<_> = manifest.entries.iter().map(|e| e.path.as_str()).collect();
+    paths.sort();
+    assert_eq!(
+        paths,
+        vec![
+            "apks/org.example/base.apk",
+            "commands/id.txt",
+            "files/sdcard/DCIM/a.jpg",
+            "files/sdcard/DCIM/sub/b.jpg",
+            "files/sdcard/notes.txt",
+        ]
+    );
+    let notes = manifest
+        .entries
+        .iter()
+        .find(|e| e.source == "/sdcard/notes.txt")
+        .expect("notes");
+    let mut hasher = transfer::HashAlgorithm::Sha256.hasher();
+    hasher.update(b"notes");
+    assert_eq!(notes.sha256, hasher.finish());
+    assert_eq!(notes.bytes, 5);
+
+    let mut zip = zip::ZipArchive::new(std::fs::File::open(&dest).expect("open")).expect("zip");
+    let mut json = String::new();
+    std::io::Read::read_to_string(
+        &mut zip
+            .by_name(acquisition::ACQUISITION_MANIFEST)
+            .expect("manifest"),
+        &mut json,
+    )
+    .expect("read");
+    assert_eq!(json, manifest.to_json());
+    assert_eq!(zip.len(), 6);
+
+    let dest = tmp_dir.path().join("evidence.tar");
+    acquisition.write_to(&dest).await.expect("tar");
+    let mut archive = tar::Archive::new(std::fs::File::open(&dest).expect("open"));
+    let mut names: Vec<_> = archive
+        .entries()
+        .expect("entries")
+        .map(|entry| {
+            entry
+                .expect("entry")
+                .path()
+                .expect("path")
+                .display()
+                .to_string()
+        })
+        .collect();
+    names.sort();
+    assert_eq!(names.len(), 6);
+    assert_eq!(names[3], "files/sdcard/DCIM/sub/b.jpg");
+
+    assert!(acquisition
+        .write_to(&tmp_dir.path().join("evidence.7z"))
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn mock_device_audit_log() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("id", "uid=2000(shell)\n");
+    server.add_file("/sdcard/evidence.txt", b"evidence");
+    let tmp_dir = tempdir().expect("tempdir");
+    let path = tmp_dir.path().join("audit.jsonl");
+    let log = AuditLog::open(&path).expect("open").signing_key(b"key");
+    let device = Device::builder(server.host())
+        .serial("mock")
+        .audit_log(std::sync::Arc::new(log))
+        .build()
+        .await
+        .expect("device");
+
+    device.execute_host_shell_command("id").await.expect("id");
+    let mut buffer = Vec::new();
+    device
+        .pull(UnixPath::new("/sdcard/evidence.txt"), &mut buffer)
+        .await
+        .expect("pull");
+    device
+        .push(&mut &b"note"[..], UnixPath::new("/sdcard/note.txt"), 0o644)
+        .await
+        .expect("push");
+
+    let records = std::fs::read_to_string(&path).expect("read");
+    let mut hasher = transfer::HashAlgorithm::Sha256.hasher();
+    hasher.update(b"evidence");
+    let digest = hasher.finish();
+    assert!(records
+        .lines()
+        .any(|line| line.contains("\"service\": \"shell:id\"")
+            && line.contains("\"serial\": \"mock\"")));
+    assert!(records
+        .lines()
+        .any(|line| line.contains("\"event\": \"pull\"")
+            && line.contains("\"path\": \"/sdcard/evidence.txt\"")
+            && line.contains(&format!("\"bytes\": 8, \"sha256\": \"{digest}\""))));
+    assert!(records
+        .lines()
+        .any(|line| line.contains("\"event\": \"push\"")
+            && line.contains("\"path\": \"/sdcard/note.txt\"")));
+    assert_eq!(
+        AuditLog::verify(&path, Some(b"key")).expect("verify"),
+        records.lines().count() as u64
+    );
+}
+
+#[tokio::test]
+async fn mock_device_read_only() {
+    let server = testing::MockServer::with_device("mock");
+    server.add_file("/sdcard/evidence.txt", b"evidence");
+    let device = Device::builder(server.host())
+        .serial("mock")
+        .read_only(true)
+        .build()
+        .await
+        .expect("device");
+    let evidence = UnixPath::new("/sdcard/evidence.txt");
+
+    let mut buffer = Vec::new();
+    device.pull(evidence, &mut buffer).await.expect("pull");
+    assert_eq!(buffer, b"evidence");
+    let report = device
+        .remove_with_report(evidence, true)
+        .await
+        .expect("dry run");
+    assert_eq!(report.bytes, 8);
+
+    assert!(matches!(
+        device.push(&mut &b"x"[..], evidence, 0o644).await,
+        Err(DeviceError::WriteBlocked(_))
+    ));
+    assert!(matches!(
+        device.remove(evidence).await,
+        Err(DeviceError::WriteBlocked(_))
+    ));
+    assert!(matches!(
+        device.chmod(evidence, "777", false).await,
+        Err(DeviceError::WriteBlocked(_))
+    ));
+    assert!(matches!(
+        device.clear_app_data("org.example").await,
+        Err(DeviceError::WriteBlocked(_))
+    ));
+    assert!(matches!(
+        device
+            .install_session()
+            .create(&InstallOptions::new())
+            .await,
+        Err(DeviceError::WriteBlocked(_))
+    ));
+    assert!(matches!(
+        device.force_stop("org.example").await,
+        Err(DeviceError::WriteBlocked(_))
+    ));
+    assert!(matches!(
+        device
+            .launch_activity("org.example", ".Main", &[] as &[&str])
+            .await,
+        Err(DeviceError::WriteBlocked(_))
+    ));
+    assert!(matches!(
+        device.open_uri("https://example.com", None).await,
+        Err(DeviceError::WriteBlocked(_))
+    ));
+    assert!(matches!(
+        device.reverse_port(8080, 8080).await,
+        Err(DeviceError::WriteBlocked(_))
+    ));
+    assert!(!server
+        .requests()
+        .iter()
+        .any(|r| r.contains("force-stop") || r.contains("am start") || r.starts_with("reverse:")));
+    assert_eq!(
+        server.file("/sdcard/evidence.txt"),
+        Some(b"evidence".to_vec())
+    );
+
+    let writable = device.clone().read_only(false);
+    writable.chmod(evidence, "600", false).await.expect("chmod");
+    assert!(device.read_only);
+}
+
+#[tokio::test]
+async fn mock_host_set() {
+    let local = testing::MockServer::with_device("emulator-5554");
+    let lab = testing::MockServer::with_device("R58M123ABC");
+    lab.add_device("emulator-5554", "device");
+    lab.add_device("0123456789", "unauthorized");
+
+    let hosts = HostSet::new()
+        .add("local", local.host())
+        .add("lab", lab.host())
+        .add(
+            "down",
+            Host {
+                port: Some(1),
+                ..Host::default()
+            },
+        );
+    assert_eq!(hosts.names().collect::<Vec<_>>(), ["local", "lab", "down"]);
+
+    let mut listed: Vec<_> = hosts
+        .devices()
+        .await
+        .into_iter()
+        .map(|device| (device.host, device.info.serial))
+        .collect();
+    listed.sort();
+    assert_eq!(
+        listed,
+        [
+            ("lab".to_owned(), "0123456789".to_owned()),
+            ("lab".to_owned(), "R58M123ABC".to_owned()),
+            ("lab".to_owned(), "emulator-5554".to_owned()),
+            ("local".to_owned(), "emulator-5554".to_owned()),
+        ]
+    );
+
+    let device = hosts.device("R58M123ABC").await.expect("device");
+    assert_eq!(Some(&device.host), hosts.host("lab"));
+    lab.on_shell("id", "uid=2000(shell)\n");
+    assert_eq!(
+        device
+            .execute_host_shell_command("id")
+            .await
+            .expect("shell"),
+        "uid=2000(shell)\n"
+    );
+
+    assert!(matches!(
+        hosts.device("emulator-5554").await,
+        Err(DeviceError::MultipleDevices)
+    ));
+    assert!(matches!(
+        hosts.device("0123456789").await,
+        Err(DeviceError::UnknownDevice(_))
+    ));
+    let device = hosts
+        .device_on("local", "emulator-5554")
+        .await
+        .expect("device");
+    assert_eq!(Some(&device.host), hosts.host("local"));
+    assert!(hosts.device_on("missing", "emulator-5554").await.is_err());
+}
+
+#[tokio::test]
+async fn mock_device_provision_adb_key() {
+    let server = testing::MockServer::with_device("mock");
+    let device = Device::builder(server.host())
+        .serial("mock")
+        .build()
+        .await
+        .expect("device");
+
+    let key = keys::test_key();
+    let public_key = key.public_key();
+    assert!(device.provision_adb_key(&key).await.expect("provision"));
+    assert!(server.requests().iter().any(
+        |request| request.contains(&format!("echo '{public_key}' >> /data/misc/adb/adb_keys"))
+    ));
+
+    server.on_shell(
+        "cat /data/misc/adb/adb_keys",
+        format!("QAAAA other@host\n{public_key}\n"),
+    );
+    assert!(!device.provision_adb_key(&key).await.expect("provision"));
+
+    server.on_shell_result(
+        "cat /data/misc/adb/adb_keys",
+        "",
+        "cat: /data/misc/adb/adb_keys: Permission denied\n",
+        1,
+    );
+    assert!(matches!(
+        device.provision_adb_key(&key).await,
+        Err(DeviceError::Adb(message)) if message.contains("Permission denied")
+    ));
+    let requests = server.requests();
+    assert_eq!(
+        requests
+            .iter()
+            .filter(|request| request.contains(">> /data/misc/adb/adb_keys"))
+            .count(),
+        1
+    );
+
+    let mut rooted = device.clone();
+    rooted.su = SuStrategy::Su0;
+    server.on_shell_result(
+        "su 0 sh -c 'cat /data/misc/adb/adb_keys'",
+        "",
+        "cat: /data/misc/adb/adb_keys: No such file or directory\n",
+        1,
+    );
+    assert!(rooted.provision_adb_key(&key).await.expect("provision"));
+
+    let device = device.read_only(true);
+    assert!(matches!(
+        device.provision_adb_key(&key).await,
+        Err(DeviceError::WriteBlocked(_))
+    ));
+}
+
+#[tokio::test]
+async fn mock_device_direct() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("id", "uid=2000(shell)\n");
+    server.add_file("/sdcard/evidence.txt", b"evidence");
+    server.authorize_adb_key("QAAAA other@host");
+
+    // The key is unknown, so it is offered and accepted.
+    let keys = AdbKeySet::new().key(keys::test_key());
+    let direct = DeviceDirect::over(server.adbd("mock"), "10.0.0.2:5555", &keys)
+        .await
+        .expect("connect");
+    assert_eq!(server.adb_keys().len(), 2);
+    assert_eq!(direct.banner().device_state(), DeviceState::Device);
+
+    // Now the signature is accepted.
+    let direct = DeviceDirect::over(server.adbd("mock"), "10.0.0.2:5555", &keys)
+        .await
+        .expect("connect");
+    assert_eq!(server.adb_keys().len(), 2);
+
+    let device = direct.device().await.expect("device");
+    assert_eq!(device.serial, "10.0.0.2:5555");
+    assert_eq!(device.info["model"], "Mock");
+    assert!(device.supports_shell_v2().await.expect("features"));
+    assert_eq!(
+        device
+            .execute_host_shell_command("id")
+            .await
+            .expect("shell"),
+        "uid=2000(shell)\n"
+    );
+    assert_eq!(device.run("id").await.expect("run").exit_code, 0);
+
+    let mut pulled = Vec::new();
+    device
+        .pull(UnixPath::new("/sdcard/evidence.txt"), &mut pulled)
+        .await
+        .expect("pull");
+    assert_eq!(pulled, b"evidence");
+    let data = vec![7u8; 3 * 1024 * 1024];
+    device
+        .push(&mut &data[..], UnixPath::new("/sdcard/large.bin"), 0o644)
+        .await
+        .expect("push");
+    assert_eq!(server.file("/sdcard/large.bin"), Some(data));
+
+    let devices: Vec<DeviceInfo> = direct.host().devices().await.expect("devices");
+    assert_eq!(devices.len(), 1);
+    assert!(device.forward_port(0, 8080).await.is_err());
+
+    let unknown = AdbKeySet::new();
+    assert!(
+        DeviceDirect::over(server.adbd("mock"), "10.0.0.2:5555", &unknown)
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn adb_device_trait_fake_and_mock() {
+    async fn exercise(device: &dyn AdbDevice) -> Result<(String, Vec<String>, Vec<u8>)> {
+        let model = device
+            .execute_host_shell_command("getprop ro.product.model")
+            .await?;
+        device
+            .push(
+                &mut &b"copy"[..],
+                UnixPath::new("/sdcard/out/copy.txt"),
+                0o600,
+            )
+            .await?;
+        let mut names: Vec<_> = device
+            .list_dir(UnixPath::new("/sdcard"))
+            .await?
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect();
+        names.sort();
+        let mut data = Vec::new();
+        device
+            .pull(UnixPath::new("/sdcard/out/copy.txt"), &mut data)
+            .await?;
+        Ok((model, names, data))
+    }
+
+    let fake = fake::FakeDevice::new("fake");
+    fake.on_shell("getprop ro.product.model", "Pixel\n");
+    fake.add_file("/sdcard/notes.txt", "hello");
+    fake.add_package("org.example", true);
+    fake.add_package("com.android.settings", false);
+
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("getprop ro.product.model", "Pixel\n");
+    server.add_file("/sdcard/notes.txt", "hello");
+    let device = server.device("mock").await.expect("device");
+
+    let expected = (
+        "Pixel\n".to_owned(),
+        vec![
+            "notes.txt".to_owned(),
+            "out".to_owned(),
+            "out/copy.txt".to_owned(),
+        ],
+        b"copy".to_vec(),
+    );
+    assert_eq!(exercise(&fake).await.expect("fake"), expected);
+    assert_eq!(exercise(&device).await.expect("mock"), expected);
+
+    assert_eq!(fake.commands(), ["getprop ro.product.model".to_owned()]);
+    let copy = AdbDevice::stat(&fake, UnixPath::new("/sdcard/out/copy.txt"))
+        .await
+        .expect("stat");
+    assert_eq!(
+        (copy.file_mode, copy.size),
+        (UnixFileStatus::RegularFile, 4)
+    );
+    assert!(matches!(
+        fake.entry("/sdcard/out/copy.txt"),
+        Some(testing::MockEntry::File { mode: 0o600, .. })
+    ));
+    AdbDevice::chmod(&fake, UnixPath::new("/sdcard/out"), "700", true)
+        .await
+        .expect("chmod");
+    assert!(matches!(
+        fake.entry("/sdcard/out/copy.txt"),
+        Some(testing::MockEntry::File { mode: 0o700, .. })
+    ));
+    AdbDevice::remove(&fake, UnixPath::new("/sdcard/out"))
+        .await
+        .expect("remove");
+    assert!(
+        !AdbDevice::path_exists(&fake, UnixPath::new("/sdcard/out/copy.txt"), false)
+            .await
+            .expect("exists")
+    );
+    assert_eq!(
+        AdbDevice::list_packages(&fake, true)
+            .await
+            .expect("packages"),
+        ["org.example"]
+    );
+    assert!(AdbDevice::is_app_installed(&fake, "com.android.settings")
+        .await
+        .expect("installed"));
+}
+
+#[tokio::test]
+async fn mock_host_record_and_replay() {
+    use crate::record::{RecordingConnector, ReplayConnector};
+
+    async fn session(host: Host, dest: &std::path::Path, concurrency: usize) -> Result<String> {
+        let mut device = host.device_or_default(Some(&"mock")).await?;
+        device.pull_concurrency = concurrency;
+        device.pull_dir(UnixPath::new("/sdcard/many"), dest).await?;
+        device
+            .execute_host_shell_command("getprop ro.product.model")
+            .await
+    }
+
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("getprop ro.product.model", "Pixel\n");
+    for i in 0..8 {
+        server.add_file(
+            &format!("/sdcard/many/dir{}/file{i}.bin", i % 3),
+            vec![b'a' + i as u8; 1024 + i],
+        );
+    }
+
+    let recording_dir = tempdir().expect("create temp dir");
+    let recording = recording_dir.path().join("session.rec");
+    let recorder = RecordingConnector::new(server.host(), &recording).expect("recorder");
+    let host = Host {
+        connector: Some(Arc::new(recorder)),
+        ..Default::default()
+    };
+    let recorded_dir = tempdir().expect("create temp dir");
+    let model = session(host, recorded_dir.path(), 4)
+        .await
+        .expect("recorded session");
+    assert_eq!(model, "Pixel\n");
+
+    // No mock server is involved from here on.
+    let replay = ReplayConnector::open(&recording).expect("recording");
+    let host = Host {
+        connector: Some(Arc::new(replay.clone())),
+        ..Default::default()
+    };
+    let replayed_dir = tempdir().expect("create temp dir");
+    let model = session(host.clone(), replayed_dir.path(), 4)
+        .await
+        .expect("replayed session");
+    assert_eq!(model, "Pixel\n");
+    assert_eq!(replay.remaining(), 0);
+    for i in 0..8 {
+        let path = format!("dir{}/file{i}.bin", i % 3);
+        assert_eq!(
+            std::fs::read(replayed_dir.path().join(&path)).expect("replayed file"),
+            std::fs::read(recorded_dir.path().join(&path)).expect("recorded file"),
+        );
+    }
+
+    // Sync requests do not have to come on the connections they were
+    // recorded on.
+    let replay = ReplayConnector::open(&recording).expect("recording");
+    let host = Host {
+        connector: Some(Arc::new(replay.clone())),
+        ..Default::default()
+    };
+    let replayed_dir = tempdir().expect("create temp dir");
+    session(host, replayed_dir.path(), 1)
+        .await
+        .expect("replayed session");
+    assert_eq!(replay.remaining(), 0);
+    for i in 0..8 {
+        let path = format!("dir{}/file{i}.bin", i % 3);
+        assert!(replayed_dir.path().join(&path).exists());
+    }
+
+    // Requests that were never recorded fail instead of hanging.
+    let replay = ReplayConnector::open(&recording).expect("recording");
+    let host = Host {
+        connector: Some(Arc::new(replay)),
+        ..Default::default()
+    };
+    let device = host.device_or_default(Some(&"mock")).await.expect("device");
+    assert!(device
+        .execute_host_shell_command("getprop ro.build.version.sdk")
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn mock_device_pull_reports_throughput() {
+    let server = testing::MockServer::with_device("mock");
+    // Larger than the read buffer, in 64K DATA chunks.
+    let content: Vec<u8> = (0..700 * 1024).map(|i| (i % 251) as u8).collect();
+    server.add_file("/sdcard/big.bin", &content);
+    server.add_file("/sdcard/dir/small.bin", b"small");
+    let device = server.device("mock").await.expect("device");
+
+    let mut pulled = Vec::new();
+    let file = device
+        .pull_with_options(
+            UnixPath::new("/sdcard/big.bin"),
+            &mut pulled,
+            &TransferOptions::new().verify(HashAlgorithm::Sha256),
+        )
+        .await
+        .expect("pulled");
+    assert_eq!(pulled, content);
+    assert_eq!(file.bytes, content.len() as u64);
+    assert!(file.throughput().expect("timed") > 0.0);
+
+    let tmp_dir = tempdir().expect("create temp dir");
+    let report = device
+        .pull_dir_with_options(
+            UnixPath::new("/sdcard/dir"),
+            tmp_dir.path(),
+            &TransferOptions::new(),
+        )
+        .await
+        .expect("pulled");
+    assert!(report.throughput().is_some());
+
+    let report = device
+        .pull_dir_with_options(
+            UnixPath::new("/sdcard/dir"),
+            tmp_dir.path(),
+            &TransferOptions::new().dry_run(true),
+        )
+        .await
+        .expect("planned");
+    assert_eq!(report.throughput(), None);
+}
+
+#[tokio::test]
+async fn mock_device_push_pipelined_chunks() {
+    let server = testing::MockServer::with_device("mock");
+    let device = server.device("mock").await.expect("device");
+
+    // A source producing odd-sized pieces while the previous chunk is sent.
+    let content: Vec<u8> = (0..300 * 1024).map(|i| (i % 253) as u8).collect();
+    let (mut writer, mut reader) = tokio::io::duplex(10_000);
+    let source = content.clone();
+    let feeder = tokio::spawn(async move {
+        for piece in source.chunks(7_777) {
+            writer.write_all(piece).await.expect("feed");
+            tokio::task::yield_now().await;
+        }
+    });
+
+    let file = device
+        .push_with_options(
+            &mut reader,
+            UnixPath::new("/data/local/tmp/piped.bin"),
+            0o644,
+            &TransferOptions::new().verify(HashAlgorithm::Sha256),
+        )
+        .await
+        .expect("pushed");
+    feeder.await.expect("feeder");
+
+    assert_eq!(file.bytes, content.len() as u64);
+    assert_eq!(
+        server
+            .file("/data/local/tmp/piped.bin")
+            .expect("pushed file"),
+        content
+    );
+}
+
+#[tokio::test]
+async fn mock_device_pull_parallel() {
+    let image: Vec<u8> = (0..1300 * 1024).map(|i| (i % 241) as u8).collect();
+    let server = testing::MockServer::with_device("mock");
+    server.add_file("/dev/block/by-name/userdata", &image);
+    let device = server.device("mock").await.expect("device");
+
+    let tmp_dir = tempdir().expect("create temp dir");
+    let dest = tmp_dir.path().join("userdata.img");
+    let file = device
+        .pull_parallel(UnixPath::new("/dev/block/by-name/userdata"), &dest, 4)
+        .await
+        .expect("pulled");
+    assert_eq!(file.bytes, image.len() as u64);
+    assert_eq!(std::fs::read(&dest).expect("image"), image);
+
+    let ranges = server
+        .requests()
+        .into_iter()
+        .filter(|request| request.contains("iflag=skip_bytes,count_bytes"))
+        .count();
+    assert_eq!(ranges, 4);
+
+    // The device reports a different digest, e.g. because the file changed.
+    server.on_shell(
+        "sha256sum \"/dev/block/by-name/userdata\"",
+        "0000  /dev/block/by-name/userdata\n",
+    );
+    assert!(matches!(
+        device
+            .pull_parallel(UnixPath::new("/dev/block/by-name/userdata"), &dest, 2)
+            .await,
+        Err(DeviceError::ChecksumMismatch(..))
+    ));
+    assert!(device
+        .pull_parallel(UnixPath::new("/dev/block/by-name/missing"), &dest, 2)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn mock_device_dir_transfers_reuse_sync_connections() {
+    let server = testing::MockServer::with_device("mock");
+    for i in 0..12 {
+        server.add_file(
+            &format!("/sdcard/tree/dir{}/file{i}.txt", i % 3),
+            format!("file {i}"),
+        );
+    }
+    let mut device = server.device("mock").await.expect("device");
+    device.pull_concurrency = 3;
+    let sync_connections = || {
+        server
+            .requests()
+            .iter()
+            .filter(|request| *request == "sync:")
+            .count()
+    };
+
+    let tmp_dir = tempdir().expect("create temp dir");
+    let before = sync_connections();
+    device
+        .pull_dir(UnixPath::new("/sdcard/tree"), tmp_dir.path())
+        .await
+        .expect("pulled");
+    // One per listed directory, at most one per concurrent pull.
+    assert!(sync_connections() - before <= 4 + 3);
+    for i in 0..12 {
+        let path = tmp_dir.path().join(format!("dir{}/file{i}.txt", i % 3));
+        assert_eq!(
+            std::fs::read_to_string(path).expect("pulled file"),
+            format!("file {i}")
+        );
+    }
+
+    let before = sync_connections();
+    device
+        .push_dir(tmp_dir.path(), UnixPath::new("/data/local/tmp/tree"), 0o644)
+        .await
+        .expect("pushed");
+    assert_eq!(sync_connections() - before, 1);
+    for i in 0..12 {
+        assert_eq!(
+            server
+                .file(&format!("/data/local/tmp/tree/dir{}/file{i}.txt", i % 3))
+                .expect("pushed file"),
+            format!("file {i}").into_bytes()
+        );
+    }
+}
+
+#[tokio::test]
+async fn mock_device_pull_dir_skip_totals() {
+    let server = testing::MockServer::with_device("mock");
+    for i in 0..9 {
+        server.add_file(
+            &format!("/sdcard/tree/dir{}/file{i}.txt", i % 3),
+            format!("file {i}"),
+        );
+    }
+    server.add_dir("/sdcard/tree/empty");
+    let device = server.device("mock").await.expect("device");
+
+    let listed_dir = tempdir().expect("create temp dir");
+    let listed = device
+        .pull_dir_with_options(
+            UnixPath::new("/sdcard/tree"),
+            listed_dir.path(),
+            &TransferOptions::new(),
+        )
+        .await
+        .expect("pulled");
+
+    let updates = std::sync::Mutex::new(Vec::new());
+    let sink = |progress: DirectoryTransferProgress| updates.lock().unwrap().push(progress);
+    let streamed_dir = tempdir().expect("create temp dir");
+    let streamed = device
+        .pull_dir_internal(
+            UnixPath::new("/sdcard/tree"),
+            streamed_dir.path(),
+            Some(&sink),
+            &TransferOptions::new().skip_totals(true),
+        )
+        .await
+        .expect("pulled");
+
+    let paths = |report: &TransferReport| {
+        let mut paths: Vec<_> = report
+            .files
+            .iter()
+            .map(|file| (file.device_path.clone(), file.bytes))
+            .collect();
+        paths.sort();
+        paths
+    };
+    assert_eq!(paths(&streamed), paths(&listed));
+    assert!(streamed_dir.path().join("empty").is_dir());
+    for i in 0..9 {
+        let path = format!("dir{}/file{i}.txt", i % 3);
+        assert_eq!(
+            std::fs::read(streamed_dir.path().join(&path)).expect("streamed"),
+            std::fs::read(listed_dir.path().join(&path)).expect("listed"),
+        );
+    }
+
+    let updates = updates.into_inner().unwrap();
+    assert!(updates
+        .iter()
+        .all(|p| p.total_files == 0 && p.total_bytes == 0));
+    assert_eq!(updates.last().expect("progress").transferred_files, 9);
+}
+
+#[tokio::test]
+async fn mock_device_stat_many() {
+    let server = testing::MockServer::with_device("mock");
+    server.add_file("/sdcard/a.txt", "aaa");
+    server.add_dir("/sdcard/dir");
+    let device = server.device("mock").await.expect("device");
+
+    let before = server.requests().len();
+    let stats = device
+        .stat_many(&["/sdcard/a.txt", "/sdcard/missing", "/sdcard/dir"].map(UnixPath::new))
+        .await
+        .expect("stats");
+    let requests = server.requests()[before..].to_vec();
+    assert_eq!(requests.iter().filter(|r| *r == "sync:").count(), 1);
+
+    assert_eq!(stats.len(), 3);
+    let a = stats[0].as_ref().expect("a.txt");
+    assert_eq!((a.file_mode, a.size), (UnixFileStatus::RegularFile, 3));
+    assert!(matches!(
+        &stats[1],
+        Err(DeviceError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound
+    ));
+    assert_eq!(
+        stats[2].as_ref().expect("dir").file_mode,
+        UnixFileStatus::Directory
+    );
+}
+
+#[tokio::test]
+async fn mock_device_set_mtime_and_touch() {
+    let server = testing::MockServer::with_device("mock");
+    let device = server.device("mock").await.expect("device");
+    let time = SystemTime::UNIX_EPOCH + StdDuration::from_secs(1_700_000_000);
+
+    device
+        .set_mtime(UnixPath::new("/sdcard/a.txt"), time)
+        .await
+        .expect("set_mtime");
+    device
+        .touch(UnixPath::new("/sdcard/marker"))
+        .await
+        .expect("touch");
+    let requests = server.requests();
+    assert!(requests
+        .contains(&"shell:touch -c -m -d 2023-11-14T22:13:20Z \"/sdcard/a.txt\"".to_owned()));
+    assert!(requests.contains(&"shell:touch \"/sdcard/marker\"".to_owned()));
+
+    // Before Android 6.0 toolbox only takes `-t`; 2000-02-29 checks the
+    // leap day.
+    server.on_shell("getprop ro.build.version.sdk", "21\n");
+    let device = server.device("mock").await.expect("device");
+    let time = SystemTime::UNIX_EPOCH + StdDuration::from_secs(951_782_400);
+    device
+        .set_mtime(UnixPath::new("/sdcard/a.txt"), time)
+        .await
+        .expect("set_mtime");
+    assert!(server
+        .requests()
+        .contains(&"shell:TZ=UTC touch -m -t 20000229.000000 \"/sdcard/a.txt\"".to_owned()));
+
+    let mut run_as = device.clone();
+    run_as.run_as_package = Some("org.example".to_owned());
+    run_as
+        .set_mtime(UnixPath::new("/data/data/org.example/a.txt"), time)
+        .await
+        .expect("set_mtime via run-as");
+    assert!(server.requests().contains(
+        &"shell:TZ=UTC run-as org.example touch -m -t 20000229.000000 \"/data/data/org.example/a.txt\""
+            .to_owned()
+    ));
+
+    server.on_shell(
+        "touch \"/system/x\"",
+        "touch: '/system/x': Read-only file system\n",
+    );
+    match device.touch(UnixPath::new("/system/x")).await {
+        Err(DeviceError::Adb(message)) => assert!(message.contains("Read-only")),
+        other => panic!("Expected touch error, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn mock_device_rename() {
+    let server = testing::MockServer::with_device("mock");
+    let device = server.device("mock").await.expect("device");
+
+    device
+        .rename(
+            UnixPath::new("/sdcard/a.txt"),
+            UnixPath::new("/sdcard/b.txt"),
+        )
+        .await
+        .expect("rename");
+    assert!(server
+        .requests()
+        .contains(&"shell:mv \"/sdcard/a.txt\" \"/sdcard/b.txt\"".to_owned()));
+
+    server.on_shell("ls -d \"/sdcard/c.txt\"", "/sdcard/c.txt\n");
+    match device
+        .rename(
+            UnixPath::new("/sdcard/a.txt"),
+            UnixPath::new("/sdcard/c.txt"),
+        )
+        .await
+    {
+        Err(DeviceError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::AlreadyExists),
+        other => panic!("Expected exists error, got {other:?}"),
+    }
+    assert!(!server
+        .requests()
+        .iter()
+        .any(|r| r.ends_with("\"/sdcard/c.txt\"") && r.starts_with("shell:mv")));
+
+    server.on_shell(
+        "mv \"/system/a\" \"/system/b\"",
+        "mv: '/system/a': Permission denied\n",
+    );
+    match device
+        .rename(UnixPath::new("/system/a"), UnixPath::new("/system/b"))
+        .await
+    {
+        Err(DeviceError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied),
+        other => panic!("Expected permission error, got {other:?}"),
+    }
+
+    // Across filesystems the source is only removed once the copy matches.
+    server.on_shell(
+        "mv \"/sdcard/d\" \"/data/local/tmp/d\"",
+        "mv: can't rename '/sdcard/d': Cross-device link\n",
+    );
+    server.on_shell(
+        "find \"/sdcard/d\" -type f -exec sha256sum {} +",
+        "aa  /sdcard/d/x\nbb  /sdcard/d/y/z\n",
+    );
+    server.on_shell(
+        "find \"/data/local/tmp/d\" -type f -exec sha256sum {} +",
+        "aa  /data/local/tmp/d/x\nbb  /data/local/tmp/d/y/z\n",
+    );
+    device
+        .rename(
+            UnixPath::new("/sdcard/d"),
+            UnixPath::new("/data/local/tmp/d"),
+        )
+        .await
+        .expect("copy and delete");
+    let requests = server.requests();
+    assert!(requests.contains(&"shell:cp -aR \"/sdcard/d\" \"/data/local/tmp/d\"".to_owned()));
+    assert!(requests.contains(&"shell:rm -rf \"/sdcard/d\"".to_owned()));
+
+    server.on_shell(
+        "find \"/data/local/tmp/d\" -type f -exec sha256sum {} +",
+        "aa  /data/local/tmp/d/x\n",
+    );
+    let before = server.requests().len();
+    device
+        .rename(
+            UnixPath::new("/sdcard/d"),
+            UnixPath::new("/data/local/tmp/d"),
+        )
+        .await
+        .expect_err("incomplete copy");
+    let requests = server.requests()[before..].to_vec();
+    assert!(requests.contains(&"shell:rm -rf \"/data/local/tmp/d\"".to_owned()));
+    assert!(!requests.contains(&"shell:rm -rf \"/sdcard/d\"".to_owned()));
+
+    // The source is removed afterwards, so `remove`'s guard applies.
+    server.on_shell(
+        "mv \"/sdcard\" \"/data/local/tmp/sdcard\"",
+        "mv: can't rename '/sdcard': Cross-device link\n",
+    );
+    let before = server.requests().len();
+    match device
+        .rename(
+            UnixPath::new("/sdcard"),
+            UnixPath::new("/data/local/tmp/sdcard"),
+        )
+        .await
+    {
+        Err(DeviceError::RemoveBlocked(path, _)) => assert_eq!(path, "/sdcard"),
+        other => panic!("Expected remove blocked error, got {other:?}"),
+    }
+    assert!(!server.requests()[before..]
+        .iter()
+        .any(|r| r.starts_with("shell:cp ") || r.starts_with("shell:rm ")));
+
+    // Toolbox cannot copy and verify, so the source stays.
+    server.on_shell(
+        "find \"/sdcard/d\" -type f -exec sha256sum {} +",
+        "/system/bin/sh: find: not found\n",
+    );
+    let before = server.requests().len();
+    match device
+        .rename(
+            UnixPath::new("/sdcard/d"),
+            UnixPath::new("/data/local/tmp/d"),
+        )
+        .await
+    {
+        Err(DeviceError::MissingFeature(tool)) => assert_eq!(tool, "find"),
+        other => panic!("Expected missing feature error, got {other:?}"),
+    }
+    assert!(!server.requests()[before..]
+        .iter()
+        .any(|r| r.starts_with("shell:cp ") || r.starts_with("shell:rm ")));
+}
+
+#[tokio::test]
+async fn mock_device_copy() {
+    let server = testing::MockServer::with_device("mock");
+    server.add_file("/sdcard/a.txt", "aaa");
+    server.add_file("/sdcard/b.txt", "aaa");
+    let device = server.device("mock").await.expect("device");
+
+    // The mock has no `find`; answer it with the digest of the contents.
+    let digest = "9834876dcfb05cb167a5c24953eba58c4ac89b1adf57f28f2f9d09af107ee8f0";
+    server.on_shell(
+        "find \"/sdcard/a.txt\" -type f -exec sha256sum {} +",
+        format!("{digest}  /sdcard/a.txt\n"),
+    );
+    server.on_shell(
+        "find \"/sdcard/b.txt\" -type f -exec sha256sum {} +",
+        format!("{digest}  /sdcard/b.txt\n"),
+    );
+    device
+        .copy(
+            UnixPath::new("/sdcard/a.txt"),
+            UnixPath::new("/sdcard/b.txt"),
+            false,
+            true,
+        )
+        .await
+        .expect("copy");
+    assert!(server
+        .requests()
+        .contains(&"shell:cp -p \"/sdcard/a.txt\" \"/sdcard/b.txt\"".to_owned()));
+
+    server.on_shell("ls -d \"/sdcard/b.txt\"", "/sdcard/b.txt\n");
+    match device
+        .copy(
+            UnixPath::new("/sdcard/a.txt"),
+            UnixPath::new("/sdcard/b.txt"),
+            true,
+            false,
+        )
+        .await
+    {
+        Err(DeviceError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::AlreadyExists),
+        other => panic!("Expected exists error, got {other:?}"),
+    }
+
+    server.on_shell(
+        "cp -r \"/sdcard/d\" \"/sdcard/e\"",
+        "cp: '/sdcard/e': No space left on device\n",
+    );
+    let before = server.requests().len();
+    device
+        .copy(
+            UnixPath::new("/sdcard/d"),
+            UnixPath::new("/sdcard/e"),
+            true,
+            false,
+        )
+        .await
+        .expect_err("copy failed");
+    assert!(server.requests()[before..].contains(&"shell:rm -rf \"/sdcard/e\"".to_owned()));
+
+    // Toolbox has no `find`, so nothing is copied that cannot be verified.
+    server.on_shell(
+        "find \"/sdcard/f\" -type f -exec sha256sum {} +",
+        "/system/bin/sh: find: not found\n",
+    );
+    let before = server.requests().len();
+    match device
+        .copy(
+            UnixPath::new("/sdcard/f"),
+            UnixPath::new("/sdcard/g"),
+            true,
+            true,
+        )
+        .await
+    {
+        Err(DeviceError::MissingFeature(tool)) => assert_eq!(tool, "find"),
+        other => panic!("Expected missing feature error, got {other:?}"),
+    }
+    assert!(!server.requests()[before..]
+        .iter()
+        .any(|r| r.starts_with("shell:cp ")));
+}
+
+#[tokio::test]
+async fn mock_device_create_dir_with_mode_and_owner() {
+    let server = testing::MockServer::with_device("mock");
+    let mut device = server.device("mock").await.expect("device");
+    let missing = |dir: &str| format!("ls: {dir}: No such file or directory\n");
+
+    device.su = SuStrategy::Su0;
+    let su = |command: &str| SuStrategy::Su0.wrap(command).unwrap();
+    for dir in ["/data/local/tmp/a", "/data/local/tmp/a/b"] {
+        server.on_shell(&su(&format!("ls \"{dir}\"")), missing(dir));
+    }
+    device
+        .create_dir_with(
+            UnixPath::new("/data/local/tmp/a/b"),
+            &CreateDirOptions::new().mode(0o750).owner("system:system"),
+        )
+        .await
+        .expect("create_dir_with");
+    let requests = server.requests();
+    let dirs = "\"/data/local/tmp/a\" \"/data/local/tmp/a/b\"";
+    assert!(requests.contains(&format!("shell:{}", su(&format!("mkdir -p -m 750 {dirs}")))));
+    assert!(requests.contains(&format!(
+        "shell:{}",
+        su(&format!("chown system:system {dirs}"))
+    )));
+
+    // Without root `chown` is refused.
+    device.su = SuStrategy::None;
+    server.on_shell("ls \"/sdcard/x\"", missing("/sdcard/x"));
+    server.on_shell(
+        "chown system \"/sdcard/x\"",
+        "chown: /sdcard/x: Operation not permitted\n",
+    );
+    match device
+        .create_dir_with(
+            UnixPath::new("/sdcard/x"),
+            &CreateDirOptions::new().owner("system"),
+        )
+        .await
+    {
+        Err(DeviceError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied),
+        other => panic!("Expected permission error, got {other:?}"),
+    }
+    device
+        .create_dir_with(
+            UnixPath::new("/sdcard/x"),
+            &CreateDirOptions::new().owner("root; reboot"),
+        )
+        .await
+        .expect_err("invalid owner");
+
+    // Pushes on Android 9 create the destination directory with a mode
+    // instead of opening it up with `chmod -R 777` afterwards.
+    server.on_shell("getprop ro.build.version.release", "9\n");
+    server.on_shell("ls \"/data/local/tmp/p\"", missing("/data/local/tmp/p"));
+    device
+        .push(&mut &b"x"[..], UnixPath::new("/data/local/tmp/p/f"), 0o644)
+        .await
+        .expect("push");
+    let requests = server.requests();
+    assert!(requests.contains(&"shell:mkdir -p -m 755 \"/data/local/tmp/p\"".to_owned()));
+    assert!(!requests.iter().any(|r| r.contains("chmod")));
+
+    // With `su`, the directories are still created as the shell user the
+    // push runs as, not as root.
+    let mut rooted = server.device("mock").await.expect("device");
+    rooted.su = SuStrategy::Su0;
+    server.on_shell(
+        "su 0 sh -c 'ls \"/data/local/tmp/q\"'",
+        missing("/data/local/tmp/q"),
+    );
+    server.on_shell("ls \"/data/local/tmp/q\"", missing("/data/local/tmp/q"));
+    rooted
+        .push(&mut &b"x"[..], UnixPath::new("/data/local/tmp/q/f"), 0o644)
+        .await
+        .expect("push");
+    let requests = server.requests();
+    assert!(requests.contains(&"shell:mkdir -p -m 755 \"/data/local/tmp/q\"".to_owned()));
+    assert!(!requests
+        .iter()
+        .any(|r| r.starts_with("shell:su") && r.contains("mkdir -p -m 755 \"/data/local/tmp/q")));
+}
+
+#[tokio::test]
+async fn mock_device_stat_extended() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "ls -ladZ \"/data/local/tmp/run\"",
+        "drwxrwx--x 3 shell shell u:object_r:shell_data_file:s0 3488 2023-11-14 22:13 /data/local/tmp/run\n",
+    );
+    server.on_shell(
+        "ls -ladZ \"/sdcard/missing\"",
+        "ls: /sdcard/missing: No such file or directory\n",
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let metadata = device
+        .stat_extended(UnixPath::new("/data/local/tmp/run"))
+        .await
+        .expect("stat_extended");
+    assert_eq!(metadata.file_mode, Some(UnixFileStatus::Directory));
+    assert_eq!(metadata.permissions, "drwxrwx--x");
+    assert_eq!(metadata.owner, "shell");
+    assert_eq!(
+        metadata.selinux_context.as_deref(),
+        Some("u:object_r:shell_data_file:s0")
+    );
+    assert_eq!(metadata.size, Some(3488));
+
+    match device.stat_extended(UnixPath::new("/sdcard/missing")).await {
+        Err(DeviceError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::NotFound),
+        other => panic!("Expected not found error, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn mock_device_temp_paths() {
+    let server = testing::MockServer::with_device("mock");
+    let mut device = server.device("mock").await.expect("device");
+    let removed = |path: &UnixPath, command: &str| {
+        let request = format!("shell:{command} \"{}\"", path.display());
+        let server = server.clone();
+        async move {
+            for _ in 0..100 {
+                if server.requests().contains(&request) {
+                    return true;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            false
+        }
+    };
+
+    let file = device.create_temp_file("case-").await.expect("temp file");
+    let path = file.path().to_owned();
+    assert!(path.starts_with("/data/local/tmp"));
+    assert!(path
+        .file_name()
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("case-"));
+    assert!(server
+        .requests()
+        .contains(&format!("shell:touch \"{}\"", path.display())));
+    drop(file);
+    assert!(removed(&path, "rm -f").await);
+
+    let dir = device.create_temp_dir().await.expect("temp dir");
+    assert!(server
+        .requests()
+        .contains(&format!("shell:mkdir -m 700 \"{}\"", dir.path().display())));
+    let path = dir.path().to_owned();
+    dir.close().await.expect("close");
+    assert!(removed(&path, "rm -rf").await);
+
+    let kept = device.create_temp_dir().await.expect("temp dir").keep();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!server
+        .requests()
+        .iter()
+        .any(|r| r.contains(&kept.display().to_string()) && r.starts_with("shell:rm")));
+
+    device
+        .create_temp_file("a/b")
+        .await
+        .expect_err("prefix with a slash");
+
+    // `run-as` pushes go through a temporary file of their own, which is
+    // removed afterwards.
+    device.run_as_package = Some("org.example".to_owned());
+    device
+        .push(
+            &mut &b"x"[..],
+            UnixPath::new("/data/data/org.example/files/x"),
+            0o644,
+        )
+        .await
+        .expect("push");
+    let requests = server.requests();
+    let copy = requests
+        .iter()
+        .find(|r| r.contains("cp -aR \"/data/local/tmp/push."))
+        .expect("copy from a temporary file");
+    let temp = copy.split('"').nth(1).expect("quoted temporary file");
+    assert!(requests.contains(&format!("shell:rm -f \"{temp}\"")));
+}
+
+#[tokio::test]
+async fn mock_device_workspace() {
+    let server = testing::MockServer::with_device("mock");
+    let device = server.device("mock").await.expect("device");
+
+    let workspace = device.workspace("case1").await.expect("workspace");
+    let root = workspace.root().to_owned();
+    let name = root.file_name().unwrap().to_str().unwrap().to_owned();
+    assert!(root.starts_with("/data/local/tmp"));
+    assert!(name.starts_with("forensic-adb-workspace.case1."));
+    assert!(server
+        .requests()
+        .contains(&format!("shell:mkdir -m 700 \"{}\"", root.display())));
+
+    let report = workspace.path("out/report.txt").expect("path");
+    assert_eq!(report, root.join("out/report.txt"));
+    for relative in ["../escape", "/data/x", ""] {
+        workspace.path(relative).expect_err("outside the workspace");
+    }
+    let pushed = workspace
+        .push(&mut &b"tool"[..], "bin/tool", 0o755)
+        .await
+        .expect("push");
+    assert_eq!(
+        server.file(&pushed.display().to_string()),
+        Some(b"tool".to_vec())
+    );
+    assert_eq!(workspace.written(), vec![pushed, report]);
+
+    server.on_shell(
+        "ls \"/data/local/tmp\"",
+        format!("forensic-adb-workspace.old.1234\nother\n{name}\n"),
+    );
+    assert_eq!(
+        device.abandoned_workspaces().await.expect("abandoned"),
+        vec![UnixPathBuf::from(
+            "/data/local/tmp/forensic-adb-workspace.old.1234"
+        )]
+    );
+
+    workspace.cleanup().await.expect("cleanup");
+    assert!(server
+        .requests()
+        .contains(&format!("shell:rm -rf \"{}\"", root.display())));
+    assert_eq!(
+        device
+            .abandoned_workspaces()
+            .await
+            .expect("abandoned")
+            .len(),
+        2
+    );
+
+    device
+        .workspace("no/slash")
+        .await
+        .expect_err("invalid name");
+}
+
+#[tokio::test]
+async fn mock_device_push_unknown_length_with_progress() {
+    let server = testing::MockServer::with_device("mock");
+    let device = server.device("mock").await.expect("device");
+
+    // A generated stream whose length is only known once it ends.
+    let (mut writer, mut reader) = tokio::io::duplex(16 * 1024);
+    let content: Vec<u8> = (0..300 * 1024).map(|i| (i % 251) as u8).collect();
+    let data = content.clone();
+    let feeder = tokio::spawn(async move {
+        for chunk in data.chunks(10_000) {
+            writer.write_all(chunk).await.expect("write");
+        }
+    });
+
+    let updates = std::sync::Mutex::new(Vec::new());
+    device
+        .push_with_progress(
+            &mut reader,
+            UnixPath::new("/sdcard/capture.bin"),
+            0o644,
+            None,
+            |progress: FileTransferProgress| updates.lock().unwrap().push(progress),
+        )
+        .await
+        .expect("push");
+    feeder.await.expect("feeder");
+    assert_eq!(server.file("/sdcard/capture.bin"), Some(content.clone()));
+
+    let updates = updates.into_inner().unwrap();
+    assert!(updates.len() >= 2, "got {updates:?}");
+    assert!(updates.iter().all(|p| p.total_bytes.is_none()));
+    assert_eq!(updates.first().unwrap().transferred_bytes, 0);
+    assert_eq!(
+        updates.last().unwrap().transferred_bytes,
+        content.len() as u64
+    );
+}
+
+#[tokio::test]
+async fn mock_device_pull_to_vec_and_string() {
+    let server = testing::MockServer::with_device("mock");
+    server.add_file("/data/local/tmp/config.json", "{\"debug\": true}\n");
+    server.add_file("/data/local/tmp/blob", vec![0xff; 64]);
+    let mut device = server.device("mock").await.expect("device");
+    device.pull_to_vec_limit = 64;
+
+    assert_eq!(
+        device
+            .pull_to_string(UnixPath::new("/data/local/tmp/config.json"))
+            .await
+            .expect("pull_to_string"),
+        "{\"debug\": true}\n"
+    );
+    assert_eq!(
+        device
+            .pull_to_vec(UnixPath::new("/data/local/tmp/blob"))
+            .await
+            .expect("pull_to_vec"),
+        vec![0xff; 64]
+    );
+
+    let before = server.requests().len();
+    device.pull_to_vec_limit = 63;
+    match device
+        .pull_to_vec(UnixPath::new("/data/local/tmp/blob"))
+        .await
+    {
+        Err(DeviceError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::FileTooLarge),
+        other => panic!("Expected too large error, got {other:?}"),
+    }
+    assert!(!server.requests()[before..]
+        .iter()
+        .any(|r| r.starts_with("sync:RECV")));
+
+    device.pull_to_vec_limit = 64;
+    assert!(matches!(
+        device
+            .pull_to_string(UnixPath::new("/data/local/tmp/blob"))
+            .await,
+        Err(DeviceError::Utf8(_))
+    ));
+}
+
+#[tokio::test]
+async fn mock_device_measure_clock_skew() {
+    let server = testing::MockServer::with_device("mock");
+    let device_time = SystemTime::now() - std::time::Duration::from_secs(3600);
+    let since = device_time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("since epoch");
+    server.on_shell(
+        "date +%s.%N",
+        format!("{}.{:09}\n", since.as_secs(), since.subsec_nanos()),
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let skew = device.measure_clock_skew(3).await.expect("skew");
+    assert_eq!(skew.samples, 3);
+    // The canned time stays put while the host clock moves on.
+    assert!(
+        (-3_610_000_000_000..=-3_600_000_000_000).contains(&skew.offset_nanos),
+        "{skew:?}"
+    );
+    let host_time = skew.to_host_time(device_time);
+    assert!(host_time <= SystemTime::now());
+    assert!(host_time + std::time::Duration::from_secs(10) > SystemTime::now());
+    assert_eq!(
+        server
+            .requests()
+            .iter()
+            .filter(|r| *r == "exec:date +%s.%N")
+            .count(),
+        3
+    );
+
+    assert!(device.measure_clock_skew(0).await.is_err());
+    server.on_shell("date +%s.%N", "date: unknown option\n");
+    assert!(matches!(
+        device.measure_clock_skew(1).await,
+        Err(DeviceError::Adb(_))
+    ));
+}
+
+#[tokio::test]
+async fn mock_device_locale_info() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("settings get system system_locales", "de-DE,en-US\n");
+    server.on_shell("getprop persist.sys.locale", "de-DE\n");
+    server.on_shell("getprop ro.product.locale", "en-US\n");
+    server.on_shell(
+        "ime list -a -s",
+        "com.google.android.inputmethod.latin/com.android.inputmethod.latin.LatinIME\ncom.google.android.googlequicksearchbox/com.google.android.voicesearch.ime.VoiceInputMethodService\n",
+    );
+    server.on_shell(
+        "settings get secure enabled_input_methods",
+        "com.google.android.inputmethod.latin/com.android.inputmethod.latin.LatinIME;-921088104:com.example.kbd/.Ime\n",
+    );
+    server.on_shell(
+        "settings get secure default_input_method",
+        "com.google.android.inputmethod.latin/com.android.inputmethod.latin.LatinIME\n",
+    );
+    server.on_shell("settings get system time_12_24", "24\n");
+    let device = server.device("mock").await.expect("device");
+
+    let info = device.locale_info().await.expect("locale info");
+    assert_eq!(info.locales, ["de-DE", "en-US"]);
+    assert_eq!(info.default_locale.as_deref(), Some("en-US"));
+    assert_eq!(info.input_methods.len(), 2);
+    assert_eq!(
+        info.enabled_input_methods,
+        [
+            "com.google.android.inputmethod.latin/com.android.inputmethod.latin.LatinIME",
+            "com.example.kbd/.Ime"
+        ]
+    );
+    assert_eq!(
+        info.default_input_method.as_deref(),
+        Some("com.google.android.inputmethod.latin/com.android.inputmethod.latin.LatinIME")
+    );
+    assert_eq!(info.clock_format, Some(ClockFormat::Hour24));
+    assert_eq!(
+        server
+            .requests()
+            .iter()
+            .filter(|r| r.starts_with("shell"))
+            .count(),
+        1
+    );
+
+    // Android 5: no `system_locales` or `persist.sys.locale`, and the clock
+    // format follows the locale.
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("settings get system system_locales", "null\n");
+    server.on_shell("getprop persist.sys.language", "fr\n");
+    server.on_shell("getprop persist.sys.country", "CA\n");
+    server.on_shell("settings get system time_12_24", "null\n");
+    server.on_shell_result("ime list -a -s", "", "ime: not found\n", 127);
+    let device = server.device("mock").await.expect("device");
+
+    let info = device.locale_info().await.expect("locale info");
+    assert_eq!(info.locales, ["fr-CA"]);
+    assert_eq!(info.default_locale, None);
+    assert!(info.input_methods.is_empty());
+    assert_eq!(info.clock_format, None);
+}
+
+#[tokio::test]
+async fn mock_device_screencap() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("screencap -p", b"\x89PNG\r\n\x1a\n...");
+    let mut raw = Vec::new();
+    for field in [2u32, 1, 1, 0] {
+        raw.extend_from_slice(&field.to_le_bytes());
+    }
+    raw.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+    server.on_shell("screencap", &raw);
+    let device = server.device("mock").await.expect("device");
+
+    assert!(device
+        .screencap()
+        .await
+        .expect("screencap")
+        .starts_with(b"\x89PNG"));
+    #[cfg(feature = "image")]
+    {
+        let shot = device.screencap_decoded().await.expect("decoded");
+        assert_eq!((shot.width, shot.height), (2, 1));
+        assert_eq!(shot.format, ScreenshotFormat::Rgba8888);
+        assert_eq!(shot.pixels, [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    server.on_shell("screencap -p", "/system/bin/sh: screencap: inaccessible\n");
+    assert!(matches!(device.screencap().await, Err(DeviceError::Adb(_))));
+}
+
+#[tokio::test]
+async fn mock_device_screen_stream() {
+    use futures::StreamExt;
+
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("screencap -p", b"\x89PNG\r\n\x1a\nframe");
+    server.on_shell("screencap", b"raw frame");
+    let device = server.device("mock").await.expect("device");
+
+    let frames: Vec<_> = device
+        .screen_stream(50, ScreenQuality::Png)
+        .take(3)
+        .collect()
+        .await;
+    assert_eq!(frames.len(), 3);
+    for (i, frame) in frames.into_iter().enumerate() {
+        let frame = frame.expect("frame");
+        assert_eq!(frame.index, i as u64);
+        assert_eq!(frame.data, b"\x89PNG\r\n\x1a\nframe");
+    }
+
+    let mut stream = std::pin::pin!(device.screen_stream(50, ScreenQuality::Raw));
+    let frame = stream.next().await.expect("frame").expect("raw frame");
+    assert_eq!(frame.data, b"raw frame");
+
+    let mut stream = std::pin::pin!(device.screen_stream(0, ScreenQuality::Png));
+    assert!(matches!(
+        stream.next().await,
+        Some(Err(DeviceError::Adb(_)))
+    ));
+    assert!(stream.next().await.is_none());
+}
+
+#[tokio::test]
+async fn mock_device_ui_hierarchy() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "uiautomator dump /dev/tty",
+        concat!(
+            "<?xml version='1.0' encoding='UTF-8' standalone='yes' ?>",
+            "<hierarchy rotation=\"0\"><node index=\"0\" text=\"OK\" ",
+            "resource-id=\"android:id/button1\" class=\"android.widget.Button\" ",
+            "package=\"android\" clickable=\"true\" bounds=\"[10,20][110,80]\" />",
+            "</hierarchy>UI hierchary dumped to: /dev/tty\n"
+        ),
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let hierarchy = device.ui_hierarchy().await.expect("hierarchy");
+    let button = hierarchy
+        .find(&UiSelector::new().resource_id("button1"))
+        .expect("button");
+    assert_eq!(button.text, "OK");
+    assert_eq!(button.bounds.center(), (60, 50));
+    assert!(server
+        .requests()
+        .contains(&"exec:uiautomator dump /dev/tty".to_owned()));
+
+    server.on_shell(
+        "uiautomator dump /dev/tty",
+        "ERROR: could not get idle state.\n",
+    );
+    match device.ui_hierarchy().await {
+        Err(DeviceError::Adb(message)) => assert!(message.contains("idle state"), "{message}"),
+        other => panic!("Expected dump error, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn mock_device_launch_activity() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "am start -W -n com.android.settings/.Settings",
+        "Starting: Intent { cmp=com.android.settings/.Settings }\nStatus: ok\nActivity: com.android.settings/.Settings\nTotalTime: 300\nWaitTime: 320\nComplete\n",
+    );
+    server.on_shell(
+        "am start -W -n com.example/.Missing",
+        "Starting: Intent { cmp=com.example/.Missing }\nError type 3\nError: Activity class {com.example/com.example.Missing} does not exist.\n",
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let result = device
+        .launch_activity::<&str>("com.android.settings", ".Settings", &[])
+        .await
+        .expect("launched");
+    assert_eq!(result.status, LaunchStatus::Ok);
+    assert_eq!(
+        result.total_time,
+        Some(std::time::Duration::from_millis(300))
+    );
+    assert!(device
+        .launch::<&str>("com.android.settings", ".Settings", &[])
+        .await
+        .expect("launch"));
+
+    match device
+        .launch_activity::<&str>("com.example", ".Missing", &[])
+        .await
+    {
+        Err(DeviceError::LaunchFailed(component, LaunchFailure::ClassNotFound)) => {
+            assert_eq!(component, "com.example/.Missing")
+        }
+        other => panic!("Expected class not found, got {other:?}"),
+    }
+    assert!(!device
+        .launch::<&str>("com.example", ".Missing", &[])
+        .await
+        .expect("launch"));
+}
+
+#[tokio::test]
+async fn mock_device_current_activity() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "dumpsys activity activities",
+        "  * Task{c5e2f7 #12 type=standard A=1000:com.android.settings U=0 visible=true sz=1}\n    * Hist  #0: ActivityRecord{9b0e8c2 u0 com.android.settings/.Settings t12}\n  ResumedActivity: ActivityRecord{9b0e8c2 u0 com.android.settings/.Settings t12}\n",
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let current = device
+        .current_activity()
+        .await
+        .expect("current")
+        .expect("resumed activity");
+    assert_eq!(current.component(), "com.android.settings/.Settings");
+    let stack = device.activity_stack().await.expect("stack");
+    assert_eq!(stack.len(), 1);
+    assert_eq!(stack[0].activities, [current]);
+    assert!(!server
+        .requests()
+        .contains(&"shell:dumpsys window".to_owned()));
+
+    // Nothing resumed, e.g. on the lock screen: ask the window manager.
+    server.on_shell("dumpsys activity activities", "");
+    server.on_shell(
+        "dumpsys window",
+        "  mCurrentFocus=Window{5c4c0a3 u0 com.android.chrome/org.chromium.chrome.browser.ChromeTabbedActivity}\n",
+    );
+    let current = device.current_activity().await.expect("current");
+    assert_eq!(
+        current.map(|activity| activity.package),
+        Some("com.android.chrome".to_owned())
+    );
+
+    server.on_shell("dumpsys window", "  mCurrentFocus=null\n");
+    assert_eq!(device.current_activity().await.expect("current"), None);
+}
+
+#[tokio::test]
+async fn mock_device_open_uri() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "am start -W -a android.intent.action.VIEW -d 'https://example.com/p?id=1&ref=it'\\''s' -p com.example",
+        "Starting: Intent { act=android.intent.action.VIEW dat=https://example.com/... pkg=com.example }\nStatus: ok\nActivity: com.example/.LinkActivity\nTotalTime: 150\nWaitTime: 160\nComplete\n",
+    );
+    server.on_shell(
+        "am start -W -a android.intent.action.VIEW -d 'https://example.com/'",
+        "Starting: Intent { act=android.intent.action.VIEW dat=https://example.com/... }\nStatus: ok\nActivity: android/com.android.internal.app.ResolverActivity\nComplete\n",
+    );
+    server.on_shell(
+        "am start -W -a android.intent.action.VIEW -d 'unknown:x'",
+        "Starting: Intent { act=android.intent.action.VIEW dat=unknown:x }\nError: Activity not started, unable to resolve Intent { act=android.intent.action.VIEW dat=unknown:x flg=0x10000000 }\n",
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let result = device
+        .open_uri("https://example.com/p?id=1&ref=it's", Some("com.example"))
+        .await
+        .expect("opened");
+    assert_eq!(
+        result.activity.as_deref(),
+        Some("com.example/.LinkActivity")
+    );
+    assert!(!result.is_disambiguation());
+
+    let result = device
+        .open_uri("https://example.com/", None)
+        .await
+        .expect("chooser");
+    assert!(result.is_disambiguation());
+
+    assert!(matches!(
+        device.open_uri("unknown:x", None).await,
+        Err(DeviceError::LaunchFailed(
+            _,
+            LaunchFailure::IntentNotResolved
+        ))
+    ));
+    assert!(device
+        .open_uri("https://example.com/", Some("com.example; reboot"))
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn mock_device_kill_process() {
+    // Answers the existence check for `pid` until `trigger` was requested.
+    fn exit_after(server: &testing::MockServer, pid: u32, trigger: &str) {
+        let check = format!("[ -d /proc/{pid} ] && echo alive");
+        server.on_shell(&check, "alive\n");
+        let server = server.clone();
+        let trigger = format!("shell:{trigger}");
+        tokio::spawn(async move {
+            while !server.requests().contains(&trigger) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            server.on_shell(&check, "");
+        });
+    }
+
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("getprop ro.build.version.sdk", "30\n");
+    server.on_shell(
+        "ps -A",
+        "USER           PID  PPID     VSZ    RSS WCHAN            ADDR S NAME\n\
+         u0_a88        4242   620 14567360 98100 0                  0 S com.example.app\n",
+    );
+    server.on_shell(
+        "kill -9 200",
+        "/system/bin/sh: kill: 200: Operation not permitted\n",
+    );
+    server.on_shell("cat /proc/200/cmdline", "com.example.app:remote\0");
+    let device = server.device("mock").await.expect("device");
+
+    assert!(!device.kill_process(99, Signal::Term).await.expect("absent"));
+    assert!(!server.requests().iter().any(|r| r.contains("kill -15 99")));
+
+    exit_after(&server, 123, "kill -15 123");
+    assert!(device
+        .kill_process(123, Signal::Term)
+        .await
+        .expect("killed"));
+
+    server.on_shell("[ -d /proc/124 ] && echo alive", "alive\n");
+    assert!(device
+        .kill_process(124, Signal::Stop)
+        .await
+        .expect("stopped"));
+
+    exit_after(&server, 200, "am kill com.example.app");
+    assert!(device
+        .kill_process(200, Signal::Kill)
+        .await
+        .expect("am kill"));
+    assert!(!server
+        .requests()
+        .iter()
+        .any(|r| r.contains("am force-stop")));
+
+    exit_after(&server, 4242, "kill -15 4242");
+    assert!(device
+        .kill_process_by_name("com.example.app")
+        .await
+        .expect("by name"));
+    assert!(!device
+        .kill_process_by_name("com.example.other")
+        .await
+        .expect("none"));
+}
+
+#[tokio::test]
+async fn mock_device_process_oom_info() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "cat /proc/5678/oom_score /proc/5678/oom_score_adj",
+        "1020\n975\n",
+    );
+    server.on_shell(
+        "dumpsys activity lru",
+        "ACTIVITY MANAGER LRU PROCESSES (dumpsys activity lru)\n\
+         \x20 #1: fg     TOP  LCMN 12345:com.android.chrome/u0a123 act:activities\n\
+         \x20 #0: cch+75 CEM  ---- 5678:com.example.app/u0a99\n",
+    );
+    server.on_shell_result(
+        "cat /proc/99/oom_score /proc/99/oom_score_adj",
+        "",
+        "cat: /proc/99/oom_score: No such file or directory\n",
+        1,
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let info = device
+        .process_oom_info(5678)
+        .await
+        .expect("oom info")
+        .expect("running");
+    assert_eq!((info.oom_score, info.oom_score_adj), (1020, 975));
+    assert_eq!(info.priority(), OomPriority::Cached);
+    let lru = info.lru.expect("lru");
+    assert_eq!((lru.position, lru.total), (0, 2));
+
+    assert_eq!(device.process_oom_info(99).await.expect("gone"), None);
+}
+
+#[tokio::test]
+async fn mock_device_process_fds_and_maps() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("cat /proc/4242/cmdline", "com.example.app\0");
+    server.on_shell(
+        "run-as com.example.app ls -l /proc/4242/fd",
+        "total 0\n\
+         lrwx------ 1 u0_a99 u0_a99 64 2024-05-01 10:00 41 -> /data/data/com.example.app/databases/app.db\n\
+         lrwx------ 1 u0_a99 u0_a99 64 2024-05-01 10:00 43 -> socket:[123456]\n",
+    );
+    server.on_shell(
+        "run-as com.example.app cat /proc/4242/maps",
+        "12c00000-32c00000 rw-p 00000000 00:00 0                                [anon:dalvik-main space]\n\
+         7b2c4e1000-7b2c4e5000 r-xp 00042000 fd:05 1234                       /data/app/com.example.app/lib/arm64/libnative.so\n",
+    );
+    server.on_shell("cat /proc/1/cmdline", "/system/bin/init\0second_stage\0");
+    server.on_shell("ls -l /proc/1/fd", "ls: /proc/1/fd: Permission denied\n");
+    let mut device = server.device("mock").await.expect("device");
+    device.run_as_package = Some("com.example.app".to_owned());
+
+    let fds = device.process_fds(4242).await.expect("fds");
+    assert_eq!(fds.len(), 2);
+    assert_eq!(fds[1].target, FdTarget::Socket(123456));
+
+    let maps = device.process_maps(4242).await.expect("maps");
+    assert_eq!(
+        maps[1].path.as_deref(),
+        Some("/data/app/com.example.app/lib/arm64/libnative.so")
+    );
+    assert!(maps[1].executable);
+
+    assert!(device.process_fds(1).await.is_err());
+}
+
+#[tokio::test]
+async fn mock_device_open_files() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "lsof",
+        "COMMAND     PID       USER   FD      TYPE             DEVICE  SIZE/OFF       NODE NAME\n\
+         app.example 4242     u0_a99   41u      REG              253,5     20480      81921 /data/data/com.example.app/databases/app.db\n\
+         app.example 4242     u0_a99   42w      REG              253,5       512      81925 /data/data/com.example.app/databases/app.db-journal\n\
+         logd         612       logd    3u      REG              253,5      8192       1001 /data/misc/logd/events\n",
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let files = device
+        .open_files(&OpenFileFilter::new().path_prefix("/data/data/com.example.app/"))
+        .await
+        .expect("lsof");
+    assert_eq!(files.len(), 2);
+    assert_eq!(files[1].fd, OpenFd::Number(42));
+
+    // Without lsof the shell prints an error instead of the header.
+    server.on_shell("lsof", "/system/bin/sh: lsof: not found\n");
+    server.on_shell(
+        "for p in /proc/[0-9]*; do echo \"$p $(cat $p/comm 2>/dev/null)\"; ls -l $p/fd 2>/dev/null; done",
+        "/proc/4242 app.example\n\
+         lrwx------ 1 u0_a99 u0_a99 64 2024-05-01 10:00 41 -> /data/data/com.example.app/databases/app.db\n",
+    );
+    let files = device
+        .open_files(&OpenFileFilter::new().process("app.example"))
+        .await
+        .expect("proc");
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].file_type, OpenFileType::Unknown);
+}
+
+#[tokio::test]
+async fn mock_device_network_interfaces() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "ip -o link",
+        "1: lo: <LOOPBACK,UP,LOWER_UP> mtu 65536 qdisc noqueue state UNKNOWN mode DEFAULT group default qlen 1000\\    link/loopback 00:00:00:00:00:00 brd 00:00:00:00:00:00\n\
+         3: wlan0: <BROADCAST,MULTICAST,UP,LOWER_UP> mtu 1500 qdisc mq state UP mode DORMANT group default qlen 3000\\    link/ether 02:00:00:44:55:66 brd ff:ff:ff:ff:ff:ff\n",
+    );
+    server.on_shell(
+        "ip -o addr",
+        "1: lo    inet 127.0.0.1/8 scope host lo\\       valid_lft forever preferred_lft forever\n\
+         3: wlan0    inet 192.168.1.23/24 brd 192.168.1.255 scope global wlan0\\       valid_lft forever preferred_lft forever\n",
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let interfaces = device.network_interfaces().await.expect("interfaces");
+    assert_eq!(
+        interfaces
+            .iter()
+            .map(|i| i.name.as_str())
+            .collect::<Vec<_>>(),
+        ["lo", "wlan0"]
+    );
+    assert_eq!(interfaces[1].mac.as_deref(), Some("02:00:00:44:55:66"));
+    assert_eq!(interfaces[1].state, InterfaceState::Up);
+    assert_eq!(
+        interfaces[1].ipv4().next().map(|a| a.address.to_string()),
+        Some("192.168.1.23".to_owned())
+    );
+}
+
+#[tokio::test]
+async fn mock_device_routes() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "ip route show table all",
+        "default via 192.168.1.1 dev wlan0 table wlan0 proto static \n\
+         0.0.0.0/1 dev tun0 table 1021 proto static scope link \n",
+    );
+    server.on_shell(
+        "ip -6 route show table all",
+        "fe80::/64 dev wlan0 table wlan0 proto kernel metric 256 pref medium\n",
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let routes = device.routes().await.expect("routes");
+    assert_eq!(routes.len(), 3);
+    assert!(routes[0].is_default());
+    assert_eq!(routes[1].interface.as_deref(), Some("tun0"));
+    assert!(routes[2].destination.is_ipv6());
+
+    server.on_shell_result(
+        "ip -6 route show table all",
+        "",
+        "/system/bin/sh: ip: not found\n",
+        127,
+    );
+    assert!(device.routes().await.is_err());
+}
+
+#[tokio::test]
+async fn mock_device_neighbors() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "ip neigh",
+        "192.168.1.1 dev wlan0 lladdr aa:bb:cc:dd:ee:ff REACHABLE\n\
+         fe80::1 dev wlan0 lladdr aa:bb:cc:dd:ee:ff router STALE\n",
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let neighbors = device.neighbors().await.expect("neighbors");
+    assert_eq!(neighbors.len(), 2);
+    assert_eq!(neighbors[0].mac.as_deref(), Some("aa:bb:cc:dd:ee:ff"));
+    assert_eq!(neighbors[1].state, NeighborState::Stale);
+}
+
+#[tokio::test]
+async fn mock_device_firewall_rules() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "iptables -t filter -S",
+        "-P OUTPUT ACCEPT\n-A OUTPUT -j fw_OUTPUT\n",
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let FirewallRules::Iptables(chains) = device.firewall_rules().await.expect("iptables") else {
+        panic!("expected iptables rules");
+    };
+    assert_eq!(chains[0].name, "OUTPUT");
+    assert_eq!(chains[0].rules.len(), 1);
+
+    for binary in ["iptables", "ip6tables"] {
+        for table in ["filter", "nat", "mangle", "raw"] {
+            server.on_shell_result(
+                &format!("{binary} -t {table} -S"),
+                "",
+                format!("{binary} v1.8.7 (legacy): can't initialize {binary} table `{table}': Permission denied (you must be root)\n"),
+                4,
+            );
+        }
+    }
+    server.on_shell(
+        "dumpsys netpolicy",
+        "Restrict background: true\nPolicy for UIDs:\n  UID=10123 policy=4 (REJECT_METERED_BACKGROUND)\n",
+    );
+    let FirewallRules::NetPolicy(restrictions) = device.firewall_rules().await.expect("netpolicy")
+    else {
+        panic!("expected netpolicy restrictions");
+    };
+    assert_eq!(restrictions.restrict_background, Some(true));
+    assert_eq!(restrictions.uid_policies[0].uid, 10123);
+}
+
+#[tokio::test]
+async fn mock_device_dns_config() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("settings get global private_dns_mode", "hostname\n");
+    server.on_shell(
+        "settings get global private_dns_specifier",
+        "dns.example.com\n",
+    );
+    server.on_shell(
+        "dumpsys connectivity",
+        "Current Networks:\n  NetworkAgentInfo{network{100}  lp{{InterfaceName: wlan0 DnsAddresses: [ /192.168.1.1 ] Domains: home.lan PrivateDnsServerName: dns.example.com}}  nc{[ Transports: WIFI ]}}\n",
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let config = device.dns_config().await.expect("dns");
+    assert_eq!(
+        config.private_dns_mode,
+        Some(PrivateDnsMode::Strict("dns.example.com".to_owned()))
+    );
+    assert_eq!(config.networks.len(), 1);
+    assert_eq!(config.networks[0].interface.as_deref(), Some("wlan0"));
+    assert_eq!(config.networks[0].search_domains, ["home.lan"]);
+
+    server.on_shell("settings get global private_dns_mode", "null\n");
+    assert_eq!(
+        device.dns_config().await.expect("dns").private_dns_mode,
+        None
+    );
+}
+
+#[tokio::test]
+async fn mock_device_vpn_status() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("getprop ro.build.version.sdk", "31\n");
+    server.on_shell("settings get secure always_on_vpn_app", "com.example.vpn\n");
+    server.on_shell("settings get secure always_on_vpn_lockdown", "1\n");
+    server.on_shell("settings get global http_proxy", "10.0.0.1:3128\n");
+    server.on_shell("settings get global global_http_proxy_host", "null\n");
+    server.on_shell(
+        "dumpsys connectivity",
+        "  NetworkAgentInfo{network{102}  lp{{InterfaceName: tun0}}  nc{[ Transports: VPN OwnerUid: 10150 Underlying: [100] ]}}\n",
+    );
+    server.on_shell(
+        "cmd package list packages --uid 10150",
+        "package:com.example.vpn\n",
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let status = device.vpn_status().await.expect("vpn");
+    assert_eq!(status.active.len(), 1);
+    assert_eq!(status.active[0].packages, ["com.example.vpn"]);
+    assert_eq!(status.active[0].underlying_networks, [100]);
+    assert_eq!(status.always_on_package.as_deref(), Some("com.example.vpn"));
+    assert!(status.lockdown);
+    let proxy = status.global_proxy.expect("proxy");
+    assert_eq!(
+        (proxy.host.as_deref(), proxy.port),
+        (Some("10.0.0.1"), Some(3128))
+    );
+
+    server.on_shell("settings get global http_proxy", ":0\n");
+    assert_eq!(device.vpn_status().await.expect("vpn").global_proxy, None);
+}
+
+#[tokio::test]
+async fn mock_device_tcpdump() {
+    // Magic, version 2.4, time zone, accuracy, snaplen and link type.
+    let mut pcap = 0xa1b2c3d4u32.to_le_bytes().to_vec();
+    pcap.extend_from_slice(&[2, 0, 4, 0]);
+    for field in [0u32, 0, 65535, 1] {
+        pcap.extend_from_slice(&field.to_le_bytes());
+    }
+    for payload in [&b"first"[..], &b"second packet"[..]] {
+        for field in [
+            1_700_000_000u32,
+            0,
+            payload.len() as u32,
+            payload.len() as u32,
+        ] {
+            pcap.extend_from_slice(&field.to_le_bytes());
+        }
+        pcap.extend_from_slice(payload);
+    }
+    let complete = pcap.len();
+    // A record cut off by the end of the capture.
+    pcap.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8, 200, 0, 0, 0, 200, 0, 0, 0, 9]);
+
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("tcpdump -U -w - -i 'wlan0' -c 2 2>/dev/null", &pcap);
+    let device = server.device("mock").await.expect("device");
+
+    let reports = std::sync::Mutex::new(Vec::new());
+    let mut output = Vec::new();
+    let stats = device
+        .tcpdump_with_progress(
+            &TcpdumpOptions::new().interface("wlan0").packet_count(2),
+            &mut output,
+            |stats: CaptureStats| reports.lock().unwrap().push(stats),
+        )
+        .await
+        .expect("capture");
+    assert_eq!(stats.packets, 2);
+    assert_eq!(stats.bytes, complete as u64);
+    assert_eq!(output, pcap[..complete]);
+    assert_eq!(reports.lock().unwrap().last(), Some(&stats));
+
+    // No tcpdump, or no root: nothing reaches stdout.
+    let mut output = Vec::new();
+    assert!(device
+        .tcpdump(&TcpdumpOptions::new(), &mut output)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn mock_device_wifi() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "cmd wifi status",
+        "Wifi is enabled\n\
+         Wifi is connected to \"HomeNet\"\n\
+         WifiInfo: SSID: \"HomeNet\", BSSID: aa:bb:cc:dd:ee:ff, Supplicant state: COMPLETED, RSSI: -61, Link speed: 144Mbps, Frequency: 2437MHz\n",
+    );
+    server.on_shell(
+        "cmd wifi list-scan-results",
+        "    BSSID              Frequency      RSSI           Age(sec)     SSID                                 Flags\n\
+         \x20 aa:bb:cc:dd:ee:ff       2437        -61            0.200       HomeNet                              [WPA2-PSK-CCMP][ESS]\n",
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let status = device.wifi_status().await.expect("status");
+    assert!(status.enabled && status.connected);
+    assert_eq!(status.ssid.as_deref(), Some("HomeNet"));
+    assert_eq!(status.rssi, Some(-61));
+    assert_eq!(status.link_speed_mbps, Some(144));
+
+    let networks = device.wifi_scan().await.expect("scan");
+    assert_eq!(networks.len(), 1);
+    assert_eq!(networks[0].frequency_mhz, 2437);
+    assert_eq!(networks[0].flags, ["WPA2-PSK-CCMP", "ESS"]);
+    assert!(server
+        .requests()
+        .iter()
+        .any(|request| request.contains("cmd wifi start-scan")));
+
+    // Before Android 11 there is no `cmd wifi`.
+    server.on_shell_result("cmd wifi status", "", "cmd: Can't find service: wifi\n", 20);
+    server.on_shell_result(
+        "cmd wifi start-scan",
+        "",
+        "cmd: Can't find service: wifi\n",
+        20,
+    );
+    server.on_shell(
+        "dumpsys wifi",
+        "Wi-Fi is enabled\n\
+         \x20 mWifiInfo SSID: \"Office\", BSSID: 11:22:33:44:55:66, Supplicant state: COMPLETED, RSSI: -70, Link speed: 65Mbps\n",
+    );
+    let status = device.wifi_status().await.expect("status");
+    assert_eq!(status.ssid.as_deref(), Some("Office"));
+    assert_eq!(status.bssid.as_deref(), Some("11:22:33:44:55:66"));
+    assert!(matches!(
+        device.wifi_scan().await,
+        Err(DeviceError::MissingFeature(_))
+    ));
+}
+
+#[tokio::test]
+async fn mock_device_cell_info() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "dumpsys telephony.registry",
+        "last known state:\n\
+         \x20 Phone Id=0\n\
+         \x20 mCellInfo=[CellInfoWcdma:{mRegistered=YES CellIdentityWcdma:{ mLac=41000 mCid=123456789 mPsc=300 mUarfcn=10787 mMcc=234 mMnc=15 mAlphaLong=Vodafone UK mAlphaShort=voda} CellSignalStrengthWcdma: ss=-79 ber=99 rscp=-92 ecno=-8 level=3}]\n\
+         \x20 Phone Id=1\n\
+         \x20 mCellInfo=[]\n",
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let cells = device.cell_info().await.expect("cells");
+    assert_eq!(cells.len(), 1);
+    assert_eq!(cells[0].network_type, CellNetworkType::Wcdma);
+    assert_eq!(
+        (cells[0].mcc.as_deref(), cells[0].mnc.as_deref()),
+        (Some("234"), Some("15"))
+    );
+    assert_eq!(
+        (cells[0].area_code, cells[0].cell_id),
+        (Some(41000), Some(123456789))
+    );
+    assert_eq!((cells[0].pci, cells[0].signal_dbm), (Some(300), Some(-92)));
+    assert_eq!(cells[0].operator.as_deref(), Some("Vodafone UK"));
+}
+
+#[tokio::test]
+async fn mock_device_cameras() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "dumpsys media.camera",
+        "== Camera service events log (most recent at top): ==\n\
+         \x20 10-16 09:15:42 : EVICT device 0 client held by package com.example.scanner (PID 2000), priority -1 (PID 3000)\n\
+         \x20 10-16 09:15:00 : CONNECT device 0 client for package com.example.scanner (PID 2000)\n\
+         == Camera device 0 static info: ==\n\
+         \x20 API1 Info:\n\
+         \x20   Has a flash unit: true\n\
+         \x20   Facing: Back\n\
+         \x20   Orientation: 90\n",
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let cameras = device.cameras().await.expect("cameras");
+    assert_eq!(cameras.len(), 1);
+    assert_eq!(cameras[0].id, "0");
+    assert_eq!(cameras[0].facing, Some(CameraFacing::Back));
+    assert_eq!(
+        cameras[0]
+            .usage
+            .iter()
+            .map(|event| (event.action, event.package.as_str(), event.pid))
+            .collect::<Vec<_>>(),
+        [
+            (CameraUsageAction::Evict, "com.example.scanner", Some(2000)),
+            (
+                CameraUsageAction::Connect,
+                "com.example.scanner",
+                Some(2000)
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn mock_device_biometric_status() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "dumpsys fingerprint",
+        "{\"service\":\"Fingerprint Manager\",\"prints\":[{\"id\":0,\"count\":2,\"accept\":4,\"reject\":1}]}\n",
+    );
+    server.on_shell("dumpsys face", "Can't find service: face\n");
+    let device = server.device("mock").await.expect("device");
+
+    let status = device.biometric_status().await.expect("status");
+    assert!(status.fingerprint_enrolled());
+    assert_eq!(
+        status.fingerprint.as_ref().map(|users| users[0].templates),
+        Some(2)
+    );
+    assert_eq!(status.face, None);
+    assert!(!status.face_enrolled());
+}
+
+#[tokio::test]
+async fn mock_device_wake_and_unlock_if_insecure() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "dumpsys window policy",
+        "    KeyguardServiceDelegate\n      showing=true\n      secure=false\n      deviceHasKeyguard=true\n      enabled=true\n",
+    );
+    server.on_shell("dumpsys deviceidle", "  mScreenOn=false\n");
+    let device = server.device("mock").await.expect("device");
+
+    let state = device.keyguard_state().await.expect("state");
+    assert_eq!(state.security, Some(KeyguardSecurity::Swipe));
+    assert_eq!((state.locked, state.screen_on), (Some(true), Some(false)));
+
+    let unlocked = server.clone();
+    tokio::spawn(async move {
+        let trigger = "shell:input keyevent KEYCODE_MENU".to_owned();
+        while !unlocked.requests().contains(&trigger) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        unlocked.on_shell(
+            "dumpsys window policy",
+            "    KeyguardServiceDelegate\n      showing=false\n      secure=false\n",
+        );
+    });
+    assert!(device.wake_and_unlock_if_insecure().await.expect("unlock"));
+    assert!(server
+        .requests()
+        .contains(&"shell:input keyevent KEYCODE_WAKEUP".to_owned()));
+
+    // A PIN is never attempted.
+    server.on_shell(
+        "dumpsys window policy",
+        "    KeyguardServiceDelegate\n      showing=true\n      secure=true\n",
+    );
+    let requests = server.requests().len();
+    assert!(!device.wake_and_unlock_if_insecure().await.expect("unlock"));
+    assert!(server.requests()[requests..]
+        .iter()
+        .all(|request| !request.contains("input keyevent")));
+}
+
+#[tokio::test]
+async fn mock_device_encryption_state() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("getprop ro.crypto.state", "encrypted\n");
+    server.on_shell("getprop ro.crypto.type", "file\n");
+    server.on_shell("getprop ro.crypto.metadata.enabled", "true\n");
+    server.on_shell(
+        "dumpsys user",
+        "Users:\n  UserInfo{0:Owner:c13} running\n    State: RUNNING_LOCKED\n",
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let state = device.encryption_state().await.expect("state");
+    assert_eq!(state.crypto_state.as_deref(), Some("encrypted"));
+    assert_eq!(state.encryption_type, Some(EncryptionType::FileBased));
+    assert!(state.metadata_encryption);
+    assert_eq!(state.users.len(), 1);
+    assert!(!state.users[0].ce_unlocked());
+
+    server.on_shell("getprop ro.crypto.type", "\n");
+    let state = device.encryption_state().await.expect("state");
+    assert_eq!(state.encryption_type, Some(EncryptionType::FullDisk));
+}
+
+#[tokio::test]
+async fn mock_device_verified_boot_state() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("getprop ro.boot.verifiedbootstate", "green\n");
+    server.on_shell("getprop ro.boot.flash.locked", "1\n");
+    server.on_shell("getprop ro.boot.veritymode", "enforcing\n");
+    server.on_shell(
+        "getprop ro.boot.vbmeta.digest",
+        "3f2c9a1bd0e2f0b65c0a3e9f2f9c4c7d1b8e5a6f7c8d9e0a1b2c3d4e5f6a7b8c\n",
+    );
+    server.on_shell("getprop ro.boot.vbmeta.hash_alg", "sha256\n");
+    server.on_shell("getprop ro.boot.vbmeta.avb_version", "1.1\n");
+    let device = server.device("mock").await.expect("device");
+
+    let boot = device.verified_boot_state().await.expect("boot");
+    assert_eq!(boot.state, Some(VerifiedBootState::Green));
+    assert_eq!(boot.verity_mode, Some(VerityMode::Enforcing));
+    assert_eq!(boot.vbmeta_hash_alg.as_deref(), Some("sha256"));
+    assert_eq!(boot.vbmeta_digest.as_ref().map(String::len), Some(64));
+    assert!(boot.is_trusted());
+
+    server.on_shell("getprop ro.boot.verifiedbootstate", "orange\n");
+    server.on_shell("getprop ro.boot.flash.locked", "\n");
+    server.on_shell("getprop ro.boot.vbmeta.device_state", "unlocked\n");
+    let boot = device.verified_boot_state().await.expect("boot");
+    assert_eq!(boot.state, Some(VerifiedBootState::Orange));
+    assert_eq!(boot.bootloader_locked, Some(false));
+    assert!(!boot.is_trusted());
+}
+
+#[tokio::test]
+async fn mock_device_bootloader_status() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("getprop ro.boot.flash.locked", "1\n");
+    server.on_shell("getprop ro.oem_unlock_supported", "1\n");
+    server.on_shell("getprop sys.oem_unlock_allowed", "0\n");
+    server.on_shell("getprop ro.warranty_bit", "0\n");
+    server.on_shell("getprop ro.bootloader", "G991BXXU5CVF3\n");
+    let device = server.device("mock").await.expect("device");
+
+    let status = device.bootloader_status().await.expect("status");
+    assert_eq!(
+        status,
+        BootloaderStatus {
+            locked: Some(true),
+            oem_unlock_supported: Some(true),
+            oem_unlock_allowed: Some(false),
+            warranty_void: Some(false),
+            version: Some("G991BXXU5CVF3".to_owned()),
+        }
+    );
+    assert!(!status.is_flashable() && !status.can_unlock());
+
+    server.on_shell("getprop sys.oem_unlock_allowed", "1\n");
+    let status = device.bootloader_status().await.expect("status");
+    assert!(!status.is_flashable() && status.can_unlock());
+}
+
+#[tokio::test]
+async fn mock_device_firmware_info() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("getprop ro.build.id", "TQ3A.230805.001\n");
+    server.on_shell("getprop ro.build.version.release", "13\n");
+    server.on_shell("getprop ro.build.date.utc", "1690000000\n");
+    server.on_shell("getprop ro.build.version.security_patch", "2023-08-05\n");
+    server.on_shell(
+        "getprop gsm.version.baseband",
+        "g5123b-116954-230511-B-10112789,g5123b-116954-230511-B-10112789\n",
+    );
+    server.on_shell(
+        "cat /proc/version",
+        "Linux version 5.10.157-android13-4-00001-g5c7ff5dc7aac (build-user@build-host) (Android (8508608) clang version 14.0.7) #1 SMP PREEMPT Thu Jun 1 00:00:00 UTC 2023\n",
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let firmware = device.firmware_info().await.expect("firmware");
+    assert_eq!(firmware.build_id.as_deref(), Some("TQ3A.230805.001"));
+    assert_eq!(firmware.android_version.as_deref(), Some("13"));
+    assert_eq!(firmware.build_time, Some(1_690_000_000));
+    assert_eq!(firmware.security_patch.as_deref(), Some("2023-08-05"));
+    assert_eq!(firmware.vendor_security_patch, None);
+    assert_eq!(firmware.baseband, ["g5123b-116954-230511-B-10112789"]);
+    assert_eq!(
+        firmware.kernel_release.as_deref(),
+        Some("5.10.157-android13-4-00001-g5c7ff5dc7aac")
+    );
+}
+
+#[tokio::test]
+async fn mock_device_device_policy() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "dumpsys device_policy",
+        "Current Device Policy Manager state:\n\
+         \x20 Profile Owner (User 10): \n\
+         \x20   admin=ComponentInfo{com.example.work/com.example.work.Admin}\n\
+         \x20   package=com.example.work\n\
+         \x20 Enabled Device Admins (User 10, provisioningState: 3):\n\
+         \x20   com.example.work/.Admin:\n\
+         \x20     uid=1010150\n\
+         \x20     userRestrictions:\n\
+         \x20       no_cross_profile_copy_paste\n",
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let policy = device.device_policy().await.expect("policy");
+    assert!(policy.is_managed());
+    assert_eq!(policy.device_owner, None);
+    assert_eq!(policy.profile_owners[0].user_id, 10);
+    assert_eq!(policy.admins[0].uid, Some(1010150));
+    assert_eq!(
+        policy.admins[0].user_restrictions,
+        ["no_cross_profile_copy_paste"]
+    );
+}
+
+#[tokio::test]
+async fn mock_device_managed_profiles() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "dumpsys user",
+        "Users:\n\
+         \x20 UserInfo{0:Owner:c13} running\n\
+         \x20   State: RUNNING_UNLOCKED\n\
+         \x20 UserInfo{11:Guest:814}\n\
+         \x20 UserInfo{10:Work profile:1030} running\n\
+         \x20   State: RUNNING_UNLOCKED\n",
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let profiles = device.managed_profiles().await.expect("profiles");
+    assert_eq!(profiles.len(), 1);
+    assert_eq!(profiles[0].user_id, 10);
+    assert_eq!(profiles[0].state, Some(UserRunState::RunningUnlocked));
+    assert_eq!(profiles[0].ce_data_root(), UnixPath::new("/data/user/10"));
+    assert_eq!(
+        profiles[0].de_data_root(),
+        UnixPath::new("/data/user_de/10")
+    );
+}
+
+#[tokio::test]
+async fn mock_device_app_ops() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "appops get com.example.app",
+        "Uid mode: CAMERA: foreground\n\
+         CAMERA: allow; time=+5m10s ago; duration=+30s\n\
+         RECORD_AUDIO: ignore; rejectTime=+3d ago\n",
+    );
+    server.on_shell(
+        "dumpsys appops --package com.example.app",
+        "Current AppOps Service state:\n\
+         \x20 Uid 10123:\n\
+         \x20   Package com.example.app:\n\
+         \x20     CAMERA (allow):\n\
+         \x20         Access: [top-s] 2023-10-16 11:55:01.123 (-5m10s) duration=+30s\n",
+    );
+    server.on_shell_result(
+        "appops get com.example.missing",
+        "",
+        "Error: No UID for com.example.missing in user 0\n",
+        255,
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let ops = device.app_ops("com.example.app").await.expect("app ops");
+    assert_eq!(ops.len(), 2);
+    assert_eq!(ops[0].name, "CAMERA");
+    assert_eq!(ops[0].mode, AppOpMode::Allow);
+    assert_eq!(ops[0].uid_mode, Some(AppOpMode::Foreground));
+    assert_eq!(ops[0].last_access, Some(Duration::from_secs(310)));
+    assert_eq!(
+        ops[0].last_access_time.as_deref(),
+        Some("2023-10-16 11:55:01.123")
+    );
+    assert_eq!(ops[1].last_reject, Some(Duration::from_secs(3 * 86_400)));
+
+    assert!(matches!(
+        device.app_ops("com.example.missing").await,
+        Err(DeviceError::Adb(message)) if message.starts_with("Error: No UID")
+    ));
+}
+
+#[tokio::test]
+async fn mock_device_permission_report() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "dumpsys package packages",
+        "Packages:\n\
+         \x20 Package [com.example.app] (1a2b3c):\n\
+         \x20   userId=10123\n\
+         \x20   User 0: installed=true\n\
+         \x20     runtime permissions:\n\
+         \x20       android.permission.RECORD_AUDIO: granted=true, flags=[ USER_SET ]\n\
+         \x20   User 10: installed=true\n\
+         \x20     runtime permissions:\n\
+         \x20       android.permission.RECORD_AUDIO: granted=false, flags=[ POLICY_FIXED ]\n",
+    );
+    let mut device = server.device("mock").await.expect("device");
+
+    let grants = device.permission_report().await.expect("report");
+    assert_eq!(grants.len(), 2);
+    assert_eq!(grants[0].uid, Some(10123));
+    assert!(grants[0].granted && grants[0].has_flag("USER_SET"));
+
+    device.user = Some(10);
+    let grants = device.permission_report().await.expect("report");
+    assert_eq!(grants.len(), 1);
+    assert_eq!(grants[0].user_id, 10);
+    assert!(!grants[0].granted && grants[0].has_flag("POLICY_FIXED"));
+}
+
+#[tokio::test]
+async fn mock_device_special_access_services() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "settings get secure enabled_accessibility_services",
+        "com.example.spy/.SpyService:com.google.android.marvin.talkback/.TalkBackService\n",
+    );
+    server.on_shell(
+        "settings get secure enabled_notification_listeners",
+        "com.example.spy/com.example.spy.Listener\n",
+    );
+    server.on_shell(
+        "dumpsys device_policy",
+        "  Enabled Device Admins (User 0, provisioningState: 0):\n\
+         \x20   com.example.spy/.Admin:\n\
+         \x20     uid=10123\n",
+    );
+    server.on_shell_result(
+        "appops query-op GET_USAGE_STATS allow",
+        "",
+        "Unknown command: query-op\n",
+        255,
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let services = device.special_access_services().await.expect("services");
+    assert_eq!(services.accessibility_services.len(), 2);
+    assert_eq!(
+        services.accessibility_services[1].package,
+        "com.google.android.marvin.talkback"
+    );
+    assert_eq!(
+        services.notification_listeners[0].component,
+        "com.example.spy/com.example.spy.Listener"
+    );
+    assert_eq!(
+        services.device_admins[0].component,
+        "com.example.spy/.Admin"
+    );
+    assert!(services.usage_access.is_empty());
+    assert_eq!(
+        services.packages(),
+        ["com.example.spy", "com.google.android.marvin.talkback"]
+    );
+
+    server.on_shell(
+        "appops query-op GET_USAGE_STATS allow",
+        "com.example.spy\ncom.android.settings\n",
+    );
+    server.on_shell(
+        "settings get secure enabled_accessibility_services",
+        "null\n",
+    );
+    let services = device.special_access_services().await.expect("services");
+    assert!(services.accessibility_services.is_empty());
+    assert_eq!(
+        services.usage_access,
+        ["com.example.spy", "com.android.settings"]
+    );
+}
+
+#[tokio::test]
+async fn mock_device_power_whitelist() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "dumpsys deviceidle whitelist",
+        "system,com.google.android.gms,10089\nuser,com.example.app,10123\n",
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let whitelist = device.power_whitelist().await.expect("whitelist");
+    assert_eq!(whitelist.system.len(), 1);
+    assert_eq!(whitelist.user[0].package, "com.example.app");
+    assert_eq!(whitelist.user[0].uid, Some(10123));
+
+    server.on_shell(
+        "dumpsys deviceidle whitelist",
+        "Can't find service: deviceidle\n",
+    );
+    assert!(matches!(
+        device.power_whitelist().await,
+        Err(DeviceError::MissingFeature(feature)) if feature == "deviceidle"
+    ));
+}
+
+#[tokio::test]
+async fn mock_device_storage_volumes() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell(
+        "sm list-volumes all",
+        "emulated;0 mounted null\npublic:179,1 mounted 1234-ABCD\n",
+    );
+    server.on_shell(
+        "dumpsys mount",
+        "Disks:\n\
+         \x20 DiskInfo{disk:179,0}:\n\
+         \x20   flags=ADOPTABLE|SD size=63864569856\n\
+         Volumes:\n\
+         \x20 VolumeInfo{public:179,1}:\n\
+         \x20   type=PUBLIC diskId=disk:179,0 mountFlags=VISIBLE\n\
+         \x20   fsType=vfat fsUuid=1234-ABCD fsLabel=null\n\
+         \x20   path=/storage/1234-ABCD internalPath=/mnt/media_rw/1234-ABCD\n",
+    );
+    let device = server.device("mock").await.expect("device");
+
+    let volumes = device.storage_volumes().await.expect("volumes");
+    assert_eq!(volumes.len(), 2);
+    assert_eq!(volumes[0].volume_type, VolumeType::Emulated);
+    assert_eq!(volumes[0].uuid, None);
+    let card = &volumes[1];
+    assert!(card.is_sd_card());
+    assert_eq!(card.state, VolumeState::Mounted);
+    assert_eq!(card.fs_type.as_deref(), Some("vfat"));
+    assert_eq!(card.label, None);
+    assert_eq!(
+        card.path.as_deref(),
+        Some(UnixPath::new("/storage/1234-ABCD"))
+    );
+
+    server.on_shell_result("sm list-volumes all", "", "sm: not found\n", 127);
+    server.on_shell("dumpsys mount", "");
+    assert!(matches!(
+        device.storage_volumes().await,
+        Err(DeviceError::MissingFeature(feature)) if feature == "sm"
+    ));
+}
+
+#[tokio::test]
+async fn mock_device_pull_with_progress_sink() {
+    let server = testing::MockServer::with_device("mock");
+    let content = vec![b'x'; 200 * 1024];
+    server.add_file("/sdcard/big.bin", content.clone());
+    let mut device = server.device("mock").await.expect("device");
+    device.progress_granularity = ProgressGranularity {
+        steps: 4,
+        min_interval: 0,
+        max_interval: u64::MAX,
+    };
+
+    let updates = std::sync::Mutex::new(Vec::new());
+    let mut buffer = Vec::new();
+    device
+        .pull_with_progress(
+            UnixPath::new("/sdcard/big.bin"),
+            &mut buffer,
+            |progress: FileTransferProgress| updates.lock().unwrap().push(progress),
+        )
+        .await
+        .expect("file has been pulled");
+    assert_eq!(buffer, content);
+
+    let updates = updates.into_inner().unwrap();
+    assert!(updates.len() >= 4, "got {updates:?}");
+    assert!(updates
+        .iter()
+        .all(|p| p.total_bytes == Some(content.len() as u64)));
+    assert_eq!(updates.first().unwrap().transferred_bytes, 0);
+    assert_eq!(
+        updates.last().unwrap().transferred_bytes,
+        content.len() as u64
+    );
+
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    device
+        .pull_dir_with_progress(
+            UnixPath::new("/sdcard"),
+            tempdir().expect("tempdir").path(),
+            sender,
+        )
+        .await
+        .expect("directory has been pulled");
+    let mut last = None;
+    while let Ok(progress) = receiver.try_recv() {
+        last = Some(progress);
+    }
+    let last: DirectoryTransferProgress = last.expect("progress updates");
+    assert_eq!(last.transferred_files, 1);
+    assert_eq!(last.transferred_bytes, content.len() as u64);
+}
+
+#[tokio::test]
+async fn mock_device_shell_stream() {
+    use futures::StreamExt;
+
+    let server = testing::MockServer::with_device("mock");
+    let output: Vec<u8> = (0..300000u32).map(|i| b'a' + (i % 26) as u8).collect();
+    server.on_shell("logcat -d", output.clone());
+    let device = server.device("mock").await.expect("device");
+
+    let stream = device.execute_host_shell_stream("logcat -d");
+    futures::pin_mut!(stream);
+    let mut received = Vec::new();
+    let mut chunks = 0;
+    while let Some(chunk) = stream.next().await {
+        received.extend_from_slice(&chunk.expect("chunk"));
+        chunks += 1;
+    }
+    assert_eq!(received, output);
+    assert!(chunks > 1);
+}
+
+#[tokio::test]
+async fn mock_device_interactive_shell() {
+    use crate::interactive::ShellEvent;
+    use futures::StreamExt;
+
+    let server = testing::MockServer::with_device("mock");
+    let device = server.device("mock").await.expect("device");
+
+    let mut shell = device.interactive_shell().await.expect("shell");
+    shell.stdin.resize(24, 80).await.expect("resize");
+    shell.stdin.write_all(b"id\n").await.expect("stdin");
+    shell.stdin.flush().await.expect("flush");
+    shell.stdin.close().await.expect("close");
+
+    let mut events = Vec::new();
+    while let Some(event) = shell.events.next().await {
+        events.push(event.expect("event"));
+    }
+    assert_eq!(
+        events,
+        vec![
+            ShellEvent::Stderr("24x80,0x0\0".into()),
+            ShellEvent::Stdout("id\n".into()),
+            ShellEvent::Exit(0),
+        ]
+    );
+    assert!(server
+        .requests()
+        .contains(&"shell,v2,TERM=xterm-256color,pty:".to_owned()));
+
+    let mut shell = device.interactive_shell().await.expect("shell");
+    shell.stdin.interrupt().await.expect("interrupt");
+    let mut last = None;
+    while let Some(event) = shell.events.next().await {
+        last = Some(event.expect("event"));
+    }
+    assert_eq!(last, Some(ShellEvent::Exit(130)));
+
+    let server = testing::MockServer::with_device("legacy");
+    server.set_host_features(&[]);
+    let device = server.device("legacy").await.expect("device");
+    assert!(matches!(
+        device.interactive_shell().await,
+        Err(DeviceError::MissingFeature(_))
+    ));
+}
+
+#[tokio::test]
+async fn mock_device_rejects_unsafe_paths() {
+    let server = testing::MockServer::with_device("mock");
+    let device = server.device("mock").await.expect("device");
+
+    device
+        .remove(UnixPath::new("/sdcard/My Files"))
+        .await
+        .expect("removed");
+    assert!(server
+        .requests()
+        .contains(&"shell:rm -rf \"/sdcard/My Files\"".to_owned()));
+
+    let before = server.requests().len();
+    for path in ["/sdcard/x\"; reboot; \"", "/sdcard/../data/data"] {
+        match device.remove(UnixPath::new(path)).await {
+            Err(DeviceError::InvalidPath(rejected, _)) => assert_eq!(rejected, path),
+            other => panic!("Expected invalid path error, got {other:?}"),
+        }
+    }
+    assert_eq!(server.requests().len(), before);
+}
+
+#[tokio::test]
+async fn mock_device_remove_variants() {
+    let server = testing::MockServer::with_device("mock");
+    let mut device = server.device("mock").await.expect("device");
+
+    device
+        .remove_file(UnixPath::new("/sdcard/a.txt"))
+        .await
+        .expect("remove_file");
+    server.on_shell("rm -f \"/sdcard/dir\"", "rm: /sdcard/dir: Is a directory\n");
+    match device.remove_file(UnixPath::new("/sdcard/dir")).await {
+        Err(DeviceError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::IsADirectory),
+        other => panic!("Expected directory error, got {other:?}"),
+    }
+    server.on_shell(
+        "rmdir \"/sdcard/dir\"",
+        "rmdir: '/sdcard/dir': Directory not empty\n",
+    );
+    match device.remove_dir(UnixPath::new("/sdcard/dir")).await {
+        Err(DeviceError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::DirectoryNotEmpty),
+        other => panic!("Expected not empty error, got {other:?}"),
+    }
+    device
+        .remove_dir_all(UnixPath::new("/sdcard/dir"))
+        .await
+        .expect("remove_dir_all");
+
+    let before = server.requests().len();
+    for path in ["/", "/sdcard", "/data/", "sdcard/dir"] {
+        for result in [
+            device.remove_dir_all(UnixPath::new(path)).await,
+            device.remove(UnixPath::new(path)).await,
+        ] {
+            match result {
+                Err(DeviceError::RemoveBlocked(rejected, _)) => assert_eq!(rejected, path),
+                other => panic!("Expected blocked removal of {path}, got {other:?}"),
+            }
+        }
+    }
+
+    device.remove_roots = vec![UnixPathBuf::from("/data/local/tmp/case")];
+    for path in [
+        "/data/local/tmp/case",
+        "/data/local/tmp/other",
+        "/sdcard/dir",
+    ] {
+        assert!(matches!(
+            device.remove_dir_all(UnixPath::new(path)).await,
+            Err(DeviceError::RemoveBlocked(..))
+        ));
+    }
+    assert_eq!(server.requests().len(), before);
+
+    device
+        .remove_dir_all(UnixPath::new("/data/local/tmp/case/run1"))
+        .await
+        .expect("below root");
+    assert!(server
+        .requests()
+        .contains(&"shell:rm -rf \"/data/local/tmp/case/run1\"".to_owned()));
+}
+
+#[tokio::test]
+async fn mock_device_run_exit_code() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell_result("ls /data", "", "ls: /data: Permission denied\n", 1);
+    server.on_shell("id -u", "2000\n");
+    let device = server.device("mock").await.expect("device");
+
+    let output = device.run("ls /data").await.expect("command has run");
+    assert!(!output.success());
+    assert_eq!(output.exit_code, 1);
+    assert!(output.stdout.is_empty());
+    assert_eq!(output.stderr_lossy(), "ls: /data: Permission denied\n");
+    assert!(server
+        .requests()
+        .contains(&"shell,v2,raw:ls /data".to_owned()));
+
+    // Devices without shell v2 go through the legacy shell service
+    server.set_host_features(&[]);
+    let device = server.device("mock").await.expect("device");
+    let output = device.run("ls /data").await.expect("command has run");
+    assert_eq!(output.exit_code, 1);
+    assert_eq!(output.stdout_lossy(), "ls: /data: Permission denied\n");
+    assert!(output.stderr.is_empty());
+
+    let output = device.run("id -u").await.expect("command has run");
+    assert!(output.success());
+    assert_eq!(output.stdout_lossy(), "2000\n");
+}
+
+#[tokio::test]
+async fn mock_device_run_batch() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("id -u", "2000\n");
+    server.on_shell_result("ls /data", "", "ls: /data: Permission denied\n", 1);
+    server.on_shell("getprop ro.product.model", "Pixel");
+
+    let commands = ["id -u", "ls /data", "getprop ro.product.model"];
+    let device = server.device("mock").await.expect("device");
+    let outputs = device.run_batch(&commands).await.expect("batch");
+    assert_eq!(outputs.len(), 3);
+    assert_eq!(outputs[0].stdout_lossy(), "2000\n");
+    assert!(outputs[0].success());
+    assert_eq!(outputs[1].stderr_lossy(), "ls: /data: Permission denied\n");
+    assert_eq!(outputs[1].exit_code, 1);
+    assert_eq!(outputs[2].stdout_lossy(), "Pixel");
+
+    let shells = server
+        .requests()
+        .iter()
+        .filter(|r| r.starts_with("shell"))
+        .count();
+    assert_eq!(shells, 1);
+
+    server.set_host_features(&[]);
+    let device = server.device("mock").await.expect("device");
+    let outputs = device.run_batch(&commands).await.expect("batch");
+    assert_eq!(outputs[1].stdout_lossy(), "ls: /data: Permission denied\n");
+    assert_eq!(outputs[1].exit_code, 1);
+    assert_eq!(outputs[2].stdout_lossy(), "Pixel");
+}
+
+#[tokio::test]
+async fn mock_device_features_are_cached() {
+    let server = testing::MockServer::with_device("mock");
+    server.set_host_features(&["shell_v2", "cmd", "stat_v2"]);
+    let device = server.device("mock").await.expect("device");
+
+    assert!(device.supports_shell_v2().await.expect("features"));
+    assert!(device.supports_stat_v2().await.expect("features"));
+    assert!(!device.supports_sendrecv_v2().await.expect("features"));
+
+    // Clones share the cache, so later changes are not observed
+    server.set_host_features(&[]);
+    let clone = device.clone();
+    assert!(clone.supports_shell_v2().await.expect("features"));
+    clone.run("true").await.expect("command has run");
+
+    let queries = server
+        .requests()
+        .iter()
+        .filter(|request| *request == "host-serial:mock:features")
+        .count();
+    assert_eq!(queries, 1);
+}
+
+#[tokio::test]
+async fn mock_device_builder() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell_result(
+        "run-as com.example.release true",
+        "",
+        "run-as: package not debuggable: com.example.release\n",
+        1,
+    );
+    server.on_shell(
+        "su 0 sh -c 'cmd package list packages --user 10'",
+        "package:b\npackage:a\n",
+    );
+
+    let device = Device::builder(server.host())
+        .serial("mock")
+        .run_as_package("com.example.debug")
+        .tempfile_dir("/sdcard/tmp")
+        .su(SuStrategy::Su0)
+        .user(10)
+        .build()
+        .await
+        .expect("device");
+    assert_eq!(device.run_as_package.as_deref(), Some("com.example.debug"));
+    assert_eq!(device.tempfile_dir, UnixPathBuf::from("/sdcard/tmp"));
+    assert_eq!(
+        device.list_packages(false).await.expect("packages"),
+        vec!["a".to_owned(), "b".to_owned()]
+    );
+
+    match device.clone().with_run_as("com.example.release").await {
+        Err(DeviceError::NotDebuggable(package, message)) => {
+            assert_eq!(package, "com.example.release");
+            assert_eq!(
+                message,
+                "run-as: package not debuggable: com.example.release"
+            );
+        }
+        other => panic!("Expected not debuggable error, got {other:?}"),
+    }
+
+    let requests = server.requests().len();
+    match device.with_run_as("com.example; reboot").await {
+        Err(DeviceError::Adb(message)) => assert!(message.contains("invalid package name")),
+        other => panic!("Expected invalid package error, got {other:?}"),
+    }
+    assert_eq!(server.requests().len(), requests);
+}
+
+#[tokio::test]
+async fn mock_device_auto_storage() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("getprop ro.build.version.sdk", "29\n");
+    server.on_shell("echo $EXTERNAL_STORAGE", "/storage/emulated/0\n");
+
+    let device = Device::builder(server.host())
+        .storage(AndroidStorageInput::Auto)
+        .build()
+        .await
+        .expect("device");
+    assert_eq!(device.storage, Some(AndroidStorage::Sdcard));
+    assert_eq!(
+        device.storage_root,
+        Some(UnixPathBuf::from("/storage/emulated/0"))
+    );
+    let requests = server.requests();
+    assert!(requests
+        .iter()
+        .any(|request| request.ends_with("test -w \"/storage/emulated/0\"")));
+    assert!(!requests.iter().any(|request| request.contains("touch")));
+
+    server.on_shell("getprop ro.build.version.sdk", "33\n");
+    let mut device = server.device("mock").await.expect("device");
+    assert_eq!(
+        device
+            .select_storage(AndroidStorageInput::Auto)
+            .await
+            .expect("storage"),
+        AndroidStorage::Internal
+    );
+    assert_eq!(
+        device.storage_root,
+        Some(UnixPathBuf::from("/data/local/tmp"))
+    );
+
+    device.run_as_package = Some("com.example.debug".to_owned());
+    assert_eq!(
+        device
+            .select_storage(AndroidStorageInput::Auto)
+            .await
+            .expect("storage"),
+        AndroidStorage::App
+    );
+    assert_eq!(
+        device.storage_root,
+        Some(UnixPathBuf::from("/data/data/com.example.debug"))
+    );
+}
+
+#[tokio::test]
+async fn mock_read_only_device_auto_storage() {
+    let server = testing::MockServer::with_device("mock");
+    server.on_shell("getprop ro.build.version.sdk", "28\n");
+    server.on_shell("echo $EXTERNAL_STORAGE", "/sdcard\n");
+    let mut device = server.device("mock").await.expect("device");
+    device.read_only = true;
+
+    assert_eq!(
+        device
+            .select_storage(AndroidStorageInput::Auto)
+            .await
+            .expect("storage"),
+        AndroidStorage::Sdcard
+    );
+    let requests = server.requests();
+    assert!(requests
+        .iter()
+        .any(|request| request.ends_with("test -w \"/sdcard\"")));
+    assert!(!requests
+        .iter()
+        .any(|request| request.contains("touch") || request.contains("rm ")));
+}
+
+#[tokio::test]
+async fn mock_resilient_device_restores_forwards() {
+    let server = testing::MockServer::with_device("mock");
+    let device = ResilientDevice::new(server.device("mock").await.expect("device"));
+    let mut events = device.subscribe();
+
+    assert_eq!(
+        device.forward_port(8000, 9000).await.expect("forward"),
+        8000
+    );
+    assert!(device.is_connected());
+
+    server.add_device("mock", "offline");
+    let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+        .await
+        .expect("event")
+        .expect("channel");
+    assert_eq!(
+        event,
+        ConnectionEvent::Disconnected(Some(DeviceState::Offline))
+    );
+    assert!(!device.is_connected());
+
+    server.add_device("mock", "device");
+    let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+        .await
+        .expect("event")
+        .expect("channel");
+    assert_eq!(event, ConnectionEvent::Reconnected);
+    device
+        .wait_online(Duration::from_secs(5))
+        .await
+        .expect("online");
+
+    let forwards = server
+        .requests()
+        .into_iter()
+        .filter(|r| r == "host-serial:mock:forward:tcp:8000;tcp:9000")
+        .count();
+    assert_eq!(forwards, 2);
+}
+
+#[tokio::test]
+async fn mock_device_ping() {
+    let server = testing::MockServer::with_device("mock");
+    let device = server.device("mock").await.expect("device");
+
+    device
+        .ping(Duration::from_secs(5))
+        .await
+        .expect("ping to succeed");
+    assert!(device.is_online().await);
+
+    server.add_device("mock", "unauthorized");
+    match device.ping(Duration::from_secs(5)).await {
+        Err(DeviceError::NotOnline(serial, DeviceState::Unauthorized)) => {
+            assert_eq!(serial, "mock")
+        }
+        other => panic!("Expected not online error, got {other:?}"),
+    }
+
+    server.remove_device("mock");
+    assert!(matches!(
+        device.ping(Duration::from_secs(5)).await,
+        Err(DeviceError::UnknownDevice(_))
+    ));
+    assert!(!device.is_online().await);
+}
+
+#[tokio::test]
+async fn mock_device_retries_while_offline() {
+    let server = testing::MockServer::with_device("mock");
+    server.add_file("/sdcard/foo", "foo");
+    server.add_device("mock", "offline");
+
+    let host = Host {
+        retry: Some(RetryPolicy {
+            initial_backoff: Duration::from_millis(10),
+            ..Default::default()
+        }),
+        ..server.host()
+    };
+    let device = Device::new(host, "mock".to_owned(), BTreeMap::new())
+        .await
+        .expect("device");
+
+    let flip = server.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        flip.add_device("mock", "device");
+    });
+
+    let stats = device
+        .stat(UnixPath::new("/sdcard/foo"))
+        .await
+        .expect("stat to succeed once the device is back");
+    assert_eq!(stats.size, 3);
+}
+
+#[tokio::test]
+async fn mock_device_retries_at_one_layer() {
+    let server = testing::MockServer::with_device("mock");
+    server.add_file("/sdcard/foo", "foo");
+    let host = Host {
+        retry: Some(RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            ..Default::default()
+        }),
+        ..server.host()
+    };
+    let device = Device::new(host, "mock".to_owned(), BTreeMap::new())
+        .await
+        .expect("device");
+    server.add_device("mock", "offline");
+    let transports = || {
+        server
+            .requests()
+            .iter()
+            .filter(|request| *request == "host:transport:mock")
+            .count()
+    };
+
+    for operation in ["stat", "list_dir", "stat_many"] {
+        let before = transports();
+        let failed = match operation {
+            "stat" => device.stat(UnixPath::new("/sdcard/foo")).await.is_err(),
+            "list_dir" => device.list_dir(UnixPath::new("/sdcard")).await.is_err(),
+            _ => device.stat_many(&["/sdcard/foo"]).await.is_err(),
+        };
+        assert!(failed, "{operation}");
+        assert_eq!(transports() - before, 3, "{operation}");
+    }
+}
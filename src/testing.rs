@@ -0,0 +1,1012 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! In-process fake adb server for tests.
+//!
+//! [`MockServer`] speaks enough of the adb server protocol (`host:` services,
+//! `host:transport:`, `shell:`, `shell,v2`, `exec:` and the `sync:`
+//! LIST/STAT/RECV/SEND requests) to drive a [`Device`] without a real device or
+//! adb binary.
+//! Connections are served over in-memory pipes, see [`MockServer::host`].
+//!
+//! ```no_run
+//! # async fn example() -> forensic_adb::Result<()> {
+//! use forensic_adb::testing::MockServer;
+//!
+//! let server = MockServer::with_device("emulator-5554");
+//! server.on_shell("getprop ro.product.model", "Pixel\n");
+//!
+//! let device = server.device("emulator-5554").await?;
+//! assert_eq!(
+//!     device.execute_host_shell_command("getprop ro.product.model").await?,
+//!     "Pixel\n"
+//! );
+//! # Ok(())
+//! # }
+//! ```
+
+use futures_core::future::BoxFuture;
+use rsa::Pkcs1v15Sign;
+use sha1::Sha1;
+use std::collections::BTreeMap;
+use std::io;
+use std::pin::pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::sync::Notify;
+
+use crate::adb::{ShellPacket, SyncCommand};
+use crate::batch::BATCH_MARKER;
+use crate::direct::{
+    read_message, write_message, Message, Multiplexer, AUTH_RSAPUBLICKEY, AUTH_SIGNATURE,
+    AUTH_TOKEN, A_AUTH, A_CLSE, A_CNXN, A_OKAY, A_OPEN, A_VERSION, MAX_PAYLOAD,
+};
+use crate::keys::parse_public_key;
+use crate::shell_v2::EXIT_MARKER;
+use crate::transport::{BoxedTransport, Connector};
+use crate::{Device, HashAlgorithm, Host, Result};
+
+const PIPE_CAPACITY: usize = 256 * 1024;
+
+/// An entry of the fake device filesystem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockEntry {
+    File {
+        data: Vec<u8>,
+        mode: u32,
+        mtime: u32,
+    },
+    Directory {
+        mode: u32,
+        mtime: u32,
+    },
+    Symlink {
+        target: String,
+        mtime: u32,
+    },
+}
+
+impl MockEntry {
+    pub(crate) fn stat(&self) -> (u32, u32, u32) {
+        match self {
+            MockEntry::File { data, mode, mtime } => {
+                (0x8000 | (mode & 0o7777), data.len() as u32, *mtime)
+            }
+            MockEntry::Directory { mode, mtime } => (0x4000 | (mode & 0o7777), 0, *mtime),
+            MockEntry::Symlink { target, mtime } => (0xA000 | 0o777, target.len() as u32, *mtime),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct MockShell {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    exit_code: i32,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    devices: BTreeMap<String, String>,
+    host_features: Vec<String>,
+    shell: BTreeMap<String, MockShell>,
+    files: BTreeMap<String, MockEntry>,
+    requests: Vec<String>,
+    adb_keys: Vec<String>,
+}
+
+/// A scriptable adb server running inside the test process.
+///
+/// Cloning a `MockServer` yields another handle to the same state, so
+/// responses can be changed and pushed files inspected while a [`Device`]
+/// is using it.
+#[derive(Debug, Clone, Default)]
+pub struct MockServer {
+    state: Arc<Mutex<State>>,
+    /// Wakes `host:track-devices` connections when the listing changes.
+    devices_changed: Arc<Notify>,
+}
+
+impl MockServer {
+    /// Creates a server without any devices attached.
+    pub fn new() -> MockServer {
+        let server = MockServer::default();
+        server.state().host_features = vec!["shell_v2".to_owned(), "cmd".to_owned()];
+        server.on_shell("getprop ro.build.version.sdk", "34\n");
+        server.on_shell("echo forensic-adb-ping", "forensic-adb-ping\n");
+        server
+    }
+
+    /// Creates a server with a single online device.
+    pub fn with_device(serial: &str) -> MockServer {
+        let server = MockServer::new();
+        server.add_device(serial, "device");
+        server
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Lists a device with the given connection state, e.g. `"device"` or
+    /// `"unauthorized"`.
+    pub fn add_device(&self, serial: &str, state: &str) {
+        self.state()
+            .devices
+            .insert(serial.to_owned(), state.to_owned());
+        self.devices_changed.notify_waiters();
+    }
+
+    /// Removes a device from the listing.
+    pub fn remove_device(&self, serial: &str) {
+        self.state().devices.remove(serial);
+        self.devices_changed.notify_waiters();
+    }
+
+    fn device_listing(&self) -> String {
+        self.state()
+            .devices
+            .iter()
+            .map(|(serial, state)| format!("{serial}\t{state}\n"))
+            .collect()
+    }
+
+    /// Sets the feature list returned by `host:features`.
+    pub fn set_host_features(&self, features: &[&str]) {
+        self.state().host_features = features.iter().map(|f| f.to_string()).collect();
+    }
+
+    /// Sets the output returned for a `shell:` or `exec:` command.
+    ///
+    /// Commands without a canned response produce empty output.
+    pub fn on_shell<T: AsRef<[u8]>>(&self, command: &str, output: T) {
+        self.on_shell_result(command, output, "", 0);
+    }
+
+    /// Sets stdout, stderr and exit code of a shell command.
+    ///
+    /// Stderr is only kept separate for `shell,v2` requests; the legacy
+    /// `shell:` service appends it to stdout.
+    pub fn on_shell_result<O: AsRef<[u8]>, E: AsRef<[u8]>>(
+        &self,
+        command: &str,
+        stdout: O,
+        stderr: E,
+        exit_code: i32,
+    ) {
+        self.state().shell.insert(
+            command.to_owned(),
+            MockShell {
+                stdout: stdout.as_ref().to_vec(),
+                stderr: stderr.as_ref().to_vec(),
+                exit_code,
+            },
+        );
+    }
+
+    fn shell(&self, command: &str) -> MockShell {
+        if let Some(shell) = self.state().shell.get(command).cloned() {
+            return shell;
+        }
+        self.checksum(command)
+            .or_else(|| self.readlink(command))
+            .or_else(|| self.file_size(command))
+            .unwrap_or_default()
+    }
+
+    /// Answers `stat -c %s` of a quoted path from the fake filesystem.
+    fn file_size(&self, command: &str) -> Option<MockShell> {
+        let path = unquote(command.strip_prefix("stat -c %s ")?)?;
+        Some(match self.file(&path) {
+            Some(data) => MockShell {
+                stdout: format!("{}\n", data.len()).into_bytes(),
+                ..Default::default()
+            },
+            None => MockShell {
+                stderr: format!("stat: '{path}': No such file or directory\n").into_bytes(),
+                exit_code: 1,
+                ..Default::default()
+            },
+        })
+    }
+
+    /// Appends `data` to the regular file at `path`, creating it if needed.
+    fn append_file(&self, path: &str, data: &[u8]) {
+        let mut contents = self.file(path).unwrap_or_default();
+        contents.extend_from_slice(data);
+        self.add_file(path, contents);
+    }
+
+    /// Answers `readlink` of a quoted path from the fake filesystem.
+    fn readlink(&self, command: &str) -> Option<MockShell> {
+        let path = unquote(command.strip_prefix("readlink ")?)?;
+        Some(match self.entry(&path) {
+            Some(MockEntry::Symlink { target, .. }) => MockShell {
+                stdout: format!("{target}\n").into_bytes(),
+                ..Default::default()
+            },
+            _ => MockShell {
+                exit_code: 1,
+                ..Default::default()
+            },
+        })
+    }
+
+    /// Answers `md5sum`/`sha1sum`/`sha256sum` of a quoted path from the fake
+    /// filesystem.
+    fn checksum(&self, command: &str) -> Option<MockShell> {
+        let (tool, path) = command.split_once(' ')?;
+        let algorithm = [
+            HashAlgorithm::Md5,
+            HashAlgorithm::Sha1,
+            HashAlgorithm::Sha256,
+        ]
+        .into_iter()
+        .find(|algorithm| algorithm.command() == tool)?;
+        let path = unquote(path)?;
+
+        Some(match self.file(&path) {
+            Some(data) => {
+                let mut hasher = algorithm.hasher();
+                hasher.update(&data);
+                MockShell {
+                    stdout: format!("{}  {path}\n", hasher.finish()).into_bytes(),
+                    ..Default::default()
+                }
+            }
+            None => MockShell {
+                stderr: format!("{tool}: {path}: No such file or directory\n").into_bytes(),
+                exit_code: 1,
+                ..Default::default()
+            },
+        })
+    }
+
+    /// Looks up the response to `command`, answering the commands of a
+    /// [`Device::run_batch`] script one by one.
+    fn run_shell(&self, command: &str, merge_stderr: bool) -> MockShell {
+        let Some(commands) = batch_commands(command) else {
+            return self.shell(command);
+        };
+
+        let mut result = MockShell::default();
+        for (i, command) in commands.into_iter().enumerate() {
+            let shell = self.shell(command);
+            result.stdout.extend_from_slice(&shell.stdout);
+            if merge_stderr {
+                result.stdout.extend_from_slice(&shell.stderr);
+            } else {
+                result.stderr.extend_from_slice(&shell.stderr);
+                result
+                    .stderr
+                    .extend_from_slice(format!("{BATCH_MARKER}{i}\n").as_bytes());
+            }
+            result
+                .stdout
+                .extend_from_slice(format!("{BATCH_MARKER}{i}:{}\n", shell.exit_code).as_bytes());
+        }
+        result
+    }
+
+    /// Adds a regular file, creating its parent directories.
+    pub fn add_file<T: AsRef<[u8]>>(&self, path: &str, data: T) {
+        self.insert(
+            path,
+            MockEntry::File {
+                data: data.as_ref().to_vec(),
+                mode: 0o644,
+                mtime: now(),
+            },
+        );
+    }
+
+    /// Adds a directory, creating its parent directories.
+    pub fn add_dir(&self, path: &str) {
+        self.insert(
+            path,
+            MockEntry::Directory {
+                mode: 0o755,
+                mtime: now(),
+            },
+        );
+    }
+
+    /// Adds a symbolic link pointing at `target`.
+    pub fn add_symlink(&self, path: &str, target: &str) {
+        self.insert(
+            path,
+            MockEntry::Symlink {
+                target: target.to_owned(),
+                mtime: now(),
+            },
+        );
+    }
+
+    /// Adds an arbitrary entry, creating its parent directories.
+    pub fn insert(&self, path: &str, entry: MockEntry) {
+        let path = normalize(path);
+        let mut state = self.state();
+        let mut parent = parent_of(&path);
+        while let Some(dir) = parent {
+            state
+                .files
+                .entry(dir.clone())
+                .or_insert(MockEntry::Directory {
+                    mode: 0o755,
+                    mtime: now(),
+                });
+            parent = parent_of(&dir);
+        }
+        state.files.insert(path, entry);
+    }
+
+    /// Returns the entry stored at `path`, e.g. to inspect a pushed file.
+    pub fn entry(&self, path: &str) -> Option<MockEntry> {
+        self.state().files.get(&normalize(path)).cloned()
+    }
+
+    /// Returns the contents of the regular file at `path`.
+    pub fn file(&self, path: &str) -> Option<Vec<u8>> {
+        match self.entry(path) {
+            Some(MockEntry::File { data, .. }) => Some(data),
+            _ => None,
+        }
+    }
+
+    /// Returns all services and sync requests received so far, e.g.
+    /// `"host:transport:emulator-5554"`, `"shell:id"` or `"sync:RECV /sdcard/foo"`.
+    pub fn requests(&self) -> Vec<String> {
+        self.state().requests.clone()
+    }
+
+    /// Returns a [`Host`] connected to this server.
+    pub fn host(&self) -> Host {
+        Host {
+            connector: Some(Arc::new(MockConnector {
+                server: self.clone(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Returns a [`Device`] for the given serial connected to this server.
+    pub async fn device(&self, serial: &str) -> Result<Device> {
+        self.host().device_or_default(Some(&serial)).await
+    }
+
+    /// Returns a connection to an emulated `adbd` for the device `serial`,
+    /// for [`DeviceDirect::over`](crate::DeviceDirect::over).  Services
+    /// opened on it are served like those requested through
+    /// [`MockServer::host`].
+    ///
+    /// Once a key is authorized with [`MockServer::authorize_adb_key`],
+    /// clients have to sign the auth token with one of the authorized keys
+    /// or offer a public key, which is accepted as if the user confirmed
+    /// the prompt.
+    pub fn adbd(&self, serial: &str) -> BoxedTransport {
+        let (client, server) = tokio::io::duplex(PIPE_CAPACITY);
+        let mock = self.clone();
+        let serial = serial.to_owned();
+        tokio::spawn(async move {
+            let _ = serve_adbd(mock, serial, server).await;
+        });
+        Box::new(client)
+    }
+
+    /// Adds an `adb_keys` line to the keys accepted by [`MockServer::adbd`].
+    pub fn authorize_adb_key(&self, public_key: &str) {
+        self.state().adb_keys.push(public_key.to_owned());
+    }
+
+    /// Returns the keys accepted by [`MockServer::adbd`], including those
+    /// offered by clients.
+    pub fn adb_keys(&self) -> Vec<String> {
+        self.state().adb_keys.clone()
+    }
+
+    fn record(&self, request: String) {
+        self.state().requests.push(request);
+    }
+}
+
+#[derive(Debug)]
+struct MockConnector {
+    server: MockServer,
+}
+
+impl Connector for MockConnector {
+    fn connect(&self) -> BoxFuture<'_, Result<BoxedTransport>> {
+        Box::pin(async move {
+            let (client, server) = tokio::io::duplex(PIPE_CAPACITY);
+            let mock = self.server.clone();
+            tokio::spawn(async move {
+                let _ = serve(mock, server).await;
+            });
+            Ok(Box::new(client) as BoxedTransport)
+        })
+    }
+}
+
+/// Serves the adb message protocol, passing each opened stream to [`serve`].
+async fn serve_adbd(server: MockServer, serial: String, stream: DuplexStream) -> io::Result<()> {
+    let stream: BoxedTransport = Box::new(stream);
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let connect = read_message(&mut reader).await?;
+    if connect.command != A_CNXN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "expected CNXN"));
+    }
+
+    let token = [0x5a; 20];
+    if !server.adb_keys().is_empty() {
+        write_message(&mut writer, &Message::new(A_AUTH, AUTH_TOKEN, 0, &token)).await?;
+        loop {
+            let reply = read_message(&mut reader).await?;
+            match reply.arg0 {
+                AUTH_SIGNATURE => {
+                    let signed = server
+                        .adb_keys()
+                        .iter()
+                        .filter_map(|key| parse_public_key(key))
+                        .any(|key| {
+                            key.verify(Pkcs1v15Sign::new::<Sha1>(), &token, &reply.data)
+                                .is_ok()
+                        });
+                    if signed {
+                        break;
+                    }
+                    write_message(&mut writer, &Message::new(A_AUTH, AUTH_TOKEN, 0, &token))
+                        .await?;
+                }
+                AUTH_RSAPUBLICKEY => {
+                    let public_key = String::from_utf8_lossy(&reply.data);
+                    server.authorize_adb_key(public_key.trim_end_matches('\0'));
+                    break;
+                }
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "bad AUTH")),
+            }
+        }
+    }
+
+    let banner = format!(
+        "device::ro.product.name=mock;ro.product.model=Mock;ro.product.device=mock;features={}\0",
+        server.state().host_features.join(",")
+    );
+    write_message(
+        &mut writer,
+        &Message::new(A_CNXN, A_VERSION, MAX_PAYLOAD, banner.as_bytes()),
+    )
+    .await?;
+
+    let mux = Arc::new(Multiplexer::new(writer, connect.arg1));
+    while let Ok(message) = read_message(&mut reader).await {
+        let Some(message) = mux.dispatch(message) else {
+            continue;
+        };
+        if message.command != A_OPEN {
+            continue;
+        }
+
+        let service = String::from_utf8_lossy(&message.data);
+        let service = service.trim_end_matches('\0');
+        let remote = message.arg0;
+        let (mut client, stream) = tokio::io::duplex(PIPE_CAPACITY);
+        tokio::spawn(serve(server.clone(), stream));
+        let mut status = [0u8; 4];
+        for request in [format!("host:transport:{serial}"), service.to_owned()] {
+            client
+                .write_all(format!("{:04x}{request}", request.len()).as_bytes())
+                .await?;
+            client.read_exact(&mut status).await?;
+            if &status != SyncCommand::Okay.code() {
+                break;
+            }
+        }
+        if &status != SyncCommand::Okay.code() {
+            mux.send(A_CLSE, 0, remote, &[]).await?;
+            continue;
+        }
+
+        let (local, receivers) = mux.register();
+        mux.send(A_OKAY, local, remote, &[]).await?;
+        let mux = mux.clone();
+        tokio::spawn(async move { mux.pump(client, local, remote, receivers).await });
+    }
+    mux.close_all();
+    Ok(())
+}
+
+/// Reads a tar archive up to its end-of-archive blocks, which is where
+/// `tar -xf -` stops reading, or until the input ends.
+async fn read_tar<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<Vec<u8>> {
+    let mut archive = Vec::new();
+    let mut block = [0u8; 512];
+    let mut zero_blocks = 0;
+    while zero_blocks < 2 {
+        match stream.read_exact(&mut block).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        archive.extend_from_slice(&block);
+        zero_blocks = match block.iter().all(|&b| b == 0) {
+            true => zero_blocks + 1,
+            false => 0,
+        };
+    }
+    Ok(archive)
+}
+
+/// Reverses `DevicePath::quoted`.
+fn unquote(quoted: &str) -> Option<String> {
+    let inner = quoted.strip_prefix('"')?.strip_suffix('"')?;
+    let mut path = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => path.push(chars.next()?),
+            c => path.push(c),
+        }
+    }
+    Some(path)
+}
+
+/// Recovers the commands of a script built by `batch::batch_script`.
+fn batch_commands(script: &str) -> Option<Vec<&str>> {
+    let separator = format!("\n); echo \"{BATCH_MARKER}");
+    if !script.contains(&separator) {
+        return None;
+    }
+
+    let mut commands = Vec::new();
+    for (i, piece) in script.split(separator.as_str()).enumerate() {
+        let command = match i {
+            0 => piece.strip_prefix("(\n"),
+            _ => piece.split_once("\n(\n").map(|(_, command)| command),
+        };
+        match command {
+            Some(command) => commands.push(command),
+            None => break,
+        }
+    }
+    Some(commands)
+}
+
+pub(crate) fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as u32)
+        .unwrap_or(0)
+}
+
+pub(crate) fn normalize(path: &str) -> String {
+    let trimmed = path.trim_end_matches('/');
+    if trimmed.is_empty() {
+        "/".to_owned()
+    } else {
+        trimmed.to_owned()
+    }
+}
+
+pub(crate) fn parent_of(path: &str) -> Option<String> {
+    if path == "/" {
+        return None;
+    }
+    match path.rfind('/') {
+        Some(0) => Some("/".to_owned()),
+        Some(i) => Some(path[..i].to_owned()),
+        None => None,
+    }
+}
+
+async fn write_okay_with_payload(stream: &mut DuplexStream, payload: &[u8]) -> io::Result<()> {
+    stream.write_all(SyncCommand::Okay.code()).await?;
+    stream
+        .write_all(format!("{:04x}", payload.len()).as_bytes())
+        .await?;
+    stream.write_all(payload).await
+}
+
+async fn write_fail(stream: &mut DuplexStream, message: &str) -> io::Result<()> {
+    stream.write_all(SyncCommand::Fail.code()).await?;
+    stream
+        .write_all(format!("{:04x}", message.len()).as_bytes())
+        .await?;
+    stream.write_all(message.as_bytes()).await
+}
+
+async fn read_request(stream: &mut DuplexStream) -> io::Result<Option<String>> {
+    let mut length = [0u8; 4];
+    match stream.read_exact(&mut length).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let length = std::str::from_utf8(&length)
+        .ok()
+        .and_then(|s| usize::from_str_radix(s, 16).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid length"))?;
+    let mut payload = vec![0u8; length];
+    stream.read_exact(&mut payload).await?;
+    String::from_utf8(payload)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+async fn serve(server: MockServer, mut stream: DuplexStream) -> io::Result<()> {
+    let mut transport: Option<String> = None;
+
+    while let Some(request) = read_request(&mut stream).await? {
+        server.record(request.clone());
+
+        if let Some(serial) = request.strip_prefix("host:transport:") {
+            let online = server.state().devices.get(serial).map(|s| s == "device");
+            match online {
+                Some(true) => {
+                    stream.write_all(SyncCommand::Okay.code()).await?;
+                    transport = Some(serial.to_owned());
+                    continue;
+                }
+                Some(false) => return write_fail(&mut stream, "device offline").await,
+                None => {
+                    return write_fail(&mut stream, &format!("device '{serial}' not found")).await
+                }
+            }
+        }
+
+        if request == "host:track-devices" {
+            stream.write_all(SyncCommand::Okay.code()).await?;
+            return serve_track_devices(&server, &mut stream).await;
+        }
+
+        if let Some(service) = request.strip_prefix("host:") {
+            return serve_host(&server, &mut stream, service).await;
+        }
+
+        if let Some(rest) = request.strip_prefix("host-serial:") {
+            let (serial, service) = rest.split_once(':').unwrap_or((rest, ""));
+            if !server.state().devices.contains_key(serial) {
+                return write_fail(&mut stream, &format!("device '{serial}' not found")).await;
+            }
+            return serve_host(&server, &mut stream, service).await;
+        }
+
+        if transport.is_none() {
+            return write_fail(&mut stream, "no device selected").await;
+        }
+
+        if request.starts_with("shell,v2,") && request.contains("pty:") {
+            stream.write_all(SyncCommand::Okay.code()).await?;
+            return serve_pty(&mut stream).await;
+        }
+
+        let command = request
+            .strip_prefix("shell,v2,raw:")
+            .or_else(|| request.strip_prefix("shell,v2:"));
+        if let Some(command) = command {
+            let shell = server.run_shell(command, false);
+            stream.write_all(SyncCommand::Okay.code()).await?;
+            write_shell_packet(&mut stream, ShellPacket::Stdout, &shell.stdout).await?;
+            write_shell_packet(&mut stream, ShellPacket::Stderr, &shell.stderr).await?;
+            write_shell_packet(&mut stream, ShellPacket::Exit, &[shell.exit_code as u8]).await?;
+            return stream.shutdown().await;
+        }
+
+        if let Some(command) = request.strip_prefix("shell:") {
+            let wrapped = command
+                .strip_prefix("(\n")
+                .and_then(|c| c.strip_suffix(&format!("\n); echo \"{EXIT_MARKER}$?\"")));
+            let shell = server.run_shell(wrapped.unwrap_or(command), true);
+            stream.write_all(SyncCommand::Okay.code()).await?;
+            stream.write_all(&shell.stdout).await?;
+            stream.write_all(&shell.stderr).await?;
+            if wrapped.is_some() {
+                let status = format!("{EXIT_MARKER}{}\n", shell.exit_code);
+                stream.write_all(status.as_bytes()).await?;
+            }
+            return stream.shutdown().await;
+        }
+
+        if request.starts_with("reverse:forward:") {
+            return stream.write_all(b"OKAYOKAY").await;
+        }
+
+        if let Some(command) = request.strip_prefix("exec:") {
+            // `dd` appending its input, as used by `Device::push_resume`.
+            let append = command
+                .strip_prefix("dd of=")
+                .and_then(|c| c.strip_suffix(" oflag=append conv=notrunc bs=65536 2>/dev/null"))
+                .and_then(unquote);
+            if let Some(path) = append {
+                stream.write_all(SyncCommand::Okay.code()).await?;
+                let mut data = Vec::new();
+                stream.read_to_end(&mut data).await?;
+                server.append_file(&path, &data);
+                return stream.shutdown().await;
+            }
+
+            // `dd` reading a range of a file, as used by `Device::pull_parallel`.
+            let range = command
+                .strip_prefix("dd if=")
+                .and_then(|c| c.strip_suffix(" bs=65536 2>/dev/null"))
+                .and_then(|c| c.split_once(" iflag=skip_bytes,count_bytes skip="))
+                .and_then(|(path, c)| {
+                    let (skip, count) = c.split_once(" count=")?;
+                    Some((
+                        unquote(path)?,
+                        skip.parse::<usize>().ok()?,
+                        count.parse::<usize>().ok()?,
+                    ))
+                });
+            if let Some((path, skip, count)) = range {
+                let data = server.file(&path).unwrap_or_default();
+                let start = data.len().min(skip);
+                let end = data.len().min(start + count);
+                stream.write_all(SyncCommand::Okay.code()).await?;
+                stream.write_all(&data[start..end]).await?;
+                return stream.shutdown().await;
+            }
+
+            // `tar` extracting its input, as used by `Device::push_tar`.
+            let extract = command
+                .strip_prefix("tar -xf - -C ")
+                .and_then(|c| c.strip_suffix(" 2>&1"))
+                .and_then(unquote);
+            if let Some(dest_dir) = extract {
+                stream.write_all(SyncCommand::Okay.code()).await?;
+                let data = read_tar(&mut stream).await?;
+                let mut archive = tar::Archive::new(&data[..]);
+                let mut failed = false;
+                for entry in archive.entries()? {
+                    let Ok(mut entry) = entry else {
+                        failed = true;
+                        break;
+                    };
+                    let name = entry.path()?.to_string_lossy().into_owned();
+                    let name = name.trim_start_matches("./").trim_end_matches('/');
+                    if name.is_empty() || name == "." {
+                        continue;
+                    }
+                    let path = format!("{}/{name}", dest_dir.trim_end_matches('/'));
+                    if entry.header().entry_type().is_dir() {
+                        server.add_dir(&path);
+                    } else {
+                        let mut data = Vec::new();
+                        std::io::Read::read_to_end(&mut entry, &mut data)?;
+                        server.add_file(&path, &data);
+                    }
+                }
+                if failed {
+                    stream.write_all(b"tar: Not tar\n").await?;
+                }
+                return stream.shutdown().await;
+            }
+
+            // `pm install-write` streaming a split into a session, as used by
+            // `InstallSession::write`.  Written splits show up under
+            // `/data/app/vmdl<session>.tmp/`.
+            let install_write = command
+                .split_once(" install-write -S ")
+                .and_then(|(_, c)| c.strip_suffix(" - 2>&1"))
+                .and_then(|c| {
+                    let mut args = c.split(' ');
+                    Some((
+                        args.next()?.parse::<u64>().ok()?,
+                        args.next()?,
+                        args.next()?,
+                    ))
+                });
+            if let Some((size, session, name)) = install_write {
+                stream.write_all(SyncCommand::Okay.code()).await?;
+                let mut data = vec![0; size as usize];
+                stream.read_exact(&mut data).await?;
+                server.add_file(&format!("/data/app/vmdl{session}.tmp/{name}"), &data);
+                stream
+                    .write_all(format!("Success: streamed {size} bytes\n").as_bytes())
+                    .await?;
+                return stream.shutdown().await;
+            }
+
+            stream.write_all(SyncCommand::Okay.code()).await?;
+            stream.write_all(&server.shell(command).stdout).await?;
+            return stream.shutdown().await;
+        }
+
+        if request == "sync:" {
+            stream.write_all(SyncCommand::Okay.code()).await?;
+            return serve_sync(&server, &mut stream).await;
+        }
+
+        return write_fail(&mut stream, &format!("unknown service: {request}")).await;
+    }
+
+    Ok(())
+}
+
+async fn serve_host(
+    server: &MockServer,
+    stream: &mut DuplexStream,
+    service: &str,
+) -> io::Result<()> {
+    let payload = match service {
+        "version" => "0029".to_owned(),
+        "features" => server.state().host_features.join(","),
+        "devices" | "devices-l" => server.device_listing(),
+        _ if service.starts_with("forward:") => return stream.write_all(b"OKAYOKAY").await,
+        _ => return write_fail(stream, &format!("unknown host service: {service}")).await,
+    };
+    write_okay_with_payload(stream, payload.as_bytes()).await
+}
+
+/// Sends the device listing, and again whenever it changes.
+async fn serve_track_devices(server: &MockServer, stream: &mut DuplexStream) -> io::Result<()> {
+    loop {
+        let mut changed = pin!(server.devices_changed.notified());
+        changed.as_mut().enable();
+
+        let listing = server.device_listing();
+        stream
+            .write_all(format!("{:04x}", listing.len()).as_bytes())
+            .await?;
+        stream.write_all(listing.as_bytes()).await?;
+
+        changed.await;
+    }
+}
+
+async fn write_shell_packet(
+    stream: &mut DuplexStream,
+    packet: ShellPacket,
+    payload: &[u8],
+) -> io::Result<()> {
+    stream.write_all(&[packet.id()]).await?;
+    stream
+        .write_all(&(payload.len() as u32).to_le_bytes())
+        .await?;
+    stream.write_all(payload).await
+}
+
+/// Echoes stdin back as stdout and reports window size changes on stderr.
+/// Ctrl-C exits with status 130, closing stdin with status 0.
+async fn serve_pty(stream: &mut DuplexStream) -> io::Result<()> {
+    loop {
+        let mut id = [0u8; 1];
+        match stream.read_exact(&mut id).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let length = read_u32(stream).await? as usize;
+        let mut payload = vec![0u8; length];
+        stream.read_exact(&mut payload).await?;
+
+        match ShellPacket::from_id(id[0]) {
+            Some(ShellPacket::Stdin) if payload.contains(&0x03) => {
+                write_shell_packet(stream, ShellPacket::Exit, &[130]).await?;
+                return stream.shutdown().await;
+            }
+            Some(ShellPacket::Stdin) => {
+                write_shell_packet(stream, ShellPacket::Stdout, &payload).await?;
+            }
+            Some(ShellPacket::WindowSizeChange) => {
+                write_shell_packet(stream, ShellPacket::Stderr, &payload).await?;
+            }
+            Some(ShellPacket::CloseStdin) => {
+                write_shell_packet(stream, ShellPacket::Exit, &[0]).await?;
+                return stream.shutdown().await;
+            }
+            _ => {}
+        }
+    }
+}
+
+async fn read_u32(stream: &mut DuplexStream) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    stream.read_exact(&mut bytes).await?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+async fn write_sync_fail(stream: &mut DuplexStream, message: &str) -> io::Result<()> {
+    stream.write_all(SyncCommand::Fail.code()).await?;
+    stream
+        .write_all(&(message.len() as u32).to_le_bytes())
+        .await?;
+    stream.write_all(message.as_bytes()).await
+}
+
+async fn serve_sync(server: &MockServer, stream: &mut DuplexStream) -> io::Result<()> {
+    loop {
+        let mut id = [0u8; 4];
+        match stream.read_exact(&mut id).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let length = read_u32(stream).await? as usize;
+        let mut arg = vec![0u8; length];
+        stream.read_exact(&mut arg).await?;
+        let arg = String::from_utf8_lossy(&arg).into_owned();
+
+        server.record(format!(
+            "sync:{} {}",
+            String::from_utf8_lossy(&id),
+            arg.as_str()
+        ));
+
+        if &id == SyncCommand::Stat.code() {
+            let (mode, size, mtime) = server
+                .entry(&arg)
+                .map(|entry| entry.stat())
+                .unwrap_or((0, 0, 0));
+            stream.write_all(SyncCommand::Stat.code()).await?;
+            for value in [mode, size, mtime] {
+                stream.write_all(&value.to_le_bytes()).await?;
+            }
+        } else if &id == SyncCommand::List.code() {
+            let dir = normalize(&arg);
+            let children: Vec<(String, MockEntry)> = server
+                .state()
+                .files
+                .iter()
+                .filter(|(path, _)| parent_of(path).as_deref() == Some(dir.as_str()))
+                .map(|(path, entry)| {
+                    let name = path.rsplit('/').next().unwrap_or(path).to_owned();
+                    (name, entry.clone())
+                })
+                .collect();
+            for (name, entry) in children {
+                let (mode, size, mtime) = entry.stat();
+                stream.write_all(SyncCommand::Dent.code()).await?;
+                for value in [mode, size, mtime, name.len() as u32] {
+                    stream.write_all(&value.to_le_bytes()).await?;
+                }
+                stream.write_all(name.as_bytes()).await?;
+            }
+            stream.write_all(SyncCommand::Done.code()).await?;
+            stream.write_all(&[0u8; 16]).await?;
+        } else if &id == SyncCommand::Recv.code() {
+            match server.file(&arg) {
+                Some(data) => {
+                    for chunk in data.chunks(64 * 1024) {
+                        stream.write_all(SyncCommand::Data.code()).await?;
+                        stream
+                            .write_all(&(chunk.len() as u32).to_le_bytes())
+                            .await?;
+                        stream.write_all(chunk).await?;
+                    }
+                    stream.write_all(SyncCommand::Done.code()).await?;
+                    stream.write_all(&[0u8; 4]).await?;
+                }
+                None => write_sync_fail(stream, "No such file or directory").await?,
+            }
+        } else if &id == SyncCommand::Send.code() {
+            let (path, mode) = match arg.rsplit_once(',') {
+                Some((path, mode)) => (path.to_owned(), mode.parse::<u32>().unwrap_or(0o644)),
+                None => (arg.clone(), 0o644),
+            };
+            let mut data = Vec::new();
+            let mtime = loop {
+                let mut id = [0u8; 4];
+                stream.read_exact(&mut id).await?;
+                let value = read_u32(stream).await?;
+                if &id == SyncCommand::Data.code() {
+                    let start = data.len();
+                    data.resize(start + value as usize, 0);
+                    stream.read_exact(&mut data[start..]).await?;
+                } else if &id == SyncCommand::Done.code() {
+                    break value;
+                } else {
+                    return write_sync_fail(stream, "unexpected sync request").await;
+                }
+            };
+            server.insert(&path, MockEntry::File { data, mode, mtime });
+            stream.write_all(SyncCommand::Okay.code()).await?;
+            stream.write_all(&[0u8; 4]).await?;
+        } else if &id == SyncCommand::Quit.code() {
+            return Ok(());
+        } else {
+            return write_sync_fail(stream, "unknown sync request").await;
+        }
+    }
+}
@@ -0,0 +1,714 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Options for file transfers and what they report back.
+
+use globset::{GlobBuilder, GlobMatcher};
+use sha2::digest::DynDigest;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+use crate::sparse::SparseFile;
+use crate::{
+    BoxedTransport, Device, DeviceError, DevicePath, DirectoryTransferProgress,
+    FileTransferProgress, ProgressSink, Result, UnixFileStatus, UnixPath, UnixPathBuf,
+};
+
+/// Digest algorithms that can be computed both on the host and on the
+/// device (toybox provides the matching `*sum` tools).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Md5,
+    Sha1,
+    Sha256,
+}
+
+impl HashAlgorithm {
+    /// The device command printing this digest.
+    pub fn command(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Md5 => "md5sum",
+            HashAlgorithm::Sha1 => "sha1sum",
+            HashAlgorithm::Sha256 => "sha256sum",
+        }
+    }
+
+    pub(crate) fn hasher(&self) -> Hasher {
+        Hasher(match self {
+            HashAlgorithm::Md5 => Box::new(md5::Md5::default()),
+            HashAlgorithm::Sha1 => Box::new(sha1::Sha1::default()),
+            HashAlgorithm::Sha256 => Box::new(sha2::Sha256::default()),
+        })
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HashAlgorithm::Md5 => "md5",
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Sha256 => "sha256",
+        })
+    }
+}
+
+/// Incremental digest of the bytes of one transfer.
+pub(crate) struct Hasher(Box<dyn DynDigest + Send + Sync>);
+
+impl Hasher {
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    /// Returns the digest as lowercase hex, like the `*sum` tools print it.
+    pub(crate) fn finish(self) -> String {
+        self.0
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+}
+
+/// Handling of symbolic links found by directory pulls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkMode {
+    #[default]
+    Skip,
+    /// Reads the link target and recreates the link on the host.  Where
+    /// that is not possible the link is only listed in
+    /// [`TransferReport::symlinks`].
+    Recreate,
+}
+
+/// Settings for the `*_with_options` transfer methods.
+///
+/// ```no_run
+/// # async fn example(device: forensic_adb::Device) -> forensic_adb::Result<()> {
+/// use forensic_adb::{HashAlgorithm, TransferOptions, UnixPath};
+///
+/// let options = TransferOptions::new().verify(HashAlgorithm::Sha256);
+/// let report = device
+///     .pull_dir_with_options(UnixPath::new("/sdcard/DCIM"), "dcim".as_ref(), &options)
+///     .await?;
+/// for file in &report.files {
+///     println!("{} {}", file.digest.as_deref().unwrap_or("-"), file.device_path.display());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransferOptions {
+    /// Digest to compute over the transferred bytes and compare with the
+    /// digest computed on the device.
+    pub verify: Option<HashAlgorithm>,
+    /// Carries modification times over: pulled files and directories get
+    /// the device timestamps, pushed files the host ones.
+    pub preserve_times: bool,
+    /// Modification time sent for a single pushed file instead of the
+    /// current time.
+    pub mtime: Option<SystemTime>,
+    /// What directory pulls do with symbolic links.
+    pub symlinks: SymlinkMode,
+    /// Only enumerates: the report lists what would be transferred, with
+    /// sizes from the listing, and neither the device nor the host is
+    /// written to.
+    pub dry_run: bool,
+    /// Leaves blocks of zeros in pulled files as holes on the host, so that
+    /// disk images do not take up their full size.  Only applies where the
+    /// destination is a host file; zeros are still transferred, and pushes
+    /// always write them out on the device.
+    pub sparse: bool,
+    /// Starts pulling a directory while it is still being listed, rather
+    /// than listing it completely first to count its files and bytes.
+    /// Directory progress then reports both totals as zero.
+    pub skip_totals: bool,
+}
+
+impl TransferOptions {
+    pub fn new() -> TransferOptions {
+        TransferOptions::default()
+    }
+
+    /// Verifies every transferred file, failing with
+    /// [`DeviceError::ChecksumMismatch`] if the digests differ.
+    pub fn verify(mut self, algorithm: HashAlgorithm) -> TransferOptions {
+        self.verify = Some(algorithm);
+        self
+    }
+
+    pub fn preserve_times(mut self, preserve: bool) -> TransferOptions {
+        self.preserve_times = preserve;
+        self
+    }
+
+    pub fn symlinks(mut self, mode: SymlinkMode) -> TransferOptions {
+        self.symlinks = mode;
+        self
+    }
+
+    pub fn sparse(mut self, sparse: bool) -> TransferOptions {
+        self.sparse = sparse;
+        self
+    }
+
+    pub fn skip_totals(mut self, skip: bool) -> TransferOptions {
+        self.skip_totals = skip;
+        self
+    }
+
+    pub fn dry_run(mut self, dry_run: bool) -> TransferOptions {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// See [`TransferOptions::mtime`].
+    pub fn mtime(mut self, mtime: SystemTime) -> TransferOptions {
+        self.mtime = Some(mtime);
+        self
+    }
+
+    /// Returns the per-file state for a transfer with these options.
+    pub(crate) fn state(&self) -> TransferState {
+        TransferState {
+            hasher: self.verify.map(|algorithm| algorithm.hasher()),
+            mtime: None,
+            sparse: self.sparse,
+            sparse_bytes: 0,
+            elapsed: Duration::ZERO,
+            connections: None,
+        }
+    }
+}
+
+/// Per-file state threaded through the sync loops.
+#[derive(Default)]
+pub(crate) struct TransferState {
+    /// Digest of the bytes transferred so far.
+    pub(crate) hasher: Option<Hasher>,
+    /// Modification time sent when a push completes, instead of now, or
+    /// set on the host file after a pull.
+    pub(crate) mtime: Option<SystemTime>,
+    /// Whether pulled zero blocks are counted as holes.
+    pub(crate) sparse: bool,
+    pub(crate) sparse_bytes: u64,
+    /// Time from opening the sync connection to the end of the transfer.
+    pub(crate) elapsed: Duration,
+    /// Idle sync connections to use and give back; without them, each
+    /// transfer opens its own.
+    pub(crate) connections: Option<Arc<SyncConnections>>,
+}
+
+/// Idle sync connections shared by the files of a directory transfer, so
+/// that not every file pays for a connection and `sync:` handshake.
+#[derive(Default)]
+pub(crate) struct SyncConnections(Mutex<Vec<BoxedTransport>>);
+
+impl SyncConnections {
+    pub(crate) fn take(&self) -> Option<BoxedTransport> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).pop()
+    }
+
+    /// Returns a connection whose last request completed.
+    pub(crate) fn put(&self, stream: BoxedTransport) {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(stream);
+    }
+}
+
+/// Creates a symbolic link on the host pointing at a device path.  The
+/// target is kept verbatim, so absolute targets refer to device paths.
+#[cfg(unix)]
+pub(crate) fn create_host_symlink(target: &UnixPath, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target.display().to_string(), link)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn create_host_symlink(_target: &UnixPath, _link: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symbolic links are not supported on this platform",
+    ))
+}
+
+/// Compiles a glob where `*` stops at `/` and `**` spans directories.
+fn compile_glob(pattern: &str) -> Result<GlobMatcher> {
+    GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .map(|glob| glob.compile_matcher())
+        .map_err(|e| DeviceError::InvalidPattern(pattern.to_owned(), e.kind().to_string()))
+}
+
+/// Sets the modification time of a host directory.  Not supported on all
+/// platforms, so callers treat failures as non-fatal.
+pub(crate) fn set_directory_mtime(path: &Path, mtime: SystemTime) -> io::Result<()> {
+    File::open(path)?.set_modified(mtime)
+}
+
+/// A file moved by a transfer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferredFile {
+    pub device_path: UnixPathBuf,
+    /// `None` for transfers from or to a caller-provided buffer.
+    pub host_path: Option<PathBuf>,
+    pub bytes: u64,
+    /// Part of `bytes` left as holes by a sparse pull.
+    pub sparse_bytes: u64,
+    /// Verified digest, if [`TransferOptions::verify`] was set.
+    pub digest: Option<String>,
+    /// Duration of the sync transfer, excluding verification.  Zero for
+    /// dry runs.
+    pub elapsed: Duration,
+}
+
+impl TransferredFile {
+    /// Bytes per second, or `None` if nothing was timed.
+    pub fn throughput(&self) -> Option<f64> {
+        throughput(self.bytes, self.elapsed)
+    }
+}
+
+/// A symbolic link found by a directory pull.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PulledSymlink {
+    pub device_path: UnixPathBuf,
+    pub host_path: PathBuf,
+    pub target: UnixPathBuf,
+    /// Whether the link was recreated at `host_path`.
+    pub created: bool,
+}
+
+/// Result of a directory transfer.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransferReport {
+    pub files: Vec<TransferredFile>,
+    /// Links seen with [`SymlinkMode::Recreate`], doubling as a manifest
+    /// where they could not be recreated.
+    pub symlinks: Vec<PulledSymlink>,
+    /// Wall-clock duration of the whole transfer.  Zero for dry runs.
+    pub elapsed: Duration,
+}
+
+impl TransferReport {
+    pub fn total_bytes(&self) -> u64 {
+        self.files.iter().map(|file| file.bytes).sum()
+    }
+
+    /// Overall bytes per second, or `None` if nothing was timed.  With
+    /// concurrent transfers this exceeds the throughput of single files.
+    pub fn throughput(&self) -> Option<f64> {
+        throughput(self.total_bytes(), self.elapsed)
+    }
+}
+
+fn throughput(bytes: u64, elapsed: Duration) -> Option<f64> {
+    Some(bytes as f64 / elapsed.as_secs_f64()).filter(|_| !elapsed.is_zero())
+}
+
+impl Device {
+    /// Computes the digest of a device file with the matching `*sum` tool.
+    pub async fn checksum(&self, path: &UnixPath, algorithm: HashAlgorithm) -> Result<String> {
+        let quoted = DevicePath::new(path)?.quoted();
+        let output = self
+            .execute_host_shell_command_as(
+                &format!("{} {quoted}", algorithm.command()),
+                self.enable_run_as_for_path(path),
+            )
+            .await?;
+
+        let digest = output.split_whitespace().next().unwrap_or_default();
+        if digest.is_empty() || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(DeviceError::Adb(format!(
+                "{} failed: {}",
+                algorithm.command(),
+                output.trim()
+            )));
+        }
+
+        Ok(digest.to_ascii_lowercase())
+    }
+
+    /// Returns the target of the symbolic link at `path`.
+    pub async fn read_link(&self, path: &UnixPath) -> Result<UnixPathBuf> {
+        let quoted = DevicePath::new(path)?.quoted();
+        let output = self
+            .execute_host_shell_command_as(
+                &format!("readlink {quoted}"),
+                self.enable_run_as_for_path(path),
+            )
+            .await?;
+
+        let target = output.trim_end_matches('\n');
+        if target.is_empty() || target.starts_with("readlink:") {
+            return Err(DeviceError::Adb(format!(
+                "readlink {} failed: {}",
+                path.display(),
+                target
+            )));
+        }
+
+        Ok(UnixPathBuf::from(target))
+    }
+
+    /// Finishes the digest of a transfer, if requested, and compares it with
+    /// the one of the file on the device.
+    pub(crate) async fn verify_digest(
+        &self,
+        path: &UnixPath,
+        options: &TransferOptions,
+        state: TransferState,
+    ) -> Result<Option<String>> {
+        let (Some(algorithm), Some(hasher)) = (options.verify, state.hasher) else {
+            return Ok(None);
+        };
+
+        let host = hasher.finish();
+        let device = self.checksum(path, algorithm).await?;
+        if host != device {
+            return Err(DeviceError::ChecksumMismatch(
+                path.display().to_string(),
+                host,
+                device,
+            ));
+        }
+        Ok(Some(host))
+    }
+
+    /// Like [`Device::pull`], with the given options applied.
+    pub async fn pull_with_options<W: AsyncWrite + Unpin>(
+        &self,
+        src: &UnixPath,
+        buffer: &mut W,
+        options: &TransferOptions,
+    ) -> Result<TransferredFile> {
+        if options.dry_run {
+            return Ok(TransferredFile {
+                device_path: src.to_path_buf(),
+                host_path: None,
+                bytes: self.stat(src).await?.size as u64,
+                sparse_bytes: 0,
+                digest: None,
+                elapsed: Duration::ZERO,
+            });
+        }
+
+        let mut state = options.state();
+        let bytes = self
+            .pull_internal(src, buffer, None, None, &mut state)
+            .await?;
+        let elapsed = state.elapsed;
+        let digest = self.verify_digest(src, options, state).await?;
+
+        Ok(TransferredFile {
+            device_path: src.to_path_buf(),
+            host_path: None,
+            bytes,
+            sparse_bytes: 0,
+            digest,
+            elapsed,
+        })
+    }
+
+    /// Pulls `src` into a new host file at `dest`, with the given options
+    /// applied.  Unlike [`Device::pull_with_options`] this can honor
+    /// [`TransferOptions::sparse`] and [`TransferOptions::preserve_times`].
+    pub async fn pull_to_file(
+        &self,
+        src: &UnixPath,
+        dest: &Path,
+        options: &TransferOptions,
+    ) -> Result<TransferredFile> {
+        if options.dry_run {
+            return Ok(TransferredFile {
+                device_path: src.to_path_buf(),
+                host_path: Some(dest.to_path_buf()),
+                bytes: self.stat(src).await?.size as u64,
+                sparse_bytes: 0,
+                digest: None,
+                elapsed: Duration::ZERO,
+            });
+        }
+
+        let mut state = options.state();
+        if options.preserve_times {
+            state.mtime = self.stat(src).await?.modified_time;
+        }
+        self.pull_file(src, dest, None, None, options, state).await
+    }
+
+    /// Pulls `src` into memory, for small files such as configs.
+    ///
+    /// The buffer is allocated from the size reported by `stat`.  Files of
+    /// more than [`Device::pull_to_vec_limit`] bytes, also ones growing past
+    /// it during the pull, fail with an [`io::ErrorKind::FileTooLarge`]
+    /// error.
+    pub async fn pull_to_vec(&self, src: &UnixPath) -> Result<Vec<u8>> {
+        let max_bytes = self.pull_to_vec_limit;
+        let size = self.stat(src).await?.size as u64;
+        if size > max_bytes {
+            return Err(too_large(src, max_bytes).into());
+        }
+
+        let mut buffer = CappedBuffer {
+            data: Vec::with_capacity(size as usize),
+            max_bytes,
+            path: src,
+        };
+        self.pull_internal(src, &mut buffer, None, None, &mut TransferState::default())
+            .await?;
+        Ok(buffer.data)
+    }
+
+    /// Pulls `src` like [`Device::pull_to_vec`] and decodes it as UTF-8.
+    pub async fn pull_to_string(&self, src: &UnixPath) -> Result<String> {
+        let data = self.pull_to_vec(src).await?;
+        String::from_utf8(data).map_err(|e| e.utf8_error().into())
+    }
+
+    /// Pulls `src` into a new host file at `dest`, leaving holes and setting
+    /// `state.mtime` as requested by `options`.
+    pub(crate) async fn pull_file(
+        &self,
+        src: &UnixPath,
+        dest: &Path,
+        size: Option<u64>,
+        progress: Option<&dyn ProgressSink<FileTransferProgress>>,
+        options: &TransferOptions,
+        mut state: TransferState,
+    ) -> Result<TransferredFile> {
+        let file = tokio::fs::File::create(dest).await?;
+
+        let (bytes, file) = if options.sparse {
+            let mut file = SparseFile::new(file);
+            let bytes = self
+                .pull_internal(src, &mut file, size, progress, &mut state)
+                .await?;
+            (bytes, file.finish().await?)
+        } else {
+            let mut file = file;
+            let bytes = self
+                .pull_internal(src, &mut file, size, progress, &mut state)
+                .await?;
+            // Dropping a tokio `File` does not wait for pending writes.
+            file.flush().await?;
+            (bytes, file)
+        };
+        if let (true, Some(mtime)) = (options.preserve_times, state.mtime) {
+            file.into_std().await.set_modified(mtime)?;
+        }
+
+        let (sparse_bytes, elapsed) = (state.sparse_bytes, state.elapsed);
+        let digest = self.verify_digest(src, options, state).await?;
+
+        Ok(TransferredFile {
+            device_path: src.to_path_buf(),
+            host_path: Some(dest.to_path_buf()),
+            bytes,
+            sparse_bytes,
+            digest,
+            elapsed,
+        })
+    }
+
+    /// Like [`Device::push`], with the given options applied.
+    pub async fn push_with_options<R: AsyncRead + Unpin>(
+        &self,
+        buffer: &mut R,
+        dest: &UnixPath,
+        mode: u32,
+        options: &TransferOptions,
+    ) -> Result<TransferredFile> {
+        // Reading the source is harmless, and the only way to size it.
+        if options.dry_run {
+            return Ok(TransferredFile {
+                device_path: dest.to_path_buf(),
+                host_path: None,
+                bytes: tokio::io::copy(buffer, &mut tokio::io::sink()).await?,
+                sparse_bytes: 0,
+                digest: None,
+                elapsed: Duration::ZERO,
+            });
+        }
+
+        let mut state = options.state();
+        state.mtime = options.mtime;
+        let bytes = self
+            .push_internal(buffer, dest, mode, None, None, &mut state)
+            .await?;
+        let elapsed = state.elapsed;
+        let digest = self.verify_digest(dest, options, state).await?;
+
+        Ok(TransferredFile {
+            device_path: dest.to_path_buf(),
+            host_path: None,
+            bytes,
+            sparse_bytes: 0,
+            digest,
+            elapsed,
+        })
+    }
+
+    /// Like [`Device::pull_dir`], with the given options applied.
+    pub async fn pull_dir_with_options(
+        &self,
+        src: &UnixPath,
+        dest_dir: &Path,
+        options: &TransferOptions,
+    ) -> Result<TransferReport> {
+        self.pull_dir_internal(src, dest_dir, None, options).await
+    }
+
+    /// Pulls the files below `base` matching the glob `pattern` into
+    /// `dest_dir`, keeping their paths relative to `base`.
+    ///
+    /// A relative pattern such as `**/*.jpg` is matched against paths
+    /// relative to `base`, an absolute one such as `/sdcard/DCIM/**/*.jpg`
+    /// against full device paths.  `*` does not cross directories, `**`
+    /// does.
+    pub async fn pull_matching(
+        &self,
+        base: &UnixPath,
+        pattern: &str,
+        dest_dir: &Path,
+        options: &TransferOptions,
+    ) -> Result<TransferReport> {
+        self.pull_matching_internal(base, pattern, dest_dir, options, None)
+            .await
+    }
+
+    /// Like [`Device::pull_matching`], reporting progress across all matched
+    /// files.
+    pub async fn pull_matching_with_progress(
+        &self,
+        base: &UnixPath,
+        pattern: &str,
+        dest_dir: &Path,
+        options: &TransferOptions,
+        progress: impl ProgressSink<DirectoryTransferProgress>,
+    ) -> Result<TransferReport> {
+        self.pull_matching_internal(base, pattern, dest_dir, options, Some(&progress))
+            .await
+    }
+
+    async fn pull_matching_internal(
+        &self,
+        base: &UnixPath,
+        pattern: &str,
+        dest_dir: &Path,
+        options: &TransferOptions,
+        progress: Option<&dyn ProgressSink<DirectoryTransferProgress>>,
+    ) -> Result<TransferReport> {
+        let glob = compile_glob(pattern)?;
+        let absolute = pattern.starts_with('/');
+
+        let entries = self
+            .list_dir(base)
+            .await?
+            .into_iter()
+            .filter(|entry| entry.file_mode != UnixFileStatus::Directory)
+            .filter(|entry| match absolute {
+                true => glob.is_match(base.join(&entry.path).display().to_string()),
+                false => glob.is_match(&entry.path),
+            })
+            .collect();
+
+        self.pull_entries(base, entries, dest_dir, progress, options)
+            .await
+    }
+
+    /// Like [`Device::push_dir`], with the given options applied.
+    pub async fn push_dir_with_options(
+        &self,
+        source: &Path,
+        dest_dir: &UnixPath,
+        mode: u32,
+        options: &TransferOptions,
+    ) -> Result<TransferReport> {
+        self.push_dir_internal(source, dest_dir, mode, None, options)
+            .await
+    }
+}
+
+/// Collects a pull in memory, failing once it exceeds `max_bytes`.
+struct CappedBuffer<'a> {
+    data: Vec<u8>,
+    max_bytes: u64,
+    path: &'a UnixPath,
+}
+
+impl AsyncWrite for CappedBuffer<'_> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if (self.data.len() + buf.len()) as u64 > self.max_bytes {
+            return Poll::Ready(Err(too_large(self.path, self.max_bytes)));
+        }
+        self.data.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn too_large(path: &UnixPath, max_bytes: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::FileTooLarge,
+        format!("{} is larger than {} bytes", path.display(), max_bytes),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_separators() {
+        let glob = compile_glob("**/*.jpg").expect("glob");
+        assert!(glob.is_match("a.jpg"));
+        assert!(glob.is_match("Camera/2024/a.jpg"));
+        assert!(!glob.is_match("a.png"));
+
+        let glob = compile_glob("/sdcard/DCIM/*.jpg").expect("glob");
+        assert!(glob.is_match("/sdcard/DCIM/a.jpg"));
+        assert!(!glob.is_match("/sdcard/DCIM/Camera/a.jpg"));
+
+        assert!(matches!(
+            compile_glob("[a"),
+            Err(DeviceError::InvalidPattern(..))
+        ));
+    }
+
+    #[test]
+    fn hex_digests() {
+        let mut hasher = HashAlgorithm::Sha256.hasher();
+        hasher.update(b"ab");
+        hasher.update(b"c");
+        assert_eq!(
+            hasher.finish(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let mut hasher = HashAlgorithm::Md5.hasher();
+        hasher.update(b"abc");
+        assert_eq!(hasher.finish(), "900150983cd24fb0d6963f7d28e17f72");
+    }
+}
@@ -0,0 +1,355 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use futures_core::future::BoxFuture;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout, Duration, Instant, Sleep};
+
+use crate::{DeviceError, Result};
+
+/// A bidirectional byte stream speaking the adb server protocol.
+///
+/// Every type implementing `AsyncRead + AsyncWrite` is a transport, so TCP and
+/// unix sockets, TLS streams or in-memory pipes can all be used interchangeably.
+pub trait Transport: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Transport for T {}
+
+/// Boxed transport as handed out by [`Host::connect`](crate::Host::connect).
+pub type BoxedTransport = Box<dyn Transport>;
+
+/// Opens new connections to an adb server.
+///
+/// Set [`Host::connector`](crate::Host::connector) to plug in an alternative
+/// transport; the default is [`TcpConnector`].
+pub trait Connector: fmt::Debug + Send + Sync {
+    fn connect(&self) -> BoxFuture<'_, Result<BoxedTransport>>;
+}
+
+/// Connects to an adb server listening on a TCP address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TcpConnector {
+    /// The address to connect to, e.g. `"localhost:5037"`.
+    pub addr: String,
+    /// How long to wait for the connection to be established.
+    pub connect_timeout: Duration,
+}
+
+impl TcpConnector {
+    pub fn new<T: Into<String>>(addr: T, connect_timeout: Duration) -> TcpConnector {
+        TcpConnector {
+            addr: addr.into(),
+            connect_timeout,
+        }
+    }
+}
+
+impl Connector for TcpConnector {
+    fn connect(&self) -> BoxFuture<'_, Result<BoxedTransport>> {
+        Box::pin(async move {
+            let stream = timeout(self.connect_timeout, TcpStream::connect(&self.addr))
+                .await
+                .map_err(|_| DeviceError::ConnectTimeout)??;
+
+            stream.set_nodelay(true)?;
+
+            Ok(Box::new(stream) as BoxedTransport)
+        })
+    }
+}
+
+/// Connects to an adb server listening on a unix domain socket.
+#[cfg(unix)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnixConnector {
+    /// Path of the socket in the filesystem.
+    pub path: PathBuf,
+    /// How long to wait for the connection to be established.
+    pub connect_timeout: Duration,
+}
+
+#[cfg(unix)]
+impl UnixConnector {
+    pub fn new<T: Into<PathBuf>>(path: T, connect_timeout: Duration) -> UnixConnector {
+        UnixConnector {
+            path: path.into(),
+            connect_timeout,
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Connector for UnixConnector {
+    fn connect(&self) -> BoxFuture<'_, Result<BoxedTransport>> {
+        Box::pin(async move {
+            let stream = timeout(
+                self.connect_timeout,
+                tokio::net::UnixStream::connect(&self.path),
+            )
+            .await
+            .map_err(|_| DeviceError::ConnectTimeout)??;
+
+            Ok(Box::new(stream) as BoxedTransport)
+        })
+    }
+}
+
+/// Address of an adb server, written as in the `ADB_SERVER_SOCKET`
+/// environment variable: `tcp:<port>`, `tcp:<host>:<port>`,
+/// `localfilesystem:<path>` or `localabstract:<name>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerAddress {
+    Tcp {
+        host: String,
+        port: u16,
+    },
+    /// A unix domain socket in the filesystem.
+    Unix(PathBuf),
+    /// A socket in the Linux abstract namespace.
+    Abstract(String),
+}
+
+impl ServerAddress {
+    /// Connects to the server.
+    pub async fn connect(&self, connect_timeout: Duration) -> Result<BoxedTransport> {
+        match self {
+            ServerAddress::Tcp { host, port } => {
+                TcpConnector::new(format!("{host}:{port}"), connect_timeout)
+                    .connect()
+                    .await
+            }
+            #[cfg(unix)]
+            ServerAddress::Unix(path) => {
+                UnixConnector::new(path.clone(), connect_timeout)
+                    .connect()
+                    .await
+            }
+            #[cfg(target_os = "linux")]
+            ServerAddress::Abstract(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                use std::os::unix::net::{SocketAddr, UnixStream};
+
+                let addr = SocketAddr::from_abstract_name(name.as_bytes())?;
+                // tokio cannot connect to abstract addresses, so the blocking
+                // connect runs off the runtime.
+                let connect = tokio::task::spawn_blocking(move || UnixStream::connect_addr(&addr));
+                let stream = timeout(connect_timeout, connect)
+                    .await
+                    .map_err(|_| DeviceError::ConnectTimeout)?
+                    .map_err(io::Error::other)??;
+                stream.set_nonblocking(true)?;
+
+                Ok(Box::new(tokio::net::UnixStream::from_std(stream)?) as BoxedTransport)
+            }
+            #[allow(unreachable_patterns)]
+            _ => Err(DeviceError::Io(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("adb server address {self} is not supported on this platform"),
+            ))),
+        }
+    }
+}
+
+impl FromStr for ServerAddress {
+    type Err = DeviceError;
+
+    fn from_str(s: &str) -> Result<ServerAddress> {
+        let invalid = || DeviceError::InvalidServerAddress(s.to_owned());
+
+        let (scheme, rest) = s.split_once(':').ok_or_else(invalid)?;
+        match scheme {
+            "tcp" => {
+                let (host, port) = match rest.rsplit_once(':') {
+                    Some((host, port)) => (host, port),
+                    None => ("localhost", rest),
+                };
+                let port = port.parse().map_err(|_| invalid())?;
+                Ok(ServerAddress::Tcp {
+                    host: host.to_owned(),
+                    port,
+                })
+            }
+            "localfilesystem" if !rest.is_empty() => Ok(ServerAddress::Unix(PathBuf::from(rest))),
+            "localabstract" if !rest.is_empty() => Ok(ServerAddress::Abstract(rest.to_owned())),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for ServerAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerAddress::Tcp { host, port } => write!(f, "tcp:{host}:{port}"),
+            ServerAddress::Unix(path) => write!(f, "localfilesystem:{}", path.display()),
+            ServerAddress::Abstract(name) => write!(f, "localabstract:{name}"),
+        }
+    }
+}
+
+/// Wraps a transport and fails reads and writes with
+/// [`io::ErrorKind::TimedOut`] once they make no progress for `idle`.
+pub(crate) struct IdleTimeout<T> {
+    inner: T,
+    idle: Duration,
+    deadline: Pin<Box<Sleep>>,
+    armed: bool,
+}
+
+impl<T> IdleTimeout<T> {
+    pub(crate) fn new(inner: T, idle: Duration) -> IdleTimeout<T> {
+        IdleTimeout {
+            inner,
+            idle,
+            deadline: Box::pin(sleep(idle)),
+            armed: false,
+        }
+    }
+
+    /// Called when the inner operation is pending; arms the deadline on the
+    /// first stall and reports whether it has expired.
+    fn poll_idle<R>(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<R>> {
+        if !self.armed {
+            self.deadline.as_mut().reset(Instant::now() + self.idle);
+            self.armed = true;
+        }
+
+        match self.deadline.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("no progress on adb connection for {:?}", self.idle),
+            ))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn progress<R>(&mut self, result: io::Result<R>) -> Poll<io::Result<R>> {
+        self.armed = false;
+        Poll::Ready(result)
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for IdleTimeout<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Ready(result) => this.progress(result),
+            Poll::Pending => this.poll_idle(cx),
+        }
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for IdleTimeout<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_write(cx, buf) {
+            Poll::Ready(result) => this.progress(result),
+            Poll::Pending => this.poll_idle(cx),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_flush(cx) {
+            Poll::Ready(result) => this.progress(result),
+            Poll::Pending => this.poll_idle(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_server_address() {
+        assert_eq!(
+            "tcp:5038".parse::<ServerAddress>().unwrap(),
+            ServerAddress::Tcp {
+                host: "localhost".to_owned(),
+                port: 5038
+            }
+        );
+        assert_eq!(
+            "tcp:10.0.0.2:5037".parse::<ServerAddress>().unwrap(),
+            ServerAddress::Tcp {
+                host: "10.0.0.2".to_owned(),
+                port: 5037
+            }
+        );
+        assert_eq!(
+            "localfilesystem:/tmp/adb.sock"
+                .parse::<ServerAddress>()
+                .unwrap(),
+            ServerAddress::Unix(PathBuf::from("/tmp/adb.sock"))
+        );
+        assert_eq!(
+            "localabstract:adb".parse::<ServerAddress>().unwrap(),
+            ServerAddress::Abstract("adb".to_owned())
+        );
+    }
+
+    #[test]
+    fn parse_invalid_server_address() {
+        for address in [
+            "",
+            "5037",
+            "tcp:",
+            "tcp:host:port",
+            "localfilesystem:",
+            "udp:5037",
+        ] {
+            assert!(
+                address.parse::<ServerAddress>().is_err(),
+                "{address} should not parse"
+            );
+        }
+    }
+
+    #[test]
+    fn server_address_round_trip() {
+        for address in ["tcp:localhost:5037", "localfilesystem:/tmp/adb.sock"] {
+            assert_eq!(
+                address.parse::<ServerAddress>().unwrap().to_string(),
+                address
+            );
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn connect_abstract_server_address() {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::{SocketAddr, UnixListener};
+
+        let name = format!("forensic-adb-test-{}", uuid::Uuid::new_v4());
+        let addr = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        let listener = UnixListener::bind_addr(&addr).unwrap();
+
+        ServerAddress::Abstract(name)
+            .connect(Duration::from_secs(5))
+            .await
+            .expect("connect");
+        listener.accept().expect("accept");
+    }
+}
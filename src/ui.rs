@@ -0,0 +1,505 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The view hierarchy of the screen as dumped by `uiautomator`, with
+//! selectors to find nodes in it.
+
+use crate::{Device, DeviceError, Result};
+
+/// Screen rectangle of a [`UiNode`] in pixels.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UiBounds {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+impl UiBounds {
+    /// The point to tap to hit the node.
+    pub fn center(&self) -> (i32, i32) {
+        ((self.left + self.right) / 2, (self.top + self.bottom) / 2)
+    }
+
+    /// Parses `[left,top][right,bottom]`.
+    fn parse(value: &str) -> Option<UiBounds> {
+        let (start, end) = value
+            .strip_prefix('[')?
+            .strip_suffix(']')?
+            .split_once("][")?;
+        let (left, top) = start.split_once(',')?;
+        let (right, bottom) = end.split_once(',')?;
+        Some(UiBounds {
+            left: left.parse().ok()?,
+            top: top.parse().ok()?,
+            right: right.parse().ok()?,
+            bottom: bottom.parse().ok()?,
+        })
+    }
+}
+
+/// A view of the hierarchy, see [`Device::ui_hierarchy`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UiNode {
+    /// Widget class, e.g. `android.widget.Button`.
+    pub class: String,
+    pub package: String,
+    /// Fully qualified id such as `com.android.settings:id/search`, if the
+    /// view has one.
+    pub resource_id: Option<String>,
+    pub text: String,
+    pub content_desc: String,
+    pub bounds: UiBounds,
+    pub checkable: bool,
+    pub checked: bool,
+    pub clickable: bool,
+    pub enabled: bool,
+    pub focusable: bool,
+    pub focused: bool,
+    pub scrollable: bool,
+    pub long_clickable: bool,
+    pub password: bool,
+    pub selected: bool,
+    pub children: Vec<UiNode>,
+}
+
+impl UiNode {
+    /// This node and its descendants, depth first.
+    pub fn iter(&self) -> impl Iterator<Item = &UiNode> {
+        let mut stack = vec![self];
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.children.iter().rev());
+            Some(node)
+        })
+    }
+
+    fn from_element(element: &Element) -> UiNode {
+        let flag = |name| element.attribute(name) == "true";
+        let resource_id = element.attribute("resource-id");
+        UiNode {
+            class: element.attribute("class").to_owned(),
+            package: element.attribute("package").to_owned(),
+            resource_id: (!resource_id.is_empty()).then(|| resource_id.to_owned()),
+            text: element.attribute("text").to_owned(),
+            content_desc: element.attribute("content-desc").to_owned(),
+            bounds: UiBounds::parse(element.attribute("bounds")).unwrap_or_default(),
+            checkable: flag("checkable"),
+            checked: flag("checked"),
+            clickable: flag("clickable"),
+            enabled: flag("enabled"),
+            focusable: flag("focusable"),
+            focused: flag("focused"),
+            scrollable: flag("scrollable"),
+            long_clickable: flag("long-clickable"),
+            password: flag("password"),
+            selected: flag("selected"),
+            children: element
+                .children
+                .iter()
+                .filter(|child| child.name == "node")
+                .map(UiNode::from_element)
+                .collect(),
+        }
+    }
+}
+
+/// Result of [`Device::ui_hierarchy`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UiHierarchy {
+    /// Screen rotation in quarter turns.
+    pub rotation: u32,
+    /// Root views, one per window.
+    pub nodes: Vec<UiNode>,
+}
+
+impl UiHierarchy {
+    /// Parses the XML written by `uiautomator dump`.
+    pub fn parse(xml: &str) -> Result<UiHierarchy> {
+        let root = Parser::new(xml)
+            .document()
+            .filter(|root| root.name == "hierarchy")
+            .ok_or_else(|| DeviceError::Adb("Invalid uiautomator hierarchy".to_owned()))?;
+
+        Ok(UiHierarchy {
+            rotation: root.attribute("rotation").parse().unwrap_or(0),
+            nodes: root
+                .children
+                .iter()
+                .filter(|child| child.name == "node")
+                .map(UiNode::from_element)
+                .collect(),
+        })
+    }
+
+    /// All nodes, depth first.
+    pub fn iter(&self) -> impl Iterator<Item = &UiNode> {
+        self.nodes.iter().flat_map(UiNode::iter)
+    }
+
+    /// The first node matching `selector`, depth first.
+    pub fn find(&self, selector: &UiSelector) -> Option<&UiNode> {
+        self.iter().find(|node| selector.matches(node))
+    }
+
+    /// All nodes matching `selector`, depth first.
+    pub fn find_all(&self, selector: &UiSelector) -> Vec<&UiNode> {
+        self.iter().filter(|node| selector.matches(node)).collect()
+    }
+}
+
+/// Conditions on a [`UiNode`], all of which must hold.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UiSelector {
+    class: Option<String>,
+    resource_id: Option<String>,
+    text: Option<String>,
+    text_contains: Option<String>,
+    content_desc: Option<String>,
+    package: Option<String>,
+    clickable: Option<bool>,
+}
+
+impl UiSelector {
+    pub fn new() -> UiSelector {
+        UiSelector::default()
+    }
+
+    pub fn class<T: Into<String>>(mut self, class: T) -> UiSelector {
+        self.class = Some(class.into());
+        self
+    }
+
+    /// Matches the full id, e.g. `com.android.settings:id/search`, or just
+    /// the part after `:id/`.
+    pub fn resource_id<T: Into<String>>(mut self, id: T) -> UiSelector {
+        self.resource_id = Some(id.into());
+        self
+    }
+
+    pub fn text<T: Into<String>>(mut self, text: T) -> UiSelector {
+        self.text = Some(text.into());
+        self
+    }
+
+    pub fn text_contains<T: Into<String>>(mut self, text: T) -> UiSelector {
+        self.text_contains = Some(text.into());
+        self
+    }
+
+    pub fn content_desc<T: Into<String>>(mut self, description: T) -> UiSelector {
+        self.content_desc = Some(description.into());
+        self
+    }
+
+    pub fn package<T: Into<String>>(mut self, package: T) -> UiSelector {
+        self.package = Some(package.into());
+        self
+    }
+
+    pub fn clickable(mut self, clickable: bool) -> UiSelector {
+        self.clickable = Some(clickable);
+        self
+    }
+
+    pub fn matches(&self, node: &UiNode) -> bool {
+        let id_matches = |id: &String| {
+            node.resource_id.as_ref().is_some_and(|node_id| {
+                node_id == id
+                    || node_id
+                        .split_once(":id/")
+                        .is_some_and(|(_, name)| name == id)
+            })
+        };
+
+        self.class.as_ref().is_none_or(|class| node.class == *class)
+            && self.resource_id.as_ref().is_none_or(id_matches)
+            && self.text.as_ref().is_none_or(|text| node.text == *text)
+            && self
+                .text_contains
+                .as_ref()
+                .is_none_or(|text| node.text.contains(text.as_str()))
+            && self
+                .content_desc
+                .as_ref()
+                .is_none_or(|description| node.content_desc == *description)
+            && self
+                .package
+                .as_ref()
+                .is_none_or(|package| node.package == *package)
+            && self
+                .clickable
+                .is_none_or(|clickable| node.clickable == clickable)
+    }
+}
+
+impl Device {
+    /// Dumps the view hierarchy of the screen with `uiautomator`.
+    ///
+    /// The dump is written to `/dev/tty` and read over `exec:`, so nothing
+    /// is stored on the device.  Fails while the screen keeps changing,
+    /// e.g. during animations, as `uiautomator` then cannot get an idle
+    /// state.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn ui_hierarchy(&self) -> Result<UiHierarchy> {
+        let output = self
+            .execute_host_exec_out_command("uiautomator dump /dev/tty")
+            .await?;
+        let output = String::from_utf8_lossy(&output);
+
+        // The XML is followed by "UI hierchary dumped to: /dev/tty".
+        let xml = output
+            .find("<?xml")
+            .or_else(|| output.find("<hierarchy"))
+            .and_then(|start| {
+                let end = output.rfind("</hierarchy>")? + "</hierarchy>".len();
+                output.get(start..end)
+            });
+        match xml {
+            Some(xml) => UiHierarchy::parse(xml),
+            None => Err(DeviceError::Adb(format!(
+                "uiautomator dump failed: {}",
+                output.trim()
+            ))),
+        }
+    }
+}
+
+/// An XML element, the only markup `uiautomator` writes besides the
+/// declaration.
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Element>,
+}
+
+impl Element {
+    /// The attribute's value, empty if missing.
+    fn attribute(&self, name: &str) -> &str {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map_or("", |(_, value)| value)
+    }
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn new(xml: &'a str) -> Parser<'a> {
+        Parser { rest: xml }
+    }
+
+    fn document(&mut self) -> Option<Element> {
+        self.skip_misc()?;
+        self.element()
+    }
+
+    /// Skips whitespace, the XML declaration and comments.
+    fn skip_misc(&mut self) -> Option<()> {
+        loop {
+            self.rest = self.rest.trim_start();
+            if self.rest.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if self.rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else {
+                return Some(());
+            }
+        }
+    }
+
+    fn skip_past(&mut self, end: &str) -> Option<()> {
+        let index = self.rest.find(end)?;
+        self.rest = &self.rest[index + end.len()..];
+        Some(())
+    }
+
+    fn name(&mut self) -> Option<String> {
+        let end = self
+            .rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '=' | '>' | '/'))
+            .unwrap_or(self.rest.len());
+        if end == 0 {
+            return None;
+        }
+        let (name, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Some(name.to_owned())
+    }
+
+    fn element(&mut self) -> Option<Element> {
+        self.rest = self.rest.strip_prefix('<')?;
+        let name = self.name()?;
+
+        let mut attributes = Vec::new();
+        loop {
+            self.rest = self.rest.trim_start();
+            if let Some(rest) = self.rest.strip_prefix("/>") {
+                self.rest = rest;
+                return Some(Element {
+                    name,
+                    attributes,
+                    children: Vec::new(),
+                });
+            }
+            if let Some(rest) = self.rest.strip_prefix('>') {
+                self.rest = rest;
+                break;
+            }
+
+            let key = self.name()?;
+            self.rest = self.rest.trim_start().strip_prefix('=')?.trim_start();
+            let quote = self
+                .rest
+                .chars()
+                .next()
+                .filter(|c| matches!(c, '"' | '\''))?;
+            let end = self.rest[1..].find(quote)? + 1;
+            attributes.push((key, unescape(&self.rest[1..end])?));
+            self.rest = &self.rest[end + 1..];
+        }
+
+        let mut children = Vec::new();
+        loop {
+            // Text between elements carries nothing.
+            let start = self.rest.find('<')?;
+            self.rest = &self.rest[start..];
+            if let Some(rest) = self.rest.strip_prefix("</") {
+                let end = rest.find('>')?;
+                if rest[..end].trim() != name {
+                    return None;
+                }
+                self.rest = &rest[end + 1..];
+                return Some(Element {
+                    name,
+                    attributes,
+                    children,
+                });
+            }
+            if self.rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+                continue;
+            }
+            children.push(self.element()?);
+        }
+    }
+}
+
+/// Resolves the predefined and numeric character references of `value`.
+fn unescape(value: &str) -> Option<String> {
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let end = rest.find(';')?;
+        let c = match &rest[..end] {
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "quot" => '"',
+            "apos" => '\'',
+            reference => {
+                let code = match reference.strip_prefix("#x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => reference.strip_prefix('#')?.parse().ok()?,
+                };
+                char::from_u32(code)?
+            }
+        };
+        result.push(c);
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DUMP: &str = r#"<?xml version='1.0' encoding='UTF-8' standalone='yes' ?><hierarchy rotation="1"><node index="0" text="" resource-id="" class="android.widget.FrameLayout" package="com.android.settings" content-desc="" checkable="false" checked="false" clickable="false" enabled="true" focusable="false" focused="false" scrollable="false" long-clickable="false" password="false" selected="false" bounds="[0,0][1080,2400]"><node index="0" text="Search &amp; settings" resource-id="com.android.settings:id/search_action_bar_title" class="android.widget.TextView" package="com.android.settings" content-desc="" checkable="false" checked="false" clickable="true" enabled="true" focusable="true" focused="false" scrollable="false" long-clickable="false" password="false" selected="false" bounds="[42,160][1038,286]" /><node index="1" text="Wi&#8209;Fi" resource-id="" class="android.widget.Switch" package="com.android.settings" content-desc="Wi-Fi" checkable="true" checked="true" clickable="true" enabled="true" focusable="true" focused="false" scrollable="false" long-clickable="false" password="false" selected="false" bounds="[900,400][1038,480]" /></node></hierarchy>"#;
+
+    #[test]
+    fn parses_uiautomator_dump() {
+        let hierarchy = UiHierarchy::parse(DUMP).expect("hierarchy");
+        assert_eq!(hierarchy.rotation, 1);
+        assert_eq!(hierarchy.nodes.len(), 1);
+        assert_eq!(hierarchy.iter().count(), 3);
+
+        let root = &hierarchy.nodes[0];
+        assert_eq!(root.class, "android.widget.FrameLayout");
+        assert_eq!(root.resource_id, None);
+        assert_eq!(
+            root.bounds,
+            UiBounds {
+                left: 0,
+                top: 0,
+                right: 1080,
+                bottom: 2400
+            }
+        );
+
+        let title = &root.children[0];
+        assert_eq!(title.text, "Search & settings");
+        assert!(title.clickable && title.enabled && !title.checked);
+        assert_eq!(title.bounds.center(), (540, 223));
+
+        let switch = &root.children[1];
+        assert_eq!(switch.text, "Wi\u{2011}Fi");
+        assert!(switch.checkable && switch.checked);
+    }
+
+    #[test]
+    fn selects_nodes() {
+        let hierarchy = UiHierarchy::parse(DUMP).expect("hierarchy");
+
+        let title = hierarchy
+            .find(&UiSelector::new().resource_id("search_action_bar_title"))
+            .expect("by short id");
+        assert_eq!(title.text, "Search & settings");
+        assert!(hierarchy
+            .find(&UiSelector::new().resource_id("com.android.settings:id/search_action_bar_title"))
+            .is_some());
+        assert_eq!(
+            hierarchy
+                .find(
+                    &UiSelector::new()
+                        .class("android.widget.Switch")
+                        .content_desc("Wi-Fi")
+                )
+                .map(|node| node.checked),
+            Some(true)
+        );
+        assert_eq!(
+            hierarchy
+                .find_all(
+                    &UiSelector::new()
+                        .package("com.android.settings")
+                        .clickable(true)
+                )
+                .len(),
+            2
+        );
+        assert!(hierarchy
+            .find(&UiSelector::new().text_contains("settings"))
+            .is_some());
+        assert!(hierarchy
+            .find(&UiSelector::new().text("Bluetooth"))
+            .is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_xml() {
+        assert!(UiHierarchy::parse("<hierarchy><node></hierarchy>").is_err());
+        assert!(UiHierarchy::parse("<node/>").is_err());
+        assert!(UiHierarchy::parse(r#"<hierarchy rotation="0"/>"#).is_ok());
+    }
+}
@@ -0,0 +1,212 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Enumeration of USB adb interfaces without an adb server.
+//!
+//! Devices are found through sysfs, so nothing is opened or claimed and the
+//! listing is safe to show before deciding which tool gets the device.
+//! Only Linux is supported.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::Result;
+
+/// Interface class of the adb interface (vendor specific).
+pub const ADB_CLASS: u8 = 0xff;
+/// Interface subclass of the adb interface.
+pub const ADB_SUBCLASS: u8 = 0x42;
+/// Interface protocol of the adb interface.
+pub const ADB_PROTOCOL: u8 = 0x01;
+
+const SYSFS_USB_DEVICES: &str = "/sys/bus/usb/devices";
+
+/// An adb interface of an attached USB device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsbAdbInterface {
+    pub bus: u8,
+    pub address: u8,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    /// The serial number, as listed by `adb devices` once the device is
+    /// attached to a server.
+    pub serial: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    /// `bInterfaceNumber` of the adb interface.
+    pub interface: u8,
+    /// Kernel driver bound to the interface; `usbfs` when a process such as
+    /// an adb server claimed it.
+    pub driver: Option<String>,
+}
+
+impl UsbAdbInterface {
+    /// Whether another process or driver holds the interface.
+    pub fn is_claimed(&self) -> bool {
+        self.driver.is_some()
+    }
+
+    /// The usbfs device node, e.g. `/dev/bus/usb/001/004`.
+    pub fn device_node(&self) -> PathBuf {
+        PathBuf::from(format!("/dev/bus/usb/{:03}/{:03}", self.bus, self.address))
+    }
+}
+
+/// Lists the adb interfaces of all attached USB devices, sorted by bus and
+/// address.
+pub fn list_adb_interfaces() -> Result<Vec<UsbAdbInterface>> {
+    if !cfg!(target_os = "linux") {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "USB enumeration is only supported on Linux",
+        )
+        .into());
+    }
+    list_adb_interfaces_in(Path::new(SYSFS_USB_DEVICES))
+}
+
+fn list_adb_interfaces_in(root: &Path) -> Result<Vec<UsbAdbInterface>> {
+    let mut interfaces = Vec::new();
+    for entry in fs::read_dir(root)? {
+        let path = entry?.path();
+        // Interfaces are named `<port path>:<config>.<interface>`.
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let Some((device_name, _)) = name.split_once(':') else {
+            continue;
+        };
+
+        let is_adb = read_hex(&path, "bInterfaceClass") == Some(u32::from(ADB_CLASS))
+            && read_hex(&path, "bInterfaceSubClass") == Some(u32::from(ADB_SUBCLASS))
+            && read_hex(&path, "bInterfaceProtocol") == Some(u32::from(ADB_PROTOCOL));
+        if !is_adb {
+            continue;
+        }
+
+        let device = root.join(device_name);
+        let (Some(bus), Some(address), Some(vendor_id), Some(product_id)) = (
+            read_attribute(&device, "busnum").and_then(|v| v.parse().ok()),
+            read_attribute(&device, "devnum").and_then(|v| v.parse().ok()),
+            read_hex(&device, "idVendor"),
+            read_hex(&device, "idProduct"),
+        ) else {
+            continue;
+        };
+
+        interfaces.push(UsbAdbInterface {
+            bus,
+            address,
+            vendor_id: vendor_id as u16,
+            product_id: product_id as u16,
+            serial: read_attribute(&device, "serial"),
+            manufacturer: read_attribute(&device, "manufacturer"),
+            product: read_attribute(&device, "product"),
+            interface: read_hex(&path, "bInterfaceNumber").unwrap_or_default() as u8,
+            driver: fs::read_link(path.join("driver"))
+                .ok()
+                .and_then(|driver| Some(driver.file_name()?.to_string_lossy().into_owned())),
+        });
+    }
+
+    interfaces.sort_by_key(|interface| (interface.bus, interface.address, interface.interface));
+    Ok(interfaces)
+}
+
+fn read_attribute(dir: &Path, name: &str) -> Option<String> {
+    let value = fs::read_to_string(dir.join(name)).ok()?;
+    Some(value.trim().to_owned()).filter(|value| !value.is_empty())
+}
+
+fn read_hex(dir: &Path, name: &str) -> Option<u32> {
+    u32::from_str_radix(&read_attribute(dir, name)?, 16).ok()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn write_attributes(dir: &Path, attributes: &[(&str, &str)]) {
+        fs::create_dir_all(dir).expect("mkdir");
+        for (name, value) in attributes {
+            fs::write(dir.join(name), format!("{value}\n")).expect("write");
+        }
+    }
+
+    #[test]
+    fn sysfs_listing() {
+        let root = tempfile::tempdir().expect("tempdir");
+        let root = root.path();
+
+        write_attributes(
+            &root.join("1-2"),
+            &[
+                ("busnum", "1"),
+                ("devnum", "4"),
+                ("idVendor", "18d1"),
+                ("idProduct", "4ee7"),
+                ("serial", "8AHX0T8KM"),
+                ("manufacturer", "Google"),
+                ("product", "Pixel 3a"),
+            ],
+        );
+        // MTP and adb on one device; only the latter is listed.
+        write_attributes(
+            &root.join("1-2:1.0"),
+            &[
+                ("bInterfaceClass", "06"),
+                ("bInterfaceSubClass", "01"),
+                ("bInterfaceProtocol", "01"),
+                ("bInterfaceNumber", "00"),
+            ],
+        );
+        write_attributes(
+            &root.join("1-2:1.1"),
+            &[
+                ("bInterfaceClass", "ff"),
+                ("bInterfaceSubClass", "42"),
+                ("bInterfaceProtocol", "01"),
+                ("bInterfaceNumber", "01"),
+            ],
+        );
+        let driver = root.join("drivers/usbfs");
+        fs::create_dir_all(&driver).expect("mkdir");
+        std::os::unix::fs::symlink(&driver, root.join("1-2:1.1/driver")).expect("symlink");
+
+        write_attributes(
+            &root.join("2-1"),
+            &[
+                ("busnum", "2"),
+                ("devnum", "3"),
+                ("idVendor", "04e8"),
+                ("idProduct", "6860"),
+            ],
+        );
+        write_attributes(
+            &root.join("2-1:1.0"),
+            &[
+                ("bInterfaceClass", "ff"),
+                ("bInterfaceSubClass", "42"),
+                ("bInterfaceProtocol", "01"),
+                ("bInterfaceNumber", "00"),
+            ],
+        );
+
+        let interfaces = list_adb_interfaces_in(root).expect("list");
+        assert_eq!(interfaces.len(), 2);
+
+        let pixel = &interfaces[0];
+        assert_eq!((pixel.vendor_id, pixel.product_id), (0x18d1, 0x4ee7));
+        assert_eq!(pixel.serial.as_deref(), Some("8AHX0T8KM"));
+        assert_eq!(pixel.interface, 1);
+        assert_eq!(pixel.driver.as_deref(), Some("usbfs"));
+        assert!(pixel.is_claimed());
+        assert_eq!(pixel.device_node(), PathBuf::from("/dev/bus/usb/001/004"));
+
+        let samsung = &interfaces[1];
+        assert_eq!(samsung.serial, None);
+        assert!(!samsung.is_claimed());
+    }
+}
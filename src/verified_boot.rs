@@ -0,0 +1,122 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Verified boot state, bootloader lock and dm-verity, to document the
+//! integrity of the software a device runs.
+
+use crate::locale::setting;
+use crate::{Device, Result};
+
+const VERIFIED_BOOT_PROPERTIES: [&str; 7] = [
+    "getprop ro.boot.verifiedbootstate",
+    "getprop ro.boot.flash.locked",
+    "getprop ro.boot.vbmeta.device_state",
+    "getprop ro.boot.veritymode",
+    "getprop ro.boot.vbmeta.digest",
+    "getprop ro.boot.vbmeta.hash_alg",
+    "getprop ro.boot.vbmeta.avb_version",
+];
+
+/// Boot state the bootloader reports, as shown by its boot screen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifiedBootState {
+    /// Verified with the OEM key on a locked bootloader.
+    Green,
+    /// Verified with a user-set key on a locked bootloader.
+    Yellow,
+    /// Unlocked bootloader: the software is not verified.
+    Orange,
+    /// Verification failed.
+    Red,
+    Other(String),
+}
+
+/// dm-verity mode of the system partitions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerityMode {
+    /// Corruption stops the device.
+    Enforcing,
+    /// Corruption is logged only.
+    Logging,
+    /// Corruption returns I/O errors.
+    Eio,
+    Disabled,
+    Other(String),
+}
+
+/// Result of [`Device::verified_boot_state`]; fields are `None` when the
+/// bootloader does not report them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifiedBoot {
+    pub state: Option<VerifiedBootState>,
+    pub bootloader_locked: Option<bool>,
+    pub verity_mode: Option<VerityMode>,
+    /// Hex digest of all vbmeta structures, Android Verified Boot 2.0.
+    pub vbmeta_digest: Option<String>,
+    /// Algorithm of [`vbmeta_digest`](VerifiedBoot::vbmeta_digest), e.g.
+    /// `sha256`.
+    pub vbmeta_hash_alg: Option<String>,
+    /// Android Verified Boot version, e.g. `1.1`.
+    pub avb_version: Option<String>,
+}
+
+impl VerifiedBoot {
+    /// Whether the device booted OEM-verified software with a locked
+    /// bootloader.
+    pub fn is_trusted(&self) -> bool {
+        self.state == Some(VerifiedBootState::Green) && self.bootloader_locked == Some(true)
+    }
+}
+
+impl Device {
+    /// Collects the verified boot state, bootloader lock, dm-verity mode
+    /// and vbmeta digest from the `ro.boot.*` properties the bootloader
+    /// passes, in one shell round trip.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn verified_boot_state(&self) -> Result<VerifiedBoot> {
+        let outputs = self.run_batch(&VERIFIED_BOOT_PROPERTIES).await?;
+        let value = |i: usize| setting(&outputs[i]);
+
+        Ok(VerifiedBoot {
+            state: value(0).map(|state| match state.as_str() {
+                "green" => VerifiedBootState::Green,
+                "yellow" => VerifiedBootState::Yellow,
+                "orange" => VerifiedBootState::Orange,
+                "red" => VerifiedBootState::Red,
+                _ => VerifiedBootState::Other(state),
+            }),
+            bootloader_locked: bootloader_locked(value(1), value(2)),
+            verity_mode: value(3).map(|mode| match mode.as_str() {
+                "enforcing" => VerityMode::Enforcing,
+                "logging" => VerityMode::Logging,
+                "eio" => VerityMode::Eio,
+                "disabled" => VerityMode::Disabled,
+                _ => VerityMode::Other(mode),
+            }),
+            vbmeta_digest: value(4),
+            vbmeta_hash_alg: value(5),
+            avb_version: value(6),
+        })
+    }
+}
+
+/// Whether the bootloader is locked, from `ro.boot.flash.locked` (`1`/`0`),
+/// which most bootloaders set, or AVB's `ro.boot.vbmeta.device_state`.
+pub(crate) fn bootloader_locked(
+    flash_locked: Option<String>,
+    device_state: Option<String>,
+) -> Option<bool> {
+    match flash_locked.as_deref() {
+        Some("1") => Some(true),
+        Some("0") => Some(false),
+        _ => match device_state.as_deref() {
+            Some("locked") => Some(true),
+            Some("unlocked") => Some(false),
+            _ => None,
+        },
+    }
+}
@@ -0,0 +1,316 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Storage volumes: emulated shared storage, SD cards, USB drives and
+//! adopted storage, with where each is mounted.
+
+use crate::{Device, DeviceError, Result, UnixPathBuf};
+
+/// Type of a [`StorageVolume`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VolumeType {
+    /// Internal or adopted storage holding apps and their data.
+    Private,
+    /// Portable storage, e.g. an SD card or USB drive formatted as such.
+    Public,
+    /// Shared storage of a user, backed by a private volume.
+    Emulated,
+    Stub,
+    Other(String),
+}
+
+impl VolumeType {
+    fn parse(kind: &str) -> VolumeType {
+        match kind.to_ascii_lowercase().as_str() {
+            "private" => VolumeType::Private,
+            "public" => VolumeType::Public,
+            "emulated" => VolumeType::Emulated,
+            "stub" => VolumeType::Stub,
+            other => VolumeType::Other(other.to_owned()),
+        }
+    }
+}
+
+/// State of a [`StorageVolume`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VolumeState {
+    Unmounted,
+    Checking,
+    Mounted,
+    MountedReadOnly,
+    Formatting,
+    Ejecting,
+    Unmountable,
+    Removed,
+    BadRemoval,
+    Other(String),
+}
+
+impl VolumeState {
+    fn parse(state: &str) -> VolumeState {
+        match state.to_ascii_lowercase().as_str() {
+            "unmounted" => VolumeState::Unmounted,
+            "checking" => VolumeState::Checking,
+            "mounted" => VolumeState::Mounted,
+            "mounted_read_only" | "mounted_ro" => VolumeState::MountedReadOnly,
+            "formatting" => VolumeState::Formatting,
+            "ejecting" => VolumeState::Ejecting,
+            "unmountable" => VolumeState::Unmountable,
+            "removed" => VolumeState::Removed,
+            "bad_removal" => VolumeState::BadRemoval,
+            other => VolumeState::Other(other.to_owned()),
+        }
+    }
+}
+
+/// A volume of [`Device::storage_volumes`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageVolume {
+    /// Volume id, e.g. `public:179,1`, `emulated;0` or `private`.
+    pub id: String,
+    pub volume_type: VolumeType,
+    pub state: VolumeState,
+    /// Filesystem uuid, e.g. `1234-ABCD`; `None` for internal storage.
+    pub uuid: Option<String>,
+    /// Filesystem, e.g. `vfat`, `exfat` or `ext4`.
+    pub fs_type: Option<String>,
+    pub label: Option<String>,
+    /// Where apps see the volume, e.g. `/storage/1234-ABCD`.
+    pub path: Option<UnixPathBuf>,
+    /// Where the volume is mounted, e.g. `/mnt/media_rw/1234-ABCD`.
+    pub internal_path: Option<UnixPathBuf>,
+    /// Disk the volume is on, e.g. `disk:179,0`.
+    pub disk_id: Option<String>,
+    /// Flags of that disk, e.g. `SD`, `USB` or `ADOPTABLE`.
+    pub disk_flags: Vec<String>,
+    /// Whether this is the primary shared storage, usually `/sdcard`.
+    pub primary: bool,
+}
+
+impl StorageVolume {
+    fn new(id: &str) -> StorageVolume {
+        StorageVolume {
+            id: id.to_owned(),
+            volume_type: VolumeType::parse(id.split([':', ';']).next().unwrap_or(id)),
+            state: VolumeState::Other(String::new()),
+            uuid: None,
+            fs_type: None,
+            label: None,
+            path: None,
+            internal_path: None,
+            disk_id: None,
+            disk_flags: Vec::new(),
+            primary: false,
+        }
+    }
+
+    /// Whether the volume is on an SD card.
+    pub fn is_sd_card(&self) -> bool {
+        self.disk_flags.iter().any(|flag| flag == "SD")
+    }
+
+    /// Whether the volume is removable storage adopted as internal storage.
+    pub fn is_adopted(&self) -> bool {
+        self.volume_type == VolumeType::Private && self.uuid.is_some()
+    }
+}
+
+impl Device {
+    /// Lists the storage volumes from `sm list-volumes all` and `dumpsys
+    /// mount`, in one shell round trip.
+    ///
+    /// Fails with [`DeviceError::MissingFeature`] before Android 6.0, which
+    /// has neither.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn storage_volumes(&self) -> Result<Vec<StorageVolume>> {
+        let outputs = self
+            .run_batch(&["sm list-volumes all", "dumpsys mount"])
+            .await?;
+        let list = match outputs[0].success() {
+            true => outputs[0].stdout_lossy(),
+            false => String::new(),
+        };
+        let volumes = parse_volumes(&list, &outputs[1].stdout_lossy());
+        if volumes.is_empty() && !outputs[0].success() {
+            return Err(DeviceError::MissingFeature("sm".to_owned()));
+        }
+        Ok(volumes)
+    }
+}
+
+/// Merges the `public:179,1 mounted 1234-ABCD` lines of `sm list-volumes`
+/// with the `DiskInfo{disk:179,0}:` and `VolumeInfo{public:179,1}:` blocks
+/// of `dumpsys mount`, whose `key=value` lines give the details.
+fn parse_volumes(list: &str, dumpsys: &str) -> Vec<StorageVolume> {
+    let mut volumes: Vec<StorageVolume> = Vec::new();
+    for line in list.lines() {
+        let mut fields = line.split_whitespace();
+        let (Some(id), Some(state)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        let mut volume = StorageVolume::new(id);
+        volume.state = VolumeState::parse(state);
+        volume.uuid = fields.next().and_then(value);
+        volumes.push(volume);
+    }
+
+    let mut disks: Vec<(String, Vec<String>)> = Vec::new();
+    let mut current = None;
+    let mut in_disk = false;
+    for line in dumpsys.lines().map(str::trim) {
+        if let Some(id) = line.strip_prefix("DiskInfo{") {
+            disks.push((id.trim_end_matches("}:").to_owned(), Vec::new()));
+            current = None;
+            in_disk = true;
+            continue;
+        }
+        if let Some(id) = line.strip_prefix("VolumeInfo{") {
+            let id = id.trim_end_matches("}:");
+            let index = match volumes.iter().position(|volume| volume.id == id) {
+                Some(index) => index,
+                None => {
+                    volumes.push(StorageVolume::new(id));
+                    volumes.len() - 1
+                }
+            };
+            current = Some(index);
+            in_disk = false;
+            continue;
+        }
+        if line.is_empty() || line.ends_with(':') {
+            current = None;
+            in_disk = false;
+            continue;
+        }
+        for (key, field) in fields(line) {
+            if in_disk {
+                if let (Some((_, flags)), "flags") = (disks.last_mut(), key) {
+                    flags.extend(split_flags(&field));
+                }
+                continue;
+            }
+            let Some(volume) = current.map(|index| &mut volumes[index]) else {
+                continue;
+            };
+            match key {
+                "type" => volume.volume_type = VolumeType::parse(&field),
+                "state" => volume.state = VolumeState::parse(&field),
+                "diskId" => volume.disk_id = value(&field),
+                "mountFlags" => volume.primary = split_flags(&field).any(|flag| flag == "PRIMARY"),
+                "fsType" => volume.fs_type = value(&field),
+                "fsUuid" => volume.uuid = value(&field).or(volume.uuid.take()),
+                "fsLabel" => volume.label = value(&field),
+                "path" => volume.path = value(&field).map(UnixPathBuf::from),
+                "internalPath" => volume.internal_path = value(&field).map(UnixPathBuf::from),
+                _ => {}
+            }
+        }
+    }
+
+    for volume in &mut volumes {
+        if let Some((_, flags)) = disks
+            .iter()
+            .find(|(id, _)| Some(id) == volume.disk_id.as_ref())
+        {
+            volume.disk_flags = flags.clone();
+        }
+    }
+    volumes
+}
+
+/// Splits `type=PUBLIC diskId=disk:179,0 fsLabel=My Card` into its fields;
+/// a word without `=` continues the previous value.
+fn fields(line: &str) -> Vec<(&str, String)> {
+    let mut fields: Vec<(&str, String)> = Vec::new();
+    for word in line.split_whitespace() {
+        match word.split_once('=') {
+            Some((key, value)) => fields.push((key, value.to_owned())),
+            None => {
+                if let Some((_, value)) = fields.last_mut() {
+                    value.push(' ');
+                    value.push_str(word);
+                }
+            }
+        }
+    }
+    fields
+}
+
+fn split_flags(flags: &str) -> impl Iterator<Item = String> + '_ {
+    flags
+        .split('|')
+        .map(str::trim)
+        .filter(|flag| !flag.is_empty())
+        .map(str::to_owned)
+}
+
+fn value(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty() && value != "null").then(|| value.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_volumes() {
+        let volumes = parse_volumes(
+            "private mounted null\n\
+             emulated;0 mounted null\n\
+             public:179,1 mounted 1234-ABCD\n\
+             private:179,2 mounted 57f8f4bc-abf4-655f-bf67-946fc0f9f25b\n",
+            "Disks:\n\
+             \x20 DiskInfo{disk:179,0}:\n\
+             \x20   flags=ADOPTABLE|SD size=63864569856 label=SanDisk\n\
+             \x20   sysPath=/sys/devices/platform/soc/mmc1\n\
+             \n\
+             Volumes:\n\
+             \x20 VolumeInfo{emulated;0}:\n\
+             \x20   type=EMULATED diskId=null partGuid=null mountFlags=PRIMARY|VISIBLE mountUserId=0\n\
+             \x20   state=MOUNTED\n\
+             \x20   fsType=null fsUuid=null fsLabel=null\n\
+             \x20   path=/storage/emulated internalPath=/data/media\n\
+             \x20 VolumeInfo{public:179,1}:\n\
+             \x20   type=PUBLIC diskId=disk:179,0 partGuid=null mountFlags=VISIBLE mountUserId=0\n\
+             \x20   state=MOUNTED\n\
+             \x20   fsType=exfat fsUuid=1234-ABCD fsLabel=My Card\n\
+             \x20   path=/storage/1234-ABCD internalPath=/mnt/media_rw/1234-ABCD\n\
+             \x20 VolumeInfo{stub:42}:\n\
+             \x20   type=STUB diskId=null partGuid=null mountFlags=0 mountUserId=0\n\
+             \x20   state=UNMOUNTED\n",
+        );
+        assert_eq!(volumes.len(), 5);
+        assert_eq!(volumes[0].volume_type, VolumeType::Private);
+        assert!(!volumes[0].is_adopted());
+        assert!(volumes[1].primary);
+        assert_eq!(
+            volumes[1].internal_path,
+            Some(UnixPathBuf::from("/data/media"))
+        );
+        assert_eq!(
+            volumes[2],
+            StorageVolume {
+                id: "public:179,1".to_owned(),
+                volume_type: VolumeType::Public,
+                state: VolumeState::Mounted,
+                uuid: Some("1234-ABCD".to_owned()),
+                fs_type: Some("exfat".to_owned()),
+                label: Some("My Card".to_owned()),
+                path: Some(UnixPathBuf::from("/storage/1234-ABCD")),
+                internal_path: Some(UnixPathBuf::from("/mnt/media_rw/1234-ABCD")),
+                disk_id: Some("disk:179,0".to_owned()),
+                disk_flags: vec!["ADOPTABLE".to_owned(), "SD".to_owned()],
+                primary: false,
+            }
+        );
+        assert!(volumes[2].is_sd_card());
+        assert!(volumes[3].is_adopted());
+        assert_eq!(volumes[4].volume_type, VolumeType::Stub);
+        assert_eq!(volumes[4].state, VolumeState::Unmounted);
+    }
+}
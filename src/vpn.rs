@@ -0,0 +1,165 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Active VPNs, the always-on VPN setting and the global HTTP proxy, which
+//! decide where the device's traffic went at capture time.
+
+use crate::dns::{field, list, network_agents};
+use crate::locale::setting;
+use crate::{Device, PackageListOptions, Result};
+
+const VPN_COMMANDS: [&str; 8] = [
+    "settings get secure always_on_vpn_app",
+    "settings get secure always_on_vpn_lockdown",
+    "settings get global http_proxy",
+    "settings get global global_http_proxy_host",
+    "settings get global global_http_proxy_port",
+    "settings get global global_http_proxy_exclusion_list",
+    "settings get global global_proxy_pac_url",
+    "dumpsys connectivity",
+];
+
+/// A connected VPN network of [`VpnStatus`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ActiveVpn {
+    pub network_id: Option<u32>,
+    /// Tunnel interface, e.g. `tun0`.
+    pub interface: Option<String>,
+    /// Uid of the app providing the VPN, Android 10 and later.
+    pub owner_uid: Option<u32>,
+    /// Packages running as [`owner_uid`](ActiveVpn::owner_uid).
+    pub packages: Vec<String>,
+    /// Network ids the tunnel runs over, Android 12 and later.
+    pub underlying_networks: Vec<u32>,
+}
+
+/// The global HTTP proxy, set by a device owner or `settings`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HttpProxy {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    /// Hosts bypassing the proxy.
+    pub exclusion_list: Vec<String>,
+    /// Proxy auto-config script.
+    pub pac_url: Option<String>,
+}
+
+/// Result of [`Device::vpn_status`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VpnStatus {
+    pub active: Vec<ActiveVpn>,
+    /// Package configured as always-on VPN.
+    pub always_on_package: Option<String>,
+    /// Whether traffic is blocked while the always-on VPN is down.
+    pub lockdown: bool,
+    pub global_proxy: Option<HttpProxy>,
+}
+
+impl Device {
+    /// Reports connected VPNs with the apps providing them, the always-on
+    /// VPN configuration and the global HTTP proxy.
+    ///
+    /// Reads `dumpsys connectivity` and the settings in one shell round
+    /// trip, plus one `pm list packages` per VPN to name its app.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn vpn_status(&self) -> Result<VpnStatus> {
+        let outputs = self.run_batch(&VPN_COMMANDS).await?;
+        let value = |i: usize| setting(&outputs[i]);
+
+        let mut active = parse_vpns(&outputs[7].stdout_lossy());
+        for vpn in &mut active {
+            if let Some(uid) = vpn.owner_uid {
+                let options = PackageListOptions::new().uid(uid);
+                vpn.packages = self
+                    .list_packages_with(&options)
+                    .await?
+                    .into_iter()
+                    .map(|package| package.name)
+                    .collect();
+            }
+        }
+
+        // `global_http_proxy_*` is set through the API, `http_proxy` by
+        // `settings put`; `:0` clears the latter.
+        let (host, port) = match value(3) {
+            Some(host) => (Some(host), value(4).and_then(|port| port.parse().ok())),
+            None => match value(2).as_deref().and_then(|proxy| proxy.rsplit_once(':')) {
+                Some((host, port)) if !host.is_empty() => {
+                    (Some(host.to_owned()), port.parse().ok())
+                }
+                _ => (None, None),
+            },
+        };
+        let pac_url = value(6);
+        let global_proxy = (host.is_some() || pac_url.is_some()).then(|| HttpProxy {
+            host,
+            port,
+            exclusion_list: value(5)
+                .map(|list| {
+                    list.split(',')
+                        .map(str::trim)
+                        .filter(|host| !host.is_empty())
+                        .map(str::to_owned)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            pac_url,
+        });
+
+        Ok(VpnStatus {
+            active,
+            always_on_package: value(0),
+            lockdown: value(1).as_deref() == Some("1"),
+            global_proxy,
+        })
+    }
+}
+
+/// Picks the networks with the VPN transport from `dumpsys connectivity`,
+/// whose capabilities read like `nc{[ Transports: VPN ... OwnerUid: 10123
+/// ... Underlying: [100] ]}`.
+fn parse_vpns(output: &str) -> Vec<ActiveVpn> {
+    network_agents(output)
+        .into_iter()
+        .filter(|(_, line)| {
+            field(line, "Transports: ")
+                .is_some_and(|transports| transports.split('|').any(|t| t == "VPN"))
+        })
+        .map(|(network_id, line)| ActiveVpn {
+            network_id,
+            interface: field(line, "InterfaceName: ").map(str::to_owned),
+            owner_uid: field(line, "OwnerUid: ").and_then(|uid| uid.parse().ok()),
+            packages: Vec::new(),
+            underlying_networks: list(line, "Underlying:")
+                .into_iter()
+                .filter_map(|id| id.parse().ok())
+                .collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_vpns() {
+        let output = "Current Networks:\n\
+                      \x20 NetworkAgentInfo{network{100}  lp{{InterfaceName: wlan0 DnsAddresses: [ /192.168.1.1 ]}}  nc{[ Transports: WIFI Capabilities: INTERNET ]}}\n\
+                      \x20 NetworkAgentInfo{network{102}  ni{VPN CONNECTED}  lp{{InterfaceName: tun0 LinkAddresses: [ 10.8.0.2/32 ]}}  nc{[ Transports: VPN Capabilities: INTERNET&NOT_VPN OwnerUid: 10150 AdminUids: [10150] Underlying: [100] ]}}\n";
+        assert_eq!(
+            parse_vpns(output),
+            [ActiveVpn {
+                network_id: Some(102),
+                interface: Some("tun0".to_owned()),
+                owner_uid: Some(10150),
+                packages: Vec::new(),
+                underlying_networks: vec![100],
+            }]
+        );
+    }
+}
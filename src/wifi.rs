@@ -0,0 +1,261 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The Wi-Fi connection and nearby access points, which place the device
+//! during triage.
+
+use std::net::IpAddr;
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::{Device, DeviceError, Result};
+
+/// How long [`Device::wifi_scan`] waits for fresh results.
+const WIFI_SCAN_TIMEOUT: Duration = Duration::from_secs(10);
+const WIFI_SCAN_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// BSSID and MAC reported when the real one is hidden or unknown.
+const REDACTED_MAC: &str = "02:00:00:00:00:00";
+
+/// Result of [`Device::wifi_status`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WifiStatus {
+    pub enabled: bool,
+    /// Associated and authenticated with an access point.
+    pub connected: bool,
+    pub ssid: Option<String>,
+    /// MAC address of the access point.
+    pub bssid: Option<String>,
+    /// Signal strength in dBm.
+    pub rssi: Option<i32>,
+    pub link_speed_mbps: Option<u32>,
+    pub frequency_mhz: Option<u32>,
+    pub ip: Option<IpAddr>,
+}
+
+/// An access point of [`Device::wifi_scan`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct WifiNetwork {
+    pub bssid: String,
+    /// `None` for hidden networks.
+    pub ssid: Option<String>,
+    pub frequency_mhz: u32,
+    /// Signal strength in dBm.
+    pub rssi: i32,
+    /// Time since the access point was last seen.
+    pub age: Duration,
+    /// Security and capability flags, e.g. `WPA2-PSK-CCMP` or `ESS`.
+    pub flags: Vec<String>,
+}
+
+impl Device {
+    /// Reports whether Wi-Fi is on and the network it is connected to.
+    ///
+    /// Uses `cmd wifi status` (Android 11+), falling back to `dumpsys wifi`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn wifi_status(&self) -> Result<WifiStatus> {
+        let output = self.run("cmd wifi status").await?;
+        let status = match output.success() {
+            true => output.stdout_lossy(),
+            false => self.execute_host_shell_command("dumpsys wifi").await?,
+        };
+        Ok(parse_wifi_status(&status))
+    }
+
+    /// Scans for access points with `cmd wifi start-scan` and returns the
+    /// results once they are fresh, Android 11 and later.
+    ///
+    /// Scans take a few seconds and Android throttles them; when no new
+    /// results arrive within 10 seconds the cached ones are returned, with
+    /// their [`age`](WifiNetwork::age).
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(serial = %self.serial), err)
+    )]
+    pub async fn wifi_scan(&self) -> Result<Vec<WifiNetwork>> {
+        let output = self.run("cmd wifi start-scan").await?;
+        if !output.success() {
+            return Err(DeviceError::MissingFeature("cmd wifi".to_owned()));
+        }
+
+        let started = Instant::now();
+        loop {
+            let networks = parse_scan_results(
+                &self
+                    .execute_host_shell_command("cmd wifi list-scan-results")
+                    .await?,
+            );
+            let fresh = networks
+                .iter()
+                .any(|network| network.age <= started.elapsed());
+            if fresh || started.elapsed() >= WIFI_SCAN_TIMEOUT {
+                return Ok(networks);
+            }
+            sleep(WIFI_SCAN_POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// Parses `Wifi is enabled` and the `WifiInfo` of `cmd wifi status`, or
+/// `mWifiInfo` of `dumpsys wifi`: `SSID: "home", BSSID: aa:bb:cc:dd:ee:ff,
+/// ... Supplicant state: COMPLETED, RSSI: -55, Link speed: 433Mbps, ...`.
+fn parse_wifi_status(output: &str) -> WifiStatus {
+    let mut status = WifiStatus {
+        enabled: output.lines().any(|line| {
+            line.starts_with("Wifi is enabled") || line.starts_with("Wi-Fi is enabled")
+        }),
+        ..WifiStatus::default()
+    };
+    let Some(info) = output
+        .lines()
+        .map(str::trim)
+        .find(|line| line.starts_with("WifiInfo: ") || line.starts_with("mWifiInfo "))
+    else {
+        return status;
+    };
+
+    // SSIDs are quoted and may contain the `, ` separating the fields.
+    let rest = match info.split_once("SSID: \"") {
+        Some((_, rest)) => match rest.split_once("\", ") {
+            Some((ssid, rest)) => {
+                status.ssid = Some(ssid.to_owned());
+                rest
+            }
+            None => rest,
+        },
+        None => info,
+    };
+    for field in rest.split(", ") {
+        let Some((key, value)) = field.split_once(": ") else {
+            continue;
+        };
+        match key {
+            "BSSID" if value != REDACTED_MAC && value != "null" => {
+                status.bssid = Some(value.to_owned())
+            }
+            "IP" => status.ip = value.trim_start_matches('/').parse().ok(),
+            "Supplicant state" => status.connected = value == "COMPLETED",
+            "RSSI" => status.rssi = value.parse().ok(),
+            "Link speed" => status.link_speed_mbps = value.trim_end_matches("Mbps").parse().ok(),
+            "Frequency" => status.frequency_mhz = value.trim_end_matches("MHz").parse().ok(),
+            _ => {}
+        }
+    }
+    status
+}
+
+/// Parses `cmd wifi list-scan-results`, whose columns are BSSID,
+/// frequency, RSSI, age in seconds, SSID (which may contain spaces or be
+/// empty) and flags like `[WPA2-PSK-CCMP][ESS]`.
+fn parse_scan_results(output: &str) -> Vec<WifiNetwork> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            let bssid = columns.next()?;
+            if bssid.len() != 17 || bssid.matches(':').count() != 5 {
+                return None;
+            }
+            let frequency_mhz = columns.next()?.parse().ok()?;
+            let rssi = columns.next()?.parse().ok()?;
+            let age = columns.next()?;
+            let age =
+                Duration::try_from_secs_f64(age.trim_start_matches('>').parse().ok()?).ok()?;
+
+            // What follows the age: the SSID, then the flags.
+            let rest = line[line.find(bssid)? + bssid.len()..]
+                .split_whitespace()
+                .skip(3)
+                .collect::<Vec<_>>();
+            let (ssid, flags) = match rest.last() {
+                Some(last) if last.starts_with('[') => (&rest[..rest.len() - 1], Some(*last)),
+                _ => (&rest[..], None),
+            };
+            let ssid = ssid.join(" ");
+
+            Some(WifiNetwork {
+                bssid: bssid.to_owned(),
+                ssid: (!ssid.is_empty()).then_some(ssid),
+                frequency_mhz,
+                rssi,
+                age,
+                flags: flags
+                    .map(|flags| {
+                        flags
+                            .split(['[', ']'])
+                            .filter(|flag| !flag.is_empty())
+                            .map(str::to_owned)
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_status() {
+        let status = parse_wifi_status(
+            "Wifi is enabled\n\
+             Wifi scanning is always available\n\
+             ==== Primary ClientModeManager instance ====\n\
+             Wifi is connected to \"Cafe, Main St\"\n\
+             WifiInfo: SSID: \"Cafe, Main St\", BSSID: aa:bb:cc:dd:ee:ff, MAC: 02:00:00:00:00:00, IP: /192.168.1.23, Security type: 2, Supplicant state: COMPLETED, Wi-Fi standard: 5, RSSI: -55, Link speed: 433Mbps, Tx Link speed: 433Mbps, Frequency: 5180MHz, Net ID: 0\n",
+        );
+        assert_eq!(
+            status,
+            WifiStatus {
+                enabled: true,
+                connected: true,
+                ssid: Some("Cafe, Main St".to_owned()),
+                bssid: Some("aa:bb:cc:dd:ee:ff".to_owned()),
+                rssi: Some(-55),
+                link_speed_mbps: Some(433),
+                frequency_mhz: Some(5180),
+                ip: Some("192.168.1.23".parse().unwrap()),
+            }
+        );
+
+        let status = parse_wifi_status(
+            "Wi-Fi is enabled\n\
+             \x20 mWifiInfo SSID: <unknown ssid>, BSSID: 02:00:00:00:00:00, MAC: 02:00:00:00:00:00, Supplicant state: DISCONNECTED, RSSI: -127, Link speed: -1Mbps, Frequency: -1MHz\n",
+        );
+        assert!(status.enabled && !status.connected);
+        assert_eq!((status.ssid, status.bssid), (None, None));
+
+        assert!(!parse_wifi_status("Wifi is disabled\n").enabled);
+    }
+
+    #[test]
+    fn parses_scan_results() {
+        let networks = parse_scan_results(
+            "    BSSID              Frequency      RSSI           Age(sec)     SSID                                 Flags\n\
+             \x20 aa:bb:cc:dd:ee:ff       5180        -55            1.500       Cafe Main St                         [WPA2-PSK-CCMP][RSN-PSK-CCMP][ESS]\n\
+             \x20 11:22:33:44:55:66       2412        -80          >1000.000                                          [ESS]\n",
+        );
+        assert_eq!(networks.len(), 2);
+        assert_eq!(
+            networks[0],
+            WifiNetwork {
+                bssid: "aa:bb:cc:dd:ee:ff".to_owned(),
+                ssid: Some("Cafe Main St".to_owned()),
+                frequency_mhz: 5180,
+                rssi: -55,
+                age: Duration::from_millis(1500),
+                flags: vec![
+                    "WPA2-PSK-CCMP".to_owned(),
+                    "RSN-PSK-CCMP".to_owned(),
+                    "ESS".to_owned()
+                ],
+            }
+        );
+        assert_eq!(networks[1].ssid, None);
+        assert_eq!(networks[1].age, Duration::from_secs(1000));
+    }
+}
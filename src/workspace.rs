@@ -0,0 +1,190 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Scoped working directories on the device.
+//!
+//! A [`Workspace`] is a directory of its own below
+//! [`Device::tempfile_dir`], named `forensic-adb-workspace.<name>.<uuid>`,
+//! that is removed with everything in it once the workspace is dropped or
+//! cleaned up.  Workspaces left behind by crashed or killed clients are
+//! found by [`Device::abandoned_workspaces`].
+
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+#[cfg(not(feature = "tracing"))]
+use log::warn;
+use once_cell::sync::Lazy;
+use tokio::io::AsyncRead;
+#[cfg(feature = "tracing")]
+use tracing::warn;
+use unix_path::Component;
+
+use crate::{Device, DeviceError, DevicePath, DeviceTempPath, Result, UnixPath, UnixPathBuf};
+
+const WORKSPACE_PREFIX: &str = "forensic-adb-workspace.";
+
+/// Workspaces of this process that are still in use, by device serial.
+static LIVE: Lazy<Mutex<BTreeSet<(String, UnixPathBuf)>>> = Lazy::new(Default::default);
+
+fn live() -> std::sync::MutexGuard<'static, BTreeSet<(String, UnixPathBuf)>> {
+    LIVE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// A directory on the device for the files of one task, see
+/// [`Device::workspace`].
+///
+/// Paths inside it are handed out by [`Workspace::path`], which records
+/// them, so [`Workspace::written`] lists what the task put there.
+#[derive(Debug)]
+pub struct Workspace {
+    device: Device,
+    dir: Option<DeviceTempPath>,
+    root: UnixPathBuf,
+    written: Mutex<BTreeSet<UnixPathBuf>>,
+}
+
+impl Workspace {
+    pub fn root(&self) -> &UnixPath {
+        &self.root
+    }
+
+    /// Returns the path of `relative` inside the workspace.  Absolute paths
+    /// and `..` are rejected.
+    pub fn path<P: AsRef<UnixPath>>(&self, relative: P) -> Result<UnixPathBuf> {
+        let relative = relative.as_ref();
+        let inside = relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+            && relative.components().next().is_some();
+        if !inside {
+            return Err(DeviceError::InvalidPath(
+                relative.display().to_string(),
+                "not a relative path inside the workspace".to_owned(),
+            ));
+        }
+        let path = self.root.join(relative);
+        DevicePath::new(&path)?;
+        self.written
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(path.clone());
+        Ok(path)
+    }
+
+    /// Pushes `source` to `relative` inside the workspace and returns the
+    /// device path.
+    pub async fn push<R: AsyncRead + Unpin>(
+        &self,
+        source: &mut R,
+        relative: &str,
+        mode: u32,
+    ) -> Result<UnixPathBuf> {
+        let path = self.path(relative)?;
+        self.device.push(source, &path, mode).await?;
+        Ok(path)
+    }
+
+    /// The paths handed out by [`Workspace::path`], sorted.
+    pub fn written(&self) -> Vec<UnixPathBuf> {
+        self.written
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Removes the workspace and everything in it now.
+    pub async fn cleanup(mut self) -> Result<()> {
+        match self.dir.take() {
+            Some(dir) => dir.close().await,
+            None => Ok(()),
+        }
+    }
+
+    /// Leaves the workspace on the device and returns its root.  It is then
+    /// reported by [`Device::abandoned_workspaces`].
+    pub fn keep(mut self) -> UnixPathBuf {
+        if let Some(dir) = self.dir.take() {
+            dir.keep();
+        }
+        self.root.clone()
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        // `dir`, if still set, removes the directory when dropped after this.
+        live().remove(&(self.device.serial.clone(), self.root.clone()));
+    }
+}
+
+impl Device {
+    /// Creates a workspace named after `name` below
+    /// [`Device::tempfile_dir`].
+    ///
+    /// `name` may contain ASCII letters, digits, `-` and `_`.  Abandoned
+    /// workspaces found in the same directory are logged as warnings.
+    pub async fn workspace(&self, name: &str) -> Result<Workspace> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+        {
+            return Err(DeviceError::InvalidPath(
+                name.to_owned(),
+                "invalid workspace name".to_owned(),
+            ));
+        }
+
+        match self.abandoned_workspaces().await {
+            Ok(abandoned) if !abandoned.is_empty() => warn!(
+                "{} abandoned workspaces in {}",
+                abandoned.len(),
+                self.tempfile_dir.display()
+            ),
+            Ok(_) => {}
+            Err(e) => warn!("Failed to look for abandoned workspaces: {}", e),
+        }
+
+        let dir = self
+            .create_temp_dir_with_prefix(&format!("{WORKSPACE_PREFIX}{name}."))
+            .await?;
+        let root = dir.path().to_path_buf();
+        live().insert((self.serial.clone(), root.clone()));
+
+        Ok(Workspace {
+            device: self.clone(),
+            dir: Some(dir),
+            root,
+            written: Mutex::default(),
+        })
+    }
+
+    /// Lists the workspaces in [`Device::tempfile_dir`] that no
+    /// [`Workspace`] of this process uses, i.e. left behind by an earlier
+    /// run or kept with [`Workspace::keep`].
+    ///
+    /// Workspaces of other clients running at the same time are listed too.
+    pub async fn abandoned_workspaces(&self) -> Result<Vec<UnixPathBuf>> {
+        let quoted = DevicePath::new(&self.tempfile_dir)?.quoted();
+        let output = self
+            .execute_host_shell_command_as(
+                &format!("ls {quoted}"),
+                self.enable_run_as_for_path(&self.tempfile_dir),
+            )
+            .await?;
+
+        let serial = &self.serial;
+        let live = live();
+        Ok(output
+            .lines()
+            .map(str::trim)
+            .filter(|name| name.starts_with(WORKSPACE_PREFIX))
+            .map(|name| self.tempfile_dir.join(name))
+            .filter(|path| !live.contains(&(serial.clone(), path.clone())))
+            .collect())
+    }
+}